@@ -0,0 +1,364 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local event archive
+//!
+//! Events are appended as JSON lines to segment files in a directory, each
+//! segment `<start_ns>.log` comes with an index `<start_ns>.idx` holding one
+//! JSON line per event with its ingest time, its position in the segment and
+//! the values of the indexed fields. Segments are rotated once they reach
+//! `segment_size` bytes and only the newest `max_segments` are kept.
+//!
+//! Queries select events by ingest time and by the values of indexed fields
+//! reading only the indexes and the matching lines of the segments.
+
+use crate::config::OffRamp;
+use crate::errors::{Error, Result};
+use async_std::fs::{self, File};
+use async_std::io::prelude::*;
+use async_std::stream::StreamExt;
+use std::path::{Path, PathBuf};
+use tremor_common::asy::file;
+use tremor_pipeline::ConfigImpl;
+use tremor_value::literal;
+use tremor_value::prelude::*;
+
+const DATA_EXT: &str = "log";
+const INDEX_EXT: &str = "idx";
+
+/// Archive configuration, the config of `archive` offramps
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Config {
+    /// directory holding the segments
+    pub dir: String,
+    /// dotted paths of the event fields to index
+    #[serde(default)]
+    pub index: Vec<String>,
+    /// bytes after which a new segment is started
+    #[serde(default = "d_segment_size")]
+    pub segment_size: u64,
+    /// segments kept, older ones are deleted
+    #[serde(default = "d_max_segments")]
+    pub max_segments: usize,
+    /// milliseconds without events after which writes are flushed
+    #[serde(default = "d_flush_timeout")]
+    pub flush_timeout: u64,
+}
+
+fn d_segment_size() -> u64 {
+    16 * 1024 * 1024
+}
+
+fn d_max_segments() -> usize {
+    16
+}
+
+fn d_flush_timeout() -> u64 {
+    1000
+}
+
+impl ConfigImpl for Config {}
+
+/// A query over the archived events
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Query {
+    /// earliest ingest time in nanoseconds
+    pub from: Option<u64>,
+    /// latest ingest time in nanoseconds
+    pub to: Option<u64>,
+    /// indexed field paths and the values they have to match, non string
+    /// values are matched against their JSON encoding
+    pub filters: Vec<(String, String)>,
+    /// maximum number of events, the most recent ones are returned
+    pub limit: usize,
+}
+
+fn lookup<'value, 'event>(
+    value: &'value Value<'event>,
+    path: &str,
+) -> Option<&'value Value<'event>> {
+    path.split('.').try_fold(value, |v, key| v.get(key))
+}
+
+fn segment_path(dir: &Path, start_ns: u64, ext: &str) -> PathBuf {
+    dir.join(format!("{:020}.{}", start_ns, ext))
+}
+
+/// the start times of the segments in `dir`, oldest first
+async fn segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut res = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next().await {
+        let path: PathBuf = entry?.path().into();
+        if path.extension().and_then(std::ffi::OsStr::to_str) == Some(INDEX_EXT) {
+            if let Some(start) = path
+                .file_stem()
+                .and_then(std::ffi::OsStr::to_str)
+                .and_then(|s| s.parse().ok())
+            {
+                res.push(start);
+            }
+        }
+    }
+    res.sort_unstable();
+    Ok(res)
+}
+
+struct Segment {
+    data: File,
+    index: File,
+    size: u64,
+}
+
+/// Appends events to the archive
+pub(crate) struct Writer {
+    config: Config,
+    segment: Option<Segment>,
+}
+
+impl Writer {
+    pub(crate) fn new(config: Config) -> Self {
+        Self {
+            config,
+            segment: None,
+        }
+    }
+
+    async fn rotate(&mut self, ingest_ns: u64) -> Result<()> {
+        self.flush().await?;
+        let dir = Path::new(&self.config.dir);
+        fs::create_dir_all(dir).await?;
+        let existing = segments(dir).await?;
+        // segment names have to be unique and ordered even if ingest times aren't
+        let start_ns = existing
+            .last()
+            .map_or(ingest_ns, |last| ingest_ns.max(last + 1));
+        let excess = (existing.len() + 1).saturating_sub(self.config.max_segments.max(1));
+        for old in existing.iter().take(excess) {
+            fs::remove_file(segment_path(dir, *old, INDEX_EXT)).await?;
+            fs::remove_file(segment_path(dir, *old, DATA_EXT)).await?;
+        }
+        self.segment = Some(Segment {
+            data: file::create(&segment_path(dir, start_ns, DATA_EXT)).await?,
+            index: file::create(&segment_path(dir, start_ns, INDEX_EXT)).await?,
+            size: 0,
+        });
+        Ok(())
+    }
+
+    /// Appends an event, starting a new segment if the current one is full
+    pub(crate) async fn append(&mut self, ingest_ns: u64, value: &Value<'_>) -> Result<()> {
+        let mut line = value.encode();
+        line.push('\n');
+        let len = line.len() as u64;
+        let full = self.segment.as_ref().map_or(true, |s| {
+            s.size > 0 && s.size + len > self.config.segment_size
+        });
+        if full {
+            self.rotate(ingest_ns).await?;
+        }
+        let mut fields = Value::object_with_capacity(self.config.index.len());
+        for path in &self.config.index {
+            if let Some(v) = lookup(value, path) {
+                fields.try_insert(path.clone(), v.clone_static());
+            }
+        }
+        let segment = self.segment.as_mut().ok_or("No archive segment")?;
+        let mut entry = literal!({
+            "t": ingest_ns,
+            "o": segment.size,
+            "l": len,
+            "f": fields
+        })
+        .encode();
+        entry.push('\n');
+        segment.data.write_all(line.as_bytes()).await?;
+        segment.index.write_all(entry.as_bytes()).await?;
+        segment.size += len;
+        Ok(())
+    }
+
+    /// Flushes the current segment
+    pub(crate) async fn flush(&mut self) -> Result<()> {
+        if let Some(segment) = &mut self.segment {
+            segment.data.flush().await?;
+            segment.index.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+fn is_match(entry: &Value, query: &Query) -> bool {
+    let t = entry.get_u64("t").unwrap_or_default();
+    if query.from.map_or(false, |from| t < from) || query.to.map_or(false, |to| t > to) {
+        return false;
+    }
+    let fields = entry.get("f");
+    query.filters.iter().all(|(path, expected)| {
+        fields
+            .and_then(|f| f.get(path.as_str()))
+            .map_or(false, |v| match v.as_str() {
+                Some(s) => s == expected,
+                None => v.encode() == *expected,
+            })
+    })
+}
+
+/// Runs a query over the archive in `dir`, returning the matching events as
+/// `{"ingest_ns": ..., "data": ...}` ordered by their position in the archive
+pub(crate) async fn search(dir: &Path, query: &Query) -> Result<Vec<Value<'static>>> {
+    let starts = segments(dir).await?;
+    let mut res = Vec::new();
+    // newest first, a segment can hold events up to the start of the next one
+    for (i, start) in starts.iter().enumerate().rev() {
+        if res.len() >= query.limit {
+            break;
+        }
+        if query.to.map_or(false, |to| *start > to) {
+            continue;
+        }
+        let next = starts.get(i + 1).copied();
+        if let (Some(from), Some(next)) = (query.from, next) {
+            if next <= from {
+                break;
+            }
+        }
+        let mut index = fs::read(segment_path(dir, *start, INDEX_EXT)).await?;
+        let mut found = Vec::new();
+        for line in index.split_mut(|b| *b == b'\n') {
+            // the last line may be partially written
+            if let Ok(entry) = tremor_value::parse_to_value(line) {
+                if is_match(&entry, query) {
+                    found.push((
+                        entry.get_u64("t").unwrap_or_default(),
+                        entry.get_u64("o").unwrap_or_default(),
+                        entry.get_u64("l").unwrap_or_default(),
+                    ));
+                }
+            }
+        }
+        if found.is_empty() {
+            continue;
+        }
+        let skip = found.len().saturating_sub(query.limit - res.len());
+        let data = fs::read(segment_path(dir, *start, DATA_EXT)).await?;
+        #[allow(clippy::cast_possible_truncation)]
+        for (t, offset, len) in found.into_iter().skip(skip).rev() {
+            let mut line = data
+                .get(offset as usize..(offset + len) as usize)
+                .ok_or_else(|| Error::from("Archive segment is shorter than its index"))?
+                .to_vec();
+            let value = tremor_value::parse_to_value(&mut line)?.into_static();
+            res.push(literal!({ "ingest_ns": t, "data": value }));
+        }
+    }
+    res.reverse();
+    Ok(res)
+}
+
+/// Runs a query over the archive written by an `archive` offramp, `None` if
+/// the offramp is of another type
+///
+/// # Errors
+///   * if the offramp config is invalid or the archive can't be read
+pub async fn query(offramp: &OffRamp, query: &Query) -> Result<Option<Vec<Value<'static>>>> {
+    if offramp.binding_type != "archive" {
+        return Ok(None);
+    }
+    let config = offramp
+        .config
+        .as_ref()
+        .ok_or("Archive offramp requires a config")?;
+    let config = Config::new(config)?;
+    search(Path::new(&config.dir), query).await.map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[async_std::test]
+    async fn append_and_search() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = Config {
+            dir: dir.path().to_string_lossy().to_string(),
+            index: vec!["host.name".to_string(), "status".to_string()],
+            segment_size: 100,
+            max_segments: 3,
+            flush_timeout: 0,
+        };
+        let mut writer = Writer::new(config);
+        for i in 0..10_u64 {
+            let host = if i % 2 == 0 { "a" } else { "b" };
+            let status = 200 + i % 3;
+            let event = literal!({"host": {"name": host}, "status": status, "i": i});
+            writer.append(i * 10, &event).await?;
+        }
+        writer.flush().await?;
+        // old segments were dropped
+        let starts = segments(dir.path()).await?;
+        assert_eq!(3, starts.len());
+        let oldest = starts.first().copied().unwrap_or_default();
+        assert!(oldest > 0);
+
+        let all = search(
+            dir.path(),
+            &Query {
+                limit: 100,
+                ..Query::default()
+            },
+        )
+        .await?;
+        assert_eq!(Some(90), all.last().and_then(|e| e.get_u64("ingest_ns")));
+        assert_eq!(
+            Some(oldest),
+            all.first().and_then(|e| e.get_u64("ingest_ns"))
+        );
+
+        let found = search(
+            dir.path(),
+            &Query {
+                from: Some(50),
+                to: Some(90),
+                filters: vec![("host.name".to_string(), "a".to_string())],
+                limit: 100,
+            },
+        )
+        .await?;
+        let is: Vec<_> = found
+            .iter()
+            .filter_map(|e| e.get("data").and_then(|d| d.get_u64("i")))
+            .collect();
+        assert_eq!(vec![6, 8], is);
+
+        let found = search(
+            dir.path(),
+            &Query {
+                filters: vec![("status".to_string(), "200".to_string())],
+                limit: 1,
+                ..Query::default()
+            },
+        )
+        .await?;
+        assert_eq!(1, found.len());
+        assert_eq!(
+            Some(9),
+            found
+                .first()
+                .and_then(|e| e.get("data"))
+                .and_then(|d| d.get_u64("i"))
+        );
+        Ok(())
+    }
+}
@@ -83,6 +83,15 @@ impl<T> AsyncSink<T> {
     pub fn has_capacity(&self) -> bool {
         self.queue.len() < self.capacity
     }
+
+    /// Waits for all queued tasks to complete, their results are dropped
+    pub async fn drain(&mut self) {
+        while let Some(rx) = self.queue.pop_front() {
+            if let Err(e) = rx.recv().await {
+                debug!("Task dropped without a result: {}", e);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
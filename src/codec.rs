@@ -12,17 +12,34 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::CodecSpec;
 use crate::errors::Result;
+use crate::OpConfig;
 use tremor_script::Value;
+pub(crate) mod apache_log;
+pub(crate) mod avro;
+pub(crate) mod base64;
 pub(crate) mod binary;
 pub(crate) mod binflux;
+pub(crate) mod cef;
+pub(crate) mod chain;
+pub(crate) mod csv;
+pub(crate) mod dissector;
+pub(crate) mod edi;
+pub(crate) mod fix;
+pub(crate) mod gelf;
+pub(crate) mod hl7;
 pub(crate) mod influx;
 pub(crate) mod json;
+pub(crate) mod json_lines;
+pub(crate) mod leef;
 pub(crate) mod msgpack;
 pub(crate) mod null;
+pub(crate) mod protobuf;
 pub(crate) mod statsd;
 pub(crate) mod string;
 pub(crate) mod syslog;
+pub(crate) mod thrift;
 pub(crate) mod yaml;
 
 const MIME_TYPES: [&str; 8] = [
@@ -43,6 +60,10 @@ mod prelude {
     pub use tremor_script::{Object, Value};
 }
 
+/// Metadata field selecting the codec of an event, the name is looked up
+/// in the `codec_map` of the ramp
+pub(crate) const META_FIELD: &str = "codec";
+
 /// The codec trait, to encode and decode data
 pub trait Codec: Send + Sync {
     /// The canonical name for this codec
@@ -75,14 +96,20 @@ pub trait Codec: Send + Sync {
     ///  * If the encoding fails
     fn encode(&self, data: &Value) -> Result<Vec<u8>>;
 
-    /// Encodes into an existing buffer
+    /// Encodes a Value, appending to an existing buffer, this allows
+    /// sinks to reuse their buffers instead of allocating one per event.
+    /// Codecs that can write in place should implement it.
     ///
     /// # Errors
     ///  * when we can't write encode to the given vector
     #[cfg(not(tarpaulin_include))]
     fn encode_into(&self, data: &Value, dst: &mut Vec<u8>) -> Result<()> {
         let mut res = self.encode(data)?;
-        std::mem::swap(&mut res, dst);
+        if dst.is_empty() {
+            std::mem::swap(&mut res, dst);
+        } else {
+            dst.append(&mut res);
+        }
         Ok(())
     }
 
@@ -98,6 +125,15 @@ pub trait Codec: Send + Sync {
 /// # Errors
 ///  * if the codec doesn't exist
 pub fn lookup(name: &str) -> Result<Box<dyn Codec>> {
+    lookup_with_config(name, &None)
+}
+
+/// Codec lookup function for codecs that can be configured
+///
+/// # Errors
+///  * if the codec doesn't exist
+///  * if the config is invalid for the codec
+pub fn lookup_with_config(name: &str, config: &Option<OpConfig>) -> Result<Box<dyn Codec>> {
     match name {
         "json" => Ok(Box::new(json::Json::default())),
         "msgpack" => Ok(Box::new(msgpack::MsgPack {})),
@@ -109,10 +145,46 @@ pub fn lookup(name: &str) -> Result<Box<dyn Codec>> {
         "yaml" => Ok(Box::new(yaml::Yaml {})),
         "binary" => Ok(Box::new(binary::Binary {})),
         "syslog" => Ok(Box::new(syslog::Syslog {})),
+        "thrift" => Ok(Box::new(thrift::Thrift::from_config(config)?)),
+        "fix" => Ok(Box::new(fix::Fix::from_config(config)?)),
+        "hl7" => Ok(Box::new(hl7::Hl7 {})),
+        "edi" => Ok(Box::new(edi::Edi {})),
+        "base64" => Ok(Box::new(base64::Base64 {})),
+        "avro" => Ok(Box::new(avro::Avro::from_config(config)?)),
+        "protobuf" => Ok(Box::new(protobuf::Protobuf::from_config(config)?)),
+        "csv" => Ok(Box::new(csv::Csv::from_config(config)?)),
+        "gelf" => Ok(Box::new(gelf::Gelf::default())),
+        "apache-log" => Ok(Box::new(apache_log::ApacheLog {})),
+        "cef" => Ok(Box::new(cef::Cef {})),
+        "json-lines" => Ok(Box::new(json_lines::JsonLines::from_config(config)?)),
+        "leef" => Ok(Box::new(leef::Leef {})),
+        "dissector" => Ok(Box::new(dissector::Dissector::from_config(config)?)),
         _ => Err(format!("Codec '{}' not found.", name).into()),
     }
 }
 
+/// Codec lookup function for a single codec or a codec chain, for chains
+/// the configuration of each codec is looked up by its name in `config`
+///
+/// # Errors
+///  * if a codec doesn't exist
+///  * if the config is invalid for a codec
+pub fn lookup_spec(spec: &CodecSpec, config: &Option<OpConfig>) -> Result<Box<dyn Codec>> {
+    match spec {
+        CodecSpec::Single(name) => lookup_with_config(name, config),
+        CodecSpec::Chain(names) => {
+            let codecs = names
+                .iter()
+                .map(|name| {
+                    let config = config.as_ref().and_then(|c| c.get(name.as_str())).cloned();
+                    lookup_with_config(name, &config)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Box::new(chain::Chain::new(codecs)?))
+        }
+    }
+}
+
 /// Map from Mime types to codecs for all builtin codecs mappable to Mime types
 /// these are all safe mappings
 /// if you have a specific codec to be used for a more unspecific mime type
@@ -157,12 +229,58 @@ mod test {
         assert!(super::lookup("statsd").is_ok());
         assert!(super::lookup("yaml").is_ok());
         assert!(super::lookup("syslog").is_ok());
+        assert!(super::lookup("thrift").is_ok());
+        assert!(super::lookup("fix").is_ok());
+        assert!(super::lookup("hl7").is_ok());
+        assert!(super::lookup("edi").is_ok());
+        assert!(super::lookup("base64").is_ok());
+        assert!(super::lookup("csv").is_ok());
+        assert!(super::lookup("gelf").is_ok());
+        // avro requires a schema or registry
+        assert!(super::lookup("avro").is_err());
+        // protobuf requires a descriptor set and message
+        assert!(super::lookup("protobuf").is_err());
         assert_eq!(
             super::lookup("snot").err().unwrap().to_string(),
             "Codec 'snot' not found."
         )
     }
 
+    #[test]
+    fn lookup_spec() {
+        use crate::config::CodecSpec;
+        let chain = CodecSpec::Chain(vec!["base64".to_string(), "json".to_string()]);
+        assert_eq!(
+            Some("base64|json".to_string()),
+            super::lookup_spec(&chain, &None)
+                .ok()
+                .map(|c| c.name().to_string())
+        );
+        let single = CodecSpec::from("json");
+        assert!(super::lookup_spec(&single, &None).is_ok());
+        let invalid = CodecSpec::Chain(vec!["json".to_string(), "snot".to_string()]);
+        assert!(super::lookup_spec(&invalid, &None).is_err());
+        let short = CodecSpec::Chain(vec!["json".to_string()]);
+        assert!(super::lookup_spec(&short, &None).is_err());
+    }
+
+    #[test]
+    fn encode_into() -> crate::errors::Result<()> {
+        let value = tremor_value::literal!({"snot": "badger", "n": [1, 2]});
+        for name in &["json", "msgpack", "string", "yaml", "null"] {
+            let codec = super::lookup(name)?;
+            let encoded = codec.encode(&value)?;
+            // appends to what is in the buffer already
+            let mut buf = b"prefix".to_vec();
+            codec.encode_into(&value, &mut buf)?;
+            assert_eq!(Some(encoded.as_slice()), buf.get(6..), "{}", name);
+            let mut buf = Vec::new();
+            codec.encode_into(&value, &mut buf)?;
+            assert_eq!(encoded, buf, "{}", name);
+        }
+        Ok(())
+    }
+
     #[test]
     fn builtin_codec_map() {
         let map = super::builtin_codec_map();
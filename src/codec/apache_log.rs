@@ -0,0 +1,360 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Apache access log codec for the Common and Combined Log Format
+//!
+//! ```text
+//! 127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /a.gif HTTP/1.0" 200 2326 "http://example.com/" "Mozilla/4.08"
+//! ```
+//!
+//! decodes to
+//!
+//! ```json
+//! {
+//!   "client": "127.0.0.1",
+//!   "ident": null,
+//!   "user": "frank",
+//!   "timestamp": 971211336000000000,
+//!   "request": "GET /a.gif HTTP/1.0",
+//!   "method": "GET",
+//!   "path": "/a.gif",
+//!   "protocol": "HTTP/1.0",
+//!   "status": 200,
+//!   "bytes": 2326,
+//!   "referer": "http://example.com/",
+//!   "agent": "Mozilla/4.08"
+//! }
+//! ```
+//!
+//! `-` is decoded as `null`, `referer` and `agent` are only present for the
+//! Combined Log Format. Request lines that aren't made of method, path and
+//! protocol, e.g. `-` for timed out connections, leave those `null`.
+//!
+//! Encoding writes the Combined Log Format if `referer` or `agent` are set,
+//! otherwise the Common Log Format. The request line is built from `method`,
+//! `path` and `protocol` if present, otherwise `request` is used.
+
+use super::prelude::*;
+use chrono::{DateTime, TimeZone, Utc};
+use std::borrow::Cow;
+use std::str;
+
+const TIME_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+#[derive(Clone)]
+pub struct ApacheLog {}
+
+fn invalid(reason: &str) -> Error {
+    format!("Invalid Apache log line: {}", reason).into()
+}
+
+/// Splits off the field up to the next space
+fn field(input: &str) -> Result<(&str, &str)> {
+    let input = input.trim_start_matches(' ');
+    if input.is_empty() {
+        return Err(invalid("unexpected end of line"));
+    }
+    Ok(input.split_at(input.find(' ').unwrap_or_else(|| input.len())))
+}
+
+/// Splits off the field enclosed by `[` and `]`
+fn bracketed(input: &str) -> Result<(&str, &str)> {
+    let input = input
+        .trim_start_matches(' ')
+        .strip_prefix('[')
+        .ok_or_else(|| invalid("expected `[`"))?;
+    let end = input.find(']').ok_or_else(|| invalid("expected `]`"))?;
+    Ok((
+        input.get(..end).unwrap_or_default(),
+        input.get(end + 1..).unwrap_or_default(),
+    ))
+}
+
+/// Splits off a quoted field, unescaping `\"` and `\\`
+fn quoted(input: &str) -> Result<(Cow<str>, &str)> {
+    let input = input
+        .trim_start_matches(' ')
+        .strip_prefix('"')
+        .ok_or_else(|| invalid("expected `\"`"))?;
+    let mut unescaped: Option<String> = None;
+    let mut start = 0;
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let rest = input.get(i + 1..).unwrap_or_default();
+                let tail = input.get(start..i).unwrap_or_default();
+                return Ok(match unescaped {
+                    Some(mut s) => {
+                        s.push_str(tail);
+                        (Cow::Owned(s), rest)
+                    }
+                    None => (Cow::Borrowed(tail), rest),
+                });
+            }
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    let s = unescaped.get_or_insert_with(String::new);
+                    s.push_str(input.get(start..i).unwrap_or_default());
+                    if escaped != '"' && escaped != '\\' {
+                        s.push('\\');
+                    }
+                    s.push(escaped);
+                    start = i + 1 + escaped.len_utf8();
+                }
+            }
+            _ => (),
+        }
+    }
+    Err(invalid("unterminated `\"`"))
+}
+
+fn dash_to_null(s: &str) -> Value {
+    if s == "-" {
+        Value::null()
+    } else {
+        Value::from(s)
+    }
+}
+
+fn cow_to_value(s: Cow<str>) -> Value {
+    match s {
+        Cow::Borrowed("-") => Value::null(),
+        Cow::Borrowed(s) => Value::from(s),
+        Cow::Owned(s) => Value::from(s),
+    }
+}
+
+fn decode(line: &str) -> Result<Value> {
+    let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+    let (client, rest) = field(line)?;
+    let (ident, rest) = field(rest)?;
+    let (user, rest) = field(rest)?;
+    let (time, rest) = bracketed(rest)?;
+    let timestamp = DateTime::parse_from_str(time, TIME_FORMAT)
+        .map_err(|e| invalid(&format!("bad timestamp `{}`: {}", time, e)))?
+        .timestamp_nanos();
+    let (request, rest) = quoted(rest)?;
+    let (status, rest) = field(rest)?;
+    let status: u16 = status
+        .parse()
+        .map_err(|_| invalid(&format!("bad status `{}`", status)))?;
+    let (bytes, rest) = field(rest)?;
+    let bytes = if bytes == "-" {
+        Value::null()
+    } else {
+        Value::from(
+            bytes
+                .parse::<u64>()
+                .map_err(|_| invalid(&format!("bad size `{}`", bytes)))?,
+        )
+    };
+
+    let mut res = Value::object_with_capacity(12);
+    res.try_insert("client", client);
+    res.try_insert("ident", dash_to_null(ident));
+    res.try_insert("user", dash_to_null(user));
+    res.try_insert("timestamp", timestamp);
+    let parts: Vec<&str> = request.split(' ').collect();
+    if let [method, path, protocol] = parts.as_slice() {
+        res.try_insert("method", Value::from(method.to_string()));
+        res.try_insert("path", Value::from(path.to_string()));
+        res.try_insert("protocol", Value::from(protocol.to_string()));
+    } else {
+        res.try_insert("method", Value::null());
+        res.try_insert("path", Value::null());
+        res.try_insert("protocol", Value::null());
+    }
+    res.try_insert("request", cow_to_value(request));
+    res.try_insert("status", status);
+    res.try_insert("bytes", bytes);
+    if !rest.trim().is_empty() {
+        let (referer, rest) = quoted(rest)?;
+        let (agent, rest) = quoted(rest)?;
+        if !rest.trim().is_empty() {
+            return Err(invalid("trailing characters"));
+        }
+        res.try_insert("referer", cow_to_value(referer));
+        res.try_insert("agent", cow_to_value(agent));
+    }
+    Ok(res)
+}
+
+fn push_field(res: &mut String, value: Option<&Value>) {
+    match value {
+        Some(Value::String(s)) if !s.is_empty() => res.push_str(s),
+        Some(v) if !v.is_null() && !v.is_str() => res.push_str(&v.encode()),
+        _ => res.push('-'),
+    }
+}
+
+fn push_quoted(res: &mut String, value: Option<&str>) {
+    res.push('"');
+    match value {
+        Some(s) if !s.is_empty() => {
+            for c in s.chars() {
+                if c == '"' || c == '\\' {
+                    res.push('\\');
+                }
+                res.push(c);
+            }
+        }
+        _ => res.push('-'),
+    }
+    res.push('"');
+}
+
+fn encode(data: &Value) -> Result<Vec<u8>> {
+    let obj = data
+        .as_object()
+        .ok_or_else(|| Error::from("Apache log lines must be encoded from records"))?;
+    let mut res = String::with_capacity(128);
+    push_field(&mut res, obj.get("client"));
+    res.push(' ');
+    push_field(&mut res, obj.get("ident"));
+    res.push(' ');
+    push_field(&mut res, obj.get("user"));
+    res.push_str(" [");
+    #[allow(clippy::cast_possible_wrap)]
+    let timestamp = obj
+        .get("timestamp")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| Error::from("Apache log lines require a `timestamp` in nanoseconds"))?
+        as i64;
+    let time = Utc.timestamp_nanos(timestamp);
+    res.push_str(&time.format(TIME_FORMAT).to_string());
+    res.push_str("] ");
+    let method = obj.get("method").and_then(Value::as_str);
+    let path = obj.get("path").and_then(Value::as_str);
+    if let (Some(method), Some(path)) = (method, path) {
+        let mut request = format!("{} {}", method, path);
+        if let Some(protocol) = obj.get("protocol").and_then(Value::as_str) {
+            request.push(' ');
+            request.push_str(protocol);
+        }
+        push_quoted(&mut res, Some(&request));
+    } else {
+        push_quoted(&mut res, obj.get("request").and_then(Value::as_str));
+    }
+    res.push(' ');
+    let status = obj
+        .get("status")
+        .and_then(Value::as_u16)
+        .ok_or_else(|| Error::from("Apache log lines require a numeric `status`"))?;
+    res.push_str(&status.to_string());
+    res.push(' ');
+    push_field(&mut res, obj.get("bytes"));
+    let referer = obj.get("referer").and_then(Value::as_str);
+    let agent = obj.get("agent").and_then(Value::as_str);
+    if referer.is_some() || agent.is_some() {
+        res.push(' ');
+        push_quoted(&mut res, referer);
+        res.push(' ');
+        push_quoted(&mut res, agent);
+    }
+    Ok(res.into_bytes())
+}
+
+impl Codec for ApacheLog {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "apache-log"
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let line = str::from_utf8(data)?;
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        decode(line).map(Some)
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        encode(data)
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    const COMBINED: &str = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /a.gif HTTP/1.0" 200 2326 "http://example.com/" "Mozilla/4.08 \"x\"""#;
+
+    #[test]
+    fn decode_combined() -> Result<()> {
+        let mut codec = ApacheLog {};
+        let mut data = COMBINED.as_bytes().to_vec();
+        assert_eq!(
+            Some(literal!({
+                "client": "127.0.0.1",
+                "ident": null,
+                "user": "frank",
+                "timestamp": 971_211_336_000_000_000_i64,
+                "method": "GET",
+                "path": "/a.gif",
+                "protocol": "HTTP/1.0",
+                "request": "GET /a.gif HTTP/1.0",
+                "status": 200_u16,
+                "bytes": 2326_u64,
+                "referer": "http://example.com/",
+                "agent": "Mozilla/4.08 \"x\""
+            })),
+            codec.decode(&mut data, 0)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decode_common() -> Result<()> {
+        let mut codec = ApacheLog {};
+        let mut data = br#"10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "-" 408 -"#.to_vec();
+        let decoded = codec.decode(&mut data, 0)?.expect("a line");
+        assert_eq!(Some(&Value::null()), decoded.get("method"));
+        assert_eq!(Some(&Value::null()), decoded.get("request"));
+        assert_eq!(Some(&Value::null()), decoded.get("bytes"));
+        assert_eq!(Some(408), decoded.get_u16("status"));
+        assert!(decoded.get("agent").is_none());
+
+        let mut data = br#"10.0.0.1 - - [10/Oct/2000] "GET / HTTP/1.1" 200 1"#.to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        let mut data =
+            br#"10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1 200 1"#.to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let mut codec = ApacheLog {};
+        let mut data = COMBINED.as_bytes().to_vec();
+        let decoded = codec.decode(&mut data, 0)?.expect("a line").into_static();
+        let mut encoded = codec.encode(&decoded)?;
+        assert_eq!(
+            r#"127.0.0.1 - frank [10/Oct/2000:20:55:36 +0000] "GET /a.gif HTTP/1.0" 200 2326 "http://example.com/" "Mozilla/4.08 \"x\"""#,
+            str::from_utf8(&encoded)?
+        );
+        assert_eq!(Some(decoded), codec.decode(&mut encoded, 0)?);
+        Ok(())
+    }
+}
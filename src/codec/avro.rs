@@ -0,0 +1,941 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Avro codec for binary encoded records.
+//!
+//! With a `registry` configured, data uses the Confluent wire format: a zero
+//! magic byte and the 4 byte big-endian schema id precede the record. Schemas
+//! for decoding are fetched by id and cached. Encoding uses the latest schema
+//! of `subject`, fetched once when the codec is created, or the configured
+//! `schema` registered as `schema_id`. Ids the registry fails to return
+//! are asked for again with an exponential backoff, data with them fails to
+//! decode meanwhile.
+//!
+//! Without a registry, records are encoded and decoded with the configured
+//! `schema` (inline JSON) or `schema_file`.
+//!
+//! Unions are decoded to the value of the chosen branch, enums to their
+//! symbol and fixed to bytes. Logical types are treated as their underlying
+//! type.
+//!
+//! ```yaml
+//! codec: avro
+//! codec_config:
+//!   registry: http://localhost:8081
+//!   subject: events-value
+//! ```
+
+use super::prelude::*;
+use crate::OpConfig;
+use beef::Cow;
+use halfbrown::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+use tremor_common::time::nanotime;
+use tremor_pipeline::ConfigImpl;
+
+const MAGIC: u8 = 0;
+/// initial delay before asking the registry again for a schema it failed to return
+const BACKOFF_NS: u64 = 1_000_000_000;
+/// longest delay between asking the registry for a schema it failed to return
+const MAX_BACKOFF_NS: u64 = 60_000_000_000;
+
+#[derive(Clone, Debug, Deserialize, Default)]
+pub(crate) struct Config {
+    /// inline schema JSON
+    schema: Option<String>,
+    /// path to a schema JSON file
+    schema_file: Option<String>,
+    /// base url of a Confluent schema registry
+    registry: Option<String>,
+    /// subject whose latest schema is used for encoding
+    subject: Option<String>,
+    /// registry id of the configured `schema`, used for encoding
+    schema_id: Option<u32>,
+    /// timeout for registry requests in milliseconds
+    #[serde(default = "d_timeout")]
+    timeout_ms: u64,
+}
+
+fn d_timeout() -> u64 {
+    1000
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(String, Vec<Field>),
+    Enum(String, Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(String, usize),
+    /// reference to a named type by its full name
+    Ref(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Field {
+    name: String,
+    schema: Schema,
+    default: Option<Value<'static>>,
+}
+
+/// A parsed schema with its named types
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Parsed {
+    root: Schema,
+    names: HashMap<String, Schema>,
+}
+
+impl Schema {
+    fn name(&self) -> &str {
+        match self {
+            Self::Null => "null",
+            Self::Boolean => "boolean",
+            Self::Int => "int",
+            Self::Long => "long",
+            Self::Float => "float",
+            Self::Double => "double",
+            Self::Bytes => "bytes",
+            Self::String => "string",
+            Self::Array(_) => "array",
+            Self::Map(_) => "map",
+            Self::Union(_) => "union",
+            Self::Record(name, _)
+            | Self::Enum(name, _)
+            | Self::Fixed(name, _)
+            | Self::Ref(name) => name,
+        }
+    }
+}
+
+fn qualify(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(ns) if !name.contains('.') && !ns.is_empty() => format!("{}.{}", ns, name),
+        _ => name.to_string(),
+    }
+}
+
+impl Parsed {
+    pub(crate) fn parse(raw: &str) -> Result<Self> {
+        let mut raw = raw.as_bytes().to_vec();
+        let json = tremor_value::parse_to_value(&mut raw)
+            .map_err(|e| Error::from(format!("Invalid avro schema: {}", e)))?;
+        let mut names = HashMap::new();
+        let root = parse_schema(&json, None, &mut names)?;
+        Ok(Self { root, names })
+    }
+
+    fn resolve<'schema>(&'schema self, schema: &'schema Schema) -> Result<&'schema Schema> {
+        if let Schema::Ref(name) = schema {
+            let short = name.rsplit('.').next().unwrap_or(name);
+            self.names
+                .get(name)
+                .or_else(|| self.names.get(short))
+                .ok_or_else(|| format!("Unknown avro type {}", name).into())
+        } else {
+            Ok(schema)
+        }
+    }
+}
+
+fn parse_schema(
+    json: &Value,
+    namespace: Option<&str>,
+    names: &mut HashMap<String, Schema>,
+) -> Result<Schema> {
+    if let Some(name) = json.as_str() {
+        return Ok(match name {
+            "null" => Schema::Null,
+            "boolean" => Schema::Boolean,
+            "int" => Schema::Int,
+            "long" => Schema::Long,
+            "float" => Schema::Float,
+            "double" => Schema::Double,
+            "bytes" => Schema::Bytes,
+            "string" => Schema::String,
+            other => Schema::Ref(qualify(other, namespace)),
+        });
+    }
+    if let Some(branches) = json.as_array() {
+        return Ok(Schema::Union(
+            branches
+                .iter()
+                .map(|b| parse_schema(b, namespace, names))
+                .collect::<Result<_>>()?,
+        ));
+    }
+    let ty = json
+        .get("type")
+        .ok_or_else(|| Error::from("Avro schema is missing `type`"))?;
+    let ty = if let Some(ty) = ty.as_str() {
+        ty
+    } else {
+        // e.g. `{"type": {"type": "array", ...}}`
+        return parse_schema(ty, namespace, names);
+    };
+    let named = |json: &Value| -> Result<(String, Option<String>)> {
+        let name = json
+            .get_str("name")
+            .ok_or_else(|| Error::from(format!("Avro {} is missing `name`", ty)))?;
+        let ns = json.get_str("namespace").or(namespace);
+        let full = qualify(name, ns);
+        let ns = full.rsplitn(2, '.').nth(1).map(ToString::to_string);
+        Ok((full, ns))
+    };
+    let schema = match ty {
+        "record" | "error" => {
+            let (full, ns) = named(json)?;
+            // register a reference first to allow recursive records
+            names.insert(full.clone(), Schema::Ref(full.clone()));
+            let mut fields = Vec::new();
+            for field in json
+                .get_array("fields")
+                .ok_or_else(|| Error::from(format!("Avro record {} is missing `fields`", full)))?
+            {
+                let name = field.get_str("name").ok_or_else(|| {
+                    Error::from(format!("Avro record {} has a field without `name`", full))
+                })?;
+                let schema = parse_schema(
+                    field.get("type").ok_or_else(|| {
+                        Error::from(format!("Avro field {} is missing `type`", name))
+                    })?,
+                    ns.as_deref(),
+                    names,
+                )?;
+                fields.push(Field {
+                    name: name.to_string(),
+                    schema,
+                    default: field.get("default").map(Value::clone_static),
+                });
+            }
+            let schema = Schema::Record(full.clone(), fields);
+            names.insert(full, schema.clone());
+            return Ok(schema);
+        }
+        "enum" => {
+            let (full, _) = named(json)?;
+            let symbols = json
+                .get_array("symbols")
+                .ok_or_else(|| Error::from(format!("Avro enum {} is missing `symbols`", full)))?
+                .iter()
+                .filter_map(|s| s.as_str().map(ToString::to_string))
+                .collect();
+            Schema::Enum(full, symbols)
+        }
+        "fixed" => {
+            let (full, _) = named(json)?;
+            let size = json
+                .get_u64("size")
+                .ok_or_else(|| Error::from(format!("Avro fixed {} is missing `size`", full)))?;
+            let size = usize::try_from(size)?;
+            Schema::Fixed(full, size)
+        }
+        "array" => Schema::Array(Box::new(parse_schema(
+            json.get("items")
+                .ok_or_else(|| Error::from("Avro array is missing `items`"))?,
+            namespace,
+            names,
+        )?)),
+        "map" => Schema::Map(Box::new(parse_schema(
+            json.get("values")
+                .ok_or_else(|| Error::from("Avro map is missing `values`"))?,
+            namespace,
+            names,
+        )?)),
+        // primitive types, possibly with a logical type
+        _ => return parse_schema(&Value::from(ty), namespace, names),
+    };
+    if let Schema::Enum(name, _) | Schema::Fixed(name, _) = &schema {
+        names.insert(name.clone(), schema.clone());
+    }
+    Ok(schema)
+}
+
+#[derive(Clone)]
+struct Registry {
+    url: String,
+    timeout: Duration,
+}
+
+impl Registry {
+    /// requests the percent-encoded `path` segments
+    fn get(&self, path: &[&str]) -> Result<Value<'static>> {
+        let mut url = url::Url::parse(&self.url)?;
+        url.path_segments_mut()
+            .map_err(|_| Error::from(format!("Invalid schema registry url {}", self.url)))?
+            .pop_if_empty()
+            .extend(path);
+        let res = attohttpc::get(url.as_str())
+            .timeout(self.timeout)
+            .send()
+            .map_err(|e| Error::from(format!("Schema registry request failed: {}", e)))?;
+        if !res.is_success() {
+            return Err(format!("Schema registry returned {} for {}", res.status(), url).into());
+        }
+        let mut body = res
+            .bytes()
+            .map_err(|e| Error::from(format!("Invalid schema registry response: {}", e)))?;
+        Ok(tremor_value::parse_to_value(&mut body)
+            .map_err(|e| Error::from(format!("Invalid schema registry response: {}", e)))?
+            .into_static())
+    }
+
+    fn schema(response: &Value) -> Result<Parsed> {
+        if response
+            .get_str("schemaType")
+            .map_or(false, |t| t != "AVRO")
+        {
+            return Err("Schema registry returned a non avro schema".into());
+        }
+        Parsed::parse(
+            response
+                .get_str("schema")
+                .ok_or_else(|| Error::from("Schema registry response is missing `schema`"))?,
+        )
+    }
+
+    fn by_id(&self, id: u32) -> Result<Parsed> {
+        Self::schema(&self.get(&["schemas", "ids", id.to_string().as_str()])?)
+    }
+
+    fn latest(&self, subject: &str) -> Result<(u32, Parsed)> {
+        let response = self.get(&["subjects", subject, "versions", "latest"])?;
+        let id = response
+            .get_u64("id")
+            .ok_or_else(|| Error::from("Schema registry response is missing `id`"))?;
+        let id = u32::try_from(id)?;
+        Ok((id, Self::schema(&response)?))
+    }
+}
+
+/// A schema id the registry failed to return
+#[derive(Clone, Debug)]
+struct Failed {
+    error: String,
+    backoff_ns: u64,
+    retry_ns: u64,
+}
+
+#[derive(Clone)]
+pub struct Avro {
+    registry: Option<Registry>,
+    /// schema used for decoding without a registry and for encoding
+    schema: Option<Arc<Parsed>>,
+    /// registry id of `schema`, written when encoding with a registry
+    schema_id: Option<u32>,
+    cache: HashMap<u32, Arc<Parsed>>,
+    /// schema ids not to ask the registry for before their retry
+    failed: HashMap<u32, Failed>,
+}
+
+impl Avro {
+    pub(crate) fn from_config(config: &Option<OpConfig>) -> Result<Self> {
+        let config = config
+            .as_ref()
+            .map(Config::new)
+            .transpose()?
+            .unwrap_or_default();
+        let raw =
+            match (&config.schema, &config.schema_file) {
+                (Some(raw), _) => Some(raw.clone()),
+                (None, Some(path)) => Some(std::fs::read_to_string(path).map_err(|e| {
+                    Error::from(format!("Unable to read avro schema {}: {}", path, e))
+                })?),
+                (None, None) => None,
+            };
+        let mut schema = raw.as_deref().map(Parsed::parse).transpose()?.map(Arc::new);
+        let mut schema_id = config.schema_id;
+        let registry = config.registry.map(|url| Registry {
+            url,
+            timeout: Duration::from_millis(config.timeout_ms),
+        });
+        match (&registry, &config.subject) {
+            (Some(registry), Some(subject)) => {
+                let (id, parsed) = registry.latest(subject)?;
+                schema_id = Some(id);
+                schema = Some(Arc::new(parsed));
+            }
+            (None, None) if schema.is_none() => {
+                return Err(
+                    "The avro codec requires a `schema`, `schema_file` or `registry`".into(),
+                )
+            }
+            (None, Some(_)) => {
+                return Err("The avro codec requires a `registry` for `subject`".into())
+            }
+            _ => (),
+        }
+        Ok(Self {
+            registry,
+            schema,
+            schema_id,
+            cache: HashMap::new(),
+            failed: HashMap::new(),
+        })
+    }
+}
+
+impl Codec for Avro {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "avro"
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn mime_types(&self) -> Vec<&str> {
+        vec!["avro/binary", "application/vnd.apache.avro+binary"]
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let data: &'input [u8] = data;
+        let (schema, data) = if let Some(registry) = &self.registry {
+            match data {
+                [MAGIC, a, b, c, d, rest @ ..] => {
+                    let id = u32::from_be_bytes([*a, *b, *c, *d]);
+                    let schema = if let Some(schema) = self.cache.get(&id) {
+                        schema.clone()
+                    } else {
+                        let now_ns = nanotime();
+                        if let Some(failed) = self.failed.get(&id) {
+                            if now_ns < failed.retry_ns {
+                                return Err(format!(
+                                    "Avro schema {} unavailable: {}",
+                                    id, failed.error
+                                )
+                                .into());
+                            }
+                        }
+                        match registry.by_id(id) {
+                            Ok(parsed) => {
+                                self.failed.remove(&id);
+                                let schema = Arc::new(parsed);
+                                self.cache.insert(id, schema.clone());
+                                schema
+                            }
+                            Err(e) => {
+                                let backoff_ns = self.failed.get(&id).map_or(BACKOFF_NS, |f| {
+                                    f.backoff_ns.saturating_mul(2).min(MAX_BACKOFF_NS)
+                                });
+                                self.failed.insert(
+                                    id,
+                                    Failed {
+                                        error: e.to_string(),
+                                        backoff_ns,
+                                        retry_ns: now_ns.saturating_add(backoff_ns),
+                                    },
+                                );
+                                return Err(e);
+                            }
+                        }
+                    };
+                    (schema, rest)
+                }
+                _ => return Err("Invalid avro data: missing schema registry header".into()),
+            }
+        } else if let Some(schema) = &self.schema {
+            (schema.clone(), data)
+        } else {
+            return Err("No avro schema configured".into());
+        };
+        let mut reader = Reader { data, pos: 0 };
+        let value = reader.read(&schema, &schema.root)?;
+        Ok(Some(value))
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let schema = self
+            .schema
+            .as_ref()
+            .ok_or_else(|| Error::from("No avro schema configured for encoding"))?;
+        let mut buf = Vec::with_capacity(128);
+        if self.registry.is_some() {
+            let id = self.schema_id.ok_or_else(|| {
+                Error::from("Encoding with a schema registry requires a `subject` or `schema_id`")
+            })?;
+            buf.push(MAGIC);
+            buf.extend_from_slice(&id.to_be_bytes());
+        }
+        write(&mut buf, schema, &schema.root, data)?;
+        Ok(buf)
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+struct Reader<'input> {
+    data: &'input [u8],
+    pos: usize,
+}
+
+impl<'input> Reader<'input> {
+    fn take(&mut self, len: usize) -> Result<&'input [u8]> {
+        let data: &'input [u8] = self.data;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= data.len())
+            .ok_or_else(|| Error::from("Invalid avro data: unexpected end of input"))?;
+        let res = &data[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    fn long(&mut self) -> Result<i64> {
+        let mut res: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.take(1)?[0];
+            res |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                #[allow(clippy::cast_possible_wrap)]
+                return Ok((res >> 1) as i64 ^ -((res & 1) as i64));
+            }
+        }
+        Err("Invalid avro data: varint too long".into())
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        usize::try_from(self.long()?).map_err(|_| "Invalid avro data: negative length".into())
+    }
+
+    /// reads the item count of the next array or map block
+    fn block(&mut self) -> Result<usize> {
+        let count = self.long()?;
+        if count < 0 {
+            // a negative count is followed by the block size in bytes
+            self.long()?;
+        }
+        usize::try_from(count.abs()).map_err(|_| "Invalid avro data: bad block count".into())
+    }
+
+    fn string(&mut self) -> Result<&'input str> {
+        let len = self.len()?;
+        Ok(std::str::from_utf8(self.take(len)?)?)
+    }
+
+    fn read(&mut self, parsed: &Parsed, schema: &Schema) -> Result<Value<'input>> {
+        Ok(match parsed.resolve(schema)? {
+            Schema::Null => Value::null(),
+            Schema::Boolean => Value::from(self.take(1)?[0] != 0),
+            Schema::Int | Schema::Long => Value::from(self.long()?),
+            Schema::Float => {
+                let mut b = [0_u8; 4];
+                b.copy_from_slice(self.take(4)?);
+                Value::from(f64::from(f32::from_le_bytes(b)))
+            }
+            Schema::Double => {
+                let mut b = [0_u8; 8];
+                b.copy_from_slice(self.take(8)?);
+                Value::from(f64::from_le_bytes(b))
+            }
+            Schema::Bytes => {
+                let len = self.len()?;
+                Value::Bytes(self.take(len)?.into())
+            }
+            Schema::String => Value::from(self.string()?),
+            Schema::Fixed(_, size) => Value::Bytes(self.take(*size)?.into()),
+            Schema::Enum(name, symbols) => {
+                let idx = self.len()?;
+                let symbol = symbols.get(idx).ok_or_else(|| {
+                    Error::from(format!("Invalid symbol {} for enum {}", idx, name))
+                })?;
+                Value::from(symbol.clone())
+            }
+            Schema::Record(_, fields) => {
+                let mut obj = Object::with_capacity(fields.len());
+                for field in fields {
+                    let v = self.read(parsed, &field.schema)?;
+                    obj.insert(field.name.clone().into(), v);
+                }
+                Value::from(obj)
+            }
+            Schema::Array(items) => {
+                let mut arr = Vec::new();
+                loop {
+                    let count = self.block()?;
+                    if count == 0 {
+                        break;
+                    }
+                    arr.reserve(count);
+                    for _ in 0..count {
+                        arr.push(self.read(parsed, items)?);
+                    }
+                }
+                Value::from(arr)
+            }
+            Schema::Map(values) => {
+                let mut obj = Object::new();
+                loop {
+                    let count = self.block()?;
+                    if count == 0 {
+                        break;
+                    }
+                    for _ in 0..count {
+                        let k = self.string()?;
+                        let v = self.read(parsed, values)?;
+                        obj.insert(Cow::from(k), v);
+                    }
+                }
+                Value::from(obj)
+            }
+            Schema::Union(branches) => {
+                let idx = self.len()?;
+                let branch = branches
+                    .get(idx)
+                    .ok_or_else(|| Error::from(format!("Invalid avro union branch {}", idx)))?;
+                self.read(parsed, branch)?
+            }
+            Schema::Ref(name) => return Err(format!("Unresolved avro type {}", name).into()),
+        })
+    }
+}
+
+fn write_long(buf: &mut Vec<u8>, n: i64) {
+    #[allow(clippy::cast_sign_loss)]
+    let mut z = ((n << 1) ^ (n >> 63)) as u64;
+    loop {
+        #[allow(clippy::cast_possible_truncation)]
+        let b = (z & 0x7f) as u8;
+        z >>= 7;
+        if z == 0 {
+            buf.push(b);
+            break;
+        }
+        buf.push(b | 0x80);
+    }
+}
+
+fn write_len(buf: &mut Vec<u8>, len: usize) -> Result<()> {
+    write_long(buf, i64::try_from(len)?);
+    Ok(())
+}
+
+fn write_bytes(buf: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+    write_len(buf, data.len())?;
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+fn as_bytes<'value>(value: &'value Value) -> Option<&'value [u8]> {
+    match value {
+        Value::Bytes(b) => Some(b),
+        Value::String(s) => Some(s.as_bytes()),
+        _ => None,
+    }
+}
+
+/// checks if a value can be encoded with the schema, used to pick union branches
+fn matches(parsed: &Parsed, schema: &Schema, value: &Value) -> bool {
+    match parsed.resolve(schema) {
+        Ok(Schema::Null) => value.is_null(),
+        Ok(Schema::Boolean) => value.is_bool(),
+        Ok(Schema::Int) => value.as_i32().is_some(),
+        Ok(Schema::Long) => value.as_i64().is_some(),
+        Ok(Schema::Float) | Ok(Schema::Double) => value.cast_f64().is_some(),
+        Ok(Schema::Bytes) => matches!(value, Value::Bytes(_)),
+        Ok(Schema::String) => value.is_str(),
+        Ok(Schema::Fixed(_, size)) => as_bytes(value).map_or(false, |b| b.len() == *size),
+        Ok(Schema::Enum(_, symbols)) => value
+            .as_str()
+            .map_or(false, |s| symbols.iter().any(|sym| sym == s)),
+        Ok(Schema::Record(_, fields)) => value.as_object().map_or(false, |o| {
+            fields.iter().all(|f| {
+                o.get(f.name.as_str()).map_or(
+                    f.default.is_some() || matches(parsed, &f.schema, &Value::null()),
+                    |v| matches(parsed, &f.schema, v),
+                )
+            })
+        }),
+        Ok(Schema::Map(_)) => value.is_object(),
+        Ok(Schema::Array(_)) => value.is_array(),
+        Ok(Schema::Union(_)) | Ok(Schema::Ref(_)) | Err(_) => false,
+    }
+}
+
+fn write(buf: &mut Vec<u8>, parsed: &Parsed, schema: &Schema, value: &Value) -> Result<()> {
+    let schema = parsed.resolve(schema)?;
+    let mismatch =
+        || -> Error { format!("Expected avro {}, got {}", schema.name(), value.encode()).into() };
+    match schema {
+        Schema::Null => {
+            if !value.is_null() {
+                return Err(mismatch());
+            }
+        }
+        Schema::Boolean => buf.push(u8::from(value.as_bool().ok_or_else(mismatch)?)),
+        Schema::Int => write_long(buf, i64::from(value.as_i32().ok_or_else(mismatch)?)),
+        Schema::Long => write_long(buf, value.as_i64().ok_or_else(mismatch)?),
+        #[allow(clippy::cast_possible_truncation)]
+        Schema::Float => {
+            buf.extend_from_slice(&(value.cast_f64().ok_or_else(mismatch)? as f32).to_le_bytes())
+        }
+        Schema::Double => {
+            buf.extend_from_slice(&value.cast_f64().ok_or_else(mismatch)?.to_le_bytes())
+        }
+        Schema::Bytes => write_bytes(buf, as_bytes(value).ok_or_else(mismatch)?)?,
+        Schema::String => write_bytes(buf, value.as_str().ok_or_else(mismatch)?.as_bytes())?,
+        Schema::Fixed(_, size) => {
+            let data = as_bytes(value)
+                .filter(|b| b.len() == *size)
+                .ok_or_else(mismatch)?;
+            buf.extend_from_slice(data);
+        }
+        Schema::Enum(_, symbols) => {
+            let symbol = value.as_str().ok_or_else(mismatch)?;
+            let idx = symbols
+                .iter()
+                .position(|s| s == symbol)
+                .ok_or_else(mismatch)?;
+            write_len(buf, idx)?;
+        }
+        Schema::Record(name, fields) => {
+            let obj = value.as_object().ok_or_else(mismatch)?;
+            for field in fields {
+                if let Some(v) = obj.get(field.name.as_str()) {
+                    write(buf, parsed, &field.schema, v)?;
+                } else if let Some(default) = &field.default {
+                    write(buf, parsed, &field.schema, default)?;
+                } else if matches(parsed, &field.schema, &Value::null()) {
+                    write(buf, parsed, &field.schema, &Value::null())?;
+                } else {
+                    return Err(
+                        format!("Missing field {} of avro record {}", field.name, name).into(),
+                    );
+                }
+            }
+        }
+        Schema::Array(items) => {
+            let arr = value.as_array().ok_or_else(mismatch)?;
+            if !arr.is_empty() {
+                write_len(buf, arr.len())?;
+                for v in arr {
+                    write(buf, parsed, items, v)?;
+                }
+            }
+            buf.push(0);
+        }
+        Schema::Map(values) => {
+            let obj = value.as_object().ok_or_else(mismatch)?;
+            if !obj.is_empty() {
+                write_len(buf, obj.len())?;
+                for (k, v) in obj.iter() {
+                    write_bytes(buf, k.as_bytes())?;
+                    write(buf, parsed, values, v)?;
+                }
+            }
+            buf.push(0);
+        }
+        Schema::Union(branches) => {
+            let idx = branches
+                .iter()
+                .position(|b| matches(parsed, b, value))
+                .ok_or_else(mismatch)?;
+            write_len(buf, idx)?;
+            if let Some(branch) = branches.get(idx) {
+                write(buf, parsed, branch, value)?;
+            }
+        }
+        Schema::Ref(name) => return Err(format!("Unresolved avro type {}", name).into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Event",
+        "namespace": "com.example",
+        "fields": [
+            {"name": "id", "type": "long"},
+            {"name": "name", "type": "string"},
+            {"name": "score", "type": "double"},
+            {"name": "tags", "type": {"type": "array", "items": "string"}},
+            {"name": "attrs", "type": {"type": "map", "values": "int"}},
+            {"name": "kind", "type": {"type": "enum", "name": "Kind", "symbols": ["A", "B"]}},
+            {"name": "parent", "type": ["null", "Event"], "default": null},
+            {"name": "flag", "type": "boolean", "default": true}
+        ]
+    }"#;
+
+    fn codec() -> Avro {
+        Avro {
+            registry: None,
+            schema: Some(Arc::new(Parsed::parse(SCHEMA).expect("valid schema"))),
+            schema_id: None,
+            cache: HashMap::new(),
+            failed: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn parse_schema() -> Result<()> {
+        let parsed = Parsed::parse(SCHEMA)?;
+        assert!(parsed.names.contains_key("com.example.Event"));
+        assert!(parsed.names.contains_key("com.example.Kind"));
+        if let Schema::Record(name, fields) = &parsed.root {
+            assert_eq!("com.example.Event", name);
+            assert_eq!(8, fields.len());
+        } else {
+            panic!("expected a record");
+        }
+        assert!(Parsed::parse(r#"{"type": "record"}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn wire_format() -> Result<()> {
+        let parsed = Parsed::parse(
+            r#"{"type": "record", "name": "R", "fields": [{"name": "a", "type": "long"}, {"name": "b", "type": "string"}]}"#,
+        )?;
+        let mut buf = vec![];
+        write(
+            &mut buf,
+            &parsed,
+            &parsed.root,
+            &literal!({"a": -64, "b": "hi"}),
+        )?;
+        // -64 zigzags to 127, a single varint byte
+        assert_eq!(vec![0x7f, 0x04, b'h', b'i'], buf);
+        let mut buf = vec![];
+        write(
+            &mut buf,
+            &parsed,
+            &parsed.root,
+            &literal!({"a": 64, "b": ""}),
+        )?;
+        assert_eq!(vec![0x80, 0x01, 0x00], buf);
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut codec = codec();
+        let event = literal!({
+            "id": 1_234_567_890_123_i64,
+            "name": "snot",
+            "score": 0.5,
+            "tags": ["a", "b"],
+            "attrs": {"x": 1, "y": -2},
+            "kind": "B",
+            "parent": {
+                "id": 1,
+                "name": "badger",
+                "score": 1.0,
+                "tags": [],
+                "attrs": {},
+                "kind": "A",
+                "parent": null,
+                "flag": false
+            },
+            "flag": true
+        });
+        let mut data = codec.encode(&event)?;
+        let decoded = codec.decode(data.as_mut_slice(), 0)?.expect("no value");
+        assert_eq!(event, decoded);
+        Ok(())
+    }
+
+    #[test]
+    fn defaults_and_errors() -> Result<()> {
+        let mut codec = codec();
+        let event = literal!({
+            "id": 1, "name": "n", "score": 2.0, "tags": [], "attrs": {}, "kind": "A"
+        });
+        let mut data = codec.encode(&event)?;
+        let decoded = codec.decode(data.as_mut_slice(), 0)?.expect("no value");
+        assert_eq!(Some(true), decoded.get_bool("flag"));
+        assert!(decoded.get("parent").map_or(false, Value::is_null));
+
+        assert!(codec.encode(&literal!({"id": 1})).is_err());
+        let mut kind = event.clone();
+        if let Some(obj) = kind.as_object_mut() {
+            obj.insert("kind".into(), Value::from("C"));
+        }
+        assert!(codec.encode(&kind).is_err());
+        let mut data = vec![0x02];
+        assert!(codec.decode(data.as_mut_slice(), 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn confluent_header() -> Result<()> {
+        let mut codec = codec();
+        codec.registry = Some(Registry {
+            url: "http://localhost:8081".to_string(),
+            timeout: Duration::from_millis(10),
+        });
+        assert!(codec.encode(&literal!({})).is_err());
+        codec.schema_id = Some(42);
+        let parsed = codec.schema.clone().expect("schema");
+        codec.cache.insert(42, parsed);
+        let event = literal!({
+            "id": 1, "name": "n", "score": 2.0, "tags": [], "attrs": {}, "kind": "A",
+            "parent": null, "flag": true
+        });
+        let mut data = codec.encode(&event)?;
+        assert_eq!(&[0, 0, 0, 0, 42], &data[..5]);
+        let decoded = codec.decode(data.as_mut_slice(), 0)?.expect("no value");
+        assert_eq!(event, decoded);
+        let mut data = vec![1, 0, 0, 0, 42];
+        assert!(codec.decode(data.as_mut_slice(), 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn backs_off_unavailable_schemas() {
+        let mut codec = codec();
+        codec.registry = Some(Registry {
+            url: "http://127.0.0.1:1".to_string(),
+            timeout: Duration::from_millis(10),
+        });
+        let mut data = vec![0, 0, 0, 0, 7, 0];
+        assert!(codec.decode(data.as_mut_slice(), 0).is_err());
+        assert_eq!(Some(BACKOFF_NS), codec.failed.get(&7).map(|f| f.backoff_ns));
+        // the registry isn't asked again before the retry
+        let mut data = vec![0, 0, 0, 0, 7, 0];
+        assert!(codec.decode(data.as_mut_slice(), 0).is_err());
+        assert_eq!(Some(BACKOFF_NS), codec.failed.get(&7).map(|f| f.backoff_ns));
+        if let Some(failed) = codec.failed.get_mut(&7) {
+            failed.retry_ns = 0;
+        }
+        let mut data = vec![0, 0, 0, 0, 7, 0];
+        assert!(codec.decode(data.as_mut_slice(), 0).is_err());
+        assert_eq!(
+            Some(BACKOFF_NS * 2),
+            codec.failed.get(&7).map(|f| f.backoff_ns)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::prelude::*;
+
+/// Decodes base64 into binary and encodes strings or binaries to base64,
+/// mainly useful as the first step in a codec chain
+#[derive(Clone)]
+pub struct Base64 {}
+
+impl Codec for Base64 {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "base64"
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let decoded = ::base64::decode(trim(data))?;
+        Ok(Some(Value::Bytes(decoded.into())))
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        if let Some(s) = data.as_str() {
+            Ok(::base64::encode(s).into_bytes())
+        } else if let Value::Bytes(b) = data {
+            Ok(::base64::encode(b).into_bytes())
+        } else {
+            Err(format!("Can't base64 encode {}", data.encode()).into())
+        }
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+/// strips surrounding whitespace, e.g. trailing newlines
+fn trim(data: &[u8]) -> &[u8] {
+    let start = data
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(data.len());
+    let end = data
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |e| e + 1);
+    data.get(start..end).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut codec = Base64 {};
+        let mut data = b"c25vdCBiYWRnZXI=\n".to_vec();
+        let decoded = codec.decode(&mut data, 0)?;
+        assert_eq!(Some(Value::Bytes(b"snot badger".to_vec().into())), decoded);
+        assert_eq!(
+            b"c25vdCBiYWRnZXI=".to_vec(),
+            codec.encode(&Value::from("snot badger"))?
+        );
+        assert!(codec.encode(&Value::from(42)).is_err());
+        Ok(())
+    }
+}
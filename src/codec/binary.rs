@@ -38,13 +38,20 @@ impl Codec for Binary {
     }
 
     fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let mut v = Vec::new();
+        self.encode_into(data, &mut v)?;
+        Ok(v)
+    }
+
+    fn encode_into(&self, data: &Value, dst: &mut Vec<u8>) -> Result<()> {
         if let Some(s) = data.as_str() {
-            Ok(s.as_bytes().to_vec())
+            dst.extend_from_slice(s.as_bytes());
         } else if let Value::Bytes(b) = data {
-            Ok(b.to_vec())
+            dst.extend_from_slice(b);
         } else {
-            Ok(simd_json::to_vec(&data)?)
+            data.write(dst)?;
         }
+        Ok(())
     }
 
     #[cfg(not(tarpaulin_include))]
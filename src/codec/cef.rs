@@ -0,0 +1,402 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ArcSight Common Event Format (CEF) codec
+//!
+//! ```text
+//! Sep 19 08:26:10 host CEF:0|Security|threatmanager|1.0|100|worm successfully stopped|10|src=10.0.0.1 dst=2.1.2.2 msg=Detected a threat. No action needed.
+//! ```
+//!
+//! decodes to
+//!
+//! ```json
+//! {
+//!   "prefix": "Sep 19 08:26:10 host",
+//!   "version": 0,
+//!   "device_vendor": "Security",
+//!   "device_product": "threatmanager",
+//!   "device_version": "1.0",
+//!   "signature_id": "100",
+//!   "name": "worm successfully stopped",
+//!   "severity": 10,
+//!   "extension": {
+//!     "src": "10.0.0.1",
+//!     "dst": "2.1.2.2",
+//!     "msg": "Detected a threat. No action needed."
+//!   }
+//! }
+//! ```
+//!
+//! `prefix` holds anything in front of `CEF:`, usually a syslog header, and is
+//! only present if there is one. Numeric severities are decoded as numbers,
+//! others like `High` as strings. Extension values are strings, `\=`, `\\`,
+//! `\n` and `\r` are unescaped, as are `\|` and `\\` in header fields.
+//!
+//! Encoding escapes the same characters, extension entries that are `null`
+//! are left out.
+
+use super::prelude::*;
+use std::borrow::Cow;
+use std::str;
+
+#[derive(Clone)]
+pub struct Cef {}
+
+fn invalid(reason: &str) -> Error {
+    format!("Invalid CEF event: {}", reason).into()
+}
+
+fn finish<'input>(
+    unescaped: Option<String>,
+    input: &'input str,
+    start: usize,
+    end: usize,
+) -> Cow<'input, str> {
+    let tail = input.get(start..end).unwrap_or_default();
+    match unescaped {
+        Some(mut s) => {
+            s.push_str(tail);
+            Cow::Owned(s)
+        }
+        None => Cow::Borrowed(tail),
+    }
+}
+
+/// Splits off the header field up to the next unescaped `|`, unescaping `\|`
+/// and `\\`, the rest is `None` if there is no further `|`
+pub(super) fn header_field(input: &str) -> (Cow<str>, Option<&str>) {
+    let mut unescaped: Option<String> = None;
+    let mut start = 0;
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '|' => return (finish(unescaped, input, start, i), input.get(i + 1..)),
+            '\\' => {
+                if let Some((j, escaped)) = chars.next() {
+                    if escaped == '|' || escaped == '\\' {
+                        let s = unescaped.get_or_insert_with(String::new);
+                        s.push_str(input.get(start..i).unwrap_or_default());
+                        s.push(escaped);
+                        start = j + 1;
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    (finish(unescaped, input, start, input.len()), None)
+}
+
+/// Takes the next header field off `rest`, failing once there are none left
+pub(super) fn next_field<'input>(
+    rest: &mut Option<&'input str>,
+    invalid: fn(&str) -> Error,
+) -> Result<Cow<'input, str>> {
+    let (field, r) = header_field(rest.ok_or_else(|| invalid("missing header fields"))?);
+    *rest = r;
+    Ok(field)
+}
+
+/// Splits a line at the `marker`, e.g. `CEF:`, into the trimmed prefix and
+/// the event starting after the marker
+pub(super) fn split_prefix<'input>(
+    line: &'input str,
+    marker: &str,
+) -> Option<(&'input str, &'input str)> {
+    let start = line.find(marker)?;
+    Some((
+        line.get(..start).unwrap_or_default().trim(),
+        line.get(start + marker.len()..).unwrap_or_default(),
+    ))
+}
+
+pub(super) fn cow_to_value(s: Cow<str>) -> Value {
+    match s {
+        Cow::Borrowed(s) => Value::from(s),
+        Cow::Owned(s) => Value::from(s),
+    }
+}
+
+fn unescape_value(value: &str) -> Value {
+    if !value.contains('\\') {
+        return Value::from(value);
+    }
+    let mut res = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => res.push('\n'),
+                Some('r') => res.push('\r'),
+                Some(c) if c == '=' || c == '\\' => res.push(c),
+                Some(c) => {
+                    res.push('\\');
+                    res.push(c);
+                }
+                None => res.push('\\'),
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    Value::from(res)
+}
+
+/// Parses the space separated `key=value` pairs of the extension, values may
+/// contain spaces, a key starts after the last space in front of an unescaped `=`
+fn extension(input: &str) -> Result<Value> {
+    let input = input.trim();
+    // key start and `=` position of each entry
+    let mut keys: Vec<(usize, usize)> = Vec::new();
+    let mut escaped = false;
+    for (i, b) in input.bytes().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match b {
+            b'\\' => escaped = true,
+            b'=' => {
+                let start = input
+                    .get(..i)
+                    .and_then(|s| s.rfind(' '))
+                    .map_or(0, |p| p + 1);
+                match keys.last() {
+                    // an `=` in a value
+                    Some((_, eq)) if start <= *eq || start == i => (),
+                    None if start > 0 || start == i => {
+                        return Err(invalid("extension has to start with a key"))
+                    }
+                    _ => keys.push((start, i)),
+                }
+            }
+            _ => (),
+        }
+    }
+    if keys.is_empty() && !input.is_empty() {
+        return Err(invalid("extension without `key=value` pairs"));
+    }
+    let mut res = Value::object_with_capacity(keys.len());
+    for (n, (start, eq)) in keys.iter().enumerate() {
+        let end = keys.get(n + 1).map_or(input.len(), |(next, _)| next - 1);
+        let key = input.get(*start..*eq).unwrap_or_default();
+        let value = input.get(eq + 1..end).unwrap_or_default().trim_end();
+        res.try_insert(key, unescape_value(value));
+    }
+    Ok(res)
+}
+
+fn decode(line: &str) -> Result<Value> {
+    let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+    let (prefix, event) = split_prefix(line, "CEF:").ok_or_else(|| invalid("missing `CEF:`"))?;
+    let mut res = Value::object_with_capacity(9);
+    if !prefix.is_empty() {
+        res.try_insert("prefix", prefix);
+    }
+    let mut rest = Some(event);
+    let version = next_field(&mut rest, invalid)?;
+    let version: u64 = version
+        .trim()
+        .parse()
+        .map_err(|_| invalid(&format!("bad version `{}`", version)))?;
+    res.try_insert("version", version);
+    res.try_insert(
+        "device_vendor",
+        cow_to_value(next_field(&mut rest, invalid)?),
+    );
+    res.try_insert(
+        "device_product",
+        cow_to_value(next_field(&mut rest, invalid)?),
+    );
+    res.try_insert(
+        "device_version",
+        cow_to_value(next_field(&mut rest, invalid)?),
+    );
+    res.try_insert(
+        "signature_id",
+        cow_to_value(next_field(&mut rest, invalid)?),
+    );
+    res.try_insert("name", cow_to_value(next_field(&mut rest, invalid)?));
+    let severity = next_field(&mut rest, invalid)?;
+    match severity.trim().parse::<u8>() {
+        Ok(severity) => res.try_insert("severity", severity),
+        Err(_) => res.try_insert("severity", cow_to_value(severity)),
+    };
+    res.try_insert("extension", extension(rest.unwrap_or_default())?);
+    Ok(res)
+}
+
+/// Writes a header field followed by `|`, escaping `|` and `\`
+pub(super) fn push_header(res: &mut String, value: Option<&Value>) {
+    if let Some(v) = value.filter(|v| !v.is_null()) {
+        let s = v
+            .as_str()
+            .map_or_else(|| Cow::Owned(v.encode()), Cow::Borrowed);
+        for c in s.chars() {
+            if c == '|' || c == '\\' {
+                res.push('\\');
+            }
+            res.push(c);
+        }
+    }
+    res.push('|');
+}
+
+fn encode(data: &Value) -> Result<Vec<u8>> {
+    let obj = data
+        .as_object()
+        .ok_or_else(|| Error::from("CEF events must be encoded from records"))?;
+    let mut res = String::with_capacity(128);
+    if let Some(prefix) = obj.get("prefix").and_then(Value::as_str) {
+        res.push_str(prefix);
+        res.push(' ');
+    }
+    res.push_str("CEF:");
+    res.push_str(
+        &obj.get("version")
+            .and_then(Value::as_u64)
+            .unwrap_or_default()
+            .to_string(),
+    );
+    res.push('|');
+    push_header(&mut res, obj.get("device_vendor"));
+    push_header(&mut res, obj.get("device_product"));
+    push_header(&mut res, obj.get("device_version"));
+    push_header(&mut res, obj.get("signature_id"));
+    push_header(&mut res, obj.get("name"));
+    push_header(&mut res, obj.get("severity"));
+    if let Some(extension) = obj.get("extension").and_then(Value::as_object) {
+        let mut first = true;
+        for (k, v) in extension.iter().filter(|(_, v)| !v.is_null()) {
+            if !first {
+                res.push(' ');
+            }
+            first = false;
+            res.push_str(k);
+            res.push('=');
+            let s = v
+                .as_str()
+                .map_or_else(|| Cow::Owned(v.encode()), Cow::Borrowed);
+            for c in s.chars() {
+                match c {
+                    '\n' => res.push_str("\\n"),
+                    '\r' => res.push_str("\\r"),
+                    '=' | '\\' => {
+                        res.push('\\');
+                        res.push(c);
+                    }
+                    c => res.push(c),
+                }
+            }
+        }
+    }
+    Ok(res.into_bytes())
+}
+
+impl Codec for Cef {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "cef"
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let line: &'input str = str::from_utf8(data)?;
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        decode(line).map(Some)
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        encode(data)
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    #[test]
+    fn decode_syslog() -> Result<()> {
+        let mut codec = Cef {};
+        let mut data = b"Sep 19 08:26:10 host CEF:0|Security|threatmanager|1.0|100|worm successfully stopped|10|src=10.0.0.1 dst=2.1.2.2 msg=Detected a threat. No action needed.".to_vec();
+        assert_eq!(
+            Some(literal!({
+                "prefix": "Sep 19 08:26:10 host",
+                "version": 0_u64,
+                "device_vendor": "Security",
+                "device_product": "threatmanager",
+                "device_version": "1.0",
+                "signature_id": "100",
+                "name": "worm successfully stopped",
+                "severity": 10_u8,
+                "extension": {
+                    "src": "10.0.0.1",
+                    "dst": "2.1.2.2",
+                    "msg": "Detected a threat. No action needed."
+                }
+            })),
+            codec.decode(&mut data, 0)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decode_escapes() -> Result<()> {
+        let mut codec = Cef {};
+        let mut data = br#"CEF:0|security|threat\|manager|1.0|100|detected a \\ in message|High|act=blocked a \= sign path=C:\\temp cs1=a\nb"#.to_vec();
+        let decoded = codec.decode(&mut data, 0)?.expect("an event");
+        assert_eq!(Some("threat|manager"), decoded.get_str("device_product"));
+        assert_eq!(Some("detected a \\ in message"), decoded.get_str("name"));
+        assert_eq!(Some("High"), decoded.get_str("severity"));
+        assert_eq!(
+            Some(&literal!({"act": "blocked a = sign", "path": "C:\\temp", "cs1": "a\nb"})),
+            decoded.get("extension")
+        );
+
+        let mut data = b"CEF:0|a|b|c|d|e|1|".to_vec();
+        let decoded = codec.decode(&mut data, 0)?.expect("an event");
+        assert_eq!(Some(&literal!({})), decoded.get("extension"));
+
+        let mut data = b"CEF:0|a|b|c".to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        let mut data = b"CEF:x|a|b|c|d|e|1|".to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        let mut data = b"CEF:0|a|b|c|d|e|1|junk k=v".to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let mut codec = Cef {};
+        let line = r#"CEF:0|security|threat\|manager|1.0|100|name|7|act=blocked a \= sign msg=line\nbreak"#;
+        let mut data = line.as_bytes().to_vec();
+        let decoded = codec.decode(&mut data, 0)?.expect("an event").into_static();
+        let mut encoded = codec.encode(&decoded)?;
+        assert_eq!(line, str::from_utf8(&encoded)?);
+        assert_eq!(Some(decoded), codec.decode(&mut encoded, 0)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::prelude::*;
+
+/// An ordered chain of codecs.
+///
+/// On decoding the first codec decodes the raw data, every following codec
+/// decodes the string or binary value produced by its predecessor. Encoding
+/// runs the chain in reverse, handing the output of each codec to the previous
+/// one as a string, or as binary if it isn't valid UTF-8.
+pub struct Chain {
+    name: String,
+    codecs: Vec<Box<dyn Codec>>,
+}
+
+impl Chain {
+    pub(crate) fn new(codecs: Vec<Box<dyn Codec>>) -> Result<Self> {
+        if codecs.len() < 2 {
+            return Err("A codec chain requires at least two codecs".into());
+        }
+        let name = codecs
+            .iter()
+            .map(|c| c.name())
+            .collect::<Vec<_>>()
+            .join("|");
+        Ok(Self { name, codecs })
+    }
+}
+
+impl Clone for Chain {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            codecs: self.codecs.iter().map(|c| c.boxed_clone()).collect(),
+        }
+    }
+}
+
+impl Codec for Chain {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let mut codecs = self.codecs.iter_mut();
+        let mut value = match codecs.next() {
+            Some(first) => match first.decode(data, ingest_ns)? {
+                Some(v) => v.into_static(),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        for codec in codecs {
+            let mut raw = match value {
+                Value::String(s) => s.into_owned().into_bytes(),
+                Value::Bytes(b) => b.into_owned(),
+                other => {
+                    return Err(format!(
+                        "Codec {} in chain {} got a non string value: {}",
+                        codec.name(),
+                        self.name,
+                        other.encode()
+                    )
+                    .into())
+                }
+            };
+            value = match codec.decode(&mut raw, ingest_ns)? {
+                Some(v) => v.into_static(),
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(value))
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let mut codecs = self.codecs.iter().rev();
+        let mut raw = match codecs.next() {
+            Some(last) => last.encode(data)?,
+            None => return Ok(vec![]),
+        };
+        for codec in codecs {
+            let value = match String::from_utf8(raw) {
+                Ok(s) => Value::from(s),
+                Err(e) => Value::Bytes(e.into_bytes().into()),
+            };
+            raw = codec.encode(&value)?;
+        }
+        Ok(raw)
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::{base64::Base64, json::Json, string};
+    use tremor_value::literal;
+
+    #[test]
+    fn base64_json() -> Result<()> {
+        let mut chain = Chain::new(vec![Box::new(Base64 {}), Box::new(Json::default())])?;
+        let value = literal!({"snot": "badger"});
+        let mut encoded = chain.encode(&value)?;
+        assert_eq!(b"eyJzbm90IjoiYmFkZ2VyIn0=".to_vec(), encoded);
+        assert_eq!(Some(value), chain.decode(&mut encoded, 0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn double_json() -> Result<()> {
+        let mut chain = Chain::new(vec![Box::new(Json::default()), Box::new(Json::default())])?;
+        let mut data = br#""{\"snot\":\"badger\"}""#.to_vec();
+        assert_eq!(
+            Some(literal!({"snot": "badger"})),
+            chain.decode(&mut data, 0)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> Result<()> {
+        assert!(Chain::new(vec![Box::new(string::String {})]).is_err());
+        let mut chain = Chain::new(vec![Box::new(Json::default()), Box::new(Json::default())])?;
+        let mut data = br#"{"snot": "badger"}"#.to_vec();
+        assert!(chain.decode(&mut data, 0).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,361 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CSV codec, decoding a single record per event, usually combined with the
+//! `lines` preprocessor.
+//!
+//! Records are decoded into objects keyed by the configured `columns`, or
+//! by the column names of the first record if `header` is set. Without
+//! either, records are decoded into arrays. Fields are decoded as strings.
+//!
+//! Objects are encoded in the order of the `columns` or the header, missing
+//! fields are left empty. Arrays are encoded as they are. The header row is
+//! not written on encoding.
+//!
+//! ```yaml
+//! codec: csv
+//! codec_config:
+//!   delimiter: ";"
+//!   quote: "'"
+//!   columns: [time, host, message]
+//! ```
+
+use super::prelude::*;
+use crate::OpConfig;
+use std::str;
+use tremor_pipeline::ConfigImpl;
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Config {
+    /// field delimiter
+    #[serde(default = "d_delimiter")]
+    delimiter: char,
+    /// quote character for fields containing delimiters, quotes or newlines
+    #[serde(default = "d_quote")]
+    quote: char,
+    /// column names
+    #[serde(default)]
+    columns: Option<Vec<String>>,
+    /// the first record is a header row, if no `columns` are given the
+    /// column names are taken from it
+    #[serde(default)]
+    header: bool,
+}
+
+fn d_delimiter() -> char {
+    ','
+}
+
+fn d_quote() -> char {
+    '"'
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            delimiter: d_delimiter(),
+            quote: d_quote(),
+            columns: None,
+            header: false,
+        }
+    }
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone)]
+pub struct Csv {
+    delimiter: char,
+    quote: char,
+    columns: Option<Vec<String>>,
+    /// a header row still has to be read
+    expect_header: bool,
+}
+
+impl Csv {
+    pub(crate) fn from_config(config: &Option<OpConfig>) -> Result<Self> {
+        let config = config
+            .as_ref()
+            .map(Config::new)
+            .transpose()?
+            .unwrap_or_default();
+        if config.delimiter == config.quote {
+            return Err("CSV delimiter and quote must differ".into());
+        }
+        if ['\r', '\n'].contains(&config.delimiter) || ['\r', '\n'].contains(&config.quote) {
+            return Err("CSV delimiter and quote can not be line breaks".into());
+        }
+        Ok(Self {
+            delimiter: config.delimiter,
+            quote: config.quote,
+            columns: config.columns,
+            expect_header: config.header,
+        })
+    }
+
+    fn split<'record>(&self, record: &'record str) -> Result<Vec<Value<'record>>> {
+        let mut fields = Vec::new();
+        let mut chars = record.char_indices().peekable();
+        loop {
+            match chars.peek().copied() {
+                Some((_, c)) if c == self.quote => {
+                    chars.next();
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, c)) if c == self.quote => {
+                                // quotes inside quoted fields are doubled
+                                if chars.peek().map(|(_, c)| *c) == Some(self.quote) {
+                                    chars.next();
+                                    field.push(c);
+                                } else {
+                                    break;
+                                }
+                            }
+                            Some((_, c)) => field.push(c),
+                            None => return Err("Unterminated quoted CSV field".into()),
+                        }
+                    }
+                    fields.push(Value::from(field));
+                    match chars.next() {
+                        None => return Ok(fields),
+                        Some((_, c)) if c == self.delimiter => (),
+                        Some((i, _)) => {
+                            return Err(format!(
+                                "Unexpected character after quoted CSV field at {}",
+                                i
+                            )
+                            .into())
+                        }
+                    }
+                }
+                Some((start, _)) => {
+                    let end = loop {
+                        match chars.next() {
+                            Some((i, c)) if c == self.delimiter => break Some(i),
+                            Some(_) => (),
+                            None => break None,
+                        }
+                    };
+                    if let Some(end) = end {
+                        fields.push(Value::from(&record[start..end]));
+                    } else {
+                        fields.push(Value::from(&record[start..]));
+                        return Ok(fields);
+                    }
+                }
+                // the record ended with a delimiter
+                None => {
+                    fields.push(Value::from(""));
+                    return Ok(fields);
+                }
+            }
+        }
+    }
+
+    fn write_field(&self, out: &mut String, value: &Value) {
+        let raw = match value {
+            Value::String(s) => s.to_string(),
+            Value::Static(StaticNode::Null) => String::new(),
+            Value::Static(s) => s.to_string(),
+            other => other.encode(),
+        };
+        if raw.contains(|c| c == self.delimiter || c == self.quote || c == '\r' || c == '\n') {
+            out.push(self.quote);
+            for c in raw.chars() {
+                if c == self.quote {
+                    out.push(c);
+                }
+                out.push(c);
+            }
+            out.push(self.quote);
+        } else {
+            out.push_str(&raw);
+        }
+    }
+}
+
+impl Codec for Csv {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn mime_types(&self) -> Vec<&str> {
+        vec!["text/csv"]
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let data: &'input [u8] = data;
+        let record = str::from_utf8(data)?.trim_end_matches(&['\r', '\n'][..]);
+        if record.is_empty() {
+            return Ok(None);
+        }
+        let fields = self.split(record)?;
+        if self.expect_header {
+            self.expect_header = false;
+            if self.columns.is_none() {
+                self.columns = Some(
+                    fields
+                        .iter()
+                        .map(|f| f.as_str().unwrap_or_default().to_string())
+                        .collect(),
+                );
+            }
+            return Ok(None);
+        }
+        if let Some(columns) = &self.columns {
+            if fields.len() > columns.len() {
+                return Err(format!(
+                    "CSV record has {} fields, expected at most {}",
+                    fields.len(),
+                    columns.len()
+                )
+                .into());
+            }
+            let mut obj = Object::with_capacity(columns.len());
+            for (column, field) in columns.iter().zip(fields) {
+                obj.insert(column.clone().into(), field);
+            }
+            Ok(Some(Value::from(obj)))
+        } else {
+            Ok(Some(Value::from(fields)))
+        }
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let mut out = String::with_capacity(128);
+        match (data, &self.columns) {
+            (Value::Array(fields), _) => {
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push(self.delimiter);
+                    }
+                    self.write_field(&mut out, field);
+                }
+            }
+            (Value::Object(obj), Some(columns)) => {
+                for (i, column) in columns.iter().enumerate() {
+                    if i > 0 {
+                        out.push(self.delimiter);
+                    }
+                    if let Some(field) = obj.get(column.as_str()) {
+                        self.write_field(&mut out, field);
+                    }
+                }
+            }
+            (Value::Object(_), None) => {
+                return Err("CSV records can only be encoded from objects with `columns`".into())
+            }
+            (other, _) => self.write_field(&mut out, other),
+        }
+        Ok(out.into_bytes())
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    fn codec(config: Value) -> Result<Csv> {
+        let config: OpConfig = serde_yaml::from_str(&config.encode())?;
+        Csv::from_config(&Some(config))
+    }
+
+    #[test]
+    fn decode_columns() -> Result<()> {
+        let mut codec = codec(literal!({"columns": ["a", "b", "c"]}))?;
+        let mut raw = b"1,\"snot, \"\"badger\"\"\",\r\n".to_vec();
+        assert_eq!(
+            Some(literal!({"a": "1", "b": "snot, \"badger\"", "c": ""})),
+            codec.decode(&mut raw, 0)?
+        );
+        let mut raw = b"1".to_vec();
+        assert_eq!(Some(literal!({"a": "1"})), codec.decode(&mut raw, 0)?);
+        let mut raw = b"1,2,3,4".to_vec();
+        assert!(codec.decode(&mut raw, 0).is_err());
+        let mut raw = b"\"1,2".to_vec();
+        assert!(codec.decode(&mut raw, 0).is_err());
+        let mut raw = b"\"1\"2".to_vec();
+        assert!(codec.decode(&mut raw, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_header() -> Result<()> {
+        let mut codec = codec(literal!({"delimiter": ";", "quote": "'", "header": true}))?;
+        let mut raw = b"host;'message'\n".to_vec();
+        assert_eq!(None, codec.decode(&mut raw, 0)?);
+        let mut raw = b"snot;'a;b'\n".to_vec();
+        assert_eq!(
+            Some(literal!({"host": "snot", "message": "a;b"})),
+            codec.decode(&mut raw, 0)?
+        );
+        let mut raw = b"\n".to_vec();
+        assert_eq!(None, codec.decode(&mut raw, 0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn decode_arrays() -> Result<()> {
+        let mut codec = Csv::from_config(&None)?;
+        let mut raw = b"a,,b".to_vec();
+        assert_eq!(Some(literal!(["a", "", "b"])), codec.decode(&mut raw, 0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn encode() -> Result<()> {
+        let codec = codec(literal!({"columns": ["a", "b", "c", "d"]}))?;
+        let data = literal!({"a": 1, "b": "snot, \"badger\"", "d": [1]});
+        assert_eq!(
+            b"1,\"snot, \"\"badger\"\"\",,\"[1]\"".to_vec(),
+            codec.encode(&data)?
+        );
+        assert_eq!(
+            b"x,null,".to_vec(),
+            codec.encode(&literal!(["x", "null", null]))?
+        );
+        assert!(Csv::from_config(&None)?
+            .encode(&literal!({"a": 1}))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut codec = codec(literal!({"columns": ["a", "b"]}))?;
+        let data = literal!({"a": "x\ny", "b": "'\""});
+        let mut raw = codec.encode(&data)?;
+        assert_eq!(Some(data), codec.decode(&mut raw, 0)?);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(codec(literal!({"delimiter": "\""})).is_err());
+        assert!(codec(literal!({"quote": "\n"})).is_err());
+    }
+}
@@ -0,0 +1,627 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Binary dissector codec, decoding simple binary formats described by a
+//! list of `fields` into records keyed by the field names.
+//!
+//! Fields follow each other unless they give an `offset` from the start of
+//! the enclosing struct. Integers (`u8` to `u64`, `i8` to `i64`) and floats
+//! (`f32`, `f64`) are read in the `endian` of the field, its struct or the
+//! codec, `big` by default. `bytes`, `string`, `struct` and `tlv` fields span
+//! `length` bytes, given as a number or as the name of an integer field
+//! decoded before, or the rest of the enclosing struct.
+//!
+//! `struct` fields are decoded from their own `fields`. `tlv` fields are a
+//! sequence of tag, length and value entries, with the tag and length read
+//! as the `tag` and `size` integer types, `u8` by default. The values are
+//! decoded as the field configured for their tag in `tags`, the name of
+//! which they are keyed by, unknown tags are kept as bytes keyed by the tag
+//! number. Repeated tags are collected into arrays.
+//!
+//! Records are encoded the same way, fields with a fixed `length` are
+//! padded with zeros and skipped bytes before an `offset` are zero.
+//!
+//! ```yaml
+//! codec: dissector
+//! codec_config:
+//!   endian: big
+//!   fields:
+//!     - name: version
+//!       type: u8
+//!     - name: length
+//!       type: u16
+//!     - name: host
+//!       type: string
+//!       length: 16
+//!     - name: payload
+//!       type: bytes
+//!       length: length
+//!     - name: options
+//!       type: tlv
+//!       tag: u8
+//!       size: u16
+//!       tags:
+//!         1:
+//!           name: ttl
+//!           type: u32
+//! ```
+
+use super::prelude::*;
+use crate::OpConfig;
+use halfbrown::HashMap;
+use std::convert::TryFrom;
+use std::str;
+use tremor_pipeline::ConfigImpl;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Endian {
+    Big,
+    Little,
+}
+
+/// Integer types of TLV tags and lengths
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Int {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl Int {
+    fn size(self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::U16 => 2,
+            Self::U32 => 4,
+            Self::U64 => 8,
+        }
+    }
+}
+
+impl Default for Int {
+    fn default() -> Self {
+        Self::U8
+    }
+}
+
+/// The length of a field, fixed or taken from an integer field
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Length {
+    Fixed(usize),
+    Field(String),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum Kind {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Bytes {
+        #[serde(default)]
+        length: Option<Length>,
+    },
+    String {
+        #[serde(default)]
+        length: Option<Length>,
+    },
+    Struct {
+        #[serde(default)]
+        length: Option<Length>,
+        fields: Vec<Field>,
+    },
+    Tlv {
+        #[serde(default)]
+        length: Option<Length>,
+        #[serde(default)]
+        tag: Int,
+        #[serde(default)]
+        size: Int,
+        #[serde(default)]
+        tags: HashMap<u64, Field>,
+    },
+}
+
+impl Kind {
+    /// the size of numbers, `None` for variable sized kinds
+    fn size(&self) -> Option<usize> {
+        match self {
+            Self::U8 | Self::I8 => Some(1),
+            Self::U16 | Self::I16 => Some(2),
+            Self::U32 | Self::I32 | Self::F32 => Some(4),
+            Self::U64 | Self::I64 | Self::F64 => Some(8),
+            _ => None,
+        }
+    }
+
+    fn length(&self) -> Option<&Length> {
+        match self {
+            Self::Bytes { length }
+            | Self::String { length }
+            | Self::Struct { length, .. }
+            | Self::Tlv { length, .. } => length.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Field {
+    name: String,
+    /// offset from the start of the enclosing struct
+    #[serde(default)]
+    offset: Option<usize>,
+    /// endianness of the field, defaults to the one of the enclosing struct
+    #[serde(default)]
+    endian: Option<Endian>,
+    #[serde(flatten)]
+    kind: Kind,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Config {
+    #[serde(default = "d_endian")]
+    endian: Endian,
+    fields: Vec<Field>,
+}
+
+fn d_endian() -> Endian {
+    Endian::Big
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone)]
+pub struct Dissector {
+    config: Config,
+}
+
+impl Dissector {
+    pub(crate) fn from_config(config: &Option<OpConfig>) -> Result<Self> {
+        let config = config
+            .as_ref()
+            .ok_or_else(|| Error::from("The dissector codec requires a config with `fields`"))
+            .and_then(|c| Ok(Config::new(c)?))?;
+        Ok(Self { config })
+    }
+}
+
+fn read_uint(data: &[u8], endian: Endian) -> u64 {
+    let fold = |v: u64, b: &u8| v << 8 | u64::from(*b);
+    match endian {
+        Endian::Big => data.iter().fold(0, fold),
+        Endian::Little => data.iter().rev().fold(0, fold),
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn write_uint(v: u64, size: usize, endian: Endian, dst: &mut Vec<u8>) {
+    match endian {
+        Endian::Big => dst.extend((0..size).rev().map(|i| (v >> (8 * i)) as u8)),
+        Endian::Little => dst.extend((0..size).map(|i| (v >> (8 * i)) as u8)),
+    }
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn sign_extend(v: u64, size: usize) -> i64 {
+    let shift = 64 - 8 * size;
+    ((v << shift) as i64) >> shift
+}
+
+/// slices `len` bytes off `data` at `pos`
+fn slice<'input>(data: &'input [u8], pos: usize, len: usize, name: &str) -> Result<&'input [u8]> {
+    pos.checked_add(len)
+        .and_then(|end| data.get(pos..end))
+        .ok_or_else(|| {
+            format!(
+                "Field {} needs {} bytes at offset {}, but only {} are left",
+                name,
+                len,
+                pos,
+                data.len().saturating_sub(pos)
+            )
+            .into()
+        })
+}
+
+fn decode_fields<'input>(
+    fields: &[Field],
+    endian: Endian,
+    data: &'input [u8],
+) -> Result<Value<'input>> {
+    let mut record = Value::object_with_capacity(fields.len());
+    let mut pos = 0;
+    for field in fields {
+        pos = field.offset.unwrap_or(pos);
+        let len = match (field.kind.size(), field.kind.length()) {
+            (Some(size), _) => size,
+            (None, Some(Length::Fixed(len))) => *len,
+            (None, Some(Length::Field(name))) => record
+                .get(name.as_str())
+                .and_then(ValueAccess::as_usize)
+                .ok_or_else(|| {
+                    Error::from(format!(
+                        "The length of field {} is no unsigned integer field decoded before",
+                        field.name
+                    ))
+                })?,
+            (None, None) => data.len().saturating_sub(pos),
+        };
+        let bytes = slice(data, pos, len, &field.name)?;
+        let value = decode_value(
+            &field.kind,
+            field.endian.unwrap_or(endian),
+            bytes,
+            &field.name,
+        )?;
+        record.try_insert(field.name.clone(), value);
+        pos += len;
+    }
+    Ok(record)
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn decode_value<'input>(
+    kind: &Kind,
+    endian: Endian,
+    data: &'input [u8],
+    name: &str,
+) -> Result<Value<'input>> {
+    let uint = || read_uint(data, endian);
+    Ok(match kind {
+        Kind::U8 | Kind::U16 | Kind::U32 | Kind::U64 => Value::from(uint()),
+        Kind::I8 | Kind::I16 | Kind::I32 | Kind::I64 => {
+            Value::from(sign_extend(uint(), data.len()))
+        }
+        Kind::F32 => Value::from(f64::from(f32::from_bits(uint() as u32))),
+        Kind::F64 => Value::from(f64::from_bits(uint())),
+        Kind::Bytes { .. } => Value::Bytes(data.into()),
+        Kind::String { .. } => {
+            // fixed length strings are padded with zeros
+            let end = data
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or_else(|| data.len());
+            Value::from(
+                str::from_utf8(&data[..end])
+                    .map_err(|e| Error::from(format!("Field {} is no string: {}", name, e)))?,
+            )
+        }
+        Kind::Struct { fields, .. } => decode_fields(fields, endian, data)?,
+        Kind::Tlv {
+            tag, size, tags, ..
+        } => {
+            let mut record = Value::object();
+            let mut pos = 0;
+            while pos < data.len() {
+                let t = read_uint(slice(data, pos, tag.size(), name)?, endian);
+                pos += tag.size();
+                let len = read_uint(slice(data, pos, size.size(), name)?, endian);
+                pos += size.size();
+                let len = usize::try_from(len)
+                    .map_err(|_| Error::from(format!("Invalid TLV length in field {}", name)))?;
+                let bytes = slice(data, pos, len, name)?;
+                pos += len;
+                let (key, value) = if let Some(field) = tags.get(&t) {
+                    let endian = field.endian.unwrap_or(endian);
+                    (
+                        field.name.clone(),
+                        decode_value(&field.kind, endian, bytes, &field.name)?,
+                    )
+                } else {
+                    (t.to_string(), Value::Bytes(bytes.into()))
+                };
+                if let Some(existing) = record.get_mut(key.as_str()) {
+                    if let Some(values) = existing.as_array_mut() {
+                        values.push(value);
+                    } else {
+                        let first = std::mem::take(existing);
+                        *existing = Value::from(vec![first, value]);
+                    }
+                } else {
+                    record.try_insert(key, value);
+                }
+            }
+            record
+        }
+    })
+}
+
+fn encode_fields(fields: &[Field], endian: Endian, data: &Value, dst: &mut Vec<u8>) -> Result<()> {
+    let start = dst.len();
+    for field in fields {
+        if let Some(offset) = field.offset {
+            let target = start + offset;
+            if dst.len() > target {
+                return Err(format!(
+                    "Field {} at offset {} overlaps the field before",
+                    field.name, offset
+                )
+                .into());
+            }
+            dst.resize(target, 0);
+        }
+        let value = data
+            .get(field.name.as_str())
+            .ok_or_else(|| Error::from(format!("Missing field {}", field.name)))?;
+        let before = dst.len();
+        encode_value(
+            &field.kind,
+            field.endian.unwrap_or(endian),
+            value,
+            &field.name,
+            dst,
+        )?;
+        if let Some(Length::Fixed(len)) = field.kind.length() {
+            let written = dst.len() - before;
+            if written > *len {
+                return Err(format!(
+                    "Field {} is {} bytes long, more than its length {}",
+                    field.name, written, len
+                )
+                .into());
+            }
+            dst.resize(before + len, 0);
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+fn encode_value(
+    kind: &Kind,
+    endian: Endian,
+    value: &Value,
+    name: &str,
+    dst: &mut Vec<u8>,
+) -> Result<()> {
+    let invalid = || {
+        Error::from(format!(
+            "Invalid value for field {}: {}",
+            name,
+            value.encode()
+        ))
+    };
+    match kind {
+        Kind::U8 | Kind::U16 | Kind::U32 | Kind::U64 => {
+            let size = kind.size().unwrap_or_default();
+            let v = value.as_u64().ok_or_else(invalid)?;
+            if size < 8 && v >> (8 * size) != 0 {
+                return Err(invalid());
+            }
+            write_uint(v, size, endian, dst);
+        }
+        Kind::I8 | Kind::I16 | Kind::I32 | Kind::I64 => {
+            let size = kind.size().unwrap_or_default();
+            let v = value.as_i64().ok_or_else(invalid)?;
+            if sign_extend(v as u64, size) != v {
+                return Err(invalid());
+            }
+            write_uint(v as u64, size, endian, dst);
+        }
+        Kind::F32 => {
+            let v = value.cast_f64().ok_or_else(invalid)? as f32;
+            write_uint(u64::from(v.to_bits()), 4, endian, dst);
+        }
+        Kind::F64 => {
+            let v = value.cast_f64().ok_or_else(invalid)?;
+            write_uint(v.to_bits(), 8, endian, dst);
+        }
+        Kind::Bytes { .. } => {
+            if let Value::Bytes(b) = value {
+                dst.extend_from_slice(b);
+            } else {
+                dst.extend_from_slice(value.as_str().ok_or_else(invalid)?.as_bytes());
+            }
+        }
+        Kind::String { .. } => {
+            dst.extend_from_slice(value.as_str().ok_or_else(invalid)?.as_bytes())
+        }
+        Kind::Struct { fields, .. } => encode_fields(fields, endian, value, dst)?,
+        Kind::Tlv {
+            tag, size, tags, ..
+        } => {
+            let record = value.as_object().ok_or_else(invalid)?;
+            for (key, values) in record.iter() {
+                let (t, field) = if let Some((t, field)) =
+                    tags.iter().find(|(_, f)| f.name.as_str() == key.as_ref())
+                {
+                    (*t, Some(field))
+                } else {
+                    (key.parse::<u64>().map_err(|_| invalid())?, None)
+                };
+                let values = values
+                    .as_array()
+                    .map_or_else(|| vec![values], |vs| vs.iter().collect());
+                for value in values {
+                    let mut encoded = Vec::new();
+                    if let Some(field) = field {
+                        let endian = field.endian.unwrap_or(endian);
+                        encode_value(&field.kind, endian, value, &field.name, &mut encoded)?;
+                    } else {
+                        encode_value(
+                            &Kind::Bytes { length: None },
+                            endian,
+                            value,
+                            name,
+                            &mut encoded,
+                        )?;
+                    }
+                    let len = encoded.len() as u64;
+                    if size.size() < 8 && len >> (8 * size.size()) != 0 {
+                        return Err(format!("TLV value of field {} is too long", name).into());
+                    }
+                    write_uint(t, tag.size(), endian, dst);
+                    write_uint(len, size.size(), endian, dst);
+                    dst.append(&mut encoded);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Codec for Dissector {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "dissector"
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let data: &'input [u8] = data;
+        decode_fields(&self.config.fields, self.config.endian, data).map(Some)
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let mut res = Vec::with_capacity(64);
+        encode_fields(&self.config.fields, self.config.endian, data, &mut res)?;
+        Ok(res)
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    fn codec(config: &str) -> Result<Dissector> {
+        let config: OpConfig = serde_yaml::from_str(config)?;
+        Dissector::from_config(&Some(config))
+    }
+
+    const CONFIG: &str = r#"
+endian: big
+fields:
+  - name: version
+    type: u8
+  - name: temp
+    type: i16
+    endian: little
+  - name: length
+    type: u16
+  - name: host
+    type: string
+    length: 8
+  - name: payload
+    type: bytes
+    length: length
+  - name: header
+    type: struct
+    length: 4
+    fields:
+      - name: flags
+        type: u8
+      - name: seq
+        type: u16
+        offset: 2
+  - name: options
+    type: tlv
+    tags:
+      1:
+        name: ttl
+        type: u16
+      2:
+        name: route
+        type: tlv
+        tags:
+          3:
+            name: hop
+            type: string
+"#;
+
+    #[test]
+    fn decode() -> Result<()> {
+        let mut codec = codec(CONFIG)?;
+        let mut raw = vec![
+            1, // version
+            0xfe, 0xff, // temp
+            0, 2, // length
+            b'h', b'o', b's', b't', 0, 0, 0, 0, // host
+            0xca, 0xfe, // payload
+            7, 0, 0, 42, // header
+            1, 2, 0, 60, // ttl
+            2, 6, 3, 1, b'a', 3, 1, b'b', // route
+            9, 1, 0xff, // unknown tag
+        ];
+        let payload: &[u8] = &[0xca, 0xfe];
+        let unknown: &[u8] = &[0xff];
+        let mut expected = literal!({
+            "version": 1,
+            "temp": -2,
+            "length": 2,
+            "host": "host",
+            "payload": null,
+            "header": {"flags": 7, "seq": 42},
+            "options": {
+                "ttl": 60,
+                "route": {"hop": ["a", "b"]},
+                "9": null
+            }
+        });
+        expected.insert("payload", Value::Bytes(payload.into()))?;
+        if let Some(options) = expected.get_mut("options") {
+            options.insert("9", Value::Bytes(unknown.into()))?;
+        }
+        let mut data = raw.clone();
+        let decoded = codec.decode(&mut data, 0)?.unwrap_or_default();
+        assert_eq!(expected, decoded);
+        let mut encoded = codec.encode(&decoded)?;
+        assert_eq!(Some(expected), codec.decode(&mut encoded, 0)?);
+        // truncated data
+        raw.truncate(10);
+        assert!(codec.decode(&mut raw, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encode_padding() -> Result<()> {
+        let codec = codec(CONFIG)?;
+        let mut record = literal!({
+            "version": 2,
+            "temp": 3,
+            "length": 0,
+            "host": "h",
+            "payload": "",
+            "header": {"flags": 1, "seq": 2},
+            "options": {}
+        });
+        assert_eq!(
+            vec![2, 3, 0, 0, 0, b'h', 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 2],
+            codec.encode(&record)?
+        );
+        record.insert("version", 256)?;
+        assert!(codec.encode(&record).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! EDI codec for ANSI X12 and UN/EDIFACT interchanges.
+//!
+//! The standard is detected from the interchange header (`ISA` for X12,
+//! `UNA` or `UNB` for EDIFACT). An interchange is decoded into its segments,
+//! including all envelope segments, in their original order:
+//!
+//! ```json
+//! {
+//!   "standard": "x12",
+//!   "delimiters": {"element": "*", "component": ":", "segment": "~"},
+//!   "segments": [
+//!     {"tag": "ISA", "elements": ["00", "          ", ...]},
+//!     {"tag": "GS", "elements": ["PO", "SENDER", ...]},
+//!     ...
+//!   ]
+//! }
+//! ```
+//!
+//! Elements with components are decoded into an array of components. For
+//! EDIFACT the `release` character is resolved on decoding and applied on
+//! encoding, and `una` marks if the interchange carried a service string
+//! advice. Encoding uses the `delimiters` of the event, or the standard's
+//! defaults.
+
+use super::prelude::*;
+use std::str;
+
+const X12: &str = "x12";
+const EDIFACT: &str = "edifact";
+/// length of the fixed width X12 `ISA` segment including its terminator
+const ISA_LEN: usize = 106;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Delimiters {
+    element: char,
+    component: char,
+    segment: char,
+    release: Option<char>,
+}
+
+impl Delimiters {
+    fn x12() -> Self {
+        Self {
+            element: '*',
+            component: ':',
+            segment: '~',
+            release: None,
+        }
+    }
+
+    fn edifact() -> Self {
+        Self {
+            element: '+',
+            component: ':',
+            segment: '\'',
+            release: Some('?'),
+        }
+    }
+
+    fn from_value(standard: &str, v: Option<&Value>) -> Self {
+        let mut d = if standard == X12 {
+            Self::x12()
+        } else {
+            Self::edifact()
+        };
+        let get = |k| v.and_then(|v| v.get_str(k)).and_then(|s| s.chars().next());
+        d.element = get("element").unwrap_or(d.element);
+        d.component = get("component").unwrap_or(d.component);
+        d.segment = get("segment").unwrap_or(d.segment);
+        if d.release.is_some() {
+            d.release = get("release").or(d.release);
+        }
+        d
+    }
+
+    fn to_value(self) -> Value<'static> {
+        let mut obj = Object::with_capacity(4);
+        obj.insert("element".into(), Value::from(self.element.to_string()));
+        obj.insert("component".into(), Value::from(self.component.to_string()));
+        obj.insert("segment".into(), Value::from(self.segment.to_string()));
+        if let Some(r) = self.release {
+            obj.insert("release".into(), Value::from(r.to_string()));
+        }
+        Value::from(obj)
+    }
+
+    /// splits on `sep` while honouring the release character
+    fn split<'s>(&self, s: &'s str, sep: char) -> Vec<&'s str> {
+        let mut res = Vec::new();
+        let mut start = 0;
+        let mut released = false;
+        for (i, c) in s.char_indices() {
+            if released {
+                released = false;
+            } else if Some(c) == self.release {
+                released = true;
+            } else if c == sep {
+                res.push(s.get(start..i).unwrap_or_default());
+                start = i + c.len_utf8();
+            }
+        }
+        res.push(s.get(start..).unwrap_or_default());
+        res
+    }
+
+    fn unescape(&self, s: &str) -> Value<'static> {
+        match self.release {
+            Some(r) if s.contains(r) => {
+                let mut out = String::with_capacity(s.len());
+                let mut released = false;
+                for c in s.chars() {
+                    if c == r && !released {
+                        released = true;
+                    } else {
+                        out.push(c);
+                        released = false;
+                    }
+                }
+                Value::from(out)
+            }
+            _ => Value::from(s.to_string()),
+        }
+    }
+
+    fn escape_into(&self, s: &str, out: &mut String) -> Result<()> {
+        for c in s.chars() {
+            let special = c == self.element
+                || c == self.component
+                || c == self.segment
+                || Some(c) == self.release;
+            if special {
+                match self.release {
+                    Some(r) => out.push(r),
+                    None => return Err(format!("Value `{}` contains an X12 delimiter", s).into()),
+                }
+            }
+            out.push(c);
+        }
+        Ok(())
+    }
+
+    fn decode_element(&self, s: &str) -> Value<'static> {
+        let components = self.split(s, self.component);
+        if components.len() > 1 {
+            Value::from(
+                components
+                    .into_iter()
+                    .map(|c| self.unescape(c))
+                    .collect::<Vec<_>>(),
+            )
+        } else {
+            self.unescape(s)
+        }
+    }
+
+    fn encode_leaf(&self, v: &Value, out: &mut String) -> Result<()> {
+        match v {
+            Value::String(s) => self.escape_into(s, out)?,
+            Value::Static(StaticNode::Null) => (),
+            Value::Static(s) => self.escape_into(&s.to_string(), out)?,
+            other => return Err(format!("Invalid EDI value: {}", other.encode()).into()),
+        }
+        Ok(())
+    }
+
+    fn encode_element(&self, v: &Value, out: &mut String) -> Result<()> {
+        if let Some(components) = v.as_array() {
+            for (i, c) in components.iter().enumerate() {
+                if i > 0 {
+                    out.push(self.component);
+                }
+                self.encode_leaf(c, out)?;
+            }
+            Ok(())
+        } else {
+            self.encode_leaf(v, out)
+        }
+    }
+}
+
+fn segment_value(tag: &str, elements: Vec<Value<'static>>) -> Value<'static> {
+    let mut seg = Object::with_capacity(2);
+    seg.insert("tag".into(), Value::from(tag.to_string()));
+    seg.insert("elements".into(), Value::from(elements));
+    Value::from(seg)
+}
+
+#[derive(Clone)]
+pub struct Edi {}
+
+impl Edi {
+    fn decode_x12(msg: &str) -> Result<Value<'static>> {
+        let isa = msg
+            .get(..ISA_LEN)
+            .ok_or_else(|| Error::from("X12 ISA segment is too short"))?;
+        let mut chars = isa.chars();
+        let element = chars
+            .nth(3)
+            .ok_or_else(|| Error::from("Invalid X12 ISA segment"))?;
+        let mut tail = isa.chars().rev();
+        let segment = tail
+            .next()
+            .ok_or_else(|| Error::from("Invalid X12 ISA segment"))?;
+        let component = tail
+            .next()
+            .ok_or_else(|| Error::from("Invalid X12 ISA segment"))?;
+        let d = Delimiters {
+            element,
+            component,
+            segment,
+            release: None,
+        };
+
+        let mut segments = Vec::with_capacity(16);
+        // the ISA segment is fixed width and its last element is the
+        // component separator, so it is never split into components
+        let isa_elements = isa
+            .get(4..ISA_LEN - 1)
+            .unwrap_or_default()
+            .split(element)
+            .map(|e| Value::from(e.to_string()))
+            .collect();
+        segments.push(segment_value("ISA", isa_elements));
+        for seg in msg.get(ISA_LEN..).unwrap_or_default().split(segment) {
+            let seg = seg.trim_start_matches(&['\r', '\n'][..]);
+            if seg.trim().is_empty() {
+                continue;
+            }
+            let mut parts = seg.split(element);
+            let tag = parts.next().unwrap_or_default();
+            let elements = parts.map(|e| d.decode_element(e)).collect();
+            segments.push(segment_value(tag, elements));
+        }
+        let mut obj = Object::with_capacity(3);
+        obj.insert("standard".into(), Value::from(X12));
+        obj.insert("delimiters".into(), d.to_value());
+        obj.insert("segments".into(), Value::from(segments));
+        Ok(Value::from(obj))
+    }
+
+    fn decode_edifact(msg: &str) -> Result<Value<'static>> {
+        let mut d = Delimiters::edifact();
+        let una = msg.starts_with("UNA");
+        let body = if una {
+            let advice: Vec<char> = msg.chars().skip(3).take(6).collect();
+            if advice.len() < 6 {
+                return Err("Invalid EDIFACT UNA segment".into());
+            }
+            d.component = advice[0];
+            d.element = advice[1];
+            d.release = Some(advice[3]);
+            d.segment = advice[5];
+            msg.get(9..).unwrap_or_default()
+        } else {
+            msg
+        };
+        let mut segments = Vec::with_capacity(16);
+        for seg in d.split(body, d.segment) {
+            let seg = seg.trim_start_matches(&['\r', '\n'][..]);
+            if seg.trim().is_empty() {
+                continue;
+            }
+            let mut parts = d.split(seg, d.element).into_iter();
+            let tag = parts.next().unwrap_or_default();
+            let elements = parts.map(|e| d.decode_element(e)).collect();
+            segments.push(segment_value(tag, elements));
+        }
+        let mut obj = Object::with_capacity(4);
+        obj.insert("standard".into(), Value::from(EDIFACT));
+        obj.insert("una".into(), Value::from(una));
+        obj.insert("delimiters".into(), d.to_value());
+        obj.insert("segments".into(), Value::from(segments));
+        Ok(Value::from(obj))
+    }
+}
+
+impl Codec for Edi {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "edi"
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn mime_types(&self) -> Vec<&str> {
+        vec!["application/edi-x12", "application/edifact"]
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let msg = str::from_utf8(data)?.trim_start();
+        if msg.starts_with("ISA") {
+            Ok(Some(Self::decode_x12(msg)?))
+        } else if msg.starts_with("UNA") || msg.starts_with("UNB") {
+            Ok(Some(Self::decode_edifact(msg)?))
+        } else {
+            Err("Data is neither an X12 nor an EDIFACT interchange".into())
+        }
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let standard = data.get_str("standard").unwrap_or(X12);
+        if standard != X12 && standard != EDIFACT {
+            return Err(format!("Unknown EDI standard {}", standard).into());
+        }
+        let d = Delimiters::from_value(standard, data.get("delimiters"));
+        let segments = data
+            .get_array("segments")
+            .ok_or_else(|| Error::from("EDI interchanges require a `segments` array"))?;
+        let mut out = String::with_capacity(1024);
+        if standard == EDIFACT && data.get_bool("una").unwrap_or_default() {
+            out.push_str("UNA");
+            out.push(d.component);
+            out.push(d.element);
+            out.push('.');
+            out.push(d.release.unwrap_or('?'));
+            out.push(' ');
+            out.push(d.segment);
+        }
+        for seg in segments {
+            let tag = seg
+                .get_str("tag")
+                .ok_or_else(|| Error::from("EDI segment is missing its `tag`"))?;
+            out.push_str(tag);
+            let elements = seg.get_array("elements").map_or(&[][..], Vec::as_slice);
+            for e in elements {
+                out.push(d.element);
+                if tag == "ISA" {
+                    // fixed width values are written verbatim
+                    out.push_str(e.as_str().unwrap_or_default());
+                } else {
+                    d.encode_element(e, &mut out)?;
+                }
+            }
+            out.push(d.segment);
+        }
+        Ok(out.into_bytes())
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    const X12_850: &str = "ISA*00*          *00*          *ZZ*SNOT           *ZZ*BADGER         *210401*1200*U*00401*000000001*0*P*>~\
+                           GS*PO*SNOT*BADGER*20210401*1200*1*X*004010~\
+                           ST*850*0001~\
+                           BEG*00*SA*PO1**20210401~\
+                           PO1*1*10*EA*9.99**VP*A>B~\
+                           SE*4*0001~\
+                           GE*1*1~\
+                           IEA*1*000000001~";
+
+    const EDIFACT_ORDERS: &str = "UNA:+.? 'UNB+UNOC:3+SNOT+BADGER+210401:1200+1'\
+                                  UNH+1+ORDERS:D:96A:UN'\
+                                  FTX+AAI+++Snot?+badger?'s'\
+                                  UNT+3+1'\
+                                  UNZ+1+1'";
+
+    #[test]
+    fn decode_x12() -> Result<()> {
+        let mut codec = Edi {};
+        let mut raw = X12_850.as_bytes().to_vec();
+        let decoded = codec
+            .decode(&mut raw, 0)?
+            .ok_or_else(|| Error::from("no value"))?;
+        assert_eq!(Some(X12), decoded.get_str("standard"));
+        assert_eq!(
+            Some(&literal!({"element": "*", "component": ">", "segment": "~"})),
+            decoded.get("delimiters")
+        );
+        let segments = decoded
+            .get_array("segments")
+            .ok_or_else(|| Error::from("no segments"))?;
+        assert_eq!(8, segments.len());
+        assert_eq!(
+            Some(16),
+            segments
+                .first()
+                .and_then(|s| s.get_array("elements"))
+                .map(Vec::len)
+        );
+        assert_eq!(
+            Some(
+                &literal!({"tag": "PO1", "elements": ["1", "10", "EA", "9.99", "", "VP", ["A", "B"]]})
+            ),
+            segments.get(4)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decode_edifact() -> Result<()> {
+        let mut codec = Edi {};
+        let mut raw = EDIFACT_ORDERS.as_bytes().to_vec();
+        let decoded = codec
+            .decode(&mut raw, 0)?
+            .ok_or_else(|| Error::from("no value"))?;
+        assert_eq!(Some(EDIFACT), decoded.get_str("standard"));
+        let segments = decoded
+            .get_array("segments")
+            .ok_or_else(|| Error::from("no segments"))?;
+        assert_eq!(5, segments.len());
+        assert_eq!(
+            Some(
+                &literal!({"tag": "UNB", "elements": [["UNOC", "3"], "SNOT", "BADGER", ["210401", "1200"], "1"]})
+            ),
+            segments.first()
+        );
+        assert_eq!(
+            Some(&literal!({"tag": "FTX", "elements": ["AAI", "", "", "Snot+badger's"]})),
+            segments.get(2)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut codec = Edi {};
+        for msg in &[X12_850, EDIFACT_ORDERS] {
+            let mut raw = msg.as_bytes().to_vec();
+            let decoded = codec
+                .decode(&mut raw, 0)?
+                .ok_or_else(|| Error::from("no value"))?;
+            assert_eq!(*msg, str::from_utf8(&codec.encode(&decoded)?)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        let mut codec = Edi {};
+        let mut raw = b"snot badger".to_vec();
+        assert!(codec.decode(&mut raw, 0).is_err());
+        let mut raw = b"ISA*00*".to_vec();
+        assert!(codec.decode(&mut raw, 0).is_err());
+        assert!(codec
+            .encode(
+                &literal!({"standard": "x12", "segments": [{"tag": "N1", "elements": ["a*b"]}]})
+            )
+            .is_err());
+    }
+}
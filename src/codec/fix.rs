@@ -0,0 +1,466 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! FIX 4.x `tag=value` codec.
+//!
+//! Fields are named and typed using a QuickFIX style XML `dictionary`, tags
+//! not found in the dictionary (or all tags if none is given) are keyed by
+//! their number. Repeated tags, as used in repeating groups, are decoded into
+//! arrays. On encoding `BodyLength (9)` and `CheckSum (10)` are computed, the
+//! standard header is written first and the remaining tags in numeric order.
+//!
+//! ```yaml
+//! codec: fix
+//! codec_config:
+//!   dictionary: /etc/tremor/FIX44.xml
+//!   validate_checksum: true
+//! ```
+
+use super::prelude::*;
+use crate::OpConfig;
+use beef::Cow;
+use halfbrown::HashMap;
+use regex::Regex;
+use std::sync::Arc;
+use std::{fmt::Write, str};
+use tremor_pipeline::ConfigImpl;
+
+const SOH: char = '\u{1}';
+const BEGIN_STRING: u32 = 8;
+const BODY_LENGTH: u32 = 9;
+const MSG_TYPE: u32 = 35;
+const CHECKSUM: u32 = 10;
+
+/// tags of the standard header, after `BeginString`, `BodyLength` and `MsgType`
+const HEADER_TAGS: [u32; 25] = [
+    49, 56, 115, 128, 90, 91, 50, 142, 57, 143, 116, 144, 129, 145, 34, 43, 97, 52, 122, 212, 213,
+    347, 369, 627, 1128,
+];
+
+lazy_static! {
+    static ref FIELD_RE: Regex = {
+        // ALLOW: we tested this
+        #[allow(clippy::unwrap_used)]
+        Regex::new(r#"(?s)<field\s([^>]*?)(?:/>|>(.*?)</field>)"#).unwrap()
+    };
+    static ref ATTR_RE: Regex = {
+        // ALLOW: we tested this
+        #[allow(clippy::unwrap_used)]
+        Regex::new(r#"(\w+)\s*=\s*"([^"]*)""#).unwrap()
+    };
+}
+
+fn d_delimiter() -> String {
+    SOH.to_string()
+}
+
+fn d_true() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Config {
+    /// path to a QuickFIX style XML dictionary
+    #[serde(default)]
+    dictionary: Option<String>,
+    /// field delimiter, `|` is commonly used in logs
+    #[serde(default = "d_delimiter")]
+    delimiter: String,
+    /// reject messages with an invalid `CheckSum (10)`
+    #[serde(default = "d_true")]
+    validate_checksum: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dictionary: None,
+            delimiter: d_delimiter(),
+            validate_checksum: true,
+        }
+    }
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FieldType {
+    Int,
+    Float,
+    Bool,
+    String,
+}
+
+impl FieldType {
+    fn from_fix(t: &str) -> Self {
+        match t {
+            "INT" | "LENGTH" | "SEQNUM" | "NUMINGROUP" | "TAGNUM" | "DAYOFMONTH" => Self::Int,
+            "FLOAT" | "PRICE" | "QTY" | "AMT" | "PRICEOFFSET" | "PERCENTAGE" => Self::Float,
+            "BOOLEAN" => Self::Bool,
+            _ => Self::String,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Dictionary {
+    by_tag: HashMap<u32, (String, FieldType)>,
+    by_name: HashMap<String, u32>,
+}
+
+impl Dictionary {
+    pub(crate) fn parse(xml: &str) -> Result<Self> {
+        let mut dict = Self::default();
+        for field in FIELD_RE.captures_iter(xml) {
+            let attrs: HashMap<&str, &str> = ATTR_RE
+                .captures_iter(field.get(1).map_or("", |m| m.as_str()))
+                .filter_map(|a| Some((a.get(1)?.as_str(), a.get(2)?.as_str())))
+                .collect();
+            // field references inside messages and components carry no number
+            if let (Some(number), Some(name)) = (attrs.get("number"), attrs.get("name")) {
+                let tag: u32 = number.parse()?;
+                let ty = attrs
+                    .get("type")
+                    .map_or(FieldType::String, |t| FieldType::from_fix(t));
+                dict.by_tag.insert(tag, ((*name).to_string(), ty));
+                dict.by_name.insert((*name).to_string(), tag);
+            }
+        }
+        if dict.by_tag.is_empty() {
+            return Err("No fields found in FIX dictionary".into());
+        }
+        Ok(dict)
+    }
+}
+
+#[derive(Clone)]
+pub struct Fix {
+    dictionary: Arc<Dictionary>,
+    delimiter: String,
+    validate_checksum: bool,
+}
+
+impl Fix {
+    pub(crate) fn from_config(config: &Option<OpConfig>) -> Result<Self> {
+        let config = config
+            .as_ref()
+            .map(Config::new)
+            .transpose()?
+            .unwrap_or_default();
+        if config.delimiter.is_empty() {
+            return Err("FIX delimiter can not be empty".into());
+        }
+        let dictionary = if let Some(path) = &config.dictionary {
+            let raw = std::fs::read_to_string(path).map_err(|e| {
+                Error::from(format!("Unable to read FIX dictionary {}: {}", path, e))
+            })?;
+            Dictionary::parse(&raw)?
+        } else {
+            Dictionary::default()
+        };
+        Ok(Self {
+            dictionary: Arc::new(dictionary),
+            delimiter: config.delimiter,
+            validate_checksum: config.validate_checksum,
+        })
+    }
+
+    fn typed<'v>(&self, tag: u32, raw: &'v str) -> (Cow<'v, str>, Value<'v>) {
+        if let Some((name, ty)) = self.dictionary.by_tag.get(&tag) {
+            let value = match ty {
+                FieldType::Int => raw.parse::<i64>().ok().map(Value::from),
+                FieldType::Float => raw.parse::<f64>().ok().map(Value::from),
+                FieldType::Bool => match raw {
+                    "Y" => Some(Value::from(true)),
+                    "N" => Some(Value::from(false)),
+                    _ => None,
+                },
+                FieldType::String => None,
+            };
+            (
+                Cow::owned(name.clone()),
+                value.unwrap_or_else(|| Value::from(raw)),
+            )
+        } else {
+            (Cow::owned(tag.to_string()), Value::from(raw))
+        }
+    }
+
+    fn tag(&self, key: &str) -> Result<u32> {
+        key.parse::<u32>().or_else(|_| {
+            self.dictionary
+                .by_name
+                .get(key)
+                .copied()
+                .ok_or_else(|| Error::from(format!("Unknown FIX field {}", key)))
+        })
+    }
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0_u8, |acc, b| acc.wrapping_add(*b))
+}
+
+fn write_value(out: &mut String, tag: u32, value: &Value, delimiter: &str) -> Result<()> {
+    if let Some(a) = value.as_array() {
+        for v in a {
+            write_value(out, tag, v, delimiter)?;
+        }
+        return Ok(());
+    }
+    let _ = write!(out, "{}=", tag);
+    match value {
+        Value::String(s) => out.push_str(s),
+        Value::Static(StaticNode::Bool(true)) => out.push('Y'),
+        Value::Static(StaticNode::Bool(false)) => out.push('N'),
+        Value::Static(StaticNode::Null) | Value::Object(_) | Value::Array(_) | Value::Bytes(_) => {
+            return Err(format!("Invalid value for FIX tag {}: {}", tag, value.encode()).into())
+        }
+        Value::Static(s) => {
+            let _ = write!(out, "{}", s);
+        }
+    }
+    out.push_str(delimiter);
+    Ok(())
+}
+
+impl Codec for Fix {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "fix"
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn mime_types(&self) -> Vec<&str> {
+        vec!["application/fix"]
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let data: &'input [u8] = data;
+        let msg = str::from_utf8(data)?.trim_end_matches(&['\r', '\n'][..]);
+        let mut obj = Object::with_capacity(16);
+        let mut offset = 0;
+        for field in msg.split(self.delimiter.as_str()) {
+            let field_start = offset;
+            offset += field.len() + self.delimiter.len();
+            if field.is_empty() {
+                continue;
+            }
+            let mut parts = field.splitn(2, '=');
+            let (tag, raw) = match (parts.next(), parts.next()) {
+                (Some(tag), Some(raw)) => (tag, raw),
+                _ => return Err(format!("Invalid FIX field: {}", field).into()),
+            };
+            let tag: u32 = tag.parse()?;
+            if tag == CHECKSUM && self.validate_checksum {
+                // the checksum covers all bytes up to the checksum field, with
+                // the original delimiter replaced by SOH
+                let covered = msg.get(..field_start).unwrap_or_default();
+                let expected = if self.delimiter.as_str() == "\u{1}" {
+                    checksum(covered.as_bytes())
+                } else {
+                    checksum(covered.replace(self.delimiter.as_str(), "\u{1}").as_bytes())
+                };
+                let actual: u8 = raw.parse()?;
+                if expected != actual {
+                    return Err(format!(
+                        "Invalid FIX checksum: expected {:03}, got {}",
+                        expected, raw
+                    )
+                    .into());
+                }
+            }
+            let (key, value) = self.typed(tag, raw);
+            match obj.get_mut(&*key) {
+                Some(Value::Array(a)) => a.push(value),
+                Some(existing) => {
+                    let first = std::mem::replace(existing, Value::null());
+                    *existing = Value::from(vec![first, value]);
+                }
+                None => {
+                    obj.insert(key, value);
+                }
+            }
+        }
+        Ok(Some(Value::from(obj)))
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let obj = data
+            .as_object()
+            .ok_or_else(|| Error::from("FIX messages must be encoded from records"))?;
+        let mut begin_string = None;
+        let mut msg_type = None;
+        let mut header = Vec::new();
+        let mut body = Vec::new();
+        for (k, v) in obj.iter() {
+            match self.tag(k)? {
+                BEGIN_STRING => begin_string = Some(v),
+                MSG_TYPE => msg_type = Some(v),
+                BODY_LENGTH | CHECKSUM => (),
+                tag => {
+                    if let Some(pos) = HEADER_TAGS.iter().position(|t| *t == tag) {
+                        header.push((pos, tag, v));
+                    } else {
+                        body.push((tag, v));
+                    }
+                }
+            }
+        }
+        let begin_string =
+            begin_string.ok_or_else(|| Error::from("Missing FIX field BeginString (8)"))?;
+        let msg_type = msg_type.ok_or_else(|| Error::from("Missing FIX field MsgType (35)"))?;
+        header.sort_by_key(|(pos, _, _)| *pos);
+        body.sort_by_key(|(tag, _)| *tag);
+
+        let d = self.delimiter.as_str();
+        let mut rest = String::with_capacity(256);
+        write_value(&mut rest, MSG_TYPE, msg_type, d)?;
+        for (_, tag, v) in header {
+            write_value(&mut rest, tag, v, d)?;
+        }
+        for (tag, v) in body {
+            write_value(&mut rest, tag, v, d)?;
+        }
+
+        let mut out = String::with_capacity(rest.len() + 32);
+        write_value(&mut out, BEGIN_STRING, begin_string, d)?;
+        // BodyLength is measured with single byte SOH delimiters
+        let body_len = rest.len() - (d.len() - 1) * rest.matches(d).count();
+        let _ = write!(out, "{}={}{}", BODY_LENGTH, body_len, d);
+        out.push_str(&rest);
+        let sum = if d == "\u{1}" {
+            checksum(out.as_bytes())
+        } else {
+            checksum(out.replace(d, "\u{1}").as_bytes())
+        };
+        let _ = write!(out, "{}={:03}{}", CHECKSUM, sum, d);
+        Ok(out.into_bytes())
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    const DICT: &str = r#"
+<fix major="4" minor="4">
+  <messages>
+    <message name="Heartbeat" msgtype="0" msgcat="admin">
+      <field name="TestReqID" required="N"/>
+    </message>
+  </messages>
+  <fields>
+    <field number="8" name="BeginString" type="STRING"/>
+    <field number="9" name="BodyLength" type="LENGTH"/>
+    <field number="10" name="CheckSum" type="STRING"/>
+    <field number="34" name="MsgSeqNum" type="SEQNUM"/>
+    <field number="35" name="MsgType" type="STRING">
+      <value enum="0" description="HEARTBEAT"/>
+      <value enum="D" description="ORDER_SINGLE"/>
+    </field>
+    <field number="44" name="Price" type="PRICE"/>
+    <field number="49" name="SenderCompID" type="STRING"/>
+    <field number="55" name="Symbol" type="STRING"/>
+    <field number="56" name="TargetCompID" type="STRING"/>
+    <field number="1013" name="SomeFlag" type="BOOLEAN"/>
+  </fields>
+</fix>
+"#;
+
+    fn codec(delimiter: &str) -> Result<Fix> {
+        Ok(Fix {
+            dictionary: Arc::new(Dictionary::parse(DICT)?),
+            delimiter: delimiter.to_string(),
+            validate_checksum: true,
+        })
+    }
+
+    #[test]
+    fn parse_dictionary() -> Result<()> {
+        let dict = Dictionary::parse(DICT)?;
+        assert_eq!(10, dict.by_tag.len());
+        assert_eq!(Some(&44), dict.by_name.get("Price"));
+        assert!(Dictionary::parse("<fix></fix>").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_known_message() -> Result<()> {
+        let mut codec = codec("|")?;
+        let mut raw = b"8=FIX.4.4|9=43|35=D|49=SNOT|56=BADGER|34=2|55=AAPL|44=1.5|10=255|".to_vec();
+        let decoded = codec.decode(&mut raw, 0)?;
+        assert_eq!(
+            Some(literal!({
+                "BeginString": "FIX.4.4",
+                "BodyLength": 43,
+                "MsgType": "D",
+                "SenderCompID": "SNOT",
+                "TargetCompID": "BADGER",
+                "MsgSeqNum": 2,
+                "Symbol": "AAPL",
+                "Price": 1.5,
+                "CheckSum": "255"
+            })),
+            decoded
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_checksum() -> Result<()> {
+        let mut codec = codec("|")?;
+        let mut raw = b"8=FIX.4.4|9=5|35=0|10=001|".to_vec();
+        assert!(codec.decode(&mut raw, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        for d in &["\u{1}", "|"] {
+            let mut codec = codec(d)?;
+            let event = literal!({
+                "BeginString": "FIX.4.4",
+                "MsgType": "D",
+                "Symbol": "AAPL",
+                "SenderCompID": "SNOT",
+                "MsgSeqNum": 2,
+                "SomeFlag": true,
+                "9999": ["a", "b"]
+            });
+            let mut raw = codec.encode(&event)?;
+            let s = String::from_utf8(raw.clone())?.replace(d, "|");
+            assert!(
+                s.starts_with("8=FIX.4.4|9=47|35=D|49=SNOT|34=2|55=AAPL|1013=Y|9999=a|9999=b|10=")
+            );
+            let decoded = codec.decode(&mut raw, 0)?;
+            let decoded = decoded.ok_or_else(|| Error::from("no value"))?;
+            assert_eq!(Some(&Value::from("AAPL")), decoded.get("Symbol"));
+            assert_eq!(Some(&Value::from(true)), decoded.get("SomeFlag"));
+            assert_eq!(Some(&literal!(["a", "b"])), decoded.get("9999"));
+            assert_eq!(Some(&Value::from(47)), decoded.get("BodyLength"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! GELF 1.1 codec, for chunked UDP messages combine it with the
+//! `gelf-chunking` preprocessor and the `gelf` postprocessor.
+//!
+//! Decoding validates that `host` and `short_message` are present, a trailing
+//! null byte as used by GELF over TCP is ignored. Additional fields keep
+//! their `_` prefix.
+//!
+//! Encoding sets `version` to `1.1` if missing and prefixes fields that are
+//! not part of the GELF spec with `_`. Nested additional fields are encoded
+//! as JSON strings, since GELF only allows strings and numbers.
+
+use super::json::Json;
+use super::prelude::*;
+
+const VERSION: &str = "1.1";
+const STANDARD_FIELDS: [&str; 9] = [
+    "version",
+    "host",
+    "short_message",
+    "full_message",
+    "timestamp",
+    "level",
+    // deprecated but still sent by many clients
+    "facility",
+    "line",
+    "file",
+];
+
+#[derive(Clone, Default)]
+pub struct Gelf {
+    json: Json,
+}
+
+fn validate(obj: &Object) -> Result<()> {
+    for field in &["host", "short_message"] {
+        if !obj.get(*field).map_or(false, Value::is_str) {
+            return Err(format!("Invalid GELF message: missing `{}`", field).into());
+        }
+    }
+    if obj.contains_key("_id") {
+        return Err("Invalid GELF message: `_id` is reserved".into());
+    }
+    Ok(())
+}
+
+impl Codec for Gelf {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "gelf"
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let len = data.iter().rposition(|b| *b != 0).map_or(0, |p| p + 1);
+        let data = data.get_mut(..len).unwrap_or_default();
+        let value = self.json.decode(data, ingest_ns)?;
+        if let Some(value) = &value {
+            let obj = value
+                .as_object()
+                .ok_or_else(|| Error::from("Invalid GELF message: not an object"))?;
+            validate(obj)?;
+        }
+        Ok(value)
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let obj = data
+            .as_object()
+            .ok_or_else(|| Error::from("GELF messages must be encoded from records"))?;
+        let mut msg = Object::with_capacity(obj.len() + 1);
+        for (k, v) in obj.iter() {
+            if STANDARD_FIELDS.contains(&&**k) || k.starts_with('_') {
+                msg.insert(k.clone(), v.clone());
+            } else {
+                let v = if v.is_array() || v.is_object() {
+                    Value::from(v.encode())
+                } else {
+                    v.clone()
+                };
+                msg.insert(format!("_{}", k).into(), v);
+            }
+        }
+        if !msg.contains_key("version") {
+            msg.insert("version".into(), Value::from(VERSION));
+        }
+        validate(&msg)?;
+        self.json.encode(&Value::from(msg))
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    #[test]
+    fn decode() -> Result<()> {
+        let mut codec = Gelf::default();
+        let mut data =
+            br#"{"version":"1.1","host":"snot","short_message":"badger","_user":"x"}"#.to_vec();
+        data.push(0);
+        assert_eq!(
+            Some(literal!({
+                "version": "1.1",
+                "host": "snot",
+                "short_message": "badger",
+                "_user": "x"
+            })),
+            codec.decode(&mut data, 0)?
+        );
+        let mut data = br#"{"version":"1.1","host":"snot"}"#.to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        let mut data = br#"[1]"#.to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn encode() -> Result<()> {
+        let mut codec = Gelf::default();
+        let data = literal!({
+            "host": "snot",
+            "short_message": "badger",
+            "user": {"id": 1},
+            "_request": "abc"
+        });
+        let mut encoded = codec.encode(&data)?;
+        assert_eq!(
+            Some(literal!({
+                "version": "1.1",
+                "host": "snot",
+                "short_message": "badger",
+                "_user": "{\"id\":1}",
+                "_request": "abc"
+            })),
+            codec.decode(&mut encoded, 0)?
+        );
+        assert!(codec.encode(&literal!({"host": "snot"})).is_err());
+        assert!(codec
+            .encode(&literal!({"host": "snot", "short_message": "badger", "id": 1}))
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! HL7 v2.x codec.
+//!
+//! A message is decoded into an array of segments in their original order:
+//!
+//! ```json
+//! [
+//!   {"segment": "MSH", "fields": ["|", "^~\\&", "SENDER", ...]},
+//!   {"segment": "PID", "fields": ["1", "", [["12345", "", "", "MRN"], ["678"]], ...]}
+//! ]
+//! ```
+//!
+//! `fields[n - 1]` holds field `n` of the segment, so for `MSH` the field
+//! separator and encoding characters are the first two entries. A field is
+//! decoded into
+//!
+//! * a string if it has no components or repetitions,
+//! * an array of components, where a component with subcomponents is an array
+//!   of strings,
+//! * an array of repetitions if it repeats, where every repetition is an array
+//!   of components.
+//!
+//! Escape sequences for the delimiters are resolved on decoding and applied on
+//! encoding, the delimiters are taken from the `MSH` segment.
+
+use super::prelude::*;
+use std::str;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Delimiters {
+    field: char,
+    component: char,
+    repetition: char,
+    escape: char,
+    subcomponent: char,
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Self {
+            field: '|',
+            component: '^',
+            repetition: '~',
+            escape: '\\',
+            subcomponent: '&',
+        }
+    }
+}
+
+impl Delimiters {
+    fn from_msh(segment: &str) -> Result<Self> {
+        let mut chars = segment.chars().skip(3);
+        let field = chars
+            .next()
+            .ok_or_else(|| Error::from("Missing HL7 field separator"))?;
+        let mut enc = chars.take_while(|c| *c != field);
+        let d = Self::default();
+        Ok(Self {
+            field,
+            component: enc.next().unwrap_or(d.component),
+            repetition: enc.next().unwrap_or(d.repetition),
+            escape: enc.next().unwrap_or(d.escape),
+            subcomponent: enc.next().unwrap_or(d.subcomponent),
+        })
+    }
+
+    fn encoding_characters(&self) -> String {
+        [
+            self.component,
+            self.repetition,
+            self.escape,
+            self.subcomponent,
+        ]
+        .iter()
+        .collect()
+    }
+
+    fn unescape<'v>(&self, s: &'v str) -> Value<'v> {
+        if !s.contains(self.escape) {
+            return Value::from(s);
+        }
+        let mut out = String::with_capacity(s.len());
+        let mut parts = s.split(self.escape);
+        if let Some(first) = parts.next() {
+            out.push_str(first);
+        }
+        // escape sequences are enclosed in escape characters, so every odd
+        // part is a sequence and every even part is text
+        let mut in_seq = true;
+        for part in parts {
+            if in_seq {
+                match part {
+                    "F" => out.push(self.field),
+                    "S" => out.push(self.component),
+                    "R" => out.push(self.repetition),
+                    "E" => out.push(self.escape),
+                    "T" => out.push(self.subcomponent),
+                    // unknown sequences such as formatting are kept as is
+                    other => {
+                        out.push(self.escape);
+                        out.push_str(other);
+                        out.push(self.escape);
+                    }
+                }
+            } else {
+                out.push_str(part);
+            }
+            in_seq = !in_seq;
+        }
+        Value::from(out)
+    }
+
+    fn escape_into(&self, s: &str, out: &mut String) {
+        for c in s.chars() {
+            let seq = if c == self.escape {
+                Some('E')
+            } else if c == self.field {
+                Some('F')
+            } else if c == self.component {
+                Some('S')
+            } else if c == self.repetition {
+                Some('R')
+            } else if c == self.subcomponent {
+                Some('T')
+            } else {
+                None
+            };
+            if let Some(seq) = seq {
+                out.push(self.escape);
+                out.push(seq);
+                out.push(self.escape);
+            } else {
+                out.push(c);
+            }
+        }
+    }
+
+    fn decode_component<'v>(&self, s: &'v str) -> Value<'v> {
+        if s.contains(self.subcomponent) {
+            Value::from(
+                s.split(self.subcomponent)
+                    .map(|sc| self.unescape(sc))
+                    .collect::<Vec<_>>(),
+            )
+        } else {
+            self.unescape(s)
+        }
+    }
+
+    fn decode_components<'v>(&self, s: &'v str) -> Vec<Value<'v>> {
+        s.split(self.component)
+            .map(|c| self.decode_component(c))
+            .collect()
+    }
+
+    fn decode_field<'v>(&self, s: &'v str) -> Value<'v> {
+        if s.contains(self.repetition) {
+            Value::from(
+                s.split(self.repetition)
+                    .map(|r| Value::from(self.decode_components(r)))
+                    .collect::<Vec<_>>(),
+            )
+        } else if s.contains(self.component) {
+            let components = self.decode_components(s);
+            // a field where every component is an array would read as a list
+            // of repetitions, so we wrap it as a single repetition
+            if components.iter().all(|c| c.is_array()) {
+                Value::from(vec![Value::from(components)])
+            } else {
+                Value::from(components)
+            }
+        } else if s.contains(self.subcomponent) {
+            Value::from(vec![Value::from(vec![self.decode_component(s)])])
+        } else {
+            self.unescape(s)
+        }
+    }
+
+    fn encode_leaf(&self, v: &Value, out: &mut String) -> Result<()> {
+        match v {
+            Value::String(s) => self.escape_into(s, out),
+            Value::Static(StaticNode::Null) => (),
+            Value::Static(s) => out.push_str(&s.to_string()),
+            other => {
+                return Err(format!("Invalid HL7 value: {}", other.encode()).into());
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_component(&self, v: &Value, out: &mut String) -> Result<()> {
+        if let Some(subs) = v.as_array() {
+            for (i, sc) in subs.iter().enumerate() {
+                if i > 0 {
+                    out.push(self.subcomponent);
+                }
+                self.encode_leaf(sc, out)?;
+            }
+            Ok(())
+        } else {
+            self.encode_leaf(v, out)
+        }
+    }
+
+    fn encode_components(&self, v: &Value, out: &mut String) -> Result<()> {
+        if let Some(components) = v.as_array() {
+            for (i, c) in components.iter().enumerate() {
+                if i > 0 {
+                    out.push(self.component);
+                }
+                self.encode_component(c, out)?;
+            }
+            Ok(())
+        } else {
+            self.encode_leaf(v, out)
+        }
+    }
+
+    fn encode_field(&self, v: &Value, out: &mut String) -> Result<()> {
+        match v.as_array() {
+            Some(a) if !a.is_empty() && a.iter().all(ValueAccess::is_array) => {
+                for (i, r) in a.iter().enumerate() {
+                    if i > 0 {
+                        out.push(self.repetition);
+                    }
+                    self.encode_components(r, out)?;
+                }
+                Ok(())
+            }
+            _ => self.encode_components(v, out),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Hl7 {}
+
+impl Codec for Hl7 {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "hl7"
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn mime_types(&self) -> Vec<&str> {
+        vec!["application/hl7-v2", "x-application/hl7-v2+er7"]
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let data: &'input [u8] = data;
+        // strip MLLP framing if present
+        let msg = str::from_utf8(data)?.trim_matches(&['\u{b}', '\u{1c}'][..]);
+        let mut segments = msg
+            .split(&['\r', '\n'][..])
+            .filter(|s| !s.trim().is_empty());
+        let msh = segments
+            .next()
+            .ok_or_else(|| Error::from("Empty HL7 message"))?;
+        if !msh.starts_with("MSH") && !msh.starts_with("FHS") && !msh.starts_with("BHS") {
+            return Err("HL7 message must start with a MSH segment".into());
+        }
+        let d = Delimiters::from_msh(msh)?;
+
+        let mut res = Vec::with_capacity(16);
+        for (i, segment) in std::iter::once(msh).chain(segments).enumerate() {
+            let mut fields = segment.split(d.field);
+            let id = fields.next().unwrap_or_default();
+            let mut values = Vec::with_capacity(24);
+            if i == 0 {
+                // MSH-1 is the separator itself and MSH-2 is not split
+                values.push(Value::from(d.field.to_string()));
+                values.push(Value::from(fields.next().unwrap_or_default()));
+            }
+            values.extend(fields.map(|f| d.decode_field(f)));
+            let mut seg = Object::with_capacity(2);
+            seg.insert("segment".into(), Value::from(id));
+            seg.insert("fields".into(), Value::from(values));
+            res.push(Value::from(seg));
+        }
+        Ok(Some(Value::from(res)))
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let segments = data
+            .as_array()
+            .ok_or_else(|| Error::from("HL7 messages must be encoded from an array of segments"))?;
+        let mut d = Delimiters::default();
+        let mut out = String::with_capacity(512);
+        for (i, seg) in segments.iter().enumerate() {
+            let id = seg
+                .get_str("segment")
+                .ok_or_else(|| Error::from("HL7 segment is missing its `segment` id"))?;
+            let fields = seg.get_array("fields").map_or(&[][..], Vec::as_slice);
+            out.push_str(id);
+            let mut fields = fields.iter();
+            if i == 0 {
+                if let Some(sep) = fields
+                    .next()
+                    .and_then(ValueAccess::as_str)
+                    .and_then(|s| s.chars().next())
+                {
+                    d.field = sep;
+                }
+                if let Some(enc) = fields.next().and_then(ValueAccess::as_str) {
+                    let mut enc = enc.chars();
+                    d.component = enc.next().unwrap_or(d.component);
+                    d.repetition = enc.next().unwrap_or(d.repetition);
+                    d.escape = enc.next().unwrap_or(d.escape);
+                    d.subcomponent = enc.next().unwrap_or(d.subcomponent);
+                }
+                out.push(d.field);
+                out.push_str(&d.encoding_characters());
+            }
+            for f in fields {
+                out.push(d.field);
+                d.encode_field(f, &mut out)?;
+            }
+            out.push('\r');
+        }
+        Ok(out.into_bytes())
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    const ADT: &str = "MSH|^~\\&|SNOT|BADGER|REC|FAC|20210401120000||ADT^A01|MSG0001|P|2.5\r\
+                       PID|1||12345^^^MRN~678^^^SSN||Doe^John^Q\\S\\R||19700101|M|||1 Main St^^Town&County\r\
+                       OBX|1|NM|HR||72|bpm\r";
+
+    #[test]
+    fn decode_adt() -> Result<()> {
+        let mut codec = Hl7 {};
+        let mut raw = ADT.as_bytes().to_vec();
+        let decoded = codec.decode(&mut raw, 0)?;
+        let decoded = decoded.ok_or_else(|| Error::from("no value"))?;
+        assert_eq!(
+            Some(&literal!({
+                "segment": "MSH",
+                "fields": ["|", "^~\\&", "SNOT", "BADGER", "REC", "FAC", "20210401120000", "", ["ADT", "A01"], "MSG0001", "P", "2.5"]
+            })),
+            decoded.get_idx(0)
+        );
+        let pid = decoded.get_idx(1).and_then(|s| s.get("fields"));
+        assert_eq!(
+            Some(&literal!([
+                ["12345", "", "", "MRN"],
+                ["678", "", "", "SSN"]
+            ])),
+            pid.and_then(|f| f.get_idx(2))
+        );
+        assert_eq!(
+            Some(&literal!(["Doe", "John", "Q^R"])),
+            pid.and_then(|f| f.get_idx(4))
+        );
+        assert_eq!(
+            Some(&literal!(["1 Main St", "", ["Town", "County"]])),
+            pid.and_then(|f| f.get_idx(10))
+        );
+        assert_eq!(Some(3), decoded.as_array().map(Vec::len));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut codec = Hl7 {};
+        let encoded = {
+            let mut raw = ADT.as_bytes().to_vec();
+            let decoded = codec.decode(&mut raw, 0)?;
+            codec.encode(&decoded.ok_or_else(|| Error::from("no value"))?)?
+        };
+        assert_eq!(ADT, str::from_utf8(&encoded)?);
+        Ok(())
+    }
+
+    #[test]
+    fn subcomponents_only() -> Result<()> {
+        let mut codec = Hl7 {};
+        let msg = "MSH|^~\\&|a&b\r";
+        let mut raw = msg.as_bytes().to_vec();
+        let decoded = codec
+            .decode(&mut raw, 0)?
+            .ok_or_else(|| Error::from("no value"))?;
+        assert_eq!(msg, str::from_utf8(&codec.encode(&decoded)?)?);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        let mut codec = Hl7 {};
+        let mut raw = b"PID|1\r".to_vec();
+        assert!(codec.decode(&mut raw, 0).is_err());
+        assert!(codec.encode(&literal!({"snot": "badger"})).is_err());
+    }
+}
@@ -32,12 +32,16 @@
 //!     "timestamp": 1465839830100400200
 //! }
 //! ```
+//! Payloads with multiple newline separated points, as sent in batched
+//! writes, are decoded into an array of those structures, empty lines and
+//! comments are skipped. Arrays are encoded as one line per element.
+//!
 //! ## Configuration
 //!
 //! This operator takes no configuration
 
 use super::prelude::*;
-use std::{mem, str};
+use std::str;
 use tremor_influx as influx;
 
 #[derive(Clone)]
@@ -54,17 +58,44 @@ impl Codec for Influx {
         data: &'input mut [u8],
         ingest_ns: u64,
     ) -> Result<Option<Value<'input>>> {
-        // This is safe as from_utf8 does not change the memory location
-        // of the bytes, simply validates that it is UTF8 and if so
-        // change the type.
-        let s: &str = unsafe { mem::transmute(str::from_utf8(data)?) };
-        influx::decode::<'static, Value<'static>>(s, ingest_ns).map_err(|e| {
-            ErrorKind::InvalidInfluxData(String::from_utf8_lossy(data).to_string(), e).into()
-        })
+        // points borrow their measurement, tags, field keys and strings
+        // from `data`, only escaped sequences are copied
+        let s: &'input str = str::from_utf8(data)?;
+        // most payloads are a single point, only batches need an array
+        let mut first = None;
+        let mut rest = Vec::new();
+        for line in s.lines() {
+            let point = influx::decode::<'input, Value<'input>>(line, ingest_ns)
+                .map_err(|e| Error::from(ErrorKind::InvalidInfluxData(line.to_string(), e)))?;
+            match (point, &first) {
+                (Some(point), None) => first = Some(point),
+                (Some(point), Some(_)) => rest.push(point),
+                (None, _) => (),
+            }
+        }
+        if rest.is_empty() {
+            Ok(first)
+        } else {
+            let mut points = Vec::with_capacity(rest.len() + 1);
+            points.extend(first);
+            points.append(&mut rest);
+            Ok(Some(Value::from(points)))
+        }
     }
 
     fn encode(&self, data: &Value) -> Result<Vec<u8>> {
-        Ok(influx::encode(data)?)
+        if let Some(points) = data.as_array() {
+            let mut res = Vec::new();
+            for point in points {
+                if !res.is_empty() {
+                    res.push(b'\n');
+                }
+                res.extend(influx::encode(point)?);
+            }
+            Ok(res)
+        } else {
+            Ok(influx::encode(data)?)
+        }
     }
 
     fn boxed_clone(&self) -> Box<dyn Codec> {
@@ -137,6 +168,47 @@ mod tests {
         assert_eq!(decoded, &e)
     }
 
+    #[test]
+    pub fn decode_batch() -> Result<()> {
+        let mut s = b"# batch\nweather,location=us-midwest temperature=82 1465839830100400200\n\nweather,location=us-east temperature=75i 1465839830100400300\n".to_vec();
+        let mut codec = Influx {};
+
+        let decoded = codec
+            .decode(s.as_mut_slice(), 0)?
+            .expect("failed to decode");
+
+        let e: Value = literal!([
+            {
+                "measurement": "weather",
+                "tags": {"location": "us-midwest"},
+                "fields": {"temperature": 82.0},
+                "timestamp": 1_465_839_830_100_400_200_i64
+            },
+            {
+                "measurement": "weather",
+                "tags": {"location": "us-east"},
+                "fields": {"temperature": 75},
+                "timestamp": 1_465_839_830_100_400_300_i64
+            }
+        ]);
+        assert_eq!(decoded, &e);
+
+        let mut encoded = codec.encode(&decoded)?;
+        assert_eq!(
+            Some(e),
+            codec
+                .decode(encoded.as_mut_slice(), 0)?
+                .map(Value::into_static)
+        );
+
+        let mut s =
+            b"weather temperature=82 1465839830100400200\nweather,location temperature=1".to_vec();
+        assert!(codec.decode(s.as_mut_slice(), 0).is_err());
+        let mut s = b"\n# nothing to see\n".to_vec();
+        assert_eq!(None, codec.decode(s.as_mut_slice(), 0)?);
+        Ok(())
+    }
+
     #[allow(clippy::too_many_lines)]
     fn get_data_for_tests() -> [(Vec<u8>, Value<'static>, &'static str); 13] {
         [
@@ -0,0 +1,220 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Newline delimited JSON codec, decoding each line of a payload on its own
+//! into an array of the decoded lines. Empty lines are skipped.
+//!
+//! Malformed lines are handled according to `on_error`:
+//!
+//! * `fail` fails the whole payload, like the `json` codec
+//! * `drop` leaves the line out
+//! * `emit` decodes the line as `{"error": "...", "line": "..."}`
+//!
+//! Arrays are encoded as one line per element, all other values as a single
+//! line.
+//!
+//! ```yaml
+//! codec: json-lines
+//! codec_config:
+//!   on_error: emit
+//! ```
+
+use super::prelude::*;
+use crate::OpConfig;
+use std::cmp::max;
+use tremor_pipeline::ConfigImpl;
+use tremor_value::AlignedBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OnError {
+    Fail,
+    Drop,
+    Emit,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Config {
+    /// how malformed lines are handled
+    #[serde(default = "d_on_error")]
+    on_error: OnError,
+}
+
+fn d_on_error() -> OnError {
+    OnError::Fail
+}
+
+impl ConfigImpl for Config {}
+
+pub struct JsonLines {
+    on_error: OnError,
+    input_buffer: AlignedBuf,
+    string_buffer: Vec<u8>,
+}
+
+impl Clone for JsonLines {
+    fn clone(&self) -> Self {
+        Self::new(self.on_error)
+    }
+}
+
+impl JsonLines {
+    fn new(on_error: OnError) -> Self {
+        Self {
+            on_error,
+            input_buffer: AlignedBuf::with_capacity(1024),
+            string_buffer: Vec::with_capacity(1024),
+        }
+    }
+
+    pub(crate) fn from_config(config: &Option<OpConfig>) -> Result<Self> {
+        let on_error = config
+            .as_ref()
+            .map(Config::new)
+            .transpose()?
+            .map_or_else(d_on_error, |c| c.on_error);
+        Ok(Self::new(on_error))
+    }
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
+
+impl Codec for JsonLines {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "json-lines"
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn mime_types(&self) -> Vec<&str> {
+        vec!["application/x-ndjson", "application/jsonlines"]
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let mut res = Vec::new();
+        for (i, line) in data.split_mut(|b| *b == b'\n').enumerate() {
+            if is_blank(line) {
+                continue;
+            }
+            if self.string_buffer.capacity() < line.len() {
+                let new_len = max(self.string_buffer.capacity(), line.len()) * 2;
+                self.string_buffer.reserve(new_len);
+            }
+            // parsing may modify the line in place, keep it around for error records
+            let raw = if self.on_error == OnError::Emit {
+                Some(String::from_utf8_lossy(line).trim_end().to_string())
+            } else {
+                None
+            };
+            match tremor_value::parse_to_value_with_buffers(
+                line,
+                &mut self.input_buffer,
+                &mut self.string_buffer,
+            ) {
+                Ok(value) => res.push(value),
+                Err(e) => match self.on_error {
+                    OnError::Fail => {
+                        return Err(format!("Invalid JSON in line {}: {}", i + 1, e).into())
+                    }
+                    OnError::Drop => (),
+                    OnError::Emit => {
+                        let mut error = Value::object_with_capacity(2);
+                        error.try_insert("error", e.to_string());
+                        error.try_insert("line", raw.unwrap_or_default());
+                        res.push(error);
+                    }
+                },
+            }
+        }
+        Ok(Some(Value::from(res)))
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let mut v = Vec::with_capacity(1024);
+        self.encode_into(data, &mut v)?;
+        Ok(v)
+    }
+
+    fn encode_into(&self, data: &Value, dst: &mut Vec<u8>) -> Result<()> {
+        if let Some(values) = data.as_array() {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    dst.push(b'\n');
+                }
+                value.write(dst)?;
+            }
+        } else {
+            data.write(dst)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    fn codec(on_error: &str) -> Result<JsonLines> {
+        let config: OpConfig = serde_yaml::from_str(&format!("on_error: {}", on_error))?;
+        JsonLines::from_config(&Some(config))
+    }
+
+    const INPUT: &[u8] = b"{\"a\": 1}\n\n[1, 2]\n{\"snot\n\"badger\"\r\n";
+
+    #[test]
+    fn decode() -> Result<()> {
+        let mut data = INPUT.to_vec();
+        assert!(codec("fail")?.decode(&mut data, 0).is_err());
+
+        let mut data = INPUT.to_vec();
+        assert_eq!(
+            Some(literal!([{"a": 1}, [1, 2], "badger"])),
+            codec("drop")?.decode(&mut data, 0)?
+        );
+
+        let mut data = INPUT.to_vec();
+        let decoded = codec("emit")?.decode(&mut data, 0)?;
+        let decoded = decoded.as_ref().and_then(Value::as_array);
+        assert_eq!(Some(4), decoded.map(Vec::len));
+        let error = decoded.and_then(|d| d.get(2));
+        assert_eq!(Some("{\"snot"), error.get_str("line"));
+        assert!(error.get_str("error").is_some());
+
+        assert!(JsonLines::from_config(&Some(serde_yaml::from_str("on_error: snot")?)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let mut codec = codec("fail")?;
+        let value = literal!([{"a": 1}, [1, 2], "badger"]);
+        let mut encoded = codec.encode(&value)?;
+        assert_eq!(b"{\"a\":1}\n[1,2]\n\"badger\"".to_vec(), encoded);
+        assert_eq!(Some(value), codec.decode(&mut encoded, 0)?);
+        assert_eq!(b"{\"a\":1}".to_vec(), codec.encode(&literal!({"a": 1}))?);
+        Ok(())
+    }
+}
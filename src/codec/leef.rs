@@ -0,0 +1,282 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! IBM Log Event Extended Format (LEEF) codec
+//!
+//! ```text
+//! LEEF:2.0|Lancope|StealthWatch|1.0|41|^|src=10.0.1.8^dst=10.0.0.5^sev=5
+//! ```
+//!
+//! decodes to
+//!
+//! ```json
+//! {
+//!   "version": "2.0",
+//!   "device_vendor": "Lancope",
+//!   "device_product": "StealthWatch",
+//!   "device_version": "1.0",
+//!   "event_id": "41",
+//!   "delimiter": "^",
+//!   "attributes": {"src": "10.0.1.8", "dst": "10.0.0.5", "sev": "5"}
+//! }
+//! ```
+//!
+//! LEEF 1.0 attributes are separated by tabs. LEEF 2.0 declares the delimiter
+//! in the header, either as a single character or as its hex code like `x09`,
+//! an empty delimiter field means tabs. As for `cef`, anything in front of
+//! `LEEF:` is decoded as `prefix`.
+//!
+//! Encoding uses the `delimiter`, tabs if there is none, and only writes the
+//! delimiter field for LEEF 2.0.
+
+use super::cef::{cow_to_value, next_field, push_header, split_prefix};
+use super::prelude::*;
+use std::borrow::Cow;
+use std::str;
+
+#[derive(Clone)]
+pub struct Leef {}
+
+fn invalid(reason: &str) -> Error {
+    format!("Invalid LEEF event: {}", reason).into()
+}
+
+fn delimiter(field: &str) -> Result<char> {
+    let mut chars = field.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Ok('\t'),
+        (Some(c), None) => Ok(c),
+        _ => {
+            let lower = field.to_lowercase();
+            let hex = lower
+                .strip_prefix("0x")
+                .or_else(|| lower.strip_prefix('x'))
+                .ok_or_else(|| invalid(&format!("bad delimiter `{}`", field)))?;
+            u32::from_str_radix(hex, 16)
+                .ok()
+                .and_then(std::char::from_u32)
+                .ok_or_else(|| invalid(&format!("bad delimiter `{}`", field)))
+        }
+    }
+}
+
+fn attributes(input: &str, delimiter: char) -> Result<Value> {
+    let mut res = Value::object();
+    for attribute in input.split(delimiter).filter(|a| !a.trim().is_empty()) {
+        let eq = attribute
+            .find('=')
+            .ok_or_else(|| invalid(&format!("attribute `{}` without `=`", attribute)))?;
+        let key = attribute.get(..eq).unwrap_or_default().trim();
+        let value = attribute.get(eq + 1..).unwrap_or_default();
+        res.try_insert(key, value);
+    }
+    Ok(res)
+}
+
+fn decode(line: &str) -> Result<Value> {
+    let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+    let (prefix, event) = split_prefix(line, "LEEF:").ok_or_else(|| invalid("missing `LEEF:`"))?;
+    let mut res = Value::object_with_capacity(8);
+    if !prefix.is_empty() {
+        res.try_insert("prefix", prefix);
+    }
+    let mut rest = Some(event);
+    let version = next_field(&mut rest, invalid)?;
+    let v2 = version.starts_with('2');
+    if !v2 && !version.starts_with('1') {
+        return Err(invalid(&format!("unsupported version `{}`", version)));
+    }
+    res.try_insert("version", cow_to_value(version));
+    res.try_insert(
+        "device_vendor",
+        cow_to_value(next_field(&mut rest, invalid)?),
+    );
+    res.try_insert(
+        "device_product",
+        cow_to_value(next_field(&mut rest, invalid)?),
+    );
+    res.try_insert(
+        "device_version",
+        cow_to_value(next_field(&mut rest, invalid)?),
+    );
+    res.try_insert("event_id", cow_to_value(next_field(&mut rest, invalid)?));
+    let delimiter = if v2 {
+        let delimiter = delimiter(&next_field(&mut rest, invalid)?)?;
+        res.try_insert("delimiter", delimiter.to_string());
+        delimiter
+    } else {
+        '\t'
+    };
+    res.try_insert(
+        "attributes",
+        attributes(rest.unwrap_or_default(), delimiter)?,
+    );
+    Ok(res)
+}
+
+fn encode(data: &Value) -> Result<Vec<u8>> {
+    let obj = data
+        .as_object()
+        .ok_or_else(|| Error::from("LEEF events must be encoded from records"))?;
+    let mut res = String::with_capacity(128);
+    if let Some(prefix) = obj.get("prefix").and_then(Value::as_str) {
+        res.push_str(prefix);
+        res.push(' ');
+    }
+    let version = obj.get("version").and_then(Value::as_str).unwrap_or("1.0");
+    res.push_str("LEEF:");
+    res.push_str(version);
+    res.push('|');
+    push_header(&mut res, obj.get("device_vendor"));
+    push_header(&mut res, obj.get("device_product"));
+    push_header(&mut res, obj.get("device_version"));
+    push_header(&mut res, obj.get("event_id"));
+    let delimiter = obj
+        .get("delimiter")
+        .and_then(Value::as_str)
+        .and_then(|d| d.chars().next())
+        .unwrap_or('\t');
+    if version.starts_with('2') {
+        if delimiter.is_ascii_graphic() && delimiter != '|' {
+            res.push(delimiter);
+        } else {
+            res.push_str(&format!("x{:02X}", u32::from(delimiter)));
+        }
+        res.push('|');
+    }
+    if let Some(attributes) = obj.get("attributes").and_then(Value::as_object) {
+        let mut first = true;
+        for (k, v) in attributes.iter().filter(|(_, v)| !v.is_null()) {
+            if !first {
+                res.push(delimiter);
+            }
+            first = false;
+            let s = v
+                .as_str()
+                .map_or_else(|| Cow::Owned(v.encode()), Cow::Borrowed);
+            if s.contains(delimiter) {
+                return Err(format!("LEEF attribute `{}` contains the delimiter", k).into());
+            }
+            res.push_str(k);
+            res.push('=');
+            res.push_str(&s);
+        }
+    }
+    Ok(res.into_bytes())
+}
+
+impl Codec for Leef {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "leef"
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let line: &'input str = str::from_utf8(data)?;
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        decode(line).map(Some)
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        encode(data)
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    #[test]
+    fn decode_v1() -> Result<()> {
+        let mut codec = Leef {};
+        let mut data =
+            b"<13>Jan 18 11:07:53 host LEEF:1.0|Microsoft|MSExchange|4.0 SP1|15345|src=192.0.2.0\tdst=172.50.123.1\tsev=5\tmsg=a b".to_vec();
+        assert_eq!(
+            Some(literal!({
+                "prefix": "<13>Jan 18 11:07:53 host",
+                "version": "1.0",
+                "device_vendor": "Microsoft",
+                "device_product": "MSExchange",
+                "device_version": "4.0 SP1",
+                "event_id": "15345",
+                "attributes": {
+                    "src": "192.0.2.0",
+                    "dst": "172.50.123.1",
+                    "sev": "5",
+                    "msg": "a b"
+                }
+            })),
+            codec.decode(&mut data, 0)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decode_v2() -> Result<()> {
+        let mut codec = Leef {};
+        let mut data =
+            b"LEEF:2.0|Lancope|StealthWatch|1.0|41|^|src=10.0.1.8^dst=10.0.0.5^".to_vec();
+        let decoded = codec.decode(&mut data, 0)?.expect("an event");
+        assert_eq!(Some("^"), decoded.get_str("delimiter"));
+        assert_eq!(
+            Some(&literal!({"src": "10.0.1.8", "dst": "10.0.0.5"})),
+            decoded.get("attributes")
+        );
+
+        let mut data = b"LEEF:2.0|V|P|1.0|1|x09|a=b\tc=d=e".to_vec();
+        let decoded = codec.decode(&mut data, 0)?.expect("an event");
+        assert_eq!(Some("\t"), decoded.get_str("delimiter"));
+        assert_eq!(
+            Some(&literal!({"a": "b", "c": "d=e"})),
+            decoded.get("attributes")
+        );
+
+        let mut data = b"LEEF:2.0|V|P|1.0|1|xZZ|a=b".to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        let mut data = b"LEEF:3.0|V|P|1.0|1|a=b".to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        let mut data = b"LEEF:1.0|V|P|1.0|1|a=b\tjunk".to_vec();
+        assert!(codec.decode(&mut data, 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let mut codec = Leef {};
+        for line in &[
+            "LEEF:1.0|V|P\\|Q|1.0|1|a=b\tc=d",
+            "LEEF:2.0|V|P|1.0|1|^|a=b^c=d e",
+            "LEEF:2.0|V|P|1.0|1|x09|a=b\tc=d",
+        ] {
+            let mut data = line.as_bytes().to_vec();
+            let decoded = codec.decode(&mut data, 0)?.expect("an event").into_static();
+            let mut encoded = codec.encode(&decoded)?;
+            assert_eq!(*line, str::from_utf8(&encoded)?);
+            assert_eq!(Some(decoded), codec.decode(&mut encoded, 0)?);
+        }
+        Ok(())
+    }
+}
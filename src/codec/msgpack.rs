@@ -47,6 +47,10 @@ impl Codec for MsgPack {
         Ok(rmps::to_vec(&data)?)
     }
 
+    fn encode_into(&self, data: &Value, dst: &mut Vec<u8>) -> Result<()> {
+        Ok(rmps::encode::write(dst, &data)?)
+    }
+
     #[cfg(not(tarpaulin_include))]
     fn boxed_clone(&self) -> Box<dyn Codec> {
         Box::new(self.clone())
@@ -64,10 +68,31 @@ mod test {
 
         let mut codec = MsgPack {};
         let mut as_raw = codec.encode(&seed)?;
-        let as_json = codec.decode(as_raw.as_mut_slice(), 0);
+        let as_json = codec.decode(as_raw.as_mut_slice(), 0)?;
+
+        assert_eq!(Some(seed), as_json);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_msgpack_nested() -> Result<()> {
+        let seed = literal!({
+            "device": "sensor-1",
+            "readings": [1, -2, 3.5, null, true],
+            "location": { "lat": 52.5, "lon": 13.4, "tags": ["a", "b"] },
+            "empty": {}
+        });
 
-        let _ = dbg!(as_json);
+        let mut codec = MsgPack {};
+        let mut as_raw = codec.encode(&seed)?;
+        // fixmap with 4 entries
+        assert_eq!(Some(&0x84), as_raw.first());
+        let decoded = codec.decode(as_raw.as_mut_slice(), 0)?;
+        assert_eq!(Some(seed), decoded);
 
+        let mut bad = vec![0xc1];
+        assert!(codec.decode(bad.as_mut_slice(), 0).is_err());
         Ok(())
     }
 }
@@ -34,6 +34,10 @@ impl Codec for Null {
         Ok(vec![])
     }
 
+    fn encode_into(&self, _data: &Value, _dst: &mut Vec<u8>) -> Result<()> {
+        Ok(())
+    }
+
     #[cfg(not(tarpaulin_include))]
     fn boxed_clone(&self) -> Box<dyn Codec> {
         Box::new(self.clone())
@@ -0,0 +1,999 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Protocol buffers codec.
+//!
+//! Messages are described by a compiled `FileDescriptorSet`, as produced by
+//! `protoc --include_imports --descriptor_set_out=events.desc events.proto`,
+//! and `message` names the fully qualified type of the encoded messages.
+//!
+//! Messages are decoded to records keyed by field name, fields not present
+//! in the payload are omitted. Repeated fields become arrays, maps become
+//! records, enums are decoded to their symbol and bytes fields to bytes.
+//! Unknown fields are skipped when decoding, while unknown keys are rejected
+//! when encoding. Groups are not supported.
+//!
+//! ```yaml
+//! codec: protobuf
+//! codec_config:
+//!   descriptor_set: /etc/tremor/events.desc
+//!   message: com.example.Event
+//! ```
+
+use super::prelude::*;
+use crate::OpConfig;
+use beef::Cow;
+use halfbrown::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tremor_pipeline::ConfigImpl;
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Config {
+    /// path to a compiled `FileDescriptorSet`
+    descriptor_set: String,
+    /// fully qualified name of the message type
+    message: String,
+}
+
+impl ConfigImpl for Config {}
+
+pub(crate) const VARINT: u8 = 0;
+pub(crate) const FIXED64: u8 = 1;
+pub(crate) const LEN: u8 = 2;
+pub(crate) const FIXED32: u8 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Double,
+    Float,
+    Int64,
+    UInt64,
+    Int32,
+    Fixed64,
+    Fixed32,
+    Bool,
+    String,
+    Message(String),
+    Bytes,
+    UInt32,
+    Enum(String),
+    SFixed32,
+    SFixed64,
+    SInt32,
+    SInt64,
+}
+
+impl Kind {
+    fn from_descriptor(kind: u64, type_name: &str) -> Result<Self> {
+        Ok(match kind {
+            1 => Self::Double,
+            2 => Self::Float,
+            3 => Self::Int64,
+            4 => Self::UInt64,
+            5 => Self::Int32,
+            6 => Self::Fixed64,
+            7 => Self::Fixed32,
+            8 => Self::Bool,
+            9 => Self::String,
+            11 => Self::Message(type_name.to_string()),
+            12 => Self::Bytes,
+            13 => Self::UInt32,
+            14 => Self::Enum(type_name.to_string()),
+            15 => Self::SFixed32,
+            16 => Self::SFixed64,
+            17 => Self::SInt32,
+            18 => Self::SInt64,
+            10 => return Err("Protobuf groups are not supported".into()),
+            other => return Err(format!("Invalid protobuf field type {}", other).into()),
+        })
+    }
+
+    fn wire_type(&self) -> u8 {
+        match self {
+            Self::Double | Self::Fixed64 | Self::SFixed64 => FIXED64,
+            Self::Float | Self::Fixed32 | Self::SFixed32 => FIXED32,
+            Self::String | Self::Bytes | Self::Message(_) => LEN,
+            _ => VARINT,
+        }
+    }
+
+    fn packable(&self) -> bool {
+        self.wire_type() != LEN
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Field {
+    name: String,
+    number: u32,
+    kind: Kind,
+    repeated: bool,
+    packed: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Message {
+    fields: Vec<Field>,
+    map_entry: bool,
+}
+
+impl Message {
+    fn field(&self, number: u32) -> Option<&Field> {
+        self.fields.iter().find(|f| f.number == number)
+    }
+}
+
+/// Message and enum types of a `FileDescriptorSet`, keyed by their fully
+/// qualified name with a leading `.`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Descriptors {
+    messages: HashMap<String, Message>,
+    enums: HashMap<String, Vec<(String, i32)>>,
+}
+
+/// Iterates the `(field number, value)` pairs of an encoded message
+pub(crate) struct Fields<'input> {
+    data: &'input [u8],
+    pos: usize,
+}
+
+pub(crate) enum Wire<'input> {
+    Varint(u64),
+    Fixed64([u8; 8]),
+    Len(&'input [u8]),
+    Fixed32([u8; 4]),
+}
+
+impl<'input> Fields<'input> {
+    pub(crate) fn new(data: &'input [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'input [u8]> {
+        let data: &'input [u8] = self.data;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= data.len())
+            .ok_or_else(|| Error::from("Invalid protobuf data: unexpected end of input"))?;
+        let res = &data[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut res: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.take(1)?[0];
+            res |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(res);
+            }
+        }
+        Err("Invalid protobuf data: varint too long".into())
+    }
+
+    fn wire(&mut self, wire_type: u8) -> Result<Wire<'input>> {
+        Ok(match wire_type {
+            VARINT => Wire::Varint(self.varint()?),
+            FIXED64 => {
+                let mut b = [0_u8; 8];
+                b.copy_from_slice(self.take(8)?);
+                Wire::Fixed64(b)
+            }
+            LEN => {
+                let len = usize::try_from(self.varint()?)?;
+                Wire::Len(self.take(len)?)
+            }
+            FIXED32 => {
+                let mut b = [0_u8; 4];
+                b.copy_from_slice(self.take(4)?);
+                Wire::Fixed32(b)
+            }
+            other => {
+                return Err(format!("Unsupported protobuf wire type {}", other).into());
+            }
+        })
+    }
+}
+
+impl<'input> Iterator for Fields<'input> {
+    type Item = Result<(u32, Wire<'input>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        Some(self.varint().and_then(|key| {
+            let number = u32::try_from(key >> 3)?;
+            #[allow(clippy::cast_possible_truncation)]
+            let wire_type = (key & 0x07) as u8;
+            Ok((number, self.wire(wire_type)?))
+        }))
+    }
+}
+
+fn str_of(data: &[u8]) -> Result<&str> {
+    Ok(std::str::from_utf8(data)?)
+}
+
+impl Descriptors {
+    /// Parses a binary `FileDescriptorSet`
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        let mut res = Self::default();
+        for f in Fields::new(data) {
+            if let (1, Wire::Len(file)) = f? {
+                res.parse_file(file)?;
+            }
+        }
+        Ok(res)
+    }
+
+    fn parse_file(&mut self, data: &[u8]) -> Result<()> {
+        let mut package = "";
+        let mut proto3 = false;
+        let mut messages = Vec::new();
+        let mut enums = Vec::new();
+        for f in Fields::new(data) {
+            match f? {
+                (2, Wire::Len(d)) => package = str_of(d)?,
+                (4, Wire::Len(d)) => messages.push(d),
+                (5, Wire::Len(d)) => enums.push(d),
+                (12, Wire::Len(d)) => proto3 = d == b"proto3",
+                _ => (),
+            }
+        }
+        let scope = if package.is_empty() {
+            String::new()
+        } else {
+            format!(".{}", package)
+        };
+        for m in messages {
+            self.parse_message(&scope, m, proto3)?;
+        }
+        for e in enums {
+            self.parse_enum(&scope, e)?;
+        }
+        Ok(())
+    }
+
+    fn parse_message(&mut self, scope: &str, data: &[u8], proto3: bool) -> Result<()> {
+        let mut name = "";
+        let mut fields = Vec::new();
+        let mut nested = Vec::new();
+        let mut enums = Vec::new();
+        let mut map_entry = false;
+        for f in Fields::new(data) {
+            match f? {
+                (1, Wire::Len(d)) => name = str_of(d)?,
+                (2, Wire::Len(d)) => fields.push(d),
+                (3, Wire::Len(d)) => nested.push(d),
+                (4, Wire::Len(d)) => enums.push(d),
+                (7, Wire::Len(options)) => {
+                    for o in Fields::new(options) {
+                        if let (7, Wire::Varint(v)) = o? {
+                            map_entry = v != 0;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        let full_name = format!("{}.{}", scope, name);
+        let fields = fields
+            .into_iter()
+            .map(|d| Self::parse_field(d, proto3))
+            .collect::<Result<_>>()?;
+        for n in nested {
+            self.parse_message(&full_name, n, proto3)?;
+        }
+        for e in enums {
+            self.parse_enum(&full_name, e)?;
+        }
+        self.messages
+            .insert(full_name, Message { fields, map_entry });
+        Ok(())
+    }
+
+    fn parse_field(data: &[u8], proto3: bool) -> Result<Field> {
+        let mut name = "";
+        let mut number = 0;
+        let mut repeated = false;
+        let mut kind = 0;
+        let mut type_name = "";
+        let mut packed = None;
+        for f in Fields::new(data) {
+            match f? {
+                (1, Wire::Len(d)) => name = str_of(d)?,
+                (3, Wire::Varint(v)) => number = u32::try_from(v)?,
+                (4, Wire::Varint(v)) => repeated = v == 3,
+                (5, Wire::Varint(v)) => kind = v,
+                (6, Wire::Len(d)) => type_name = str_of(d)?,
+                (8, Wire::Len(options)) => {
+                    for o in Fields::new(options) {
+                        if let (2, Wire::Varint(v)) = o? {
+                            packed = Some(v != 0);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        let kind = Kind::from_descriptor(kind, type_name)?;
+        // proto3 packs repeated scalars unless told otherwise
+        let packed = repeated && kind.packable() && packed.unwrap_or(proto3);
+        Ok(Field {
+            name: name.to_string(),
+            number,
+            kind,
+            repeated,
+            packed,
+        })
+    }
+
+    fn parse_enum(&mut self, scope: &str, data: &[u8]) -> Result<()> {
+        let mut name = "";
+        let mut values = Vec::new();
+        for f in Fields::new(data) {
+            match f? {
+                (1, Wire::Len(d)) => name = str_of(d)?,
+                (2, Wire::Len(d)) => {
+                    let mut value_name = "";
+                    let mut number = 0;
+                    for v in Fields::new(d) {
+                        match v? {
+                            (1, Wire::Len(d)) => value_name = str_of(d)?,
+                            #[allow(clippy::cast_possible_truncation)]
+                            (2, Wire::Varint(n)) => number = n as i32,
+                            _ => (),
+                        }
+                    }
+                    values.push((value_name.to_string(), number));
+                }
+                _ => (),
+            }
+        }
+        self.enums.insert(format!("{}.{}", scope, name), values);
+        Ok(())
+    }
+
+    fn message(&self, name: &str) -> Result<&Message> {
+        self.messages
+            .get(name)
+            .ok_or_else(|| format!("Unknown protobuf message {}", name).into())
+    }
+
+    fn enumeration(&self, name: &str) -> Result<&[(String, i32)]> {
+        self.enums
+            .get(name)
+            .map(Vec::as_slice)
+            .ok_or_else(|| format!("Unknown protobuf enum {}", name).into())
+    }
+
+    /// checks that all types referenced from `name` are known
+    fn validate(&self, name: &str) -> Result<()> {
+        let mut pending = vec![name];
+        let mut seen = Vec::new();
+        while let Some(name) = pending.pop() {
+            if seen.contains(&name) {
+                continue;
+            }
+            seen.push(name);
+            for field in &self.message(name)?.fields {
+                match &field.kind {
+                    Kind::Message(m) => pending.push(m),
+                    Kind::Enum(e) => {
+                        self.enumeration(e)?;
+                    }
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Protobuf {
+    descriptors: Arc<Descriptors>,
+    message: String,
+}
+
+impl Protobuf {
+    pub(crate) fn from_config(config: &Option<OpConfig>) -> Result<Self> {
+        let config = config
+            .as_ref()
+            .map(Config::new)
+            .transpose()?
+            .ok_or_else(|| {
+                Error::from("The protobuf codec requires a `descriptor_set` and a `message`")
+            })?;
+        let data = std::fs::read(&config.descriptor_set).map_err(|e| {
+            Error::from(format!(
+                "Unable to read protobuf descriptor set {}: {}",
+                config.descriptor_set, e
+            ))
+        })?;
+        Self::new(Descriptors::parse(&data)?, &config.message)
+    }
+
+    fn new(descriptors: Descriptors, message: &str) -> Result<Self> {
+        let message = if message.starts_with('.') {
+            message.to_string()
+        } else {
+            format!(".{}", message)
+        };
+        descriptors.validate(&message)?;
+        Ok(Self {
+            descriptors: Arc::new(descriptors),
+            message,
+        })
+    }
+}
+
+impl Codec for Protobuf {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "protobuf"
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn mime_types(&self) -> Vec<&str> {
+        vec!["application/protobuf", "application/x-protobuf"]
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let data: &'input [u8] = data;
+        decode_message(&self.descriptors, &self.message, data).map(Some)
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(128);
+        encode_message(&mut buf, &self.descriptors, &self.message, data)?;
+        Ok(buf)
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn zigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+fn decode_scalar<'input>(
+    descriptors: &Descriptors,
+    kind: &Kind,
+    wire: Wire<'input>,
+) -> Result<Value<'input>> {
+    Ok(match (kind, wire) {
+        (Kind::Int64, Wire::Varint(v)) => Value::from(v as i64),
+        (Kind::Int32, Wire::Varint(v)) => Value::from(i64::from(v as i32)),
+        (Kind::UInt64, Wire::Varint(v)) => Value::from(v),
+        (Kind::UInt32, Wire::Varint(v)) => Value::from(u64::from(v as u32)),
+        (Kind::SInt64, Wire::Varint(v)) | (Kind::SInt32, Wire::Varint(v)) => Value::from(zigzag(v)),
+        (Kind::Bool, Wire::Varint(v)) => Value::from(v != 0),
+        (Kind::Enum(name), Wire::Varint(v)) => {
+            let n = v as i32;
+            descriptors
+                .enumeration(name)?
+                .iter()
+                .find(|(_, number)| *number == n)
+                .map_or_else(
+                    || Value::from(i64::from(n)),
+                    |(s, _)| Value::from(s.clone()),
+                )
+        }
+        (Kind::Double, Wire::Fixed64(b)) => Value::from(f64::from_le_bytes(b)),
+        (Kind::Fixed64, Wire::Fixed64(b)) => Value::from(u64::from_le_bytes(b)),
+        (Kind::SFixed64, Wire::Fixed64(b)) => Value::from(i64::from_le_bytes(b)),
+        (Kind::Float, Wire::Fixed32(b)) => Value::from(f64::from(f32::from_le_bytes(b))),
+        (Kind::Fixed32, Wire::Fixed32(b)) => Value::from(u64::from(u32::from_le_bytes(b))),
+        (Kind::SFixed32, Wire::Fixed32(b)) => Value::from(i64::from(i32::from_le_bytes(b))),
+        (Kind::String, Wire::Len(d)) => Value::from(str_of(d)?),
+        (Kind::Bytes, Wire::Len(d)) => Value::Bytes(d.into()),
+        (Kind::Message(name), Wire::Len(d)) => decode_message(descriptors, name, d)?,
+        (kind, _) => return Err(format!("Invalid protobuf wire type for {:?}", kind).into()),
+    })
+}
+
+/// the record key of a map entry key
+fn map_key(key: &Value) -> String {
+    key.as_str()
+        .map_or_else(|| key.encode(), ToString::to_string)
+}
+
+fn decode_message<'input>(
+    descriptors: &Descriptors,
+    name: &str,
+    data: &'input [u8],
+) -> Result<Value<'input>> {
+    let message = descriptors.message(name)?;
+    let mut obj = Object::with_capacity(message.fields.len());
+    for f in Fields::new(data) {
+        let (number, wire) = f?;
+        let field = if let Some(field) = message.field(number) {
+            field
+        } else {
+            continue;
+        };
+        let key = Cow::from(field.name.clone());
+        match (&field.kind, wire) {
+            (Kind::Message(entry), Wire::Len(d))
+                if field.repeated && descriptors.message(entry)?.map_entry =>
+            {
+                let mut entry = decode_message(descriptors, entry, d)?;
+                let k = entry.get("key").map_or_else(String::new, map_key);
+                let v = entry
+                    .as_object_mut()
+                    .and_then(|e| e.remove("value"))
+                    .unwrap_or_else(Value::null);
+                if let Some(map) = obj
+                    .entry(key)
+                    .or_insert_with(|| Value::object_with_capacity(1))
+                    .as_object_mut()
+                {
+                    map.insert(k.into(), v);
+                }
+            }
+            (kind, Wire::Len(d)) if field.repeated && kind.packable() => {
+                let values = obj
+                    .entry(key)
+                    .or_insert_with(|| Value::array_with_capacity(d.len()));
+                let mut packed = Fields::new(d);
+                while packed.pos < d.len() {
+                    let v = decode_scalar(descriptors, kind, packed.wire(kind.wire_type())?)?;
+                    if let Some(values) = values.as_array_mut() {
+                        values.push(v);
+                    }
+                }
+            }
+            (kind, wire) if field.repeated => {
+                let v = decode_scalar(descriptors, kind, wire)?;
+                if let Some(values) = obj
+                    .entry(key)
+                    .or_insert_with(|| Value::array_with_capacity(1))
+                    .as_array_mut()
+                {
+                    values.push(v);
+                }
+            }
+            (kind, wire) => {
+                obj.insert(key, decode_scalar(descriptors, kind, wire)?);
+            }
+        }
+    }
+    Ok(Value::from(obj))
+}
+
+pub(crate) fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    loop {
+        #[allow(clippy::cast_possible_truncation)]
+        let b = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            buf.push(b);
+            break;
+        }
+        buf.push(b | 0x80);
+    }
+}
+
+pub(crate) fn write_len(buf: &mut Vec<u8>, data: &[u8]) {
+    write_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+pub(crate) fn write_key(buf: &mut Vec<u8>, number: u32, wire_type: u8) {
+    write_varint(buf, u64::from(number) << 3 | u64::from(wire_type));
+}
+
+#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+fn encode_scalar(
+    buf: &mut Vec<u8>,
+    descriptors: &Descriptors,
+    kind: &Kind,
+    value: &Value,
+) -> Result<()> {
+    let mismatch =
+        || -> Error { format!("Expected protobuf {:?}, got {}", kind, value.encode()).into() };
+    match kind {
+        Kind::Int64 => write_varint(buf, value.as_i64().ok_or_else(mismatch)? as u64),
+        Kind::Int32 => write_varint(buf, i64::from(value.as_i32().ok_or_else(mismatch)?) as u64),
+        Kind::UInt64 => write_varint(buf, value.as_u64().ok_or_else(mismatch)?),
+        Kind::UInt32 => write_varint(buf, u64::from(value.as_u32().ok_or_else(mismatch)?)),
+        Kind::SInt64 | Kind::SInt32 => {
+            let n = if *kind == Kind::SInt32 {
+                i64::from(value.as_i32().ok_or_else(mismatch)?)
+            } else {
+                value.as_i64().ok_or_else(mismatch)?
+            };
+            write_varint(buf, ((n << 1) ^ (n >> 63)) as u64)
+        }
+        Kind::Bool => write_varint(buf, u64::from(value.as_bool().ok_or_else(mismatch)?)),
+        Kind::Enum(name) => {
+            let n = if let Some(symbol) = value.as_str() {
+                descriptors
+                    .enumeration(name)?
+                    .iter()
+                    .find(|(s, _)| s == symbol)
+                    .map(|(_, n)| *n)
+                    .ok_or_else(mismatch)?
+            } else {
+                value.as_i32().ok_or_else(mismatch)?
+            };
+            write_varint(buf, i64::from(n) as u64)
+        }
+        Kind::Double => {
+            buf.extend_from_slice(&value.cast_f64().ok_or_else(mismatch)?.to_le_bytes())
+        }
+        Kind::Float => {
+            buf.extend_from_slice(&(value.cast_f64().ok_or_else(mismatch)? as f32).to_le_bytes())
+        }
+        Kind::Fixed64 => buf.extend_from_slice(&value.as_u64().ok_or_else(mismatch)?.to_le_bytes()),
+        Kind::SFixed64 => {
+            buf.extend_from_slice(&value.as_i64().ok_or_else(mismatch)?.to_le_bytes())
+        }
+        Kind::Fixed32 => buf.extend_from_slice(&value.as_u32().ok_or_else(mismatch)?.to_le_bytes()),
+        Kind::SFixed32 => {
+            buf.extend_from_slice(&value.as_i32().ok_or_else(mismatch)?.to_le_bytes())
+        }
+        Kind::String => write_len(buf, value.as_str().ok_or_else(mismatch)?.as_bytes()),
+        Kind::Bytes => match value {
+            Value::Bytes(b) => write_len(buf, b),
+            Value::String(s) => write_len(buf, s.as_bytes()),
+            _ => return Err(mismatch()),
+        },
+        Kind::Message(name) => {
+            let mut nested = Vec::with_capacity(32);
+            encode_message(&mut nested, descriptors, name, value)?;
+            write_len(buf, &nested);
+        }
+    }
+    Ok(())
+}
+
+/// parses a record key back into the key type of a map entry
+fn parse_map_key(kind: &Kind, key: &str) -> Result<Value<'static>> {
+    let invalid = || Error::from(format!("Invalid protobuf map key {}", key));
+    Ok(match kind {
+        Kind::String => Value::from(key.to_string()),
+        Kind::Bool => Value::from(key.parse::<bool>().map_err(|_| invalid())?),
+        Kind::UInt64 | Kind::UInt32 | Kind::Fixed64 | Kind::Fixed32 => {
+            Value::from(key.parse::<u64>().map_err(|_| invalid())?)
+        }
+        _ => Value::from(key.parse::<i64>().map_err(|_| invalid())?),
+    })
+}
+
+fn encode_field(
+    buf: &mut Vec<u8>,
+    descriptors: &Descriptors,
+    field: &Field,
+    value: &Value,
+) -> Result<()> {
+    if let (Kind::Message(entry), true) = (&field.kind, field.repeated) {
+        let entry = descriptors.message(entry)?;
+        if entry.map_entry {
+            let map = value.as_object().ok_or_else(|| {
+                Error::from(format!("Expected a record for protobuf map {}", field.name))
+            })?;
+            let (key, val) = match (entry.field(1), entry.field(2)) {
+                (Some(k), Some(v)) => (k, v),
+                _ => return Err(format!("Invalid protobuf map entry for {}", field.name).into()),
+            };
+            for (k, v) in map.iter() {
+                let mut nested = Vec::with_capacity(32);
+                write_key(&mut nested, key.number, key.kind.wire_type());
+                encode_scalar(
+                    &mut nested,
+                    descriptors,
+                    &key.kind,
+                    &parse_map_key(&key.kind, k)?,
+                )?;
+                write_key(&mut nested, val.number, val.kind.wire_type());
+                encode_scalar(&mut nested, descriptors, &val.kind, v)?;
+                write_key(buf, field.number, LEN);
+                write_len(buf, &nested);
+            }
+            return Ok(());
+        }
+    }
+    if field.repeated {
+        let values = value.as_array().ok_or_else(|| {
+            Error::from(format!(
+                "Expected an array for repeated protobuf field {}",
+                field.name
+            ))
+        })?;
+        if field.packed {
+            if !values.is_empty() {
+                let mut packed = Vec::with_capacity(values.len() * 8);
+                for v in values {
+                    encode_scalar(&mut packed, descriptors, &field.kind, v)?;
+                }
+                write_key(buf, field.number, LEN);
+                write_len(buf, &packed);
+            }
+        } else {
+            for v in values {
+                write_key(buf, field.number, field.kind.wire_type());
+                encode_scalar(buf, descriptors, &field.kind, v)?;
+            }
+        }
+    } else if !value.is_null() {
+        write_key(buf, field.number, field.kind.wire_type());
+        encode_scalar(buf, descriptors, &field.kind, value)?;
+    }
+    Ok(())
+}
+
+fn encode_message(
+    buf: &mut Vec<u8>,
+    descriptors: &Descriptors,
+    name: &str,
+    value: &Value,
+) -> Result<()> {
+    let message = descriptors.message(name)?;
+    let obj = value.as_object().ok_or_else(|| {
+        Error::from(format!(
+            "Expected a record for protobuf message {}, got {}",
+            name,
+            value.encode()
+        ))
+    })?;
+    for (k, v) in obj.iter() {
+        let field = message
+            .fields
+            .iter()
+            .find(|f| f.name.as_str() == &**k)
+            .ok_or_else(|| {
+                Error::from(format!("Unknown field {} of protobuf message {}", k, name))
+            })?;
+        encode_field(buf, descriptors, field, v)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tremor_value::literal;
+
+    fn string(buf: &mut Vec<u8>, number: u32, s: &str) {
+        write_key(buf, number, LEN);
+        write_len(buf, s.as_bytes());
+    }
+
+    fn nested(buf: &mut Vec<u8>, number: u32, data: &[u8]) {
+        write_key(buf, number, LEN);
+        write_len(buf, data);
+    }
+
+    fn varint(buf: &mut Vec<u8>, number: u32, v: u64) {
+        write_key(buf, number, VARINT);
+        write_varint(buf, v);
+    }
+
+    fn field(name: &str, number: u64, label: u64, kind: u64, type_name: &str) -> Vec<u8> {
+        let mut f = Vec::new();
+        string(&mut f, 1, name);
+        varint(&mut f, 3, number);
+        varint(&mut f, 4, label);
+        varint(&mut f, 5, kind);
+        if !type_name.is_empty() {
+            string(&mut f, 6, type_name);
+        }
+        f
+    }
+
+    /// The descriptor set of
+    ///
+    /// ```proto
+    /// syntax = "proto3";
+    /// package com.example;
+    /// enum Kind { A = 0; B = 1; }
+    /// message Event {
+    ///   int64 id = 1;
+    ///   string name = 2;
+    ///   double score = 3;
+    ///   repeated string tags = 4;
+    ///   map<string, int32> attrs = 5;
+    ///   Kind kind = 6;
+    ///   Event parent = 7;
+    ///   repeated sint32 deltas = 8;
+    ///   bytes payload = 9;
+    ///   bool flag = 10;
+    /// }
+    /// ```
+    fn descriptor_set() -> Vec<u8> {
+        let mut entry = Vec::new();
+        string(&mut entry, 1, "AttrsEntry");
+        nested(&mut entry, 2, &field("key", 1, 1, 9, ""));
+        nested(&mut entry, 2, &field("value", 2, 1, 5, ""));
+        let mut options = Vec::new();
+        varint(&mut options, 7, 1);
+        nested(&mut entry, 7, &options);
+
+        let mut event = Vec::new();
+        string(&mut event, 1, "Event");
+        nested(&mut event, 2, &field("id", 1, 1, 3, ""));
+        nested(&mut event, 2, &field("name", 2, 1, 9, ""));
+        nested(&mut event, 2, &field("score", 3, 1, 1, ""));
+        nested(&mut event, 2, &field("tags", 4, 3, 9, ""));
+        nested(
+            &mut event,
+            2,
+            &field("attrs", 5, 3, 11, ".com.example.Event.AttrsEntry"),
+        );
+        nested(&mut event, 2, &field("kind", 6, 1, 14, ".com.example.Kind"));
+        nested(
+            &mut event,
+            2,
+            &field("parent", 7, 1, 11, ".com.example.Event"),
+        );
+        nested(&mut event, 2, &field("deltas", 8, 3, 17, ""));
+        nested(&mut event, 2, &field("payload", 9, 1, 12, ""));
+        nested(&mut event, 2, &field("flag", 10, 1, 8, ""));
+        nested(&mut event, 3, &entry);
+
+        let mut kind = Vec::new();
+        string(&mut kind, 1, "Kind");
+        for (i, symbol) in ["A", "B"].iter().enumerate() {
+            let mut value = Vec::new();
+            string(&mut value, 1, symbol);
+            varint(&mut value, 2, i as u64);
+            nested(&mut kind, 2, &value);
+        }
+
+        let mut file = Vec::new();
+        string(&mut file, 1, "event.proto");
+        string(&mut file, 2, "com.example");
+        nested(&mut file, 4, &event);
+        nested(&mut file, 5, &kind);
+        string(&mut file, 12, "proto3");
+
+        let mut set = Vec::new();
+        nested(&mut set, 1, &file);
+        set
+    }
+
+    fn codec() -> Protobuf {
+        let descriptors = Descriptors::parse(&descriptor_set()).expect("valid descriptor set");
+        Protobuf::new(descriptors, "com.example.Event").expect("valid message")
+    }
+
+    #[test]
+    fn parse_descriptors() -> Result<()> {
+        let descriptors = Descriptors::parse(&descriptor_set())?;
+        let event = descriptors.message(".com.example.Event")?;
+        assert_eq!(10, event.fields.len());
+        assert!(event.field(8).map_or(false, |f| f.packed));
+        assert!(event.field(4).map_or(false, |f| !f.packed));
+        assert!(
+            descriptors
+                .message(".com.example.Event.AttrsEntry")?
+                .map_entry
+        );
+        assert_eq!(2, descriptors.enumeration(".com.example.Kind")?.len());
+        assert!(Protobuf::new(descriptors, "com.example.Snot").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn wire_format() -> Result<()> {
+        let codec = codec();
+        // the example from the protobuf encoding guide
+        assert_eq!(
+            vec![0x08, 0x96, 0x01],
+            codec.encode(&literal!({"id": 150}))?
+        );
+        assert_eq!(
+            vec![0x12, 0x07, b't', b'e', b's', b't', b'i', b'n', b'g'],
+            codec.encode(&literal!({"name": "testing"}))?
+        );
+        // packed sint32
+        assert_eq!(
+            vec![0x42, 0x03, 0x01, 0x02, 0x03],
+            codec.encode(&literal!({"deltas": [-1, 1, -2]}))?
+        );
+        // negative int64 uses ten bytes
+        assert_eq!(11, codec.encode(&literal!({"id": -1}))?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut codec = codec();
+        let event = literal!({
+            "id": 1_234_567_890_123_i64,
+            "name": "snot",
+            "score": 0.5,
+            "tags": ["a", "b"],
+            "attrs": {"x": 1, "y": -2},
+            "kind": "B",
+            "parent": {
+                "id": -1,
+                "name": "badger",
+                "kind": "A"
+            },
+            "deltas": [-1, 0, 300],
+            "flag": true
+        });
+        let mut data = codec.encode(&event)?;
+        let decoded = codec.decode(data.as_mut_slice(), 0)?.expect("no value");
+        assert_eq!(event, decoded);
+
+        let mut event = literal!({"payload": "snot"});
+        if let Some(obj) = event.as_object_mut() {
+            obj.insert("payload".into(), Value::Bytes(b"badger".to_vec().into()));
+        }
+        let mut data = codec.encode(&event)?;
+        let decoded = codec.decode(data.as_mut_slice(), 0)?.expect("no value");
+        assert_eq!(event, decoded);
+        Ok(())
+    }
+
+    #[test]
+    fn unpacked_and_unknown() -> Result<()> {
+        let mut codec = codec();
+        let mut data = Vec::new();
+        // unpacked sint32 values are accepted as well
+        varint(&mut data, 8, 1);
+        varint(&mut data, 8, 2);
+        // unknown fields are skipped
+        string(&mut data, 99, "snot");
+        let decoded = codec.decode(data.as_mut_slice(), 0)?.expect("no value");
+        assert_eq!(literal!({"deltas": [-1, 1]}), decoded);
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        let mut codec = codec();
+        assert!(codec.encode(&literal!({"snot": 1})).is_err());
+        assert!(codec.encode(&literal!({"kind": "C"})).is_err());
+        assert!(codec.encode(&literal!({"name": 1})).is_err());
+        assert!(codec.encode(&literal!([])).is_err());
+        let mut data = vec![0x12, 0x07, b't'];
+        assert!(codec.decode(data.as_mut_slice(), 0).is_err());
+        let mut data = vec![0x12, 0x01, 0xff];
+        assert!(codec.decode(data.as_mut_slice(), 0).is_err());
+    }
+
+    #[test]
+    fn from_config() -> Result<()> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(&descriptor_set())?;
+        let path = file.path().to_string_lossy().to_string();
+        let config = literal!({"descriptor_set": path.clone(), "message": ".com.example.Event"});
+        let config: OpConfig = serde_yaml::from_str(&config.encode())?;
+        assert!(Protobuf::from_config(&Some(config)).is_ok());
+        let config = literal!({"descriptor_set": path, "message": "com.example.Kind"});
+        let config: OpConfig = serde_yaml::from_str(&config.encode())?;
+        assert!(Protobuf::from_config(&Some(config)).is_err());
+        assert!(Protobuf::from_config(&None).is_err());
+        Ok(())
+    }
+}
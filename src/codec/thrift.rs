@@ -0,0 +1,1215 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Thrift codec for serialized structs in the `binary` or `compact` protocol.
+//!
+//! Given an `idl` file and the name of the root `struct`, fields are decoded
+//! by name and encoded according to their declared types. Without an IDL,
+//! fields are keyed by their numeric id and types for encoding are inferred
+//! from the values.
+//!
+//! ```yaml
+//! codec: thrift
+//! codec_config:
+//!   protocol: compact
+//!   idl: /etc/tremor/idl/event.thrift
+//!   struct: Event
+//! ```
+
+use super::prelude::*;
+use crate::OpConfig;
+use halfbrown::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use tremor_pipeline::ConfigImpl;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Protocol {
+    Binary,
+    Compact,
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self::Binary
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Default)]
+pub(crate) struct Config {
+    #[serde(default)]
+    protocol: Protocol,
+    /// path to the thrift IDL file
+    idl: Option<String>,
+    /// the root struct of the encoded data
+    #[serde(rename = "struct")]
+    root: Option<String>,
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Type {
+    Bool,
+    Byte,
+    I16,
+    I32,
+    I64,
+    Double,
+    String,
+    Binary,
+    List(Box<Type>),
+    Set(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    /// a struct, enum or typedef
+    Named(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Field {
+    id: i16,
+    name: String,
+    ty: Type,
+    required: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Idl {
+    structs: HashMap<String, Vec<Field>>,
+    enums: HashMap<String, HashMap<i32, String>>,
+    typedefs: HashMap<String, Type>,
+}
+
+#[derive(Clone)]
+pub struct Thrift {
+    protocol: Protocol,
+    idl: Arc<Idl>,
+    root: Option<String>,
+}
+
+impl Thrift {
+    pub(crate) fn from_config(config: &Option<OpConfig>) -> Result<Self> {
+        let config = config
+            .as_ref()
+            .map(Config::new)
+            .transpose()?
+            .unwrap_or_default();
+        let idl = if let Some(path) = &config.idl {
+            let raw = std::fs::read_to_string(path)
+                .map_err(|e| Error::from(format!("Unable to read thrift IDL {}: {}", path, e)))?;
+            Idl::parse(&raw)?
+        } else {
+            Idl::default()
+        };
+        if let Some(root) = &config.root {
+            if !idl.structs.contains_key(root) {
+                return Err(format!("Struct {} not found in thrift IDL", root).into());
+            }
+        }
+        Ok(Self {
+            protocol: config.protocol,
+            idl: Arc::new(idl),
+            root: config.root,
+        })
+    }
+}
+
+impl Codec for Thrift {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "thrift"
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn mime_types(&self) -> Vec<&str> {
+        vec![
+            "application/x-thrift",
+            "application/vnd.apache.thrift.binary",
+        ]
+    }
+
+    fn decode<'input>(
+        &mut self,
+        data: &'input mut [u8],
+        _ingest_ns: u64,
+    ) -> Result<Option<Value<'input>>> {
+        let mut reader = Reader {
+            data,
+            pos: 0,
+            compact: self.protocol == Protocol::Compact,
+            last_ids: vec![],
+            pending_bool: None,
+        };
+        let value = reader.read_struct(&self.idl, self.root.as_deref())?;
+        Ok(Some(value))
+    }
+
+    fn encode(&self, data: &Value) -> Result<Vec<u8>> {
+        let mut writer = Writer {
+            buf: Vec::with_capacity(128),
+            compact: self.protocol == Protocol::Compact,
+            last_ids: vec![],
+        };
+        writer.write_struct(&self.idl, self.root.as_deref(), data)?;
+        Ok(writer.buf)
+    }
+
+    #[cfg(not(tarpaulin_include))]
+    fn boxed_clone(&self) -> Box<dyn Codec> {
+        Box::new(self.clone())
+    }
+}
+
+/// Protocol independent wire types
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Wire {
+    Bool,
+    Byte,
+    I16,
+    I32,
+    I64,
+    Double,
+    Binary,
+    Struct,
+    Map,
+    Set,
+    List,
+}
+
+impl Wire {
+    fn binary_id(self) -> u8 {
+        match self {
+            Wire::Bool => 2,
+            Wire::Byte => 3,
+            Wire::Double => 4,
+            Wire::I16 => 6,
+            Wire::I32 => 8,
+            Wire::I64 => 10,
+            Wire::Binary => 11,
+            Wire::Struct => 12,
+            Wire::Map => 13,
+            Wire::Set => 14,
+            Wire::List => 15,
+        }
+    }
+    fn from_binary(id: u8) -> Result<Self> {
+        Ok(match id {
+            2 => Wire::Bool,
+            3 => Wire::Byte,
+            4 => Wire::Double,
+            6 => Wire::I16,
+            8 => Wire::I32,
+            10 => Wire::I64,
+            11 => Wire::Binary,
+            12 => Wire::Struct,
+            13 => Wire::Map,
+            14 => Wire::Set,
+            15 => Wire::List,
+            other => return Err(format!("Invalid thrift type id {}", other).into()),
+        })
+    }
+    fn compact_id(self) -> u8 {
+        match self {
+            Wire::Bool => 1,
+            Wire::Byte => 3,
+            Wire::I16 => 4,
+            Wire::I32 => 5,
+            Wire::I64 => 6,
+            Wire::Double => 7,
+            Wire::Binary => 8,
+            Wire::List => 9,
+            Wire::Set => 10,
+            Wire::Map => 11,
+            Wire::Struct => 12,
+        }
+    }
+    fn from_compact(id: u8) -> Result<Self> {
+        Ok(match id {
+            1 | 2 => Wire::Bool,
+            3 => Wire::Byte,
+            4 => Wire::I16,
+            5 => Wire::I32,
+            6 => Wire::I64,
+            7 => Wire::Double,
+            8 => Wire::Binary,
+            9 => Wire::List,
+            10 => Wire::Set,
+            11 => Wire::Map,
+            12 => Wire::Struct,
+            other => return Err(format!("Invalid thrift compact type id {}", other).into()),
+        })
+    }
+}
+
+impl Idl {
+    fn resolve<'t>(&'t self, ty: &'t Type) -> &'t Type {
+        let mut ty = ty;
+        // bounded to avoid looping on cyclic typedefs
+        for _ in 0..32 {
+            match ty {
+                Type::Named(n) => match self.typedefs.get(n) {
+                    Some(t) => ty = t,
+                    None => return ty,
+                },
+                _ => return ty,
+            }
+        }
+        ty
+    }
+
+    fn wire(&self, ty: &Type) -> Wire {
+        match self.resolve(ty) {
+            Type::Bool => Wire::Bool,
+            Type::Byte => Wire::Byte,
+            Type::I16 => Wire::I16,
+            Type::I32 => Wire::I32,
+            Type::I64 => Wire::I64,
+            Type::Double => Wire::Double,
+            Type::String | Type::Binary => Wire::Binary,
+            Type::List(_) => Wire::List,
+            Type::Set(_) => Wire::Set,
+            Type::Map(_, _) => Wire::Map,
+            Type::Named(n) if self.enums.contains_key(n) => Wire::I32,
+            Type::Named(_) => Wire::Struct,
+        }
+    }
+}
+
+struct Reader<'data> {
+    data: &'data [u8],
+    pos: usize,
+    compact: bool,
+    last_ids: Vec<i16>,
+    pending_bool: Option<bool>,
+}
+
+impl<'data> Reader<'data> {
+    fn take(&mut self, n: usize) -> Result<&'data [u8]> {
+        let data: &'data [u8] = self.data;
+        let end = self.pos + n;
+        let res = data
+            .get(self.pos..end)
+            .ok_or_else(|| Error::from("Unexpected end of thrift data"))?;
+        self.pos = end;
+        Ok(res)
+    }
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?.first().copied().unwrap_or_default())
+    }
+    fn varint(&mut self) -> Result<u64> {
+        let mut result = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            result |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err("Invalid thrift varint".into())
+    }
+    #[allow(clippy::cast_possible_wrap)]
+    fn zigzag(&mut self) -> Result<i64> {
+        let n = self.varint()?;
+        Ok((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+    fn be<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut res = [0_u8; N];
+        res.copy_from_slice(self.take(N)?);
+        Ok(res)
+    }
+    fn read_i16(&mut self) -> Result<i16> {
+        if self.compact {
+            Ok(i16::try_from(self.zigzag()?)?)
+        } else {
+            Ok(i16::from_be_bytes(self.be()?))
+        }
+    }
+    fn read_i32(&mut self) -> Result<i32> {
+        if self.compact {
+            Ok(i32::try_from(self.zigzag()?)?)
+        } else {
+            Ok(i32::from_be_bytes(self.be()?))
+        }
+    }
+    fn read_i64(&mut self) -> Result<i64> {
+        if self.compact {
+            self.zigzag()
+        } else {
+            Ok(i64::from_be_bytes(self.be()?))
+        }
+    }
+    fn read_double(&mut self) -> Result<f64> {
+        if self.compact {
+            Ok(f64::from_le_bytes(self.be()?))
+        } else {
+            Ok(f64::from_be_bytes(self.be()?))
+        }
+    }
+    fn read_binary(&mut self) -> Result<&'data [u8]> {
+        let len = if self.compact {
+            usize::try_from(self.varint()?)?
+        } else {
+            usize::try_from(i32::from_be_bytes(self.be()?))?
+        };
+        self.take(len)
+    }
+    fn read_bool(&mut self) -> Result<bool> {
+        if let Some(b) = self.pending_bool.take() {
+            Ok(b)
+        } else if self.compact {
+            Ok(self.byte()? == 1)
+        } else {
+            Ok(self.byte()? != 0)
+        }
+    }
+    fn read_field_header(&mut self) -> Result<Option<(Wire, i16)>> {
+        let b = self.byte()?;
+        if b == 0 {
+            return Ok(None);
+        }
+        if self.compact {
+            let ty = b & 0x0f;
+            let delta = i16::from(b >> 4);
+            let last = self.last_ids.last().copied().unwrap_or_default();
+            let id = if delta == 0 {
+                i16::try_from(self.zigzag()?)?
+            } else {
+                last + delta
+            };
+            if let Some(l) = self.last_ids.last_mut() {
+                *l = id;
+            }
+            if ty == 1 || ty == 2 {
+                self.pending_bool = Some(ty == 1);
+            }
+            Ok(Some((Wire::from_compact(ty)?, id)))
+        } else {
+            let ty = Wire::from_binary(b)?;
+            let id = i16::from_be_bytes(self.be()?);
+            Ok(Some((ty, id)))
+        }
+    }
+    fn read_list_header(&mut self) -> Result<(Wire, usize)> {
+        if self.compact {
+            let b = self.byte()?;
+            let size = if b >> 4 == 0x0f {
+                usize::try_from(self.varint()?)?
+            } else {
+                usize::from(b >> 4)
+            };
+            Ok((Wire::from_compact(b & 0x0f)?, size))
+        } else {
+            let ty = Wire::from_binary(self.byte()?)?;
+            let size = usize::try_from(i32::from_be_bytes(self.be()?))?;
+            Ok((ty, size))
+        }
+    }
+    fn read_map_header(&mut self) -> Result<Option<(Wire, Wire, usize)>> {
+        if self.compact {
+            let size = usize::try_from(self.varint()?)?;
+            if size == 0 {
+                return Ok(None);
+            }
+            let b = self.byte()?;
+            Ok(Some((
+                Wire::from_compact(b >> 4)?,
+                Wire::from_compact(b & 0x0f)?,
+                size,
+            )))
+        } else {
+            let k = Wire::from_binary(self.byte()?)?;
+            let v = Wire::from_binary(self.byte()?)?;
+            let size = usize::try_from(i32::from_be_bytes(self.be()?))?;
+            Ok(Some((k, v, size)))
+        }
+    }
+
+    fn read_struct(&mut self, idl: &Idl, name: Option<&str>) -> Result<Value<'static>> {
+        let fields = name.and_then(|n| idl.structs.get(n));
+        let mut obj = Object::new();
+        self.last_ids.push(0);
+        while let Some((wire, id)) = self.read_field_header()? {
+            let field = fields.and_then(|fs| fs.iter().find(|f| f.id == id));
+            let value = self.read_value(idl, wire, field.map(|f| &f.ty))?;
+            let key = field.map_or_else(|| id.to_string(), |f| f.name.clone());
+            obj.insert(key.into(), value);
+        }
+        self.last_ids.pop();
+        Ok(Value::from(obj))
+    }
+
+    fn read_value(&mut self, idl: &Idl, wire: Wire, ty: Option<&Type>) -> Result<Value<'static>> {
+        let ty = ty.map(|t| idl.resolve(t));
+        Ok(match wire {
+            Wire::Bool => Value::from(self.read_bool()?),
+            Wire::Byte => Value::from(i64::from(i8::from_be_bytes([self.byte()?]))),
+            Wire::I16 => Value::from(i64::from(self.read_i16()?)),
+            Wire::I32 => {
+                let n = self.read_i32()?;
+                match ty {
+                    Some(Type::Named(e)) => idl
+                        .enums
+                        .get(e)
+                        .and_then(|vs| vs.get(&n))
+                        .map_or_else(|| Value::from(i64::from(n)), |s| Value::from(s.clone())),
+                    _ => Value::from(i64::from(n)),
+                }
+            }
+            Wire::I64 => Value::from(self.read_i64()?),
+            Wire::Double => Value::from(self.read_double()?),
+            Wire::Binary => {
+                let data = self.read_binary()?;
+                match (ty, std::str::from_utf8(data)) {
+                    (Some(Type::Binary), _) | (None, Err(_)) => Value::Bytes(data.to_vec().into()),
+                    (_, Ok(s)) => Value::from(s.to_string()),
+                    (_, Err(e)) => return Err(e.into()),
+                }
+            }
+            Wire::Struct => {
+                let name = match ty {
+                    Some(Type::Named(n)) => Some(n.as_str()),
+                    _ => None,
+                };
+                self.read_struct(idl, name)?
+            }
+            Wire::List | Wire::Set => {
+                let (elem_wire, size) = self.read_list_header()?;
+                let elem_ty = match ty {
+                    Some(Type::List(t)) | Some(Type::Set(t)) => Some(t.as_ref()),
+                    _ => None,
+                };
+                let mut arr = Vec::with_capacity(size.min(1024));
+                for _ in 0..size {
+                    arr.push(self.read_value(idl, elem_wire, elem_ty)?);
+                }
+                Value::from(arr)
+            }
+            Wire::Map => {
+                let mut obj = Object::new();
+                if let Some((kw, vw, size)) = self.read_map_header()? {
+                    let (kt, vt) = match ty {
+                        Some(Type::Map(k, v)) => (Some(k.as_ref()), Some(v.as_ref())),
+                        _ => (None, None),
+                    };
+                    for _ in 0..size {
+                        let k = self.read_value(idl, kw, kt)?;
+                        let v = self.read_value(idl, vw, vt)?;
+                        let k = k.as_str().map_or_else(|| k.encode(), ToString::to_string);
+                        obj.insert(k.into(), v);
+                    }
+                }
+                Value::from(obj)
+            }
+        })
+    }
+}
+
+struct Writer {
+    buf: Vec<u8>,
+    compact: bool,
+    last_ids: Vec<i16>,
+}
+
+#[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+impl Writer {
+    fn varint(&mut self, mut n: u64) {
+        loop {
+            if n < 0x80 {
+                self.buf.push(n as u8);
+                return;
+            }
+            self.buf.push((n as u8 & 0x7f) | 0x80);
+            n >>= 7;
+        }
+    }
+    fn zigzag(&mut self, n: i64) {
+        self.varint(((n << 1) ^ (n >> 63)) as u64);
+    }
+    fn write_i16(&mut self, n: i16) {
+        if self.compact {
+            self.zigzag(i64::from(n));
+        } else {
+            self.buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+    fn write_i32(&mut self, n: i32) {
+        if self.compact {
+            self.zigzag(i64::from(n));
+        } else {
+            self.buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+    fn write_i64(&mut self, n: i64) {
+        if self.compact {
+            self.zigzag(n);
+        } else {
+            self.buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+    fn write_double(&mut self, n: f64) {
+        if self.compact {
+            self.buf.extend_from_slice(&n.to_le_bytes());
+        } else {
+            self.buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+    fn write_binary(&mut self, data: &[u8]) -> Result<()> {
+        if self.compact {
+            self.varint(data.len() as u64);
+        } else {
+            self.buf
+                .extend_from_slice(&i32::try_from(data.len())?.to_be_bytes());
+        }
+        self.buf.extend_from_slice(data);
+        Ok(())
+    }
+    fn write_field_header(&mut self, wire: Wire, id: i16, bool_value: Option<bool>) {
+        if self.compact {
+            let ty = match bool_value {
+                Some(true) => 1,
+                Some(false) => 2,
+                None => wire.compact_id(),
+            };
+            let last = self.last_ids.last().copied().unwrap_or_default();
+            let delta = id - last;
+            if delta > 0 && delta <= 15 {
+                self.buf.push(((delta as u8) << 4) | ty);
+            } else {
+                self.buf.push(ty);
+                self.zigzag(i64::from(id));
+            }
+            if let Some(l) = self.last_ids.last_mut() {
+                *l = id;
+            }
+        } else {
+            self.buf.push(wire.binary_id());
+            self.buf.extend_from_slice(&id.to_be_bytes());
+        }
+    }
+    fn write_list_header(&mut self, wire: Wire, size: usize) -> Result<()> {
+        if self.compact {
+            if size < 15 {
+                self.buf.push(((size as u8) << 4) | wire.compact_id());
+            } else {
+                self.buf.push(0xf0 | wire.compact_id());
+                self.varint(size as u64);
+            }
+        } else {
+            self.buf.push(wire.binary_id());
+            self.buf
+                .extend_from_slice(&i32::try_from(size)?.to_be_bytes());
+        }
+        Ok(())
+    }
+    fn write_map_header(&mut self, k: Wire, v: Wire, size: usize) -> Result<()> {
+        if self.compact {
+            self.varint(size as u64);
+            if size > 0 {
+                self.buf.push((k.compact_id() << 4) | v.compact_id());
+            }
+        } else {
+            self.buf.push(k.binary_id());
+            self.buf.push(v.binary_id());
+            self.buf
+                .extend_from_slice(&i32::try_from(size)?.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    fn write_struct(&mut self, idl: &Idl, name: Option<&str>, value: &Value) -> Result<()> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| Error::from("Thrift structs must be encoded from records"))?;
+        self.last_ids.push(0);
+        if let Some(fields) = name.and_then(|n| idl.structs.get(n)) {
+            for field in fields {
+                match obj.get(field.name.as_str()) {
+                    Some(v) if !v.is_null() => {
+                        let wire = idl.wire(&field.ty);
+                        let bool_value = if self.compact { v.as_bool() } else { None };
+                        self.write_field_header(wire, field.id, bool_value);
+                        self.write_value(idl, Some(&field.ty), wire, v)?;
+                    }
+                    _ if field.required => {
+                        return Err(format!("Missing required thrift field {}", field.name).into())
+                    }
+                    _ => (),
+                }
+            }
+        } else {
+            let mut fields: Vec<(i16, &Value)> = Vec::with_capacity(obj.len());
+            for (k, v) in obj.iter() {
+                let id = k
+                    .parse::<i16>()
+                    .map_err(|_| Error::from(format!("Field {} is not a thrift field id", k)))?;
+                fields.push((id, v));
+            }
+            fields.sort_by_key(|(id, _)| *id);
+            for (id, v) in fields {
+                if v.is_null() {
+                    continue;
+                }
+                let wire = infer_wire(v);
+                let bool_value = if self.compact { v.as_bool() } else { None };
+                self.write_field_header(wire, id, bool_value);
+                self.write_value(idl, None, wire, v)?;
+            }
+        }
+        self.last_ids.pop();
+        self.buf.push(0);
+        Ok(())
+    }
+
+    fn write_value(
+        &mut self,
+        idl: &Idl,
+        ty: Option<&Type>,
+        wire: Wire,
+        value: &Value,
+    ) -> Result<()> {
+        let ty = ty.map(|t| idl.resolve(t));
+        let int = || {
+            value
+                .as_i64()
+                .ok_or_else(|| Error::from(format!("Expected an integer, got {}", value.encode())))
+        };
+        match wire {
+            Wire::Bool => {
+                let b = value
+                    .as_bool()
+                    .ok_or_else(|| Error::from("Expected a boolean"))?;
+                // compact struct fields carry their boolean in the field header
+                if !self.compact {
+                    self.buf.push(u8::from(b));
+                }
+            }
+            Wire::Byte => self
+                .buf
+                .extend_from_slice(&i8::try_from(int()?)?.to_be_bytes()),
+            Wire::I16 => self.write_i16(i16::try_from(int()?)?),
+            Wire::I32 => {
+                let n = match (ty, value.as_str()) {
+                    (Some(Type::Named(e)), Some(s)) => idl
+                        .enums
+                        .get(e)
+                        .and_then(|vs| vs.iter().find(|(_, n)| n.as_str() == s))
+                        .map(|(n, _)| *n)
+                        .ok_or_else(|| {
+                            Error::from(format!("Unknown value {} for enum {}", s, e))
+                        })?,
+                    _ => i32::try_from(int()?)?,
+                };
+                self.write_i32(n);
+            }
+            Wire::I64 => self.write_i64(int()?),
+            Wire::Double => self.write_double(
+                value
+                    .cast_f64()
+                    .ok_or_else(|| Error::from("Expected a float"))?,
+            ),
+            Wire::Binary => {
+                if let Some(s) = value.as_str() {
+                    self.write_binary(s.as_bytes())?;
+                } else if let Value::Bytes(b) = value {
+                    self.write_binary(b)?;
+                } else {
+                    return Err(format!("Expected a string, got {}", value.encode()).into());
+                }
+            }
+            Wire::Struct => {
+                let name = match ty {
+                    Some(Type::Named(n)) => Some(n.as_str()),
+                    _ => None,
+                };
+                self.write_struct(idl, name, value)?;
+            }
+            Wire::List | Wire::Set => {
+                let arr = value
+                    .as_array()
+                    .ok_or_else(|| Error::from("Expected an array"))?;
+                let elem_ty = match ty {
+                    Some(Type::List(t)) | Some(Type::Set(t)) => Some(t.as_ref()),
+                    _ => None,
+                };
+                let elem_wire = elem_ty.map_or_else(
+                    || arr.first().map_or(Wire::I32, infer_wire),
+                    |t| idl.wire(t),
+                );
+                self.write_list_header(elem_wire, arr.len())?;
+                for v in arr {
+                    self.write_element(idl, elem_ty, elem_wire, v)?;
+                }
+            }
+            Wire::Map => {
+                let obj = value
+                    .as_object()
+                    .ok_or_else(|| Error::from("Expected a record"))?;
+                let (kt, vt) = match ty {
+                    Some(Type::Map(k, v)) => (Some(k.as_ref()), Some(v.as_ref())),
+                    _ => (None, None),
+                };
+                let kw = kt.map_or(Wire::Binary, |t| idl.wire(t));
+                let vw = vt.map_or_else(
+                    || obj.values().next().map_or(Wire::Binary, infer_wire),
+                    |t| idl.wire(t),
+                );
+                self.write_map_header(kw, vw, obj.len())?;
+                for (k, v) in obj.iter() {
+                    let key = match kw {
+                        Wire::Bool => Value::from(k == "true"),
+                        Wire::Binary => Value::from(k.to_string()),
+                        _ => Value::from(k.parse::<i64>()?),
+                    };
+                    self.write_element(idl, kt, kw, &key)?;
+                    self.write_element(idl, vt, vw, v)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// writes a list, set or map element, booleans are never part of a header here
+    fn write_element(
+        &mut self,
+        idl: &Idl,
+        ty: Option<&Type>,
+        wire: Wire,
+        value: &Value,
+    ) -> Result<()> {
+        if wire == Wire::Bool && self.compact {
+            let b = value
+                .as_bool()
+                .ok_or_else(|| Error::from("Expected a boolean"))?;
+            self.buf.push(if b { 1 } else { 2 });
+            Ok(())
+        } else {
+            self.write_value(idl, ty, wire, value)
+        }
+    }
+}
+
+fn infer_wire(value: &Value) -> Wire {
+    match value {
+        Value::Static(StaticNode::Bool(_)) => Wire::Bool,
+        Value::Static(StaticNode::F64(_)) => Wire::Double,
+        Value::Static(_) => Wire::I64,
+        Value::String(_) | Value::Bytes(_) => Wire::Binary,
+        Value::Array(_) => Wire::List,
+        Value::Object(o) if o.keys().all(|k| k.parse::<i16>().is_ok()) => Wire::Struct,
+        Value::Object(_) => Wire::Map,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Str(String),
+    Sym(char),
+}
+
+fn tokenize(raw: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '#' => {
+                while chars.peek().map_or(false, |c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().map_or(false, |c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in &mut chars {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '"' | '\'' => {
+                let mut s = String::new();
+                for n in &mut chars {
+                    if n == c {
+                        break;
+                    }
+                    s.push(n);
+                }
+                tokens.push(Token::Str(s));
+            }
+            c if c.is_ascii_digit() || c == '-' || c == '+' => {
+                let mut s = c.to_string();
+                while let Some(n) = chars
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || **c == '.')
+                {
+                    s.push(*n);
+                    chars.next();
+                }
+                // doubles are only used in defaults and consts, which we skip
+                tokens.push(s.parse().map_or(Token::Int(0), Token::Int));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut s = c.to_string();
+                while let Some(n) = chars
+                    .peek()
+                    .filter(|c| c.is_alphanumeric() || **c == '_' || **c == '.')
+                {
+                    s.push(*n);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(s));
+            }
+            c => tokens.push(Token::Sym(c)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn next(&mut self) -> Result<Token> {
+        let t = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| Error::from("Unexpected end of thrift IDL"))?;
+        self.pos += 1;
+        Ok(t)
+    }
+    fn ident(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Ident(i) => Ok(i),
+            t => Err(format!("Expected identifier in thrift IDL, found {:?}", t).into()),
+        }
+    }
+    fn expect(&mut self, c: char) -> Result<()> {
+        match self.next()? {
+            Token::Sym(s) if s == c => Ok(()),
+            t => Err(format!("Expected `{}` in thrift IDL, found {:?}", c, t).into()),
+        }
+    }
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Sym(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn skip_separator(&mut self) {
+        let _ = self.eat(',') || self.eat(';');
+    }
+    /// skips a value or a balanced block
+    fn skip_value(&mut self) -> Result<()> {
+        let mut depth = 0_usize;
+        loop {
+            match self.next()? {
+                Token::Sym('{') | Token::Sym('[') | Token::Sym('(') => depth += 1,
+                Token::Sym('}') | Token::Sym(']') | Token::Sym(')') => {
+                    depth = depth.saturating_sub(1)
+                }
+                _ => (),
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+    fn skip_annotations(&mut self) -> Result<()> {
+        if self.peek() == Some(&Token::Sym('(')) {
+            self.skip_value()?;
+        }
+        Ok(())
+    }
+    fn ty(&mut self) -> Result<Type> {
+        let name = self.ident()?;
+        let ty = match name.as_str() {
+            "bool" => Type::Bool,
+            "byte" | "i8" => Type::Byte,
+            "i16" => Type::I16,
+            "i32" => Type::I32,
+            "i64" => Type::I64,
+            "double" => Type::Double,
+            "string" => Type::String,
+            "binary" => Type::Binary,
+            "list" | "set" => {
+                self.expect('<')?;
+                let t = Box::new(self.ty()?);
+                self.expect('>')?;
+                if name == "list" {
+                    Type::List(t)
+                } else {
+                    Type::Set(t)
+                }
+            }
+            "map" => {
+                self.expect('<')?;
+                let k = Box::new(self.ty()?);
+                self.expect(',')?;
+                let v = Box::new(self.ty()?);
+                self.expect('>')?;
+                Type::Map(k, v)
+            }
+            // strip the include prefix, we resolve all names in one namespace
+            other => Type::Named(other.rsplit('.').next().unwrap_or(other).to_string()),
+        };
+        self.skip_annotations()?;
+        Ok(ty)
+    }
+    fn fields(&mut self) -> Result<Vec<Field>> {
+        self.expect('{')?;
+        let mut fields = vec![];
+        let mut next_implicit = -1_i16;
+        while !self.eat('}') {
+            let id = if let Some(Token::Int(id)) = self.peek().cloned() {
+                self.pos += 1;
+                self.expect(':')?;
+                i16::try_from(id)?
+            } else {
+                next_implicit -= 1;
+                next_implicit + 1
+            };
+            let mut required = false;
+            if let Some(Token::Ident(q)) = self.peek() {
+                if q == "required" || q == "optional" {
+                    required = q == "required";
+                    self.pos += 1;
+                }
+            }
+            let ty = self.ty()?;
+            let name = self.ident()?;
+            if self.eat('=') {
+                self.skip_value()?;
+            }
+            self.skip_annotations()?;
+            self.skip_separator();
+            fields.push(Field {
+                id,
+                name,
+                ty,
+                required,
+            });
+        }
+        self.skip_annotations()?;
+        Ok(fields)
+    }
+    fn enum_values(&mut self) -> Result<HashMap<i32, String>> {
+        self.expect('{')?;
+        let mut values = HashMap::new();
+        let mut next = 0_i32;
+        while !self.eat('}') {
+            let name = self.ident()?;
+            if self.eat('=') {
+                match self.next()? {
+                    Token::Int(n) => next = i32::try_from(n)?,
+                    t => return Err(format!("Invalid enum value {:?}", t).into()),
+                }
+            }
+            self.skip_annotations()?;
+            self.skip_separator();
+            values.insert(next, name);
+            next += 1;
+        }
+        self.skip_annotations()?;
+        Ok(values)
+    }
+}
+
+impl Idl {
+    pub(crate) fn parse(raw: &str) -> Result<Self> {
+        let mut p = Parser {
+            tokens: tokenize(raw)?,
+            pos: 0,
+        };
+        let mut idl = Self::default();
+        while p.peek().is_some() {
+            match p.ident()?.as_str() {
+                "namespace" => {
+                    p.ident()?;
+                    p.ident()?;
+                }
+                "include" | "cpp_include" => {
+                    p.next()?;
+                }
+                "typedef" => {
+                    let ty = p.ty()?;
+                    let name = p.ident()?;
+                    idl.typedefs.insert(name, ty);
+                }
+                "enum" => {
+                    let name = p.ident()?;
+                    let values = p.enum_values()?;
+                    idl.enums.insert(name, values);
+                }
+                "struct" | "union" | "exception" => {
+                    let name = p.ident()?;
+                    let fields = p.fields()?;
+                    idl.structs.insert(name, fields);
+                }
+                "const" => {
+                    p.ty()?;
+                    p.ident()?;
+                    p.expect('=')?;
+                    p.skip_value()?;
+                }
+                "service" => {
+                    p.ident()?;
+                    if let Some(Token::Ident(e)) = p.peek() {
+                        if e == "extends" {
+                            p.pos += 1;
+                            p.ident()?;
+                        }
+                    }
+                    p.skip_value()?;
+                }
+                "senum" => {
+                    p.ident()?;
+                    p.skip_value()?;
+                }
+                other => return Err(format!("Unexpected `{}` in thrift IDL", other).into()),
+            }
+            p.skip_separator();
+        }
+        Ok(idl)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    const IDL: &str = r#"
+        namespace java com.example
+        // comment
+        enum Level { DEBUG = 1, INFO, WARN }
+        typedef i64 Timestamp
+        struct Tag {
+          1: string key,
+          2: string value
+        }
+        /* the event */
+        struct Event {
+          1: required Timestamp ts;
+          2: optional string message = "none",
+          3: Level level,
+          4: list<Tag> tags,
+          5: map<string, i32> counts,
+          6: bool flag,
+          7: binary raw,
+          8: double ratio
+        }
+        service Ingest { void push(1: Event e) }
+    "#;
+
+    fn codec(protocol: Protocol) -> Result<Thrift> {
+        Ok(Thrift {
+            protocol,
+            idl: Arc::new(Idl::parse(IDL)?),
+            root: Some("Event".to_string()),
+        })
+    }
+
+    #[test]
+    fn parse_idl() -> Result<()> {
+        let idl = Idl::parse(IDL)?;
+        assert_eq!(2, idl.structs.len());
+        assert_eq!(
+            8,
+            idl.structs.get("Event").map(Vec::len).unwrap_or_default()
+        );
+        assert_eq!(
+            Some(&"INFO".to_string()),
+            idl.enums.get("Level").and_then(|l| l.get(&2))
+        );
+        assert_eq!(Some(&Type::I64), idl.typedefs.get("Timestamp"));
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_with_idl() -> Result<()> {
+        let event = literal!({
+            "ts": 1_617_000_000,
+            "message": "snot badger",
+            "level": "WARN",
+            "tags": [{"key": "env", "value": "prod"}],
+            "counts": {"a": 1, "b": -2},
+            "flag": true,
+            "ratio": 0.5
+        });
+        for protocol in &[Protocol::Binary, Protocol::Compact] {
+            let mut codec = codec(*protocol)?;
+            let mut raw = codec.encode(&event)?;
+            let decoded = codec.decode(&mut raw, 0)?;
+            assert_eq!(Some(event.clone()), decoded);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn missing_required() -> Result<()> {
+        let codec = codec(Protocol::Binary)?;
+        assert!(codec.encode(&literal!({"message": "snot"})).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_without_idl() -> Result<()> {
+        let event = literal!({
+            "1": 42,
+            "2": "badger",
+            "3": [1, 2, 3],
+            "4": {"1": false}
+        });
+        for protocol in &[Protocol::Binary, Protocol::Compact] {
+            let mut codec = Thrift {
+                protocol: *protocol,
+                idl: Arc::new(Idl::default()),
+                root: None,
+            };
+            let mut raw = codec.encode(&event)?;
+            let decoded = codec.decode(&mut raw, 0)?;
+            assert_eq!(Some(event.clone()), decoded);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn binary_wire_format() -> Result<()> {
+        let codec = Thrift {
+            protocol: Protocol::Binary,
+            idl: Arc::new(Idl::default()),
+            root: None,
+        };
+        let raw = codec.encode(&literal!({"1": 1}))?;
+        assert_eq!(vec![10, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0], raw);
+        Ok(())
+    }
+}
@@ -12,9 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::errors::Result;
 use crate::url::TremorUrl;
 use hashbrown::HashMap;
 
+mod include;
+mod template;
+pub(crate) use include::load;
+pub use template::{Instance, Template};
+
 pub(crate) type Id = String;
 pub(crate) type OnRampVec = Vec<OnRamp>;
 pub(crate) type OffRampVec = Vec<OffRamp>;
@@ -26,14 +32,63 @@ pub(crate) type MappingMap = HashMap<TremorUrl, HashMap<String, String>>;
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
+    /// deployment files to include, relative to this file
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) include: Vec<String>,
     #[serde(default = "Default::default")]
     pub(crate) onramp: OnRampVec,
     #[serde(default = "Default::default")]
     pub(crate) offramp: OffRampVec,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) pipeline: Vec<Pipeline>,
     #[serde(default = "Default::default")]
     pub(crate) binding: Vec<Binding>,
     #[serde(default = "Default::default")]
     pub(crate) mapping: MappingMap,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) template: Vec<Template>,
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) instance: Vec<Instance>,
+    /// environment variables scripts are allowed to read via `system::env`
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) env: Vec<String>,
+}
+
+impl Config {
+    /// Merges another config into this one
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.include.extend(other.include);
+        self.onramp.extend(other.onramp);
+        self.offramp.extend(other.offramp);
+        self.pipeline.extend(other.pipeline);
+        self.binding.extend(other.binding);
+        self.mapping.extend(other.mapping);
+        self.template.extend(other.template);
+        self.instance.extend(other.instance);
+        self.env.extend(other.env);
+    }
+
+    /// Expands all template instances into the config
+    pub(crate) fn expand_templates(&mut self) -> Result<()> {
+        let mut expanded = Vec::with_capacity(self.instance.len());
+        for instance in &self.instance {
+            let template = self
+                .template
+                .iter()
+                .find(|t| t.id == instance.template)
+                .ok_or_else(|| {
+                    format!(
+                        "Instance {} refers to unknown template {}",
+                        instance.id, instance.template
+                    )
+                })?;
+            expanded.push(template.instantiate(instance)?);
+        }
+        for config in expanded {
+            self.merge(config);
+        }
+        Ok(())
+    }
 }
 
 /// Configuration for an onramp
@@ -52,8 +107,13 @@ pub struct OnRamp {
     pub(crate) is_linked: bool,
     #[serde(default = "Default::default")]
     pub(crate) err_required: bool,
+    /// a single codec or an ordered chain of codecs
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    pub(crate) codec: Option<CodecSpec>,
+    /// configuration for codecs that require one, for a chain this is a map
+    /// from codec name to its configuration
     #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
-    pub(crate) codec: Option<String>,
+    pub(crate) codec_config: Option<crate::OpConfig>,
     /// mapping from mime-type to codec used to handle requests/responses
     /// with this mime-type
     ///
@@ -68,10 +128,17 @@ pub struct OnRamp {
     pub(crate) codec_map: Option<halfbrown::HashMap<String, String>>,
     #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
     pub(crate) preprocessors: Option<Vec<String>>,
+    /// configuration for preprocessors that require one, a map from
+    /// preprocessor name to its configuration
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    pub(crate) preprocessor_config: Option<crate::OpConfig>,
     #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
     pub(crate) postprocessors: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) metrics_interval_s: Option<u64>,
+    /// where to extract a correlation id from, stored as `$correlation_id`
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    pub(crate) correlation: Option<crate::correlation::Config>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) config: tremor_pipeline::ConfigMap,
 }
@@ -90,8 +157,13 @@ pub struct OffRamp {
     #[serde(rename = "linked", default = "Default::default")]
     // TODO validate that this is turned on only for supported offramps (rest, ws)
     pub(crate) is_linked: bool,
+    /// a single codec or an ordered chain of codecs
     #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
-    pub(crate) codec: Option<String>,
+    pub(crate) codec: Option<CodecSpec>,
+    /// configuration for codecs that require one, for a chain this is a map
+    /// from codec name to its configuration
+    #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
+    pub(crate) codec_config: Option<crate::OpConfig>,
     /// mapping from mime-type to codec used to handle requests/responses
     /// with this mime-type
     ///
@@ -114,6 +186,26 @@ pub struct OffRamp {
     pub(crate) config: tremor_pipeline::ConfigMap,
 }
 
+/// A single codec, or a chain of codecs applied in order on decoding and
+/// in reverse order on encoding
+///
+/// e.g.:
+///       codec: [base64, json]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum CodecSpec {
+    /// a single codec
+    Single(String),
+    /// a chain of codecs
+    Chain(Vec<String>),
+}
+
+impl From<&str> for CodecSpec {
+    fn from(name: &str) -> Self {
+        Self::Single(name.to_string())
+    }
+}
+
 /// Configuration for a Binding
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -123,4 +215,23 @@ pub struct Binding {
     #[serde(default = "Default::default")]
     pub(crate) description: String,
     pub(crate) links: BindingMap, // is this right? this should be url to url?
+    /// filters for the events an onramp sends to the linked pipelines
+    #[serde(
+        default = "Default::default",
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub(crate) filters: HashMap<TremorUrl, crate::filter::Filter>,
+    /// bindings that have to be linked before this one
+    #[serde(default = "Default::default", skip_serializing_if = "Vec::is_empty")]
+    pub(crate) depends_on: Vec<Id>,
+}
+
+/// An inline trickle query
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pipeline {
+    /// ID of the pipeline
+    pub id: Id,
+    /// the trickle query
+    pub(crate) query: String,
 }
@@ -0,0 +1,136 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deployment file includes
+//!
+//! A deployment file can `include` other deployment files, relative to its
+//! own location. Included files are merged into the including one and can
+//! include files themselves. A file included more than once, e.g. by two
+//! included files, is merged only once. Cycles are rejected, as are includes
+//! resolving to a location outside of the directory of the file initially
+//! loaded.
+//!
+//! ```yaml
+//! include:
+//!   - onramps.yaml
+//!   - tenants/acme.yaml
+//! ```
+//!
+//! Includes are a top level `include` list rather than an `!include` tag:
+//! serde_yaml doesn't expose custom tags, so resolving them would need a
+//! second YAML parser, and a list keeps every file plain YAML whose sections
+//! merge into the including file.
+
+use super::Config;
+use crate::errors::{Error, Result};
+use std::collections::HashSet;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use tremor_common::file;
+
+/// Loads a deployment file, resolving its includes
+pub(crate) fn load(file_name: &str) -> Result<Config> {
+    let path = file::canonicalize(file_name)?;
+    let root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut stack = Vec::new();
+    let mut loaded = HashSet::new();
+    load_included(&path, &root, &mut stack, &mut loaded)
+}
+
+fn load_included(
+    path: &Path,
+    root: &Path,
+    stack: &mut Vec<PathBuf>,
+    loaded: &mut HashSet<PathBuf>,
+) -> Result<Config> {
+    if stack.iter().any(|p| p == path) {
+        let cycle: Vec<_> = stack
+            .iter()
+            .chain(std::iter::once(&path.to_path_buf()))
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        return Err(format!("Include cycle: {}", cycle.join(" -> ")).into());
+    }
+    let reader = BufReader::new(file::open(path)?);
+    let mut config: Config = serde_yaml::from_reader(reader).map_err(|e| {
+        Error::from(format!(
+            "Invalid deployment file {}: {}",
+            path.to_string_lossy(),
+            e
+        ))
+    })?;
+    let dir = path.parent().unwrap_or(root);
+    stack.push(path.to_path_buf());
+    loaded.insert(path.to_path_buf());
+    for include in std::mem::take(&mut config.include) {
+        let included = file::canonicalize(&dir.join(&include))?;
+        if !included.starts_with(root) {
+            return Err(format!(
+                "Included file {} is outside of {}",
+                include,
+                root.to_string_lossy()
+            )
+            .into());
+        }
+        // files on the stack are loaded again to report the cycle
+        if loaded.contains(&included) && !stack.contains(&included) {
+            continue;
+        }
+        config.merge(load_included(&included, root, stack, loaded)?);
+    }
+    stack.pop();
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn includes() -> Result<()> {
+        let config = load("tests/configs/include/main.yaml")?;
+        assert!(config.include.is_empty());
+        assert_eq!(1, config.onramp.len());
+        assert_eq!(1, config.offramp.len());
+        assert_eq!(1, config.binding.len());
+        assert_eq!(1, config.mapping.len());
+        Ok(())
+    }
+
+    #[test]
+    fn diamond() -> Result<()> {
+        let config = load("tests/configs/include/diamond.yaml")?;
+        assert_eq!(1, config.onramp.len());
+        assert_eq!(1, config.offramp.len());
+        Ok(())
+    }
+
+    #[test]
+    fn cycle() {
+        let e = load("tests/configs/include/cycle-a.yaml")
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(e.starts_with("Include cycle:"), "{}", e);
+    }
+
+    #[test]
+    fn sandbox() {
+        let e = load("tests/configs/include/outside.yaml")
+            .err()
+            .map(|e| e.to_string())
+            .unwrap_or_default();
+        assert!(e.starts_with("Included file"), "{}", e);
+    }
+}
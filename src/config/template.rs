@@ -0,0 +1,275 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deployment templates
+//!
+//! A template is a parameterized set of onramps, offramps, pipelines,
+//! bindings and mappings. Each `instance` of a template is expanded into
+//! the deployment with `{{arg}}` placeholders replaced by the instance
+//! `args`, falling back to the defaults of the template. An arg without a
+//! default (`~`) is required. The instance id is available as `{{id}}`.
+//!
+//! A string consisting of a single placeholder is replaced by the arg value
+//! itself, so numbers and booleans keep their type.
+//!
+//! ```yaml
+//! template:
+//!   - id: tenant
+//!     args:
+//!       topic: ~
+//!       limit: 100
+//!     onramp:
+//!       - id: "{{id}}-in"
+//!         type: kafka
+//!         config:
+//!           topics: ["{{topic}}"]
+//!     pipeline:
+//!       - id: "{{id}}"
+//!         query: |
+//!           select event from in where event.size < {{limit}} into out;
+//! instance:
+//!   - id: acme
+//!     template: tenant
+//!     args:
+//!       topic: acme-events
+//! ```
+
+use super::{Config, Id};
+use crate::errors::{Error, Result};
+use hashbrown::HashMap;
+use serde_yaml::{Mapping, Value};
+
+/// A parameterized deployment template
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    /// ID of the template
+    pub id: Id,
+    #[serde(default = "Default::default")]
+    pub(crate) description: String,
+    /// args with their defaults, `~` for required args
+    #[serde(default = "Default::default")]
+    pub(crate) args: HashMap<String, Value>,
+    #[serde(default = "Default::default")]
+    pub(crate) onramp: Value,
+    #[serde(default = "Default::default")]
+    pub(crate) offramp: Value,
+    #[serde(default = "Default::default")]
+    pub(crate) pipeline: Value,
+    #[serde(default = "Default::default")]
+    pub(crate) binding: Value,
+    #[serde(default = "Default::default")]
+    pub(crate) mapping: Value,
+}
+
+/// An instance of a template
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Instance {
+    /// ID of the instance
+    pub id: Id,
+    /// ID of the template to instantiate
+    pub(crate) template: Id,
+    #[serde(default = "Default::default")]
+    pub(crate) args: HashMap<String, Value>,
+}
+
+impl Template {
+    /// Expands the template for the given instance
+    pub(crate) fn instantiate(&self, instance: &Instance) -> Result<Config> {
+        if let Some(unknown) = instance.args.keys().find(|k| !self.args.contains_key(*k)) {
+            return Err(format!(
+                "Instance {} sets unknown arg {} of template {}",
+                instance.id, unknown, self.id
+            )
+            .into());
+        }
+        let mut args = HashMap::with_capacity(self.args.len() + 1);
+        args.insert("id".to_string(), Value::String(instance.id.clone()));
+        for (name, default) in &self.args {
+            match instance.args.get(name).unwrap_or(default) {
+                Value::Null => {
+                    return Err(format!(
+                        "Instance {} is missing the required arg {} of template {}",
+                        instance.id, name, self.id
+                    )
+                    .into())
+                }
+                value => {
+                    args.insert(name.clone(), value.clone());
+                }
+            }
+        }
+
+        let mut body = Mapping::new();
+        for (section, value) in &[
+            ("onramp", &self.onramp),
+            ("offramp", &self.offramp),
+            ("pipeline", &self.pipeline),
+            ("binding", &self.binding),
+            ("mapping", &self.mapping),
+        ] {
+            if !value.is_null() {
+                body.insert(
+                    Value::String((*section).to_string()),
+                    substitute(value, &args)?,
+                );
+            }
+        }
+        serde_yaml::from_value(Value::Mapping(body)).map_err(|e| {
+            Error::from(format!(
+                "Invalid instance {} of template {}: {}",
+                instance.id, self.id, e
+            ))
+        })
+    }
+}
+
+fn substitute(value: &Value, args: &HashMap<String, Value>) -> Result<Value> {
+    Ok(match value {
+        Value::String(s) => render(s, args)?,
+        Value::Sequence(seq) => Value::Sequence(
+            seq.iter()
+                .map(|v| substitute(v, args))
+                .collect::<Result<_>>()?,
+        ),
+        Value::Mapping(map) => {
+            let mut res = Mapping::new();
+            for (k, v) in map {
+                res.insert(substitute(k, args)?, substitute(v, args)?);
+            }
+            Value::Mapping(res)
+        }
+        other => other.clone(),
+    })
+}
+
+fn lookup<'args>(name: &str, args: &'args HashMap<String, Value>) -> Result<&'args Value> {
+    args.get(name.trim())
+        .ok_or_else(|| format!("Unknown template arg {}", name.trim()).into())
+}
+
+fn render(s: &str, args: &HashMap<String, Value>) -> Result<Value> {
+    let trimmed = s.trim();
+    if trimmed.starts_with("{{") && trimmed.ends_with("}}") && trimmed.matches("{{").count() == 1 {
+        return lookup(&trimmed[2..trimmed.len() - 2], args).map(Clone::clone);
+    }
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find("{{") {
+        let end = if let Some(end) = rest[start..].find("}}") {
+            start + end
+        } else {
+            break;
+        };
+        res.push_str(&rest[..start]);
+        match lookup(&rest[start + 2..end], args)? {
+            Value::String(v) => res.push_str(v),
+            Value::Number(v) => res.push_str(&v.to_string()),
+            Value::Bool(v) => res.push_str(&v.to_string()),
+            _ => {
+                return Err(format!(
+                    "Template arg {} can not be interpolated into a string",
+                    rest[start + 2..end].trim()
+                )
+                .into())
+            }
+        }
+        rest = &rest[end + 2..];
+    }
+    res.push_str(rest);
+    Ok(Value::String(res))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn template() -> Template {
+        serde_yaml::from_str(
+            r#"
+id: tenant
+args:
+  topic: ~
+  limit: 100
+onramp:
+  - id: "{{id}}-in"
+    type: kafka
+    config:
+      topics: ["{{ topic }}"]
+      limit: "{{limit}}"
+binding:
+  - id: "{{id}}"
+    links:
+      "/onramp/{{id}}-in/{instance}/out": ["/pipeline/{{id}}/{instance}/in"]
+"#,
+        )
+        .expect("valid template")
+    }
+
+    fn instance(args: &str) -> Instance {
+        serde_yaml::from_str(&format!("{{id: acme, template: tenant, args: {}}}", args))
+            .expect("valid instance")
+    }
+
+    #[test]
+    fn instantiate() -> Result<()> {
+        let config = template().instantiate(&instance("{topic: acme-events}"))?;
+        assert_eq!(1, config.onramp.len());
+        let onramp = &config.onramp[0];
+        assert_eq!("acme-in", onramp.id);
+        let onramp_config = onramp.config.clone().unwrap_or_default();
+        let topics: Vec<String> = serde_yaml::from_value(onramp_config["topics"].clone())?;
+        assert_eq!(vec!["acme-events".to_string()], topics);
+        assert_eq!(Some(100), onramp_config["limit"].as_u64());
+        assert_eq!("acme", config.binding[0].id);
+        assert!(config.binding[0]
+            .links
+            .keys()
+            .any(|k| k.to_string().contains("/onramp/acme-in/")));
+
+        let config = template().instantiate(&instance("{topic: t, limit: 5}"))?;
+        let onramp_config = config.onramp[0].config.clone().unwrap_or_default();
+        assert_eq!(Some(5), onramp_config["limit"].as_u64());
+        Ok(())
+    }
+
+    #[test]
+    fn bad_args() {
+        assert!(template().instantiate(&instance("{}")).is_err());
+        assert!(template()
+            .instantiate(&instance("{topic: t, snot: badger}"))
+            .is_err());
+    }
+
+    #[test]
+    fn interpolation() -> Result<()> {
+        let mut args = HashMap::new();
+        args.insert("a".to_string(), Value::String("snot".to_string()));
+        args.insert("b".to_string(), Value::Number(42_u64.into()));
+        args.insert(
+            "c".to_string(),
+            Value::Sequence(vec![Value::String("badger".to_string())]),
+        );
+        assert_eq!(
+            Value::String("snot-42-{{".to_string()),
+            render("{{a}}-{{ b }}-{{", &args)?
+        );
+        assert_eq!(Value::Number(42_u64.into()), render(" {{b}} ", &args)?);
+        assert!(render("{{c}}", &args)?.is_sequence());
+        assert!(render("badger-{{c}}", &args).is_err());
+        assert!(render("{{d}}", &args).is_err());
+        Ok(())
+    }
+}
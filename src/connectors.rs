@@ -13,6 +13,10 @@
 // limitations under the License.
 
 pub(crate) mod qos;
+pub(crate) mod reconnect;
+pub(crate) mod tls;
+#[cfg(unix)]
+pub(crate) mod unix_socket;
 
 /// Extensions for `CNCF OpenTelemetry` support
 pub mod otel;
@@ -20,4 +24,10 @@ pub mod otel;
 /// Extensions for the `Google Cloud Platform`
 pub mod gcp;
 
+/// Extensions for `Amazon Web Services`
+pub mod aws;
+
+/// Extensions for `Microsoft Azure`
+pub mod azure;
+
 pub(crate) mod pb;
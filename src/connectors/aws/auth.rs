@@ -0,0 +1,369 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Authentication against Amazon Web Services
+//!
+//! Credentials are resolved like the standard AWS provider chain does, from
+//! `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and, for temporary
+//! credentials, `AWS_SESSION_TOKEN`, falling back to the `AWS_PROFILE`
+//! (or `default`) profile of the shared credentials file at
+//! `AWS_SHARED_CREDENTIALS_FILE` or `~/.aws/credentials`. Requests are signed
+//! with Signature Version 4.
+
+use crate::errors::Result;
+use crate::sink::rest::encode_segment;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+#[derive(Clone, Debug)]
+pub(crate) struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    /// Resolves credentials from the environment or the shared credentials
+    /// file
+    pub(crate) fn resolve() -> Result<Self> {
+        if let Some(credentials) = Self::from_env() {
+            return Ok(credentials);
+        }
+        let path = std::env::var("AWS_SHARED_CREDENTIALS_FILE")
+            .ok()
+            .or_else(|| {
+                std::env::var("HOME")
+                    .ok()
+                    .map(|home| format!("{}/.aws/credentials", home))
+            });
+        let profile = std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+        path.and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|file| Self::from_profile(&file, &profile))
+            .ok_or_else(|| {
+                format!(
+                    "No AWS credentials found, set `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` \
+                     or add the `{}` profile to the shared credentials file",
+                    profile
+                )
+                .into()
+            })
+    }
+
+    fn from_env() -> Option<Self> {
+        Some(Self {
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID").ok()?,
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY").ok()?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+        })
+    }
+
+    /// reads a profile from the ini formatted shared credentials file
+    fn from_profile(file: &str, profile: &str) -> Option<Self> {
+        let mut in_profile = false;
+        let mut access_key_id = None;
+        let mut secret_access_key = None;
+        let mut session_token = None;
+        for line in file.lines().map(str::trim) {
+            if line.starts_with('[') && line.ends_with(']') {
+                in_profile = line.trim_start_matches('[').trim_end_matches(']').trim() == profile;
+            } else if in_profile {
+                let mut kv = line.splitn(2, '=');
+                let key = kv.next().map(str::trim);
+                let value = kv.next().map(|v| v.trim().to_string());
+                match key {
+                    Some("aws_access_key_id") => access_key_id = value,
+                    Some("aws_secret_access_key") => secret_access_key = value,
+                    Some("aws_session_token") => session_token = value,
+                    _ => (),
+                }
+            }
+        }
+        Some(Self {
+            access_key_id: access_key_id?,
+            secret_access_key: secret_access_key?,
+            session_token,
+        })
+    }
+}
+
+/// Resolves the configured region, falling back to `AWS_REGION` and
+/// `AWS_DEFAULT_REGION`
+pub(crate) fn region(configured: Option<&str>) -> Result<String> {
+    configured
+        .map(ToString::to_string)
+        .or_else(|| std::env::var("AWS_REGION").ok())
+        .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+        .ok_or_else(|| "No AWS region configured and `AWS_REGION` is not set".into())
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    // ALLOW: HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC can take a key of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Signs requests to one service in one region
+#[derive(Clone, Debug)]
+pub(crate) struct Signer {
+    credentials: Credentials,
+    region: String,
+    service: String,
+}
+
+impl Signer {
+    pub(crate) fn new(credentials: Credentials, region: &str, service: &str) -> Self {
+        Self {
+            credentials,
+            region: region.to_string(),
+            service: service.to_string(),
+        }
+    }
+
+    /// Returns the headers to add to the request, `headers` are the
+    /// lowercase names and values of the headers to sign besides `host`
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn sign(
+        &self,
+        method: &str,
+        host: &str,
+        path: &str,
+        query: &str,
+        headers: &[(&str, &str)],
+        payload: &[u8],
+        now: DateTime<Utc>,
+    ) -> Vec<(String, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let mut signed: Vec<(&str, &str)> = headers.to_vec();
+        signed.push(("host", host));
+        signed.push(("x-amz-date", &amz_date));
+        if let Some(token) = &self.credentials.session_token {
+            signed.push(("x-amz-security-token", token));
+        }
+        signed.sort_unstable();
+        let canonical_headers: String = signed
+            .iter()
+            .map(|(k, v)| format!("{}:{}\n", k, v.trim()))
+            .collect();
+        let signed_headers = signed.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            path,
+            query,
+            canonical_headers,
+            signed_headers,
+            hex_sha256(payload)
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            ALGORITHM,
+            amz_date,
+            scope,
+            hex_sha256(canonical_request.as_bytes())
+        );
+        let key = format!("AWS4{}", self.credentials.secret_access_key);
+        let key = hmac(key.as_bytes(), &date);
+        let key = hmac(&key, &self.region);
+        let key = hmac(&key, &self.service);
+        let key = hmac(&key, "aws4_request");
+        let signature: String = hmac(&key, &string_to_sign)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        let mut res = vec![
+            ("x-amz-date".to_string(), amz_date.clone()),
+            (
+                "authorization".to_string(),
+                format!(
+                    "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+                    ALGORITHM, self.credentials.access_key_id, scope, signed_headers, signature
+                ),
+            ),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            res.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        res
+    }
+}
+
+/// A client for one AWS service endpoint signing each request
+#[derive(Clone)]
+pub(crate) struct AwsClient {
+    client: Client,
+    signer: Signer,
+    host: String,
+}
+
+impl AwsClient {
+    /// Creates a client for `service` in `region`, `endpoint` overrides the
+    /// default `{service}.{region}.amazonaws.com` host
+    pub(crate) fn new(service: &str, region: &str, endpoint: Option<&str>) -> Result<Self> {
+        let host = endpoint.map_or_else(
+            || format!("{}.{}.amazonaws.com", service, region),
+            ToString::to_string,
+        );
+        Ok(Self {
+            client: Client::builder().build()?,
+            signer: Signer::new(Credentials::resolve()?, region, service),
+            host,
+        })
+    }
+
+    /// Posts `body` to the root path, returning the status and response body
+    pub(crate) async fn post(
+        &self,
+        headers: &[(&str, &str)],
+        body: String,
+    ) -> Result<(u16, Vec<u8>)> {
+        let signed = self.signer.sign(
+            "POST",
+            &self.host,
+            "/",
+            "",
+            headers,
+            body.as_bytes(),
+            Utc::now(),
+        );
+        let mut request = self.client.post(&format!("https://{}/", self.host));
+        for (k, v) in headers {
+            request = request.header(*k, *v);
+        }
+        for (k, v) in signed {
+            request = request.header(k.as_str(), v);
+        }
+        let response = request.body(body).send().await?;
+        let status = response.status().as_u16();
+        Ok((status, response.bytes().await?.to_vec()))
+    }
+
+    /// Gets `path`, which has to be percent encoded, with the query
+    /// parameters, returning the response to stream its body
+    pub(crate) async fn get(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<reqwest::Response> {
+        let mut query = query
+            .iter()
+            .map(|(k, v)| (encode_segment(k), encode_segment(v)))
+            .collect::<Vec<_>>();
+        query.sort_unstable();
+        let query = query
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+        // S3 requires the payload hash as a header
+        let payload_hash = hex_sha256(b"");
+        let headers = [("x-amz-content-sha256", payload_hash.as_str())];
+        let signed = self
+            .signer
+            .sign("GET", &self.host, path, &query, &headers, b"", Utc::now());
+        let url = if query.is_empty() {
+            format!("https://{}{}", self.host, path)
+        } else {
+            format!("https://{}{}?{}", self.host, path, query)
+        };
+        let mut request = self.client.get(&url);
+        for (k, v) in &headers {
+            request = request.header(*k, *v);
+        }
+        for (k, v) in signed {
+            request = request.header(k.as_str(), v);
+        }
+        Ok(request.send().await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn profile() {
+        let file = r#"
+[default]
+aws_access_key_id = snot
+aws_secret_access_key = badger
+
+[ci]
+aws_access_key_id=AKID
+aws_secret_access_key=secret
+aws_session_token=token
+"#;
+        let credentials = Credentials::from_profile(file, "ci").expect("the ci profile");
+        assert_eq!("AKID", credentials.access_key_id);
+        assert_eq!("secret", credentials.secret_access_key);
+        assert_eq!(Some("token".to_string()), credentials.session_token);
+        let credentials = Credentials::from_profile(file, "default").expect("the default profile");
+        assert_eq!("snot", credentials.access_key_id);
+        assert_eq!(None, credentials.session_token);
+        assert!(Credentials::from_profile(file, "missing").is_none());
+    }
+
+    #[test]
+    fn sign() {
+        // the example from the Signature Version 4 documentation
+        let signer = Signer::new(
+            Credentials {
+                access_key_id: "AKIDEXAMPLE".to_string(),
+                secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+                session_token: None,
+            },
+            "us-east-1",
+            "iam",
+        );
+        let headers = signer.sign(
+            "GET",
+            "iam.amazonaws.com",
+            "/",
+            "Action=ListUsers&Version=2010-05-08",
+            &[(
+                "content-type",
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )],
+            b"",
+            Utc.ymd(2015, 8, 30).and_hms(12, 36, 0),
+        );
+        assert_eq!(
+            vec![
+                ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+                (
+                    "authorization".to_string(),
+                    "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+                     SignedHeaders=content-type;host;x-amz-date, \
+                     Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+                        .to_string()
+                )
+            ],
+            headers
+        );
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! CloudWatch Logs and CloudWatch Metrics APIs
+
+use super::auth::AwsClient;
+use crate::errors::Result;
+use crate::sink::rest::encode_segment;
+use tremor_value::literal;
+use tremor_value::prelude::*;
+
+/// events per `PutLogEvents` call
+pub(crate) const MAX_LOG_EVENTS: usize = 10_000;
+/// bytes per `PutLogEvents` call, counting the message plus 26 bytes per event
+pub(crate) const MAX_LOG_BYTES: usize = 1_048_576;
+pub(crate) const LOG_EVENT_OVERHEAD: usize = 26;
+/// milliseconds a single `PutLogEvents` call may span
+pub(crate) const MAX_LOG_SPAN_MS: u64 = 24 * 60 * 60 * 1000;
+/// metrics per `PutMetricData` call
+pub(crate) const MAX_METRICS: usize = 20;
+
+const LOGS_CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded; charset=utf-8";
+
+/// Outcome of a `PutLogEvents` call
+#[derive(Debug, PartialEq)]
+pub(crate) enum PutLogs {
+    /// accepted, with the token for the next call
+    Ok(Option<String>),
+    /// the sequence token was wrong, retry with the expected one
+    InvalidToken(Option<String>),
+    /// the log group or stream doesn't exist
+    NotFound(String),
+    /// failed
+    Err(String),
+}
+
+/// A log event, `timestamp` in milliseconds since epoch
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LogEvent {
+    pub(crate) timestamp: u64,
+    pub(crate) message: String,
+}
+
+fn error_type(body: &Value) -> &str {
+    // `__type` may be prefixed with the service namespace
+    body.get_str("__type")
+        .and_then(|t| t.rsplit('#').next())
+        .unwrap_or_default()
+}
+
+fn parse_put_logs(status: u16, body: &mut [u8]) -> PutLogs {
+    let body = tremor_value::parse_to_value(body).unwrap_or_else(|_| Value::null());
+    if (200..300).contains(&status) {
+        return PutLogs::Ok(body.get_str("nextSequenceToken").map(ToString::to_string));
+    }
+    let expected = body
+        .get_str("expectedSequenceToken")
+        .map(ToString::to_string);
+    let message = body
+        .get_str("message")
+        .or_else(|| body.get_str("Message"))
+        .unwrap_or_default();
+    match error_type(&body) {
+        // the batch was already accepted before, continue with the next token
+        "DataAlreadyAcceptedException" => PutLogs::Ok(expected),
+        "InvalidSequenceTokenException" => PutLogs::InvalidToken(expected),
+        "ResourceNotFoundException" => PutLogs::NotFound(message.to_string()),
+        other => PutLogs::Err(format!("{} ({}): {}", other, status, message)),
+    }
+}
+
+/// Puts `events`, which have to be sorted by timestamp, to a log stream
+pub(crate) async fn put_log_events(
+    client: &AwsClient,
+    group: &str,
+    stream: &str,
+    token: Option<&str>,
+    events: &[LogEvent],
+) -> Result<PutLogs> {
+    let events: Vec<Value> = events
+        .iter()
+        .map(|e| literal!({ "timestamp": e.timestamp, "message": e.message.clone() }))
+        .collect();
+    let mut body = literal!({
+        "logGroupName": group.to_string(),
+        "logStreamName": stream.to_string(),
+        "logEvents": events
+    });
+    if let Some(token) = token {
+        body.try_insert("sequenceToken", token.to_string());
+    }
+    let headers = [
+        ("content-type", LOGS_CONTENT_TYPE),
+        ("x-amz-target", "Logs_20140328.PutLogEvents"),
+    ];
+    let (status, mut response) = client.post(&headers, body.encode()).await?;
+    Ok(parse_put_logs(status, &mut response))
+}
+
+/// Creates a log stream, succeeding if it already exists
+pub(crate) async fn create_log_stream(client: &AwsClient, group: &str, stream: &str) -> Result<()> {
+    let body = literal!({
+        "logGroupName": group.to_string(),
+        "logStreamName": stream.to_string()
+    });
+    let headers = [
+        ("content-type", LOGS_CONTENT_TYPE),
+        ("x-amz-target", "Logs_20140328.CreateLogStream"),
+    ];
+    let (status, mut response) = client.post(&headers, body.encode()).await?;
+    let response = tremor_value::parse_to_value(&mut response).unwrap_or_else(|_| Value::null());
+    if (200..300).contains(&status) || error_type(&response) == "ResourceAlreadyExistsException" {
+        Ok(())
+    } else {
+        Err(format!(
+            "Unable to create log stream {}/{}: {} {}",
+            group,
+            stream,
+            status,
+            response.get_str("message").unwrap_or_default()
+        )
+        .into())
+    }
+}
+
+/// Statistics of the values of a metric
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Statistics {
+    pub(crate) count: u64,
+    pub(crate) sum: f64,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+}
+
+impl Statistics {
+    pub(crate) fn new(value: f64) -> Self {
+        Self {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    pub(crate) fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+/// A metric aggregated over a flush interval
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Metric {
+    pub(crate) name: String,
+    pub(crate) unit: Option<String>,
+    /// sorted by name
+    pub(crate) dimensions: Vec<(String, String)>,
+    pub(crate) statistics: Statistics,
+}
+
+fn metric_data_form(namespace: &str, timestamp: &str, metrics: &[Metric]) -> String {
+    let mut params = vec![
+        ("Action".to_string(), "PutMetricData".to_string()),
+        ("Version".to_string(), "2010-08-01".to_string()),
+        ("Namespace".to_string(), namespace.to_string()),
+    ];
+    for (i, metric) in (1..).zip(metrics) {
+        let prefix = format!("MetricData.member.{}", i);
+        params.push((format!("{}.MetricName", prefix), metric.name.clone()));
+        params.push((format!("{}.Timestamp", prefix), timestamp.to_string()));
+        if let Some(unit) = &metric.unit {
+            params.push((format!("{}.Unit", prefix), unit.clone()));
+        }
+        for (j, (name, value)) in (1..).zip(&metric.dimensions) {
+            let dimension = format!("{}.Dimensions.member.{}", prefix, j);
+            params.push((format!("{}.Name", dimension), name.clone()));
+            params.push((format!("{}.Value", dimension), value.clone()));
+        }
+        let stats = &metric.statistics;
+        let stats_prefix = format!("{}.StatisticValues", prefix);
+        params.push((
+            format!("{}.SampleCount", stats_prefix),
+            stats.count.to_string(),
+        ));
+        params.push((format!("{}.Sum", stats_prefix), stats.sum.to_string()));
+        params.push((format!("{}.Minimum", stats_prefix), stats.min.to_string()));
+        params.push((format!("{}.Maximum", stats_prefix), stats.max.to_string()));
+    }
+    params
+        .iter()
+        .map(|(k, v)| format!("{}={}", encode_segment(k), encode_segment(v)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Puts up to `MAX_METRICS` metrics, `timestamp` in ISO 8601
+pub(crate) async fn put_metric_data(
+    client: &AwsClient,
+    namespace: &str,
+    timestamp: &str,
+    metrics: &[Metric],
+) -> Result<()> {
+    let headers = [("content-type", FORM_CONTENT_TYPE)];
+    let body = metric_data_form(namespace, timestamp, metrics);
+    let (status, response) = client.post(&headers, body).await?;
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(format!(
+            "PutMetricData failed with {}: {}",
+            status,
+            String::from_utf8_lossy(&response)
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn put_logs_response() {
+        assert_eq!(
+            PutLogs::Ok(Some("a".to_string())),
+            parse_put_logs(200, &mut br#"{"nextSequenceToken":"a"}"#.to_vec())
+        );
+        assert_eq!(
+            PutLogs::InvalidToken(Some("b".to_string())),
+            parse_put_logs(
+                400,
+                &mut br#"{"__type":"InvalidSequenceTokenException","expectedSequenceToken":"b","message":"x"}"#
+                    .to_vec()
+            )
+        );
+        assert_eq!(
+            PutLogs::Ok(Some("c".to_string())),
+            parse_put_logs(
+                400,
+                &mut br#"{"__type":"com.amazonaws.logs#DataAlreadyAcceptedException","expectedSequenceToken":"c"}"#
+                    .to_vec()
+            )
+        );
+        assert_eq!(
+            PutLogs::NotFound("The specified log stream does not exist.".to_string()),
+            parse_put_logs(
+                400,
+                &mut br#"{"__type":"ResourceNotFoundException","message":"The specified log stream does not exist."}"#
+                    .to_vec()
+            )
+        );
+        assert!(matches!(
+            parse_put_logs(500, &mut b"snot".to_vec()),
+            PutLogs::Err(_)
+        ));
+    }
+
+    #[test]
+    fn metric_data() {
+        let mut statistics = Statistics::new(1.0);
+        statistics.add(3.5);
+        let metrics = vec![Metric {
+            name: "latency".to_string(),
+            unit: Some("Milliseconds".to_string()),
+            dimensions: vec![("host".to_string(), "a b".to_string())],
+            statistics,
+        }];
+        assert_eq!(
+            "Action=PutMetricData&Version=2010-08-01&Namespace=tremor\
+             &MetricData.member.1.MetricName=latency\
+             &MetricData.member.1.Timestamp=2021-01-01T00%3A00%3A00Z\
+             &MetricData.member.1.Unit=Milliseconds\
+             &MetricData.member.1.Dimensions.member.1.Name=host\
+             &MetricData.member.1.Dimensions.member.1.Value=a%20b\
+             &MetricData.member.1.StatisticValues.SampleCount=2\
+             &MetricData.member.1.StatisticValues.Sum=4.5\
+             &MetricData.member.1.StatisticValues.Minimum=1\
+             &MetricData.member.1.StatisticValues.Maximum=3.5",
+            metric_data_form("tremor", "2021-01-01T00:00:00Z", &metrics)
+        );
+    }
+}
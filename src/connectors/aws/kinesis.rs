@@ -0,0 +1,273 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Kinesis Data Streams API
+
+use super::auth::AwsClient;
+use crate::errors::{Error, Result};
+use std::cmp::Ordering;
+use tremor_value::literal;
+use tremor_value::prelude::*;
+
+/// records per `GetRecords` call
+pub(crate) const MAX_RECORDS: u64 = 10_000;
+
+const CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+
+/// A shard of a stream, with the shards it was split from or merged from
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Shard {
+    pub(crate) shard_id: String,
+    pub(crate) parent_shard_id: Option<String>,
+    pub(crate) adjacent_parent_shard_id: Option<String>,
+}
+
+impl Shard {
+    /// the shards that have to be read to their end before this one
+    pub(crate) fn parents(&self) -> impl Iterator<Item = &str> {
+        self.parent_shard_id
+            .iter()
+            .chain(self.adjacent_parent_shard_id.iter())
+            .map(String::as_str)
+    }
+}
+
+/// Where to start reading a shard
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Position {
+    TrimHorizon,
+    Latest,
+    At(String),
+    After(String),
+}
+
+/// A record of a shard
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Record {
+    pub(crate) sequence_number: String,
+    pub(crate) partition_key: String,
+    pub(crate) data: Vec<u8>,
+    /// seconds since epoch
+    pub(crate) approximate_arrival_timestamp: Option<f64>,
+}
+
+/// The result of a `GetRecords` call
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Records {
+    pub(crate) records: Vec<Record>,
+    /// `None` once a closed shard was read to its end
+    pub(crate) next_shard_iterator: Option<String>,
+    pub(crate) millis_behind_latest: Option<u64>,
+}
+
+/// Orders sequence numbers, which are decimal numbers of up to 128 bits
+pub(crate) fn cmp_sequence_numbers(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+async fn call(client: &AwsClient, action: &str, body: &Value<'_>) -> Result<Vec<u8>> {
+    let target = format!("Kinesis_20131202.{}", action);
+    let headers = [
+        ("content-type", CONTENT_TYPE),
+        ("x-amz-target", target.as_str()),
+    ];
+    let (status, mut response) = client.post(&headers, body.encode()).await?;
+    if (200..300).contains(&status) {
+        return Ok(response);
+    }
+    let body = tremor_value::parse_to_value(&mut response).unwrap_or_else(|_| Value::null());
+    Err(format!(
+        "{} failed with {} {}: {}",
+        action,
+        status,
+        body.get_str("__type")
+            .and_then(|t| t.rsplit('#').next())
+            .unwrap_or_default(),
+        body.get_str("message").unwrap_or_default()
+    )
+    .into())
+}
+
+fn optional_str(value: &Value, key: &str) -> Option<String> {
+    value.get_str(key).map(ToString::to_string)
+}
+
+fn parse_shards(response: &mut [u8]) -> Result<(Vec<Shard>, Option<String>)> {
+    let body = tremor_value::parse_to_value(response)?;
+    let shards = body
+        .get_array("Shards")
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .map(|s| {
+            Ok(Shard {
+                shard_id: optional_str(s, "ShardId")
+                    .ok_or_else(|| Error::from("Listed shard without `ShardId`"))?,
+                parent_shard_id: optional_str(s, "ParentShardId"),
+                adjacent_parent_shard_id: optional_str(s, "AdjacentParentShardId"),
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok((shards, optional_str(&body, "NextToken")))
+}
+
+/// Lists all shards of a stream, open and closed ones
+pub(crate) async fn list_shards(client: &AwsClient, stream: &str) -> Result<Vec<Shard>> {
+    let mut shards = Vec::new();
+    let mut body = literal!({ "StreamName": stream.to_string() });
+    loop {
+        let mut response = call(client, "ListShards", &body).await?;
+        let (mut page, next_token) = parse_shards(&mut response)?;
+        shards.append(&mut page);
+        match next_token {
+            // the stream name must not be set along with a token
+            Some(token) => body = literal!({ "NextToken": token }),
+            None => return Ok(shards),
+        }
+    }
+}
+
+/// Returns an iterator to read a shard from `position`
+pub(crate) async fn get_shard_iterator(
+    client: &AwsClient,
+    stream: &str,
+    shard_id: &str,
+    position: &Position,
+) -> Result<String> {
+    let mut body = literal!({
+        "StreamName": stream.to_string(),
+        "ShardId": shard_id.to_string()
+    });
+    let (kind, sequence_number) = match position {
+        Position::TrimHorizon => ("TRIM_HORIZON", None),
+        Position::Latest => ("LATEST", None),
+        Position::At(s) => ("AT_SEQUENCE_NUMBER", Some(s)),
+        Position::After(s) => ("AFTER_SEQUENCE_NUMBER", Some(s)),
+    };
+    body.try_insert("ShardIteratorType", kind);
+    if let Some(sequence_number) = sequence_number {
+        body.try_insert("StartingSequenceNumber", sequence_number.clone());
+    }
+    let mut response = call(client, "GetShardIterator", &body).await?;
+    let body = tremor_value::parse_to_value(&mut response)?;
+    optional_str(&body, "ShardIterator").ok_or_else(|| "No shard iterator returned".into())
+}
+
+fn parse_records(response: &mut [u8]) -> Result<Records> {
+    let body = tremor_value::parse_to_value(response)?;
+    let records = body
+        .get_array("Records")
+        .map_or(&[][..], Vec::as_slice)
+        .iter()
+        .map(|r| {
+            let field = |name: &str| {
+                optional_str(r, name)
+                    .ok_or_else(|| Error::from(format!("Record without `{}`", name)))
+            };
+            Ok(Record {
+                sequence_number: field("SequenceNumber")?,
+                partition_key: field("PartitionKey")?,
+                data: base64::decode(field("Data")?)?,
+                approximate_arrival_timestamp: r
+                    .get("ApproximateArrivalTimestamp")
+                    .and_then(|v| v.cast_f64()),
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Records {
+        records,
+        next_shard_iterator: optional_str(&body, "NextShardIterator"),
+        millis_behind_latest: body.get_u64("MillisBehindLatest"),
+    })
+}
+
+/// Reads up to `limit` records from a shard iterator
+pub(crate) async fn get_records(client: &AwsClient, iterator: &str, limit: u64) -> Result<Records> {
+    let body = literal!({
+        "ShardIterator": iterator.to_string(),
+        "Limit": limit
+    });
+    let mut response = call(client, "GetRecords", &body).await?;
+    parse_records(&mut response)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shards() -> Result<()> {
+        let mut response = br#"{"Shards":[
+            {"ShardId":"shardId-000000000000","HashKeyRange":{}},
+            {"ShardId":"shardId-000000000001","ParentShardId":"shardId-000000000000"},
+            {"ShardId":"shardId-000000000002","ParentShardId":"shardId-000000000000","AdjacentParentShardId":"shardId-000000000001"}
+        ],"NextToken":"t"}"#
+            .to_vec();
+        let (shards, token) = parse_shards(&mut response)?;
+        assert_eq!(Some("t".to_string()), token);
+        assert_eq!(3, shards.len());
+        let parents = |i: usize| {
+            shards
+                .get(i)
+                .map(|s| s.parents().map(ToString::to_string).collect::<Vec<_>>())
+        };
+        assert_eq!(Some(vec![]), parents(0));
+        assert_eq!(Some(vec!["shardId-000000000000".to_string()]), parents(1));
+        assert_eq!(
+            Some(vec![
+                "shardId-000000000000".to_string(),
+                "shardId-000000000001".to_string()
+            ]),
+            parents(2)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn records() -> Result<()> {
+        let mut response = br#"{"Records":[{
+            "SequenceNumber":"49590338271490256608559692538361571095921575989136588898",
+            "PartitionKey":"snot",
+            "Data":"eyJzbm90IjoiYmFkZ2VyIn0=",
+            "ApproximateArrivalTimestamp":1.615e9
+        }],"NextShardIterator":"i","MillisBehindLatest":12}"#
+            .to_vec();
+        let records = parse_records(&mut response)?;
+        assert_eq!(Some("i".to_string()), records.next_shard_iterator);
+        assert_eq!(Some(12), records.millis_behind_latest);
+        let record = records.records.get(0).expect("a record");
+        assert_eq!(br#"{"snot":"badger"}"#.to_vec(), record.data);
+        assert_eq!("snot", record.partition_key);
+
+        // a closed shard read to its end
+        let records = parse_records(&mut br#"{"Records":[]}"#.to_vec())?;
+        assert_eq!(None, records.next_shard_iterator);
+        assert!(records.records.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        assert_eq!(Ordering::Less, cmp_sequence_numbers("9", "10"));
+        assert_eq!(
+            Ordering::Greater,
+            cmp_sequence_numbers(
+                "49590338271490256608559692538361571095921575989136588898",
+                "49590338271490256608559692538361571095921575989136588897"
+            )
+        );
+        assert_eq!(Ordering::Equal, cmp_sequence_numbers("007", "7"));
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! S3 API, using path style requests
+
+use super::auth::AwsClient;
+use crate::errors::{Error, Result};
+use crate::sink::rest::encode_segment;
+
+/// keys per `ListObjectsV2` call
+const MAX_KEYS: &str = "1000";
+
+/// An object of a listing
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Object {
+    pub(crate) key: String,
+    /// the etag without its quotes
+    pub(crate) etag: String,
+    pub(crate) size: u64,
+    pub(crate) last_modified: String,
+}
+
+/// The contents of the elements named `tag`, the elements must not be
+/// nested in themselves
+fn elements<'xml>(xml: &'xml str, tag: &str) -> Vec<&'xml str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut res = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        if let Some(end) = rest.find(&close) {
+            res.push(&rest[..end]);
+            rest = &rest[end + close.len()..];
+        } else {
+            break;
+        }
+    }
+    res
+}
+
+/// The unescaped text of the first element named `tag`
+fn text(xml: &str, tag: &str) -> Option<String> {
+    elements(xml, tag).first().map(|t| {
+        t.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&#13;", "\r")
+            .replace("&amp;", "&")
+    })
+}
+
+/// The objects and the continuation token of a `ListObjectsV2` response
+fn parse_list(xml: &str) -> Result<(Vec<Object>, Option<String>)> {
+    let objects = elements(xml, "Contents")
+        .into_iter()
+        .map(|contents| {
+            let field = |name: &str| {
+                text(contents, name)
+                    .ok_or_else(|| Error::from(format!("Listed object without `{}`", name)))
+            };
+            Ok(Object {
+                key: field("Key")?,
+                etag: field("ETag")?.trim_matches('"').to_string(),
+                size: field("Size")?.parse()?,
+                last_modified: field("LastModified")?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let token = if text(xml, "IsTruncated").as_deref() == Some("true") {
+        text(xml, "NextContinuationToken")
+    } else {
+        None
+    };
+    Ok((objects, token))
+}
+
+/// returns the response of successful calls
+async fn check(action: &str, response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status().as_u16();
+    if (200..300).contains(&status) {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(format!(
+        "{} failed with {} {}: {}",
+        action,
+        status,
+        text(&body, "Code").unwrap_or_default(),
+        text(&body, "Message").unwrap_or_default()
+    )
+    .into())
+}
+
+/// The path of an object, percent encoded
+fn object_path(bucket: &str, key: &str) -> String {
+    let key: Vec<String> = key.split('/').map(encode_segment).collect();
+    format!("/{}/{}", encode_segment(bucket), key.join("/"))
+}
+
+/// Lists the objects with `prefix` in their key, following the continuation
+/// token of a previous call
+pub(crate) async fn list_objects(
+    client: &AwsClient,
+    bucket: &str,
+    prefix: &str,
+    continuation_token: Option<&str>,
+) -> Result<(Vec<Object>, Option<String>)> {
+    let mut query = vec![
+        ("list-type", "2"),
+        ("prefix", prefix),
+        ("max-keys", MAX_KEYS),
+    ];
+    if let Some(token) = continuation_token {
+        query.push(("continuation-token", token));
+    }
+    let path = format!("/{}", encode_segment(bucket));
+    let response = check("ListObjectsV2", client.get(&path, &query).await?).await?;
+    parse_list(&response.text().await?)
+}
+
+/// Gets an object, the body is read from the returned response
+pub(crate) async fn get_object(
+    client: &AwsClient,
+    bucket: &str,
+    key: &str,
+) -> Result<reqwest::Response> {
+    let response = client.get(&object_path(bucket, key), &[]).await?;
+    check("GetObject", response).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn list() -> Result<()> {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>snot</Name>
+  <Prefix>logs/</Prefix>
+  <KeyCount>2</KeyCount>
+  <MaxKeys>1000</MaxKeys>
+  <IsTruncated>true</IsTruncated>
+  <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+  <Contents>
+    <Key>logs/a&amp;b.log</Key>
+    <LastModified>2021-05-01T10:00:00.000Z</LastModified>
+    <ETag>&quot;9b2cf535f27731c974343645a3985328&quot;</ETag>
+    <Size>42</Size>
+    <StorageClass>STANDARD</StorageClass>
+  </Contents>
+  <Contents>
+    <Key>logs/b.log.gz</Key>
+    <LastModified>2021-05-01T11:00:00.000Z</LastModified>
+    <ETag>"d41d8cd98f00b204e9800998ecf8427e-2"</ETag>
+    <Size>0</Size>
+  </Contents>
+</ListBucketResult>"#;
+        let (objects, token) = parse_list(xml)?;
+        assert_eq!(
+            vec![
+                Object {
+                    key: "logs/a&b.log".to_string(),
+                    etag: "9b2cf535f27731c974343645a3985328".to_string(),
+                    size: 42,
+                    last_modified: "2021-05-01T10:00:00.000Z".to_string(),
+                },
+                Object {
+                    key: "logs/b.log.gz".to_string(),
+                    etag: "d41d8cd98f00b204e9800998ecf8427e-2".to_string(),
+                    size: 0,
+                    last_modified: "2021-05-01T11:00:00.000Z".to_string(),
+                }
+            ],
+            objects
+        );
+        assert_eq!(
+            Some("1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=".to_string()),
+            token
+        );
+
+        let (objects, token) =
+            parse_list("<ListBucketResult><IsTruncated>false</IsTruncated></ListBucketResult>")?;
+        assert!(objects.is_empty());
+        assert_eq!(None, token);
+        assert!(parse_list("<Contents><Key>k</Key></Contents>").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            "/snot/logs/2021%2005/a%2Bb.log",
+            object_path("snot", "logs/2021 05/a+b.log")
+        );
+    }
+}
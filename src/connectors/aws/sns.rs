@@ -0,0 +1,104 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SNS API
+
+use super::auth::AwsClient;
+use crate::errors::Result;
+use crate::sink::rest::encode_segment;
+
+/// bytes of a message
+pub(crate) const MAX_MESSAGE_BYTES: usize = 262_144;
+
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded; charset=utf-8";
+
+fn publish_form(
+    topic_arn: &str,
+    message: &str,
+    subject: Option<&str>,
+    group_id: Option<&str>,
+    deduplication_id: Option<&str>,
+) -> String {
+    let mut params = vec![
+        ("Action", "Publish"),
+        ("Version", "2010-03-31"),
+        ("TopicArn", topic_arn),
+        ("Message", message),
+    ];
+    if let Some(subject) = subject {
+        params.push(("Subject", subject));
+    }
+    if let Some(group_id) = group_id {
+        params.push(("MessageGroupId", group_id));
+    }
+    if let Some(deduplication_id) = deduplication_id {
+        params.push(("MessageDeduplicationId", deduplication_id));
+    }
+    params
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, encode_segment(v)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Publishes a message to a topic, `group_id` and `deduplication_id` are for
+/// FIFO topics
+pub(crate) async fn publish(
+    client: &AwsClient,
+    topic_arn: &str,
+    message: &str,
+    subject: Option<&str>,
+    group_id: Option<&str>,
+    deduplication_id: Option<&str>,
+) -> Result<()> {
+    let headers = [("content-type", FORM_CONTENT_TYPE)];
+    let body = publish_form(topic_arn, message, subject, group_id, deduplication_id);
+    let (status, response) = client.post(&headers, body).await?;
+    if (200..300).contains(&status) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Publish failed with {}: {}",
+            status,
+            String::from_utf8_lossy(&response)
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn form() {
+        assert_eq!(
+            "Action=Publish&Version=2010-03-31\
+             &TopicArn=arn%3Aaws%3Asns%3Aeu-west-1%3A123%3Aorders\
+             &Message=%7B%22snot%22%3A%22badger%22%7D",
+            publish_form(
+                "arn:aws:sns:eu-west-1:123:orders",
+                r#"{"snot":"badger"}"#,
+                None,
+                None,
+                None
+            )
+        );
+        assert_eq!(
+            "Action=Publish&Version=2010-03-31&TopicArn=t&Message=m\
+             &Subject=a%20b&MessageGroupId=g&MessageDeduplicationId=d",
+            publish_form("t", "m", Some("a b"), Some("g"), Some("d"))
+        );
+    }
+}
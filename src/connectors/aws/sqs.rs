@@ -0,0 +1,220 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! SQS API, using the JSON protocol
+
+use super::auth::AwsClient;
+use crate::errors::{Error, Result};
+use tremor_value::literal;
+use tremor_value::prelude::*;
+
+/// messages per `ReceiveMessage` call
+pub(crate) const MAX_MESSAGES: u64 = 10;
+/// seconds a `ReceiveMessage` call may wait for messages
+pub(crate) const MAX_WAIT_TIME: u64 = 20;
+/// bytes of a message body
+pub(crate) const MAX_MESSAGE_BYTES: usize = 262_144;
+
+const CONTENT_TYPE: &str = "application/x-amz-json-1.0";
+
+/// A received message
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Message {
+    pub(crate) message_id: String,
+    pub(crate) receipt_handle: String,
+    pub(crate) body: String,
+    pub(crate) attributes: Value<'static>,
+    pub(crate) message_attributes: Value<'static>,
+}
+
+async fn call(client: &AwsClient, action: &str, body: &Value<'_>) -> Result<Vec<u8>> {
+    let target = format!("AmazonSQS.{}", action);
+    let headers = [
+        ("content-type", CONTENT_TYPE),
+        ("x-amz-target", target.as_str()),
+    ];
+    let (status, response) = client.post(&headers, body.encode()).await?;
+    check(action, status, response)
+}
+
+/// returns the response body of successful calls
+fn check(action: &str, status: u16, mut response: Vec<u8>) -> Result<Vec<u8>> {
+    if (200..300).contains(&status) {
+        return Ok(response);
+    }
+    let body = tremor_value::parse_to_value(&mut response).unwrap_or_else(|_| Value::null());
+    let error = body
+        .get_str("__type")
+        .and_then(|t| t.rsplit('#').next())
+        .unwrap_or_default();
+    let message = body
+        .get_str("message")
+        .or_else(|| body.get_str("Message"))
+        .unwrap_or_default();
+    Err(format!("{} failed with {} {}: {}", action, status, error, message).into())
+}
+
+fn parse_messages(response: &mut [u8]) -> Result<Vec<Message>> {
+    let body = tremor_value::parse_to_value(response)?;
+    let messages = body.get_array("Messages").map_or(&[][..], Vec::as_slice);
+    messages
+        .iter()
+        .map(|m| {
+            let field = |name: &str| {
+                m.get_str(name)
+                    .map(ToString::to_string)
+                    .ok_or_else(|| Error::from(format!("Received message without `{}`", name)))
+            };
+            let object = |name: &str| m.get(name).map_or_else(Value::object, |v| v.clone_static());
+            Ok(Message {
+                message_id: field("MessageId")?,
+                receipt_handle: field("ReceiptHandle")?,
+                body: field("Body")?,
+                attributes: object("Attributes"),
+                message_attributes: object("MessageAttributes"),
+            })
+        })
+        .collect()
+}
+
+/// Receives up to `max_messages` messages, waiting up to `wait_time`
+/// seconds for them to arrive
+pub(crate) async fn receive_message(
+    client: &AwsClient,
+    queue_url: &str,
+    max_messages: u64,
+    wait_time: u64,
+    visibility_timeout: u64,
+) -> Result<Vec<Message>> {
+    let body = literal!({
+        "QueueUrl": queue_url.to_string(),
+        "MaxNumberOfMessages": max_messages,
+        "WaitTimeSeconds": wait_time,
+        "VisibilityTimeout": visibility_timeout,
+        "AttributeNames": ["All"],
+        "MessageAttributeNames": ["All"]
+    });
+    let mut response = call(client, "ReceiveMessage", &body).await?;
+    parse_messages(&mut response)
+}
+
+/// Deletes a received message
+pub(crate) async fn delete_message(
+    client: &AwsClient,
+    queue_url: &str,
+    receipt_handle: &str,
+) -> Result<()> {
+    let body = literal!({
+        "QueueUrl": queue_url.to_string(),
+        "ReceiptHandle": receipt_handle.to_string()
+    });
+    call(client, "DeleteMessage", &body).await.map(|_| ())
+}
+
+/// Makes a received message visible again after `visibility_timeout`
+/// seconds, `0` returns it to the queue right away
+pub(crate) async fn change_message_visibility(
+    client: &AwsClient,
+    queue_url: &str,
+    receipt_handle: &str,
+    visibility_timeout: u64,
+) -> Result<()> {
+    let body = literal!({
+        "QueueUrl": queue_url.to_string(),
+        "ReceiptHandle": receipt_handle.to_string(),
+        "VisibilityTimeout": visibility_timeout
+    });
+    call(client, "ChangeMessageVisibility", &body)
+        .await
+        .map(|_| ())
+}
+
+/// Sends a message, `group_id` and `deduplication_id` are for FIFO queues
+pub(crate) async fn send_message(
+    client: &AwsClient,
+    queue_url: &str,
+    message: &str,
+    group_id: Option<&str>,
+    deduplication_id: Option<&str>,
+) -> Result<()> {
+    let mut body = literal!({
+        "QueueUrl": queue_url.to_string(),
+        "MessageBody": message.to_string()
+    });
+    if let Some(group_id) = group_id {
+        body.try_insert("MessageGroupId", group_id.to_string());
+    }
+    if let Some(deduplication_id) = deduplication_id {
+        body.try_insert("MessageDeduplicationId", deduplication_id.to_string());
+    }
+    call(client, "SendMessage", &body).await.map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn messages() -> Result<()> {
+        let mut response = br#"{"Messages":[{
+            "MessageId":"m1",
+            "ReceiptHandle":"r1",
+            "Body":"{\"snot\":\"badger\"}",
+            "Attributes":{"ApproximateReceiveCount":"2"},
+            "MessageAttributes":{"tenant":{"DataType":"String","StringValue":"a"}}
+        },{
+            "MessageId":"m2",
+            "ReceiptHandle":"r2",
+            "Body":"plain"
+        }]}"#
+            .to_vec();
+        let messages = parse_messages(&mut response)?;
+        assert_eq!(2, messages.len());
+        let first = messages.get(0).expect("first message");
+        assert_eq!("r1", first.receipt_handle);
+        assert_eq!(r#"{"snot":"badger"}"#, first.body);
+        assert_eq!(
+            Some("2"),
+            first.attributes.get_str("ApproximateReceiveCount")
+        );
+        assert_eq!(
+            literal!({"tenant": {"DataType": "String", "StringValue": "a"}}),
+            first.message_attributes
+        );
+        let second = messages.get(1).expect("second message");
+        assert_eq!("m2", second.message_id);
+        assert_eq!(Value::object(), second.attributes);
+
+        // no messages arrived within the wait time
+        assert!(parse_messages(&mut b"{}".to_vec())?.is_empty());
+        assert!(parse_messages(&mut br#"{"Messages":[{"MessageId":"m"}]}"#.to_vec()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        assert!(check("SendMessage", 200, b"{}".to_vec()).is_ok());
+        let err = check(
+            "SendMessage",
+            400,
+            br#"{"__type":"com.amazonaws.sqs#QueueDoesNotExist","message":"gone"}"#.to_vec(),
+        )
+        .err()
+        .map(|e| e.to_string());
+        assert_eq!(
+            Some("SendMessage failed with 400 QueueDoesNotExist: gone".to_string()),
+            err
+        );
+    }
+}
@@ -0,0 +1,1315 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal AMQP 1.0 client, covering what Event Hubs needs: SASL PLAIN,
+//! one session on channel 0, receiving and sending links with link credit
+//! and messages with data bodies.
+
+use crate::errors::{Error, Result};
+use async_std::io::prelude::*;
+use halfbrown::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+use tremor_value::prelude::*;
+
+// performatives
+const OPEN: u64 = 0x10;
+const BEGIN: u64 = 0x11;
+const ATTACH: u64 = 0x12;
+const FLOW: u64 = 0x13;
+const TRANSFER: u64 = 0x14;
+const DISPOSITION: u64 = 0x15;
+const DETACH: u64 = 0x16;
+const END: u64 = 0x17;
+const CLOSE: u64 = 0x18;
+const SASL_MECHANISMS: u64 = 0x40;
+const SASL_INIT: u64 = 0x41;
+const SASL_OUTCOME: u64 = 0x44;
+// link terminus and delivery states
+const SOURCE: u64 = 0x28;
+const TARGET: u64 = 0x29;
+const ACCEPTED: u64 = 0x24;
+// message sections
+const MESSAGE_ANNOTATIONS: u64 = 0x72;
+const PROPERTIES: u64 = 0x73;
+const APPLICATION_PROPERTIES: u64 = 0x74;
+const DATA: u64 = 0x75;
+const AMQP_VALUE: u64 = 0x77;
+
+const FRAME_AMQP: u8 = 0;
+const FRAME_SASL: u8 = 1;
+const MAX_FRAME_SIZE: u32 = 65_536;
+/// milliseconds after which the peer closes an idle connection
+const IDLE_TIMEOUT: u32 = 60_000;
+/// transfers the peer may send before the session window is renewed
+const INCOMING_WINDOW: u32 = 5000;
+/// frame header and the largest transfer performative sent
+const TRANSFER_OVERHEAD: usize = 64;
+
+/// An AMQP 1.0 value
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Amqp {
+    Null,
+    Bool(bool),
+    Ubyte(u8),
+    Ushort(u16),
+    Uint(u32),
+    Ulong(u64),
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Char(char),
+    /// milliseconds since epoch
+    Timestamp(i64),
+    Uuid([u8; 16]),
+    /// binaries and, as their raw bytes, decimals
+    Binary(Vec<u8>),
+    String(String),
+    Symbol(String),
+    List(Vec<Amqp>),
+    Map(Vec<(Amqp, Amqp)>),
+    Array(Vec<Amqp>),
+    Described(Box<Amqp>, Box<Amqp>),
+}
+
+impl Amqp {
+    pub(crate) fn described(code: u64, value: Self) -> Self {
+        Self::Described(Box::new(Self::Ulong(code)), Box::new(value))
+    }
+
+    pub(crate) fn symbol(s: &str) -> Self {
+        Self::Symbol(s.to_string())
+    }
+
+    pub(crate) fn string(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+
+    /// the descriptor code and value of a described value
+    pub(crate) fn as_described(&self) -> Option<(u64, &Self)> {
+        match self {
+            Self::Described(descriptor, value) => descriptor.as_u64().map(|code| (code, &**value)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_list(&self) -> Option<&[Self]> {
+        match self {
+            Self::List(items) | Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) | Self::Symbol(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Ubyte(v) => Some(u64::from(*v)),
+            Self::Ushort(v) => Some(u64::from(*v)),
+            Self::Uint(v) => Some(u64::from(*v)),
+            Self::Ulong(v) => Some(*v),
+            _ => self.as_i64().and_then(|v| u64::try_from(v).ok()),
+        }
+    }
+
+    pub(crate) fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Byte(v) => Some(i64::from(*v)),
+            Self::Short(v) => Some(i64::from(*v)),
+            Self::Int(v) => Some(i64::from(*v)),
+            Self::Long(v) | Self::Timestamp(v) => Some(*v),
+            Self::Ubyte(_) | Self::Ushort(_) | Self::Uint(_) | Self::Ulong(_) => {
+                self.as_u64().and_then(|v| i64::try_from(v).ok())
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// the entry of a map with a string or symbol key
+    pub(crate) fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Encodes the value, in its most compact form
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Null => out.push(0x40),
+            Self::Bool(true) => out.push(0x41),
+            Self::Bool(false) => out.push(0x42),
+            Self::Uint(0) => out.push(0x43),
+            Self::Uint(v) if *v < 256 => out.extend_from_slice(&[0x52, *v as u8]),
+            Self::Ulong(0) => out.push(0x44),
+            Self::Ulong(v) if *v < 256 => out.extend_from_slice(&[0x53, *v as u8]),
+            Self::Int(v) if i8::try_from(*v).is_ok() => out.extend_from_slice(&[0x54, *v as u8]),
+            Self::Long(v) if i8::try_from(*v).is_ok() => out.extend_from_slice(&[0x55, *v as u8]),
+            Self::Binary(v) if v.len() < 256 => {
+                out.extend_from_slice(&[0xa0, v.len() as u8]);
+                out.extend_from_slice(v);
+            }
+            Self::String(s) if s.len() < 256 => {
+                out.extend_from_slice(&[0xa1, s.len() as u8]);
+                out.extend_from_slice(s.as_bytes());
+            }
+            Self::Symbol(s) if s.len() < 256 => {
+                out.extend_from_slice(&[0xa3, s.len() as u8]);
+                out.extend_from_slice(s.as_bytes());
+            }
+            Self::List(items) if items.is_empty() => out.push(0x45),
+            Self::Described(descriptor, value) => {
+                out.push(0x00);
+                descriptor.encode(out);
+                value.encode(out);
+            }
+            _ => {
+                let at = out.len();
+                out.push(0);
+                let constructor = self.encode_wide(out);
+                if let Some(c) = out.get_mut(at) {
+                    *c = constructor;
+                }
+            }
+        }
+    }
+
+    /// Encodes the value without its constructor in its widest form, as
+    /// array elements share theirs, returning the constructor
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn encode_wide(&self, out: &mut Vec<u8>) -> u8 {
+        match self {
+            Self::Null => 0x40,
+            Self::Bool(b) => {
+                out.push(u8::from(*b));
+                0x56
+            }
+            Self::Ubyte(v) => {
+                out.push(*v);
+                0x50
+            }
+            Self::Ushort(v) => {
+                out.extend_from_slice(&v.to_be_bytes());
+                0x60
+            }
+            Self::Uint(v) => {
+                out.extend_from_slice(&v.to_be_bytes());
+                0x70
+            }
+            Self::Ulong(v) => {
+                out.extend_from_slice(&v.to_be_bytes());
+                0x80
+            }
+            Self::Byte(v) => {
+                out.push(*v as u8);
+                0x51
+            }
+            Self::Short(v) => {
+                out.extend_from_slice(&v.to_be_bytes());
+                0x61
+            }
+            Self::Int(v) => {
+                out.extend_from_slice(&v.to_be_bytes());
+                0x71
+            }
+            Self::Long(v) => {
+                out.extend_from_slice(&v.to_be_bytes());
+                0x81
+            }
+            Self::Float(v) => {
+                out.extend_from_slice(&v.to_bits().to_be_bytes());
+                0x72
+            }
+            Self::Double(v) => {
+                out.extend_from_slice(&v.to_bits().to_be_bytes());
+                0x82
+            }
+            Self::Char(c) => {
+                out.extend_from_slice(&u32::from(*c).to_be_bytes());
+                0x73
+            }
+            Self::Timestamp(v) => {
+                out.extend_from_slice(&v.to_be_bytes());
+                0x83
+            }
+            Self::Uuid(v) => {
+                out.extend_from_slice(v);
+                0x98
+            }
+            Self::Binary(v) => {
+                out.extend_from_slice(&(v.len() as u32).to_be_bytes());
+                out.extend_from_slice(v);
+                0xb0
+            }
+            Self::String(s) => {
+                out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+                out.extend_from_slice(s.as_bytes());
+                0xb1
+            }
+            Self::Symbol(s) => {
+                out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+                out.extend_from_slice(s.as_bytes());
+                0xb3
+            }
+            Self::List(items) => {
+                let mut body = Vec::new();
+                for item in items {
+                    item.encode(&mut body);
+                }
+                compound(out, items.len(), &body);
+                0xd0
+            }
+            Self::Map(entries) => {
+                let mut body = Vec::new();
+                for (k, v) in entries {
+                    k.encode(&mut body);
+                    v.encode(&mut body);
+                }
+                compound(out, entries.len() * 2, &body);
+                0xd1
+            }
+            Self::Array(items) => {
+                let mut body = Vec::new();
+                let mut constructor = 0x40;
+                for item in items {
+                    constructor = item.encode_wide(&mut body);
+                }
+                let mut elements = vec![constructor];
+                elements.append(&mut body);
+                compound(out, items.len(), &elements);
+                0xf0
+            }
+            // described values in arrays are rare enough to not share the
+            // descriptor
+            Self::Described(_, value) => value.encode_wide(out),
+        }
+    }
+}
+
+/// writes the size and count of a list, map or array and its body
+#[allow(clippy::cast_possible_truncation)]
+fn compound(out: &mut Vec<u8>, count: usize, body: &[u8]) {
+    out.extend_from_slice(&(body.len() as u32 + 4).to_be_bytes());
+    out.extend_from_slice(&(count as u32).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+fn take<'buf>(buf: &mut &'buf [u8], n: usize) -> Result<&'buf [u8]> {
+    if buf.len() < n {
+        return Err("Truncated AMQP value".into());
+    }
+    let (head, tail) = buf.split_at(n);
+    *buf = tail;
+    Ok(head)
+}
+
+fn take_array<const N: usize>(buf: &mut &[u8]) -> Result<[u8; N]> {
+    let mut array = [0; N];
+    array.copy_from_slice(take(buf, N)?);
+    Ok(array)
+}
+
+fn take_u8(buf: &mut &[u8]) -> Result<u8> {
+    Ok(u8::from_be_bytes(take_array(buf)?))
+}
+
+fn take_u32(buf: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_be_bytes(take_array(buf)?))
+}
+
+/// the body of a variable width value, preceded by its size
+fn take_sized<'buf>(buf: &mut &'buf [u8], wide: bool) -> Result<&'buf [u8]> {
+    let size = if wide {
+        take_u32(buf)? as usize
+    } else {
+        usize::from(take_u8(buf)?)
+    };
+    take(buf, size)
+}
+
+/// how deep compound and described values may nest
+const MAX_DEPTH: usize = 32;
+/// how many elements an array of a constructor without data, like null, may have
+const MAX_ZERO_WIDTH_ITEMS: usize = 1024;
+
+/// Decodes the value at the front of `buf`, advancing it
+pub(crate) fn decode(buf: &mut &[u8]) -> Result<Amqp> {
+    decode_nested(buf, 0)
+}
+
+fn decode_nested(buf: &mut &[u8], depth: usize) -> Result<Amqp> {
+    if depth > MAX_DEPTH {
+        return Err("AMQP value nested too deep".into());
+    }
+    let constructor = take_u8(buf)?;
+    if constructor == 0x00 {
+        let descriptor = decode_nested(buf, depth + 1)?;
+        let value = decode_nested(buf, depth + 1)?;
+        Ok(Amqp::Described(Box::new(descriptor), Box::new(value)))
+    } else {
+        decode_value(constructor, buf, depth)
+    }
+}
+
+#[allow(clippy::cast_possible_wrap)]
+fn decode_value(constructor: u8, buf: &mut &[u8], depth: usize) -> Result<Amqp> {
+    let utf8 = |bytes: &[u8]| -> Result<String> { Ok(String::from_utf8(bytes.to_vec())?) };
+    Ok(match constructor {
+        0x40 => Amqp::Null,
+        0x41 => Amqp::Bool(true),
+        0x42 => Amqp::Bool(false),
+        0x56 => Amqp::Bool(take_u8(buf)? != 0),
+        0x50 => Amqp::Ubyte(take_u8(buf)?),
+        0x60 => Amqp::Ushort(u16::from_be_bytes(take_array(buf)?)),
+        0x70 => Amqp::Uint(take_u32(buf)?),
+        0x52 => Amqp::Uint(u32::from(take_u8(buf)?)),
+        0x43 => Amqp::Uint(0),
+        0x80 => Amqp::Ulong(u64::from_be_bytes(take_array(buf)?)),
+        0x53 => Amqp::Ulong(u64::from(take_u8(buf)?)),
+        0x44 => Amqp::Ulong(0),
+        0x51 => Amqp::Byte(take_u8(buf)? as i8),
+        0x61 => Amqp::Short(i16::from_be_bytes(take_array(buf)?)),
+        0x71 => Amqp::Int(i32::from_be_bytes(take_array(buf)?)),
+        0x54 => Amqp::Int(i32::from(take_u8(buf)? as i8)),
+        0x81 => Amqp::Long(i64::from_be_bytes(take_array(buf)?)),
+        0x55 => Amqp::Long(i64::from(take_u8(buf)? as i8)),
+        0x72 => Amqp::Float(f32::from_bits(take_u32(buf)?)),
+        0x82 => Amqp::Double(f64::from_bits(u64::from_be_bytes(take_array(buf)?))),
+        0x73 => Amqp::Char(
+            std::char::from_u32(take_u32(buf)?).ok_or_else(|| Error::from("Invalid AMQP char"))?,
+        ),
+        0x83 => Amqp::Timestamp(i64::from_be_bytes(take_array(buf)?)),
+        0x98 => Amqp::Uuid(take_array(buf)?),
+        0x74 => Amqp::Binary(take(buf, 4)?.to_vec()),
+        0x84 => Amqp::Binary(take(buf, 8)?.to_vec()),
+        0x94 => Amqp::Binary(take(buf, 16)?.to_vec()),
+        0xa0 | 0xb0 => Amqp::Binary(take_sized(buf, constructor == 0xb0)?.to_vec()),
+        0xa1 | 0xb1 => Amqp::String(utf8(take_sized(buf, constructor == 0xb1)?)?),
+        0xa3 | 0xb3 => Amqp::Symbol(utf8(take_sized(buf, constructor == 0xb3)?)?),
+        0x45 => Amqp::List(vec![]),
+        0xc0 | 0xd0 | 0xc1 | 0xd1 | 0xe0 | 0xf0 => {
+            let wide = constructor & 0xf0 != 0xc0 && constructor & 0xf0 != 0xe0;
+            let mut body = take_sized(buf, wide)?;
+            let count = if wide {
+                take_u32(&mut body)? as usize
+            } else {
+                usize::from(take_u8(&mut body)?)
+            };
+            match constructor {
+                0xc0 | 0xd0 => Amqp::List(decode_items(body, count, depth + 1)?),
+                0xc1 | 0xd1 => {
+                    let mut items = decode_items(body, count, depth + 1)?.into_iter();
+                    let mut entries = Vec::with_capacity(count / 2);
+                    while let Some(k) = items.next() {
+                        entries.push((k, items.next().unwrap_or(Amqp::Null)));
+                    }
+                    Amqp::Map(entries)
+                }
+                _ => Amqp::Array(decode_array(body, count, depth + 1)?),
+            }
+        }
+        c => return Err(format!("Unknown AMQP constructor 0x{:02x}", c).into()),
+    })
+}
+
+fn decode_items(mut body: &[u8], count: usize, depth: usize) -> Result<Vec<Amqp>> {
+    // every item has at least its constructor
+    if count > body.len() {
+        return Err("Truncated AMQP value".into());
+    }
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        items.push(decode_nested(&mut body, depth)?);
+    }
+    Ok(items)
+}
+
+fn decode_array(mut body: &[u8], count: usize, depth: usize) -> Result<Vec<Amqp>> {
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    let mut constructor = take_u8(&mut body)?;
+    let descriptor = if constructor == 0x00 {
+        let descriptor = decode_nested(&mut body, depth + 1)?;
+        constructor = take_u8(&mut body)?;
+        Some(descriptor)
+    } else {
+        None
+    };
+    // elements of null, true, false, uint0, ulong0 and list0 take no bytes
+    if (0x40..=0x45).contains(&constructor) {
+        if count > MAX_ZERO_WIDTH_ITEMS {
+            return Err("Too many elements in AMQP array".into());
+        }
+    } else if count > body.len() {
+        return Err("Truncated AMQP value".into());
+    }
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        let value = decode_value(constructor, &mut body, depth)?;
+        items.push(match &descriptor {
+            Some(d) => Amqp::Described(Box::new(d.clone()), Box::new(value)),
+            None => value,
+        });
+    }
+    Ok(items)
+}
+
+/// Converts an AMQP value to a tremor value, timestamps stay milliseconds
+pub(crate) fn to_value(value: &Amqp) -> Value<'static> {
+    match value {
+        Amqp::Null => Value::null(),
+        Amqp::Bool(b) => Value::from(*b),
+        Amqp::Float(f) => Value::from(f64::from(*f)),
+        Amqp::Double(f) => Value::from(*f),
+        Amqp::Char(c) => Value::from(c.to_string()),
+        Amqp::Uuid(u) => Value::from(u.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+        Amqp::Binary(b) => Value::Bytes(b.clone().into()),
+        Amqp::String(s) | Amqp::Symbol(s) => Value::from(s.clone()),
+        Amqp::List(items) | Amqp::Array(items) => {
+            Value::from(items.iter().map(to_value).collect::<Vec<_>>())
+        }
+        Amqp::Map(entries) => {
+            let mut object = Value::object_with_capacity(entries.len());
+            for (k, v) in entries {
+                let key = k
+                    .as_str()
+                    .map_or_else(|| to_value(k).encode(), ToString::to_string);
+                object.try_insert(key, to_value(v));
+            }
+            object
+        }
+        Amqp::Described(_, value) => to_value(value),
+        number => number
+            .as_i64()
+            .map(Value::from)
+            .or_else(|| number.as_u64().map(Value::from))
+            .unwrap_or_else(Value::null),
+    }
+}
+
+/// Converts a tremor value to an AMQP one, for application properties
+pub(crate) fn from_value(value: &Value) -> Amqp {
+    if let Some(s) = value.as_str() {
+        Amqp::String(s.to_string())
+    } else if let Some(b) = value.as_bool() {
+        Amqp::Bool(b)
+    } else if let Some(i) = value.as_i64() {
+        Amqp::Long(i)
+    } else if let Some(u) = value.as_u64() {
+        Amqp::Ulong(u)
+    } else if let Some(f) = value.as_f64() {
+        Amqp::Double(f)
+    } else if value.is_null() {
+        Amqp::Null
+    } else {
+        Amqp::String(value.encode())
+    }
+}
+
+/// A received message
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Message {
+    /// the data sections, or a string or binary value
+    pub(crate) body: Vec<u8>,
+    /// any other value
+    pub(crate) value: Amqp,
+    pub(crate) annotations: Amqp,
+    pub(crate) properties: Amqp,
+}
+
+impl Default for Amqp {
+    fn default() -> Self {
+        Self::Null
+    }
+}
+
+impl Message {
+    /// Decodes the sections of a message
+    pub(crate) fn decode(mut payload: &[u8]) -> Result<Self> {
+        let mut message = Self::default();
+        while !payload.is_empty() {
+            let section = decode(&mut payload)?;
+            match section.as_described() {
+                Some((MESSAGE_ANNOTATIONS, annotations)) => {
+                    message.annotations = annotations.clone();
+                }
+                Some((APPLICATION_PROPERTIES, properties)) => {
+                    message.properties = properties.clone();
+                }
+                Some((DATA, Amqp::Binary(data))) | Some((AMQP_VALUE, Amqp::Binary(data))) => {
+                    message.body.extend_from_slice(data);
+                }
+                Some((AMQP_VALUE, Amqp::String(s))) => message.body.extend_from_slice(s.as_bytes()),
+                Some((AMQP_VALUE, value)) => message.value = value.clone(),
+                // header, delivery annotations, properties, sequences and footer
+                _ => (),
+            }
+        }
+        Ok(message)
+    }
+
+    /// the message annotation `key`
+    pub(crate) fn annotation(&self, key: &str) -> Option<&Amqp> {
+        self.annotations.get(key)
+    }
+}
+
+/// Encodes a message with a data body
+pub(crate) fn encode_message(
+    body: &[u8],
+    annotations: Vec<(Amqp, Amqp)>,
+    properties: Option<Amqp>,
+    application_properties: Vec<(Amqp, Amqp)>,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(body.len() + 64);
+    if !annotations.is_empty() {
+        Amqp::described(MESSAGE_ANNOTATIONS, Amqp::Map(annotations)).encode(&mut out);
+    }
+    if let Some(properties) = properties {
+        Amqp::described(PROPERTIES, properties).encode(&mut out);
+    }
+    if !application_properties.is_empty() {
+        Amqp::described(APPLICATION_PROPERTIES, Amqp::Map(application_properties)).encode(&mut out);
+    }
+    Amqp::described(DATA, Amqp::Binary(body.to_vec())).encode(&mut out);
+    out
+}
+
+/// A frame, heartbeats aren't passed on
+#[derive(Debug, PartialEq)]
+struct Frame {
+    performative: Amqp,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn code(&self) -> Option<u64> {
+        self.performative.as_described().map(|(code, _)| code)
+    }
+
+    /// the field at `index` of the performative, `None` if it is null
+    fn field(&self, index: usize) -> Option<&Amqp> {
+        self.performative
+            .as_described()
+            .and_then(|(_, fields)| fields.as_list())
+            .and_then(|fields| fields.get(index))
+            .filter(|f| **f != Amqp::Null)
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn encode_frame(frame_type: u8, performative: Option<&Amqp>, payload: &[u8]) -> Vec<u8> {
+    // size, data offset of 2 words, type and channel 0
+    let mut out = vec![0, 0, 0, 0, 2, frame_type, 0, 0];
+    if let Some(performative) = performative {
+        performative.encode(&mut out);
+    }
+    out.extend_from_slice(payload);
+    let size = (out.len() as u32).to_be_bytes();
+    if let Some(head) = out.get_mut(..4) {
+        head.copy_from_slice(&size);
+    }
+    out
+}
+
+/// Takes the first frame from `buf` once it is complete
+fn parse_frame(buf: &mut Vec<u8>) -> Result<Option<Option<Frame>>> {
+    let mut header = buf.as_slice();
+    if header.len() < 8 {
+        return Ok(None);
+    }
+    let size = take_u32(&mut header)? as usize;
+    let data_offset = usize::from(take_u8(&mut header)?) * 4;
+    if size < 8 || data_offset < 8 || data_offset > size {
+        return Err("Invalid AMQP frame".into());
+    }
+    if buf.len() < size {
+        return Ok(None);
+    }
+    let frame: Vec<u8> = buf.drain(..size).collect();
+    let mut body = frame.get(data_offset..).unwrap_or_default();
+    if body.is_empty() {
+        // heartbeat
+        return Ok(Some(None));
+    }
+    let performative = decode(&mut body)?;
+    Ok(Some(Some(Frame {
+        performative,
+        payload: body.to_vec(),
+    })))
+}
+
+/// Events of the links of a session
+#[derive(Debug, PartialEq)]
+pub(crate) enum Event {
+    /// a message was received on a receiving link
+    Message { handle: u32, message: Message },
+    /// deliveries `first` to `last` were settled, `accepted` or not
+    Settled {
+        first: u32,
+        last: u32,
+        accepted: bool,
+    },
+    /// a sending link was granted credit
+    Credit { handle: u32 },
+    /// the peer detached a link
+    Detached { handle: u32, error: Option<String> },
+}
+
+#[derive(Default)]
+struct Link {
+    receiver: bool,
+    delivery_count: u32,
+    credit: u32,
+    /// credit granted by receivers
+    prefetch: u32,
+    /// the payload of a transfer split over several frames
+    partial: Vec<u8>,
+}
+
+fn error_description(error: Option<&Amqp>) -> Option<String> {
+    // error: condition, description, info
+    let fields = error?.as_described()?.1.as_list()?;
+    let condition = fields.get(0).and_then(Amqp::as_str).unwrap_or_default();
+    let description = fields.get(1).and_then(Amqp::as_str).unwrap_or_default();
+    Some(format!("{} {}", condition, description))
+}
+
+/// A connection with one session
+pub(crate) struct Connection<S> {
+    stream: S,
+    buf: Vec<u8>,
+    chunk: Vec<u8>,
+    last_sent: Instant,
+    /// half the idle timeout of the peer
+    heartbeat: Option<Duration>,
+    max_frame_size: usize,
+    next_outgoing_id: u32,
+    next_incoming_id: u32,
+    /// transfers received since the session window was renewed
+    incoming: u32,
+    next_handle: u32,
+    next_delivery_id: u32,
+    links: HashMap<u32, Link>,
+}
+
+impl<S> Connection<S>
+where
+    S: Read + Write + Unpin + Send,
+{
+    /// Authenticates with SASL PLAIN, opens the connection and begins the
+    /// session
+    pub(crate) async fn open(
+        stream: S,
+        hostname: &str,
+        user: &str,
+        password: &str,
+        container_id: &str,
+    ) -> Result<Self> {
+        let mut connection = Self {
+            stream,
+            buf: Vec::new(),
+            chunk: vec![0; 16 * 1024],
+            last_sent: Instant::now(),
+            heartbeat: None,
+            max_frame_size: MAX_FRAME_SIZE as usize,
+            next_outgoing_id: 0,
+            next_incoming_id: 0,
+            incoming: 0,
+            next_handle: 0,
+            next_delivery_id: 0,
+            links: HashMap::new(),
+        };
+        connection.header(3).await?;
+        let mechanisms = connection.expect(SASL_MECHANISMS).await?;
+        let plain = mechanisms.field(0).map_or(false, |m| {
+            m.as_str() == Some("PLAIN")
+                || m.as_list()
+                    .map_or(false, |l| l.iter().any(|m| m.as_str() == Some("PLAIN")))
+        });
+        if !plain {
+            return Err("The AMQP server doesn't support SASL PLAIN".into());
+        }
+        let mut response = vec![0];
+        response.extend_from_slice(user.as_bytes());
+        response.push(0);
+        response.extend_from_slice(password.as_bytes());
+        let init = Amqp::described(
+            SASL_INIT,
+            Amqp::List(vec![
+                Amqp::symbol("PLAIN"),
+                Amqp::Binary(response),
+                Amqp::string(hostname),
+            ]),
+        );
+        connection.write(FRAME_SASL, Some(&init), &[]).await?;
+        let outcome = connection.expect(SASL_OUTCOME).await?;
+        if outcome.field(0).and_then(Amqp::as_u64) != Some(0) {
+            return Err("AMQP authentication failed".into());
+        }
+
+        connection.header(0).await?;
+        let open = Amqp::described(
+            OPEN,
+            Amqp::List(vec![
+                Amqp::string(container_id),
+                Amqp::string(hostname),
+                Amqp::Uint(MAX_FRAME_SIZE),
+                Amqp::Ushort(0),
+                Amqp::Uint(IDLE_TIMEOUT),
+            ]),
+        );
+        connection.write(FRAME_AMQP, Some(&open), &[]).await?;
+        let open = connection.expect(OPEN).await?;
+        if let Some(size) = open.field(2).and_then(Amqp::as_u64) {
+            connection.max_frame_size = connection
+                .max_frame_size
+                .min(usize::try_from(size).unwrap_or(usize::MAX))
+                .max(512);
+        }
+        connection.heartbeat = open
+            .field(4)
+            .and_then(Amqp::as_u64)
+            .filter(|ms| *ms > 0)
+            .map(|ms| Duration::from_millis(ms / 2));
+
+        let begin = Amqp::described(
+            BEGIN,
+            Amqp::List(vec![
+                Amqp::Null,
+                Amqp::Uint(0),
+                Amqp::Uint(INCOMING_WINDOW),
+                Amqp::Uint(u32::MAX),
+            ]),
+        );
+        connection.write(FRAME_AMQP, Some(&begin), &[]).await?;
+        let begin = connection.expect(BEGIN).await?;
+        connection.next_incoming_id = begin
+            .field(1)
+            .and_then(Amqp::as_u64)
+            .and_then(|id| u32::try_from(id).ok())
+            .unwrap_or_default();
+        Ok(connection)
+    }
+
+    /// exchanges the protocol header with the given protocol id
+    async fn header(&mut self, protocol: u8) -> Result<()> {
+        let header = [b'A', b'M', b'Q', b'P', protocol, 1, 0, 0];
+        self.stream.write_all(&header).await?;
+        while self.buf.len() < header.len() {
+            self.fill().await?;
+        }
+        let received: Vec<u8> = self.buf.drain(..header.len()).collect();
+        if received == header {
+            Ok(())
+        } else {
+            Err(format!("Unsupported AMQP protocol header {:?}", received).into())
+        }
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        let n = self.stream.read(&mut self.chunk).await?;
+        if n == 0 {
+            return Err("AMQP connection closed".into());
+        }
+        self.buf
+            .extend_from_slice(self.chunk.get(..n).unwrap_or_default());
+        Ok(())
+    }
+
+    /// the next frame, this can be cancelled without losing data
+    async fn recv(&mut self) -> Result<Frame> {
+        loop {
+            match parse_frame(&mut self.buf)? {
+                Some(Some(frame)) => return Ok(frame),
+                // heartbeat
+                Some(None) => (),
+                None => self.fill().await?,
+            }
+        }
+    }
+
+    /// the next frame, failing if it isn't the performative `code`
+    async fn expect(&mut self, code: u64) -> Result<Frame> {
+        let frame = self.recv().await?;
+        match frame.code() {
+            Some(c) if c == code => Ok(frame),
+            Some(CLOSE) | Some(END) => Err(format!(
+                "AMQP connection closed: {}",
+                error_description(frame.field(0)).unwrap_or_default()
+            )
+            .into()),
+            c => Err(format!("Unexpected AMQP performative {:?}", c).into()),
+        }
+    }
+
+    async fn write(
+        &mut self,
+        frame_type: u8,
+        performative: Option<&Amqp>,
+        payload: &[u8],
+    ) -> Result<()> {
+        let frame = encode_frame(frame_type, performative, payload);
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Sends a heartbeat if nothing was sent for half the idle timeout of
+    /// the peer
+    pub(crate) async fn keep_alive(&mut self) -> Result<()> {
+        match self.heartbeat {
+            Some(heartbeat) if self.last_sent.elapsed() >= heartbeat => {
+                self.write(FRAME_AMQP, None, &[]).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn session_state(&self) -> Vec<Amqp> {
+        vec![
+            Amqp::Uint(self.next_incoming_id),
+            Amqp::Uint(INCOMING_WINDOW),
+            Amqp::Uint(self.next_outgoing_id),
+            Amqp::Uint(u32::MAX),
+        ]
+    }
+
+    async fn attach(
+        &mut self,
+        name: &str,
+        receiver: bool,
+        source: Amqp,
+        target: Amqp,
+    ) -> Result<u32> {
+        let handle = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        let attach = Amqp::described(
+            ATTACH,
+            Amqp::List(vec![
+                Amqp::string(name),
+                Amqp::Uint(handle),
+                Amqp::Bool(receiver),
+                // receivers get settled deliveries, senders wait for them to
+                // be settled
+                Amqp::Ubyte(if receiver { 1 } else { 0 }),
+                Amqp::Ubyte(0),
+                source,
+                target,
+                Amqp::Null,
+                Amqp::Bool(false),
+                if receiver { Amqp::Null } else { Amqp::Uint(0) },
+            ]),
+        );
+        self.write(FRAME_AMQP, Some(&attach), &[]).await?;
+        self.links.insert(
+            handle,
+            Link {
+                receiver,
+                ..Link::default()
+            },
+        );
+        Ok(handle)
+    }
+
+    /// Attaches a link receiving from `address`, granting `prefetch`
+    /// credit. The `filter` is an entry of the source filter set
+    pub(crate) async fn attach_receiver(
+        &mut self,
+        name: &str,
+        address: &str,
+        target: &str,
+        filter: Option<(Amqp, Amqp)>,
+        prefetch: u32,
+    ) -> Result<u32> {
+        let filters = filter.map_or(Amqp::Null, |f| Amqp::Map(vec![f]));
+        let source = Amqp::described(
+            SOURCE,
+            Amqp::List(vec![
+                Amqp::string(address),
+                Amqp::Null,
+                Amqp::Null,
+                Amqp::Null,
+                Amqp::Null,
+                Amqp::Null,
+                Amqp::Null,
+                filters,
+            ]),
+        );
+        let target = Amqp::described(TARGET, Amqp::List(vec![Amqp::string(target)]));
+        let handle = self.attach(name, true, source, target).await?;
+        if let Some(link) = self.links.get_mut(&handle) {
+            link.prefetch = prefetch.max(1);
+        }
+        self.grant(handle).await?;
+        Ok(handle)
+    }
+
+    /// Attaches a link sending to `address`
+    pub(crate) async fn attach_sender(&mut self, name: &str, address: &str) -> Result<u32> {
+        let source = Amqp::described(SOURCE, Amqp::List(vec![Amqp::string(name)]));
+        let target = Amqp::described(TARGET, Amqp::List(vec![Amqp::string(address)]));
+        self.attach(name, false, source, target).await
+    }
+
+    /// Detaches and closes a link, transfers still arriving for it are
+    /// dropped
+    pub(crate) async fn detach(&mut self, handle: u32) -> Result<()> {
+        self.links.remove(&handle);
+        let detach = Amqp::described(
+            DETACH,
+            Amqp::List(vec![Amqp::Uint(handle), Amqp::Bool(true)]),
+        );
+        self.write(FRAME_AMQP, Some(&detach), &[]).await
+    }
+
+    /// renews the credit of a receiving link
+    async fn grant(&mut self, handle: u32) -> Result<()> {
+        let (delivery_count, prefetch) = match self.links.get_mut(&handle) {
+            Some(link) => {
+                link.credit = link.prefetch;
+                (link.delivery_count, link.prefetch)
+            }
+            None => return Ok(()),
+        };
+        let mut fields = self.session_state();
+        fields.push(Amqp::Uint(handle));
+        fields.push(Amqp::Uint(delivery_count));
+        fields.push(Amqp::Uint(prefetch));
+        self.incoming = 0;
+        self.write(
+            FRAME_AMQP,
+            Some(&Amqp::described(FLOW, Amqp::List(fields))),
+            &[],
+        )
+        .await
+    }
+
+    /// renews the session window without granting credit
+    async fn renew_window(&mut self) -> Result<()> {
+        self.incoming = 0;
+        let fields = self.session_state();
+        self.write(
+            FRAME_AMQP,
+            Some(&Amqp::described(FLOW, Amqp::List(fields))),
+            &[],
+        )
+        .await
+    }
+
+    /// the credit of a sending link
+    pub(crate) fn credit(&self, handle: u32) -> u32 {
+        self.links.get(&handle).map_or(0, |l| l.credit)
+    }
+
+    /// Sends a message on a sending link with credit, returning its
+    /// delivery id
+    pub(crate) async fn send(&mut self, handle: u32, message: &[u8]) -> Result<u32> {
+        match self.links.get_mut(&handle) {
+            Some(link) if !link.receiver && link.credit > 0 => {
+                link.credit -= 1;
+                link.delivery_count = link.delivery_count.wrapping_add(1);
+            }
+            Some(_) => return Err("No credit to send on the AMQP link".into()),
+            None => return Err("Unknown AMQP link".into()),
+        }
+        let delivery_id = self.next_delivery_id;
+        self.next_delivery_id = self.next_delivery_id.wrapping_add(1);
+        let max_payload = self.max_frame_size.saturating_sub(TRANSFER_OVERHEAD).max(1);
+        let mut chunks = message.chunks(max_payload).peekable();
+        let mut first = true;
+        while let Some(chunk) = chunks.next() {
+            let more = chunks.peek().is_some();
+            let fields = if first {
+                vec![
+                    Amqp::Uint(handle),
+                    Amqp::Uint(delivery_id),
+                    Amqp::Binary(delivery_id.to_be_bytes().to_vec()),
+                    Amqp::Uint(0),
+                    Amqp::Bool(false),
+                    Amqp::Bool(more),
+                ]
+            } else {
+                vec![
+                    Amqp::Uint(handle),
+                    Amqp::Null,
+                    Amqp::Null,
+                    Amqp::Null,
+                    Amqp::Null,
+                    Amqp::Bool(more),
+                ]
+            };
+            first = false;
+            let transfer = Amqp::described(TRANSFER, Amqp::List(fields));
+            self.write(FRAME_AMQP, Some(&transfer), chunk).await?;
+            self.next_outgoing_id = self.next_outgoing_id.wrapping_add(1);
+        }
+        Ok(delivery_id)
+    }
+
+    /// Handles frames until one of them is an event for the caller, this
+    /// can be cancelled without losing frames
+    pub(crate) async fn next(&mut self) -> Result<Event> {
+        loop {
+            let frame = self.recv().await?;
+            if let Some(event) = self.handle(frame).await? {
+                return Ok(event);
+            }
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    async fn handle(&mut self, frame: Frame) -> Result<Option<Event>> {
+        let u32_field = |index: usize| frame.field(index).and_then(Amqp::as_u64).map(|v| v as u32);
+        match frame.code() {
+            Some(TRANSFER) => {
+                self.next_incoming_id = self.next_incoming_id.wrapping_add(1);
+                self.incoming += 1;
+                let handle = u32_field(0).unwrap_or_default();
+                let more = frame.field(5).and_then(Amqp::as_bool).unwrap_or_default();
+                let mut complete = None;
+                let mut renew = false;
+                if let Some(link) = self.links.get_mut(&handle) {
+                    link.partial.extend_from_slice(&frame.payload);
+                    if !more {
+                        complete = Some(std::mem::take(&mut link.partial));
+                        link.delivery_count = link.delivery_count.wrapping_add(1);
+                        link.credit = link.credit.saturating_sub(1);
+                        renew = link.credit <= link.prefetch / 2;
+                    }
+                }
+                if renew {
+                    self.grant(handle).await?;
+                } else if self.incoming >= INCOMING_WINDOW / 2 {
+                    self.renew_window().await?;
+                }
+                match complete {
+                    Some(payload) => Ok(Some(Event::Message {
+                        handle,
+                        message: Message::decode(&payload)?,
+                    })),
+                    None => Ok(None),
+                }
+            }
+            Some(ATTACH) => {
+                // the initial delivery count of the peer sending to us
+                if let Some((handle, count)) = u32_field(1).zip(u32_field(9)) {
+                    if let Some(link) = self.links.get_mut(&handle) {
+                        link.delivery_count = count;
+                    }
+                }
+                Ok(None)
+            }
+            Some(FLOW) => {
+                // credit granted to a sending link
+                if let Some(handle) = u32_field(4) {
+                    let delivery_count = u32_field(5).unwrap_or_default();
+                    let credit = u32_field(6).unwrap_or_default();
+                    if let Some(link) = self.links.get_mut(&handle) {
+                        if !link.receiver {
+                            link.credit = delivery_count
+                                .wrapping_add(credit)
+                                .wrapping_sub(link.delivery_count);
+                            return Ok(Some(Event::Credit { handle }));
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            Some(DISPOSITION) => {
+                let first = u32_field(1).unwrap_or_default();
+                let last = u32_field(2).unwrap_or(first);
+                let accepted = frame
+                    .field(4)
+                    .and_then(Amqp::as_described)
+                    .map_or(false, |(code, _)| code == ACCEPTED);
+                Ok(Some(Event::Settled {
+                    first,
+                    last,
+                    accepted,
+                }))
+            }
+            Some(DETACH) => {
+                let handle = u32_field(0).unwrap_or_default();
+                let error = error_description(frame.field(2));
+                if self.links.remove(&handle).is_some() {
+                    // acknowledge the detach
+                    let detach = Amqp::described(
+                        DETACH,
+                        Amqp::List(vec![Amqp::Uint(handle), Amqp::Bool(true)]),
+                    );
+                    self.write(FRAME_AMQP, Some(&detach), &[]).await?;
+                    Ok(Some(Event::Detached { handle, error }))
+                } else {
+                    Ok(None)
+                }
+            }
+            Some(END) | Some(CLOSE) => Err(format!(
+                "AMQP connection closed: {}",
+                error_description(frame.field(0)).unwrap_or_default()
+            )
+            .into()),
+            _ => Ok(None),
+        }
+    }
+
+    /// Closes the connection
+    pub(crate) async fn close(&mut self) -> Result<()> {
+        let close = Amqp::described(CLOSE, Amqp::List(vec![]));
+        self.write(FRAME_AMQP, Some(&close), &[]).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roundtrip(value: Amqp) -> Result<()> {
+        let mut encoded = Vec::new();
+        value.encode(&mut encoded);
+        let mut buf = encoded.as_slice();
+        assert_eq!(value, decode(&mut buf)?);
+        assert!(buf.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn codec() -> Result<()> {
+        roundtrip(Amqp::Null)?;
+        roundtrip(Amqp::Bool(true))?;
+        roundtrip(Amqp::Uint(0))?;
+        roundtrip(Amqp::Uint(42))?;
+        roundtrip(Amqp::Uint(65_536))?;
+        roundtrip(Amqp::Ulong(u64::MAX))?;
+        roundtrip(Amqp::Int(-1))?;
+        roundtrip(Amqp::Long(-1_000))?;
+        roundtrip(Amqp::Double(1.5))?;
+        roundtrip(Amqp::Timestamp(1_616_417_712_000))?;
+        roundtrip(Amqp::Binary(vec![1; 300]))?;
+        roundtrip(Amqp::string("snot"))?;
+        roundtrip(Amqp::Symbol("b".repeat(300)))?;
+        roundtrip(Amqp::List(vec![]))?;
+        roundtrip(Amqp::List(vec![Amqp::Null, Amqp::string("badger")]))?;
+        roundtrip(Amqp::Map(vec![(Amqp::symbol("k"), Amqp::Long(1))]))?;
+        roundtrip(Amqp::Array(vec![Amqp::symbol("a"), Amqp::symbol("b")]))?;
+        roundtrip(Amqp::described(ATTACH, Amqp::List(vec![Amqp::Uint(1)])))?;
+        Ok(())
+    }
+
+    #[test]
+    fn compact_forms() -> Result<()> {
+        // list8 of a uint and a smallulong, as sent by brokers
+        let mut buf: &[u8] = &[0xc0, 0x05, 0x02, 0x52, 0x07, 0x53, 0x09];
+        assert_eq!(
+            Amqp::List(vec![Amqp::Uint(7), Amqp::Ulong(9)]),
+            decode(&mut buf)?
+        );
+        // array8 of sym8
+        let mut buf: &[u8] = &[0xe0, 0x08, 0x02, 0xa3, 0x02, b'a', b'b', 0x01, b'c'];
+        assert_eq!(
+            Amqp::Array(vec![Amqp::symbol("ab"), Amqp::symbol("c")]),
+            decode(&mut buf)?
+        );
+        let mut buf: &[u8] = &[0xa1, 0x05, b's'];
+        assert!(decode(&mut buf).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn bounded() -> Result<()> {
+        // list32 and array32 claiming more items than they have bytes
+        let mut buf: &[u8] = &[0xd0, 0, 0, 0, 6, 0xff, 0xff, 0xff, 0xff, 0x40, 0x40];
+        assert!(decode(&mut buf).is_err());
+        let mut buf: &[u8] = &[0xf0, 0, 0, 0, 6, 0xff, 0xff, 0xff, 0xff, 0x52, 0x01];
+        assert!(decode(&mut buf).is_err());
+        // an array of nulls has no bytes per element, its count is capped
+        let mut buf: &[u8] = &[0xf0, 0, 0, 0, 5, 0xff, 0xff, 0xff, 0xff, 0x40];
+        assert!(decode(&mut buf).is_err());
+        let mut buf: &[u8] = &[0xe0, 0x02, 0x03, 0x40];
+        assert_eq!(Amqp::Array(vec![Amqp::Null; 3]), decode(&mut buf)?);
+        // descriptors nested in descriptors
+        let nested = [0x00; 1000];
+        let mut buf: &[u8] = &nested;
+        assert_eq!(
+            Some("AMQP value nested too deep".to_string()),
+            decode(&mut buf).err().map(|e| e.to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn messages() -> Result<()> {
+        let encoded = encode_message(
+            b"snot",
+            vec![(Amqp::symbol("x-opt-partition-key"), Amqp::string("badger"))],
+            None,
+            vec![(Amqp::string("count"), Amqp::Long(2))],
+        );
+        let message = Message::decode(&encoded)?;
+        assert_eq!(b"snot".to_vec(), message.body);
+        assert_eq!(
+            Some(&Amqp::string("badger")),
+            message.annotation("x-opt-partition-key")
+        );
+        assert_eq!(
+            tremor_value::literal!({"count": 2}),
+            to_value(&message.properties)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn frames() -> Result<()> {
+        let performative = Amqp::described(FLOW, Amqp::List(vec![Amqp::Uint(1)]));
+        let mut buf = encode_frame(FRAME_AMQP, Some(&performative), b"payload");
+        // heartbeat
+        buf.extend_from_slice(&encode_frame(FRAME_AMQP, None, &[]));
+        let complete = buf.len();
+        buf.extend_from_slice(&[0, 0]);
+        let frame = parse_frame(&mut buf)?.and_then(|f| f);
+        assert_eq!(
+            Some(Frame {
+                performative,
+                payload: b"payload".to_vec()
+            }),
+            frame
+        );
+        assert_eq!(Some(None), parse_frame(&mut buf)?);
+        assert_eq!(None, parse_frame(&mut buf)?);
+        assert_eq!(2, buf.len());
+        assert!(complete > 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::connectors::azure::amqp::{self, Amqp, Connection, Event, Message};
+use crate::errors::{Error, Result};
+use async_std::net::TcpStream;
+use async_tls::client::TlsStream;
+use async_tls::TlsConnector;
+use std::time::Duration;
+use tremor_value::prelude::*;
+
+/// AMQP over TLS
+const PORT: u16 = 5671;
+const SELECTOR_FILTER: &str = "apache.org:selector-filter:string";
+const MANAGEMENT: &str = "$management";
+const PARTITION_KEY: &str = "x-opt-partition-key";
+
+pub(crate) type Client = Connection<TlsStream<TcpStream>>;
+
+/// The parts of a connection string, as shown for the shared access
+/// policies of a namespace or event hub
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ConnectionString {
+    pub(crate) host: String,
+    pub(crate) key_name: String,
+    pub(crate) key: String,
+    /// the event hub of connection strings of an event hub policy
+    pub(crate) entity_path: Option<String>,
+}
+
+impl ConnectionString {
+    /// Parses `Endpoint=sb://{namespace}.servicebus.windows.net/;SharedAccessKeyName={name};SharedAccessKey={key}`
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let mut host = None;
+        let mut key_name = None;
+        let mut key = None;
+        let mut entity_path = None;
+        for part in s.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut kv = part.splitn(2, '=');
+            let name = kv.next().unwrap_or_default();
+            let value = kv.next().unwrap_or_default().to_string();
+            match name {
+                "Endpoint" => {
+                    let endpoint = url::Url::parse(&value)?;
+                    host = endpoint.host_str().map(ToString::to_string);
+                }
+                "SharedAccessKeyName" => key_name = Some(value),
+                "SharedAccessKey" => key = Some(value),
+                "EntityPath" => entity_path = Some(value),
+                _ => (),
+            }
+        }
+        let missing = |part: &str| Error::from(format!("Connection string has no {}", part));
+        Ok(Self {
+            host: host.ok_or_else(|| missing("Endpoint"))?,
+            key_name: key_name.ok_or_else(|| missing("SharedAccessKeyName"))?,
+            key: key.ok_or_else(|| missing("SharedAccessKey"))?,
+            entity_path,
+        })
+    }
+}
+
+/// Connects to the namespace, authenticating with the shared access key
+pub(crate) async fn connect(
+    connection_string: &ConnectionString,
+    container_id: &str,
+) -> Result<Client> {
+    let host = connection_string.host.as_str();
+    let stream = TcpStream::connect((host, PORT)).await?;
+    let stream = TlsConnector::default().connect(host, stream).await?;
+    Connection::open(
+        stream,
+        host,
+        &connection_string.key_name,
+        &connection_string.key,
+        container_id,
+    )
+    .await
+}
+
+/// The address of a partition for a consumer group
+pub(crate) fn partition_address(hub: &str, consumer_group: &str, partition: &str) -> String {
+    format!(
+        "{}/ConsumerGroups/{}/Partitions/{}",
+        hub, consumer_group, partition
+    )
+}
+
+/// The filter of a receiver starting after, or with if `inclusive`, the
+/// event with the sequence number
+pub(crate) fn from_sequence_number(sequence_number: i64, inclusive: bool) -> (Amqp, Amqp) {
+    selector(&format!(
+        "amqp.annotation.x-opt-sequence-number {} '{}'",
+        if inclusive { ">=" } else { ">" },
+        sequence_number
+    ))
+}
+
+/// The filter of a receiver starting after the offset, `-1` for the first
+/// event retained and `@latest` for the events still to arrive
+pub(crate) fn from_offset(offset: &str) -> (Amqp, Amqp) {
+    selector(&format!("amqp.annotation.x-opt-offset > '{}'", offset))
+}
+
+fn selector(expression: &str) -> (Amqp, Amqp) {
+    (
+        Amqp::symbol(SELECTOR_FILTER),
+        Amqp::Described(
+            Box::new(Amqp::symbol(SELECTOR_FILTER)),
+            Box::new(Amqp::string(expression)),
+        ),
+    )
+}
+
+/// Waits for an event matching `f`, other events are dropped
+async fn wait_for<T, F>(client: &mut Client, timeout: Duration, mut f: F) -> Result<T>
+where
+    F: FnMut(Event) -> Option<T>,
+{
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        let event = async_std::future::timeout(remaining, client.next())
+            .await
+            .map_err(|_| Error::from("Timeout waiting for Event Hubs"))??;
+        if let Event::Detached { error, .. } = &event {
+            return Err(format!(
+                "Event Hubs detached a link: {}",
+                error.as_deref().unwrap_or_default()
+            )
+            .into());
+        }
+        if let Some(result) = f(event) {
+            return Ok(result);
+        }
+    }
+}
+
+/// Waits until the sending link has credit
+pub(crate) async fn wait_for_credit(
+    client: &mut Client,
+    handle: u32,
+    timeout: Duration,
+) -> Result<()> {
+    if client.credit(handle) > 0 {
+        return Ok(());
+    }
+    wait_for(client, timeout, |event| match event {
+        Event::Credit { handle: h } if h == handle => Some(()),
+        _ => None,
+    })
+    .await
+}
+
+/// Reads the partition ids of the event hub from the management node
+pub(crate) async fn partition_ids(
+    client: &mut Client,
+    hub: &str,
+    timeout: Duration,
+) -> Result<Vec<String>> {
+    let reply_to = "tremor-management";
+    let receiver = client
+        .attach_receiver(reply_to, MANAGEMENT, reply_to, None, 1)
+        .await?;
+    let sender = client
+        .attach_sender("tremor-management-sender", MANAGEMENT)
+        .await?;
+    wait_for_credit(client, sender, timeout).await?;
+    let request = amqp::encode_message(
+        &[],
+        vec![],
+        // message-id, user-id, to, subject and reply-to
+        Some(Amqp::List(vec![
+            Amqp::string("partitions"),
+            Amqp::Null,
+            Amqp::Null,
+            Amqp::Null,
+            Amqp::string(reply_to),
+        ])),
+        vec![
+            (Amqp::string("operation"), Amqp::string("READ")),
+            (Amqp::string("name"), Amqp::string(hub)),
+            (Amqp::string("type"), Amqp::string("com.microsoft:eventhub")),
+        ],
+    );
+    client.send(sender, &request).await?;
+    let response = wait_for(client, timeout, |event| match event {
+        Event::Message { handle, message } if handle == receiver => Some(message),
+        _ => None,
+    })
+    .await?;
+    client.detach(sender).await?;
+    client.detach(receiver).await?;
+
+    let status = response
+        .properties
+        .get("status-code")
+        .and_then(Amqp::as_u64);
+    if status != Some(200) {
+        return Err(format!(
+            "Reading the partitions of {} failed with {:?}: {}",
+            hub,
+            status,
+            response
+                .properties
+                .get("status-description")
+                .and_then(Amqp::as_str)
+                .unwrap_or_default()
+        )
+        .into());
+    }
+    response
+        .value
+        .get("partition_ids")
+        .and_then(Amqp::as_list)
+        .map(|ids| {
+            ids.iter()
+                .filter_map(Amqp::as_str)
+                .map(ToString::to_string)
+                .collect()
+        })
+        .ok_or_else(|| Error::from("Event Hubs returned no partition ids"))
+}
+
+/// The sequence number of a received event
+pub(crate) fn sequence_number(message: &Message) -> Option<i64> {
+    message
+        .annotation("x-opt-sequence-number")
+        .and_then(Amqp::as_i64)
+}
+
+/// The `$eventhubs` metadata of a received event
+pub(crate) fn message_meta(partition: &str, message: &Message) -> Value<'static> {
+    let annotation = |key: &str| {
+        message
+            .annotation(key)
+            .map_or_else(Value::null, amqp::to_value)
+    };
+    let mut meta = Value::object_with_capacity(6);
+    meta.try_insert("partition", partition.to_string());
+    meta.try_insert("offset", annotation("x-opt-offset"));
+    meta.try_insert("sequence_number", annotation("x-opt-sequence-number"));
+    meta.try_insert(
+        "enqueued_time",
+        message
+            .annotation("x-opt-enqueued-time")
+            .and_then(Amqp::as_i64)
+            .map_or_else(Value::null, |ms| Value::from(ms.saturating_mul(1_000_000))),
+    );
+    meta.try_insert("partition_key", annotation(PARTITION_KEY));
+    meta.try_insert("properties", amqp::to_value(&message.properties));
+    meta
+}
+
+/// Encodes an event with the partition key and properties of its
+/// `$eventhubs` metadata
+pub(crate) fn message(body: &[u8], meta: Option<&Value>) -> Vec<u8> {
+    let annotations = meta
+        .get_str("partition_key")
+        .map(|key| vec![(Amqp::symbol(PARTITION_KEY), Amqp::string(key))])
+        .unwrap_or_default();
+    let properties = meta
+        .get_object("properties")
+        .map(|properties| {
+            properties
+                .iter()
+                .map(|(k, v)| (Amqp::string(k), amqp::from_value(v)))
+                .collect()
+        })
+        .unwrap_or_default();
+    amqp::encode_message(body, annotations, None, properties)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn connection_string() -> Result<()> {
+        let parsed = ConnectionString::parse(
+            "Endpoint=sb://snot.servicebus.windows.net/;SharedAccessKeyName=RootManageSharedAccessKey;SharedAccessKey=a2V5=;EntityPath=badger",
+        )?;
+        assert_eq!(
+            ConnectionString {
+                host: "snot.servicebus.windows.net".to_string(),
+                key_name: "RootManageSharedAccessKey".to_string(),
+                key: "a2V5=".to_string(),
+                entity_path: Some("badger".to_string()),
+            },
+            parsed
+        );
+        assert!(ConnectionString::parse("Endpoint=sb://snot/;SharedAccessKeyName=k").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn meta() -> Result<()> {
+        let meta = tremor_value::literal!({
+            "partition_key": "badger",
+            "properties": {"snot": 1}
+        });
+        let encoded = message(b"data", Some(&meta));
+        let mut received = Message::decode(&encoded)?;
+        received.annotations = Amqp::Map(vec![
+            (Amqp::symbol("x-opt-sequence-number"), Amqp::Long(7)),
+            (Amqp::symbol("x-opt-offset"), Amqp::string("4096")),
+            (Amqp::symbol("x-opt-enqueued-time"), Amqp::Timestamp(1000)),
+            (Amqp::symbol(PARTITION_KEY), Amqp::string("badger")),
+        ]);
+        assert_eq!(Some(7), sequence_number(&received));
+        assert_eq!(
+            tremor_value::literal!({
+                "partition": "0",
+                "offset": "4096",
+                "sequence_number": 7,
+                "enqueued_time": 1_000_000_000,
+                "partition_key": "badger",
+                "properties": {"snot": 1}
+            }),
+            message_meta("0", &received)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            selector("amqp.annotation.x-opt-sequence-number >= '3'"),
+            from_sequence_number(3, true)
+        );
+        assert_eq!(
+            selector("amqp.annotation.x-opt-offset > '@latest'"),
+            from_offset("@latest")
+        );
+    }
+}
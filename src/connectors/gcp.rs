@@ -13,4 +13,7 @@
 // limitations under the License.
 
 pub(crate) mod auth;
+pub(crate) mod bigquery;
+pub(crate) mod logging;
+pub(crate) mod pubsub;
 pub(crate) mod storage;
@@ -12,35 +12,195 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::errors::Result;
-/// Using `GOOGLE_APPLICATION_CREDENTIALS="<path to service token json file>"` this function
-/// will authenticate against the google cloud platform using the authentication flow defined
-/// in the file. The provided PEM file should be a current non-revoked and complete copy of the
-/// required certificate chain for the Google Cloud Platform.
+//! Authentication against the Google Cloud Platform
+//!
+//! Credentials are looked up in order from:
+//!
+//! * the service account or authorized user file referenced by
+//!   `GOOGLE_APPLICATION_CREDENTIALS` or the gcloud default credentials file
+//! * the metadata server, providing the workload identity of pods on GKE
+//!   and the service account of GCE instances
+//!
+//! Tokens are refreshed before they expire and requests rejected with `401`
+//! are retried once with a fresh token.
+
+use crate::errors::{Error, Result};
+use async_std::sync::Mutex;
+use async_std::task;
 use gouth::Token;
-use reqwest::header::HeaderMap;
-use reqwest::Client;
-
-pub(crate) async fn authenticate_bearer() -> Result<String> {
-    Ok(Token::new()?.header_value()?.to_string())
-}
-
-pub(crate) async fn json_api_client(extra_headers: &HeaderMap) -> Result<Client> {
-    let bearer = authenticate_bearer().await?;
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        "authorization",
-        reqwest::header::HeaderValue::from_maybe_shared(bearer)?,
-    );
-    headers.insert(
-        "content-type",
-        reqwest::header::HeaderValue::from_static("application/json"),
-    );
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tremor_value::prelude::*;
+
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+/// tokens are refreshed this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+enum Source {
+    /// refreshes tokens on its own
+    File(Arc<Token>),
+    Metadata,
+}
+
+struct CachedToken {
+    header: String,
+    expires: Instant,
+}
+
+/// Provides bearer tokens from the first available credential source
+pub(crate) struct TokenProvider {
+    source: Source,
+    cached: Mutex<Option<CachedToken>>,
+}
+
+impl TokenProvider {
+    pub(crate) async fn new() -> Result<Self> {
+        let file_error = match task::spawn_blocking(Token::new).await {
+            Ok(token) => {
+                return Ok(Self {
+                    source: Source::File(Arc::new(token)),
+                    cached: Mutex::new(None),
+                })
+            }
+            Err(e) => e,
+        };
+        let metadata = Self {
+            source: Source::Metadata,
+            cached: Mutex::new(None),
+        };
+        match metadata.header_value().await {
+            Ok(_) => Ok(metadata),
+            Err(e) => Err(format!(
+                "No Google Cloud credentials found, credentials file: {}, metadata server: {}",
+                file_error, e
+            )
+            .into()),
+        }
+    }
+
+    /// The `authorization` header value for the current token
+    pub(crate) async fn header_value(&self) -> Result<String> {
+        match &self.source {
+            Source::File(token) => {
+                let token = token.clone();
+                task::spawn_blocking(move || {
+                    token
+                        .header_value()
+                        .map(|v| v.to_string())
+                        .map_err(Error::from)
+                })
+                .await
+            }
+            Source::Metadata => {
+                let mut cached = self.cached.lock().await;
+                match &*cached {
+                    Some(token) if token.expires > Instant::now() + EXPIRY_MARGIN => {
+                        Ok(token.header.clone())
+                    }
+                    _ => {
+                        let token = fetch_metadata_token().await?;
+                        let header = token.header.clone();
+                        *cached = Some(token);
+                        Ok(header)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drops the cached token so the next request fetches a fresh one
+    pub(crate) async fn invalidate(&self) {
+        *self.cached.lock().await = None;
+    }
+}
+
+async fn fetch_metadata_token() -> Result<CachedToken> {
+    let requested = Instant::now();
+    let response = Client::new()
+        .get(METADATA_TOKEN_URL)
+        .header("Metadata-Flavor", "Google")
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(format!("metadata server returned {}", response.status()).into());
+    }
+    let mut body = response.bytes().await?.to_vec();
+    parse_metadata_token(&mut body, requested)
+}
+
+fn parse_metadata_token(body: &mut [u8], requested: Instant) -> Result<CachedToken> {
+    let token = tremor_value::parse_to_value(body)?;
+    let access_token = token
+        .get_str("access_token")
+        .ok_or("metadata server response is missing the `access_token`")?;
+    let expires_in = token.get_u64("expires_in").unwrap_or_default();
+    Ok(CachedToken {
+        header: format!("Bearer {}", access_token),
+        expires: requested + Duration::from_secs(expires_in),
+    })
+}
+
+/// A client for the Google JSON APIs authenticating each request
+pub(crate) struct AuthClient {
+    client: Client,
+    tokens: TokenProvider,
+}
+
+impl AuthClient {
+    /// Sends the request built by `request`, retrying it once with a fresh
+    /// token if it is rejected as unauthorized
+    pub(crate) async fn send<F>(&self, request: F) -> Result<Response>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let bearer = self.tokens.header_value().await?;
+        let response = request(&self.client)
+            .header(AUTHORIZATION, HeaderValue::from_str(&bearer)?)
+            .send()
+            .await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            self.tokens.invalidate().await;
+            let bearer = self.tokens.header_value().await?;
+            Ok(request(&self.client)
+                .header(AUTHORIZATION, HeaderValue::from_str(&bearer)?)
+                .send()
+                .await?)
+        } else {
+            Ok(response)
+        }
+    }
+}
+
+pub(crate) async fn json_api_client(extra_headers: &HeaderMap) -> Result<AuthClient> {
+    let tokens = TokenProvider::new().await?;
+    let mut headers = HeaderMap::new();
+    headers.insert("content-type", HeaderValue::from_static("application/json"));
     for header in extra_headers {
         headers.append(header.0, header.1.clone());
     }
 
-    Ok(reqwest::Client::builder()
-        .default_headers(headers)
-        .build()?)
+    let client = Client::builder().default_headers(headers).build()?;
+    Ok(AuthClient { client, tokens })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn metadata_token() -> Result<()> {
+        let requested = Instant::now();
+        let mut body =
+            br#"{"access_token":"snot","expires_in":3599,"token_type":"Bearer"}"#.to_vec();
+        let token = parse_metadata_token(&mut body, requested)?;
+        assert_eq!("Bearer snot", token.header);
+        assert_eq!(requested + Duration::from_secs(3599), token.expires);
+
+        let mut body = br#"{"error":"badger"}"#.to_vec();
+        assert!(parse_metadata_token(&mut body, requested).is_err());
+        Ok(())
+    }
 }
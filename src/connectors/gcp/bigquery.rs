@@ -0,0 +1,603 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `BigQuery` Storage Write API
+//!
+//! Rows are appended to a `COMMITTED` write stream as protobuf messages,
+//! described by a descriptor built from the configured columns. Every append
+//! carries the stream offset of its first row, so an append that is retried
+//! after it already succeeded is answered with `ALREADY_EXISTS` instead of
+//! writing the rows twice.
+
+use super::auth::TokenProvider;
+use crate::codec::protobuf::{
+    write_key, write_len, write_varint, Fields, Wire, FIXED64, LEN, VARINT,
+};
+use crate::errors::{Error, Result};
+use crate::sink::grpc::RawCodec;
+use http::uri::PathAndQuery;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic::{Code, Status};
+use tremor_value::prelude::*;
+
+const CREATE_WRITE_STREAM: &str =
+    "/google.cloud.bigquery.storage.v1.BigQueryWrite/CreateWriteStream";
+const APPEND_ROWS: &str = "/google.cloud.bigquery.storage.v1.BigQueryWrite/AppendRows";
+/// name of the row message in the descriptor
+const ROW_MESSAGE: &str = "tremor_row";
+/// `WriteStream.Type.COMMITTED`
+const COMMITTED: u64 = 1;
+
+/// A column of the destination table and the event field it is filled from
+#[derive(Debug, Clone, Deserialize)]
+pub struct Column {
+    /// name of the column
+    pub name: String,
+    /// `BigQuery` type of the column
+    #[serde(rename = "type")]
+    pub kind: ColumnType,
+    /// `NULLABLE`, `REQUIRED` or `REPEATED`
+    #[serde(default)]
+    pub mode: Mode,
+    /// dotted path of the event field, defaults to the column name
+    #[serde(default)]
+    pub field: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ColumnType {
+    String,
+    #[serde(alias = "INTEGER")]
+    Int64,
+    #[serde(alias = "FLOAT")]
+    Float64,
+    #[serde(alias = "BOOLEAN")]
+    Bool,
+    Bytes,
+    /// nanoseconds since epoch, written as microseconds
+    Timestamp,
+    /// numbers or their string representation
+    Numeric,
+    /// any value, written as its JSON encoding
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Mode {
+    Nullable,
+    Required,
+    Repeated,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Mode::Nullable
+    }
+}
+
+impl ColumnType {
+    /// `FieldDescriptorProto.Type` used to send values of this type
+    fn proto_type(self) -> u64 {
+        match self {
+            ColumnType::Float64 => 1,
+            ColumnType::Int64 | ColumnType::Timestamp => 3,
+            ColumnType::Bool => 8,
+            ColumnType::String | ColumnType::Numeric | ColumnType::Json => 9,
+            ColumnType::Bytes => 12,
+        }
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    fn encode(self, buf: &mut Vec<u8>, number: u32, value: &Value) -> Option<()> {
+        match self {
+            ColumnType::String => {
+                write_key(buf, number, LEN);
+                write_len(buf, value.as_str()?.as_bytes());
+            }
+            ColumnType::Int64 => {
+                let n = value.as_i64()?;
+                write_key(buf, number, VARINT);
+                write_varint(buf, n as u64);
+            }
+            ColumnType::Float64 => {
+                let f = value.cast_f64()?;
+                write_key(buf, number, FIXED64);
+                buf.extend_from_slice(&f.to_le_bytes());
+            }
+            ColumnType::Bool => {
+                let b = value.as_bool()?;
+                write_key(buf, number, VARINT);
+                write_varint(buf, u64::from(b));
+            }
+            ColumnType::Bytes => {
+                let bytes = value
+                    .as_bytes()
+                    .or_else(|| value.as_str().map(str::as_bytes))?;
+                write_key(buf, number, LEN);
+                write_len(buf, bytes);
+            }
+            ColumnType::Timestamp => {
+                let micros = value.as_i64()? / 1000;
+                write_key(buf, number, VARINT);
+                write_varint(buf, micros as u64);
+            }
+            ColumnType::Numeric => {
+                let n = if value.is_number() {
+                    value.encode()
+                } else {
+                    value.as_str()?.to_string()
+                };
+                write_key(buf, number, LEN);
+                write_len(buf, n.as_bytes());
+            }
+            ColumnType::Json => {
+                write_key(buf, number, LEN);
+                write_len(buf, value.encode().as_bytes());
+            }
+        }
+        Some(())
+    }
+}
+
+impl Mode {
+    /// `FieldDescriptorProto.Label`, required columns are validated when
+    /// encoding rows
+    fn label(self) -> u64 {
+        match self {
+            Mode::Nullable | Mode::Required => 1,
+            Mode::Repeated => 3,
+        }
+    }
+}
+
+impl Column {
+    fn lookup<'value, 'event>(
+        &self,
+        event: &'value Value<'event>,
+    ) -> Option<&'value Value<'event>> {
+        self.field
+            .as_deref()
+            .unwrap_or(&self.name)
+            .split('.')
+            .try_fold(event, |value, key| value.get(key))
+            .filter(|value| !value.is_null())
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>, number: u32, value: &Value) -> Result<()> {
+        self.kind.encode(buf, number, value).ok_or_else(|| {
+            Error::from(format!(
+                "Column `{}` of type {:?} can't hold `{}`",
+                self.name,
+                self.kind,
+                value.encode()
+            ))
+        })
+    }
+}
+
+/// Encodes the `DescriptorProto` of the row message, fields are numbered in
+/// column order
+pub(crate) fn descriptor(columns: &[Column]) -> Vec<u8> {
+    let mut descriptor = Vec::new();
+    write_key(&mut descriptor, 1, LEN);
+    write_len(&mut descriptor, ROW_MESSAGE.as_bytes());
+    for (number, column) in (1_u64..).zip(columns) {
+        let mut field = Vec::new();
+        write_key(&mut field, 1, LEN);
+        write_len(&mut field, column.name.as_bytes());
+        write_key(&mut field, 3, VARINT);
+        write_varint(&mut field, number);
+        write_key(&mut field, 4, VARINT);
+        write_varint(&mut field, column.mode.label());
+        write_key(&mut field, 5, VARINT);
+        write_varint(&mut field, column.kind.proto_type());
+        write_key(&mut descriptor, 2, LEN);
+        write_len(&mut descriptor, &field);
+    }
+    descriptor
+}
+
+/// Encodes the row for `event`
+///
+/// # Errors
+///   * if a required column is missing or a value doesn't match its column
+pub(crate) fn encode_row(columns: &[Column], event: &Value) -> Result<Vec<u8>> {
+    let mut row = Vec::new();
+    for (number, column) in (1_u32..).zip(columns) {
+        match (column.lookup(event), column.mode) {
+            (None, Mode::Required) => {
+                return Err(format!("Missing value for required column `{}`", column.name).into())
+            }
+            (None, _) => (),
+            (Some(values), Mode::Repeated) => {
+                let values = values.as_array().ok_or_else(|| {
+                    Error::from(format!(
+                        "Column `{}` is repeated and requires an array",
+                        column.name
+                    ))
+                })?;
+                for value in values {
+                    column.encode(&mut row, number, value)?;
+                }
+            }
+            (Some(value), _) => column.encode(&mut row, number, value)?,
+        }
+    }
+    Ok(row)
+}
+
+fn create_write_stream_request(table: &str) -> Vec<u8> {
+    let mut stream = Vec::new();
+    write_key(&mut stream, 2, VARINT);
+    write_varint(&mut stream, COMMITTED);
+    let mut request = Vec::new();
+    write_key(&mut request, 1, LEN);
+    write_len(&mut request, table.as_bytes());
+    write_key(&mut request, 2, LEN);
+    write_len(&mut request, &stream);
+    request
+}
+
+fn append_rows_request(stream: &str, offset: u64, descriptor: &[u8], rows: &[Vec<u8>]) -> Vec<u8> {
+    let mut schema = Vec::new();
+    write_key(&mut schema, 1, LEN);
+    write_len(&mut schema, descriptor);
+    let mut proto_rows = Vec::new();
+    for row in rows {
+        write_key(&mut proto_rows, 1, LEN);
+        write_len(&mut proto_rows, row);
+    }
+    let mut data = Vec::new();
+    write_key(&mut data, 1, LEN);
+    write_len(&mut data, &schema);
+    write_key(&mut data, 2, LEN);
+    write_len(&mut data, &proto_rows);
+    let mut int64_value = Vec::new();
+    write_key(&mut int64_value, 1, VARINT);
+    write_varint(&mut int64_value, offset);
+
+    let mut request = Vec::new();
+    write_key(&mut request, 1, LEN);
+    write_len(&mut request, stream.as_bytes());
+    write_key(&mut request, 2, LEN);
+    write_len(&mut request, &int64_value);
+    write_key(&mut request, 4, LEN);
+    write_len(&mut request, &data);
+    request
+}
+
+fn parse_write_stream(data: &[u8]) -> Result<String> {
+    for field in Fields::new(data) {
+        if let (1, Wire::Len(name)) = field? {
+            return Ok(String::from_utf8_lossy(name).to_string());
+        }
+    }
+    Err("BigQuery returned a write stream without a name".into())
+}
+
+/// Outcome of an append
+#[derive(Debug, PartialEq)]
+pub(crate) enum Append {
+    /// the rows were appended
+    Ok,
+    /// rows were already appended at this offset
+    AlreadyExists,
+    /// failed, might succeed when retried
+    Retry(String),
+    /// rejected, e.g. rows that don't match the table schema
+    Rejected(String),
+    /// the offset doesn't match the stream, a new stream is required
+    Reset(String),
+}
+
+impl Append {
+    fn from_code(code: Code, message: String) -> Self {
+        match code {
+            Code::Ok => Append::Ok,
+            Code::AlreadyExists => Append::AlreadyExists,
+            Code::OutOfRange => Append::Reset(message),
+            Code::InvalidArgument => Append::Rejected(message),
+            _ => Append::Retry(message),
+        }
+    }
+}
+
+fn parse_append_response(data: &[u8]) -> Result<Append> {
+    let mut code = 0;
+    let mut message = String::new();
+    let mut row_errors = Vec::new();
+    for field in Fields::new(data) {
+        match field? {
+            (2, Wire::Len(status)) => {
+                for field in Fields::new(status) {
+                    match field? {
+                        (1, Wire::Varint(c)) => code = c,
+                        (2, Wire::Len(m)) => message = String::from_utf8_lossy(m).to_string(),
+                        _ => (),
+                    }
+                }
+            }
+            (4, Wire::Len(row_error)) => {
+                let mut index = 0;
+                let mut message = String::new();
+                for field in Fields::new(row_error) {
+                    match field? {
+                        (1, Wire::Varint(i)) => index = i,
+                        (3, Wire::Len(m)) => message = String::from_utf8_lossy(m).to_string(),
+                        _ => (),
+                    }
+                }
+                row_errors.push(format!("row {}: {}", index, message));
+            }
+            _ => (),
+        }
+    }
+    if !row_errors.is_empty() {
+        return Ok(Append::Rejected(row_errors.join(", ")));
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let code = Code::from_i32(code as i32);
+    Ok(Append::from_code(code, message))
+}
+
+/// Client for the `BigQueryWrite` service
+pub(crate) struct Client {
+    grpc: tonic::client::Grpc<Channel>,
+    tokens: TokenProvider,
+    timeout: Duration,
+}
+
+impl Client {
+    pub(crate) async fn connect(endpoint: &str, timeout: Duration) -> Result<Self> {
+        let mut channel = Endpoint::from_shared(endpoint.to_string())
+            .map_err(|e| format!("Invalid BigQuery endpoint {}: {}", endpoint, e))?
+            .timeout(timeout);
+        if endpoint.starts_with("https://") {
+            channel = channel
+                .tls_config(ClientTlsConfig::new())
+                .map_err(|e| format!("Invalid TLS config for {}: {}", endpoint, e))?;
+        }
+        let channel = channel
+            .connect()
+            .await
+            .map_err(|e| format!("Unable to connect to {}: {}", endpoint, e))?;
+        Ok(Self {
+            grpc: tonic::client::Grpc::new(channel),
+            tokens: TokenProvider::new().await?,
+            timeout,
+        })
+    }
+
+    async fn request<T>(
+        &self,
+        message: T,
+        routing: &str,
+    ) -> std::result::Result<tonic::Request<T>, Status> {
+        let mut request = tonic::Request::new(message);
+        let md = request.metadata_mut();
+        let token = self
+            .tokens
+            .header_value()
+            .await
+            .map_err(|e| Status::unauthenticated(e.to_string()))?;
+        md.insert(
+            "authorization",
+            AsciiMetadataValue::from_str(&token)
+                .map_err(|e| Status::unauthenticated(e.to_string()))?,
+        );
+        // routes the call to the region of the table
+        md.insert(
+            "x-goog-request-params",
+            AsciiMetadataValue::from_str(routing)
+                .map_err(|e| Status::invalid_argument(e.to_string()))?,
+        );
+        Ok(request)
+    }
+
+    async fn try_call(
+        &mut self,
+        path: &PathAndQuery,
+        routing: &str,
+        payload: Vec<u8>,
+    ) -> std::result::Result<Vec<u8>, Status> {
+        self.grpc
+            .ready()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        if path.as_str() == APPEND_ROWS {
+            // `AppendRows` is a bidirectional stream, we send one request per
+            // call and wait for its response
+            let request = self
+                .request(futures::stream::iter(vec![payload]), routing)
+                .await?;
+            self.grpc
+                .streaming(request, path.clone(), RawCodec)
+                .await?
+                .into_inner()
+                .message()
+                .await?
+                .ok_or_else(|| Status::unavailable("AppendRows stream closed"))
+        } else {
+            let request = self.request(payload, routing).await?;
+            Ok(self
+                .grpc
+                .unary(request, path.clone(), RawCodec)
+                .await?
+                .into_inner())
+        }
+    }
+
+    /// Issues the call and retries it once with a fresh token if it was
+    /// rejected as unauthenticated
+    async fn call(
+        &mut self,
+        method: &'static str,
+        routing: &str,
+        payload: Vec<u8>,
+    ) -> std::result::Result<Vec<u8>, Status> {
+        let path = PathAndQuery::from_static(method);
+        let mut refreshed = false;
+        loop {
+            let timeout = self.timeout;
+            let res =
+                async_std::future::timeout(timeout, self.try_call(&path, routing, payload.clone()))
+                    .await
+                    .unwrap_or_else(|_| Err(Status::deadline_exceeded("deadline exceeded")));
+            match res {
+                Err(status) if status.code() == Code::Unauthenticated && !refreshed => {
+                    self.tokens.invalidate().await;
+                    refreshed = true;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Creates a `COMMITTED` write stream on `table`, given as
+    /// `projects/{project}/datasets/{dataset}/tables/{table}`
+    pub(crate) async fn create_stream(&mut self, table: &str) -> Result<String> {
+        let routing = format!("parent={}", table);
+        let response = self
+            .call(
+                CREATE_WRITE_STREAM,
+                &routing,
+                create_write_stream_request(table),
+            )
+            .await
+            .map_err(|status| format!("Unable to create BigQuery write stream: {}", status))?;
+        parse_write_stream(&response)
+    }
+
+    /// Appends `rows` at `offset` of `stream`
+    pub(crate) async fn append(
+        &mut self,
+        stream: &str,
+        offset: u64,
+        descriptor: &[u8],
+        rows: &[Vec<u8>],
+    ) -> Append {
+        let routing = format!("write_stream={}", stream);
+        let request = append_rows_request(stream, offset, descriptor, rows);
+        match self.call(APPEND_ROWS, &routing, request).await {
+            Ok(response) => parse_append_response(&response)
+                .unwrap_or_else(|e| Append::Retry(format!("Invalid AppendRows response: {}", e))),
+            Err(status) => Append::from_code(status.code(), status.message().to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    fn columns() -> Vec<Column> {
+        serde_yaml::from_str(
+            r#"
+- name: host
+  type: STRING
+  mode: REQUIRED
+- name: count
+  type: INTEGER
+- name: tags
+  type: STRING
+  mode: REPEATED
+- name: user_id
+  type: INT64
+  field: user.id
+"#,
+        )
+        .expect("valid columns")
+    }
+
+    #[test]
+    fn encode_row() -> Result<()> {
+        let columns = columns();
+        let row = super::encode_row(
+            &columns,
+            &literal!({"host": "a", "count": -1, "tags": ["x"], "user": {"id": 2}, "snot": 1}),
+        )?;
+        let mut expected = vec![0x0a, 1, b'a', 0x10];
+        expected.extend_from_slice(&[0xff; 9]);
+        expected.extend_from_slice(&[0x01, 0x1a, 1, b'x', 0x20, 2]);
+        assert_eq!(expected, row);
+
+        let row = super::encode_row(&columns, &literal!({"host": "a", "count": null}))?;
+        assert_eq!(vec![0x0a, 1, b'a'], row);
+        assert!(super::encode_row(&columns, &literal!({"count": 1})).is_err());
+        assert!(super::encode_row(&columns, &literal!({"host": "a", "count": "1"})).is_err());
+        assert!(super::encode_row(&columns, &literal!({"host": "a", "tags": "x"})).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn descriptor() -> Result<()> {
+        let descriptor = super::descriptor(&columns());
+        let mut fields = Vec::new();
+        for field in Fields::new(&descriptor) {
+            if let (2, Wire::Len(field)) = field? {
+                let mut name = String::new();
+                let mut props = Vec::new();
+                for field in Fields::new(field) {
+                    match field? {
+                        (1, Wire::Len(n)) => name = String::from_utf8_lossy(n).to_string(),
+                        (_, Wire::Varint(v)) => props.push(v),
+                        _ => (),
+                    }
+                }
+                fields.push((name, props));
+            }
+        }
+        assert_eq!(
+            vec![
+                ("host".to_string(), vec![1, 1, 9]),
+                ("count".to_string(), vec![2, 1, 3]),
+                ("tags".to_string(), vec![3, 3, 9]),
+                ("user_id".to_string(), vec![4, 1, 3]),
+            ],
+            fields
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn append_response() -> Result<()> {
+        // append_result with an offset
+        assert_eq!(
+            Append::Ok,
+            parse_append_response(&[0x0a, 4, 0x0a, 2, 0x08, 5])?
+        );
+        // error status ALREADY_EXISTS
+        assert_eq!(
+            Append::AlreadyExists,
+            parse_append_response(&[0x12, 2, 0x08, 6])?
+        );
+        // error status OUT_OF_RANGE
+        assert_eq!(
+            Append::Reset("x".to_string()),
+            parse_append_response(&[0x12, 5, 0x08, 11, 0x12, 1, b'x'])?
+        );
+        // row error for row 1
+        assert_eq!(
+            Append::Rejected("row 1: bad".to_string()),
+            parse_append_response(&[0x22, 9, 0x08, 1, 0x10, 1, 0x1a, 3, b'b', b'a', b'd'])?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Google Cloud Logging (formerly Stackdriver) API
+
+use super::auth::AuthClient;
+use crate::errors::Result;
+use tremor_value::prelude::*;
+
+const WRITE_URL: &str = "https://logging.googleapis.com/v2/entries:write";
+
+/// Writes the entries of an `entries.write` request `body`
+///
+/// # Errors
+///   * if the request fails or is rejected
+pub(crate) async fn write_entries(client: &AuthClient, body: &Value<'_>) -> Result<()> {
+    let body = body.encode();
+    let response = client
+        .send(|c| c.post(WRITE_URL).body(body.clone()))
+        .await?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        let text = response.text().await.unwrap_or_default();
+        Err(format!("Cloud Logging returned {}: {}", status, text).into())
+    }
+}
+
+/// Maps a severity name or syslog severity number to a `LogSeverity`
+pub(crate) fn severity(value: &Value) -> &'static str {
+    if let Some(n) = value.as_u64() {
+        return match n {
+            0 => "EMERGENCY",
+            1 => "ALERT",
+            2 => "CRITICAL",
+            3 => "ERROR",
+            4 => "WARNING",
+            5 => "NOTICE",
+            6 => "INFO",
+            7 => "DEBUG",
+            _ => "DEFAULT",
+        };
+    }
+    match value.as_str().map(str::to_lowercase).as_deref() {
+        Some("emergency") | Some("emerg") | Some("panic") => "EMERGENCY",
+        Some("alert") => "ALERT",
+        Some("critical") | Some("crit") | Some("fatal") => "CRITICAL",
+        Some("error") | Some("err") => "ERROR",
+        Some("warning") | Some("warn") => "WARNING",
+        Some("notice") => "NOTICE",
+        Some("info") | Some("informational") => "INFO",
+        Some("debug") | Some("trace") => "DEBUG",
+        _ => "DEFAULT",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    #[test]
+    fn severity() {
+        assert_eq!("ERROR", super::severity(&literal!("Error")));
+        assert_eq!("WARNING", super::severity(&literal!("warn")));
+        assert_eq!("NOTICE", super::severity(&literal!(5)));
+        assert_eq!("DEFAULT", super::severity(&literal!(42)));
+        assert_eq!("DEFAULT", super::severity(&literal!("snot")));
+        assert_eq!("DEFAULT", super::severity(&Value::null()));
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cloud Pub/Sub gRPC API
+
+use super::auth::TokenProvider;
+use crate::errors::{Error, Result};
+pub(crate) use googapis::google::pubsub::v1::{
+    publisher_client::PublisherClient, subscriber_client::SubscriberClient, PublishRequest,
+    PubsubMessage, ReceivedMessage, StreamingPullRequest, StreamingPullResponse,
+};
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic::Request;
+use tremor_value::prelude::*;
+
+const ENDPOINT: &str = "https://pubsub.googleapis.com";
+
+/// ack ids per acknowledgement or deadline modification, keeping requests
+/// below their size limit
+pub(crate) const MAX_ACK_IDS: usize = 2500;
+/// messages per publish request
+pub(crate) const MAX_PUBLISH_MESSAGES: usize = 1000;
+/// bounds of ack deadlines in seconds
+pub(crate) const MIN_ACK_DEADLINE: u64 = 10;
+pub(crate) const MAX_ACK_DEADLINE: u64 = 600;
+
+/// A channel to Pub/Sub, authenticated unless it is a plain text one to the
+/// emulator
+pub(crate) struct Connection {
+    channel: Channel,
+    tokens: Option<TokenProvider>,
+}
+
+impl Connection {
+    /// Connects to `endpoint`, `https://pubsub.googleapis.com` by default
+    pub(crate) async fn new(endpoint: Option<&str>) -> Result<Self> {
+        let endpoint = endpoint.unwrap_or(ENDPOINT);
+        let url = url::Url::parse(endpoint)?;
+        let mut builder = Endpoint::from_shared(endpoint.to_string())
+            .map_err(|e| Error::from(format!("Invalid Pub/Sub endpoint {}: {}", endpoint, e)))?;
+        let tls = url.scheme() == "https";
+        if tls {
+            let domain = url.host_str().unwrap_or_default().to_string();
+            builder = builder
+                .tls_config(ClientTlsConfig::new().domain_name(domain))
+                .map_err(|e| Error::from(format!("Invalid TLS config: {}", e)))?;
+        }
+        let channel = builder
+            .connect()
+            .await
+            .map_err(|e| Error::from(format!("Unable to connect to {}: {}", endpoint, e)))?;
+        let tokens = if tls {
+            Some(TokenProvider::new().await?)
+        } else {
+            None
+        };
+        Ok(Self { channel, tokens })
+    }
+
+    pub(crate) fn subscriber(&self) -> SubscriberClient<Channel> {
+        SubscriberClient::new(self.channel.clone())
+    }
+
+    pub(crate) fn publisher(&self) -> PublisherClient<Channel> {
+        PublisherClient::new(self.channel.clone())
+    }
+
+    /// A request carrying the current token
+    pub(crate) async fn request<T>(&self, message: T) -> Result<Request<T>> {
+        let mut request = Request::new(message);
+        if let Some(tokens) = &self.tokens {
+            let bearer = tokens.header_value().await?;
+            let value = AsciiMetadataValue::from_str(&bearer)
+                .map_err(|e| Error::from(format!("Invalid token: {}", e)))?;
+            request.metadata_mut().insert("authorization", value);
+        }
+        Ok(request)
+    }
+}
+
+/// The `$pubsub` metadata of a received message
+pub(crate) fn message_meta(received: &ReceivedMessage) -> Value<'static> {
+    let mut meta = Value::object_with_capacity(5);
+    if let Some(message) = &received.message {
+        let mut attributes = Value::object_with_capacity(message.attributes.len());
+        for (k, v) in &message.attributes {
+            attributes.try_insert(k.clone(), v.clone());
+        }
+        meta.try_insert("message_id", message.message_id.clone());
+        meta.try_insert("attributes", attributes);
+        if !message.ordering_key.is_empty() {
+            meta.try_insert("ordering_key", message.ordering_key.clone());
+        }
+        if let Some(time) = &message.publish_time {
+            // nanoseconds since epoch
+            #[allow(clippy::cast_sign_loss)]
+            let ns = time.seconds.max(0) as u64 * 1_000_000_000 + time.nanos.max(0) as u64;
+            meta.try_insert("publish_time", ns);
+        }
+    }
+    // only set for subscriptions with a dead letter policy
+    if received.delivery_attempt > 0 {
+        meta.try_insert("delivery_attempt", received.delivery_attempt);
+    }
+    meta
+}
+
+/// A message to publish with the `$pubsub` metadata `attributes` and
+/// `ordering_key`
+pub(crate) fn message(data: Vec<u8>, meta: Option<&Value>) -> PubsubMessage {
+    let attributes = meta
+        .get_object("attributes")
+        .map(|attributes| {
+            attributes
+                .iter()
+                .map(|(k, v)| {
+                    let v = v.as_str().map_or_else(|| v.encode(), ToString::to_string);
+                    (k.to_string(), v)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    PubsubMessage {
+        data,
+        attributes,
+        ordering_key: meta
+            .get_str("ordering_key")
+            .map(ToString::to_string)
+            .unwrap_or_default(),
+        ..PubsubMessage::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    #[test]
+    fn received_meta() {
+        let mut message = PubsubMessage {
+            data: b"snot".to_vec(),
+            message_id: "42".to_string(),
+            ordering_key: "badger".to_string(),
+            publish_time: Some(Default::default()),
+            ..PubsubMessage::default()
+        };
+        if let Some(time) = message.publish_time.as_mut() {
+            time.seconds = 1;
+            time.nanos = 2;
+        }
+        message
+            .attributes
+            .insert("snot".to_string(), "badger".to_string());
+        let received = ReceivedMessage {
+            ack_id: "ack".to_string(),
+            message: Some(message),
+            delivery_attempt: 0,
+        };
+        assert_eq!(
+            literal!({
+                "message_id": "42",
+                "attributes": {"snot": "badger"},
+                "ordering_key": "badger",
+                "publish_time": 1_000_000_002
+            }),
+            message_meta(&received)
+        );
+    }
+
+    #[test]
+    fn published_message() {
+        let meta = literal!({
+            "attributes": {"snot": "badger", "count": 1},
+            "ordering_key": "key"
+        });
+        let message = message(b"data".to_vec(), Some(&meta));
+        assert_eq!(b"data".to_vec(), message.data);
+        assert_eq!("key", message.ordering_key);
+        assert_eq!(Some(&"badger".to_string()), message.attributes.get("snot"));
+        assert_eq!(Some(&"1".to_string()), message.attributes.get("count"));
+
+        let message = super::message(vec![], None);
+        assert!(message.attributes.is_empty());
+        assert!(message.ordering_key.is_empty());
+    }
+}
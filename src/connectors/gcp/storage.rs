@@ -12,13 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::auth::AuthClient;
 use crate::errors::Result;
-use reqwest::Client;
 use std::collections::HashMap;
 use tremor_value::Value;
 
 pub(crate) async fn get_object(
-    client: &Client,
+    client: &AuthClient,
     bucket_name: &str,
     object_name: &str,
 ) -> Result<Value<'static>> {
@@ -26,34 +26,49 @@ pub(crate) async fn get_object(
         "{}/b/{}/o/{}",
         "https://storage.googleapis.com/storage/v1", bucket_name, object_name
     );
-    let mut body = client.get(url).send().await?.text().await?.into_bytes();
+    let mut body = client
+        .send(|c| c.get(&url))
+        .await?
+        .text()
+        .await?
+        .into_bytes();
     let body = tremor_value::parse_to_value(&mut body)?.into_static();
     Ok(body)
 }
 
-pub(crate) async fn list_buckets(client: &Client, project_id: &str) -> Result<Value<'static>> {
+pub(crate) async fn list_buckets(client: &AuthClient, project_id: &str) -> Result<Value<'static>> {
     let url = format!(
         "https://storage.googleapis.com/storage/v1/b?project={}",
         project_id
     );
-    let mut body = client.get(url).send().await?.text().await?.into_bytes();
+    let mut body = client
+        .send(|c| c.get(&url))
+        .await?
+        .text()
+        .await?
+        .into_bytes();
     let body = tremor_value::parse_to_value(&mut body)?.into_static();
     Ok(body)
 }
 
-pub(crate) async fn list_objects(client: &Client, bucket_name: &str) -> Result<Value<'static>> {
+pub(crate) async fn list_objects(client: &AuthClient, bucket_name: &str) -> Result<Value<'static>> {
     let url = format!(
         "{}/b/{}/o",
         "https://storage.googleapis.com/storage/v1",
         bucket_name.to_string()
     );
-    let mut body = client.get(url).send().await?.text().await?.into_bytes();
+    let mut body = client
+        .send(|c| c.get(&url))
+        .await?
+        .text()
+        .await?
+        .into_bytes();
     let body = tremor_value::parse_to_value(&mut body)?.into_static();
     Ok(body)
 }
 
 pub(crate) async fn add_object_with_slice(
-    client: &Client,
+    client: &AuthClient,
     bucket_name: &str,
     object_name: &str,
     content: Vec<u8>,
@@ -63,9 +78,7 @@ pub(crate) async fn add_object_with_slice(
         bucket_name, object_name
     );
     let mut body = client
-        .post(url)
-        .body(content)
-        .send()
+        .send(|c| c.post(&url).body(content.clone()))
         .await?
         .text()
         .await?
@@ -75,7 +88,7 @@ pub(crate) async fn add_object_with_slice(
 }
 
 pub(crate) async fn delete_object(
-    client: &Client,
+    client: &AuthClient,
     bucket_name: &str,
     object_name: &str,
 ) -> Result<Value<'static>> {
@@ -83,13 +96,18 @@ pub(crate) async fn delete_object(
         "https://storage.googleapis.com/storage/v1/b/{}/o/{}",
         bucket_name, object_name
     );
-    let mut body = client.delete(url).send().await?.text().await?.into_bytes();
+    let mut body = client
+        .send(|c| c.delete(&url))
+        .await?
+        .text()
+        .await?
+        .into_bytes();
     let body = tremor_value::parse_to_value(&mut body)?.into_static();
     Ok(body)
 }
 
 pub(crate) async fn download_object<'event>(
-    client: &Client,
+    client: &AuthClient,
     bucket_name: &str,
     object_name: &str,
 ) -> Result<Vec<u8>> {
@@ -97,14 +115,14 @@ pub(crate) async fn download_object<'event>(
         "{}/b/{}/o/{}?alt=media",
         "https://storage.googleapis.com/storage/v1", bucket_name, object_name
     );
-    let response = client.get(url).send().await?;
+    let response = client.send(|c| c.get(&url)).await?;
     let body = response;
     let bytes = body.bytes().await?;
     Ok(bytes.to_vec())
 }
 
 pub(crate) async fn create_bucket(
-    client: &Client,
+    client: &AuthClient,
     project_id: &str,
     bucket_name: &str,
 ) -> Result<Value<'static>> {
@@ -115,9 +133,7 @@ pub(crate) async fn create_bucket(
     let mut map = HashMap::new();
     map.insert("name", bucket_name);
     let mut body = client
-        .post(url)
-        .json(&map)
-        .send()
+        .send(|c| c.post(&url).json(&map))
         .await?
         .text()
         .await?
@@ -126,12 +142,20 @@ pub(crate) async fn create_bucket(
     Ok(body)
 }
 
-pub(crate) async fn delete_bucket(client: &Client, bucket_name: &str) -> Result<Value<'static>> {
+pub(crate) async fn delete_bucket(
+    client: &AuthClient,
+    bucket_name: &str,
+) -> Result<Value<'static>> {
     let url = format!(
         "https://storage.googleapis.com/storage/v1/b/{}",
         bucket_name
     );
-    let mut body = client.delete(url).send().await?.text().await?.into_bytes();
+    let mut body = client
+        .send(|c| c.delete(&url))
+        .await?
+        .text()
+        .await?
+        .into_bytes();
     let body = tremor_value::parse_to_value(&mut body)?.into_static();
     Ok(body)
 }
@@ -0,0 +1,264 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reconnect policy shared by connectors
+//!
+//! A connector that lost its connection retries it after `initial_delay`
+//! milliseconds, the delay is multiplied by `multiplier` after every failed
+//! attempt up to `max_delay`. Each delay is randomly shortened or extended by
+//! up to `jitter` of it, so connectors don't all reconnect at once. After
+//! `max_attempts` failed attempts in a row the connector is marked as failed
+//! and stops trying, without `max_attempts` it tries forever.
+//!
+//! ```yaml
+//! reconnect:
+//!   initial_delay: 1000
+//!   multiplier: 2.0
+//!   max_delay: 60000
+//!   jitter: 0.1
+//!   max_attempts: 10
+//! ```
+//!
+//! The state is reported as `connection` in the instance stats.
+
+use crate::metrics::{ConnectionState, ConnectionStatus, InstanceStats};
+use async_std::task;
+use rand::Rng;
+use std::sync::Arc;
+use std::time::Duration;
+use tremor_common::time::nanotime;
+
+/// Reconnect configuration, the `reconnect` section of connector configs
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct Config {
+    /// milliseconds before the first reconnect attempt
+    #[serde(default = "d_initial_delay")]
+    pub initial_delay: u64,
+    /// factor the delay grows by after each failed attempt
+    #[serde(default = "d_multiplier")]
+    pub multiplier: f64,
+    /// upper bound for the delay in milliseconds
+    #[serde(default = "d_max_delay")]
+    pub max_delay: u64,
+    /// fraction of the delay it is randomly shortened or extended by
+    #[serde(default = "d_jitter")]
+    pub jitter: f64,
+    /// failed attempts in a row after which the connector gives up
+    #[serde(default)]
+    pub max_attempts: Option<u64>,
+}
+
+fn d_initial_delay() -> u64 {
+    1000
+}
+
+fn d_multiplier() -> f64 {
+    2.0
+}
+
+fn d_max_delay() -> u64 {
+    60_000
+}
+
+fn d_jitter() -> f64 {
+    0.1
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            initial_delay: d_initial_delay(),
+            multiplier: d_multiplier(),
+            max_delay: d_max_delay(),
+            jitter: d_jitter(),
+            max_attempts: None,
+        }
+    }
+}
+
+/// Tracks the reconnect attempts of a connector
+pub(crate) struct Reconnect {
+    config: Config,
+    state: ConnectionState,
+    /// failed attempts since the last successful connect
+    attempts: u64,
+    /// delay before the next attempt, before jitter
+    delay_ms: u64,
+    next_attempt_ns: u64,
+    stats: Option<Arc<InstanceStats>>,
+}
+
+impl Reconnect {
+    pub(crate) fn new(config: Config) -> Self {
+        let delay_ms = config.initial_delay;
+        Self {
+            config,
+            state: ConnectionState::Connected,
+            attempts: 0,
+            delay_ms,
+            next_attempt_ns: 0,
+            stats: None,
+        }
+    }
+
+    /// reports the state to the given instance stats from now on
+    pub(crate) fn report_to(&mut self, stats: Option<Arc<InstanceStats>>) {
+        self.stats = stats;
+    }
+
+    /// records a successful connect
+    pub(crate) fn connected(&mut self) {
+        self.state = ConnectionState::Connected;
+        self.attempts = 0;
+        self.delay_ms = self.config.initial_delay;
+        self.report(None);
+    }
+
+    /// records a failed connect, returning the milliseconds until the next
+    /// attempt or `None` if the connector gave up
+    pub(crate) fn failed(&mut self, now_ns: u64, error: &str) -> Option<u64> {
+        self.attempts += 1;
+        if self
+            .config
+            .max_attempts
+            .map_or(false, |max| self.attempts >= max)
+        {
+            self.state = ConnectionState::Failed;
+            self.report(Some(error));
+            return None;
+        }
+        let delay_ms = self.jittered(now_ns);
+        self.state = ConnectionState::Reconnecting;
+        self.next_attempt_ns = now_ns + delay_ms * 1_000_000;
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss
+        )]
+        let next = (self.delay_ms as f64 * self.config.multiplier) as u64;
+        self.delay_ms = next
+            .min(self.config.max_delay)
+            .max(self.config.initial_delay);
+        self.report(Some(error));
+        Some(delay_ms)
+    }
+
+    /// records a failed connect and waits for the next attempt, returning
+    /// `false` if the connector gave up
+    pub(crate) async fn wait(&mut self, error: &str) -> bool {
+        if let Some(delay_ms) = self.failed(nanotime(), error) {
+            task::sleep(Duration::from_millis(delay_ms)).await;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// whether a reconnect attempt is due
+    pub(crate) fn due(&self, now_ns: u64) -> bool {
+        self.state == ConnectionState::Reconnecting && now_ns >= self.next_attempt_ns
+    }
+
+    pub(crate) fn is_failed(&self) -> bool {
+        self.state == ConnectionState::Failed
+    }
+
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn jittered(&self, seed: u64) -> u64 {
+        let jitter = self.config.jitter.max(0.0).min(1.0);
+        if jitter <= f64::EPSILON {
+            return self.delay_ms;
+        }
+        let factor = tremor_common::rand::make_prng(seed).gen_range(-jitter..=jitter);
+        (self.delay_ms as f64 * (1.0 + factor)) as u64
+    }
+
+    fn report(&self, error: Option<&str>) {
+        if let Some(stats) = &self.stats {
+            stats.set_connection(ConnectionStatus {
+                state: self.state,
+                attempts: self.attempts,
+                next_attempt_ns: if self.state == ConnectionState::Reconnecting {
+                    Some(self.next_attempt_ns)
+                } else {
+                    None
+                },
+                last_error: error.map(ToString::to_string),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(max_attempts: Option<u64>, jitter: f64) -> Config {
+        Config {
+            initial_delay: 100,
+            multiplier: 2.0,
+            max_delay: 350,
+            jitter,
+            max_attempts,
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let stats = Arc::new(InstanceStats::default());
+        let mut reconnect = Reconnect::new(config(Some(5), 0.0));
+        reconnect.report_to(Some(stats.clone()));
+        assert!(!reconnect.due(0));
+        assert_eq!(Some(100), reconnect.failed(0, "badger"));
+        assert!(!reconnect.due(99_999_999));
+        assert!(reconnect.due(100_000_000));
+        assert_eq!(Some(200), reconnect.failed(0, "badger"));
+        assert_eq!(Some(350), reconnect.failed(0, "badger"));
+        assert_eq!(Some(350), reconnect.failed(0, "badger"));
+        let status = stats.snapshot().connection.expect("a connection status");
+        assert_eq!(ConnectionState::Reconnecting, status.state);
+        assert_eq!(4, status.attempts);
+        assert_eq!(Some("badger".to_string()), status.last_error);
+
+        // gives up after max attempts
+        assert_eq!(None, reconnect.failed(0, "snot"));
+        assert!(reconnect.is_failed());
+        assert!(!reconnect.due(u64::MAX));
+        let status = stats.snapshot().connection.expect("a connection status");
+        assert_eq!(ConnectionState::Failed, status.state);
+        assert_eq!(None, status.next_attempt_ns);
+
+        reconnect.connected();
+        assert_eq!(Some(0), stats.snapshot().connection.map(|c| c.attempts));
+        assert_eq!(Some(100), reconnect.failed(0, "badger"));
+        assert_eq!(
+            Some(ConnectionState::Reconnecting),
+            stats.snapshot().connection.map(|c| c.state)
+        );
+    }
+
+    #[test]
+    fn jitter() {
+        let mut reconnect = Reconnect::new(config(None, 0.5));
+        for seed in 0..100 {
+            let delay = reconnect.failed(seed, "badger").unwrap_or_default();
+            assert!(delay >= 50 && delay <= 525, "{}", delay);
+        }
+        assert!(!reconnect.is_failed());
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TLS shared by connectors
+//!
+//! The `tls` section of a listening connector config names the PEM encoded
+//! certificate chain `cert` and private key `key` (PKCS8 or RSA) the
+//! connector presents. With `client_ca` clients have to authenticate with a
+//! certificate signed by one of the PEM encoded certificates in that file.
+//!
+//! ```yaml
+//! tls:
+//!   cert: /etc/tremor/tls/server.pem
+//!   key: /etc/tremor/tls/server.key
+//!   client_ca: /etc/tremor/tls/clients.pem
+//! ```
+//!
+//! Connecting connectors verify the server certificate against the `ca` in
+//! their `tls` section, or the webpki roots if it isn't set, for `domain`,
+//! which defaults to the host connected to.
+//!
+//! ```yaml
+//! tls:
+//!   ca: /etc/tremor/tls/ca.pem
+//!   domain: logs.example.com
+//! ```
+
+use crate::errors::{Error, Result};
+use async_tls::{TlsAcceptor, TlsConnector};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{
+    AllowAnyAuthenticatedClient, Certificate, ClientConfig as RustlsClientConfig, NoClientAuth,
+    PrivateKey, RootCertStore, ServerConfig,
+};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+/// TLS configuration, the `tls` section of listening connector configs
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct Config {
+    /// PEM file with the certificate chain presented to clients
+    pub cert: String,
+    /// PEM file with the private key of the certificate
+    pub key: String,
+    /// PEM file with the CAs client certificates have to be signed by
+    #[serde(default)]
+    pub client_ca: Option<String>,
+}
+
+/// TLS configuration, the `tls` section of connecting connector configs
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
+pub(crate) struct ClientConfig {
+    /// PEM file with the CAs the server certificate has to be signed by
+    #[serde(default)]
+    pub ca: Option<String>,
+    /// name the server certificate has to be valid for
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
+fn open(path: &str) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| Error::from(format!("Unable to open {}: {}", path, e)))
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>> {
+    let certs = certs(&mut open(path)?)
+        .map_err(|_| Error::from(format!("Invalid certificates in {}", path)))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path).into());
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKey> {
+    let invalid = |_| Error::from(format!("Invalid private key in {}", path));
+    let mut keys = pkcs8_private_keys(&mut open(path)?).map_err(invalid)?;
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(path)?).map_err(invalid)?;
+    }
+    keys.into_iter()
+        .next()
+        .ok_or_else(|| format!("No private key found in {}", path).into())
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(&cert)
+            .map_err(|e| Error::from(format!("Invalid CA in {}: {}", path, e)))?;
+    }
+    Ok(roots)
+}
+
+/// builds an acceptor terminating TLS on accepted connections
+pub(crate) fn acceptor(config: &Config) -> Result<TlsAcceptor> {
+    let verifier = if let Some(client_ca) = &config.client_ca {
+        AllowAnyAuthenticatedClient::new(load_roots(client_ca)?)
+    } else {
+        NoClientAuth::new()
+    };
+    let mut server_config = ServerConfig::new(verifier);
+    server_config
+        .set_single_cert(load_certs(&config.cert)?, load_key(&config.key)?)
+        .map_err(|e| Error::from(format!("Invalid TLS certificate or key: {}", e)))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+/// builds a connector establishing TLS on connections
+pub(crate) fn connector(config: &ClientConfig) -> Result<TlsConnector> {
+    if let Some(ca) = &config.ca {
+        let mut client_config = RustlsClientConfig::new();
+        client_config.root_store = load_roots(ca)?;
+        Ok(TlsConnector::from(Arc::new(client_config)))
+    } else {
+        Ok(TlsConnector::default())
+    }
+}
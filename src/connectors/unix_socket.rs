@@ -0,0 +1,91 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Unix domain sockets shared by the `unix-socket` onramp and offramp
+
+use crate::errors::Result;
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+/// The type of a unix domain socket
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SocketType {
+    /// `SOCK_STREAM`, connections carrying a stream of bytes
+    Stream,
+    /// `SOCK_DGRAM`, connectionless datagrams
+    Datagram,
+}
+
+impl Default for SocketType {
+    fn default() -> Self {
+        Self::Stream
+    }
+}
+
+/// Removes a socket left behind at `path` by a previous listener, so it can
+/// be bound again, other files are left in place and fail the bind
+pub(crate) fn remove_stale(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => Ok(fs::remove_file(path)?),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Sets the permissions of a bound socket to the octal `mode`, like `0660`
+pub(crate) fn set_mode(path: &Path, mode: &str) -> Result<()> {
+    let mode = u32::from_str_radix(mode, 8)
+        .map_err(|e| format!("Invalid socket permissions `{}`: {}", mode, e))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn socket_type() -> Result<()> {
+        assert_eq!(SocketType::Stream, SocketType::default());
+        let t: SocketType = serde_yaml::from_str("datagram")?;
+        assert_eq!(SocketType::Datagram, t);
+        assert!(serde_yaml::from_str::<SocketType>("seqpacket").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stale() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let socket = dir.path().join("tremor.sock");
+        remove_stale(&socket)?;
+
+        let bound = UnixDatagram::bind(&socket)?;
+        drop(bound);
+        set_mode(&socket, "0660")?;
+        assert_eq!(0o660, fs::metadata(&socket)?.permissions().mode() & 0o777);
+        assert!(set_mode(&socket, "rw").is_err());
+        remove_stale(&socket)?;
+        assert!(!socket.exists());
+
+        let file = dir.path().join("tremor.log");
+        fs::write(&file, "snot")?;
+        remove_stale(&file)?;
+        assert!(file.exists());
+        Ok(())
+    }
+}
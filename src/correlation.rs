@@ -0,0 +1,213 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Correlation id propagation
+//!
+//! Onramps configured with a `correlation` section extract a correlation id
+//! from the first matching location and store it as `$correlation_id` in the
+//! event metadata, where it is available to scripts and queries.
+//!
+//! Offramps sending requests with headers or metadata (rest, grpc, kafka,
+//! nats) inject `$correlation_id` as `x-correlation-id` unless the event
+//! already carries that header explicitly.
+//!
+//! ```yaml
+//! correlation:
+//!   from:
+//!     - header: x-request-id
+//!     - field: trace.id
+//!     - kafka_key
+//! ```
+
+use tremor_pipeline::Event;
+use tremor_script::prelude::*;
+use tremor_script::{Value, ValueAndMeta};
+
+/// metadata key the correlation id is stored under
+pub const META_KEY: &str = "correlation_id";
+/// header (or metadata) name used for outbound requests
+pub const HEADER: &str = "x-correlation-id";
+
+/// A location to extract a correlation id from
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Location {
+    /// a request header, as provided in `$request.headers` by the rest and ws onramps
+    Header(String),
+    /// a dot separated path into the event data
+    Field(String),
+    /// a dot separated path into the event metadata
+    Meta(String),
+    /// the message key of the kafka onramp (`$kafka.key`)
+    KafkaKey,
+}
+
+/// Correlation id extraction configuration
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// locations to look for a correlation id, the first one present wins
+    pub from: Vec<Location>,
+}
+
+fn get_path<'value, 'event>(
+    mut value: &'value Value<'event>,
+    path: &str,
+) -> Option<&'value Value<'event>> {
+    for segment in path.split('.') {
+        value = value.get(segment)?;
+    }
+    Some(value)
+}
+
+fn as_id(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.to_string()),
+        Value::Bytes(b) => std::str::from_utf8(b).ok().map(ToString::to_string),
+        Value::Static(_) if !value.is_null() => Some(value.encode()),
+        // headers can carry multiple values, we pick the first one
+        Value::Array(a) => a.first().and_then(as_id),
+        _ => None,
+    }
+}
+
+impl Location {
+    pub(crate) fn extract(&self, data: &Value, meta: &Value) -> Option<String> {
+        match self {
+            Self::Header(name) => meta
+                .get("request")
+                .and_then(|r| r.get_object("headers"))
+                .and_then(|headers| {
+                    headers
+                        .iter()
+                        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                        .map(|(_, v)| v)
+                })
+                .and_then(as_id),
+            Self::Field(path) => get_path(data, path).and_then(as_id),
+            Self::Meta(path) => get_path(meta, path).and_then(as_id),
+            Self::KafkaKey => meta.get("kafka").and_then(|k| k.get("key")).and_then(as_id),
+        }
+    }
+}
+
+impl Config {
+    /// Extracts the correlation id from the first matching location and
+    /// stores it in `$correlation_id`, an existing `$correlation_id` is kept
+    pub fn apply(&self, event: &mut ValueAndMeta) {
+        if event.meta().get(META_KEY).is_some() {
+            return;
+        }
+        let id = self
+            .from
+            .iter()
+            .find_map(|l| l.extract(event.value(), event.meta()));
+        if let Some(id) = id {
+            let meta = event.meta_mut();
+            if !meta.is_object() {
+                *meta = Value::object();
+            }
+            if let Some(meta) = meta.as_object_mut() {
+                meta.insert(META_KEY.into(), Value::from(id));
+            }
+        }
+    }
+}
+
+/// The correlation id of the (first element of the) event, if any
+#[must_use]
+pub fn event_id(event: &Event) -> Option<String> {
+    event
+        .value_meta_iter()
+        .find_map(|(_, meta)| meta.get(META_KEY).and_then(as_id))
+}
+
+/// The correlation id of a single event element, if any
+#[must_use]
+pub fn meta_id<'value>(meta: &'value Value) -> Option<&'value str> {
+    meta.get_str(META_KEY)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    fn apply(config: &Config, data: Value<'static>, meta: Value<'static>) -> Option<String> {
+        let mut vm = ValueAndMeta::from_parts(data, meta);
+        config.apply(&mut vm);
+        vm.meta().get_str(META_KEY).map(ToString::to_string)
+    }
+
+    #[test]
+    fn locations() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+from:
+  - header: X-Request-Id
+  - field: trace.id
+  - kafka_key
+"#,
+        )
+        .expect("valid config");
+        assert_eq!(
+            config.from,
+            vec![
+                Location::Header("X-Request-Id".to_string()),
+                Location::Field("trace.id".to_string()),
+                Location::KafkaKey
+            ]
+        );
+
+        let meta = literal!({"request": {"headers": {"x-request-id": ["abc", "def"]}}});
+        let data = literal!({"trace": {"id": "from-field"}});
+        assert_eq!(apply(&config, data, meta), Some("abc".to_string()));
+
+        let data = literal!({"trace": {"id": 42}});
+        assert_eq!(
+            apply(&config, data, Value::object()),
+            Some("42".to_string())
+        );
+
+        let mut meta = Value::object();
+        let mut kafka = Value::object();
+        kafka
+            .insert("key", Value::Bytes(b"snot".to_vec().into()))
+            .expect("object");
+        meta.insert("kafka", kafka).expect("object");
+        assert_eq!(
+            apply(&config, Value::object(), meta),
+            Some("snot".to_string())
+        );
+
+        assert_eq!(apply(&config, literal!("badger"), Value::object()), None);
+    }
+
+    #[test]
+    fn keeps_existing() {
+        let config = Config {
+            from: vec![Location::Meta("id".to_string())],
+        };
+        let meta = literal!({"correlation_id": "existing", "id": "other"});
+        assert_eq!(
+            apply(&config, Value::object(), meta),
+            Some("existing".to_string())
+        );
+        let meta = literal!({"id": "other"});
+        assert_eq!(
+            apply(&config, Value::object(), meta),
+            Some("other".to_string())
+        );
+    }
+}
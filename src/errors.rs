@@ -157,12 +157,15 @@ error_chain! {
         JsonAccessError(value_trait::AccessError);
         CronError(cron::error::Error);
         Postgres(postgres::Error);
+        MySql(mysql_async::Error);
         Common(tremor_common::Error);
         Sled(sled::Error);
         DnsError(async_std_resolver::ResolveError);
         GoogleAuthError(gouth::Error);
         ReqwestError(reqwest::Error);
         HttpHeaderError(http::header::InvalidHeaderValue);
+        AmqpError(lapin::Error);
+        RedisError(redis::RedisError);
     }
 
     errors {
@@ -191,6 +194,10 @@ error_chain! {
             description("The artefact is a system artefact and cannot be unpublished")
                 display("Cannot unpublish system artefact {}.", key)
         }
+        UnpublishFailedReferenced(key: String, referrers: Vec<String>) {
+            description("The artefact is referenced by bindings and cannot be unpublished")
+                display("Cannot unpublish artefact {} which is referenced by {}.", key, referrers.join(", "))
+        }
 
         BindFailedAlreadyExists(key: String) {
             description("The binding already exists")
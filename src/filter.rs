@@ -0,0 +1,138 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Binding filters
+//!
+//! Several pipelines can be bound to the same onramp instance, each of them
+//! receives every event of the onramp. A binding can restrict the events an
+//! onramp dispatches to one of its pipelines with a filter, so one consumer
+//! can feed several specialized pipelines. Filters are evaluated in the
+//! onramp before the event is sent, events not matching the filter of a
+//! pipeline are not sent to it.
+//!
+//! A filter is a list of conditions which all have to match. A condition
+//! extracts a value from one of the locations known from `correlation` and
+//! checks it against `equals`, `one_of` and `prefix`, a condition without
+//! checks only requires the value to be present.
+//!
+//! ```yaml
+//! binding:
+//!   - id: orders
+//!     links:
+//!       '/onramp/kafka/{instance}/out':
+//!         - '/pipeline/orders/{instance}/in'
+//!         - '/pipeline/audit/{instance}/in'
+//!     filters:
+//!       '/pipeline/orders/{instance}/in':
+//!         - at:
+//!             field: type
+//!           one_of: [order, refund]
+//!         - at: kafka_key
+//!           prefix: eu-
+//! ```
+
+use crate::correlation::Location;
+use tremor_script::prelude::*;
+
+/// A single condition of a filter
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// location of the value to check
+    pub at: Location,
+    /// the value has to be equal to this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+    /// the value has to be one of these
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub one_of: Option<Vec<String>>,
+    /// the value has to start with this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+}
+
+impl Condition {
+    fn matches(&self, data: &Value, meta: &Value) -> bool {
+        self.at.extract(data, meta).map_or(false, |v| {
+            self.equals.as_ref().map_or(true, |e| e == &v)
+                && self.one_of.as_ref().map_or(true, |o| o.contains(&v))
+                && self.prefix.as_ref().map_or(true, |p| v.starts_with(p))
+        })
+    }
+}
+
+/// Conditions an event has to match to be sent to a pipeline
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+#[serde(transparent)]
+pub struct Filter {
+    /// all of them have to match
+    pub conditions: Vec<Condition>,
+}
+
+impl Filter {
+    /// Whether the event data and metadata match all conditions
+    #[must_use]
+    pub fn matches(&self, data: &Value, meta: &Value) -> bool {
+        self.conditions.iter().all(|c| c.matches(data, meta))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    #[test]
+    fn conditions() {
+        let filter: Filter = serde_yaml::from_str(
+            r#"
+- at:
+    field: type
+  one_of: [order, refund]
+- at:
+    meta: kafka.topic
+  prefix: eu-
+"#,
+        )
+        .expect("valid filter");
+        assert_eq!(2, filter.conditions.len());
+
+        let meta = literal!({"kafka": {"topic": "eu-orders"}});
+        assert!(filter.matches(&literal!({"type": "order"}), &meta));
+        assert!(filter.matches(&literal!({"type": "refund"}), &meta));
+        assert!(!filter.matches(&literal!({"type": "audit"}), &meta));
+        assert!(!filter.matches(&literal!({"snot": "badger"}), &meta));
+        let meta = literal!({"kafka": {"topic": "us-orders"}});
+        assert!(!filter.matches(&literal!({"type": "order"}), &meta));
+    }
+
+    #[test]
+    fn exists_and_equals() {
+        let filter: Filter = serde_yaml::from_str(
+            r#"
+- at:
+    header: x-tenant
+- at:
+    field: version
+  equals: "2"
+"#,
+        )
+        .expect("valid filter");
+        let meta = literal!({"request": {"headers": {"X-Tenant": ["snot"]}}});
+        assert!(filter.matches(&literal!({"version": 2}), &meta));
+        assert!(!filter.matches(&literal!({"version": 1}), &meta));
+        assert!(!filter.matches(&literal!({"version": 2}), &Value::object()));
+        assert!(Filter::default().matches(&Value::null(), &Value::null()));
+    }
+}
@@ -13,11 +13,32 @@
 // limitations under the License.
 
 use crate::errors::Result;
+use crate::utils::hostname;
 use crate::version::VERSION;
+use hashbrown::HashSet;
+use std::sync::RwLock;
 use tremor_pipeline::FN_REGISTRY;
 use tremor_script::registry::Registry;
 use tremor_script::tremor_fn;
 
+lazy_static! {
+    static ref HOSTNAME: String = hostname();
+    /// environment variables scripts are allowed to read
+    static ref ALLOWED_ENV: RwLock<HashSet<String>> = RwLock::new(HashSet::new());
+}
+
+/// Allows scripts to read the environment variables `names` via `system::env`
+///
+/// # Errors
+///  * if the allow list is poisoned
+pub fn allow_env<I>(names: I) -> Result<()>
+where
+    I: IntoIterator<Item = String>,
+{
+    ALLOWED_ENV.write()?.extend(names);
+    Ok(())
+}
+
 /// Loads the function library
 ///
 /// # Errors
@@ -39,7 +60,45 @@ pub fn install(reg: &mut Registry) -> Result<()> {
     }))
     .insert(tremor_fn!(system|version(_context) {
         Ok(Value::String(VERSION.into()).into_static())
+    }))
+    .insert(tremor_fn!(system|hostname(_context) {
+        Ok(Value::from(HOSTNAME.as_str()))
+    }))
+    .insert(tremor_fn!(system|env(_context, _name: String) {
+        let allowed = ALLOWED_ENV.read().map_err(to_runtime_error)?;
+        if allowed.contains(_name) {
+            Ok(std::env::var(_name).map_or_else(|_| Value::null(), Value::from))
+        } else {
+            Err(to_runtime_error(format!("Environment variable `{}` is not allowed by the config", _name)))
+        }
     }));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_script::{registry, EventContext, Value};
+
+    #[test]
+    fn env() -> Result<()> {
+        let mut reg = registry();
+        install(&mut reg)?;
+        let env = reg.find("system", "env").expect("system::env is installed");
+        let ctx = EventContext::new(0, None);
+
+        std::env::set_var("TREMOR_FUNCTIONS_TEST_ENV", "snot");
+        let name = Value::from("TREMOR_FUNCTIONS_TEST_ENV");
+        assert!(env.invoke(&ctx, &[&name]).is_err());
+
+        allow_env(vec![
+            "TREMOR_FUNCTIONS_TEST_ENV".to_string(),
+            "TREMOR_FUNCTIONS_TEST_UNSET".to_string(),
+        ])?;
+        assert_eq!(Ok(Value::from("snot")), env.invoke(&ctx, &[&name]));
+        let unset = Value::from("TREMOR_FUNCTIONS_TEST_UNSET");
+        assert_eq!(Ok(Value::null()), env.invoke(&ctx, &[&unset]));
+        Ok(())
+    }
+}
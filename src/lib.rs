@@ -39,13 +39,19 @@ extern crate rental;
 
 #[macro_use]
 pub(crate) mod macros;
+/// Local event archive with a queryable index
+pub mod archive;
 pub(crate) mod async_sink;
 /// Tremor codecs
 pub mod codec;
 /// Tremor runtime configuration
 pub mod config;
+/// Correlation id propagation
+pub mod correlation;
 /// Tremor runtime errors
 pub mod errors;
+/// Binding filters
+pub mod filter;
 /// Tremor function library
 pub mod functions;
 pub(crate) mod lifecycle;
@@ -78,7 +84,7 @@ pub mod version;
 /// Tremor connector extensions
 pub mod connectors;
 
-use std::{io::BufReader, path::Path};
+use std::path::Path;
 
 use crate::errors::{Error, Result};
 
@@ -89,6 +95,7 @@ pub(crate) type MappingMap = config::MappingMap;
 
 pub(crate) use crate::config::{Binding, OffRamp, OnRamp};
 use crate::repository::BindingArtefact;
+use crate::source::lifecycle;
 use crate::url::TremorUrl;
 pub(crate) use serde_yaml::Value as OpConfig;
 use system::World;
@@ -107,6 +114,8 @@ pub struct IncarnatedConfig {
     pub onramps: OnRampVec,
     /// Offramps
     pub offramps: OffRampVec,
+    /// Inline pipelines
+    pub pipelines: Vec<config::Pipeline>,
     /// Bindings
     pub bindings: BindingVec,
     /// Mappings
@@ -117,13 +126,15 @@ pub struct IncarnatedConfig {
 ///
 /// # Errors
 ///  * if the pipeline can not be incarnated
-pub fn incarnate(config: config::Config) -> Result<IncarnatedConfig> {
+pub fn incarnate(mut config: config::Config) -> Result<IncarnatedConfig> {
+    config.expand_templates()?;
     let onramps = incarnate_onramps(config.onramp.clone());
     let offramps = incarnate_offramps(config.offramp.clone());
     let bindings = incarnate_links(&config.binding);
     Ok(IncarnatedConfig {
         onramps,
         offramps,
+        pipelines: config.pipeline,
         bindings,
         mappings: config.mapping,
     })
@@ -142,6 +153,45 @@ fn incarnate_links(config: &[Binding]) -> BindingVec {
     config.to_owned()
 }
 
+/// Orders the mappings so that the bindings a binding `depends_on` are linked
+/// before it, mappings of bindings without dependencies between them are
+/// ordered by their id. Dependencies on bindings not mapped here have to be
+/// linked already when the mapping is linked.
+///
+/// # Errors
+///  * if the dependencies form a cycle
+fn startup_order(
+    bindings: &[Binding],
+    mappings: MappingMap,
+) -> Result<Vec<(TremorUrl, hashbrown::HashMap<String, String>)>> {
+    let mut pending: Vec<_> = mappings.into_iter().collect();
+    pending.sort_by_key(|(id, _)| id.to_string());
+    let depends_on = |id: &TremorUrl| {
+        bindings
+            .iter()
+            .find(|b| id.artefact() == Some(b.id.as_str()))
+            .map_or(&[][..], |b| b.depends_on.as_slice())
+    };
+    let mut ordered = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        // a binding is ready once none of its dependencies is pending
+        let (ready, blocked): (Vec<_>, Vec<_>) = pending.iter().cloned().partition(|(id, _)| {
+            depends_on(id).iter().all(|dep| {
+                !pending
+                    .iter()
+                    .any(|(p, _)| p.artefact() == Some(dep.as_str()))
+            })
+        });
+        if ready.is_empty() {
+            let cycle: Vec<String> = blocked.iter().map(|(id, _)| id.to_string()).collect();
+            return Err(format!("Cyclic binding dependencies between {}", cycle.join(", ")).into());
+        }
+        ordered.extend(ready);
+        pending = blocked;
+    }
+    Ok(ordered)
+}
+
 /// Loads a tremor query file
 /// # Errors
 /// Fails if the file can not be loaded
@@ -159,12 +209,20 @@ pub async fn load_query_file(world: &World, file_name: &str) -> Result<usize> {
     file.read_to_string(&mut raw)
         .map_err(|e| Error::from(format!("Could not open file {} => {}", file_name, e)))?;
 
+    publish_query(world, &raw, file_name, &file_id).await?;
+    lifecycle::publish(lifecycle::Transition::ConfigLoaded(file_name.to_string()));
+    Ok(1)
+}
+
+/// Parses a trickle query and publishes it as a pipeline, the pipeline id
+/// is taken from the query or falls back to `default_id`
+async fn publish_query(world: &World, raw: &str, file_name: &str, default_id: &str) -> Result<()> {
     // TODO: Should ideally be const
     let aggr_reg = tremor_script::registry::aggr();
     let module_path = tremor_script::path::load();
     let query = Query::parse(
         &module_path,
-        &raw,
+        raw,
         file_name,
         vec![],
         &*FN_REGISTRY.lock()?,
@@ -174,20 +232,20 @@ pub async fn load_query_file(world: &World, file_name: &str) -> Result<usize> {
         Ok(query) => query,
         Err(e) => {
             let mut h = TermHighlighter::stderr();
-            if let Err(e) = Script::format_error_from_script(&raw, &mut h, &e) {
+            if let Err(e) = Script::format_error_from_script(raw, &mut h, &e) {
                 eprintln!("Error: {}", e);
             };
 
             return Err(format!("failed to load trickle script: {}", file_name).into());
         }
     };
-    let id = query.id().unwrap_or(&file_id);
+    let id = query.id().unwrap_or(default_id);
 
     let id = TremorUrl::parse(&format!("/pipeline/{}", id))?;
     info!("Loading {} from file {}.", id, file_name);
     world.repo.publish_pipeline(&id, false, query).await?;
 
-    Ok(1)
+    Ok(())
 }
 
 /// Loads a config yaml file
@@ -196,10 +254,10 @@ pub async fn load_query_file(world: &World, file_name: &str) -> Result<usize> {
 pub async fn load_cfg_file(world: &World, file_name: &str) -> Result<usize> {
     info!("Loading configuration from {}", file_name);
     let mut count = 0;
-    let file = tremor_common::file::open(file_name)?;
-    let buffered_reader = BufReader::new(file);
-    let config: config::Config = serde_yaml::from_reader(buffered_reader)?;
+    let config = config::load(file_name)?;
+    functions::allow_env(config.env.iter().cloned())?;
     let config = crate::incarnate(config)?;
+    let mappings = startup_order(&config.bindings, config.mappings)?;
 
     for o in config.offramps {
         let id = TremorUrl::parse(&format!("/offramp/{}", o.id))?;
@@ -214,6 +272,10 @@ pub async fn load_cfg_file(world: &World, file_name: &str) -> Result<usize> {
         world.repo.publish_onramp(&id, false, o).await?;
         count += 1;
     }
+    for p in config.pipelines {
+        publish_query(world, &p.query, file_name, &p.id).await?;
+        count += 1;
+    }
     for binding in config.bindings {
         let id = TremorUrl::parse(&format!("/binding/{}", binding.id))?;
         info!("Loading {} from file.", id);
@@ -230,10 +292,11 @@ pub async fn load_cfg_file(world: &World, file_name: &str) -> Result<usize> {
             .await?;
         count += 1;
     }
-    for (binding, mapping) in config.mappings {
+    for (binding, mapping) in mappings {
         world.link_binding(&binding, mapping).await?;
         count += 1;
     }
+    lifecycle::publish(lifecycle::Transition::ConfigLoaded(file_name.to_string()));
     Ok(count)
 }
 
@@ -251,6 +314,58 @@ mod test {
         serde_yaml::from_reader(buffered_reader).expect("Failed to read config.")
     }
 
+    #[test]
+    fn binding_startup_order() -> Result<()> {
+        let config: config::Config = serde_yaml::from_str(
+            r#"
+binding:
+  - id: ingest
+    depends_on: [enrich]
+    links: {}
+  - id: enrich
+    depends_on: [tables]
+    links: {}
+  - id: audit
+    links: {}
+mapping:
+  /binding/ingest/01: {}
+  /binding/enrich/01: {}
+  /binding/audit/01: {}
+  /binding/ingest/02: {}
+"#,
+        )?;
+        let order: Vec<String> = super::startup_order(&config.binding, config.mapping)?
+            .into_iter()
+            .map(|(id, _)| id.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "tremor://localhost/binding/audit/01",
+                "tremor://localhost/binding/enrich/01",
+                "tremor://localhost/binding/ingest/01",
+                "tremor://localhost/binding/ingest/02",
+            ],
+            order
+        );
+
+        let config: config::Config = serde_yaml::from_str(
+            r#"
+binding:
+  - id: snot
+    depends_on: [badger]
+    links: {}
+  - id: badger
+    depends_on: [snot]
+    links: {}
+mapping:
+  /binding/snot/01: {}
+  /binding/badger/01: {}
+"#,
+        )?;
+        assert!(super::startup_order(&config.binding, config.mapping).is_err());
+        Ok(())
+    }
+
     #[test]
     fn load_simple_deploys() {
         let config = slurp("tests/configs/deploy.simple.yaml");
@@ -270,4 +385,23 @@ mod test {
         assert_eq!(1, runtime.offramps.len());
         assert_eq!(2, runtime.bindings[0].links.len());
     }
+
+    #[test]
+    fn load_templated_deploys() {
+        let config = slurp("tests/configs/deploy.template.yaml");
+        let runtime = incarnate(config).expect("Failed to incarnate config");
+        assert_eq!(2, runtime.onramps.len());
+        assert_eq!(2, runtime.offramps.len());
+        assert_eq!(2, runtime.pipelines.len());
+        assert_eq!(2, runtime.bindings.len());
+        assert_eq!("acme-blaster", runtime.onramps[0].id);
+        assert_eq!("globex", runtime.pipelines[1].id);
+        assert_eq!(
+            Some(40),
+            runtime.offramps[1]
+                .config
+                .as_ref()
+                .and_then(|c| c["stop_after_secs"].as_u64())
+        );
+    }
 }
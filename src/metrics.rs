@@ -13,15 +13,281 @@
 // limitations under the License.
 
 use crate::pipeline;
+use crate::postprocessor::ByteMetrics;
+use crate::preprocessor::verify::VerifyMetrics;
 use crate::url::TremorUrl;
+use async_std::task;
 use beef::Cow;
 use halfbrown::HashMap;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tremor_common::time::nanotime;
 use tremor_pipeline::Event;
 use tremor_script::prelude::*;
 
 /// Metrics instance name
 pub static mut INSTANCE: &str = "tremor";
 
+/// Lifecycle state of a running artefact instance
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstanceState {
+    /// the instance is up and processing events
+    Running,
+    /// the instance terminated
+    Stopped,
+    /// the instance terminated with an error
+    Failed,
+}
+
+impl InstanceState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            0 => Self::Running,
+            1 => Self::Stopped,
+            _ => Self::Failed,
+        }
+    }
+}
+
+/// Runtime statistics of a single artefact instance, shared between the
+/// instance task and the world so they can be queried via the API
+#[derive(Debug)]
+pub struct InstanceStats {
+    started_ns: u64,
+    state: AtomicU8,
+    r#in: AtomicU64,
+    out: AtomicU64,
+    err: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    /// messages handled, events as well as signals, to tell progress
+    handled: AtomicU64,
+    activity: Mutex<Activity>,
+    stall: Mutex<Option<StallDiagnostics>>,
+    connection: Mutex<Option<ConnectionStatus>>,
+}
+
+/// State of the connection of a connector
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    /// the connector is connected
+    Connected,
+    /// the connector lost its connection and tries to reconnect
+    Reconnecting,
+    /// the connector gave up reconnecting
+    Failed,
+}
+
+/// Reconnect state of a connector
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConnectionStatus {
+    /// the state of the connection
+    pub state: ConnectionState,
+    /// failed reconnect attempts in a row
+    pub attempts: u64,
+    /// when the next reconnect attempt is made, in nanoseconds since epoch
+    pub next_attempt_ns: Option<u64>,
+    /// the error of the last failed attempt
+    pub last_error: Option<String>,
+}
+
+/// What a pipeline was last seen doing, kept up to date by the pipeline task
+/// so it can be reported once the task stops making progress
+#[derive(Debug, Default)]
+struct Activity {
+    blocked_on: Option<String>,
+    output_queues: BTreeMap<String, usize>,
+    last_event_ids: BTreeMap<String, String>,
+}
+
+/// Diagnostics captured when a pipeline made no progress for a while with
+/// events waiting in its input queue
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StallDiagnostics {
+    /// whether the pipeline is still stalled
+    pub ongoing: bool,
+    /// when the stall was detected, in nanoseconds since epoch
+    pub detected_ns: u64,
+    /// seconds without progress when the stall was detected
+    pub stalled_s: u64,
+    /// events and signals waiting in the input queue
+    pub input_queue: usize,
+    /// insights waiting in the contraflow queue
+    pub contraflow_queue: usize,
+    /// the output the pipeline waits to send to, if any
+    pub blocked_on: Option<String>,
+    /// messages waiting in the queues of the connected outputs
+    pub output_queues: BTreeMap<String, usize>,
+    /// the last event id seen by each operator
+    pub last_event_ids: BTreeMap<String, String>,
+}
+
+/// A point in time view of `InstanceStats`
+#[derive(Clone, Debug, Serialize)]
+pub struct InstanceStatsSnapshot {
+    /// lifecycle state
+    pub state: InstanceState,
+    /// seconds since the instance was started
+    pub uptime_s: u64,
+    /// events received
+    pub events_in: u64,
+    /// events sent on
+    pub events_out: u64,
+    /// errors encountered
+    pub errors: u64,
+    /// the most recent error, if any
+    pub last_error: Option<String>,
+    /// the most recent stall of a pipeline, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stall: Option<StallDiagnostics>,
+    /// the reconnect state of a connector, if it has a connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<ConnectionStatus>,
+}
+
+impl Default for InstanceStats {
+    fn default() -> Self {
+        Self {
+            started_ns: nanotime(),
+            state: AtomicU8::new(InstanceState::Running as u8),
+            r#in: AtomicU64::new(0),
+            out: AtomicU64::new(0),
+            err: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+            handled: AtomicU64::new(0),
+            activity: Mutex::new(Activity::default()),
+            stall: Mutex::new(None),
+            connection: Mutex::new(None),
+        }
+    }
+}
+
+impl InstanceStats {
+    pub(crate) fn increment_in(&self) {
+        self.r#in.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn increment_out(&self) {
+        self.out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_out(&self, n: usize) {
+        self.out.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn increment_err(&self) {
+        self.err.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// remembers the error as the last one, without counting it
+    pub(crate) fn set_last_error<E: ToString>(&self, e: &E) {
+        if let Ok(mut last_error) = self.last_error.lock() {
+            *last_error = Some(e.to_string());
+        }
+    }
+
+    /// counts an error and remembers it as the last one
+    pub(crate) fn record_error<E: ToString>(&self, e: &E) {
+        self.increment_err();
+        self.set_last_error(e);
+    }
+
+    /// marks the instance as terminated, as failed if it terminated with an error
+    pub(crate) fn terminated<T, E: ToString>(&self, res: &std::result::Result<T, E>) {
+        if let Err(e) = res {
+            self.set_last_error(e);
+            self.set_state(InstanceState::Failed);
+        } else {
+            self.set_state(InstanceState::Stopped);
+        }
+    }
+
+    pub(crate) fn set_state(&self, state: InstanceState) {
+        self.state.store(state as u8, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_running(&self) -> bool {
+        InstanceState::from_u8(self.state.load(Ordering::Relaxed)) == InstanceState::Running
+    }
+
+    pub(crate) fn increment_handled(&self) {
+        self.handled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn handled(&self) -> u64 {
+        self.handled.load(Ordering::Relaxed)
+    }
+
+    /// records the output the instance waits to send to
+    pub(crate) fn set_blocked_on(&self, output: Option<String>) {
+        if let Ok(mut activity) = self.activity.lock() {
+            activity.blocked_on = output;
+        }
+    }
+
+    /// records the output queue depths and the last event id of each operator
+    pub(crate) fn set_activity(
+        &self,
+        output_queues: BTreeMap<String, usize>,
+        last_event_ids: BTreeMap<String, String>,
+    ) {
+        if let Ok(mut activity) = self.activity.lock() {
+            activity.output_queues = output_queues;
+            activity.last_event_ids = last_event_ids;
+        }
+    }
+
+    /// captures the diagnostics of a stall from the last recorded activity
+    pub(crate) fn record_stall(&self, stalled_s: u64, input_queue: usize, contraflow_queue: usize) {
+        let diagnostics = self.activity.lock().ok().map(|activity| StallDiagnostics {
+            ongoing: true,
+            detected_ns: nanotime(),
+            stalled_s,
+            input_queue,
+            contraflow_queue,
+            blocked_on: activity.blocked_on.clone(),
+            output_queues: activity.output_queues.clone(),
+            last_event_ids: activity.last_event_ids.clone(),
+        });
+        if let Ok(mut stall) = self.stall.lock() {
+            *stall = diagnostics;
+        }
+    }
+
+    pub(crate) fn set_connection(&self, status: ConnectionStatus) {
+        if let Ok(mut connection) = self.connection.lock() {
+            *connection = Some(status);
+        }
+    }
+
+    /// marks the last stall as resolved
+    pub(crate) fn resolve_stall(&self) {
+        if let Ok(mut stall) = self.stall.lock() {
+            if let Some(stall) = stall.as_mut() {
+                stall.ongoing = false;
+            }
+        }
+    }
+
+    /// Takes a snapshot of the current statistics
+    #[must_use]
+    pub fn snapshot(&self) -> InstanceStatsSnapshot {
+        InstanceStatsSnapshot {
+            state: InstanceState::from_u8(self.state.load(Ordering::Relaxed)),
+            uptime_s: nanotime().saturating_sub(self.started_ns) / 1_000_000_000,
+            events_in: self.r#in.load(Ordering::Relaxed),
+            events_out: self.out.load(Ordering::Relaxed),
+            errors: self.err.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().ok().and_then(|e| e.clone()),
+            stall: self.stall.lock().ok().and_then(|s| s.clone()),
+            connection: self.connection.lock().ok().and_then(|c| c.clone()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Ramp {
     r#in: u64,
@@ -36,6 +302,9 @@ pub(crate) struct RampReporter {
     metrics_pipeline: Option<(TremorUrl, pipeline::Addr)>,
     flush_interval: Option<u64>, // as nano-seconds
     last_flush_ns: u64,
+    byte_metrics: Option<Arc<ByteMetrics>>,
+    verify_metrics: Option<Arc<VerifyMetrics>>,
+    stats: Arc<InstanceStats>,
 }
 
 impl RampReporter {
@@ -50,6 +319,42 @@ impl RampReporter {
             metrics_pipeline: None,
             flush_interval: flush_interval_s.map(|n| n * 1_000_000_000),
             last_flush_ns: 0,
+            byte_metrics: None,
+            verify_metrics: None,
+            stats: Arc::new(InstanceStats::default()),
+        }
+    }
+
+    /// runtime statistics of the ramp instance
+    pub(crate) fn stats(&self) -> Arc<InstanceStats> {
+        self.stats.clone()
+    }
+
+    /// byte metrics to be filled by the ramps postprocessors, only
+    /// available if metrics are reported
+    pub(crate) fn byte_metrics(&mut self) -> Option<Arc<ByteMetrics>> {
+        if self.flush_interval.is_some() {
+            Some(
+                self.byte_metrics
+                    .get_or_insert_with(|| Arc::new(ByteMetrics::default()))
+                    .clone(),
+            )
+        } else {
+            None
+        }
+    }
+
+    /// verification metrics to be filled by the ramps verifying
+    /// preprocessors, only available if metrics are reported
+    pub(crate) fn verify_metrics(&mut self) -> Option<Arc<VerifyMetrics>> {
+        if self.flush_interval.is_some() {
+            Some(
+                self.verify_metrics
+                    .get_or_insert_with(|| Arc::new(VerifyMetrics::default()))
+                    .clone(),
+            )
+        } else {
+            None
         }
     }
 
@@ -59,24 +364,57 @@ impl RampReporter {
 
     pub(crate) fn increment_in(&mut self) {
         self.metrics.r#in += 1;
+        self.stats.increment_in();
     }
 
     pub(crate) fn increment_out(&mut self) {
         self.metrics.out += 1;
+        self.stats.increment_out();
     }
 
     pub(crate) fn increment_err(&mut self) {
         self.metrics.err += 1;
+        self.stats.increment_err();
+    }
+
+    /// like `increment_err` but also records the error as the instances last error
+    pub(crate) fn record_error<E: ToString>(&mut self, e: &E) {
+        self.metrics.err += 1;
+        self.stats.record_error(e);
+    }
+
+    /// records an error that is counted elsewhere as the instances last error
+    pub(crate) fn set_last_error<E: ToString>(&self, e: &E) {
+        self.stats.set_last_error(e);
     }
 
     pub(crate) fn periodic_flush(&mut self, timestamp: u64) -> Option<u64> {
         if let Some(interval) = self.flush_interval {
             if timestamp >= self.last_flush_ns + interval {
-                self.send(vec![
+                let mut events = vec![
                     self.make_event(timestamp, "in", self.metrics.r#in),
                     self.make_event(timestamp, "out", self.metrics.out),
                     self.make_event(timestamp, "error", self.metrics.err),
-                ]);
+                ];
+                if let Some(bytes) = &self.byte_metrics {
+                    events.push(self.make_bytes_event(timestamp, "in", bytes.bytes_in()));
+                    events.push(self.make_bytes_event(timestamp, "out", bytes.bytes_out()));
+                }
+                if let Some(verify) = &self.verify_metrics {
+                    events.push(self.make_measurement(
+                        timestamp,
+                        "ramp_verify",
+                        "verified",
+                        verify.verified(),
+                    ));
+                    events.push(self.make_measurement(
+                        timestamp,
+                        "ramp_verify",
+                        "failed",
+                        verify.failed(),
+                    ));
+                }
+                self.send(events);
                 self.last_flush_ns = timestamp;
                 return Some(timestamp);
             }
@@ -86,11 +424,27 @@ impl RampReporter {
 
     #[must_use]
     fn make_event(&self, timestamp: u64, port: &'static str, count: u64) -> Event {
+        self.make_measurement(timestamp, "ramp_events", port, count)
+    }
+
+    #[must_use]
+    fn make_bytes_event(&self, timestamp: u64, port: &'static str, count: u64) -> Event {
+        self.make_measurement(timestamp, "ramp_bytes", port, count)
+    }
+
+    #[must_use]
+    fn make_measurement(
+        &self,
+        timestamp: u64,
+        measurement: &'static str,
+        port: &'static str,
+        count: u64,
+    ) -> Event {
         let mut tags: HashMap<Cow<'static, str>, Value<'static>> = HashMap::with_capacity(2);
         tags.insert_nocheck(Cow::from("ramp"), self.artefact_url.to_string().into());
         tags.insert_nocheck(Cow::from("port"), port.into());
 
-        let value = tremor_pipeline::influx_value(Cow::from("ramp_events"), tags, count, timestamp);
+        let value = tremor_pipeline::influx_value(Cow::from(measurement), tags, count, timestamp);
         // full metrics payload
         // TODO update origin url
         Event {
@@ -118,10 +472,76 @@ impl RampReporter {
     }
 }
 
+/// interval the counters and gauges of scripts are reported in
+const SCRIPT_METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reports the counters and gauges updated by `metrics::counter` and
+/// `metrics::gauge` to the metrics pipeline. Their series are shared by all
+/// scripts of the process, so they are reported once from here instead of by
+/// whichever pipeline reports its own metrics first.
+pub(crate) fn report_script_metrics(metrics_pipeline: (TremorUrl, pipeline::Addr)) {
+    let (metrics_input, metrics_addr) = metrics_pipeline;
+    let input: Cow<'static, str> = metrics_input
+        .instance_port()
+        .unwrap_or("in")
+        .to_string()
+        .into();
+    task::spawn(async move {
+        loop {
+            task::sleep(SCRIPT_METRICS_INTERVAL).await;
+            let timestamp = nanotime();
+            for value in tremor_script::take_script_metrics(timestamp) {
+                let event = Event {
+                    data: value.into(),
+                    ingest_ns: timestamp,
+                    ..Event::default()
+                };
+                let msg = pipeline::Msg::Event {
+                    input: input.clone(),
+                    event,
+                };
+                if let Err(e) = metrics_addr.send(msg).await {
+                    debug!("Stopped reporting script metrics: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn stall() {
+        let stats = InstanceStats::default();
+        assert_eq!(stats.snapshot().stall, None);
+        stats.increment_handled();
+        assert_eq!(stats.handled(), 1);
+        let mut queues = BTreeMap::new();
+        queues.insert("tremor://localhost/offramp/out/01/in".to_string(), 64);
+        let mut ids = BTreeMap::new();
+        ids.insert("passthrough".to_string(), "1:0:42".to_string());
+        stats.set_activity(queues.clone(), ids.clone());
+        stats.set_blocked_on(Some("tremor://localhost/offramp/out/01/in".to_string()));
+        stats.record_stall(10, 64, 0);
+
+        let stall = stats.snapshot().stall.expect("a stall");
+        assert!(stall.ongoing);
+        assert_eq!(stall.stalled_s, 10);
+        assert_eq!(stall.input_queue, 64);
+        assert_eq!(
+            stall.blocked_on.as_deref(),
+            Some("tremor://localhost/offramp/out/01/in")
+        );
+        assert_eq!(stall.output_queues, queues);
+        assert_eq!(stall.last_event_ids, ids);
+
+        stats.resolve_stall();
+        assert_eq!(stats.snapshot().stall.map(|s| s.ongoing), Some(false));
+    }
+
     #[test]
     fn test() {
         let mut r = RampReporter::new(TremorUrl::parse("/onramp/example/00").unwrap(), Some(1));
@@ -131,6 +551,20 @@ mod test {
         assert_eq!(r.metrics.out, 1);
         r.increment_err();
         assert_eq!(r.metrics.err, 1);
+        r.record_error(&"snot");
+        let stats = r.stats().snapshot();
+        assert_eq!(stats.state, InstanceState::Running);
+        assert_eq!(stats.events_in, 1);
+        assert_eq!(stats.events_out, 1);
+        assert_eq!(stats.errors, 2);
+        assert_eq!(stats.last_error, Some("snot".to_string()));
+        r.stats().terminated::<(), _>(&Ok(()));
+        assert_eq!(r.stats().snapshot().state, InstanceState::Stopped);
+        r.stats().terminated::<(), _>(&Err("badger"));
+        let stats = r.stats().snapshot();
+        assert_eq!(stats.state, InstanceState::Failed);
+        assert_eq!(stats.errors, 2);
+        assert_eq!(stats.last_error, Some("badger".to_string()));
 
         let e = r.make_event(123, "test", 42);
 
@@ -145,5 +579,13 @@ mod test {
         assert_eq!(r.periodic_flush(1_000_000_000), Some(1_000_000_000));
         assert_eq!(r.periodic_flush(1_000_000_001), None);
         assert_eq!(r.periodic_flush(2_000_000_000), Some(2_000_000_000));
+
+        let e = r.make_bytes_event(123, "out", 42);
+        let (v, _) = e.data.parts();
+        assert_eq!(v["measurement"], "ramp_bytes");
+        assert_eq!(v["tags"]["port"], "out");
+        assert!(r.byte_metrics().is_some());
+        let mut r = RampReporter::new(TremorUrl::parse("/offramp/example/00").unwrap(), None);
+        assert!(r.byte_metrics().is_none());
     }
 }
@@ -14,15 +14,17 @@
 
 use crate::codec::Codec;
 use crate::errors::Result;
-use crate::metrics::RampReporter;
+use crate::metrics::{InstanceState, RampReporter};
 use crate::permge::PriorityMerge;
 use crate::pipeline;
 use crate::registry::ServantId;
 use crate::sink::{
-    self, blackhole, cb, debug, dns, elastic, exit, file, gcs, handle_response, kafka, kv, nats,
-    newrelic, otel, postgres, rest, stderr, stdout, tcp, udp, ws,
+    self, amqp, archive, bigquery, blackhole, cb, cloudwatch_logs, cloudwatch_metrics, debug, dns,
+    elastic, eventhubs, exit, file, gcl, gcs, graphql, grpc, handle_response, kafka, kv, lb,
+    mirror, nats, newrelic, otel, postgres, pubsub, redis, rest, sns, sqs, sse, stderr, stdout,
+    tcp, udp, ws,
 };
-use crate::source::Processors;
+use crate::source::{lifecycle, Processors};
 use crate::url::ports::{IN, METRICS};
 use crate::url::TremorUrl;
 use crate::{Event, OpConfig};
@@ -36,6 +38,8 @@ use std::borrow::{Borrow, BorrowMut};
 use std::fmt;
 use tremor_common::ids::OfframpIdGen;
 use tremor_common::time::nanotime;
+use tremor_pipeline::ConfigImpl;
+use tremor_script::prelude::*;
 
 #[derive(Debug)]
 pub enum Msg {
@@ -104,10 +108,20 @@ pub trait Impl {
     fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>>;
 }
 
+/// Validates the config of an offramp when it is published, ahead of
+/// binding it, for offramps that can check more than their config format
+pub(crate) fn validate(name: &str, config: &Option<OpConfig>) -> Result<()> {
+    match (name, config) {
+        ("kafka", Some(config)) => kafka::Config::new(config)?.validate(),
+        _ => Ok(()),
+    }
+}
+
 // just a lookup
 #[cfg(not(tarpaulin_include))]
 pub fn lookup(name: &str, config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
     match name {
+        "amqp" => amqp::Amqp::from_config(config),
         "blackhole" => blackhole::Blackhole::from_config(config),
         "cb" => cb::Cb::from_config(config),
         "debug" => debug::Debug::from_config(config),
@@ -117,6 +131,8 @@ pub fn lookup(name: &str, config: &Option<OpConfig>) -> Result<Box<dyn Offramp>>
         "file" => file::File::from_config(config),
         "kafka" => kafka::Kafka::from_config(config),
         "kv" => kv::Kv::from_config(config),
+        "lb" => lb::Lb::from_config(config),
+        "mirror" => mirror::Mirror::from_config(config),
         "nats" => nats::Nats::from_config(config),
         "newrelic" => newrelic::NewRelic::from_config(config),
         "otel" => otel::OpenTelemetry::from_config(config),
@@ -126,8 +142,23 @@ pub fn lookup(name: &str, config: &Option<OpConfig>) -> Result<Box<dyn Offramp>>
         "stdout" => stdout::StdOut::from_config(config),
         "tcp" => tcp::Tcp::from_config(config),
         "udp" => udp::Udp::from_config(config),
+        #[cfg(unix)]
+        "unix-socket" => sink::unix_socket::UnixSocket::from_config(config),
         "ws" => ws::Ws::from_config(config),
         "gcs" => gcs::GoogleCloudStorage::from_config(config),
+        "grpc" => grpc::Grpc::from_config(config),
+        "graphql" => graphql::GraphQl::from_config(config),
+        "bigquery" => bigquery::BigQuery::from_config(config),
+        "gcl" => gcl::Gcl::from_config(config),
+        "archive" => archive::Archive::from_config(config),
+        "cloudwatch-logs" => cloudwatch_logs::CloudWatchLogs::from_config(config),
+        "cloudwatch-metrics" => cloudwatch_metrics::CloudWatchMetrics::from_config(config),
+        "sqs" => sqs::Sqs::from_config(config),
+        "sse" => sse::Sse::from_config(config),
+        "sns" => sns::Sns::from_config(config),
+        "pubsub" => pubsub::PubSub::from_config(config),
+        "eventhubs" => eventhubs::EventHubs::from_config(config),
+        "redis" => redis::Redis::from_config(config),
         _ => Err(format!("Offramp {} not known", name).into()),
     }
 }
@@ -195,7 +226,7 @@ impl Manager {
         r: async_channel::Sender<Result<Addr>>,
         Create {
             mut codec,
-            codec_map,
+            mut codec_map,
             mut offramp,
             preprocessors,
             postprocessors,
@@ -217,6 +248,8 @@ impl Manager {
                 Processors {
                     pre: &preprocessors,
                     post: &postprocessors,
+                    metrics: metrics_reporter.byte_metrics(),
+                    stats: Some(metrics_reporter.stats()),
                 },
                 is_linked,
                 cf_tx.clone(),
@@ -224,6 +257,10 @@ impl Manager {
             .await
         {
             error!("Failed to create offramp {}: {}", id, e);
+            lifecycle::publish(lifecycle::Transition::ConnectorFailed {
+                id: id.to_string(),
+                error: e.to_string(),
+            });
             return Err(e);
         }
         // merge channels and prioritize contraflow/insight events
@@ -260,12 +297,31 @@ impl Manager {
                                 metrics_reporter.periodic_flush(ingest_ns);
                                 metrics_reporter.increment_in();
 
-                                let c: &mut dyn Codec = codec.borrow_mut();
-                                let fail = if let Err(err) =
+                                // events can select a codec of the codec map via `$codec`
+                                let meta_codec = event
+                                    .data
+                                    .suffix()
+                                    .meta()
+                                    .get_str(crate::codec::META_FIELD)
+                                    .map(ToString::to_string);
+                                let res = if let Some(name) = meta_codec {
+                                    // taken out of the map while in use, as it is borrowed too
+                                    if let Some(mut c) = codec_map.remove(&name) {
+                                        let res = offramp
+                                            .on_event(c.as_mut(), &codec_map, input.borrow(), event)
+                                            .await;
+                                        codec_map.insert(name, c);
+                                        res
+                                    } else {
+                                        Err(format!("Unknown codec `{}` in `$codec`", name).into())
+                                    }
+                                } else {
+                                    let c: &mut dyn Codec = codec.borrow_mut();
                                     offramp.on_event(c, &codec_map, input.borrow(), event).await
-                                {
+                                };
+                                let fail = if let Err(err) = res {
                                     error!("[Offramp::{}] On Event error: {}", offramp_url, err);
-                                    metrics_reporter.increment_err();
+                                    metrics_reporter.record_error(&err);
                                     true
                                 } else {
                                     metrics_reporter.increment_out();
@@ -417,6 +473,7 @@ impl Manager {
                     }
                 }
             }
+            metrics_reporter.stats().set_state(InstanceState::Stopped);
             info!("[Offramp::{}] stopped", offramp_url);
             Ok(())
         });
@@ -11,16 +11,20 @@
 // WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 // See the License for the specific language governing permissions and
 // limitations under the License.
+use crate::config::CodecSpec;
+use crate::correlation;
 use crate::errors::Result;
 use crate::metrics::RampReporter;
 use crate::pipeline;
 use crate::repository::ServantId;
 use crate::source::prelude::*;
 use crate::source::{
-    blaster, cb, crononome, discord, file, kafka, metronome, nats, otel, postgres, rest, stdin,
-    tcp, udp, ws,
+    amqp, blaster, cb, crononome, discord, eventhubs, file, flow, http_poller, kafka, kinesis,
+    lifecycle, metronome, mysql, nats, otel, postgres, postgres_cdc, pubsub, redis, rest, s3,
+    snmp_trap, sqs, sse, stdin, tail, tcp, udp, ws, ws_client,
 };
 use crate::url::TremorUrl;
+use crate::OpConfig;
 use async_std::task::{self, JoinHandle};
 use serde_yaml::Value;
 use std::fmt;
@@ -36,6 +40,11 @@ pub(crate) trait Impl {
 #[derive(Clone, Debug)]
 pub enum Msg {
     Connect(Cow<'static, str>, Vec<(TremorUrl, pipeline::Addr)>),
+    /// only send events matching the filter to the pipeline
+    Filter {
+        id: TremorUrl,
+        filter: crate::filter::Filter,
+    },
     Disconnect {
         id: TremorUrl,
         tx: async_channel::Sender<bool>,
@@ -49,12 +58,15 @@ pub type Addr = async_channel::Sender<Msg>;
 
 pub(crate) struct OnrampConfig<'cfg> {
     pub onramp_uid: u64,
-    pub codec: &'cfg str,
+    pub codec: &'cfg CodecSpec,
+    pub codec_config: Option<OpConfig>,
     pub codec_map: halfbrown::HashMap<String, String>,
     pub processors: Processors<'cfg>,
+    pub preprocessor_config: Option<OpConfig>,
     pub metrics_reporter: RampReporter,
     pub is_linked: bool,
     pub err_required: bool,
+    pub correlation: Option<correlation::Config>,
 }
 #[async_trait::async_trait]
 pub(crate) trait Onramp: Send {
@@ -62,6 +74,15 @@ pub(crate) trait Onramp: Send {
     fn default_codec(&self) -> &str;
 }
 
+/// Validates the config of an onramp when it is published, ahead of binding
+/// it, for onramps that can check more than their config format
+pub(crate) fn validate(name: &str, config: &Option<Value>) -> Result<()> {
+    match (name, config) {
+        ("kafka", Some(config)) => kafka::Config::new(config)?.validate(),
+        _ => Ok(()),
+    }
+}
+
 // just a lookup
 #[cfg(not(tarpaulin_include))]
 pub(crate) fn lookup(
@@ -73,18 +94,36 @@ pub(crate) fn lookup(
         "blaster" => blaster::Blaster::from_config(id, config),
         "cb" => cb::Cb::from_config(id, config),
         "file" => file::File::from_config(id, config),
+        "http-poller" => http_poller::HttpPoller::from_config(id, config),
+        "tail" => tail::Tail::from_config(id, config),
         "kafka" => kafka::Kafka::from_config(id, config),
+        "kinesis" => kinesis::Kinesis::from_config(id, config),
         "postgres" => postgres::Postgres::from_config(id, config),
+        "mysql" => mysql::Mysql::from_config(id, config),
+        "postgres-cdc" => postgres_cdc::PostgresCdc::from_config(id, config),
         "metronome" => metronome::Metronome::from_config(id, config),
         "crononome" => crononome::Crononome::from_config(id, config),
+        "lifecycle" => lifecycle::Lifecycle::from_config(id, config),
         "stdin" => stdin::Stdin::from_config(id, config),
         "udp" => udp::Udp::from_config(id, config),
+        "snmp-trap" => snmp_trap::SnmpTrap::from_config(id, config),
+        "flow" => flow::Flow::from_config(id, config),
+        #[cfg(unix)]
+        "unix-socket" => crate::source::unix_socket::UnixSocket::from_config(id, config),
         "tcp" => tcp::Tcp::from_config(id, config),
         "rest" => rest::Rest::from_config(id, config),
         "ws" => ws::Ws::from_config(id, config),
+        "ws-client" => ws_client::WsClient::from_config(id, config),
+        "sse" => sse::Sse::from_config(id, config),
         "discord" => discord::Discord::from_config(id, config),
         "otel" => otel::OpenTelemetry::from_config(id, config),
         "nats" => nats::Nats::from_config(id, config),
+        "amqp" => amqp::Amqp::from_config(id, config),
+        "sqs" => sqs::Sqs::from_config(id, config),
+        "s3" => s3::S3::from_config(id, config),
+        "pubsub" => pubsub::PubSub::from_config(id, config),
+        "eventhubs" => eventhubs::EventHubs::from_config(id, config),
+        "redis" => redis::Redis::from_config(id, config),
         _ => Err(format!("[onramp:{}] Onramp type {} not known", id, name).into()),
     }
 }
@@ -92,13 +131,16 @@ pub(crate) fn lookup(
 pub(crate) struct Create {
     pub id: ServantId,
     pub stream: Box<dyn Onramp>,
-    pub codec: String,
+    pub codec: CodecSpec,
+    pub codec_config: Option<OpConfig>,
     pub codec_map: halfbrown::HashMap<String, String>,
     pub preprocessors: Vec<String>,
+    pub preprocessor_config: Option<OpConfig>,
     pub postprocessors: Vec<String>,
     pub metrics_reporter: RampReporter,
     pub is_linked: bool,
     pub err_required: bool,
+    pub correlation: Option<correlation::Config>,
 }
 
 impl fmt::Debug for Create {
@@ -137,28 +179,36 @@ impl Manager {
                     Ok(ManagerMsg::Create(r, c)) => {
                         let Create {
                             codec,
+                            codec_config,
                             codec_map,
                             mut stream,
                             preprocessors,
+                            preprocessor_config,
                             postprocessors,
                             metrics_reporter,
                             is_linked,
                             id,
                             err_required,
+                            correlation,
                         } = *c;
 
                         match stream
                             .start(OnrampConfig {
                                 onramp_uid: onramp_id_gen.next_id(),
                                 codec: &codec,
+                                codec_config,
                                 codec_map,
                                 processors: Processors {
                                     pre: &preprocessors,
                                     post: &postprocessors,
+                                    metrics: None,
+                                    stats: None,
                                 },
+                                preprocessor_config,
                                 metrics_reporter,
                                 is_linked,
                                 err_required,
+                                correlation,
                             })
                             .await
                         {
@@ -166,7 +216,13 @@ impl Manager {
                                 info!("Onramp {} started.", id);
                                 r.send(Ok(addr)).await?;
                             }
-                            Err(e) => error!("Creating an onramp failed: {}", e),
+                            Err(e) => {
+                                error!("Creating an onramp failed: {}", e);
+                                lifecycle::publish(lifecycle::Transition::ConnectorFailed {
+                                    id: id.to_string(),
+                                    error: e.to_string(),
+                                });
+                            }
                         }
                     }
                     Err(e) => {
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use crate::errors::{Error, Result};
+use crate::metrics::InstanceStats;
 use crate::permge::{PriorityMerge, M};
 use crate::registry::ServantId;
 use crate::repository::PipelineArtefact;
@@ -22,13 +23,19 @@ use async_std::stream::StreamExt;
 use async_std::task::{self, JoinHandle};
 use beef::Cow;
 use std::fmt;
+use std::sync::Arc;
 use std::time::Duration;
 use tremor_common::ids::OperatorIdGen;
 use tremor_common::time::nanotime;
 use tremor_pipeline::errors::ErrorKind as PipelineErrorKind;
 use tremor_pipeline::{CbAction, Event, ExecutableGraph, SignalKind};
+use tremor_script::Value;
 
 const TICK_MS: u64 = 100;
+const WATCHDOG_MS: u64 = 1000;
+/// seconds without progress with a non empty input queue after which a
+/// pipeline is considered stalled
+const STALL_TIMEOUT_S: u64 = 10;
 pub(crate) type Sender = async_channel::Sender<ManagerMsg>;
 type Inputs = halfbrown::HashMap<TremorUrl, (bool, Input)>;
 type Dests = halfbrown::HashMap<Cow<'static, str>, Vec<(TremorUrl, Dest)>>;
@@ -61,6 +68,9 @@ impl Addr {
     pub fn len(&self) -> usize {
         self.addr.len()
     }
+    pub(crate) fn cf_len(&self) -> usize {
+        self.cf_addr.len()
+    }
     #[cfg(not(tarpaulin_include))]
     pub fn id(&self) -> &ServantId {
         &self.id
@@ -82,6 +92,13 @@ impl Addr {
     pub(crate) async fn send_mgmt(&self, msg: MgmtMsg) -> Result<()> {
         Ok(self.mgmt_addr.send(msg).await?)
     }
+
+    /// The recording of the pipeline, if it is configured with `record_s`
+    pub async fn recording(&self) -> Result<Option<Value<'static>>> {
+        let (tx, rx) = async_channel::bounded(1);
+        self.send_mgmt(MgmtMsg::Recording(tx)).await?;
+        Ok(rx.recv().await?)
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -118,6 +135,8 @@ pub(crate) enum MgmtMsg {
     },
     DisconnectOutput(Cow<'static, str>, TremorUrl),
     DisconnectInput(TremorUrl),
+    /// the recording of the pipeline, for time-travel debugging
+    Recording(async_channel::Sender<Option<Value<'static>>>),
     // only for testing
     Echo(async_channel::Sender<()>),
 }
@@ -139,6 +158,22 @@ pub enum Dest {
 }
 
 impl Dest {
+    /// messages waiting in the queue of the destination
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Offramp(addr) => addr.len(),
+            Self::Pipeline(addr) => addr.len(),
+            Self::LinkedOnramp(addr) => addr.len(),
+        }
+    }
+    /// whether sending to the destination would wait for it to catch up
+    pub fn is_full(&self) -> bool {
+        match self {
+            Self::Offramp(addr) => addr.is_full(),
+            Self::Pipeline(addr) => addr.addr.is_full(),
+            Self::LinkedOnramp(addr) => addr.is_full(),
+        }
+    }
     pub async fn send_event(&mut self, input: Cow<'static, str>, event: Event) -> Result<()> {
         match self {
             Self::Offramp(addr) => addr.send(offramp::Msg::Event { input, event }).await?,
@@ -200,6 +235,7 @@ impl From<ConnectTarget> for Input {
 pub struct Create {
     pub config: PipelineArtefact,
     pub id: ServantId,
+    pub stats: Arc<InstanceStats>,
 }
 
 pub(crate) enum ManagerMsg {
@@ -213,17 +249,44 @@ pub(crate) struct Manager {
     operator_id_gen: OperatorIdGen,
 }
 
+/// records the destination as the one the pipeline waits for if it is full
+fn note_blocked(stats: &InstanceStats, id: &TremorUrl, dest: &Dest) -> bool {
+    let full = dest.is_full();
+    if full {
+        stats.set_blocked_on(Some(id.to_string()));
+    }
+    full
+}
+
 #[inline]
-async fn send_events(eventset: &mut Eventset, dests: &mut Dests) -> Result<()> {
+async fn send_event(
+    id: &TremorUrl,
+    dest: &mut Dest,
+    event: Event,
+    stats: &InstanceStats,
+) -> Result<()> {
+    let port = id.instance_port_required()?.to_string().into();
+    let blocked = note_blocked(stats, id, dest);
+    let res = dest.send_event(port, event).await;
+    if blocked {
+        stats.set_blocked_on(None);
+    }
+    res
+}
+
+#[inline]
+async fn send_events(
+    eventset: &mut Eventset,
+    dests: &mut Dests,
+    stats: &InstanceStats,
+) -> Result<()> {
     for (output, event) in eventset.drain(..) {
         if let Some(dest) = dests.get_mut(&output) {
             if let Some((last, rest)) = dest.split_last_mut() {
                 for (id, offramp) in rest {
-                    let port = id.instance_port_required()?.to_string().into();
-                    offramp.send_event(port, event.clone()).await?;
+                    send_event(id, offramp, event.clone(), stats).await?;
                 }
-                let last_port = last.0.instance_port_required()?.to_string().into();
-                last.1.send_event(last_port, event).await?;
+                send_event(&last.0, &mut last.1, event, stats).await?;
             }
         };
     }
@@ -231,22 +294,84 @@ async fn send_events(eventset: &mut Eventset, dests: &mut Dests) -> Result<()> {
 }
 
 #[inline]
-async fn send_signal(own_id: &TremorUrl, signal: Event, dests: &mut Dests) -> Result<()> {
+async fn send_signal(
+    own_id: &TremorUrl,
+    signal: Event,
+    dests: &mut Dests,
+    stats: &InstanceStats,
+) -> Result<()> {
     let mut offramps = dests.values_mut().flatten();
     let first = offramps.next();
     for (id, offramp) in offramps {
         if id != own_id {
+            let blocked = note_blocked(stats, id, offramp);
             offramp.send_signal(signal.clone()).await?;
+            if blocked {
+                stats.set_blocked_on(None);
+            }
         }
     }
     if let Some((id, offramp)) = first {
         if id != own_id {
+            let blocked = note_blocked(stats, id, offramp);
             offramp.send_signal(signal).await?;
+            if blocked {
+                stats.set_blocked_on(None);
+            }
         }
     }
     Ok(())
 }
 
+/// publishes what the pipeline is doing for the stall diagnostics
+fn record_activity(pipeline: &ExecutableGraph, dests: &Dests, stats: &InstanceStats) {
+    let output_queues = dests
+        .values()
+        .flatten()
+        .map(|(id, dest)| (id.to_string(), dest.len()))
+        .collect();
+    let last_event_ids = pipeline.last_event_ids().into_iter().collect();
+    stats.set_activity(output_queues, last_event_ids);
+}
+
+/// watches a pipeline for stalls, it is stalled if it handled no message for
+/// `STALL_TIMEOUT_S` while there were messages waiting in its input queue
+async fn watchdog(id: TremorUrl, addr: Addr, stats: Arc<InstanceStats>) {
+    let mut handled = stats.handled();
+    let mut waiting_since: Option<u64> = None;
+    let mut stalled = false;
+    while !addr.addr.is_closed() && stats.is_running() {
+        task::sleep(Duration::from_millis(WATCHDOG_MS)).await;
+        let now = nanotime();
+        let current = stats.handled();
+        if current != handled || addr.len() == 0 {
+            handled = current;
+            waiting_since = None;
+            if stalled {
+                stalled = false;
+                stats.resolve_stall();
+                info!("[Pipeline:{}] Recovered from stall.", id);
+            }
+            continue;
+        }
+        let since = *waiting_since.get_or_insert(now);
+        let waiting_s = now.saturating_sub(since) / 1_000_000_000;
+        if !stalled && waiting_s >= STALL_TIMEOUT_S {
+            stalled = true;
+            stats.record_stall(waiting_s, addr.len(), addr.cf_len());
+            if let Some(stall) = stats.snapshot().stall {
+                warn!(
+                    "[Pipeline:{}] Stalled for {}s with {} messages waiting, blocked on {}.",
+                    id,
+                    waiting_s,
+                    stall.input_queue,
+                    stall.blocked_on.as_deref().unwrap_or("nothing")
+                );
+            }
+        }
+    }
+}
+
 #[inline]
 async fn handle_insight(
     skip_to: Option<usize>,
@@ -370,6 +495,7 @@ async fn pipeline_task(
     rx: async_channel::Receiver<Msg>,
     cf_rx: async_channel::Receiver<CfMsg>,
     mgmt_rx: async_channel::Receiver<MgmtMsg>,
+    stats: Arc<InstanceStats>,
 ) -> Result<()> {
     let mut pid = id.clone();
     pid.trim_to_instance();
@@ -393,10 +519,13 @@ async fn pipeline_task(
                 handle_cf_msg(msg, &mut pipeline, &inputs).await?;
             }
             M::F(Msg::Event { input, event }) => {
+                stats.increment_handled();
+                stats.increment_in();
                 match pipeline.enqueue(&input, event, &mut eventset) {
                     Ok(()) => {
+                        stats.add_out(eventset.len());
                         handle_insights(&mut pipeline, &inputs).await;
-                        maybe_send(send_events(&mut eventset, &mut dests).await);
+                        maybe_send(send_events(&mut eventset, &mut dests, &stats).await);
                     }
                     Err(e) => {
                         let err_str = if let PipelineErrorKind::Script(script_kind) = e.0 {
@@ -413,11 +542,16 @@ async fn pipeline_task(
                         } else {
                             format!(" {}", e)
                         };
+                        stats.record_error(&err_str.trim());
                         error!("Error handling event:{}", err_str);
                     }
                 }
             }
             M::F(Msg::Signal(signal)) => {
+                stats.increment_handled();
+                if signal.kind == Some(SignalKind::Tick) {
+                    record_activity(&pipeline, &dests, &stats);
+                }
                 if let Err(e) = pipeline.enqueue_signal(signal.clone(), &mut eventset) {
                     let err_str = if let PipelineErrorKind::Script(script_kind) = e.0 {
                         let script_error = tremor_script::errors::Error(script_kind, e.1);
@@ -433,11 +567,12 @@ async fn pipeline_task(
                     } else {
                         format!(" {:?}", e)
                     };
+                    stats.record_error(&err_str.trim());
                     error!("[Pipeline::{}] Error handling signal:{}", pid, err_str);
                 } else {
-                    maybe_send(send_signal(&id, signal, &mut dests).await);
+                    maybe_send(send_signal(&id, signal, &mut dests, &stats).await);
                     handle_insights(&mut pipeline, &inputs).await;
-                    maybe_send(send_events(&mut eventset, &mut dests).await);
+                    maybe_send(send_events(&mut eventset, &mut dests, &stats).await);
                 }
             }
             M::M(MgmtMsg::ConnectInput {
@@ -515,6 +650,15 @@ async fn pipeline_task(
                 info!("[Pipeline::{}] Disconnecting {} from 'in'", pid, &input_url);
                 inputs.remove(&input_url);
             }
+            M::M(MgmtMsg::Recording(sender)) => {
+                let recording = pipeline.recording().map(|r| r.to_value());
+                if let Err(e) = sender.send(recording).await {
+                    error!(
+                        "[Pipeline::{}] Error responding to recording message: {}",
+                        pid, e
+                    );
+                }
+            }
             M::M(MgmtMsg::Echo(sender)) => {
                 if let Err(e) = sender.send(()).await {
                     error!(
@@ -567,6 +711,7 @@ impl Manager {
         let pipeline = config.to_pipe(&mut self.operator_id_gen)?;
 
         let id = req.id.clone();
+        let stats = req.stats;
 
         let (tx, rx) = bounded::<Msg>(self.qsize);
         // We use a unbounded channel for counterflow, while an unbounded channel seems dangerous
@@ -591,16 +736,18 @@ impl Manager {
         task::spawn(tick(tx.clone()));
 
         let addr = Addr::new(tx, cf_tx, mgmt_tx, req.id);
+        task::spawn(watchdog(id.clone(), addr.clone(), stats.clone()));
         task::Builder::new()
             .name(format!("pipeline-{}", id))
-            .spawn(pipeline_task(
-                id,
-                pipeline,
-                addr.clone(),
-                rx,
-                cf_rx,
-                mgmt_rx,
-            ))?;
+            .spawn({
+                let addr = addr.clone();
+                async move {
+                    let res =
+                        pipeline_task(id, pipeline, addr, rx, cf_rx, mgmt_rx, stats.clone()).await;
+                    stats.terminated(&res);
+                    res
+                }
+            })?;
         Ok(addr)
     }
 }
@@ -663,7 +810,11 @@ mod tests {
         let (handle, sender) = manager.start();
 
         let (tx, rx) = async_channel::bounded(1);
-        let create = Create { config, id };
+        let create = Create {
+            config,
+            id,
+            stats: Arc::default(),
+        };
         let create_msg = ManagerMsg::Create(tx, create);
         sender.send(create_msg).await?;
         let addr = rx.recv().await??;
@@ -780,7 +931,11 @@ mod tests {
         let manager = Manager::new(12);
         let (handle, sender) = manager.start();
         let (tx, rx) = async_channel::bounded(1);
-        let create = Create { config, id };
+        let create = Create {
+            config,
+            id,
+            stats: Arc::default(),
+        };
         let create_msg = ManagerMsg::Create(tx, create);
         sender.send(create_msg).await?;
         let addr = rx.recv().await??;
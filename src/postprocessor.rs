@@ -18,6 +18,7 @@ pub(crate) use gelf::Gelf;
 use crate::errors::{Error, Result};
 use byteorder::{BigEndian, WriteBytesExt};
 use std::default::Default;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tremor_common::time::nanotime;
 /// Set of Postprocessors
 pub type Postprocessors = Vec<Box<dyn Postprocessor>>;
@@ -45,8 +46,10 @@ pub trait Postprocessor: Send {
 pub fn lookup(name: &str) -> Result<Box<dyn Postprocessor>> {
     match name {
         "lines" => Ok(Box::new(Lines::default())),
+        "lines-null" => Ok(Box::new(Lines::new(b'\0'))),
         "base64" => Ok(Box::new(Base64::default())),
         "gzip" => Ok(Box::new(Gzip::default())),
+        "gzip-adaptive" => Ok(Box::new(AdaptiveGzip::default())),
         "zlib" => Ok(Box::new(Zlib::default())),
         "xz2" => Ok(Box::new(Xz2::default())),
         "snappy" => Ok(Box::new(Snappy::default())),
@@ -68,7 +71,32 @@ pub fn make_postprocessors(postprocessors: &[String]) -> Result<Postprocessors>
     postprocessors.iter().map(|n| lookup(&n)).collect()
 }
 
-/// canonical way to process encoded data passed from a `Codec`
+/// Number of bytes passed into and emitted from a chain of postprocessors
+#[derive(Debug, Default)]
+pub struct ByteMetrics {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl ByteMetrics {
+    /// bytes passed into the postprocessors
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+    /// bytes emitted by the postprocessors
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+    /// counts bytes for sinks that skip `postprocess` when they have no postprocessors
+    pub(crate) fn count(&self, bytes_in: usize, bytes_out: usize) {
+        self.bytes_in.fetch_add(bytes_in as u64, Ordering::Relaxed);
+        self.bytes_out
+            .fetch_add(bytes_out as u64, Ordering::Relaxed);
+    }
+}
+
+/// canonical way to process encoded data passed from a `Codec`, the bytes
+/// going into and coming out of the postprocessors are added to `metrics`
 ///
 /// # Errors
 ///
@@ -77,40 +105,91 @@ pub fn postprocess(
     postprocessors: &mut [Box<dyn Postprocessor>], // We are borrowing a dyn box as we don't want to pass ownership.
     ingres_ns: u64,
     data: Vec<u8>,
+    metrics: Option<&ByteMetrics>,
 ) -> Result<Vec<Vec<u8>>> {
-    let egress_ns = nanotime();
-    let mut data = vec![data];
-    let mut data1 = Vec::new();
+    if postprocessors.is_empty() {
+        if let Some(metrics) = metrics {
+            metrics.count(data.len(), data.len());
+        }
+        Ok(vec![data])
+    } else {
+        postprocess_slice(postprocessors, ingres_ns, &data, metrics)
+    }
+}
 
-    for pp in postprocessors {
-        data1.clear();
-        for d in &data {
+/// Like `postprocess` but borrows the data, so callers can reuse their
+/// encoding buffer. Without postprocessors the data is copied.
+///
+/// # Errors
+///
+///   * If a `Postprocessor` fails
+pub fn postprocess_slice(
+    postprocessors: &mut [Box<dyn Postprocessor>],
+    ingres_ns: u64,
+    data: &[u8],
+    metrics: Option<&ByteMetrics>,
+) -> Result<Vec<Vec<u8>>> {
+    let egress_ns = nanotime();
+    let mut pps = postprocessors.iter_mut();
+    // the first postprocessor reads the borrowed data, the others the output of their predecessor
+    let mut processed = match pps.next() {
+        Some(pp) => pp
+            .process(ingres_ns, egress_ns, data)
+            .map_err(|e| Error::from(format!("Postprocessor error {}", e)))?,
+        None => vec![data.to_vec()],
+    };
+    let mut processed1 = Vec::new();
+
+    for pp in pps {
+        processed1.clear();
+        for d in &processed {
             match pp.process(ingres_ns, egress_ns, d) {
-                Ok(mut r) => data1.append(&mut r),
+                Ok(mut r) => processed1.append(&mut r),
                 Err(e) => {
                     return Err(format!("Postprocessor error {}", e).into());
                 }
             }
         }
-        mem::swap(&mut data, &mut data1);
+        mem::swap(&mut processed, &mut processed1);
     }
 
-    Ok(data)
+    if let Some(metrics) = metrics {
+        metrics.count(data.len(), processed.iter().map(Vec::len).sum());
+    }
+    Ok(processed)
+}
+
+pub(crate) struct Lines {
+    separator: u8,
+}
+
+impl Lines {
+    pub(crate) fn new(separator: u8) -> Self {
+        Self { separator }
+    }
+}
+
+impl Default for Lines {
+    fn default() -> Self {
+        Self::new(b'\n')
+    }
 }
 
-#[derive(Default)]
-pub(crate) struct Lines {}
 impl Postprocessor for Lines {
     #[cfg(not(tarpaulin_include))]
     fn name(&self) -> &str {
-        "lines"
+        if self.separator == b'\0' {
+            "lines-null"
+        } else {
+            "lines"
+        }
     }
 
     fn process(&mut self, _ingres_ns: u64, _egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
-        // padding capacity with 1 to account for the new line char we will be pushing
+        // padding capacity with 1 to account for the separator we will be pushing
         let mut framed: Vec<u8> = Vec::with_capacity(data.len() + 1);
         framed.extend_from_slice(data);
-        framed.push(b'\n');
+        framed.push(self.separator);
         Ok(vec![framed])
     }
 }
@@ -145,6 +224,53 @@ impl Postprocessor for Gzip {
     }
 }
 
+/// gzip compression that picks the compression level per batch based on how
+/// well a sample of the batch compresses. Data that barely compresses is
+/// stored uncompressed within the gzip container, so receivers are unaffected.
+#[derive(Default)]
+pub(crate) struct AdaptiveGzip {}
+
+impl AdaptiveGzip {
+    /// size of the sample used to estimate compressibility
+    const SAMPLE_SIZE: usize = 8192;
+    /// payloads smaller than this are not worth compressing
+    const MIN_SIZE: usize = 256;
+
+    fn level(data: &[u8]) -> Result<u32> {
+        use flate2::{write::DeflateEncoder, Compression};
+        if data.len() < Self::MIN_SIZE {
+            return Ok(0);
+        }
+        let sample = data.get(..Self::SAMPLE_SIZE).unwrap_or(data);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(sample)?;
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = encoder.finish()?.len() as f64 / sample.len() as f64;
+        Ok(if ratio > 0.9 {
+            0
+        } else if ratio > 0.6 {
+            1
+        } else {
+            6
+        })
+    }
+}
+
+impl Postprocessor for AdaptiveGzip {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "gzip-adaptive"
+    }
+
+    fn process(&mut self, _ingres_ns: u64, _egress_ns: u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        use flate2::{write::GzEncoder, Compression};
+        let level = Self::level(data)?;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+        encoder.write_all(data)?;
+        Ok(vec![encoder.finish()?])
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct Zlib {}
 impl Postprocessor for Zlib {
@@ -270,13 +396,15 @@ mod test {
 
     #[test]
     fn line() {
-        let mut line = Lines {};
+        let mut line = Lines::default();
         let data: [u8; 0] = [];
         assert_eq!(Ok(vec![vec![b'\n']]), line.process(0, 0, &data));
         assert_eq!(
             Ok(vec![vec![b'f', b'o', b'o', b'b', b'\n']]),
             line.process(0, 0, b"foob")
         );
+        let mut line = Lines::new(b'\0');
+        assert_eq!(Ok(vec![b"foob\0".to_vec()]), line.process(0, 0, b"foob"));
     }
 
     #[test]
@@ -291,6 +419,50 @@ mod test {
         assert_eq!(Ok(vec![b"c25vdA==".to_vec()]), post.process(0, 0, b"snot"));
     }
 
+    #[test]
+    fn adaptive_gzip() -> Result<()> {
+        use std::io::Read;
+        let compressible = b"snot badger ".repeat(1000);
+        assert_eq!(6, AdaptiveGzip::level(&compressible)?);
+        assert_eq!(0, AdaptiveGzip::level(b"snot")?);
+        // pseudo random bytes do not compress
+        let mut x = 0x2545_f491_u32;
+        let random: Vec<u8> = (0..4096)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x.to_le_bytes()[0]
+            })
+            .collect();
+        assert_eq!(0, AdaptiveGzip::level(&random)?);
+
+        let mut post = AdaptiveGzip::default();
+        for data in &[compressible, random] {
+            let compressed = post.process(0, 0, data)?.pop().unwrap_or_default();
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut decoded)?;
+            assert_eq!(data, &decoded);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn metered() -> Result<()> {
+        let metrics = ByteMetrics::default();
+        let mut pps = make_postprocessors(&["gzip".to_string()])?;
+        let data = b"snot badger ".repeat(100);
+        let out = postprocess_slice(&mut pps, 0, &data, Some(&metrics))?;
+        assert_eq!(out, postprocess(&mut pps, 0, data.clone(), None)?);
+        assert_eq!(data.len() as u64, metrics.bytes_in());
+        assert_eq!(
+            out.iter().map(Vec::len).sum::<usize>() as u64,
+            metrics.bytes_out()
+        );
+        assert!(metrics.bytes_out() < metrics.bytes_in());
+        Ok(())
+    }
+
     #[test]
     fn textual_length_prefix_postp() {
         let mut post = TextualLength {};
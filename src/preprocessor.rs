@@ -15,15 +15,19 @@
 mod gelf;
 pub(crate) use gelf::Gelf;
 pub(crate) mod lines;
+pub(crate) mod verify;
 
 use crate::errors::{Error, Result};
 use crate::url::TremorUrl;
+use crate::OpConfig;
 use byteorder::{BigEndian, ByteOrder, ReadBytesExt};
 use bytes::buf::Buf;
 use bytes::BytesMut;
 use std::str;
 
 use std::io::{self, Read};
+use std::sync::Arc;
+use verify::{Verify, VerifyMetrics};
 
 //pub type Lines = lines::Lines;
 
@@ -69,6 +73,7 @@ pub fn lookup(name: &str) -> Result<Box<dyn Preprocessor>> {
         "ingest-ns" => Ok(Box::new(ExtractIngresTs {})),
         "length-prefixed" => Ok(Box::new(LengthPrefix::default())),
         "textual-length-prefix" => Ok(Box::new(TextualLength::default())),
+        "verify" => Err("The verify preprocessor requires a `preprocessor_config`".into()),
         _ => Err(format!("Preprocessor '{}' not found.", name).into()),
     }
 }
@@ -82,6 +87,30 @@ pub fn make_preprocessors(preprocessors: &[String]) -> Result<Preprocessors> {
     preprocessors.iter().map(|n| lookup(&n)).collect()
 }
 
+/// Like `make_preprocessors` for preprocessors that can be configured, the
+/// configuration of each is looked up by its name in `config`. Verifying
+/// preprocessors count into `metrics` if given.
+///
+/// # Errors
+///
+///   * If a preprocessor is not known or its config is invalid.
+pub(crate) fn make_configured_preprocessors(
+    preprocessors: &[String],
+    config: &Option<OpConfig>,
+    metrics: &Option<Arc<VerifyMetrics>>,
+) -> Result<Preprocessors> {
+    preprocessors
+        .iter()
+        .map(|name| -> Result<Box<dyn Preprocessor>> {
+            let config = config.as_ref().and_then(|c| c.get(name.as_str())).cloned();
+            match name.as_str() {
+                "verify" => Ok(Box::new(Verify::from_config(&config, metrics.clone())?)),
+                name => lookup(name),
+            }
+        })
+        .collect()
+}
+
 /// Canonical way to preprocess data before it is fed to a codec for decoding.
 ///
 /// Preprocessors might split up the given data in multiple chunks. Each of those
@@ -140,7 +140,7 @@ impl Preprocessor for Gelf {
 impl Gelf {
     fn enqueue(&mut self, ingest_ns: u64, msg: GelfSegment) -> Option<Vec<u8>> {
         // By sepc all incomplete chunks need to be destroyed after 5 seconds
-        if ingest_ns - self.last_swap > FIVE_SEC {
+        if ingest_ns.saturating_sub(self.last_swap) > FIVE_SEC {
             // clear the last buffer and swap current and last.
             self.last_swap = ingest_ns;
             self.last_buffer.clear();
@@ -270,4 +270,68 @@ mod test {
         let d = br#"{"snot": "badger"}"#;
         assert!(decode_gelf(d).is_ok());
     }
+
+    fn chunk(id: u8, seq: u8, count: u8, data: &[u8]) -> Vec<u8> {
+        let mut chunk = vec![0x1e, 0x0f, 0, 0, 0, 0, 0, 0, 0, id, seq, count];
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    fn out_of_order_chunks() -> Result<()> {
+        let mut gelf = Gelf::default();
+        let mut ingest_ns = 1;
+        assert!(gelf
+            .process(&mut ingest_ns, &chunk(1, 2, 3, b"c"))?
+            .is_empty());
+        // interleaved message
+        assert!(gelf
+            .process(&mut ingest_ns, &chunk(2, 1, 2, b"y"))?
+            .is_empty());
+        assert!(gelf
+            .process(&mut ingest_ns, &chunk(1, 0, 3, b"a"))?
+            .is_empty());
+        assert_eq!(
+            vec![b"abc".to_vec()],
+            gelf.process(&mut ingest_ns, &chunk(1, 1, 3, b"b"))?
+        );
+        assert_eq!(
+            vec![b"xy".to_vec()],
+            gelf.process(&mut ingest_ns, &chunk(2, 0, 2, b"x"))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_timeout() -> Result<()> {
+        let mut gelf = Gelf::default();
+        let mut ingest_ns = FIVE_SEC + 1;
+        assert!(gelf
+            .process(&mut ingest_ns, &chunk(1, 0, 2, b"a"))?
+            .is_empty());
+        // unrelated messages complete independently
+        let mut ingest_ns = 2 * FIVE_SEC + 1;
+        assert!(gelf
+            .process(&mut ingest_ns, &chunk(2, 0, 2, b"x"))?
+            .is_empty());
+        assert_eq!(
+            vec![b"xy".to_vec()],
+            gelf.process(&mut ingest_ns, &chunk(2, 1, 2, b"y"))?
+        );
+        // the first message is dropped after the second buffer swap
+        let mut ingest_ns = 4 * FIVE_SEC;
+        assert!(gelf
+            .process(&mut ingest_ns, &chunk(3, 0, 2, b"q"))?
+            .is_empty());
+        let mut ingest_ns = 6 * FIVE_SEC;
+        assert!(gelf
+            .process(&mut ingest_ns, &chunk(1, 1, 2, b"b"))?
+            .is_empty());
+        // out of order ingest times don't underflow
+        let mut ingest_ns = 1;
+        assert!(gelf
+            .process(&mut ingest_ns, &chunk(4, 0, 2, b"a"))?
+            .is_empty());
+        Ok(())
+    }
 }
@@ -0,0 +1,297 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Integrity verifying preprocessor.
+//!
+//! Verifies the checksum or signature each message carries as its `prefix`
+//! or `suffix`, `raw` or as lowercase `hex`, and passes on the message
+//! without it. Supported `algorithm`s are `crc32` (big endian), `sha256` and
+//! `hmac-sha256`, the HMAC key is given as `key` or read from the
+//! environment variable named in `key_env`, keeping it out of the config.
+//!
+//! Messages that fail verification are dropped with a warning, or with
+//! `on_failure: flag` sent to the `err` port of the onramp. Verified and
+//! failed messages are reported as `ramp_verify` metrics.
+//!
+//! ```yaml
+//! preprocessors:
+//!   - verify
+//! preprocessor_config:
+//!   verify:
+//!     algorithm: hmac-sha256
+//!     key_env: WEBHOOK_SECRET
+//!     format: hex
+//!     on_failure: flag
+//! ```
+
+use super::Preprocessor;
+use crate::errors::{Error, Result};
+use crate::OpConfig;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tremor_pipeline::ConfigImpl;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Algorithm {
+    Crc32,
+    Sha256,
+    HmacSha256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Position {
+    Prefix,
+    Suffix,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Format {
+    Raw,
+    Hex,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OnFailure {
+    Drop,
+    Flag,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct Config {
+    algorithm: Algorithm,
+    /// HMAC key
+    #[serde(default)]
+    key: Option<String>,
+    /// environment variable holding the HMAC key
+    #[serde(default)]
+    key_env: Option<String>,
+    /// where the checksum is
+    #[serde(default = "d_position")]
+    position: Position,
+    /// how the checksum is encoded
+    #[serde(default = "d_format")]
+    format: Format,
+    /// what to do with messages failing verification
+    #[serde(default = "d_on_failure")]
+    on_failure: OnFailure,
+}
+
+fn d_position() -> Position {
+    Position::Suffix
+}
+
+fn d_format() -> Format {
+    Format::Raw
+}
+
+fn d_on_failure() -> OnFailure {
+    OnFailure::Drop
+}
+
+impl ConfigImpl for Config {}
+
+/// Number of messages verified and failing verification
+#[derive(Debug, Default)]
+pub struct VerifyMetrics {
+    verified: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl VerifyMetrics {
+    /// messages that passed verification
+    pub fn verified(&self) -> u64 {
+        self.verified.load(Ordering::Relaxed)
+    }
+    /// messages that failed verification
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+pub(crate) struct Verify {
+    algorithm: Algorithm,
+    key: Vec<u8>,
+    position: Position,
+    format: Format,
+    on_failure: OnFailure,
+    metrics: Arc<VerifyMetrics>,
+}
+
+impl Verify {
+    pub(crate) fn from_config(
+        config: &Option<OpConfig>,
+        metrics: Option<Arc<VerifyMetrics>>,
+    ) -> Result<Self> {
+        let config = config
+            .as_ref()
+            .ok_or_else(|| Error::from("The verify preprocessor requires a config"))
+            .and_then(|c| Ok(Config::new(c)?))?;
+        let key = match (&config.key, &config.key_env) {
+            (Some(key), _) => key.as_bytes().to_vec(),
+            (None, Some(var)) => std::env::var(var)
+                .map_err(|e| Error::from(format!("Unable to read HMAC key from {}: {}", var, e)))?
+                .into_bytes(),
+            (None, None) => Vec::new(),
+        };
+        if config.algorithm == Algorithm::HmacSha256 && key.is_empty() {
+            return Err("The hmac-sha256 algorithm requires a `key` or `key_env`".into());
+        }
+        Ok(Self {
+            algorithm: config.algorithm,
+            key,
+            position: config.position,
+            format: config.format,
+            on_failure: config.on_failure,
+            metrics: metrics.unwrap_or_default(),
+        })
+    }
+
+    /// the length of raw checksums
+    fn raw_len(&self) -> usize {
+        match self.algorithm {
+            Algorithm::Crc32 => 4,
+            Algorithm::Sha256 | Algorithm::HmacSha256 => 32,
+        }
+    }
+
+    fn matches(&self, payload: &[u8], checksum: &[u8]) -> bool {
+        let checksum = match self.format {
+            Format::Raw => checksum.to_vec(),
+            Format::Hex => {
+                if let Some(decoded) = decode_hex(checksum) {
+                    decoded
+                } else {
+                    return false;
+                }
+            }
+        };
+        match self.algorithm {
+            Algorithm::Crc32 => crc32fast::hash(payload).to_be_bytes()[..] == checksum[..],
+            Algorithm::Sha256 => Sha256::digest(payload)[..] == checksum[..],
+            Algorithm::HmacSha256 => Hmac::<Sha256>::new_varkey(&self.key)
+                .map(|mut mac| {
+                    mac.update(payload);
+                    // compares in constant time
+                    mac.verify(&checksum).is_ok()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// decodes lowercase or uppercase hex, `None` if it isn't valid hex
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    let digit = |c: u8| char::from(c).to_digit(16);
+    hex.chunks(2)
+        .map(|pair| match pair {
+            [hi, lo] => Some((digit(*hi)? * 16 + digit(*lo)?) as u8),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Preprocessor for Verify {
+    #[cfg(not(tarpaulin_include))]
+    fn name(&self) -> &str {
+        "verify"
+    }
+
+    fn process(&mut self, _ingest_ns: &mut u64, data: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let len = match self.format {
+            Format::Raw => self.raw_len(),
+            Format::Hex => self.raw_len() * 2,
+        };
+        if data.len() >= len {
+            let (payload, checksum) = match self.position {
+                Position::Prefix => {
+                    let (checksum, payload) = data.split_at(len);
+                    (payload, checksum)
+                }
+                Position::Suffix => data.split_at(data.len() - len),
+            };
+            if self.matches(payload, checksum) {
+                self.metrics.verified.fetch_add(1, Ordering::Relaxed);
+                return Ok(vec![payload.to_vec()]);
+            }
+        }
+        self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+        match self.on_failure {
+            OnFailure::Drop => {
+                warn!("[Preprocessor::verify] Dropping a message that failed verification");
+                Ok(vec![])
+            }
+            OnFailure::Flag => Err("Message failed verification".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn verify(config: &str) -> Result<Verify> {
+        let config: OpConfig = serde_yaml::from_str(config)?;
+        Verify::from_config(&Some(config), None)
+    }
+
+    #[test]
+    fn crc32_suffix() -> Result<()> {
+        let mut pp = verify("algorithm: crc32")?;
+        let mut data = b"snot".to_vec();
+        data.extend_from_slice(&crc32fast::hash(b"snot").to_be_bytes());
+        assert_eq!(vec![b"snot".to_vec()], pp.process(&mut 0, &data)?);
+        data[0] = b'S';
+        assert!(pp.process(&mut 0, &data)?.is_empty());
+        // too short for a checksum
+        assert!(pp.process(&mut 0, b"abc")?.is_empty());
+        assert_eq!(1, pp.metrics.verified());
+        assert_eq!(2, pp.metrics.failed());
+        Ok(())
+    }
+
+    #[test]
+    fn hmac_hex_prefix() -> Result<()> {
+        let mut pp = verify(
+            "algorithm: hmac-sha256\nkey: secret\nposition: prefix\nformat: hex\non_failure: flag",
+        )?;
+        let mut mac = Hmac::<Sha256>::new_varkey(b"secret").map_err(|e| e.to_string())?;
+        mac.update(b"badger");
+        let signature: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let data = format!("{}badger", signature);
+        assert_eq!(
+            vec![b"badger".to_vec()],
+            pp.process(&mut 0, data.as_bytes())?
+        );
+        let forged = format!("{}badgers", signature);
+        assert!(pp.process(&mut 0, forged.as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn hmac_requires_key() {
+        assert!(verify("algorithm: hmac-sha256").is_err());
+    }
+}
@@ -0,0 +1,525 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Kafka Security
+//!
+//! Typed security settings of the `kafka` onramp and offramp, translated
+//! into the librdkafka `security.protocol`, `sasl.*` and `ssl.*` options.
+//! They are validated when the onramp or offramp is published, and can't be
+//! combined with the same options given in `rdkafka_options`.
+//!
+//! ```yaml
+//! security:
+//!   sasl:
+//!     mechanism: SCRAM-SHA-512
+//!     username: tremor
+//!     password: snot
+//!   tls:
+//!     ca: /etc/kafka/ca.pem
+//!     cert: /etc/kafka/client.pem
+//!     key: /etc/kafka/client.key
+//! ```
+//!
+//! The `protocol` is derived from `sasl` and `tls` if it isn't set.
+//!
+//! `OAUTHBEARER` tokens are read from `token_file` and handed to the client
+//! every `refresh_interval` seconds, so a sidecar can keep the file up to
+//! date. For development `unsecured_jwt` configures librdkafka's unsecured
+//! tokens instead.
+
+use crate::errors::Result;
+use halfbrown::HashMap;
+use rdkafka::client::Client;
+use rdkafka::config::ClientConfig;
+use rdkafka::ClientContext;
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tremor_common::time::nanotime;
+
+/// The `security.protocol`
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// neither authentication nor encryption
+    Plaintext,
+    /// TLS
+    Ssl,
+    /// SASL without TLS
+    SaslPlaintext,
+    /// SASL over TLS
+    SaslSsl,
+}
+
+impl Protocol {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Plaintext => "plaintext",
+            Self::Ssl => "ssl",
+            Self::SaslPlaintext => "sasl_plaintext",
+            Self::SaslSsl => "sasl_ssl",
+        }
+    }
+}
+
+/// SASL authentication
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "mechanism")]
+pub enum Sasl {
+    /// username and password in plain text, use with TLS
+    #[serde(rename = "PLAIN")]
+    Plain { username: String, password: String },
+    /// SCRAM with SHA-256
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256 { username: String, password: String },
+    /// SCRAM with SHA-512
+    #[serde(rename = "SCRAM-SHA-512")]
+    ScramSha512 { username: String, password: String },
+    /// Kerberos
+    #[serde(rename = "GSSAPI")]
+    Gssapi {
+        /// kerberos principal name of the brokers
+        #[serde(default = "d_service_name")]
+        service_name: String,
+        /// client principal
+        principal: String,
+        /// keytab of the client principal
+        keytab: String,
+    },
+    /// OAuth 2 bearer tokens
+    #[serde(rename = "OAUTHBEARER")]
+    OAuthBearer {
+        /// file holding the current token
+        #[serde(default = "Default::default")]
+        token_file: Option<String>,
+        /// principal the token is issued to
+        #[serde(default = "Default::default")]
+        principal: Option<String>,
+        /// seconds between reads of `token_file`
+        #[serde(default = "d_refresh_interval")]
+        refresh_interval: u64,
+        /// seconds a token is valid after it was read
+        #[serde(default = "d_token_lifetime")]
+        token_lifetime: u64,
+        /// librdkafka unsecured token config like `principal=admin`, for
+        /// development only
+        #[serde(default = "Default::default")]
+        unsecured_jwt: Option<String>,
+    },
+}
+
+fn d_service_name() -> String {
+    "kafka".to_string()
+}
+
+fn d_refresh_interval() -> u64 {
+    60
+}
+
+fn d_token_lifetime() -> u64 {
+    3600
+}
+
+impl Sasl {
+    fn mechanism(&self) -> &'static str {
+        match self {
+            Self::Plain { .. } => "PLAIN",
+            Self::ScramSha256 { .. } => "SCRAM-SHA-256",
+            Self::ScramSha512 { .. } => "SCRAM-SHA-512",
+            Self::Gssapi { .. } => "GSSAPI",
+            Self::OAuthBearer { .. } => "OAUTHBEARER",
+        }
+    }
+}
+
+/// TLS encryption and client certificates
+#[derive(Deserialize, Debug, Clone)]
+pub struct Tls {
+    /// CA bundle to verify the brokers with, the system CAs are used if not
+    /// set
+    #[serde(default = "Default::default")]
+    pub ca: Option<String>,
+    /// client certificate for mTLS
+    #[serde(default = "Default::default")]
+    pub cert: Option<String>,
+    /// private key of the client certificate
+    #[serde(default = "Default::default")]
+    pub key: Option<String>,
+    /// password of the private key
+    #[serde(default = "Default::default")]
+    pub key_password: Option<String>,
+    /// verify the broker hostnames against their certificates
+    #[serde(default = "d_verify_hostname")]
+    pub verify_hostname: bool,
+}
+
+fn d_verify_hostname() -> bool {
+    true
+}
+
+/// Security settings
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Security {
+    /// `plaintext`, `ssl`, `sasl_plaintext` or `sasl_ssl`
+    #[serde(default = "Default::default")]
+    pub protocol: Option<Protocol>,
+    /// SASL authentication
+    #[serde(default = "Default::default")]
+    pub sasl: Option<Sasl>,
+    /// TLS encryption
+    #[serde(default = "Default::default")]
+    pub tls: Option<Tls>,
+}
+
+/// prefixes of the librdkafka options set from the security settings
+const OPTION_PREFIXES: [&str; 4] = ["security.protocol", "sasl.", "ssl.", "enable.sasl."];
+
+fn file_exists(what: &str, path: &str) -> Result<()> {
+    if Path::new(path).is_file() {
+        Ok(())
+    } else {
+        Err(format!("Kafka {} `{}` not found", what, path).into())
+    }
+}
+
+impl Security {
+    /// the given protocol, or the one implied by `sasl` and `tls`
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+            .unwrap_or(match (self.sasl.is_some(), self.tls.is_some()) {
+                (false, false) => Protocol::Plaintext,
+                (false, true) => Protocol::Ssl,
+                (true, false) => Protocol::SaslPlaintext,
+                (true, true) => Protocol::SaslSsl,
+            })
+    }
+
+    /// Validates the settings, and that they are not also given as
+    /// `rdkafka_options`
+    ///
+    /// # Errors
+    ///   * if settings are missing, contradict each other or a file is not
+    ///     found
+    pub fn validate(&self, rdkafka_options: Option<&HashMap<String, String>>) -> Result<()> {
+        if let Some(k) = rdkafka_options
+            .iter()
+            .flat_map(|o| o.keys())
+            .find(|k| OPTION_PREFIXES.iter().any(|prefix| k.starts_with(prefix)))
+        {
+            return Err(format!(
+                "Kafka option `{}` is set by `security`, it can't be given in `rdkafka_options` too",
+                k
+            )
+            .into());
+        }
+        let protocol = self.protocol();
+        match (protocol, &self.sasl, &self.tls) {
+            (Protocol::Plaintext, Some(_), _) | (Protocol::Ssl, Some(_), _) => {
+                return Err(format!(
+                    "Kafka security protocol `{}` doesn't use `sasl`",
+                    protocol.as_str()
+                )
+                .into())
+            }
+            (Protocol::SaslPlaintext, None, _) | (Protocol::SaslSsl, None, _) => {
+                return Err(format!(
+                    "Kafka security protocol `{}` requires `sasl`",
+                    protocol.as_str()
+                )
+                .into())
+            }
+            (Protocol::Plaintext, _, Some(_)) | (Protocol::SaslPlaintext, _, Some(_)) => {
+                return Err(format!(
+                    "Kafka security protocol `{}` doesn't use `tls`",
+                    protocol.as_str()
+                )
+                .into())
+            }
+            _ => (),
+        }
+        match &self.sasl {
+            Some(Sasl::Plain { username, .. })
+            | Some(Sasl::ScramSha256 { username, .. })
+            | Some(Sasl::ScramSha512 { username, .. })
+                if username.is_empty() =>
+            {
+                return Err("Kafka SASL `username` must not be empty".into());
+            }
+            Some(Sasl::Gssapi { keytab, .. }) => file_exists("keytab", keytab)?,
+            Some(Sasl::OAuthBearer {
+                token_file,
+                principal,
+                unsecured_jwt,
+                ..
+            }) => match (token_file, unsecured_jwt) {
+                (Some(token_file), None) => {
+                    if principal.is_none() {
+                        return Err("Kafka OAUTHBEARER `token_file` requires `principal`".into());
+                    }
+                    file_exists("token file", token_file)?;
+                }
+                (None, Some(_)) => (),
+                _ => {
+                    return Err(
+                        "Kafka OAUTHBEARER requires one of `token_file` or `unsecured_jwt`".into(),
+                    )
+                }
+            },
+            _ => (),
+        }
+        if let Some(tls) = &self.tls {
+            match (&tls.cert, &tls.key) {
+                (Some(cert), Some(key)) => {
+                    file_exists("client certificate", cert)?;
+                    file_exists("client key", key)?;
+                }
+                (None, None) => (),
+                _ => return Err("Kafka TLS `cert` and `key` must be given together".into()),
+            }
+            if let Some(ca) = &tls.ca {
+                file_exists("CA bundle", ca)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the librdkafka options
+    pub fn options(&self) -> Vec<(&'static str, String)> {
+        let mut options = vec![("security.protocol", self.protocol().as_str().to_string())];
+        if let Some(sasl) = &self.sasl {
+            options.push(("sasl.mechanisms", sasl.mechanism().to_string()));
+            match sasl {
+                Sasl::Plain { username, password }
+                | Sasl::ScramSha256 { username, password }
+                | Sasl::ScramSha512 { username, password } => {
+                    options.push(("sasl.username", username.clone()));
+                    options.push(("sasl.password", password.clone()));
+                }
+                Sasl::Gssapi {
+                    service_name,
+                    principal,
+                    keytab,
+                } => {
+                    options.push(("sasl.kerberos.service.name", service_name.clone()));
+                    options.push(("sasl.kerberos.principal", principal.clone()));
+                    options.push(("sasl.kerberos.keytab", keytab.clone()));
+                }
+                Sasl::OAuthBearer { unsecured_jwt, .. } => {
+                    if let Some(config) = unsecured_jwt {
+                        options.push(("enable.sasl.oauthbearer.unsecure.jwt", "true".to_string()));
+                        options.push(("sasl.oauthbearer.config", config.clone()));
+                    }
+                }
+            }
+        }
+        if let Some(tls) = &self.tls {
+            if let Some(ca) = &tls.ca {
+                options.push(("ssl.ca.location", ca.clone()));
+            }
+            if let Some(cert) = &tls.cert {
+                options.push(("ssl.certificate.location", cert.clone()));
+            }
+            if let Some(key) = &tls.key {
+                options.push(("ssl.key.location", key.clone()));
+            }
+            if let Some(password) = &tls.key_password {
+                options.push(("ssl.key.password", password.clone()));
+            }
+            let algorithm = if tls.verify_hostname { "https" } else { "none" };
+            options.push((
+                "ssl.endpoint.identification.algorithm",
+                algorithm.to_string(),
+            ));
+        }
+        options
+    }
+
+    /// sets the librdkafka options
+    pub fn apply(&self, client_config: &mut ClientConfig) {
+        for (k, v) in self.options() {
+            client_config.set(k, &v);
+        }
+    }
+
+    /// the token refresh of `OAUTHBEARER` with a `token_file`
+    pub fn token_refresh(&self) -> Option<TokenRefresh> {
+        if let Some(Sasl::OAuthBearer {
+            token_file: Some(token_file),
+            principal,
+            refresh_interval,
+            token_lifetime,
+            ..
+        }) = &self.sasl
+        {
+            Some(TokenRefresh {
+                token_file: token_file.clone(),
+                principal: principal.clone().unwrap_or_default(),
+                interval: Duration::from_secs(*refresh_interval),
+                lifetime_ms: token_lifetime.saturating_mul(1000),
+                last: None,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Hands `OAUTHBEARER` tokens read from a file to a client
+#[derive(Debug)]
+pub struct TokenRefresh {
+    token_file: String,
+    principal: String,
+    interval: Duration,
+    lifetime_ms: u64,
+    last: Option<Instant>,
+}
+
+impl TokenRefresh {
+    /// the token is handed to the client again on the next refresh, e.g. for
+    /// a new client
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// reads the token and hands it to the client if `refresh_interval`
+    /// passed since it was last read
+    ///
+    /// # Errors
+    ///   * if the token can't be read or the client rejects it
+    pub fn refresh<C: ClientContext>(&mut self, client: &Client<C>) -> Result<()> {
+        if self
+            .last
+            .map_or(false, |last| last.elapsed() < self.interval)
+        {
+            return Ok(());
+        }
+        self.last = Some(Instant::now());
+        let token = std::fs::read_to_string(&self.token_file)?;
+        let token = CString::new(token.trim())
+            .map_err(|e| format!("Invalid Kafka OAUTHBEARER token: {}", e))?;
+        let principal = CString::new(self.principal.as_str())
+            .map_err(|e| format!("Invalid Kafka OAUTHBEARER principal: {}", e))?;
+        let lifetime_ms = nanotime() / 1_000_000 + self.lifetime_ms;
+        let lifetime_ms = i64::try_from(lifetime_ms).unwrap_or(i64::MAX);
+        set_token(client, &token, lifetime_ms, &principal)
+    }
+}
+
+fn set_token<C: ClientContext>(
+    client: &Client<C>,
+    token: &CString,
+    lifetime_ms: i64,
+    principal: &CString,
+) -> Result<()> {
+    const LEN: usize = 512;
+    let mut errstr = [0_i8; LEN];
+    let code = unsafe {
+        rdkafka_sys::bindings::rd_kafka_oauthbearer_set_token(
+            client.native_ptr(),
+            token.as_ptr(),
+            lifetime_ms,
+            principal.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            errstr.as_mut_ptr(),
+            LEN,
+        )
+    };
+    if code == rdkafka::types::RDKafkaRespErr::RD_KAFKA_RESP_ERR_NO_ERROR {
+        Ok(())
+    } else {
+        let msg = unsafe { rdkafka::util::cstr_to_owned(errstr.as_ptr()) };
+        Err(format!("Kafka rejected the OAUTHBEARER token: {}", msg).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn security(yaml: &str) -> Result<Security> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    #[test]
+    fn scram_over_tls() -> Result<()> {
+        let s = security(
+            r#"
+sasl:
+  mechanism: SCRAM-SHA-512
+  username: tremor
+  password: snot
+tls:
+  verify_hostname: false
+"#,
+        )?;
+        s.validate(None)?;
+        assert_eq!(
+            vec![
+                ("security.protocol", "sasl_ssl".to_string()),
+                ("sasl.mechanisms", "SCRAM-SHA-512".to_string()),
+                ("sasl.username", "tremor".to_string()),
+                ("sasl.password", "snot".to_string()),
+                ("ssl.endpoint.identification.algorithm", "none".to_string()),
+            ],
+            s.options()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> Result<()> {
+        let s = security("protocol: ssl\nsasl: {mechanism: PLAIN, username: a, password: b}")?;
+        assert!(s.validate(None).is_err());
+        let s = security("protocol: sasl_ssl")?;
+        assert!(s.validate(None).is_err());
+        let s = security("tls: {cert: Cargo.toml}")?;
+        assert!(s.validate(None).is_err());
+        let s = security("tls: {ca: /does/not/exist.pem}")?;
+        assert!(s.validate(None).is_err());
+        let s = security("sasl: {mechanism: OAUTHBEARER}")?;
+        assert!(s.validate(None).is_err());
+        let s = security("sasl: {mechanism: OAUTHBEARER, token_file: Cargo.toml}")?;
+        assert!(s.validate(None).is_err());
+        assert!(security("sasl: {mechanism: SNOT}").is_err());
+
+        let s = security("tls: {ca: Cargo.toml}")?;
+        s.validate(None)?;
+        let mut options = HashMap::new();
+        options.insert("ssl.ca.location".to_string(), "ca.pem".to_string());
+        assert!(s.validate(Some(&options)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn oauthbearer() -> Result<()> {
+        let s =
+            security("sasl: {mechanism: OAUTHBEARER, token_file: Cargo.toml, principal: tremor}")?;
+        s.validate(None)?;
+        assert_eq!(
+            vec![
+                ("security.protocol", "sasl_plaintext".to_string()),
+                ("sasl.mechanisms", "OAUTHBEARER".to_string()),
+            ],
+            s.options()
+        );
+        assert!(s.token_refresh().is_some());
+        let s = security("sasl: {mechanism: OAUTHBEARER, unsecured_jwt: principal=admin}")?;
+        s.validate(None)?;
+        assert!(s.token_refresh().is_none());
+        assert_eq!(4, s.options().len());
+        Ok(())
+    }
+}
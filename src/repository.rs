@@ -370,6 +370,7 @@ impl Repositories {
         system: bool,
         artefact: OnrampArtefact,
     ) -> Result<OnrampArtefact> {
+        crate::onramp::validate(&artefact.binding_type, &artefact.config)?;
         let (tx, rx) = bounded(1);
         self.onramp
             .send(Msg::PublishArtefact(tx, id.clone(), system, artefact))
@@ -456,6 +457,7 @@ impl Repositories {
         system: bool,
         artefact: OfframpArtefact,
     ) -> Result<OfframpArtefact> {
+        crate::offramp::validate(&artefact.binding_type, &artefact.config)?;
         let (tx, rx) = bounded(1);
         self.offramp
             .send(Msg::PublishArtefact(tx, id.clone(), system, artefact))
@@ -584,4 +586,69 @@ impl Repositories {
             .await?;
         rx.recv().await?
     }
+
+    /// Lists the bindings referring to an onramp, offramp or pipeline
+    ///
+    /// # Errors
+    ///  * if we can't serialize the bindings
+    pub async fn referrers(&self, id: &TremorUrl) -> Result<Vec<TremorUrl>> {
+        self.serialize_bindings()
+            .await?
+            .into_iter()
+            .filter(|b| refers_to(&b.binding, id))
+            .map(|b| TremorUrl::parse(&format!("/binding/{}", b.binding.id)))
+            .collect()
+    }
+
+    /// Ensures an onramp, offramp or pipeline is not referred to by any binding
+    ///
+    /// # Errors
+    ///  * if the artefact is still referenced
+    pub async fn ensure_unreferenced(&self, id: &TremorUrl) -> Result<()> {
+        let referrers = self.referrers(id).await?;
+        if referrers.is_empty() {
+            Ok(())
+        } else {
+            Err(ErrorKind::UnpublishFailedReferenced(
+                id.to_string(),
+                referrers.iter().map(ToString::to_string).collect(),
+            )
+            .into())
+        }
+    }
+}
+
+/// Checks if any link of the binding refers to the artefact of `id`
+fn refers_to(binding: &crate::config::Binding, id: &TremorUrl) -> bool {
+    binding
+        .links
+        .iter()
+        .flat_map(|(from, tos)| std::iter::once(from).chain(tos.iter()))
+        .any(|url| url.resource_type() == id.resource_type() && url.artefact() == id.artefact())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn binding_refers_to() -> Result<()> {
+        let binding: crate::config::Binding = serde_yaml::from_str(
+            r#"
+id: default
+links:
+  /onramp/blaster/{instance}/out: [/pipeline/main/{instance}/in]
+  /pipeline/main/{instance}/out: [/offramp/blackhole/{instance}/in]
+"#,
+        )?;
+        assert!(refers_to(&binding, &TremorUrl::parse("/onramp/blaster")?));
+        assert!(refers_to(&binding, &TremorUrl::parse("/pipeline/main")?));
+        assert!(refers_to(
+            &binding,
+            &TremorUrl::parse("/offramp/blackhole")?
+        ));
+        assert!(!refers_to(&binding, &TremorUrl::parse("/offramp/blaster")?));
+        assert!(!refers_to(&binding, &TremorUrl::parse("/pipeline/other")?));
+        Ok(())
+    }
 }
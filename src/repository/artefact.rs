@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::config::CodecSpec;
 use crate::errors::{Error, Result};
+use crate::filter::Filter;
 use crate::metrics::RampReporter;
 use crate::offramp;
 use crate::onramp;
@@ -198,9 +200,9 @@ impl Artefact for OfframpArtefact {
         // lookup codecs already here
         // this will bail out early if something is mistyped or so
         let codec = if let Some(codec) = &self.codec {
-            codec::lookup(&codec)?
+            codec::lookup_spec(codec, &self.codec_config)?
         } else {
-            codec::lookup(offramp.default_codec())?
+            codec::lookup_with_config(offramp.default_codec(), &self.codec_config)?
         };
         let mut resolved_codec_map = codec::builtin_codec_map();
         // override the builtin map
@@ -222,6 +224,7 @@ impl Artefact for OfframpArtefact {
             vec![]
         };
         let metrics_reporter = RampReporter::new(servant_id.clone(), self.metrics_interval_s);
+        let stats = metrics_reporter.stats();
 
         let (tx, rx) = bounded(1);
 
@@ -230,7 +233,7 @@ impl Artefact for OfframpArtefact {
             .send(system::ManagerMsg::CreateOfframp(
                 tx,
                 Box::new(offramp::Create {
-                    id: servant_id,
+                    id: servant_id.clone(),
                     codec,
                     codec_map: resolved_codec_map,
                     offramp,
@@ -241,7 +244,9 @@ impl Artefact for OfframpArtefact {
                 }),
             ))
             .await?;
-        rx.recv().await?
+        let addr = rx.recv().await??;
+        world.register_stats(servant_id, stats).await;
+        Ok(addr)
     }
 
     async fn link(
@@ -328,7 +333,7 @@ impl Artefact for OnrampArtefact {
     async fn spawn(&self, world: &World, servant_id: ServantId) -> Result<Self::SpawnResult> {
         let stream = onramp::lookup(&self.binding_type, &servant_id, &self.config)?;
         let codec = self.codec.as_ref().map_or_else(
-            || stream.default_codec().to_string(),
+            || CodecSpec::from(stream.default_codec()),
             std::clone::Clone::clone,
         );
         let codec_map = self
@@ -347,6 +352,7 @@ impl Artefact for OnrampArtefact {
         };
 
         let metrics_reporter = RampReporter::new(servant_id.clone(), self.metrics_interval_s);
+        let stats = metrics_reporter.stats();
         let (tx, rx) = bounded(1);
 
         world
@@ -354,19 +360,24 @@ impl Artefact for OnrampArtefact {
             .send(system::ManagerMsg::CreateOnramp(
                 tx,
                 Box::new(onramp::Create {
-                    id: servant_id,
+                    id: servant_id.clone(),
                     preprocessors,
+                    preprocessor_config: self.preprocessor_config.clone(),
                     postprocessors,
                     codec,
+                    codec_config: self.codec_config.clone(),
                     codec_map,
                     stream,
                     metrics_reporter,
                     is_linked: self.is_linked,
                     err_required: self.err_required,
+                    correlation: self.correlation.clone(),
                 }),
             ))
             .await?;
-        rx.recv().await?
+        let addr = rx.recv().await??;
+        world.register_stats(servant_id, stats).await;
+        Ok(addr)
     }
 
     async fn link(
@@ -483,7 +494,7 @@ impl Artefact for Binding {
         mappings: HashMap<Self::LinkLHS, Self::LinkRHS>,
     ) -> Result<Self::LinkResult> {
         let mut pipelines: Vec<(TremorUrl, TremorUrl)> = Vec::new(); // pipeline -> {onramp, offramp, pipeline}
-        let mut onramps: Vec<(TremorUrl, TremorUrl)> = Vec::new(); // onramp -> pipeline
+        let mut onramps: Vec<(TremorUrl, TremorUrl, Option<Filter>)> = Vec::new(); // onramp -> pipeline
         let mut offramps: Vec<(TremorUrl, TremorUrl)> = Vec::new(); // linked offramps -> pipeline
         let mut res = self.clone();
         res.binding.links.clear();
@@ -515,7 +526,8 @@ impl Artefact for Binding {
                         tos.push(to.clone());
                         match (from.resource_type(), to.resource_type()) {
                             (Some(ResourceType::Onramp), Some(ResourceType::Pipeline)) => {
-                                onramps.push((from.clone(), to));
+                                let filter = self.binding.filters.get(&dst).cloned();
+                                onramps.push((from.clone(), to, filter));
                             }
                             (Some(ResourceType::Pipeline), Some(ResourceType::Offramp))
                             | (Some(ResourceType::Pipeline), Some(ResourceType::Pipeline))
@@ -583,9 +595,20 @@ impl Artefact for Binding {
             }
         }
 
-        for (from, to) in onramps {
+        for (from, to, filter) in onramps {
             system.ensure_pipeline(&to).await?;
             system.ensure_onramp(&from).await?;
+            // the filter has to be in place before the first event is sent
+            if let Some(filter) = filter {
+                if let Some(onramp) = system.reg.find_onramp(&from).await? {
+                    onramp
+                        .send(onramp::Msg::Filter {
+                            id: to.clone(),
+                            filter,
+                        })
+                        .await?;
+                }
+            }
             system
                 .link_onramp(
                     &from,
@@ -18,27 +18,47 @@ use crate::sink::prelude::*;
 use crate::url::TremorUrl;
 use async_channel::Sender;
 use halfbrown::HashMap;
+use tremor_pipeline::SignalKind;
 
+pub(crate) mod amqp;
+pub(crate) mod archive;
+pub(crate) mod batching;
+pub(crate) mod bigquery;
 pub(crate) mod blackhole;
 pub(crate) mod cb;
+pub(crate) mod cloudwatch_logs;
+pub(crate) mod cloudwatch_metrics;
 pub(crate) mod debug;
 pub(crate) mod dns;
 pub(crate) mod elastic;
+pub(crate) mod eventhubs;
 pub(crate) mod exit;
 pub(crate) mod file;
+pub(crate) mod gcl;
 pub(crate) mod gcs;
+pub(crate) mod graphql;
+pub(crate) mod grpc;
 pub(crate) mod kafka;
 pub(crate) mod kv;
+pub(crate) mod lb;
+pub(crate) mod mirror;
 pub(crate) mod nats;
 pub(crate) mod newrelic;
 pub(crate) mod otel;
 pub(crate) mod postgres;
 pub(crate) mod prelude;
+pub(crate) mod pubsub;
+pub(crate) mod redis;
 pub(crate) mod rest;
+pub(crate) mod sns;
+pub(crate) mod sqs;
+pub(crate) mod sse;
 pub(crate) mod stderr;
 pub(crate) mod stdout;
 pub(crate) mod tcp;
 pub(crate) mod udp;
+#[cfg(unix)]
+pub(crate) mod unix_socket;
 pub(crate) mod ws;
 
 #[derive(Debug)]
@@ -103,6 +123,23 @@ pub(crate) trait Sink {
     /// Callback for graceful shutdown (default behaviour: do nothing)
     async fn terminate(&mut self) {}
 
+    // this empty function passed manual inspect, it is bug free
+    #[cfg(not(tarpaulin_include))]
+    /// Flushes data the sink buffered (default behaviour: do nothing)
+    ///
+    /// Called by the `SinkManager` once the sink received no events for
+    /// `flush_timeout`, on a shutdown signal and before `terminate`, as long
+    /// as events were received since the last flush.
+    async fn flush(&mut self) -> ResultVec {
+        Ok(None)
+    }
+
+    /// Nanoseconds without events after which buffered data is flushed,
+    /// `None` if the sink doesn't need idle flushes
+    fn flush_timeout(&self) -> Option<u64> {
+        None
+    }
+
     /// Is the sink active and ready to process events
     fn is_active(&self) -> bool;
 
@@ -127,6 +164,9 @@ where
     pipelines: HashMap<TremorUrl, pipeline::Addr>,
     // for linked offramps
     dest_pipelines: HashMap<Cow<'static, str>, Vec<(TremorUrl, pipeline::Addr)>>,
+    // events were received since the last flush
+    needs_flush: bool,
+    last_event_ns: u64,
 }
 
 impl<T> SinkManager<T>
@@ -139,6 +179,8 @@ where
             sink,
             pipelines: HashMap::new(),
             dest_pipelines: HashMap::new(),
+            needs_flush: false,
+            last_event_ns: 0,
         }
     }
 
@@ -149,6 +191,50 @@ where
     fn has_dest_pipelines(&self) -> bool {
         self.dest_pipelines.values().any(|xs| !xs.is_empty())
     }
+
+    /// flushes the sink if it received events since the last flush
+    async fn flush(&mut self) {
+        if !self.needs_flush {
+            return;
+        }
+        self.needs_flush = false;
+        match self.sink.flush().await {
+            Ok(Some(replies)) => self.handle_replies(replies).await,
+            Ok(None) => (),
+            Err(e) => {
+                if let Some(sink_url) = &self.sink_url {
+                    error!("[Sink::{}] Error flushing: {}", sink_url, e);
+                }
+            }
+        }
+    }
+
+    /// we explicitly do not fail upon send errors, just log errors
+    async fn handle_replies(&mut self, replies: Vec<Reply>) {
+        for reply in replies {
+            match reply {
+                Reply::Insight(e) => {
+                    if let Err(e) = handle_insight(e, self.pipelines.values()).await {
+                        if let Some(sink_url) = &self.sink_url {
+                            error!("[Sink::{}] Error handling insight in sink: {}", sink_url, e);
+                        }
+                    }
+                }
+                Reply::Response(port, event) => {
+                    if let Some(pipelines) = self.dest_pipelines.get_mut(&port) {
+                        if let Err(e) = handle_response(event, pipelines.iter()).await {
+                            if let Some(sink_url) = &self.sink_url {
+                                error!(
+                                    "[Sink::{}] Error handling response in sink: {}",
+                                    sink_url, e
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -157,6 +243,7 @@ where
     T: Sink + Send,
 {
     async fn terminate(&mut self) {
+        self.flush().await;
         self.sink.terminate().await
     }
     #[allow(clippy::too_many_arguments)]
@@ -191,6 +278,8 @@ where
         input: &str,
         event: Event,
     ) -> Result<()> {
+        self.needs_flush = true;
+        self.last_event_ns = nanotime();
         if let Some(mut replies) = self.sink.on_event(input, codec, codec_map, event).await? {
             for reply in replies.drain(..) {
                 match reply {
@@ -235,6 +324,18 @@ where
     }
 
     async fn on_signal(&mut self, signal: Event) -> Option<Event> {
+        match signal.kind {
+            Some(SignalKind::Tick) => {
+                let idle = self.sink.flush_timeout().map_or(false, |timeout| {
+                    signal.ingest_ns.saturating_sub(self.last_event_ns) >= timeout
+                });
+                if idle {
+                    self.flush().await;
+                }
+            }
+            Some(SignalKind::Shutdown) => self.flush().await,
+            _ => (),
+        }
         let replies = match self.sink.on_signal(signal).await {
             Ok(results) => results?,
             Err(e) => {
@@ -244,29 +345,7 @@ where
                 return None;
             }
         };
-        for reply in replies {
-            match reply {
-                Reply::Insight(e) => {
-                    if let Err(e) = handle_insight(e, self.pipelines.values()).await {
-                        if let Some(sink_url) = &self.sink_url {
-                            error!("[Sink::{}] Error handling insight in sink: {}", sink_url, e);
-                        }
-                    }
-                }
-                Reply::Response(port, event) => {
-                    if let Some(pipelines) = self.dest_pipelines.get_mut(&port) {
-                        if let Err(e) = handle_response(event, pipelines.iter()).await {
-                            if let Some(sink_url) = &self.sink_url {
-                                error!(
-                                    "[Sink::{}] Error handling response in sink: {}",
-                                    sink_url, e
-                                );
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        self.handle_replies(replies).await;
         None
     }
 
@@ -0,0 +1,133 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Archive Offramp
+//!
+//! Writes events to a local archive of segment files with an index over the
+//! ingest time and the configured `index` fields, which can be queried via
+//! the `/offramp/{id}/archive` API endpoint, e.g.
+//! `GET /offramp/archive/archive?from=1620000000000000000&host.name=a&limit=10`.
+//!
+//! Writes are flushed after `flush_timeout` milliseconds without events and
+//! on shutdown.
+//!
+//! ## Configuration
+//!
+//! See [Config](../../archive/struct.Config.html) for details.
+
+use crate::archive::{Config, Writer};
+use crate::sink::prelude::*;
+use halfbrown::HashMap;
+
+pub struct Archive {
+    writer: Writer,
+    flush_timeout: u64,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for Archive {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(SinkManager::new_box(Self {
+                flush_timeout: config.flush_timeout,
+                writer: Writer::new(config),
+                sink_url: TremorUrl::from_offramp_id("archive")?, // dummy value
+            }))
+        } else {
+            Err("Archive offramp requires a config".into())
+        }
+    }
+}
+
+impl Archive {
+    async fn append(&mut self, event: &Event) -> Result<()> {
+        for value in event.value_iter() {
+            self.writer.append(event.ingest_ns, value).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Archive {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        _codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        match self.append(&event).await {
+            Ok(()) if event.transactional => Ok(Some(vec![Reply::Insight(event.insight_ack())])),
+            Ok(()) => Ok(None),
+            Err(e) => {
+                error!("[Sink::{}] Error archiving event: {}", self.sink_url, e);
+                if event.transactional {
+                    Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+
+    async fn flush(&mut self) -> ResultVec {
+        self.writer.flush().await?;
+        Ok(None)
+    }
+
+    fn flush_timeout(&self) -> Option<u64> {
+        Some(self.flush_timeout * 1_000_000)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        _processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {
+        if let Err(e) = self.writer.flush().await {
+            error!("[Sink::{}] Failed to flush archive: {}", self.sink_url, e);
+        }
+    }
+}
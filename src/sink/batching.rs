@@ -0,0 +1,241 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Batch sizes and flush timeouts of batching offramps, adjusted to the
+//! traffic and the health of the downstream system
+//!
+//! Without `dynamic_batching` an offramp batches with its configured
+//! `batch_size` and `flush_timeout`. With it those become the upper bounds,
+//! batches start at `min_batch_size` and are adjusted after every request:
+//!
+//! * a failed request halves the batch size
+//! * a request latency above `target_latency` shrinks it by a quarter
+//! * a full batch sent with a low error rate grows it by half, as there is
+//!   traffic to fill larger batches
+//! * a batch flushed before it was full shrinks it towards the size of that
+//!   batch, to keep the delay of events low
+//!
+//! The flush timeout follows the batch size between `min_flush_timeout` and
+//! `flush_timeout`, so small batches are flushed quickly.
+//!
+//! ```yaml
+//! batch_size: 1000
+//! flush_timeout: 5000
+//! dynamic_batching:
+//!   min_batch_size: 10
+//!   min_flush_timeout: 100
+//!   target_latency: 500
+//! ```
+
+use crate::errors::Result;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// error rate in permille below which batches may grow
+const HEALTHY_ERROR_RATE: u64 = 50;
+
+/// Bounds of the dynamic batching of an offramp
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct DynamicBatching {
+    /// smallest batch size
+    #[serde(default = "d_min_batch_size")]
+    pub min_batch_size: usize,
+    /// milliseconds without events after which the smallest batches are
+    /// flushed
+    #[serde(default = "d_min_flush_timeout")]
+    pub min_flush_timeout: u64,
+    /// request latency in milliseconds above which batches shrink
+    #[serde(default = "d_target_latency")]
+    pub target_latency: u64,
+}
+
+fn d_min_batch_size() -> usize {
+    1
+}
+
+fn d_min_flush_timeout() -> u64 {
+    10
+}
+
+fn d_target_latency() -> u64 {
+    1000
+}
+
+/// The current batch size and flush timeout of an offramp
+#[derive(Debug, Clone)]
+pub(crate) struct Batching {
+    dynamic: Option<DynamicBatching>,
+    max_batch_size: usize,
+    max_flush_timeout: u64,
+    batch_size: usize,
+    flush_timeout: u64,
+    /// moving average of the request latency in milliseconds
+    latency: u64,
+    /// moving average of the failed requests in permille
+    error_rate: u64,
+}
+
+impl Batching {
+    /// Batches with `batch_size` and `flush_timeout`, or between the
+    /// `dynamic` bounds and them
+    pub(crate) fn new(
+        batch_size: usize,
+        flush_timeout: u64,
+        dynamic: Option<DynamicBatching>,
+    ) -> Result<Self> {
+        let (min_batch_size, min_flush_timeout) =
+            dynamic.as_ref().map_or((batch_size, flush_timeout), |d| {
+                (d.min_batch_size, d.min_flush_timeout)
+            });
+        if min_batch_size == 0 || min_batch_size > batch_size {
+            return Err(format!(
+                "`min_batch_size` has to be between 1 and the `batch_size` of {}",
+                batch_size
+            )
+            .into());
+        }
+        if min_flush_timeout > flush_timeout {
+            return Err(format!(
+                "`min_flush_timeout` can't exceed the `flush_timeout` of {}",
+                flush_timeout
+            )
+            .into());
+        }
+        Ok(Self {
+            dynamic,
+            max_batch_size: batch_size,
+            max_flush_timeout: flush_timeout,
+            batch_size: min_batch_size,
+            flush_timeout: min_flush_timeout,
+            latency: 0,
+            error_rate: 0,
+        })
+    }
+
+    /// events per batch
+    pub(crate) fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// nanoseconds without events after which a batch is flushed
+    pub(crate) fn flush_timeout_ns(&self) -> u64 {
+        self.flush_timeout.saturating_mul(1_000_000)
+    }
+
+    /// Adjusts the batch size to a request sending `events`, `full` if the
+    /// batch was sent as it was full rather than flushed
+    pub(crate) fn record(&mut self, events: usize, full: bool, latency: Duration, ok: bool) {
+        let dynamic = if let Some(dynamic) = &self.dynamic {
+            dynamic
+        } else {
+            return;
+        };
+        let latency = u64::try_from(latency.as_millis()).unwrap_or(u64::MAX);
+        self.latency = (self.latency.saturating_mul(3).saturating_add(latency)) / 4;
+        self.error_rate = (self.error_rate * 3 + if ok { 0 } else { 1000 }) / 4;
+
+        let size = self.batch_size;
+        let size = if !ok {
+            size / 2
+        } else if self.latency > dynamic.target_latency {
+            size - size / 4
+        } else if full && self.error_rate < HEALTHY_ERROR_RATE {
+            size.saturating_add((size / 2).max(1))
+        } else if full {
+            size
+        } else {
+            events.max(size - size / 4)
+        };
+        self.batch_size = size.max(dynamic.min_batch_size).min(self.max_batch_size);
+
+        let sizes = (self.max_batch_size - dynamic.min_batch_size) as u64;
+        let timeouts = self.max_flush_timeout - dynamic.min_flush_timeout;
+        self.flush_timeout = if sizes == 0 {
+            self.max_flush_timeout
+        } else {
+            let above_min = (self.batch_size - dynamic.min_batch_size) as u64;
+            dynamic.min_flush_timeout + timeouts.saturating_mul(above_min) / sizes
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dynamic() -> DynamicBatching {
+        DynamicBatching {
+            min_batch_size: 10,
+            min_flush_timeout: 100,
+            target_latency: 500,
+        }
+    }
+
+    const FAST: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn fixed() -> Result<()> {
+        let mut batching = Batching::new(100, 1000, None)?;
+        batching.record(100, true, FAST, true);
+        batching.record(100, true, FAST, false);
+        assert_eq!(100, batching.batch_size());
+        assert_eq!(1_000_000_000, batching.flush_timeout_ns());
+        Ok(())
+    }
+
+    #[test]
+    fn bounds() {
+        assert!(Batching::new(5, 1000, Some(dynamic())).is_err());
+        assert!(Batching::new(100, 50, Some(dynamic())).is_err());
+        let mut zero = dynamic();
+        zero.min_batch_size = 0;
+        assert!(Batching::new(100, 1000, Some(zero)).is_err());
+    }
+
+    #[test]
+    fn adjusts() -> Result<()> {
+        let mut batching = Batching::new(100, 1000, Some(dynamic()))?;
+        assert_eq!(10, batching.batch_size());
+        assert_eq!(100_000_000, batching.flush_timeout_ns());
+
+        // under load batches grow up to the bounds
+        for _ in 0..10 {
+            let size = batching.batch_size();
+            batching.record(size, true, FAST, true);
+        }
+        assert_eq!(100, batching.batch_size());
+        assert_eq!(1_000_000_000, batching.flush_timeout_ns());
+
+        // failures halve them
+        batching.record(100, true, FAST, false);
+        assert_eq!(50, batching.batch_size());
+        assert_eq!(500_000_000, batching.flush_timeout_ns());
+
+        // and keep them from growing while the error rate is high
+        batching.record(50, true, FAST, true);
+        assert_eq!(50, batching.batch_size());
+
+        // slow requests shrink them
+        batching.record(50, true, Duration::from_secs(4), true);
+        assert_eq!(38, batching.batch_size());
+
+        // as does low traffic, down to the bounds
+        for _ in 0..20 {
+            batching.record(1, false, FAST, true);
+        }
+        assert_eq!(10, batching.batch_size());
+        assert_eq!(100_000_000, batching.flush_timeout_ns());
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # `BigQuery` Offramp
+//!
+//! Streams events as rows into a `BigQuery` table via the Storage Write API.
+//! Each event value becomes one row, its `columns` are filled from the event
+//! fields they map to, so no codec is involved.
+//!
+//! Rows are batched and appended once `batch_size` rows are buffered, or
+//! after `flush_timeout` milliseconds without events and on shutdown. With
+//! `dynamic_batching` the batch size and flush timeout are adjusted to the
+//! traffic and append latency as described in `sink::batching`. Appends
+//! are sent at the offset of their first row in the write stream, retrying a
+//! failed append can't write its rows twice. Events are acked once their
+//! rows were appended and failed if they don't match the columns, were
+//! rejected by `BigQuery` or the append didn't succeed within `retries`. In
+//! the latter case it is unknown if the rows were written and a new write
+//! stream is started for the next batch.
+//!
+//! ```yaml
+//! offramp:
+//!   - id: analytics
+//!     type: bigquery
+//!     config:
+//!       project_id: my-project
+//!       dataset: logs
+//!       table: requests
+//!       columns:
+//!         - name: host
+//!           type: STRING
+//!           mode: REQUIRED
+//!         - name: status
+//!           type: INT64
+//!           field: response.status
+//!         - name: received
+//!           type: TIMESTAMP
+//! ```
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::gcp::bigquery::{self, Append, Column};
+use crate::sink::batching::{Batching, DynamicBatching};
+use crate::sink::prelude::*;
+use halfbrown::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// project of the table
+    pub project_id: String,
+    /// dataset of the table
+    pub dataset: String,
+    /// name of the table
+    pub table: String,
+    /// columns of the table and the event fields they are filled from
+    pub columns: Vec<Column>,
+    /// rows per append
+    #[serde(default = "d_batch_size")]
+    pub batch_size: usize,
+    /// milliseconds without events after which buffered rows are appended
+    #[serde(default = "d_flush_timeout")]
+    pub flush_timeout: u64,
+    /// number of retries for appends that failed with a retryable error
+    #[serde(default = "d_retries")]
+    pub retries: u32,
+    /// initial backoff between retries in milliseconds, doubled on every retry
+    #[serde(default = "d_backoff")]
+    pub backoff_ms: u64,
+    /// deadline per call in milliseconds
+    #[serde(default = "d_timeout")]
+    pub timeout_ms: u64,
+    /// Storage Write API endpoint
+    #[serde(default = "d_endpoint")]
+    pub endpoint: String,
+    /// adjusts the batch size and flush timeout up to `batch_size` and
+    /// `flush_timeout` to the traffic and append latency
+    #[serde(default)]
+    pub dynamic_batching: Option<DynamicBatching>,
+}
+
+fn d_batch_size() -> usize {
+    500
+}
+
+fn d_flush_timeout() -> u64 {
+    1000
+}
+
+fn d_retries() -> u32 {
+    3
+}
+
+fn d_backoff() -> u64 {
+    100
+}
+
+fn d_timeout() -> u64 {
+    10_000
+}
+
+fn d_endpoint() -> String {
+    "https://bigquerystorage.googleapis.com".to_string()
+}
+
+impl ConfigImpl for Config {}
+
+pub struct BigQuery {
+    config: Config,
+    /// `projects/{project}/datasets/{dataset}/tables/{table}`
+    table: String,
+    descriptor: Vec<u8>,
+    client: Option<bigquery::Client>,
+    stream: Option<String>,
+    /// offset of the next row in `stream`
+    offset: u64,
+    rows: Vec<Vec<u8>>,
+    batching: Batching,
+    /// ack insights for the transactional events of the buffered rows
+    insights: Vec<Event>,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for BigQuery {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            if config.columns.is_empty() {
+                return Err("BigQuery offramp requires at least one column".into());
+            }
+            if config.batch_size == 0 {
+                return Err("BigQuery offramp requires a `batch_size` of at least 1".into());
+            }
+            let table = format!(
+                "projects/{}/datasets/{}/tables/{}",
+                config.project_id, config.dataset, config.table
+            );
+            let descriptor = bigquery::descriptor(&config.columns);
+            let batching = Batching::new(
+                config.batch_size,
+                config.flush_timeout,
+                config.dynamic_batching.clone(),
+            )?;
+            let rows = Vec::with_capacity(batching.batch_size());
+            Ok(SinkManager::new_box(Self {
+                config,
+                table,
+                descriptor,
+                client: None,
+                stream: None,
+                offset: 0,
+                rows,
+                batching,
+                insights: vec![],
+                sink_url: TremorUrl::from_offramp_id("bigquery")?, // dummy value
+            }))
+        } else {
+            Err("BigQuery offramp requires a config".into())
+        }
+    }
+}
+
+impl BigQuery {
+    /// appends the buffered rows once, connecting and creating a write
+    /// stream if there is none
+    async fn try_append(&mut self) -> Append {
+        if self.client.is_none() {
+            let timeout = Duration::from_millis(self.config.timeout_ms);
+            match bigquery::Client::connect(&self.config.endpoint, timeout).await {
+                Ok(client) => self.client = Some(client),
+                Err(e) => return Append::Retry(e.to_string()),
+            }
+        }
+        let client = if let Some(client) = &mut self.client {
+            client
+        } else {
+            return Append::Retry("BigQuery client not connected".to_string());
+        };
+        if self.stream.is_none() {
+            match client.create_stream(&self.table).await {
+                Ok(stream) => {
+                    info!("[Sink::{}] Writing to stream {}", self.sink_url, stream);
+                    self.stream = Some(stream);
+                    self.offset = 0;
+                }
+                Err(e) => return Append::Retry(e.to_string()),
+            }
+        }
+        let stream = self.stream.as_deref().unwrap_or_default();
+        client
+            .append(stream, self.offset, &self.descriptor, &self.rows)
+            .await
+    }
+
+    /// appends the buffered rows, retrying according to the retry policy,
+    /// and resolves the insights of their events, `full` if the rows are
+    /// appended as the batch is full
+    async fn append(&mut self, full: bool) -> Vec<Reply> {
+        if self.rows.is_empty() {
+            return vec![];
+        }
+        let start = Instant::now();
+        let mut backoff = self.config.backoff_ms;
+        let mut attempt = 0;
+        let outcome = loop {
+            match self.try_append().await {
+                Append::Retry(e) if attempt < self.config.retries => {
+                    attempt += 1;
+                    warn!(
+                        "[Sink::{}] Retrying append ({}/{}): {}",
+                        self.sink_url, attempt, self.config.retries, e
+                    );
+                    task::sleep(Duration::from_millis(backoff)).await;
+                    backoff = backoff.saturating_mul(2);
+                }
+                outcome => break outcome,
+            }
+        };
+        let success = match outcome {
+            Append::Ok | Append::AlreadyExists => {
+                self.offset += self.rows.len() as u64;
+                true
+            }
+            Append::Rejected(e) => {
+                error!("[Sink::{}] Rows rejected: {}", self.sink_url, e);
+                false
+            }
+            Append::Retry(e) => {
+                error!("[Sink::{}] Append failed: {}", self.sink_url, e);
+                self.client = None;
+                self.stream = None;
+                false
+            }
+            Append::Reset(e) => {
+                error!("[Sink::{}] Write stream out of sync: {}", self.sink_url, e);
+                self.stream = None;
+                false
+            }
+        };
+        self.batching
+            .record(self.rows.len(), full, start.elapsed(), success);
+        self.rows.clear();
+        self.insights
+            .drain(..)
+            .map(|mut insight| {
+                if !success {
+                    insight.cb = CbAction::Fail;
+                }
+                Reply::Insight(insight)
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for BigQuery {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        _codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        let rows: Result<Vec<_>> = event
+            .value_iter()
+            .map(|value| bigquery::encode_row(&self.config.columns, value))
+            .collect();
+        match rows {
+            Ok(rows) => {
+                self.rows.extend(rows);
+                if event.transactional {
+                    self.insights.push(event.insight_ack());
+                }
+                if self.rows.len() >= self.batching.batch_size() {
+                    Ok(Some(self.append(true).await))
+                } else {
+                    Ok(None)
+                }
+            }
+            Err(e) => {
+                error!("[Sink::{}] Invalid event: {}", self.sink_url, e);
+                if event.transactional {
+                    Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+
+    async fn flush(&mut self) -> ResultVec {
+        Ok(Some(self.append(false).await))
+    }
+
+    fn flush_timeout(&self) -> Option<u64> {
+        Some(self.batching.flush_timeout_ns())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        _processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {}
+}
@@ -0,0 +1,351 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # CloudWatch Logs Offramp
+//!
+//! Sends events, encoded with the configured codec, as log events to AWS
+//! CloudWatch Logs. `log_group` and `log_stream` are templates like the
+//! `rest` offramp `path`, e.g. `/tremor/{$app}` or `{host}`, filled from the
+//! event data or `$` prefixed metadata.
+//!
+//! Events are batched per log stream and sent once a batch reaches
+//! `batch_size`, the size limit of a `PutLogEvents` call or spans 24 hours,
+//! and after `flush_timeout` milliseconds without events. With
+//! `dynamic_batching` the batch size and flush timeout are adjusted to the
+//! traffic and request latency as described in `sink::batching`. Sequence tokens are
+//! tracked per log stream, calls rejected for a wrong token are retried with
+//! the expected one. Missing log streams are created unless
+//! `create_log_stream` is disabled.
+//!
+//! Credentials are read from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+//! and `AWS_SESSION_TOKEN` environment variables or the shared credentials
+//! file.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::aws::auth::{self, AwsClient};
+use crate::connectors::aws::cloudwatch::{
+    self, LogEvent, PutLogs, LOG_EVENT_OVERHEAD, MAX_LOG_BYTES, MAX_LOG_EVENTS, MAX_LOG_SPAN_MS,
+};
+use crate::sink::batching::{Batching, DynamicBatching};
+use crate::sink::prelude::*;
+use crate::sink::rest::render;
+use halfbrown::HashMap;
+use std::time::Instant;
+
+/// attempts per batch, to recover from sequence token mismatches and missing
+/// log streams
+const MAX_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// AWS region, defaults to `AWS_REGION`
+    #[serde(default)]
+    pub region: Option<String>,
+    /// log group name template
+    pub log_group: String,
+    /// log stream name template
+    pub log_stream: String,
+    /// create log streams that don't exist
+    #[serde(default = "d_true")]
+    pub create_log_stream: bool,
+    /// log events per call, at most 10000
+    #[serde(default = "d_batch_size")]
+    pub batch_size: usize,
+    /// milliseconds without events after which batched events are sent
+    #[serde(default = "d_flush_timeout")]
+    pub flush_timeout: u64,
+    /// overrides the `logs.{region}.amazonaws.com` endpoint
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// adjusts the batch size and flush timeout up to `batch_size` and
+    /// `flush_timeout` to the traffic and request latency
+    #[serde(default)]
+    pub dynamic_batching: Option<DynamicBatching>,
+}
+
+fn d_true() -> bool {
+    true
+}
+
+fn d_batch_size() -> usize {
+    MAX_LOG_EVENTS
+}
+
+fn d_flush_timeout() -> u64 {
+    1000
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Default)]
+struct Batch {
+    events: Vec<LogEvent>,
+    bytes: usize,
+    first: u64,
+    last: u64,
+}
+
+impl Batch {
+    fn fits(&self, event: &LogEvent, batch_size: usize) -> bool {
+        self.events.is_empty()
+            || (self.events.len() < batch_size
+                && self.bytes + event.message.len() + LOG_EVENT_OVERHEAD <= MAX_LOG_BYTES
+                && self.last.max(event.timestamp) - self.first.min(event.timestamp)
+                    <= MAX_LOG_SPAN_MS)
+    }
+
+    fn push(&mut self, event: LogEvent) {
+        if self.events.is_empty() {
+            self.first = event.timestamp;
+            self.last = event.timestamp;
+        }
+        self.first = self.first.min(event.timestamp);
+        self.last = self.last.max(event.timestamp);
+        self.bytes += event.message.len() + LOG_EVENT_OVERHEAD;
+        self.events.push(event);
+    }
+}
+
+pub struct CloudWatchLogs {
+    config: Config,
+    region: String,
+    client: Option<AwsClient>,
+    batching: Batching,
+    /// batches by log group and stream
+    batches: HashMap<(String, String), Batch>,
+    /// the sequence tokens for the next call by log group and stream
+    tokens: HashMap<(String, String), Option<String>>,
+    /// ack insights for the transactional events of the batched log events
+    insights: Vec<Event>,
+    postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for CloudWatchLogs {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let mut config: Config = Config::new(config)?;
+            config.batch_size = config.batch_size.min(MAX_LOG_EVENTS).max(1);
+            let region = auth::region(config.region.as_deref())?;
+            let batching = Batching::new(
+                config.batch_size,
+                config.flush_timeout,
+                config.dynamic_batching.clone(),
+            )?;
+            Ok(SinkManager::new_box(Self {
+                config,
+                region,
+                client: None,
+                batching,
+                batches: HashMap::new(),
+                tokens: HashMap::new(),
+                insights: vec![],
+                postprocessors: vec![],
+                metrics: None,
+                sink_url: TremorUrl::from_offramp_id("cloudwatch-logs")?, // dummy value
+            }))
+        } else {
+            Err("CloudWatch Logs offramp requires a config".into())
+        }
+    }
+}
+
+impl CloudWatchLogs {
+    fn log_events(
+        &mut self,
+        codec: &dyn Codec,
+        event: &Event,
+    ) -> Result<Vec<(String, String, LogEvent)>> {
+        let timestamp = event.ingest_ns / 1_000_000;
+        let mut res = Vec::new();
+        for (value, meta) in event.value_meta_iter() {
+            let group = render(&self.config.log_group, value, meta, str::to_string)?;
+            let stream = render(&self.config.log_stream, value, meta, str::to_string)?;
+            let encoded = codec.encode(value)?;
+            for packet in postprocess(
+                &mut self.postprocessors,
+                event.ingest_ns,
+                encoded,
+                self.metrics.as_deref(),
+            )? {
+                let message = String::from_utf8(packet)
+                    .map_err(|_| Error::from("CloudWatch log events have to be UTF-8"))?;
+                if message.len() + LOG_EVENT_OVERHEAD > MAX_LOG_BYTES {
+                    return Err("Log event exceeds the size limit of CloudWatch Logs".into());
+                }
+                res.push((
+                    group.clone(),
+                    stream.clone(),
+                    LogEvent { timestamp, message },
+                ));
+            }
+        }
+        Ok(res)
+    }
+
+    /// sends a batch, tracking the sequence token of the log stream
+    async fn put(&mut self, group: String, stream: String, events: &[LogEvent]) -> Result<()> {
+        if self.client.is_none() {
+            self.client = Some(AwsClient::new(
+                "logs",
+                &self.region,
+                self.config.endpoint.as_deref(),
+            )?);
+        }
+        let client = self.client.as_ref().ok_or("Client error!")?;
+        let key = (group, stream);
+        for _ in 0..MAX_ATTEMPTS {
+            let token = self.tokens.get(&key).cloned().flatten();
+            match cloudwatch::put_log_events(client, &key.0, &key.1, token.as_deref(), events)
+                .await?
+            {
+                PutLogs::Ok(next) => {
+                    self.tokens.insert(key, next);
+                    return Ok(());
+                }
+                PutLogs::InvalidToken(expected) => {
+                    self.tokens.insert(key.clone(), expected);
+                }
+                PutLogs::NotFound(_) if self.config.create_log_stream => {
+                    cloudwatch::create_log_stream(client, &key.0, &key.1).await?;
+                    self.tokens.remove(&key);
+                }
+                PutLogs::NotFound(e) | PutLogs::Err(e) => return Err(e.into()),
+            }
+        }
+        Err(format!(
+            "Unable to put log events to {}/{} after {} attempts",
+            key.0, key.1, MAX_ATTEMPTS
+        )
+        .into())
+    }
+
+    /// sends all batches and resolves the insights of their events, `full`
+    /// if a batch is sent as it is full
+    async fn send_batches(&mut self, full: bool) -> Vec<Reply> {
+        let mut success = true;
+        let batches = std::mem::take(&mut self.batches);
+        for ((group, stream), mut batch) in batches {
+            batch.events.sort_by_key(|e| e.timestamp);
+            let start = Instant::now();
+            let ok = match self.put(group, stream, &batch.events).await {
+                Ok(()) => true,
+                Err(e) => {
+                    error!("[Sink::{}] Error putting log events: {}", self.sink_url, e);
+                    false
+                }
+            };
+            self.batching
+                .record(batch.events.len(), full, start.elapsed(), ok);
+            success &= ok;
+        }
+        self.insights
+            .drain(..)
+            .map(|mut insight| {
+                if !success {
+                    insight.cb = CbAction::Fail;
+                }
+                Reply::Insight(insight)
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for CloudWatchLogs {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        let log_events = match self.log_events(codec, &event) {
+            Ok(log_events) => log_events,
+            Err(e) => {
+                error!("[Sink::{}] Invalid event: {}", self.sink_url, e);
+                return if event.transactional {
+                    Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+                } else {
+                    Ok(None)
+                };
+            }
+        };
+        let mut replies = Vec::new();
+        for (group, stream, log_event) in log_events {
+            let key = (group, stream);
+            let full = self
+                .batches
+                .get(&key)
+                .map_or(false, |b| !b.fits(&log_event, self.batching.batch_size()));
+            if full {
+                replies.append(&mut self.send_batches(true).await);
+            }
+            self.batches.entry(key).or_default().push(log_event);
+        }
+        if event.transactional {
+            self.insights.push(event.insight_ack());
+        }
+        Ok(Some(replies))
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+
+    async fn flush(&mut self) -> ResultVec {
+        Ok(Some(self.send_batches(false).await))
+    }
+
+    fn flush_timeout(&self) -> Option<u64> {
+        Some(self.batching.flush_timeout_ns())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {}
+}
@@ -0,0 +1,317 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # CloudWatch Metrics Offramp
+//!
+//! Publishes metrics to AWS CloudWatch. Events are records of the form
+//! `{"name": "latency", "value": 12.5, "unit": "Milliseconds", "dimensions": {"host": "a"}}`,
+//! `unit` and `dimensions` being optional.
+//!
+//! Values are aggregated per metric name, unit and dimensions into statistic
+//! sets, sample count, sum, minimum and maximum, and published every
+//! `interval` milliseconds, which keeps the number of `PutMetricData` calls
+//! independent of the event rate. The static `dimensions` are added to all
+//! metrics.
+//!
+//! Credentials are read from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+//! and `AWS_SESSION_TOKEN` environment variables or the shared credentials
+//! file.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::aws::auth::{self, AwsClient};
+use crate::connectors::aws::cloudwatch::{self, Metric, Statistics, MAX_METRICS};
+use crate::sink::prelude::*;
+use chrono::{SecondsFormat, Utc};
+use halfbrown::HashMap;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// AWS region, defaults to `AWS_REGION`
+    #[serde(default)]
+    pub region: Option<String>,
+    /// namespace the metrics are published in
+    pub namespace: String,
+    /// dimensions added to all metrics
+    #[serde(default)]
+    pub dimensions: HashMap<String, String>,
+    /// milliseconds over which values are aggregated
+    #[serde(default = "d_interval")]
+    pub interval: u64,
+    /// overrides the `monitoring.{region}.amazonaws.com` endpoint
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+fn d_interval() -> u64 {
+    60_000
+}
+
+impl ConfigImpl for Config {}
+
+/// metric name, unit and dimensions sorted by name
+type MetricKey = (String, Option<String>, Vec<(String, String)>);
+
+pub struct CloudWatchMetrics {
+    config: Config,
+    region: String,
+    client: Option<AwsClient>,
+    metrics: HashMap<MetricKey, Statistics>,
+    /// ack insights for the transactional events of the aggregated values
+    insights: Vec<Event>,
+    last_flush_ns: u64,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for CloudWatchMetrics {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            let region = auth::region(config.region.as_deref())?;
+            Ok(SinkManager::new_box(Self {
+                config,
+                region,
+                client: None,
+                metrics: HashMap::new(),
+                insights: vec![],
+                last_flush_ns: 0,
+                sink_url: TremorUrl::from_offramp_id("cloudwatch-metrics")?, // dummy value
+            }))
+        } else {
+            Err("CloudWatch Metrics offramp requires a config".into())
+        }
+    }
+}
+
+impl CloudWatchMetrics {
+    fn metric(&self, value: &Value) -> Result<(MetricKey, f64)> {
+        let name = value
+            .get_str("name")
+            .ok_or("CloudWatch metrics require a `name`")?;
+        let v = value
+            .get("value")
+            .and_then(ValueAccess::cast_f64)
+            .ok_or("CloudWatch metrics require a numeric `value`")?;
+        let unit = value.get_str("unit").map(ToString::to_string);
+        let mut dimensions: Vec<(String, String)> = self
+            .config
+            .dimensions
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if let Some(dims) = value.get_object("dimensions") {
+            for (k, v) in dims.iter() {
+                let v = v.as_str().map_or_else(|| v.encode(), ToString::to_string);
+                dimensions.retain(|(name, _)| name != k);
+                dimensions.push((k.to_string(), v));
+            }
+        }
+        dimensions.sort();
+        Ok(((name.to_string(), unit, dimensions), v))
+    }
+
+    fn add(&mut self, event: &Event) -> Result<()> {
+        let mut values = Vec::new();
+        for value in event.value_iter() {
+            values.push(self.metric(value)?);
+        }
+        for (key, v) in values {
+            match self.metrics.get_mut(&key) {
+                Some(stats) => stats.add(v),
+                None => {
+                    self.metrics.insert(key, Statistics::new(v));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn put(&mut self, metrics: Vec<Metric>) -> Result<()> {
+        if self.client.is_none() {
+            self.client = Some(AwsClient::new(
+                "monitoring",
+                &self.region,
+                self.config.endpoint.as_deref(),
+            )?);
+        }
+        let client = self.client.as_ref().ok_or("Client error!")?;
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        for chunk in metrics.chunks(MAX_METRICS) {
+            cloudwatch::put_metric_data(client, &self.config.namespace, &timestamp, chunk).await?;
+        }
+        Ok(())
+    }
+
+    /// publishes the aggregated metrics and resolves the insights of their events
+    async fn publish(&mut self, now_ns: u64) -> Vec<Reply> {
+        self.last_flush_ns = now_ns;
+        let metrics: Vec<Metric> = std::mem::take(&mut self.metrics)
+            .into_iter()
+            .map(|((name, unit, dimensions), statistics)| Metric {
+                name,
+                unit,
+                dimensions,
+                statistics,
+            })
+            .collect();
+        let success = if metrics.is_empty() {
+            true
+        } else if let Err(e) = self.put(metrics).await {
+            error!("[Sink::{}] Error putting metric data: {}", self.sink_url, e);
+            false
+        } else {
+            true
+        };
+        self.insights
+            .drain(..)
+            .map(|mut insight| {
+                if !success {
+                    insight.cb = CbAction::Fail;
+                }
+                Reply::Insight(insight)
+            })
+            .collect()
+    }
+
+    fn due(&self, now_ns: u64) -> bool {
+        now_ns.saturating_sub(self.last_flush_ns) >= self.config.interval * 1_000_000
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for CloudWatchMetrics {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        _codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        if self.last_flush_ns == 0 {
+            self.last_flush_ns = event.ingest_ns;
+        }
+        if let Err(e) = self.add(&event) {
+            error!("[Sink::{}] Invalid metric: {}", self.sink_url, e);
+            return if event.transactional {
+                Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+            } else {
+                Ok(None)
+            };
+        }
+        if event.transactional {
+            self.insights.push(event.insight_ack());
+        }
+        if self.due(event.ingest_ns) {
+            Ok(Some(self.publish(event.ingest_ns).await))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn on_signal(&mut self, signal: Event) -> ResultVec {
+        if !self.metrics.is_empty() && self.due(signal.ingest_ns) {
+            Ok(Some(self.publish(signal.ingest_ns).await))
+        } else {
+            Ok(None)
+        }
+    }
+
+    async fn flush(&mut self) -> ResultVec {
+        Ok(Some(self.publish(nanotime()).await))
+    }
+
+    fn flush_timeout(&self) -> Option<u64> {
+        Some(self.config.interval * 1_000_000)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        _processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    #[test]
+    fn metric() -> Result<()> {
+        let config: OpConfig = serde_yaml::from_str(
+            r#"
+region: eu-west-1
+namespace: tremor
+dimensions:
+  env: prod
+  host: default
+"#,
+        )?;
+        let sink = CloudWatchMetrics {
+            config: Config::new(&config)?,
+            region: "eu-west-1".to_string(),
+            client: None,
+            metrics: HashMap::new(),
+            insights: vec![],
+            last_flush_ns: 0,
+            sink_url: TremorUrl::from_offramp_id("cloudwatch-metrics")?,
+        };
+        let ((name, unit, dimensions), value) = sink.metric(&literal!({
+            "name": "latency",
+            "value": 3,
+            "unit": "Milliseconds",
+            "dimensions": {"host": "a", "core": 2}
+        }))?;
+        assert_eq!("latency", name);
+        assert_eq!(Some("Milliseconds".to_string()), unit);
+        assert_eq!(
+            vec![
+                ("core".to_string(), "2".to_string()),
+                ("env".to_string(), "prod".to_string()),
+                ("host".to_string(), "a".to_string()),
+            ],
+            dimensions
+        );
+        assert!((value - 3.0).abs() < f64::EPSILON);
+        assert!(sink.metric(&literal!({"name": "latency"})).is_err());
+        Ok(())
+    }
+}
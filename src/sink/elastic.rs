@@ -29,6 +29,9 @@
 //!
 //! The 1st additional output is used to send divert messages that can not be
 //! enqueued due to overload
+//!
+//! On shutdown the offramp waits up to 10 seconds for requests in flight, so
+//! their responses and insights are sent.
 
 #![cfg(not(tarpaulin_include))]
 
@@ -42,7 +45,7 @@ use elastic::{
 };
 use halfbrown::HashMap;
 use simd_json::json;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::{iter, str};
 use tremor_pipeline::{EventId, EventIdGenerator};
 use tremor_script::prelude::*;
@@ -62,6 +65,9 @@ fn concurrency() -> usize {
 }
 impl ConfigImpl for Config {}
 
+/// how long to wait for requests in flight on shutdown
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct Elastic {
     sink_url: TremorUrl,
     client: SyncClient,
@@ -296,9 +302,10 @@ impl Elastic {
                 // if we have more than one event (batched), correlation will be an array with `null` or an actual value
                 // for the event at the batch position
                 let correlation_value = event.correlation_meta();
-                let meta = correlation_value.map_or_else(Value::null, |correlation| {
-                    literal!({ "correlation": correlation })
-                });
+                let meta = correlation_value.map_or_else(
+                    Value::null,
+                    |correlation| literal!({ "correlation": correlation }),
+                );
 
                 // send error response
                 if let Err(e) = response_tx.send(((data, meta).into(), ERR)).await {
@@ -601,6 +608,18 @@ impl Sink for Elastic {
     async fn on_signal(&mut self, _signal: Event) -> ResultVec {
         Ok(None) // insights are sent via reply_channel directly
     }
+    async fn flush(&mut self) -> ResultVec {
+        // requests are sent right away, but the ones in flight have to
+        // complete before the response task is terminated
+        let drained = async_std::future::timeout(FLUSH_TIMEOUT, self.queue.drain()).await;
+        if drained.is_err() {
+            warn!(
+                "[Sink::{}] Timed out waiting for requests in flight",
+                self.sink_url
+            );
+        }
+        Ok(None)
+    }
     fn is_active(&self) -> bool {
         true
     }
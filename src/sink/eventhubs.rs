@@ -0,0 +1,233 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Azure Event Hubs Offramp
+//!
+//! Sends events, encoded with the configured codec, to an event hub over
+//! AMQP 1.0. All values of a batched event are sent together. An event is
+//! acked once Event Hubs accepted all its messages and failed otherwise.
+//!
+//! `$eventhubs.partition_key` sets the partition key, events with the same
+//! key end up in the same partition. `$eventhubs.properties` sets the
+//! application properties.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::azure::amqp::Event as AmqpEvent;
+use crate::connectors::azure::eventhubs::{self, Client, ConnectionString};
+use crate::sink::prelude::*;
+use halfbrown::HashMap;
+use std::collections::HashSet;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// connection string of a shared access policy of the namespace or the
+    /// event hub
+    pub connection_string: String,
+    /// name of the event hub, defaults to the `EntityPath` of the connection
+    /// string
+    #[serde(default)]
+    pub event_hub: Option<String>,
+    /// milliseconds to wait for an event to be accepted
+    #[serde(default = "d_timeout")]
+    pub timeout: u64,
+}
+
+fn d_timeout() -> u64 {
+    10_000
+}
+
+impl ConfigImpl for Config {}
+
+pub struct EventHubs {
+    config: Config,
+    connection_string: ConnectionString,
+    event_hub: String,
+    /// the client and its sending link
+    client: Option<(Client, u32)>,
+    postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for EventHubs {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            let connection_string = ConnectionString::parse(&config.connection_string)?;
+            let event_hub = config
+                .event_hub
+                .clone()
+                .or_else(|| connection_string.entity_path.clone())
+                .ok_or("The eventhubs offramp requires an event_hub")?;
+            Ok(SinkManager::new_box(Self {
+                config,
+                connection_string,
+                event_hub,
+                client: None,
+                postprocessors: vec![],
+                metrics: None,
+                sink_url: TremorUrl::from_offramp_id("eventhubs")?, // dummy value
+            }))
+        } else {
+            Err("Event Hubs offramp requires a config".into())
+        }
+    }
+}
+
+impl EventHubs {
+    async fn send(&mut self, codec: &dyn Codec, event: &Event) -> Result<()> {
+        let mut messages = Vec::new();
+        for (value, meta) in event.value_meta_iter() {
+            let meta = meta.get("eventhubs");
+            let encoded = codec.encode(value)?;
+            for packet in postprocess(
+                &mut self.postprocessors,
+                event.ingest_ns,
+                encoded,
+                self.metrics.as_deref(),
+            )? {
+                messages.push(eventhubs::message(&packet, meta));
+            }
+        }
+
+        let timeout = Duration::from_millis(self.config.timeout);
+        if self.client.is_none() {
+            let mut client =
+                eventhubs::connect(&self.connection_string, &format!("tremor-{}", hostname()))
+                    .await?;
+            let sender = client
+                .attach_sender(&format!("tremor-{}", hostname()), &self.event_hub)
+                .await?;
+            self.client = Some((client, sender));
+        }
+        let (client, sender) = self.client.as_mut().ok_or("Client error!")?;
+        let sender = *sender;
+        let mut pending = HashSet::new();
+        for message in messages {
+            eventhubs::wait_for_credit(client, sender, timeout).await?;
+            pending.insert(client.send(sender, &message).await?);
+        }
+        let wait = async {
+            while !pending.is_empty() {
+                match client.next().await? {
+                    AmqpEvent::Settled {
+                        first,
+                        last,
+                        accepted,
+                    } => {
+                        for delivery_id in first..=last {
+                            if pending.remove(&delivery_id) && !accepted {
+                                return Err(Error::from("Event Hubs rejected a message"));
+                            }
+                        }
+                    }
+                    AmqpEvent::Detached { error, .. } => {
+                        return Err(format!(
+                            "Event Hubs detached the link: {}",
+                            error.unwrap_or_default()
+                        )
+                        .into());
+                    }
+                    _ => (),
+                }
+            }
+            Ok(())
+        };
+        async_std::future::timeout(timeout, wait)
+            .await
+            .map_err(|_| Error::from("Timeout waiting for Event Hubs to accept messages"))?
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for EventHubs {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        match self.send(codec, &event).await {
+            Ok(()) if event.transactional => Ok(Some(vec![Reply::Insight(event.insight_ack())])),
+            Ok(()) => Ok(None),
+            Err(e) => {
+                error!("[Sink::{}] Error sending events: {}", self.sink_url, e);
+                // settlements of this event would be taken for the next one
+                self.client = None;
+                if event.transactional {
+                    Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        if let Some((client, _)) = self.client.as_mut() {
+            if let Err(e) = client.keep_alive().await {
+                warn!(
+                    "[Sink::{}] Event Hubs connection lost: {}",
+                    self.sink_url, e
+                );
+                self.client = None;
+            }
+        }
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {
+        if let Some((mut client, _)) = self.client.take() {
+            if let Err(e) = client.close().await {
+                warn!("[Sink::{}] closing failed: {}", self.sink_url, e);
+            }
+        }
+    }
+}
@@ -16,6 +16,13 @@
 //!
 //! Writes events to a file, one event per line
 //!
+//! Writes are flushed after each event, or with `flush_timeout` once no
+//! events were written for that many milliseconds and on shutdown.
+//!
+//! With `flush_timeout` events are acknowledged once they are written, not
+//! once they are flushed, so events acknowledged shortly before a crash can
+//! be lost.
+//!
 //! ## Configuration
 //!
 //! See [Config](struct.Config.html) for details.
@@ -32,6 +39,7 @@ use tremor_common::asy::file as cfile;
 pub struct File {
     file: Option<FSFile>,
     postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
     config: Config,
 }
 
@@ -39,6 +47,11 @@ pub struct File {
 pub struct Config {
     /// Filename to write to
     pub file: String,
+    /// milliseconds without events after which writes are flushed, instead
+    /// of flushing after every event, events are acknowledged before they
+    /// are flushed then
+    #[serde(default = "Default::default")]
+    pub flush_timeout: Option<u64>,
 }
 
 impl ConfigImpl for Config {}
@@ -52,6 +65,7 @@ impl offramp::Impl for File {
                 file: None,
                 config,
                 postprocessors: vec![],
+                metrics: None,
             }))
         } else {
             Err("Blackhole offramp requires a config".into())
@@ -79,13 +93,20 @@ impl Sink for File {
         if let Some(file) = &mut self.file {
             for value in event.value_iter() {
                 let raw = codec.encode(value)?;
-                let packets = postprocess(&mut self.postprocessors, event.ingest_ns, raw)?;
+                let packets = postprocess(
+                    &mut self.postprocessors,
+                    event.ingest_ns,
+                    raw,
+                    self.metrics.as_deref(),
+                )?;
                 for packet in packets {
                     file.write_all(&packet).await?;
                     file.write_all(b"\n").await?;
                 }
             }
-            file.flush().await?
+            if self.config.flush_timeout.is_none() {
+                file.flush().await?
+            }
         }
         Ok(Some(vec![sink::Reply::Insight(event.insight_ack())]))
     }
@@ -104,6 +125,7 @@ impl Sink for File {
         _reply_channel: Sender<sink::Reply>,
     ) -> Result<()> {
         self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
         let file = cfile::create(&self.config.file).await?;
         self.file = Some(file);
         Ok(())
@@ -111,6 +133,15 @@ impl Sink for File {
     async fn on_signal(&mut self, _signal: Event) -> ResultVec {
         Ok(None)
     }
+    async fn flush(&mut self) -> ResultVec {
+        if let Some(file) = &mut self.file {
+            file.flush().await?;
+        }
+        Ok(None)
+    }
+    fn flush_timeout(&self) -> Option<u64> {
+        self.config.flush_timeout.map(|ms| ms * 1_000_000)
+    }
     fn is_active(&self) -> bool {
         true
     }
@@ -0,0 +1,318 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Google Cloud Logging Offramp
+//!
+//! Writes events as structured log entries to Google Cloud Logging, formerly
+//! Stackdriver, authenticated like the `gcs` offramp. Records are written as
+//! `jsonPayload`, strings as `textPayload` and all other values as their JSON
+//! encoding. All values of a batched event are written in one request.
+//!
+//! The `severity` is taken from the event field at `severity_field`, either a
+//! severity name like `warn` or `error`, or a syslog severity number. Entries
+//! carry the static `labels` and the `label_fields` read from the event.
+//!
+//! The `$gcl` metadata can override `log_name`, `resource`, `severity`,
+//! `trace` and `insert_id` and add `labels` per event.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::gcp::{auth, logging};
+use crate::sink::prelude::*;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use halfbrown::HashMap;
+use http::HeaderMap;
+use std::time::Instant;
+use tremor_value::literal;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// log to write to, `projects/{project}/logs/{log}`
+    pub log_name: String,
+    /// monitored resource the entries are written for
+    #[serde(default = "d_resource")]
+    pub resource: Resource,
+    /// labels added to all entries
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// labels filled from the dotted paths of event fields
+    #[serde(default)]
+    pub label_fields: HashMap<String, String>,
+    /// dotted path of the event field holding the severity
+    #[serde(default = "d_severity_field")]
+    pub severity_field: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resource {
+    /// monitored resource type, e.g. `global` or `k8s_container`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// labels identifying the resource
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+fn d_resource() -> Resource {
+    Resource {
+        kind: "global".to_string(),
+        labels: HashMap::new(),
+    }
+}
+
+fn d_severity_field() -> String {
+    "severity".to_string()
+}
+
+impl ConfigImpl for Config {}
+
+pub struct Gcl {
+    config: Config,
+    remote: Option<auth::AuthClient>,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for Gcl {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(SinkManager::new_box(Self {
+                config,
+                remote: None,
+                sink_url: TremorUrl::from_offramp_id("gcl")?, // dummy value
+            }))
+        } else {
+            Err("Google Cloud Logging offramp requires a config".into())
+        }
+    }
+}
+
+fn lookup<'value, 'event>(
+    value: &'value Value<'event>,
+    path: &str,
+) -> Option<&'value Value<'event>> {
+    path.split('.').try_fold(value, |v, key| v.get(key))
+}
+
+fn label_value(value: &Value) -> String {
+    value
+        .as_str()
+        .map_or_else(|| value.encode(), ToString::to_string)
+}
+
+impl Gcl {
+    fn entry(&self, data: &Value, meta: &Value, ingest_ns: u64) -> Value<'static> {
+        let gcl = meta.get("gcl");
+        let mut entry = Value::object_with_capacity(8);
+        entry.try_insert(
+            "logName",
+            gcl.get_str("log_name")
+                .unwrap_or(&self.config.log_name)
+                .to_string(),
+        );
+        let resource = gcl.and_then(|m| m.get("resource")).map_or_else(
+            || {
+                let mut labels = Value::object_with_capacity(self.config.resource.labels.len());
+                for (k, v) in &self.config.resource.labels {
+                    labels.try_insert(k.clone(), v.clone());
+                }
+                literal!({ "type": self.config.resource.kind.clone(), "labels": labels })
+            },
+            Value::clone_static,
+        );
+        entry.try_insert("resource", resource);
+        let severity = gcl
+            .and_then(|m| m.get("severity"))
+            .or_else(|| lookup(data, &self.config.severity_field))
+            .map_or("DEFAULT", logging::severity);
+        entry.try_insert("severity", severity);
+        #[allow(clippy::cast_possible_wrap)]
+        let timestamp = Utc
+            .timestamp_nanos(ingest_ns as i64)
+            .to_rfc3339_opts(SecondsFormat::Nanos, true);
+        entry.try_insert("timestamp", timestamp);
+
+        let mut labels = Value::object_with_capacity(self.config.labels.len());
+        for (k, v) in &self.config.labels {
+            labels.try_insert(k.clone(), v.clone());
+        }
+        for (k, path) in &self.config.label_fields {
+            if let Some(v) = lookup(data, path).filter(|v| !v.is_null()) {
+                labels.try_insert(k.clone(), label_value(v));
+            }
+        }
+        if let Some(meta_labels) = gcl.and_then(|m| m.get_object("labels")) {
+            for (k, v) in meta_labels.iter() {
+                labels.try_insert(k.to_string(), label_value(v));
+            }
+        }
+        if labels.as_object().map_or(false, |l| !l.is_empty()) {
+            entry.try_insert("labels", labels);
+        }
+        if let Some(trace) = gcl.get_str("trace") {
+            entry.try_insert("trace", trace.to_string());
+        }
+        if let Some(insert_id) = gcl.get_str("insert_id") {
+            entry.try_insert("insertId", insert_id.to_string());
+        }
+
+        if data.is_object() {
+            entry.try_insert("jsonPayload", data.clone_static());
+        } else if let Some(s) = data.as_str() {
+            entry.try_insert("textPayload", s.to_string());
+        } else {
+            entry.try_insert("textPayload", data.encode());
+        }
+        entry
+    }
+
+    async fn write(&mut self, event: &Event) -> Result<()> {
+        let entries: Vec<Value> = event
+            .value_meta_iter()
+            .map(|(data, meta)| self.entry(data, meta, event.ingest_ns))
+            .collect();
+        let body = literal!({ "entries": entries });
+        if self.remote.is_none() {
+            self.remote = Some(auth::json_api_client(&HeaderMap::new()).await?);
+        }
+        let remote = self.remote.as_ref().ok_or("Client error!")?;
+        logging::write_entries(remote, &body).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Gcl {
+    #[allow(clippy::cast_possible_truncation)]
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        _codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        let start = Instant::now();
+        match self.write(&event).await {
+            Ok(()) if event.transactional => Ok(Some(vec![Reply::Insight(
+                event.insight_ack_with_timing(start.elapsed().as_millis() as u64),
+            )])),
+            Ok(()) => Ok(None),
+            Err(e) => {
+                error!("[Sink::{}] Error writing log entries: {}", self.sink_url, e);
+                if event.transactional {
+                    Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        _processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sink() -> Result<Gcl> {
+        let config: OpConfig = serde_yaml::from_str(
+            r#"
+log_name: projects/snot/logs/badger
+labels:
+  env: prod
+label_fields:
+  host: host.name
+"#,
+        )?;
+        Ok(Gcl {
+            config: Config::new(&config)?,
+            remote: None,
+            sink_url: TremorUrl::from_offramp_id("gcl")?,
+        })
+    }
+
+    #[test]
+    fn entry() -> Result<()> {
+        let gcl = sink()?;
+        let data = literal!({"host": {"name": "a"}, "severity": "warn", "msg": "m"});
+        assert_eq!(
+            literal!({
+                "logName": "projects/snot/logs/badger",
+                "resource": {"type": "global", "labels": {}},
+                "severity": "WARNING",
+                "timestamp": "1970-01-01T00:00:01.000000000Z",
+                "labels": {"env": "prod", "host": "a"},
+                "jsonPayload": {"host": {"name": "a"}, "severity": "warn", "msg": "m"}
+            }),
+            gcl.entry(&data, &Value::object(), 1_000_000_000)
+        );
+        let meta = literal!({
+            "gcl": {
+                "log_name": "projects/snot/logs/other",
+                "resource": {"type": "k8s_container"},
+                "severity": 3,
+                "labels": {"pod": "p"},
+                "trace": "t"
+            }
+        });
+        assert_eq!(
+            literal!({
+                "logName": "projects/snot/logs/other",
+                "resource": {"type": "k8s_container"},
+                "severity": "ERROR",
+                "timestamp": "1970-01-01T00:00:01.000000000Z",
+                "labels": {"env": "prod", "pod": "p"},
+                "trace": "t",
+                "textPayload": "hello"
+            }),
+            gcl.entry(&literal!("hello"), &meta, 1_000_000_000)
+        );
+        Ok(())
+    }
+}
@@ -24,31 +24,37 @@
 
 use crate::connectors::gcp::{auth, storage};
 use crate::connectors::qos::{self, QoSFacilities, SinkQoS};
+use crate::connectors::reconnect::{self, Reconnect};
 use crate::sink::prelude::*;
 use futures::executor::block_on;
 use halfbrown::HashMap;
 use http::HeaderMap;
-use reqwest::Client;
 use tremor_pipeline::{EventIdGenerator, OpMeta};
 use tremor_value::Value;
 
 pub struct GoogleCloudStorage {
     #[allow(dead_code)]
     config: Config,
-    remote: Option<Client>,
+    remote: Option<auth::AuthClient>,
     is_down: bool,
     qos_facility: Box<dyn SinkQoS>,
+    reconnect: Reconnect,
     reply_channel: Option<Sender<sink::Reply>>,
     is_linked: bool,
     preprocessors: Preprocessors,
     postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
     codec: Box<dyn Codec>,
     sink_url: TremorUrl,
     event_id_gen: EventIdGenerator,
 }
 
 #[derive(Deserialize)]
-pub struct Config {}
+pub struct Config {
+    /// reconnect policy
+    #[serde(default)]
+    pub reconnect: reconnect::Config,
+}
 
 enum StorageCommand {
     Create(String, String),
@@ -91,15 +97,18 @@ impl offramp::Impl for GoogleCloudStorage {
             let headers = HeaderMap::new();
             let remote = Some(block_on(auth::json_api_client(&headers))?);
             let hostport = "storage.googleapis.com:443";
+            let reconnect = Reconnect::new(config.reconnect.clone());
             Ok(SinkManager::new_box(Self {
                 config,
                 remote,
                 is_down: false,
                 qos_facility: Box::new(QoSFacilities::recoverable(hostport.to_string())),
+                reconnect,
                 reply_channel: None,
                 is_linked: false,
                 preprocessors: vec![],
                 postprocessors: vec![],
+                metrics: None,
                 codec: Box::new(crate::codec::null::Null {}),
                 sink_url: TremorUrl::from_offramp_id("gcs")?,
                 event_id_gen: EventIdGenerator::new(0), // Fake ID overwritten in init
@@ -155,18 +164,14 @@ fn parse_command(value: &Value) -> Result<StorageCommand> {
     Err("Invalid Command".into())
 }
 
-#[async_trait::async_trait]
-impl Sink for GoogleCloudStorage {
-    async fn terminate(&mut self) {}
-
+impl GoogleCloudStorage {
+    /// runs the commands of `event`, returning their responses
     #[allow(clippy::too_many_lines)]
-    async fn on_event(
+    async fn execute(
         &mut self,
-        _input: &str,
         codec: &mut dyn Codec,
-        _codec_map: &HashMap<String, Box<dyn Codec>>,
-        mut event: Event,
-    ) -> ResultVec {
+        event: &Event,
+    ) -> Result<Vec<Value<'static>>> {
         let remote = if let Some(remote) = &self.remote {
             remote
         } else {
@@ -177,7 +182,6 @@ impl Sink for GoogleCloudStorage {
         };
 
         let mut response = Vec::new();
-        let maybe_correlation = event.correlation_meta();
         for value in event.value_iter() {
             let command = parse_command(value)?;
             match command {
@@ -210,6 +214,7 @@ impl Sink for GoogleCloudStorage {
                             codec,
                             event.ingest_ns,
                             &mut self.postprocessors,
+                            self.metrics.as_deref(),
                         )
                         .await?,
                     ));
@@ -260,6 +265,44 @@ impl Sink for GoogleCloudStorage {
                 }
             };
         }
+        Ok(response)
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for GoogleCloudStorage {
+    async fn terminate(&mut self) {}
+
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        let response = match self.execute(codec, &event).await {
+            Ok(response) => response,
+            // only connection problems take the sink down, not failing commands
+            Err(Error(ErrorKind::ReqwestError(e), _)) if e.is_connect() || e.is_timeout() => {
+                error!(
+                    "Google Cloud Storage - sink remote endpoint unreachable: {}",
+                    e
+                );
+                if !self.is_down {
+                    self.is_down = true;
+                    self.reconnect.failed(nanotime(), &e.to_string());
+                }
+                if event.transactional {
+                    return Ok(Some(vec![
+                        qos::fail(&mut event.clone()),
+                        qos::close(&mut event),
+                    ]));
+                }
+                return Ok(Some(vec![qos::close(&mut event)]));
+            }
+            Err(e) => return Err(e),
+        };
+        let maybe_correlation = event.correlation_meta();
         if self.is_linked {
             if let Some(reply_channel) = &self.reply_channel {
                 let mut meta = Object::with_capacity(1);
@@ -292,7 +335,10 @@ impl Sink for GoogleCloudStorage {
             }
         }
 
-        self.is_down = false;
+        if self.is_down {
+            self.is_down = false;
+            self.reconnect.connected();
+        }
         return Ok(Some(vec![qos::ack(&mut event)]));
     }
 
@@ -313,7 +359,9 @@ impl Sink for GoogleCloudStorage {
     ) -> Result<()> {
         self.event_id_gen = EventIdGenerator::new(sink_uid);
         self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
         self.preprocessors = make_preprocessors(processors.pre)?;
+        self.reconnect.report_to(processors.stats);
         self.reply_channel = Some(reply_channel);
         self.codec = codec.boxed_clone();
         self.is_linked = is_linked;
@@ -321,17 +369,21 @@ impl Sink for GoogleCloudStorage {
     }
 
     async fn on_signal(&mut self, signal: Event) -> ResultVec {
-        if self.is_down && self.qos_facility.probe(signal.ingest_ns) {
+        if !self.is_down || !self.reconnect.due(nanotime()) {
+            return Ok(None);
+        }
+        if self.qos_facility.probe(signal.ingest_ns) {
+            self.reconnect.connected();
             self.is_down = false;
             // This means the port is connectable
             info!("Google Cloud Storage -  sink remote endpoint - recovered and contactable");
-            self.is_down = false;
             // Clone needed to make it mutable, lint is wrong
             #[allow(clippy::redundant_clone)]
             let mut signal = signal.clone();
             return Ok(Some(vec![qos::open(&mut signal)]));
         }
-
+        self.reconnect
+            .failed(nanotime(), "remote endpoint not reachable");
         Ok(None)
     }
 
@@ -343,20 +395,22 @@ impl Sink for GoogleCloudStorage {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn upload_object(
-    client: &Client,
+    client: &auth::AuthClient,
     bucket_name: &str,
     object_name: &str,
     data: &Value<'_>,
     codec: &dyn Codec,
     ingest_ns: u64,
     postprocessors: &mut [Box<dyn Postprocessor>],
+    metrics: Option<&ByteMetrics>,
 ) -> Result<Value<'static>> {
     let mut body: Vec<u8> = vec![];
     let codec_in_use = None;
     let codec = codec_in_use.unwrap_or(codec);
     let encoded = codec.encode(data)?;
-    let mut processed = postprocess(postprocessors, ingest_ns, encoded)?;
+    let mut processed = postprocess(postprocessors, ingest_ns, encoded, metrics)?;
     for processed_elem in &mut processed {
         body.append(processed_elem);
     }
@@ -364,7 +418,7 @@ async fn upload_object(
 }
 
 async fn download_object(
-    client: &Client,
+    client: &auth::AuthClient,
     bucket_name: &str,
     object_name: &str,
     sink_url: &TremorUrl,
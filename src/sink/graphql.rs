@@ -0,0 +1,344 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # GraphQL Offramp
+//!
+//! Sends the configured `query`, a GraphQL query or mutation document, to
+//! the `endpoint` for each event. The `variables` of the request are the
+//! event data, or a `variables` template filled from it like the `rest`
+//! offramp request `mapping`.
+//!
+//! With `persisted_queries` only the sha256 hash of the document is sent,
+//! as for automatic persisted queries, and the full document only once the
+//! server replies with `PersistedQueryNotFound`.
+//!
+//! Transport errors, `429` and `5xx` responses, and GraphQL errors with one
+//! of the `retry_codes` fail the event so it can be retried upstream. Other
+//! failures, e.g. invalid queries, can't succeed on retry: they are acked
+//! and reported via the `err` port. Responses are sent via `out` with the
+//! GraphQL `data` as event data and the `errors` in `$graphql.errors`.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::sink::prelude::*;
+use crate::sink::rest;
+use halfbrown::HashMap;
+use http_types::headers::CONTENT_TYPE;
+use sha2::{Digest, Sha256};
+use tremor_pipeline::EventIdGenerator;
+use tremor_value::literal;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// GraphQL endpoint url
+    pub endpoint: String,
+    /// the query or mutation document
+    pub query: String,
+    /// the operation to execute if the document contains multiple
+    #[serde(default)]
+    pub operation_name: Option<String>,
+    /// template for the variables, defaults to the event data
+    #[serde(default)]
+    pub variables: Option<OpConfig>,
+    /// send the hash of the document instead of the document
+    #[serde(default)]
+    pub persisted_queries: bool,
+    /// additional request headers, e.g. for authorization
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// GraphQL error codes (`extensions.code`) worth retrying
+    #[serde(default = "d_retry_codes")]
+    pub retry_codes: Vec<String>,
+}
+
+fn d_retry_codes() -> Vec<String> {
+    vec![
+        "INTERNAL_SERVER_ERROR".to_string(),
+        "SERVICE_UNAVAILABLE".to_string(),
+        "TIMEOUT".to_string(),
+    ]
+}
+
+impl ConfigImpl for Config {}
+
+/// how a request ended
+#[derive(Debug)]
+enum Outcome {
+    /// a response, possibly carrying errors for parts of the data
+    Response(Value<'static>),
+    /// failed, might succeed when retried
+    Retry(String),
+    /// failed, will fail again when retried
+    Reject(String, Value<'static>),
+}
+
+pub struct GraphQl {
+    config: Config,
+    hash: String,
+    sink_url: TremorUrl,
+    event_origin_uri: EventOriginUri,
+    idgen: EventIdGenerator,
+    reply_tx: Sender<sink::Reply>,
+}
+
+impl offramp::Impl for GraphQl {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            let url = url::Url::parse(&config.endpoint)?;
+            let hash = format!("{:x}", Sha256::digest(config.query.as_bytes()));
+            let event_origin_uri = EventOriginUri {
+                uid: 0,
+                scheme: "tremor-graphql".to_string(),
+                host: url.host_str().unwrap_or_default().to_string(),
+                port: url.port(),
+                path: url
+                    .path_segments()
+                    .map_or_else(Vec::new, |s| s.map(String::from).collect()),
+            };
+            // dummy
+            let (dummy_tx, _) = async_channel::bounded(1);
+            Ok(SinkManager::new_box(Self {
+                config,
+                hash,
+                sink_url: TremorUrl::from_offramp_id("graphql")?, // dummy value
+                event_origin_uri,
+                idgen: EventIdGenerator::new(0),
+                reply_tx: dummy_tx, // dummy, will be replaced in init
+            }))
+        } else {
+            Err("GraphQL offramp requires a config".into())
+        }
+    }
+}
+
+fn error_codes(response: &Value) -> Vec<String> {
+    response
+        .get_array("errors")
+        .map(|errors| {
+            errors
+                .iter()
+                .filter_map(|e| {
+                    e.get("extensions")
+                        .and_then(|x| x.get_str("code"))
+                        .or_else(|| e.get_str("message"))
+                })
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl GraphQl {
+    fn body(&self, variables: Value<'static>, with_query: bool) -> Value<'static> {
+        let mut body = Value::object_with_capacity(4);
+        if with_query || !self.config.persisted_queries {
+            body.try_insert("query", self.config.query.clone());
+        }
+        if let Some(operation_name) = &self.config.operation_name {
+            body.try_insert("operationName", operation_name.clone());
+        }
+        body.try_insert("variables", variables);
+        if self.config.persisted_queries {
+            body.try_insert(
+                "extensions",
+                literal!({
+                    "persistedQuery": {
+                        "version": 1,
+                        "sha256Hash": self.hash.clone()
+                    }
+                }),
+            );
+        }
+        body
+    }
+
+    async fn post(&self, body: &Value<'_>) -> Result<(u16, Value<'static>)> {
+        let mut request = surf::post(&self.config.endpoint)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.encode());
+        for (k, v) in &self.config.headers {
+            request = request.header(k.as_str(), v.as_str());
+        }
+        let mut response = request.await?;
+        let status: u16 = response.status().into();
+        let mut bytes = response.body_bytes().await?;
+        let value = tremor_value::parse_to_value(&mut bytes)
+            .map_or_else(|_| Value::null(), Value::into_static);
+        Ok((status, value))
+    }
+
+    async fn send(&self, variables: Value<'static>) -> Outcome {
+        let mut res = self.post(&self.body(variables.clone(), false)).await;
+        if self.config.persisted_queries {
+            if let Ok((_, response)) = &res {
+                if error_codes(response)
+                    .iter()
+                    .any(|c| c == "PERSISTED_QUERY_NOT_FOUND" || c == "PersistedQueryNotFound")
+                {
+                    res = self.post(&self.body(variables, true)).await;
+                }
+            }
+        }
+        let (status, response) = match res {
+            Ok(res) => res,
+            Err(e) => return Outcome::Retry(format!("Error sending GraphQL request: {}", e)),
+        };
+        if status == 429 || status >= 500 {
+            return Outcome::Retry(format!("GraphQL endpoint returned {}", status));
+        }
+        let codes = error_codes(&response);
+        if codes.iter().any(|c| self.config.retry_codes.contains(c)) {
+            return Outcome::Retry(format!("GraphQL request failed: {}", codes.join(", ")));
+        }
+        // partial results carry data alongside the errors
+        if status >= 400 || response.get("data").map_or(true, Value::is_null) {
+            return Outcome::Reject(
+                format!("GraphQL request failed with status {}", status),
+                response,
+            );
+        }
+        Outcome::Response(response)
+    }
+
+    fn response_event(
+        &mut self,
+        event: &Event,
+        data: Value<'static>,
+        mut meta: Value<'static>,
+        correlation: Option<&Value>,
+    ) -> Event {
+        let mut id = self.idgen.next_id();
+        id.track(&event.id);
+        if let Some(correlation) = correlation {
+            meta.try_insert("correlation", correlation.clone_static());
+        }
+        Event {
+            id,
+            ingest_ns: nanotime(),
+            data: (data, meta).into(),
+            origin_uri: Some(self.event_origin_uri.clone()),
+            ..Event::default()
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for GraphQl {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        _codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        event: Event,
+    ) -> ResultVec {
+        let mut replies = Vec::with_capacity(event.len());
+        let mut first_error = None;
+        for (data, meta) in event.value_meta_iter() {
+            let correlation = meta.get("correlation");
+            let variables = if let Some(template) = &self.config.variables {
+                rest::fill(template, data, meta)
+            } else {
+                Ok(data.clone_static())
+            };
+            let outcome = match variables {
+                Ok(variables) => self.send(variables).await,
+                Err(e) => Outcome::Reject(e.to_string(), Value::null()),
+            };
+            match outcome {
+                Outcome::Response(mut response) => {
+                    let errors = response
+                        .remove("errors")
+                        .ok()
+                        .flatten()
+                        .unwrap_or_else(Value::null);
+                    let data = response
+                        .remove("data")
+                        .ok()
+                        .flatten()
+                        .unwrap_or_else(Value::null);
+                    let meta = literal!({ "graphql": { "errors": errors } });
+                    let e = self.response_event(&event, data, meta, correlation);
+                    replies.push(sink::Reply::Response(OUT, e));
+                }
+                Outcome::Reject(error, response) => {
+                    error!("[Sink::{}] {}", self.sink_url, error);
+                    let data = literal!({ "error": error.clone(), "response": response });
+                    let meta = literal!({ "error": error, "graphql": { "retry": false } });
+                    let e = self.response_event(&event, data, meta, correlation);
+                    replies.push(sink::Reply::Response(ERR, e));
+                }
+                Outcome::Retry(error) => {
+                    error!("[Sink::{}] {}", self.sink_url, error);
+                    let data = literal!({ "error": error.clone() });
+                    let meta = literal!({ "error": error.clone(), "graphql": { "retry": true } });
+                    let e = self.response_event(&event, data, meta, correlation);
+                    replies.push(sink::Reply::Response(ERR, e));
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        if let Some(e) = first_error {
+            // send away all response events before failing the event
+            for reply in replies {
+                if let Err(e) = self.reply_tx.send(reply).await {
+                    error!("[Sink::{}] Error sending error reply: {}", self.sink_url, e);
+                }
+            }
+            Err(e.into())
+        } else {
+            Ok(Some(replies))
+        }
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        _processors: Processors<'_>,
+        _is_linked: bool,
+        reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.event_origin_uri.uid = sink_uid;
+        self.sink_url = sink_url.clone();
+        self.idgen.set_source(sink_uid);
+        self.reply_tx = reply_channel;
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        true
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {}
+}
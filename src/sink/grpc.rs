@@ -0,0 +1,523 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # gRPC Offramp
+//!
+//! Issues a unary gRPC call per event against a configured service method.
+//!
+//! The request message is the event encoded with the configured codec
+//! (e.g. `protobuf`, or `binary` for pre-encoded messages). In linked mode the
+//! response message is decoded with the same codec and sent out via the
+//! `out` port, failed calls are sent out via the `err` port.
+//!
+//! The `$grpc` metadata can override the `method` and add call `metadata`
+//! per event. A `$correlation_id` is sent as `x-correlation-id` call metadata.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+#![cfg(not(tarpaulin_include))]
+
+use crate::connectors::reconnect::{self, Reconnect};
+use crate::correlation;
+use crate::sink::prelude::*;
+use bytes::{Buf, BufMut};
+use halfbrown::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tonic::codec::{DecodeBuf, Decoder, EncodeBuf, Encoder};
+use tonic::metadata::{AsciiMetadataValue, MetadataKey};
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Status};
+use tremor_pipeline::{EventId, EventIdGenerator};
+
+#[derive(Deserialize, Debug)]
+pub struct Config {
+    /// gRPC endpoint, e.g. `http://localhost:50051`
+    pub endpoint: String,
+    /// fully qualified method path, e.g. `/helloworld.Greeter/SayHello`
+    pub method: String,
+    /// number of channels (HTTP/2 connections) to spread calls over
+    #[serde(default = "d_pool_size")]
+    pub pool_size: usize,
+    /// deadline per call in milliseconds
+    #[serde(default = "d_timeout")]
+    pub timeout_ms: u64,
+    /// number of retries for failed calls with a retryable status
+    #[serde(default)]
+    pub retries: u32,
+    /// initial backoff between retries in milliseconds, doubled on every retry
+    #[serde(default = "d_backoff")]
+    pub backoff_ms: u64,
+    /// status codes that are retried, as lowercase names
+    #[serde(default = "d_retry_on")]
+    pub retry_on: Vec<String>,
+    /// static metadata added to every call
+    #[serde(default = "Default::default")]
+    pub metadata: HashMap<String, String>,
+    /// reconnect policy
+    #[serde(default)]
+    pub reconnect: reconnect::Config,
+}
+
+fn d_pool_size() -> usize {
+    1
+}
+
+fn d_timeout() -> u64 {
+    5000
+}
+
+fn d_backoff() -> u64 {
+    100
+}
+
+fn d_retry_on() -> Vec<String> {
+    vec!["unavailable".to_string(), "deadline_exceeded".to_string()]
+}
+
+impl ConfigImpl for Config {}
+
+fn code_from_name(name: &str) -> Option<Code> {
+    Some(match name {
+        "cancelled" => Code::Cancelled,
+        "unknown" => Code::Unknown,
+        "deadline_exceeded" => Code::DeadlineExceeded,
+        "resource_exhausted" => Code::ResourceExhausted,
+        "aborted" => Code::Aborted,
+        "internal" => Code::Internal,
+        "unavailable" => Code::Unavailable,
+        _ => return None,
+    })
+}
+
+/// Passes already encoded messages through to the wire
+#[derive(Debug, Default, Clone)]
+pub(crate) struct RawCodec;
+
+impl tonic::codec::Codec for RawCodec {
+    type Encode = Vec<u8>;
+    type Decode = Vec<u8>;
+    type Encoder = RawCodec;
+    type Decoder = RawCodec;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        Self
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        Self
+    }
+}
+
+impl Encoder for RawCodec {
+    type Item = Vec<u8>;
+    type Error = Status;
+
+    fn encode(
+        &mut self,
+        item: Self::Item,
+        dst: &mut EncodeBuf<'_>,
+    ) -> std::result::Result<(), Status> {
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+impl Decoder for RawCodec {
+    type Item = Vec<u8>;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> std::result::Result<Option<Vec<u8>>, Status> {
+        let mut data = vec![0_u8; src.remaining()];
+        src.copy_to_slice(&mut data);
+        Ok(Some(data))
+    }
+}
+
+pub struct Grpc {
+    config: Config,
+    sink_url: TremorUrl,
+    pool: Vec<tonic::client::Grpc<Channel>>,
+    next: usize,
+    retry_on: Vec<Code>,
+    postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    preprocessors: Preprocessors,
+    is_linked: bool,
+    response_ids: EventIdGenerator,
+    is_down: bool,
+    reconnect: Reconnect,
+}
+
+impl offramp::Impl for Grpc {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            if config.pool_size == 0 {
+                return Err("gRPC offramp requires a `pool_size` of at least 1".into());
+            }
+            let mut retry_on = Vec::with_capacity(config.retry_on.len());
+            for name in &config.retry_on {
+                retry_on.push(
+                    code_from_name(name)
+                        .ok_or_else(|| Error::from(format!("Unknown retryable code {}", name)))?,
+                );
+            }
+            let reconnect = Reconnect::new(config.reconnect.clone());
+            Ok(SinkManager::new_box(Self {
+                config,
+                sink_url: TremorUrl::from_offramp_id("grpc")?,
+                pool: vec![],
+                next: 0,
+                retry_on,
+                postprocessors: vec![],
+                metrics: None,
+                preprocessors: vec![],
+                is_linked: false,
+                response_ids: EventIdGenerator::new(0),
+                is_down: false,
+                reconnect,
+            }))
+        } else {
+            Err("gRPC offramp requires a config".into())
+        }
+    }
+}
+
+impl Grpc {
+    async fn connect(&mut self) -> Result<()> {
+        let endpoint = Endpoint::from_shared(self.config.endpoint.clone())
+            .map_err(|e| format!("Invalid gRPC endpoint {}: {}", self.config.endpoint, e))?
+            .timeout(Duration::from_millis(self.config.timeout_ms));
+        let mut pool = Vec::with_capacity(self.config.pool_size);
+        for _ in 0..self.config.pool_size {
+            let channel = endpoint
+                .connect()
+                .await
+                .map_err(|e| format!("Unable to connect to {}: {}", self.config.endpoint, e))?;
+            pool.push(tonic::client::Grpc::new(channel));
+        }
+        self.pool = pool;
+        Ok(())
+    }
+
+    /// connects, scheduling the next attempt according to the reconnect
+    /// policy if it fails
+    async fn try_connect(&mut self) -> bool {
+        match self.connect().await {
+            Ok(()) => {
+                self.reconnect.connected();
+                self.is_down = false;
+                true
+            }
+            Err(e) => {
+                warn!("[Sink::{}] {}", self.sink_url, e);
+                if self.reconnect.failed(nanotime(), &e.to_string()).is_none() {
+                    error!(
+                        "[Sink::{}] Gave up connecting to {}",
+                        self.sink_url, self.config.endpoint
+                    );
+                }
+                self.is_down = true;
+                false
+            }
+        }
+    }
+
+    fn request(
+        &self,
+        payload: Vec<u8>,
+        grpc_meta: Option<&Value>,
+        correlation_id: Option<&str>,
+    ) -> Result<tonic::Request<Vec<u8>>> {
+        let mut request = tonic::Request::new(payload);
+        let md = request.metadata_mut();
+        md.insert(
+            "grpc-timeout",
+            AsciiMetadataValue::from_str(&format!("{}m", self.config.timeout_ms))
+                .map_err(|e| Error::from(format!("{}", e)))?,
+        );
+        // propagate the correlation id, explicit metadata takes precedence
+        if let Some(correlation_id) = correlation_id {
+            if let Ok(value) = AsciiMetadataValue::from_str(correlation_id) {
+                md.insert(correlation::HEADER, value);
+            }
+        }
+        let event_md = grpc_meta
+            .and_then(|m| m.get_object("metadata"))
+            .into_iter()
+            .flat_map(|o| {
+                o.iter()
+                    .filter_map(|(k, v)| Some((k.to_string(), v.as_str()?.to_string())))
+            });
+        for (k, v) in self
+            .config
+            .metadata
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .chain(event_md)
+        {
+            let key = MetadataKey::from_bytes(k.as_bytes())
+                .map_err(|e| Error::from(format!("Invalid metadata key {}: {}", k, e)))?;
+            let value = AsciiMetadataValue::from_str(&v)
+                .map_err(|e| Error::from(format!("Invalid metadata value for {}: {}", k, e)))?;
+            md.insert(key, value);
+        }
+        Ok(request)
+    }
+
+    /// issues a unary call, retrying it according to the retry policy
+    async fn call(
+        &mut self,
+        method: &str,
+        payload: Vec<u8>,
+        grpc_meta: Option<&Value>,
+        correlation_id: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let path = http::uri::PathAndQuery::from_str(method)
+            .map_err(|e| Error::from(format!("Invalid gRPC method {}: {}", method, e)))?;
+        let deadline = Duration::from_millis(self.config.timeout_ms);
+        let mut backoff = self.config.backoff_ms;
+        let mut attempt = 0;
+        loop {
+            let request = self.request(payload.clone(), grpc_meta, correlation_id)?;
+            let idx = self.next % self.pool.len().max(1);
+            self.next = self.next.wrapping_add(1);
+            let client = self
+                .pool
+                .get_mut(idx)
+                .ok_or_else(|| Error::from("gRPC channel pool is empty"))?;
+            let res = async_std::future::timeout(deadline, async {
+                client
+                    .ready()
+                    .await
+                    .map_err(|e| Status::unavailable(e.to_string()))?;
+                client.unary(request, path.clone(), RawCodec).await
+            })
+            .await
+            .unwrap_or_else(|_| Err(Status::deadline_exceeded("deadline exceeded")));
+            match res {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(status)
+                    if attempt < self.config.retries && self.retry_on.contains(&status.code()) =>
+                {
+                    attempt += 1;
+                    debug!(
+                        "[Sink::{}] Retrying call to {} ({}/{}): {}",
+                        self.sink_url, method, attempt, self.config.retries, status
+                    );
+                    task::sleep(Duration::from_millis(backoff)).await;
+                    backoff = backoff.saturating_mul(2);
+                }
+                Err(status) => {
+                    if status.code() == Code::Unavailable && !self.is_down {
+                        self.is_down = true;
+                        self.reconnect.failed(nanotime(), &status.to_string());
+                    }
+                    return Err(format!("gRPC call to {} failed: {}", method, status).into());
+                }
+            }
+        }
+    }
+
+    fn build_response_event(
+        &mut self,
+        event: &Event,
+        codec: &mut dyn Codec,
+        method: &str,
+        data: Vec<u8>,
+    ) -> Result<Vec<Event>> {
+        let mut meta = Value::object_with_capacity(2);
+        if let Some(correlation) = event.correlation_meta() {
+            meta.insert("correlation", correlation)?;
+        }
+        meta.insert("grpc", literal!({ "method": method.to_string() }))?;
+        let mut ingest_ns = nanotime();
+        let preprocessed = preprocess(
+            &mut self.preprocessors,
+            &mut ingest_ns,
+            data,
+            &self.sink_url,
+        )?;
+        let mut events = Vec::with_capacity(preprocessed.len());
+        for pp in preprocessed {
+            let data = LineValue::try_new(vec![pp], |mutd| {
+                // ALLOW: we define mutd as a vector of one element above
+                let mut_data = mutd[0].as_mut_slice();
+                let body = codec
+                    .decode(mut_data, ingest_ns)?
+                    .unwrap_or_else(Value::object);
+                Ok(ValueAndMeta::from_parts(body, meta.clone()))
+            })
+            .map_err(|e: rental::RentalError<Error, _>| e.0)?;
+            let mut id = self.response_ids.next_id();
+            id.track(&event.id);
+            events.push(Event {
+                id,
+                data,
+                ingest_ns,
+                origin_uri: event.origin_uri.clone(),
+                ..Event::default()
+            });
+        }
+        Ok(events)
+    }
+
+    fn build_error_event(
+        &mut self,
+        event_id: &EventId,
+        correlation: Option<Value<'static>>,
+        e: &Error,
+    ) -> Event {
+        let mut meta = Object::with_capacity(2);
+        meta.insert_nocheck("error".into(), Value::from(e.to_string()));
+        if let Some(correlation) = correlation {
+            meta.insert_nocheck("correlation".into(), correlation);
+        }
+        let mut data = Object::with_capacity(2);
+        data.insert_nocheck("error".into(), Value::from(e.to_string()));
+        data.insert_nocheck("event_id".into(), Value::from(event_id.to_string()));
+        let mut id = self.response_ids.next_id();
+        id.track(event_id);
+        Event {
+            id,
+            data: (data, meta).into(),
+            ingest_ns: nanotime(),
+            ..Event::default()
+        }
+    }
+
+    async fn send_event(&mut self, codec: &mut dyn Codec, event: &Event) -> Result<Vec<Reply>> {
+        let mut replies = vec![];
+        for (value, meta) in event.value_meta_iter() {
+            let grpc_meta = meta.get("grpc");
+            let method = grpc_meta
+                .get_str("method")
+                .unwrap_or(&self.config.method)
+                .to_string();
+            let encoded = codec.encode(value)?;
+            for payload in postprocess(
+                &mut self.postprocessors,
+                event.ingest_ns,
+                encoded,
+                self.metrics.as_deref(),
+            )? {
+                let response = self
+                    .call(&method, payload, grpc_meta, correlation::meta_id(meta))
+                    .await?;
+                if self.is_linked {
+                    for e in self.build_response_event(event, codec, &method, response)? {
+                        replies.push(Reply::Response(OUT, e));
+                    }
+                }
+            }
+        }
+        Ok(replies)
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Grpc {
+    #[allow(clippy::cast_possible_truncation)]
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        if self.pool.is_empty() && self.reconnect.due(nanotime()) {
+            self.try_connect().await;
+        }
+        let start = Instant::now();
+        match self.send_event(codec, &event).await {
+            Ok(mut replies) => {
+                if event.transactional {
+                    replies.push(Reply::Insight(
+                        event.insight_ack_with_timing(start.elapsed().as_millis() as u64),
+                    ));
+                }
+                Ok(Some(replies))
+            }
+            Err(e) => {
+                error!("[Sink::{}] Error sending event: {}", self.sink_url, e);
+                let mut replies = vec![];
+                if self.is_linked {
+                    let correlation = event.correlation_meta();
+                    replies.push(Reply::Response(
+                        ERR,
+                        self.build_error_event(&event.id, correlation, &e),
+                    ));
+                }
+                if event.transactional {
+                    replies.push(Reply::Insight(event.to_fail()));
+                }
+                if self.is_down {
+                    replies.push(Reply::Insight(event.insight_trigger()));
+                }
+                Ok(Some(replies))
+            }
+        }
+    }
+
+    fn default_codec(&self) -> &str {
+        "binary"
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.sink_url = sink_url.clone();
+        self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
+        self.preprocessors = make_preprocessors(processors.pre)?;
+        self.is_linked = is_linked;
+        self.response_ids = EventIdGenerator::new(sink_uid);
+        self.reconnect.report_to(processors.stats);
+        // on failure we try again on events or signals once the next attempt is due
+        self.try_connect().await;
+        Ok(())
+    }
+
+    async fn on_signal(&mut self, signal: Event) -> ResultVec {
+        if self.is_down && self.reconnect.due(nanotime()) && self.try_connect().await {
+            info!(
+                "[Sink::{}] Reconnected to {}",
+                self.sink_url, self.config.endpoint
+            );
+            return Ok(Some(vec![Reply::Insight(Event::cb_restore(
+                signal.ingest_ns,
+            ))]));
+        }
+        Ok(None)
+    }
+
+    fn is_active(&self) -> bool {
+        !self.is_down
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+}
@@ -18,10 +18,19 @@
 //!
 //! The `kafka` offramp allows persisting events to a kafka queue.
 //!
+//! Message keys and headers are taken from `$kafka.key` and
+//! `$kafka.headers`, the way the `kafka` onramp provides them. Strings and
+//! bytes are sent as they are, other values are encoded with `key_codec`
+//! and `header_codec`, or as JSON if they aren't set. With `topic_from_meta`
+//! events are sent to the topic in `$kafka.topic` if they have one.
+//!
 //! ## Configuration
 //!
 //! See [Config](struct.Config.html) for details.
 
+use crate::codec;
+use crate::correlation;
+use crate::ramp::kafka::{Security, TokenRefresh};
 use crate::sink::prelude::*;
 use async_channel::{bounded, Receiver, Sender};
 use halfbrown::HashMap;
@@ -42,6 +51,10 @@ pub struct Config {
     pub brokers: Vec<String>,
     /// the topic to send to
     pub topic: String,
+    /// send events to the topic in `$kafka.topic` if they have one, off by
+    /// default as events from the `kafka` onramp carry their source topic
+    #[serde(default = "Default::default")]
+    pub topic_from_meta: bool,
     /// a map (string keys and string values) of [librdkafka options](https://github.com/edenhill/librdkafka/blob/master/CONFIGURATION.md) (default: None) - Note this can overwrite default settings.
     ///
     /// Default settings for librdkafka:
@@ -58,6 +71,17 @@ pub struct Config {
     /// key to use for messages, defaults to none
     #[serde(default = "Default::default")]
     pub key: Option<String>,
+    /// codec encoding `$kafka.key` values that are neither strings nor bytes
+    #[serde(default = "Default::default")]
+    pub key_codec: Option<String>,
+    /// codec encoding `$kafka.headers` values that are neither strings nor
+    /// bytes
+    #[serde(default = "Default::default")]
+    pub header_codec: Option<String>,
+    /// Typed SASL and TLS settings, they can't be combined with the same
+    /// settings in `rdkafka_options`
+    #[serde(default = "Default::default")]
+    pub security: Option<Security>,
 }
 
 impl Config {
@@ -72,6 +96,9 @@ impl Config {
             .set("bootstrap.servers", &self.brokers.join(","))
             .set("message.timeout.ms", "5000")
             .set("queue.buffering.max.ms", "0"); // set to 0 for sending each message out immediately without kafka client internal batching --> low latency, busy network
+        if let Some(security) = &self.security {
+            security.apply(producer_config);
+        }
 
         Ok(self
             .rdkafka_options
@@ -83,6 +110,20 @@ impl Config {
 
 impl ConfigImpl for Config {}
 
+impl Config {
+    /// Validates the config ahead of starting the offramp
+    pub(crate) fn validate(&self) -> Result<()> {
+        // fail on unknown codecs before the offramp is started
+        for name in self.key_codec.iter().chain(self.header_codec.iter()) {
+            codec::lookup(name)?;
+        }
+        if let Some(security) = &self.security {
+            security.validate(Some(&self.rdkafka_options))?;
+        }
+        Ok(())
+    }
+}
+
 fn d_host() -> String {
     hostname()
 }
@@ -93,9 +134,16 @@ pub struct Kafka {
     config: Config,
     producer: FutureProducer,
     postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    /// encoding buffer reused across events
+    buf: Vec<u8>,
     reply_tx: Sender<sink::Reply>,
     error_rx: Receiver<KafkaError>,
     error_tx: Sender<KafkaError>,
+    /// `OAUTHBEARER` tokens handed to the producer
+    token_refresh: Option<TokenRefresh>,
+    key_codec: Option<Box<dyn Codec>>,
+    header_codec: Option<Box<dyn Codec>>,
 }
 
 impl fmt::Debug for Kafka {
@@ -108,7 +156,13 @@ impl offramp::Impl for Kafka {
     fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
         if let Some(config) = config {
             let config: Config = Config::new(config)?;
+            config.validate()?;
             let producer = config.producer()?;
+            let mut token_refresh = config.security.as_ref().and_then(Security::token_refresh);
+            // authenticate with a token right away
+            if let Some(token_refresh) = token_refresh.as_mut() {
+                token_refresh.refresh(producer.client())?;
+            }
             // Create the thread pool where the expensive computation will be performed.
             let (dummy_tx, _) = bounded(1);
 
@@ -116,12 +170,21 @@ impl offramp::Impl for Kafka {
             let (error_tx, error_rx) = bounded(crate::QSIZE);
             Ok(SinkManager::new_box(Self {
                 sink_url: TremorUrl::from_offramp_id("kafka")?, // dummy
-                config,
                 producer,
                 postprocessors: vec![],
+                metrics: None,
+                buf: Vec::with_capacity(1024),
                 reply_tx: dummy_tx,
                 error_rx,
                 error_tx,
+                token_refresh,
+                key_codec: config.key_codec.as_deref().map(codec::lookup).transpose()?,
+                header_codec: config
+                    .header_codec
+                    .as_deref()
+                    .map(codec::lookup)
+                    .transpose()?,
+                config,
             }))
         } else {
             Err("Kafka offramp requires a config".into())
@@ -129,6 +192,20 @@ impl offramp::Impl for Kafka {
     }
 }
 
+/// the bytes of a key or header value, strings and bytes as they are, other
+/// values encoded with the codec or as JSON if there is none
+fn to_bytes<'value>(
+    codec: Option<&dyn Codec>,
+    value: &'value Value,
+) -> Result<std::borrow::Cow<'value, [u8]>> {
+    Ok(match (value, codec) {
+        (Value::String(s), _) => std::borrow::Cow::Borrowed(s.as_bytes()),
+        (Value::Bytes(b), _) => std::borrow::Cow::Borrowed(&b[..]),
+        (_, Some(codec)) => std::borrow::Cow::Owned(codec.encode(value)?),
+        (_, None) => std::borrow::Cow::Owned(value.encode().into_bytes()),
+    })
+}
+
 fn is_fatal(e: &KafkaError) -> bool {
     matches!(
         e,
@@ -247,6 +324,9 @@ impl Kafka {
         }
         error!("[Sink::{}] Reinitiating client...", &self.sink_url);
         self.producer = self.config.producer()?;
+        if let Some(token_refresh) = self.token_refresh.as_mut() {
+            token_refresh.reset();
+        }
         error!("[Sink::{}] Client reinitiated.", &self.sink_url);
 
         Ok(())
@@ -264,41 +344,79 @@ impl Sink for Kafka {
     ) -> ResultVec {
         // ensure we handle any fatal errors occured during last on_event invocation
         self.drain_fatal_errors()?;
+        if let Some(token_refresh) = self.token_refresh.as_mut() {
+            if let Err(e) = token_refresh.refresh(self.producer.client()) {
+                error!("[Sink::{}] {}", self.sink_url, e);
+            }
+        }
 
         let ingest_ns = event.ingest_ns;
         let mut delivery_futures = Vec::with_capacity(event.len()); // might not be enough
         let processing_start = Instant::now();
+        // the buffer is given back once all values are enqueued, on errors we start over
+        let mut buf = std::mem::take(&mut self.buf);
+        let mut processed: Vec<Vec<u8>>;
         for (value, meta) in event.value_meta_iter() {
-            let encoded = codec.encode(value)?;
-            let processed = postprocess(self.postprocessors.as_mut_slice(), ingest_ns, encoded)?;
+            buf.clear();
+            codec.encode_into(value, &mut buf)?;
+            // the producer copies payloads, so without postprocessors we send the buffer as is
+            let payloads = if self.postprocessors.is_empty() {
+                if let Some(metrics) = &self.metrics {
+                    metrics.count(buf.len(), buf.len());
+                }
+                std::slice::from_ref(&buf)
+            } else {
+                processed = postprocess_slice(
+                    self.postprocessors.as_mut_slice(),
+                    ingest_ns,
+                    &buf,
+                    self.metrics.as_deref(),
+                )?;
+                processed.as_slice()
+            };
             let meta_kafka_data = meta.get_object("kafka");
             let mut meta_kafka_key = None;
             let mut meta_kafka_headers = None;
+            let mut topic = self.config.topic.as_str();
             if let Some(meta_data) = meta_kafka_data {
                 meta_kafka_key = meta_data.get("key");
                 meta_kafka_headers = meta_data.get("headers");
+                if self.config.topic_from_meta {
+                    if let Some(meta_topic) = meta_data.get("topic").and_then(Value::as_str) {
+                        topic = meta_topic;
+                    }
+                }
+            }
+            let key = meta_kafka_key
+                .map(|k| to_bytes(self.key_codec.as_deref(), k))
+                .transpose()?;
+            let headers_obj = meta_kafka_headers.and_then(ValueAccess::as_object);
+            let mut header_values = Vec::with_capacity(headers_obj.map_or(0, HashMap::len));
+            for (name, value) in headers_obj.into_iter().flat_map(|h| h.iter()) {
+                header_values.push((name, to_bytes(self.header_codec.as_deref(), value)?));
             }
-            for payload in processed {
+            for payload in payloads {
                 // TODO: allow defining partition and timestamp in meta
-                let mut record = FutureRecord::to(self.config.topic.as_str());
-                record = record.payload(&payload);
-                if let Some(kafka_key) = meta_kafka_key {
-                    if let Some(kafka_key_str) = kafka_key.as_str() {
-                        record = record.key(kafka_key_str);
-                    }
+                let mut record = FutureRecord::to(topic);
+                record = record.payload(payload);
+                if let Some(key) = &key {
+                    record = record.key(&key[..]);
                 } else if let Some(kafka_key) = &self.config.key {
                     record = record.key(kafka_key.as_str());
                 }
-                if let Some(kafka_headers) = meta_kafka_headers {
-                    if let Some(headers_obj) = kafka_headers.as_object() {
-                        let mut headers = OwnedHeaders::new_with_capacity(headers_obj.len());
-                        for (key, val) in headers_obj.iter() {
-                            if let Some(val_str) = val.as_str() {
-                                headers = headers.add(key, val_str);
-                            }
-                        }
-                        record = record.headers(headers);
+                // propagate the correlation id, explicit headers take precedence
+                let correlation_id = correlation::meta_id(meta).filter(|_| {
+                    !headers_obj.map_or(false, |h| h.contains_key(correlation::HEADER))
+                });
+                if headers_obj.is_some() || correlation_id.is_some() {
+                    let mut headers = OwnedHeaders::new_with_capacity(header_values.len() + 1);
+                    if let Some(correlation_id) = correlation_id {
+                        headers = headers.add(correlation::HEADER, correlation_id);
+                    }
+                    for (name, value) in &header_values {
+                        headers = headers.add(name, &value[..]);
                     }
+                    record = record.headers(headers);
                 }
                 // send out without blocking on delivery
                 match self.producer.send_result(record) {
@@ -323,6 +441,7 @@ impl Sink for Kafka {
                 }
             }
         }
+        self.buf = buf;
         let insight_event = if event.transactional {
             // we gonna change the success status later, if need be
             Some(event.insight_ack())
@@ -356,6 +475,7 @@ impl Sink for Kafka {
         reply_channel: Sender<sink::Reply>,
     ) -> Result<()> {
         self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
         self.reply_tx = reply_channel;
         self.sink_url = sink_url.clone();
         Ok(())
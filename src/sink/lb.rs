@@ -0,0 +1,718 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Load Balancing Offramp
+//!
+//! Wraps multiple `targets`, e.g. several elastic coordinators or HTTP
+//! backends, and delivers each event to exactly one of them.
+//!
+//! With the `weighted_round_robin` strategy events are spread according to
+//! the `weight` of each target. With `least_loaded` each event goes to the
+//! target with the fewest unacknowledged transactional events relative to
+//! its weight. With `consistent_hash` all events with the same `key` go to
+//! the same target, events without a key are balanced round robin. The
+//! targets are placed on a hash ring by their `id`, so adding or removing a
+//! target only moves the keys of that target.
+//!
+//! A target is excluded while its circuit breaker is triggered and for
+//! `cooldown_ms` after `max_failures` consecutive failed events, keys of an
+//! excluded target move to the next target on the ring. Upstream
+//! only sees a triggered circuit breaker once all targets are down. Events
+//! failing on a target are failed upstream, they are not retried on other
+//! targets.
+//!
+//! All targets use the codec and processors of the load balancing offramp.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::correlation::Location;
+use crate::pipeline;
+use crate::sink::prelude::*;
+use async_channel::{bounded, unbounded, Receiver};
+use halfbrown::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// A wrapped offramp definition
+#[derive(Deserialize, Debug, Clone)]
+pub struct Target {
+    /// the offramp type
+    #[serde(rename = "type")]
+    kind: String,
+    /// the offramp configuration
+    #[serde(default = "Default::default")]
+    config: Option<OpConfig>,
+    /// relative share of events for this target
+    #[serde(default = "d_weight")]
+    weight: u32,
+    /// identifies the target on the hash ring, defaults to the type and
+    /// config of the target
+    #[serde(default)]
+    id: Option<String>,
+}
+
+impl Target {
+    fn ring_id(&self) -> Result<String> {
+        if let Some(id) = &self.id {
+            Ok(id.clone())
+        } else {
+            Ok(format!(
+                "{}:{}",
+                self.kind,
+                serde_yaml::to_string(&self.config)?
+            ))
+        }
+    }
+}
+
+fn d_weight() -> u32 {
+    1
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    WeightedRoundRobin,
+    LeastLoaded,
+    ConsistentHash,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Self::WeightedRoundRobin
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// the offramps events are balanced across
+    targets: Vec<Target>,
+    /// `weighted_round_robin` (default), `least_loaded` or `consistent_hash`
+    #[serde(default = "Default::default")]
+    strategy: Strategy,
+    /// where to read the key for `consistent_hash` from, the first location
+    /// present wins
+    #[serde(default = "Default::default")]
+    key: Vec<Location>,
+    /// points on the hash ring per unit of weight
+    #[serde(default = "d_virtual_nodes")]
+    virtual_nodes: u32,
+    /// consecutive failures after which a target is excluded
+    #[serde(default = "d_max_failures")]
+    max_failures: u64,
+    /// how long a failing target is excluded in milliseconds
+    #[serde(default = "d_cooldown_ms")]
+    cooldown_ms: u64,
+}
+
+fn d_max_failures() -> u64 {
+    3
+}
+
+fn d_cooldown_ms() -> u64 {
+    10_000
+}
+
+fn d_virtual_nodes() -> u32 {
+    160
+}
+
+impl ConfigImpl for Config {}
+
+/// Health of a target, shared with the task handling its insights
+#[derive(Debug, Default)]
+struct Health {
+    /// transactional events not yet acked or failed
+    in_flight: AtomicU64,
+    /// consecutive failures
+    failures: AtomicU64,
+    /// the circuit breaker of the target is triggered
+    down: AtomicBool,
+    /// the target is excluded until this time
+    excluded_until: AtomicU64,
+}
+
+impl Health {
+    fn is_available(&self, now: u64) -> bool {
+        !self.down.load(Ordering::Acquire) && self.excluded_until.load(Ordering::Acquire) <= now
+    }
+
+    fn on_success(&self) {
+        self.failures.store(0, Ordering::Release);
+    }
+
+    fn on_failure(&self, max_failures: u64, cooldown_ns: u64, now: u64) {
+        if self.failures.fetch_add(1, Ordering::AcqRel) + 1 >= max_failures {
+            self.excluded_until
+                .store(now + cooldown_ns, Ordering::Release);
+            self.failures.store(0, Ordering::Release);
+        }
+    }
+
+    fn settle(&self) {
+        // never underflow on acks for events we didn't count
+        let _ = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+    }
+}
+
+/// Scheduling state of a target
+#[derive(Debug)]
+struct Slot {
+    weight: u32,
+    /// current weight for smooth weighted round robin
+    current: i64,
+    health: Arc<Health>,
+}
+
+/// smooth weighted round robin, as used by nginx: spreads events evenly
+/// instead of sending `weight` events in a row to the same target
+fn weighted_round_robin(slots: &mut [Slot], now: u64) -> Option<usize> {
+    let mut total = 0;
+    let mut best: Option<usize> = None;
+    for i in 0..slots.len() {
+        if slots[i].health.is_available(now) {
+            let weight = i64::from(slots[i].weight);
+            slots[i].current += weight;
+            total += weight;
+            if best.map_or(true, |b| slots[i].current > slots[b].current) {
+                best = Some(i);
+            }
+        }
+    }
+    if let Some(b) = best {
+        slots[b].current -= total;
+    }
+    best
+}
+
+/// the available target with the least in flight events relative to its
+/// weight, ties are broken round robin starting at `start`
+fn least_loaded(slots: &[Slot], now: u64, start: usize) -> Option<usize> {
+    let mut best: Option<(usize, u64)> = None;
+    for i in 0..slots.len() {
+        let idx = (start + i) % slots.len();
+        let slot = &slots[idx];
+        if !slot.health.is_available(now) {
+            continue;
+        }
+        let load = slot.health.in_flight.load(Ordering::Acquire);
+        let better = best.map_or(true, |(b, b_load)| {
+            // load / weight < b_load / b_weight
+            u128::from(load) * u128::from(slots[b].weight)
+                < u128::from(b_load) * u128::from(slot.weight)
+        });
+        if better {
+            best = Some((idx, load));
+        }
+    }
+    best.map(|(idx, _)| idx)
+}
+
+fn hash<T: Hash + ?Sized>(t: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    t.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// hash ring with `weight * virtual_nodes` points per target, sorted by hash
+fn build_ring(targets: &[(String, u32)], virtual_nodes: u32) -> Vec<(u64, usize)> {
+    let mut ring = Vec::new();
+    for (idx, (id, weight)) in targets.iter().enumerate() {
+        for n in 0..weight * virtual_nodes {
+            ring.push((hash(&format!("{}#{}", id, n)), idx));
+        }
+    }
+    ring.sort_unstable();
+    ring
+}
+
+/// the first available target on the ring at or after the hash of the key
+fn consistent_hash(ring: &[(u64, usize)], slots: &[Slot], now: u64, key: &str) -> Option<usize> {
+    let key = hash(key);
+    let start = match ring.binary_search_by(|(h, _)| h.cmp(&key)) {
+        Ok(i) | Err(i) => i,
+    };
+    (0..ring.len())
+        .map(|i| ring[(start + i) % ring.len()].1)
+        .find(|idx| {
+            slots
+                .get(*idx)
+                .map_or(false, |s| s.health.is_available(now))
+        })
+}
+
+/// Handles the insights of all targets and derives the circuit breaker
+/// state of the load balancer from theirs
+struct Insights {
+    healths: Vec<Arc<Health>>,
+    triggered: AtomicBool,
+    reply_channel: Sender<sink::Reply>,
+    max_failures: u64,
+    cooldown_ns: u64,
+    url: TremorUrl,
+}
+
+impl Insights {
+    async fn handle(&self, health: &Health, insight: Event) {
+        match insight.cb {
+            CbAction::Ack => {
+                health.settle();
+                health.on_success();
+                self.forward(insight).await;
+            }
+            CbAction::Fail => {
+                health.settle();
+                health.on_failure(self.max_failures, self.cooldown_ns, nanotime());
+                self.forward(insight).await;
+            }
+            CbAction::Close => {
+                health.down.store(true, Ordering::Release);
+                if self.healths.iter().all(|h| h.down.load(Ordering::Acquire))
+                    && !self.triggered.swap(true, Ordering::AcqRel)
+                {
+                    info!("[Offramp::{}] All targets are down", self.url);
+                    self.forward(Event::cb_trigger(insight.ingest_ns)).await;
+                }
+            }
+            CbAction::Open => {
+                health.down.store(false, Ordering::Release);
+                if self.triggered.swap(false, Ordering::AcqRel) {
+                    self.forward(Event::cb_restore(insight.ingest_ns)).await;
+                }
+            }
+            CbAction::None => self.forward(insight).await,
+        }
+    }
+
+    async fn forward(&self, insight: Event) {
+        if let Err(e) = self.reply_channel.send(sink::Reply::Insight(insight)).await {
+            error!("[Offramp::{}] Failed to forward insight: {}", self.url, e);
+        }
+    }
+}
+
+/// Receives the insights a target sends to its pipelines or replies via its
+/// reply channel
+async fn tap(
+    insights: Arc<Insights>,
+    health: Arc<Health>,
+    cf_rx: Receiver<pipeline::CfMsg>,
+    reply_rx: Receiver<sink::Reply>,
+) {
+    let cf = cf_rx.map(|pipeline::CfMsg::Insight(e)| sink::Reply::Insight(e));
+    let mut replies = Box::pin(cf.merge(reply_rx));
+    while let Some(reply) = replies.next().await {
+        match reply {
+            sink::Reply::Insight(insight) => insights.handle(&health, insight).await,
+            response @ sink::Reply::Response(..) => {
+                if let Err(e) = insights.reply_channel.send(response).await {
+                    error!(
+                        "[Offramp::{}] Failed to forward response: {}",
+                        insights.url, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+pub struct Lb {
+    targets: Vec<Box<dyn Offramp>>,
+    slots: Vec<Slot>,
+    /// hash ring for `consistent_hash`
+    ring: Vec<(u64, usize)>,
+    config: Config,
+    /// round robin start for breaking ties in `least_loaded`
+    next: usize,
+    reply_channel: Option<Sender<sink::Reply>>,
+    pipelines: Vec<TremorUrl>,
+    dest_pipelines: HashMap<Cow<'static, str>, Vec<TremorUrl>>,
+}
+
+impl offramp::Impl for Lb {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            if config.targets.is_empty() {
+                return Err("Load balancing offramp requires at least one target".into());
+            }
+            let mut targets = Vec::with_capacity(config.targets.len());
+            let mut slots = Vec::with_capacity(config.targets.len());
+            let mut ring_ids = Vec::with_capacity(config.targets.len());
+            for target in &config.targets {
+                if target.kind == "lb" {
+                    return Err("Load balancing offramp can not wrap another lb offramp".into());
+                }
+                if target.weight == 0 {
+                    return Err("Load balancing offramp target weights must be at least 1".into());
+                }
+                targets.push(offramp::lookup(&target.kind, &target.config)?);
+                ring_ids.push((target.ring_id()?, target.weight));
+                slots.push(Slot {
+                    weight: target.weight,
+                    current: 0,
+                    health: Arc::new(Health::default()),
+                });
+            }
+            let ring = if config.strategy == Strategy::ConsistentHash {
+                if config.key.is_empty() {
+                    return Err("Load balancing with consistent_hash requires a key".into());
+                }
+                if config.virtual_nodes == 0 {
+                    return Err("Load balancing virtual_nodes must be at least 1".into());
+                }
+                build_ring(&ring_ids, config.virtual_nodes)
+            } else {
+                Vec::new()
+            };
+            Ok(Box::new(Self {
+                targets,
+                slots,
+                ring,
+                config,
+                next: 0,
+                reply_channel: None,
+                pipelines: Vec::new(),
+                dest_pipelines: HashMap::new(),
+            }))
+        } else {
+            Err("Load balancing offramp requires a config".into())
+        }
+    }
+}
+
+impl Lb {
+    fn pick(&mut self, now: u64, event: &Event) -> Option<usize> {
+        match self.config.strategy {
+            Strategy::WeightedRoundRobin => weighted_round_robin(&mut self.slots, now),
+            Strategy::LeastLoaded => {
+                let res = least_loaded(&self.slots, now, self.next);
+                self.next = res.map_or(self.next, |idx| idx + 1);
+                res
+            }
+            Strategy::ConsistentHash => {
+                let key = event.value_meta_iter().next().and_then(|(data, meta)| {
+                    self.config.key.iter().find_map(|l| l.extract(data, meta))
+                });
+                if let Some(key) = key {
+                    consistent_hash(&self.ring, &self.slots, now, &key)
+                } else {
+                    weighted_round_robin(&mut self.slots, now)
+                }
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pipelines.is_empty() && self.dest_pipelines.values().all(Vec::is_empty)
+    }
+}
+
+#[async_trait::async_trait]
+impl Offramp for Lb {
+    #[allow(clippy::too_many_arguments)]
+    async fn start(
+        &mut self,
+        offramp_uid: u64,
+        offramp_url: &TremorUrl,
+        codec: &dyn Codec,
+        codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        is_linked: bool,
+        reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        let insights = Arc::new(Insights {
+            healths: self.slots.iter().map(|s| s.health.clone()).collect(),
+            triggered: AtomicBool::new(false),
+            reply_channel: reply_channel.clone(),
+            max_failures: self.config.max_failures,
+            cooldown_ns: self.config.cooldown_ms * 1_000_000,
+            url: offramp_url.clone(),
+        });
+        for (target, slot) in self.targets.iter_mut().zip(&self.slots) {
+            let (reply_tx, reply_rx) = unbounded();
+            target
+                .start(
+                    offramp_uid,
+                    offramp_url,
+                    codec,
+                    codec_map,
+                    Processors {
+                        pre: processors.pre,
+                        post: processors.post,
+                        metrics: processors.metrics.clone(),
+                        stats: processors.stats.clone(),
+                    },
+                    is_linked,
+                    reply_tx,
+                )
+                .await?;
+            // the target reports insights to this tap instead of the real pipelines
+            let (tx, _) = bounded(1);
+            let (mgmt_tx, _) = bounded(1);
+            let (cf_tx, cf_rx) = unbounded();
+            target.add_pipeline(
+                offramp_url.clone(),
+                pipeline::Addr::new(tx, cf_tx, mgmt_tx, offramp_url.clone()),
+            );
+            task::spawn(tap(insights.clone(), slot.health.clone(), cf_rx, reply_rx));
+        }
+        self.reply_channel = Some(reply_channel);
+        Ok(())
+    }
+
+    async fn on_event(
+        &mut self,
+        codec: &mut dyn Codec,
+        codec_map: &HashMap<String, Box<dyn Codec>>,
+        input: &str,
+        event: Event,
+    ) -> Result<()> {
+        let now = nanotime();
+        let idx = self
+            .pick(now, &event)
+            .ok_or_else(|| Error::from("No healthy target available"))?;
+        let (target, health) = match (self.targets.get_mut(idx), self.slots.get(idx)) {
+            (Some(target), Some(slot)) => (target, &slot.health),
+            _ => return Err("Invalid load balancing target".into()),
+        };
+        let auto_ack = target.auto_ack();
+        let transactional = event.transactional;
+        let ingest_ns = event.ingest_ns;
+        let ids = event.id.clone();
+        if transactional && !auto_ack {
+            health.in_flight.fetch_add(1, Ordering::AcqRel);
+        }
+        if let Err(e) = target.on_event(codec, codec_map, input, event).await {
+            if transactional && !auto_ack {
+                health.settle();
+            }
+            health.on_failure(
+                self.config.max_failures,
+                self.config.cooldown_ms * 1_000_000,
+                now,
+            );
+            return Err(e);
+        }
+        if auto_ack {
+            health.on_success();
+            // we don't auto ack ourselves, so we have to ack for the target
+            if let (true, Some(reply_channel)) = (transactional, &self.reply_channel) {
+                reply_channel
+                    .send(sink::Reply::Insight(Event::cb_ack(ingest_ns, ids)))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_signal(&mut self, signal: Event) -> Option<Event> {
+        for target in &mut self.targets {
+            target.on_signal(signal.clone()).await;
+        }
+        None
+    }
+
+    async fn terminate(&mut self) {
+        for target in &mut self.targets {
+            target.terminate().await;
+        }
+    }
+
+    fn default_codec(&self) -> &str {
+        self.targets
+            .first()
+            .map_or("json", |target| target.default_codec())
+    }
+
+    fn add_pipeline(&mut self, id: TremorUrl, _addr: pipeline::Addr) {
+        // insights reach the pipelines via the reply channel
+        self.pipelines.push(id);
+    }
+
+    fn remove_pipeline(&mut self, id: TremorUrl) -> bool {
+        self.pipelines.retain(|p| p != &id);
+        self.is_done()
+    }
+
+    fn add_dest_pipeline(&mut self, port: Cow<'static, str>, id: TremorUrl, addr: pipeline::Addr) {
+        for target in &mut self.targets {
+            target.add_dest_pipeline(port.clone(), id.clone(), addr.clone());
+        }
+        self.dest_pipelines.entry(port).or_default().push(id);
+    }
+
+    fn remove_dest_pipeline(&mut self, port: Cow<'static, str>, id: TremorUrl) -> bool {
+        for target in &mut self.targets {
+            target.remove_dest_pipeline(port.clone(), id.clone());
+        }
+        if let Some(ids) = self.dest_pipelines.get_mut(&port) {
+            ids.retain(|p| p != &id);
+        }
+        self.is_done()
+    }
+
+    fn is_active(&self) -> bool {
+        let now = nanotime();
+        self.slots.iter().any(|s| s.health.is_available(now))
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn slots(weights: &[u32]) -> Vec<Slot> {
+        weights
+            .iter()
+            .map(|weight| Slot {
+                weight: *weight,
+                current: 0,
+                health: Arc::new(Health::default()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn smooth_weighted_round_robin() {
+        let mut slots = slots(&[5, 1, 1]);
+        let picks: Vec<_> = (0..7)
+            .filter_map(|_| weighted_round_robin(&mut slots, 0))
+            .collect();
+        // the nginx example sequence: a a b a c a a
+        assert_eq!(vec![0, 0, 1, 0, 2, 0, 0], picks);
+
+        slots[0].health.down.store(true, Ordering::Release);
+        assert!((0..4).all(|_| weighted_round_robin(&mut slots, 0) != Some(0)));
+        slots[1].health.down.store(true, Ordering::Release);
+        slots[2].health.excluded_until.store(10, Ordering::Release);
+        assert_eq!(None, weighted_round_robin(&mut slots, 5));
+        assert_eq!(Some(2), weighted_round_robin(&mut slots, 10));
+    }
+
+    #[test]
+    fn least_loaded_by_weight() {
+        let slots = slots(&[1, 2]);
+        // ties are broken round robin
+        assert_eq!(Some(0), least_loaded(&slots, 0, 0));
+        assert_eq!(Some(1), least_loaded(&slots, 0, 1));
+        slots[0].health.in_flight.store(2, Ordering::Release);
+        slots[1].health.in_flight.store(3, Ordering::Release);
+        // 3 / 2 < 2 / 1
+        assert_eq!(Some(1), least_loaded(&slots, 0, 0));
+        slots[1].health.in_flight.store(5, Ordering::Release);
+        assert_eq!(Some(0), least_loaded(&slots, 0, 0));
+        slots[0].health.down.store(true, Ordering::Release);
+        assert_eq!(Some(1), least_loaded(&slots, 0, 0));
+    }
+
+    #[test]
+    fn consistent_hash_ring() {
+        let ids: Vec<_> = (0..4).map(|i| (format!("target{}", i), 1)).collect();
+        let ring = build_ring(&ids, 160);
+        assert_eq!(4 * 160, ring.len());
+        let all = slots(&[1, 1, 1, 1]);
+        let keys: Vec<_> = (0..1000).map(|i| format!("key{}", i)).collect();
+        let before: Vec<_> = keys
+            .iter()
+            .map(|k| consistent_hash(&ring, &all, 0, k))
+            .collect();
+        // every target gets a share of the keys
+        for idx in 0..4 {
+            assert!(before.iter().filter(|t| **t == Some(idx)).count() > 100);
+        }
+
+        // removing a target only moves its own keys
+        let ring = build_ring(&ids[..3], 160);
+        let fewer = slots(&[1, 1, 1]);
+        for (key, target) in keys.iter().zip(&before) {
+            let after = consistent_hash(&ring, &fewer, 0, key);
+            if *target != Some(3) {
+                assert_eq!(*target, after);
+            }
+        }
+
+        // so does excluding one
+        let ring = build_ring(&ids, 160);
+        all[3].health.down.store(true, Ordering::Release);
+        for (key, target) in keys.iter().zip(&before) {
+            let after = consistent_hash(&ring, &all, 0, key);
+            if *target == Some(3) {
+                assert_ne!(Some(3), after);
+            } else {
+                assert_eq!(*target, after);
+            }
+        }
+        assert_eq!(None, consistent_hash(&[], &all, 0, "key"));
+    }
+
+    #[test]
+    fn health() {
+        let health = Health::default();
+        health.settle();
+        assert_eq!(0, health.in_flight.load(Ordering::Acquire));
+        health.on_failure(2, 100, 1);
+        assert!(health.is_available(1));
+        health.on_success();
+        health.on_failure(2, 100, 1);
+        assert!(health.is_available(1));
+        health.on_failure(2, 100, 1);
+        assert!(!health.is_available(50));
+        assert!(health.is_available(101));
+    }
+
+    #[test]
+    fn config() {
+        let config: OpConfig = serde_yaml::from_str(
+            r#"
+strategy: least_loaded
+targets:
+  - type: blackhole
+    weight: 2
+    config:
+      warmup_secs: 0
+      stop_after_secs: 0
+      significant_figures: 1
+  - type: stdout
+"#,
+        )
+        .expect("valid yaml");
+        assert!(<Lb as offramp::Impl>::from_config(&Some(config)).is_ok());
+        let config: OpConfig =
+            serde_yaml::from_str("targets: [{type: stdout, weight: 0}]").expect("valid yaml");
+        assert!(<Lb as offramp::Impl>::from_config(&Some(config)).is_err());
+        let config: OpConfig =
+            serde_yaml::from_str("{strategy: consistent_hash, targets: [{type: stdout}]}")
+                .expect("valid yaml");
+        assert!(<Lb as offramp::Impl>::from_config(&Some(config)).is_err());
+        let config: OpConfig = serde_yaml::from_str(
+            "{strategy: consistent_hash, key: [{field: user}], targets: [{type: stdout}]}",
+        )
+        .expect("valid yaml");
+        assert!(<Lb as offramp::Impl>::from_config(&Some(config)).is_ok());
+        let config: OpConfig = serde_yaml::from_str("targets: []").expect("valid yaml");
+        assert!(<Lb as offramp::Impl>::from_config(&Some(config)).is_err());
+        assert!(<Lb as offramp::Impl>::from_config(&None).is_err());
+    }
+}
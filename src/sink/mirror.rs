@@ -0,0 +1,359 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Mirror Offramp
+//!
+//! Wraps a `primary` offramp and duplicates all traffic to a `shadow` offramp.
+//!
+//! The shadow is strictly best-effort: it runs in its own task, is fed via a
+//! bounded queue that drops events when full, and none of its insights are
+//! ever propagated upstream. Only the primary applies backpressure and
+//! acknowledges events. This allows validating parity of a new backend
+//! before migrating to it.
+//!
+//! The shadow runs as the `shadow` port of the mirror, e.g.
+//! `tremor://localhost/offramp/mirror/01/shadow`, with a uid of its own, so
+//! its logs and event ids are told apart from the primary's.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::pipeline;
+use crate::sink::prelude::*;
+use async_channel::{bounded, unbounded, TrySendError};
+use halfbrown::HashMap;
+
+/// A wrapped offramp definition
+#[derive(Deserialize, Debug, Clone)]
+pub struct Target {
+    /// the offramp type
+    #[serde(rename = "type")]
+    kind: String,
+    /// the offramp configuration
+    #[serde(default = "Default::default")]
+    config: Option<OpConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// the offramp all events are delivered to and that is acknowledging them
+    primary: Target,
+    /// the offramp that receives a best-effort copy of all events
+    shadow: Target,
+    /// maximum number of events buffered for the shadow before dropping
+    #[serde(default = "d_qsize")]
+    shadow_qsize: usize,
+}
+
+fn d_qsize() -> usize {
+    crate::QSIZE
+}
+
+/// set on the uid of shadows, offramp uids are counted up from 0 and never
+/// reach it
+const SHADOW_UID: u64 = 1 << 63;
+
+/// the url the shadow of an offramp runs as
+fn shadow_url(offramp_url: &TremorUrl) -> TremorUrl {
+    let mut url = offramp_url.clone();
+    url.set_port("shadow");
+    url
+}
+
+impl ConfigImpl for Config {}
+
+enum ShadowMsg {
+    Event { input: String, event: Event },
+    Signal(Event),
+    Terminate,
+}
+
+pub struct Mirror {
+    primary: Box<dyn Offramp>,
+    shadow: Option<Box<dyn Offramp>>,
+    shadow_tx: Option<async_channel::Sender<ShadowMsg>>,
+    config: Config,
+    offramp_url: Option<TremorUrl>,
+    dropped: u64,
+}
+
+impl offramp::Impl for Mirror {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            if config.primary.kind == "mirror" || config.shadow.kind == "mirror" {
+                return Err("Mirror offramp can not wrap another mirror offramp".into());
+            }
+            let primary = offramp::lookup(&config.primary.kind, &config.primary.config)?;
+            let shadow = offramp::lookup(&config.shadow.kind, &config.shadow.config)?;
+            Ok(Box::new(Self {
+                primary,
+                shadow: Some(shadow),
+                shadow_tx: None,
+                config,
+                offramp_url: None,
+                dropped: 0,
+            }))
+        } else {
+            Err("Mirror offramp requires a config".into())
+        }
+    }
+}
+
+impl Mirror {
+    fn url(&self) -> String {
+        self.offramp_url
+            .as_ref()
+            .map_or_else(String::new, ToString::to_string)
+    }
+
+    fn send_to_shadow(&mut self, msg: ShadowMsg) {
+        if let Some(tx) = &self.shadow_tx {
+            match tx.try_send(msg) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    if self.dropped % 1000 == 1 {
+                        warn!(
+                            "[Offramp::{}] Shadow queue full, dropped {} events so far.",
+                            self.url(),
+                            self.dropped
+                        );
+                    }
+                }
+                Err(TrySendError::Closed(_)) => {
+                    warn!(
+                        "[Offramp::{}] Shadow offramp stopped, disabling mirroring.",
+                        self.url()
+                    );
+                    self.shadow_tx = None;
+                }
+            }
+        }
+    }
+}
+
+fn clone_codec_map(codec_map: &HashMap<String, Box<dyn Codec>>) -> HashMap<String, Box<dyn Codec>> {
+    codec_map
+        .iter()
+        .map(|(k, v)| (k.clone(), v.boxed_clone()))
+        .collect()
+}
+
+#[async_trait::async_trait]
+impl Offramp for Mirror {
+    #[allow(clippy::too_many_arguments)]
+    async fn start(
+        &mut self,
+        offramp_uid: u64,
+        offramp_url: &TremorUrl,
+        codec: &dyn Codec,
+        codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        is_linked: bool,
+        reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.offramp_url = Some(offramp_url.clone());
+        self.primary
+            .start(
+                offramp_uid,
+                offramp_url,
+                codec,
+                codec_map,
+                // only the primary counts bytes, the shadow would count them twice
+                Processors {
+                    pre: processors.pre,
+                    post: processors.post,
+                    metrics: processors.metrics.clone(),
+                    stats: processors.stats.clone(),
+                },
+                is_linked,
+                reply_channel,
+            )
+            .await?;
+
+        if let Some(mut shadow) = self.shadow.take() {
+            let shadow_url = shadow_url(offramp_url);
+            // replies of the shadow are swallowed, it must never apply backpressure
+            let (shadow_reply_tx, shadow_reply_rx) = unbounded();
+            task::spawn(async move { while shadow_reply_rx.recv().await.is_ok() {} });
+            if let Err(e) = shadow
+                .start(
+                    offramp_uid | SHADOW_UID,
+                    &shadow_url,
+                    codec,
+                    codec_map,
+                    Processors {
+                        metrics: None,
+                        stats: None,
+                        ..processors
+                    },
+                    false,
+                    shadow_reply_tx,
+                )
+                .await
+            {
+                warn!(
+                    "[Offramp::{}] Failed to start shadow offramp, mirroring disabled: {}",
+                    offramp_url, e
+                );
+                return Ok(());
+            }
+
+            let (tx, rx) = bounded(self.config.shadow_qsize);
+            let mut codec = codec.boxed_clone();
+            let codec_map = clone_codec_map(codec_map);
+            let url = shadow_url;
+            task::spawn(async move {
+                while let Ok(msg) = rx.recv().await {
+                    match msg {
+                        ShadowMsg::Event { input, event } => {
+                            if let Err(e) = shadow
+                                .on_event(codec.as_mut(), &codec_map, &input, event)
+                                .await
+                            {
+                                debug!("[Offramp::{}] Shadow error: {}", url, e);
+                            }
+                        }
+                        ShadowMsg::Signal(signal) => {
+                            shadow.on_signal(signal).await;
+                        }
+                        ShadowMsg::Terminate => break,
+                    }
+                }
+                shadow.terminate().await;
+                info!("[Offramp::{}] Shadow offramp stopped", url);
+            });
+            self.shadow_tx = Some(tx);
+        }
+        Ok(())
+    }
+
+    async fn on_event(
+        &mut self,
+        codec: &mut dyn Codec,
+        codec_map: &HashMap<String, Box<dyn Codec>>,
+        input: &str,
+        event: Event,
+    ) -> Result<()> {
+        if self.shadow_tx.is_some() {
+            self.send_to_shadow(ShadowMsg::Event {
+                input: input.to_string(),
+                event: event.clone(),
+            });
+        }
+        self.primary.on_event(codec, codec_map, input, event).await
+    }
+
+    async fn on_signal(&mut self, signal: Event) -> Option<Event> {
+        if self.shadow_tx.is_some() {
+            self.send_to_shadow(ShadowMsg::Signal(signal.clone()));
+        }
+        self.primary.on_signal(signal).await
+    }
+
+    async fn terminate(&mut self) {
+        if let Some(tx) = self.shadow_tx.take() {
+            // make sure the terminate message isn't dropped due to a full queue
+            if let Err(e) = tx.send(ShadowMsg::Terminate).await {
+                debug!("[Offramp::{}] Shadow already stopped: {}", self.url(), e);
+            }
+        }
+        self.primary.terminate().await
+    }
+
+    fn default_codec(&self) -> &str {
+        self.primary.default_codec()
+    }
+
+    fn add_pipeline(&mut self, id: TremorUrl, addr: pipeline::Addr) {
+        self.primary.add_pipeline(id, addr);
+    }
+
+    fn remove_pipeline(&mut self, id: TremorUrl) -> bool {
+        self.primary.remove_pipeline(id)
+    }
+
+    fn add_dest_pipeline(&mut self, port: Cow<'static, str>, id: TremorUrl, addr: pipeline::Addr) {
+        self.primary.add_dest_pipeline(port, id, addr);
+    }
+
+    fn remove_dest_pipeline(&mut self, port: Cow<'static, str>, id: TremorUrl) -> bool {
+        self.primary.remove_dest_pipeline(port, id)
+    }
+
+    fn is_active(&self) -> bool {
+        self.primary.is_active()
+    }
+
+    fn auto_ack(&self) -> bool {
+        self.primary.auto_ack()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shadow_url() -> Result<()> {
+        let url = TremorUrl::parse("/offramp/mirror/01")?;
+        let shadow = super::shadow_url(&url);
+        assert_eq!(Some("mirror"), shadow.artefact());
+        assert_eq!(Some("01"), shadow.instance());
+        assert_eq!(Some("shadow"), shadow.instance_port());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn full_shadow_queue() -> Result<()> {
+        let (shadow_tx, shadow_rx) = bounded(1);
+        let mut mirror = Mirror {
+            primary: offramp::lookup("stdout", &None)?,
+            shadow: None,
+            shadow_tx: Some(shadow_tx),
+            config: Config {
+                primary: Target {
+                    kind: "stdout".to_string(),
+                    config: None,
+                },
+                shadow: Target {
+                    kind: "stdout".to_string(),
+                    config: None,
+                },
+                shadow_qsize: 1,
+            },
+            offramp_url: None,
+            dropped: 0,
+        };
+        let mut codec = crate::codec::lookup("json")?;
+        let codec_map = HashMap::new();
+        // the shadow never reads, the primary still gets every event
+        for i in 0..3 {
+            let event = Event {
+                id: (0, 0, i).into(),
+                ..Event::default()
+            };
+            mirror
+                .on_event(codec.as_mut(), &codec_map, "in", event)
+                .await?;
+        }
+        assert_eq!(2, mirror.dropped);
+        assert_eq!(1, shadow_rx.len());
+        Ok(())
+    }
+}
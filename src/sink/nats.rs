@@ -17,6 +17,7 @@
 use std::iter::FromIterator;
 use std::time::Instant;
 
+use crate::correlation;
 use crate::sink::prelude::*;
 use async_channel::{bounded, Receiver};
 use async_nats::Connection as NatsConnection;
@@ -116,6 +117,7 @@ pub struct Nats {
     sink_url: TremorUrl,
     config: Config,
     postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
     reply_channel: Sender<sink::Reply>,
     connection: Option<NatsConnection>,
     error_rx: Receiver<()>,
@@ -133,6 +135,7 @@ impl offramp::Impl for Nats {
                 sink_url: TremorUrl::from_offramp_id("nats")?,
                 config,
                 postprocessors: vec![],
+                metrics: None,
                 reply_channel: dummy_tx,
                 connection: None,
                 error_rx,
@@ -178,11 +181,20 @@ impl Sink for Nats {
         if let Some(connection) = &mut self.connection {
             for (value, meta) in event.value_meta_iter() {
                 let encoded = codec.encode(value)?;
-                let processed =
-                    postprocess(self.postprocessors.as_mut_slice(), ingest_ns, encoded)?;
+                let processed = postprocess(
+                    self.postprocessors.as_mut_slice(),
+                    ingest_ns,
+                    encoded,
+                    self.metrics.as_deref(),
+                )?;
                 let nats_meta = meta.get("nats");
                 let headers = nats_meta.and_then(|v| v.get_object("headers"));
                 let reply = nats_meta.and_then(|v| v.get_str("reply"));
+                // propagate the correlation id, explicit headers take precedence
+                let correlation_id = correlation::meta_id(meta).filter(|_| {
+                    !headers.map_or(false, |h| h.contains_key(correlation::HEADER))
+                        && !self.config.headers.contains_key(correlation::HEADER)
+                });
                 for payload in processed {
                     // prepare message reply
                     let message_reply = reply.or(config_reply);
@@ -190,6 +202,9 @@ impl Sink for Nats {
                     let mut key_val: Vec<(&str, &str)> = Vec::with_capacity(
                         self.config.headers.len() + headers.map(HashMap::len).unwrap_or_default(),
                     );
+                    if let Some(correlation_id) = correlation_id {
+                        key_val.push((correlation::HEADER, correlation_id));
+                    }
                     for (key, val) in &self.config.headers {
                         for ele in val.iter() {
                             key_val.push((key.as_str(), ele.as_str()));
@@ -297,6 +312,7 @@ impl Sink for Nats {
     ) -> Result<()> {
         self.connection = Some(self.config.connection()?);
         self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
         self.reply_channel = reply_channel;
         self.sink_url = sink_url.clone();
         Ok(())
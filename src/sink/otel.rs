@@ -24,6 +24,7 @@
 
 use crate::connectors::otel::{logs, metrics, trace};
 use crate::connectors::qos::{self, QoSFacilities, SinkQoS};
+use crate::connectors::reconnect::{self, Reconnect};
 use crate::sink::prelude::*;
 use halfbrown::HashMap;
 use tonic::transport::Channel as TonicChannel;
@@ -48,6 +49,7 @@ pub struct OpenTelemetry {
     remote: Option<RemoteOpenTelemetryEndpoint>,
     is_down: bool,
     qos_facility: Box<dyn SinkQoS>,
+    reconnect: Reconnect,
 }
 
 #[derive(Deserialize)]
@@ -65,6 +67,9 @@ pub struct Config {
     /// Enables the metrics service
     #[serde(default = "d_true")]
     pub metrics: bool,
+    /// reconnect policy
+    #[serde(default)]
+    pub reconnect: reconnect::Config,
 }
 
 fn d_true() -> bool {
@@ -79,12 +84,14 @@ impl offramp::Impl for OpenTelemetry {
             let config: Config = Config::new(config)?;
             let hostport = format!("{}:{}", config.host.clone(), config.port);
             let endpoint = format!("https://{}:{}", config.host.clone().as_str(), config.port);
+            let reconnect = Reconnect::new(config.reconnect.clone());
             Ok(SinkManager::new_box(Self {
                 config,
                 endpoint,
                 remote: None,
                 is_down: false,
                 qos_facility: Box::new(QoSFacilities::recoverable(hostport)),
+                reconnect,
             }))
         } else {
             Err("Offramp otel requires a config".into())
@@ -133,7 +140,10 @@ impl Sink for OpenTelemetry {
                             let request = json_otel_metrics_to_pb(value)?;
                             if let Err(e) = remote.metrics_client.export(request).await {
                                 error!("Failed to dispatch otel/gRPC metrics message: {}", e);
-                                self.is_down = true;
+                                if !self.is_down {
+                                    self.is_down = true;
+                                    self.reconnect.failed(nanotime(), &e.to_string());
+                                }
                                 if event.transactional {
                                     return Ok(Some(vec![
                                         qos::fail(&mut event.clone()),
@@ -150,7 +160,10 @@ impl Sink for OpenTelemetry {
                             let request = json_otel_logs_to_pb(value)?;
                             if let Err(e) = remote.logs_client.export(request).await {
                                 error!("Failed to dispatch otel/gRPC logs message: {}", e);
-                                self.is_down = true;
+                                if !self.is_down {
+                                    self.is_down = true;
+                                    self.reconnect.failed(nanotime(), &e.to_string());
+                                }
                                 if event.transactional {
                                     return Ok(Some(vec![
                                         qos::fail(&mut event.clone()),
@@ -167,7 +180,10 @@ impl Sink for OpenTelemetry {
                             let request = json_otel_trace_to_pb(value)?;
                             if let Err(e) = remote.trace_client.export(request).await {
                                 error!("Failed to dispatch otel/gRPC logs message: {}", e);
-                                self.is_down = true;
+                                if !self.is_down {
+                                    self.is_down = true;
+                                    self.reconnect.failed(nanotime(), &e.to_string());
+                                }
                                 if event.transactional {
                                     return Ok(Some(vec![
                                         qos::fail(&mut event.clone()),
@@ -208,10 +224,11 @@ impl Sink for OpenTelemetry {
         _sink_url: &TremorUrl,
         _codec: &dyn Codec,
         _codec_map: &HashMap<String, Box<dyn Codec>>,
-        _processors: Processors<'_>,
+        processors: Processors<'_>,
         _is_linked: bool,
         _reply_channel: Sender<sink::Reply>,
     ) -> Result<()> {
+        self.reconnect.report_to(processors.stats);
         let channel = TonicEndpoint::from_shared(self.endpoint.clone())
             .map_err(|e| format!("Unable to connect to remote otel endpoint: {}", e))?
             .connect()
@@ -235,17 +252,21 @@ impl Sink for OpenTelemetry {
         Ok(())
     }
     async fn on_signal(&mut self, signal: Event) -> ResultVec {
-        if self.is_down && self.qos_facility.probe(signal.ingest_ns) {
+        if !self.is_down || !self.reconnect.due(nanotime()) {
+            return Ok(None);
+        }
+        if self.qos_facility.probe(signal.ingest_ns) {
+            self.reconnect.connected();
             self.is_down = false;
             // This means the port is connectable
             info!("CNCF OpenTelemetry -  sink remote endpoint - recovered and contactable");
-            self.is_down = false;
             // Clone needed to make it mutable, lint is wrong
             #[allow(clippy::redundant_clone)]
             let mut signal = signal.clone();
             return Ok(Some(vec![qos::open(&mut signal)]));
         }
-
+        self.reconnect
+            .failed(nanotime(), "remote endpoint not reachable");
         Ok(None)
     }
 
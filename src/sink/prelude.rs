@@ -16,7 +16,7 @@ pub(crate) use crate::codec::Codec;
 pub(crate) use crate::errors::*;
 pub(crate) use crate::offramp::{self, Offramp};
 pub(crate) use crate::postprocessor::{
-    make_postprocessors, postprocess, Postprocessor, Postprocessors,
+    make_postprocessors, postprocess, postprocess_slice, ByteMetrics, Postprocessor, Postprocessors,
 };
 pub(crate) use crate::preprocessor::{make_preprocessors, preprocess, Preprocessor, Preprocessors};
 pub(crate) use crate::sink::{self, Reply, ResultVec, Sink, SinkManager};
@@ -29,6 +29,7 @@ pub(crate) use async_channel::Sender;
 pub(crate) use async_std::prelude::*;
 pub(crate) use async_std::task;
 pub(crate) use beef::Cow;
+pub(crate) use std::sync::Arc;
 pub(crate) use tremor_common::time::nanotime;
 pub(crate) use tremor_pipeline::{CbAction, ConfigImpl};
 pub(crate) use tremor_script::prelude::*;
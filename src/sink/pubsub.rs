@@ -0,0 +1,205 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Google Cloud Pub/Sub Offramp
+//!
+//! Publishes events, encoded with the configured codec, as messages to a
+//! Pub/Sub topic over gRPC. All values of a batched event are published
+//! together. An event is acked once all its messages were published and
+//! failed otherwise.
+//!
+//! `$pubsub.attributes` sets the message attributes, `$pubsub.ordering_key`
+//! the ordering key. Messages with the same ordering key are published in
+//! the order of their events. Once publishing with an ordering key failed,
+//! events with that key are failed for `ordering_pause` seconds, so later
+//! messages don't overtake the failed ones when they are replayed.
+//!
+//! Credentials are looked up like for the `gcs` offramp. A plain text
+//! `endpoint`, like `http://localhost:8085` for the emulator, is used
+//! without credentials.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::gcp::pubsub::{self, Connection, PublishRequest, MAX_PUBLISH_MESSAGES};
+use crate::sink::prelude::*;
+use halfbrown::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// topic to publish to, `projects/{project}/topics/{name}`
+    pub topic: String,
+    /// overrides the `https://pubsub.googleapis.com` endpoint, ordered
+    /// messages should use a regional one
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// seconds events with an ordering key are failed after publishing with
+    /// that key failed
+    #[serde(default = "d_ordering_pause")]
+    pub ordering_pause: u64,
+}
+
+fn d_ordering_pause() -> u64 {
+    30
+}
+
+impl ConfigImpl for Config {}
+
+pub struct PubSub {
+    config: Config,
+    connection: Option<Connection>,
+    /// ordering keys whose publishing failed, by when they resume
+    paused: HashMap<String, Instant>,
+    postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for PubSub {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(SinkManager::new_box(Self {
+                config,
+                connection: None,
+                paused: HashMap::new(),
+                postprocessors: vec![],
+                metrics: None,
+                sink_url: TremorUrl::from_offramp_id("pubsub")?, // dummy value
+            }))
+        } else {
+            Err("Pub/Sub offramp requires a config".into())
+        }
+    }
+}
+
+impl PubSub {
+    async fn publish(&mut self, codec: &dyn Codec, event: &Event) -> Result<()> {
+        let mut messages = Vec::new();
+        for (value, meta) in event.value_meta_iter() {
+            let meta = meta.get("pubsub");
+            let encoded = codec.encode(value)?;
+            for packet in postprocess(
+                &mut self.postprocessors,
+                event.ingest_ns,
+                encoded,
+                self.metrics.as_deref(),
+            )? {
+                messages.push(pubsub::message(packet, meta));
+            }
+        }
+
+        let now = Instant::now();
+        let paused = &self.paused;
+        if let Some(key) = messages.iter().map(|m| &m.ordering_key).find(|key| {
+            paused
+                .get(key.as_str())
+                .map_or(false, |resume| *resume > now)
+        }) {
+            return Err(format!("Publishing with ordering key `{}` is paused", key).into());
+        }
+
+        if self.connection.is_none() {
+            self.connection = Some(Connection::new(self.config.endpoint.as_deref()).await?);
+        }
+        let connection = self.connection.as_ref().ok_or("Client error!")?;
+        while !messages.is_empty() {
+            let rest = messages.split_off(messages.len().min(MAX_PUBLISH_MESSAGES));
+            let batch = std::mem::replace(&mut messages, rest);
+            let ordering_keys: Vec<String> = batch
+                .iter()
+                .chain(messages.iter())
+                .filter(|m| !m.ordering_key.is_empty())
+                .map(|m| m.ordering_key.clone())
+                .collect();
+            let request = connection
+                .request(PublishRequest {
+                    topic: self.config.topic.clone(),
+                    messages: batch,
+                })
+                .await?;
+            if let Err(e) = connection.publisher().publish(request).await {
+                let resume = now + Duration::from_secs(self.config.ordering_pause);
+                for key in ordering_keys {
+                    self.paused.insert(key, resume);
+                }
+                return Err(format!("Publish failed: {}", e).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for PubSub {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        match self.publish(codec, &event).await {
+            Ok(()) if event.transactional => Ok(Some(vec![Reply::Insight(event.insight_ack())])),
+            Ok(()) => Ok(None),
+            Err(e) => {
+                error!("[Sink::{}] Error publishing messages: {}", self.sink_url, e);
+                if event.transactional {
+                    Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {}
+}
@@ -0,0 +1,338 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Redis Offramp
+//!
+//! Writes events, encoded with the configured codec, to redis. The `mode`
+//! selects the command:
+//!
+//! * `xadd` - adds an entry to the stream `key`, with the data in `field`
+//! * `set` - sets `key`, expiring after `ttl` seconds if given
+//! * `lpush` - pushes to the list `key`
+//! * `publish` - publishes to the channel `key`
+//!
+//! `$redis.mode`, `$redis.key` and `$redis.ttl` override the configuration
+//! per event, `$redis.fields` adds fields to stream entries. The commands of
+//! all values of a batched event are sent in one pipeline, the event is
+//! acked once all of them succeeded.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::sink::prelude::*;
+use halfbrown::HashMap;
+use redis::aio::Connection;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// `XADD`
+    Xadd,
+    /// `SET`
+    Set,
+    /// `LPUSH`
+    Lpush,
+    /// `PUBLISH`
+    Publish,
+}
+
+impl Mode {
+    fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "xadd" => Some(Self::Xadd),
+            "set" => Some(Self::Set),
+            "lpush" => Some(Self::Lpush),
+            "publish" => Some(Self::Publish),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// redis url, e.g. `redis://localhost:6379`
+    pub url: String,
+    /// command to write events with
+    #[serde(default = "d_mode")]
+    pub mode: Mode,
+    /// stream, key, list or channel to write to
+    #[serde(default)]
+    pub key: Option<String>,
+    /// field of stream entries holding the event data
+    #[serde(default = "d_field")]
+    pub field: String,
+    /// approximate maximum length streams are trimmed to
+    #[serde(default)]
+    pub maxlen: Option<u64>,
+    /// seconds after which set keys expire
+    #[serde(default)]
+    pub ttl: Option<u64>,
+}
+
+fn d_mode() -> Mode {
+    Mode::Xadd
+}
+
+fn d_field() -> String {
+    "data".to_string()
+}
+
+impl ConfigImpl for Config {}
+
+/// The command writing an encoded value with the `$redis` metadata
+fn command(config: &Config, meta: Option<&Value>, data: &[u8]) -> Result<redis::Cmd> {
+    let mode = match meta.get_str("mode") {
+        Some(mode) => {
+            Mode::parse(mode).ok_or_else(|| Error::from(format!("Unknown redis mode {}", mode)))?
+        }
+        None => config.mode,
+    };
+    let key = meta
+        .get_str("key")
+        .or_else(|| config.key.as_deref())
+        .ok_or("No redis key given")?;
+    let cmd = match mode {
+        Mode::Xadd => {
+            let mut cmd = redis::cmd("XADD");
+            cmd.arg(key);
+            if let Some(maxlen) = config.maxlen {
+                cmd.arg("MAXLEN").arg("~").arg(maxlen);
+            }
+            cmd.arg("*").arg(&config.field).arg(data);
+            if let Some(fields) = meta.get_object("fields") {
+                for (k, v) in fields.iter() {
+                    let v = v.as_str().map_or_else(|| v.encode(), ToString::to_string);
+                    cmd.arg(k.as_ref()).arg(v);
+                }
+            }
+            cmd
+        }
+        Mode::Set => {
+            let mut cmd = redis::cmd("SET");
+            cmd.arg(key).arg(data);
+            if let Some(ttl) = meta.get_u64("ttl").or(config.ttl) {
+                cmd.arg("EX").arg(ttl);
+            }
+            cmd
+        }
+        Mode::Lpush => {
+            let mut cmd = redis::cmd("LPUSH");
+            cmd.arg(key).arg(data);
+            cmd
+        }
+        Mode::Publish => {
+            let mut cmd = redis::cmd("PUBLISH");
+            cmd.arg(key).arg(data);
+            cmd
+        }
+    };
+    Ok(cmd)
+}
+
+pub struct Redis {
+    config: Config,
+    connection: Option<Connection>,
+    postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for Redis {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(SinkManager::new_box(Self {
+                config,
+                connection: None,
+                postprocessors: vec![],
+                metrics: None,
+                sink_url: TremorUrl::from_offramp_id("redis")?, // dummy value
+            }))
+        } else {
+            Err("Redis offramp requires a config".into())
+        }
+    }
+}
+
+impl Redis {
+    async fn write(&mut self, codec: &dyn Codec, event: &Event) -> Result<()> {
+        let mut pipe = redis::pipe();
+        for (value, meta) in event.value_meta_iter() {
+            let meta = meta.get("redis");
+            let encoded = codec.encode(value)?;
+            for packet in postprocess(
+                &mut self.postprocessors,
+                event.ingest_ns,
+                encoded,
+                self.metrics.as_deref(),
+            )? {
+                pipe.add_command(command(&self.config, meta, &packet)?);
+            }
+        }
+        if self.connection.is_none() {
+            let client = redis::Client::open(self.config.url.as_str())?;
+            self.connection = Some(client.get_async_std_connection().await?);
+        }
+        let connection = self.connection.as_mut().ok_or("Client error!")?;
+        pipe.query_async::<_, ()>(connection).await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Redis {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        match self.write(codec, &event).await {
+            Ok(()) if event.transactional => Ok(Some(vec![Reply::Insight(event.insight_ack())])),
+            Ok(()) => Ok(None),
+            Err(e) => {
+                error!("[Sink::{}] Error writing to redis: {}", self.sink_url, e);
+                if let Error(ErrorKind::RedisError(e), _) = &e {
+                    if e.is_io_error() || e.is_connection_dropped() {
+                        self.connection = None;
+                    }
+                }
+                if event.transactional {
+                    Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(mode: Mode) -> Config {
+        Config {
+            url: "redis://localhost".to_string(),
+            mode,
+            key: Some("snot".to_string()),
+            field: d_field(),
+            maxlen: Some(1000),
+            ttl: None,
+        }
+    }
+
+    fn packed(cmd: &redis::Cmd) -> Vec<u8> {
+        cmd.get_packed_command()
+    }
+
+    #[test]
+    fn commands() -> Result<()> {
+        let xadd = command(&config(Mode::Xadd), None, b"{}")?;
+        assert_eq!(
+            packed(
+                redis::cmd("XADD")
+                    .arg("snot")
+                    .arg("MAXLEN")
+                    .arg("~")
+                    .arg(1000)
+                    .arg("*")
+                    .arg("data")
+                    .arg(&b"{}"[..])
+            ),
+            packed(&xadd)
+        );
+        let meta = literal!({"mode": "set", "key": "badger", "ttl": 10});
+        let set = command(&config(Mode::Xadd), Some(&meta), b"1")?;
+        assert_eq!(
+            packed(
+                redis::cmd("SET")
+                    .arg("badger")
+                    .arg(&b"1"[..])
+                    .arg("EX")
+                    .arg(10)
+            ),
+            packed(&set)
+        );
+        let publish = command(&config(Mode::Publish), None, b"1")?;
+        assert_eq!(
+            packed(redis::cmd("PUBLISH").arg("snot").arg(&b"1"[..])),
+            packed(&publish)
+        );
+        let meta = literal!({"mode": "snot"});
+        assert!(command(&config(Mode::Lpush), Some(&meta), b"1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stream_fields() -> Result<()> {
+        let meta = literal!({"fields": {"source": "tremor"}});
+        let xadd = command(&config(Mode::Xadd), Some(&meta), b"1")?;
+        assert_eq!(
+            packed(
+                redis::cmd("XADD")
+                    .arg("snot")
+                    .arg("MAXLEN")
+                    .arg("~")
+                    .arg(1000)
+                    .arg("*")
+                    .arg("data")
+                    .arg(&b"1"[..])
+                    .arg("source")
+                    .arg("tremor")
+            ),
+            packed(&xadd)
+        );
+        Ok(())
+    }
+}
@@ -15,6 +15,7 @@
 #![cfg(not(tarpaulin_include))]
 
 use crate::codec::Codec;
+use crate::correlation;
 use crate::errors::ErrorKind;
 use crate::sink::prelude::*;
 use async_channel::{bounded, Receiver, Sender};
@@ -243,6 +244,10 @@ pub struct Config {
 
     #[serde(default)]
     pub headers: HashMap<String, String>,
+
+    /// declarative mapping of events to requests and responses to events
+    #[serde(default)]
+    pub mapping: Option<Mapping>,
 }
 
 fn dflt_concurrency() -> usize {
@@ -255,6 +260,215 @@ fn dflt_method() -> SerdeMethod {
 
 impl ConfigImpl for Config {}
 
+/// Maps events to requests, applied on top of the endpoint and codec.
+///
+/// Templates refer to fields of the event data with `{path.to.field}` and to
+/// its metadata with `{$path.to.field}`.
+///
+/// ```yaml
+/// mapping:
+///   path: /users/{user.id}/events
+///   query:
+///     source: "{$kafka.topic}"
+///   body:
+///     fields: [event, time]
+///   response:
+///     event_id: result.id
+/// ```
+#[derive(Clone, Debug, Deserialize, Default)]
+pub struct Mapping {
+    /// url path template, placeholders are percent encoded
+    #[serde(default)]
+    pub path: Option<String>,
+    /// query parameter templates, parameters with missing fields are left out
+    #[serde(default)]
+    pub query: HashMap<String, String>,
+    /// what to send as the request body instead of the event data
+    #[serde(default)]
+    pub body: Option<BodyMapping>,
+    /// dot separated paths into the decoded response body, extracted into
+    /// `$response.extracted` under the given names
+    #[serde(default)]
+    pub response: HashMap<String, String>,
+}
+
+/// Request body mapping
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyMapping {
+    /// the value at the given path
+    Field(String),
+    /// an object with the values at the given paths, keyed by the last
+    /// path segment
+    Fields(Vec<String>),
+    /// a template, strings consisting of a single placeholder are replaced by
+    /// the referenced value, other strings are rendered
+    Template(OpConfig),
+}
+
+fn get_path<'value, 'event>(
+    mut value: &'value Value<'event>,
+    path: &str,
+) -> Option<&'value Value<'event>> {
+    for segment in path.split('.') {
+        value = value.get(segment)?;
+    }
+    Some(value)
+}
+
+/// resolves a placeholder, `$` prefixed paths refer to the metadata
+fn lookup<'value, 'event>(
+    data: &'value Value<'event>,
+    meta: &'value Value<'event>,
+    path: &str,
+) -> Result<&'value Value<'event>> {
+    let res = if let Some(path) = path.strip_prefix('$') {
+        get_path(meta, path)
+    } else {
+        get_path(data, path)
+    };
+    res.ok_or_else(|| format!("Missing field `{}` for the request mapping", path).into())
+}
+
+fn as_text(value: &Value) -> String {
+    value
+        .as_str()
+        .map_or_else(|| value.encode(), ToString::to_string)
+}
+
+/// percent encodes everything but unreserved characters
+pub(crate) fn encode_segment(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(char::from(b));
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// renders a template, applying `escape` to each substituted value
+pub(crate) fn render(
+    template: &str,
+    data: &Value,
+    meta: &Value,
+    escape: fn(&str) -> String,
+) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| Error::from(format!("Unterminated placeholder in `{}`", template)))?;
+        let value = lookup(data, meta, &rest[start + 1..start + end])?;
+        out.push_str(&escape(&as_text(value)));
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+pub(crate) fn fill(template: &OpConfig, data: &Value, meta: &Value) -> Result<Value<'static>> {
+    Ok(match template {
+        OpConfig::Null => Value::null(),
+        OpConfig::Bool(b) => Value::from(*b),
+        OpConfig::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Value::from(i)
+            } else if let Some(u) = n.as_u64() {
+                Value::from(u)
+            } else {
+                Value::from(n.as_f64().unwrap_or_default())
+            }
+        }
+        OpConfig::String(s) => {
+            let placeholder = s
+                .strip_prefix('{')
+                .and_then(|s| s.strip_suffix('}'))
+                .filter(|p| !p.contains(&['{', '}'][..]));
+            if let Some(path) = placeholder {
+                lookup(data, meta, path)?.clone_static()
+            } else {
+                Value::from(render(s, data, meta, str::to_string)?)
+            }
+        }
+        OpConfig::Sequence(a) => a
+            .iter()
+            .map(|v| fill(v, data, meta))
+            .collect::<Result<Vec<_>>>()?
+            .into(),
+        OpConfig::Mapping(m) => {
+            let mut obj = Value::object_with_capacity(m.len());
+            for (k, v) in m {
+                let k = k
+                    .as_str()
+                    .ok_or_else(|| Error::from("Request mapping template keys must be strings"))?;
+                obj.insert(k.to_string(), fill(v, data, meta)?)?;
+            }
+            obj
+        }
+    })
+}
+
+impl Mapping {
+    fn apply_url(&self, url: &mut url::Url, data: &Value, meta: &Value) -> Result<()> {
+        if let Some(path) = &self.path {
+            url.set_path(&render(path, data, meta, encode_segment)?);
+        }
+        if !self.query.is_empty() {
+            let params: Vec<_> = self
+                .query
+                .iter()
+                .filter_map(|(k, v)| Some((k, render(v, data, meta, str::to_string).ok()?)))
+                .collect();
+            let mut pairs = url.query_pairs_mut();
+            for (k, v) in params {
+                pairs.append_pair(k, &v);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_body(&self, data: &Value, meta: &Value) -> Result<Option<Value<'static>>> {
+        Ok(match &self.body {
+            None => None,
+            Some(BodyMapping::Field(path)) => Some(lookup(data, meta, path)?.clone_static()),
+            Some(BodyMapping::Fields(paths)) => {
+                let mut obj = Value::object_with_capacity(paths.len());
+                for path in paths {
+                    let key = path
+                        .rsplit('.')
+                        .next()
+                        .unwrap_or(path)
+                        .trim_start_matches('$');
+                    obj.insert(key.to_string(), lookup(data, meta, path)?.clone_static())?;
+                }
+                Some(obj)
+            }
+            Some(BodyMapping::Template(template)) => Some(fill(template, data, meta)?),
+        })
+    }
+
+    fn extract(&self, body: &Value, meta: &mut Value<'static>) -> Result<()> {
+        if self.response.is_empty() {
+            return Ok(());
+        }
+        let mut extracted = Value::object_with_capacity(self.response.len());
+        for (name, path) in &self.response {
+            if let Some(v) = get_path(body, path) {
+                extracted.insert(name.clone(), v.clone_static())?;
+            }
+        }
+        if let Some(response) = meta.get_mut("response") {
+            response.insert("extracted", extracted)?;
+        }
+        Ok(())
+    }
+}
+
 #[allow(clippy::clippy::large_enum_variant)]
 enum CodecTaskInMsg {
     ToRequest(Event, Sender<SendTaskInMsg>),
@@ -446,6 +660,7 @@ impl Sink for Rest {
     ) -> Result<()> {
         // clone the hell out of all the shit
         let postprocessors = make_postprocessors(processors.post)?;
+        let metrics = processors.metrics;
         let preprocessors = make_preprocessors(processors.pre)?;
         let my_codec = codec.boxed_clone();
         let my_codec_map = codec_map
@@ -455,6 +670,7 @@ impl Sink for Rest {
         let default_method = self.config.method.0;
         let endpoint = self.config.endpoint.clone();
         let config_headers = self.config.headers.clone();
+        let mapping = self.config.mapping.clone();
         let cloned_sink_url = sink_url.clone();
         self.sink_url = sink_url.clone();
 
@@ -470,12 +686,14 @@ impl Sink for Rest {
                 sink_uid,
                 cloned_sink_url,
                 postprocessors,
+                metrics,
                 preprocessors,
                 my_codec,
                 my_codec_map,
                 endpoint,
                 default_method,
                 config_headers,
+                mapping,
                 reply_tx,
                 in_rx,
                 is_linked,
@@ -506,12 +724,14 @@ async fn codec_task(
     sink_uid: u64,
     sink_url: TremorUrl,
     mut postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
     mut preprocessors: Preprocessors,
     mut codec: Box<dyn Codec>,
     mut codec_map: HashMap<String, Box<dyn Codec>>,
     endpoint: Endpoint,
     default_method: Method,
     default_headers: HashMap<String, String>,
+    mapping: Option<Mapping>,
     reply_tx: Sender<sink::Reply>,
     in_rx: Receiver<CodecTaskInMsg>,
     is_linked: bool,
@@ -534,9 +754,11 @@ async fn codec_task(
                     codec,
                     &codec_map,
                     postprocessors.as_mut_slice(),
+                    metrics.as_deref(),
                     default_method,
                     &default_headers,
                     &endpoint,
+                    mapping.as_ref(),
                 ) {
                     Ok(request) => {
                         if let Err(e) = tx.send(SendTaskInMsg::Request(request)).await {
@@ -630,6 +852,7 @@ async fn codec_task(
                         codec,
                         &mut codec_map,
                         preprocessors.as_mut_slice(),
+                        mapping.as_ref(),
                     )
                     .await
                     {
@@ -734,15 +957,17 @@ async fn codec_task(
     Ok(())
 }
 
-#[allow(clippy::too_many_lines)]
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
 fn build_request(
     event: &Event,
     codec: &dyn Codec,
     codec_map: &HashMap<String, Box<dyn Codec>>,
     postprocessors: &mut [Box<dyn Postprocessor>],
+    metrics: Option<&ByteMetrics>,
     default_method: Method,
     default_headers: &HashMap<String, String>,
     config_endpoint: &Endpoint,
+    mapping: Option<&Mapping>,
 ) -> Result<surf::Request> {
     let mut body: Vec<u8> = vec![];
     let mut method = None;
@@ -835,13 +1060,32 @@ fn build_request(
 
         // apply the given codec, fall back to the configured codec if none is found
         let codec = codec_in_use.unwrap_or(codec);
-        let encoded = codec.encode(data)?;
-        let mut processed = postprocess(postprocessors, event.ingest_ns, encoded)?;
-        for processed_elem in &mut processed {
-            body.append(processed_elem);
+        let mapped = mapping
+            .map(|m| m.apply_body(data, meta))
+            .transpose()?
+            .flatten();
+        let data = mapped.as_ref().unwrap_or(data);
+        if postprocessors.is_empty() {
+            // encode straight into the body
+            let len = body.len();
+            codec.encode_into(data, &mut body)?;
+            if let Some(metrics) = metrics {
+                let encoded = body.len() - len;
+                metrics.count(encoded, encoded);
+            }
+        } else {
+            let encoded = codec.encode(data)?;
+            let mut processed = postprocess(postprocessors, event.ingest_ns, encoded, metrics)?;
+            for processed_elem in &mut processed {
+                body.append(processed_elem);
+            }
         }
     }
-    let endpoint = endpoint.map_or_else(|| config_endpoint.as_url(), |ep| ep.as_url())?;
+    let mut endpoint = endpoint.map_or_else(|| config_endpoint.as_url(), |ep| ep.as_url())?;
+    // path and query are mapped from the first event
+    if let (Some(mapping), Some((data, meta))) = (mapping, event.value_meta_iter().next()) {
+        mapping.apply_url(&mut endpoint, data, meta)?;
+    }
     trace!("endpoint [{}] chosen", &endpoint);
     let host = match (endpoint.host(), endpoint.port()) {
         (Some(host), Some(port)) => Some(format!("{}:{}", host, port)),
@@ -856,6 +1100,11 @@ fn build_request(
         request_builder = request_builder.header(k.as_str(), v.as_str());
     }
 
+    // propagate the correlation id, explicit headers from meta take precedence
+    if let Some(correlation_id) = correlation::event_id(event) {
+        request_builder = request_builder.header(correlation::HEADER, correlation_id.as_str());
+    }
+
     // build headers from meta - effectively overwrite config headers in case of conflict
     for (k, v) in headers {
         if "content-type".eq_ignore_ascii_case(k) {
@@ -928,6 +1177,7 @@ async fn build_response_events<'response>(
     codec: &'response mut dyn Codec,
     codec_map: &'response mut HashMap<String, Box<dyn Codec>>,
     preprocessors: &'response mut [Box<dyn Preprocessor>],
+    mapping: Option<&'response Mapping>,
 ) -> Result<Vec<Event>> {
     let mut meta = Value::object_with_capacity(2);
     if let Some(correlation) = correlation {
@@ -974,8 +1224,12 @@ async fn build_response_events<'response>(
                 let body = the_chosen_one
                     .decode(mut_data, nanotime())?
                     .unwrap_or_else(Value::object);
+                let mut meta = meta.clone(); // TODO: no need to clone the last element?
+                if let Some(mapping) = mapping {
+                    mapping.extract(&body, &mut meta)?;
+                }
 
-                Ok(ValueAndMeta::from_parts(body, meta.clone())) // TODO: no need to clone the last element?
+                Ok(ValueAndMeta::from_parts(body, meta))
             })
             .map_err(|e: rental::RentalError<Error, _>| e.0)
             .map(|data| {
@@ -1249,9 +1503,11 @@ mod test {
             codec.as_ref(),
             &codec_map,
             pp.as_mut_slice(),
+            None,
             Method::Get,
             &default_headers,
             &endpoint,
+            None,
         )?;
         let expected = HeaderValues::from(HeaderValue::from_str("indeed!")?);
         assert_eq!(
@@ -1268,6 +1524,64 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn mapping() -> Result<()> {
+        let config_s = r#"
+            endpoint: http://localhost:8080/
+            mapping:
+              path: /users/{user.id}/events
+              query:
+                topic: "{$kafka.topic}"
+                missing: "{snot}"
+              body:
+                template:
+                  user: "{user}"
+                  message: "{user.name} logged in"
+                  tags: [login]
+              response:
+                event_id: result.id
+        "#;
+        let v: serde_yaml::Value = serde_yaml::from_str(config_s)?;
+        let config = Config::new(&v)?;
+        let mapping = config.mapping.expect("mapping");
+        let data = literal!({"user": {"id": "a/b c", "name": "badger"}});
+        let meta = literal!({"kafka": {"topic": "logins"}});
+
+        let mut url = config.endpoint.as_url()?;
+        mapping.apply_url(&mut url, &data, &meta)?;
+        assert_eq!("/users/a%2Fb%20c/events", url.path());
+        assert_eq!(Some("topic=logins"), url.query());
+        assert!(mapping.apply_url(&mut url, &literal!({}), &meta).is_err());
+
+        assert_eq!(
+            Some(literal!({
+                "user": {"id": "a/b c", "name": "badger"},
+                "message": "badger logged in",
+                "tags": ["login"]
+            })),
+            mapping.apply_body(&data, &meta)?
+        );
+        let fields = Mapping {
+            body: Some(BodyMapping::Fields(vec![
+                "user.name".to_string(),
+                "$kafka.topic".to_string(),
+            ])),
+            ..Mapping::default()
+        };
+        assert_eq!(
+            Some(literal!({"name": "badger", "topic": "logins"})),
+            fields.apply_body(&data, &meta)?
+        );
+
+        let mut response_meta = literal!({"response": {"status": 200}});
+        mapping.extract(&literal!({"result": {"id": 42}}), &mut response_meta)?;
+        assert_eq!(
+            literal!({"response": {"status": 200, "extracted": {"event_id": 42}}}),
+            response_meta
+        );
+        Ok(())
+    }
+
     // we can't use async_std::tst here as it causes lifetime issues with codec
     #[async_std::test]
     async fn build_response() -> Result<()> {
@@ -1305,6 +1619,7 @@ mod test {
             codec.as_mut(),
             &mut codec_map,
             pp.as_mut_slice(),
+            None,
         )
         .await?;
         assert_eq!(1, res.len());
@@ -0,0 +1,184 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # SNS Offramp
+//!
+//! Publishes events, encoded with the configured codec, as messages to an
+//! AWS SNS topic. An event is acked once all its messages were published and
+//! failed otherwise. `$sns.subject` overrides the configured subject, for
+//! FIFO topics `$sns.group_id` and `$sns.deduplication_id` set the message
+//! group and deduplication id.
+//!
+//! Credentials are read from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+//! and `AWS_SESSION_TOKEN` environment variables or the shared credentials
+//! file.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::aws::auth::{self, AwsClient};
+use crate::connectors::aws::sns::{self, MAX_MESSAGE_BYTES};
+use crate::sink::prelude::*;
+use halfbrown::HashMap;
+
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// ARN of the topic
+    pub topic_arn: String,
+    /// subject of the messages, used by email subscriptions
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// AWS region, defaults to `AWS_REGION`
+    #[serde(default)]
+    pub region: Option<String>,
+    /// overrides the `sns.{region}.amazonaws.com` endpoint
+    #[serde(default)]
+    pub endpoint: Option<String>,
+}
+
+impl ConfigImpl for Config {}
+
+pub struct Sns {
+    config: Config,
+    region: String,
+    client: Option<AwsClient>,
+    postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    sink_url: TremorUrl,
+}
+
+impl offramp::Impl for Sns {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            let region = auth::region(config.region.as_deref())?;
+            Ok(SinkManager::new_box(Self {
+                config,
+                region,
+                client: None,
+                postprocessors: vec![],
+                metrics: None,
+                sink_url: TremorUrl::from_offramp_id("sns")?, // dummy value
+            }))
+        } else {
+            Err("SNS offramp requires a config".into())
+        }
+    }
+}
+
+impl Sns {
+    async fn publish(&mut self, codec: &dyn Codec, event: &Event) -> Result<()> {
+        if self.client.is_none() {
+            self.client = Some(AwsClient::new(
+                "sns",
+                &self.region,
+                self.config.endpoint.as_deref(),
+            )?);
+        }
+        let client = self.client.as_ref().ok_or("Client error!")?;
+        for (value, meta) in event.value_meta_iter() {
+            let sns_meta = meta.get("sns");
+            let subject = sns_meta
+                .and_then(|m| m.get_str("subject"))
+                .or_else(|| self.config.subject.as_deref());
+            let group_id = sns_meta.and_then(|m| m.get_str("group_id"));
+            let deduplication_id = sns_meta.and_then(|m| m.get_str("deduplication_id"));
+            let encoded = codec.encode(value)?;
+            for packet in postprocess(
+                &mut self.postprocessors,
+                event.ingest_ns,
+                encoded,
+                self.metrics.as_deref(),
+            )? {
+                let message = String::from_utf8(packet)
+                    .map_err(|_| Error::from("SNS messages have to be UTF-8"))?;
+                if message.len() > MAX_MESSAGE_BYTES {
+                    return Err("Message exceeds the size limit of SNS".into());
+                }
+                sns::publish(
+                    client,
+                    &self.config.topic_arn,
+                    &message,
+                    subject,
+                    group_id,
+                    deduplication_id,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Sns {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        match self.publish(codec, &event).await {
+            Ok(()) if event.transactional => Ok(Some(vec![Reply::Insight(event.insight_ack())])),
+            Ok(()) => Ok(None),
+            Err(e) => {
+                error!("[Sink::{}] Error publishing message: {}", self.sink_url, e);
+                if event.transactional {
+                    Ok(Some(vec![Reply::Insight(event.insight_fail())]))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
+        self.sink_url = sink_url.clone();
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        true
+    }
+
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+
+    async fn terminate(&mut self) {}
+}
@@ -0,0 +1,379 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Server-Sent Events Offramp
+//!
+//! Serves a `text/event-stream` at `path` and sends every event, encoded
+//! with the configured codec, to all connected clients, e.g. browsers using
+//! `EventSource`. `$sse.event` sets the event name and `$sse.id` the event
+//! id, which otherwise counts up from 1.
+//!
+//! The last `replay` events are kept, clients reconnecting with a
+//! `Last-Event-ID` get the events they missed since. Clients that don't keep
+//! up and have more than `client_buffer` events pending are disconnected, the
+//! browser reconnects and resumes. A comment is sent every `keep_alive`
+//! milliseconds, so proxies don't close idle streams.
+//!
+//! ```yaml
+//! config:
+//!   host: 0.0.0.0
+//!   port: 8080
+//!   path: /events
+//!   replay: 100
+//!   allow_origin: "*"
+//! ```
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::sink::prelude::*;
+use async_channel::{bounded, Receiver, TrySendError};
+use async_std::sync::Mutex;
+use async_std::task::JoinHandle;
+use halfbrown::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tide::http::mime;
+use tide::{Body, Request, Response};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// host to listen on
+    #[serde(default = "d_host")]
+    pub host: String,
+    /// port to listen on
+    pub port: u16,
+    /// path the event stream is served at
+    #[serde(default = "d_path")]
+    pub path: String,
+    /// number of events kept for clients resuming with `Last-Event-ID`
+    #[serde(default = "d_replay")]
+    pub replay: usize,
+    /// events pending for a client before it is disconnected
+    #[serde(default = "d_client_buffer")]
+    pub client_buffer: usize,
+    /// milliseconds between keep alive comments, 0 disables them
+    #[serde(default = "d_keep_alive")]
+    pub keep_alive: u64,
+    /// `Access-Control-Allow-Origin` of the stream, for browsers on other origins
+    #[serde(default = "Default::default")]
+    pub allow_origin: Option<String>,
+}
+
+fn d_host() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn d_path() -> String {
+    "/".to_string()
+}
+
+fn d_replay() -> usize {
+    100
+}
+
+fn d_client_buffer() -> usize {
+    128
+}
+
+fn d_keep_alive() -> u64 {
+    15_000
+}
+
+impl ConfigImpl for Config {}
+
+/// formats a message of the event stream
+fn frame(event: Option<&str>, id: &str, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + id.len() + 16);
+    if let Some(event) = event {
+        out.extend_from_slice(b"event: ");
+        out.extend(event.bytes().filter(|b| *b != b'\r' && *b != b'\n'));
+        out.push(b'\n');
+    }
+    out.extend_from_slice(b"id: ");
+    out.extend(id.bytes().filter(|b| *b != b'\r' && *b != b'\n' && *b != 0));
+    out.push(b'\n');
+    for line in data.split(|b| *b == b'\n') {
+        out.extend_from_slice(b"data: ");
+        out.extend_from_slice(line.strip_suffix(b"\r").unwrap_or(line));
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+    out
+}
+
+/// The connected clients and the events kept for resuming ones
+#[derive(Debug, Default)]
+struct Hub {
+    next_id: u64,
+    replay: usize,
+    client_buffer: usize,
+    /// the last `replay` messages with their ids
+    backlog: VecDeque<(String, Vec<u8>)>,
+    clients: Vec<Sender<Vec<u8>>>,
+}
+
+impl Hub {
+    fn new(config: &Config) -> Self {
+        Self {
+            next_id: 1,
+            replay: config.replay,
+            client_buffer: config.client_buffer.max(1),
+            backlog: VecDeque::with_capacity(config.replay),
+            clients: Vec::new(),
+        }
+    }
+
+    /// the id of the next event without an `$sse.id`
+    fn next_id(&mut self) -> String {
+        let id = self.next_id.to_string();
+        self.next_id += 1;
+        id
+    }
+
+    /// sends data to all clients, dropping the ones that don't keep up
+    fn broadcast(&mut self, data: &[u8]) {
+        self.clients
+            .retain(|client| match client.try_send(data.to_vec()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    debug!("[Sink::SSE] Disconnecting a client that doesn't keep up");
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+    }
+
+    /// sends a message to all clients and keeps it for resuming ones
+    fn publish(&mut self, id: String, message: Vec<u8>) {
+        self.broadcast(&message);
+        if self.replay > 0 {
+            if self.backlog.len() == self.replay {
+                self.backlog.pop_front();
+            }
+            self.backlog.push_back((id, message));
+        }
+    }
+
+    /// connects a client, queueing the messages after `last_event_id`
+    fn subscribe(&mut self, last_event_id: Option<&str>) -> Receiver<Vec<u8>> {
+        let missed: Vec<&Vec<u8>> = last_event_id
+            .and_then(|last| self.backlog.iter().position(|(id, _)| id == last))
+            .map(|i| self.backlog.iter().skip(i + 1).map(|(_, m)| m).collect())
+            .unwrap_or_default();
+        let (tx, rx) = bounded(self.client_buffer.max(missed.len()));
+        for message in missed {
+            // the channel has room for all of them
+            if let Err(e) = tx.try_send(message.clone()) {
+                debug!("[Sink::SSE] Failed to replay an event to a client: {}", e);
+            }
+        }
+        self.clients.push(tx);
+        rx
+    }
+}
+
+#[derive(Clone)]
+struct ServerState {
+    hub: Arc<Mutex<Hub>>,
+    allow_origin: Option<String>,
+}
+
+async fn handle_request(req: Request<ServerState>) -> tide::Result<Response> {
+    let ServerState { hub, allow_origin } = req.state().clone();
+    let last_event_id = req
+        .header("Last-Event-ID")
+        .map(|h| h.last().as_str().to_string());
+    let rx = hub.lock().await.subscribe(last_event_id.as_deref());
+    let stream = futures::StreamExt::map(rx, Ok::<_, std::io::Error>);
+    let mut body = Body::from_reader(futures::TryStreamExt::into_async_read(stream), None);
+    body.set_mime(mime::SSE);
+    let mut res = Response::builder(200)
+        .header("Cache-Control", "no-cache")
+        .header("Server", "Tremor")
+        .body(body)
+        .build();
+    if let Some(origin) = &allow_origin {
+        res.insert_header("Access-Control-Allow-Origin", origin.as_str());
+    }
+    Ok(res)
+}
+
+pub struct Sse {
+    config: Config,
+    hub: Arc<Mutex<Hub>>,
+    postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl offramp::Impl for Sse {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            let hub = Arc::new(Mutex::new(Hub::new(&config)));
+            Ok(SinkManager::new_box(Self {
+                config,
+                hub,
+                postprocessors: vec![],
+                metrics: None,
+                tasks: vec![],
+            }))
+        } else {
+            Err("Missing config for sse offramp".into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for Sse {
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        event: Event,
+    ) -> ResultVec {
+        let ingest_ns = event.ingest_ns;
+        let mut hub = self.hub.lock().await;
+        for (value, meta) in event.value_meta_iter() {
+            let raw = codec.encode(value)?;
+            let sse = meta.get("sse");
+            let name = sse.get_str("event");
+            for processed in postprocess(
+                &mut self.postprocessors,
+                ingest_ns,
+                raw,
+                self.metrics.as_deref(),
+            )? {
+                let id = sse
+                    .get_str("id")
+                    .map_or_else(|| hub.next_id(), ToString::to_string);
+                let message = frame(name, &id, &processed);
+                hub.publish(id, message);
+            }
+        }
+        Ok(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
+        if !self.tasks.is_empty() {
+            return Ok(());
+        }
+        let mut server = tide::Server::with_state(ServerState {
+            hub: self.hub.clone(),
+            allow_origin: self.config.allow_origin.clone(),
+        });
+        server.at(&self.config.path).get(handle_request);
+
+        let addr = format!("{}:{}", self.config.host, self.config.port);
+        let sink_url = sink_url.to_string();
+        self.tasks.push(task::spawn(async move {
+            info!("[Sink::{}] Serving events at {}", sink_url, addr);
+            if let Err(e) = server.listen(addr).await {
+                error!("[Sink::{}] Error while serving events: {}", sink_url, e);
+            }
+        }));
+
+        if self.config.keep_alive > 0 {
+            let hub = self.hub.clone();
+            let interval = Duration::from_millis(self.config.keep_alive);
+            self.tasks.push(task::spawn(async move {
+                loop {
+                    task::sleep(interval).await;
+                    hub.lock().await.broadcast(b":\n\n");
+                }
+            }));
+        }
+        Ok(())
+    }
+
+    async fn terminate(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.cancel().await;
+        }
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+    async fn on_signal(&mut self, _signal: Event) -> ResultVec {
+        Ok(None)
+    }
+    fn is_active(&self) -> bool {
+        true
+    }
+    fn auto_ack(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        assert_eq!(
+            b"event: update\nid: 7\ndata: {\"a\":1}\n\n".to_vec(),
+            frame(Some("update"), "7", b"{\"a\":1}")
+        );
+        assert_eq!(
+            b"id: 8\ndata: snot\ndata: badger\n\n".to_vec(),
+            frame(None, "8\n", b"snot\r\nbadger")
+        );
+    }
+
+    #[test]
+    fn resume() {
+        let mut hub = Hub {
+            next_id: 1,
+            replay: 2,
+            client_buffer: 1,
+            ..Hub::default()
+        };
+        let live = hub.subscribe(None);
+        for data in &[b"1", b"2", b"3"] {
+            let id = hub.next_id();
+            hub.publish(id, data.to_vec());
+        }
+        // the live client fell behind and was dropped after the first
+        assert_eq!(Ok(b"1".to_vec()), live.try_recv());
+        assert!(live.try_recv().is_err());
+        assert!(hub.clients.is_empty());
+
+        let resumed = hub.subscribe(Some("2"));
+        assert_eq!(Ok(b"3".to_vec()), resumed.try_recv());
+        assert!(resumed.try_recv().is_err());
+        // ids that aren't kept anymore resume at the live stream
+        let unknown = hub.subscribe(Some("1"));
+        assert!(unknown.try_recv().is_err());
+    }
+}
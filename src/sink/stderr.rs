@@ -29,6 +29,7 @@ use halfbrown::HashMap;
 
 pub struct StdErr {
     postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
     stderr: io::Stderr,
     config: Config,
 }
@@ -49,12 +50,14 @@ impl offramp::Impl for StdErr {
         if let Some(config) = config {
             Ok(SinkManager::new_box(Self {
                 postprocessors: vec![],
+                metrics: None,
                 stderr: io::stderr(),
                 config: Config::new(config)?,
             }))
         } else {
             Ok(SinkManager::new_box(Self {
                 postprocessors: vec![],
+                metrics: None,
                 stderr: io::stderr(),
                 config: Config::default(),
             }))
@@ -73,7 +76,12 @@ impl Sink for StdErr {
         let ingest_ns = event.ingest_ns;
         for value in event.value_iter() {
             let raw = codec.encode(value)?;
-            for processed in postprocess(&mut self.postprocessors, ingest_ns, raw)? {
+            for processed in postprocess(
+                &mut self.postprocessors,
+                ingest_ns,
+                raw,
+                self.metrics.as_deref(),
+            )? {
                 self.stderr.write_all(self.config.prefix.as_bytes()).await?;
                 if self.config.raw {
                     self.stderr.write_all(&processed).await?;
@@ -102,6 +110,7 @@ impl Sink for StdErr {
         _reply_channel: Sender<sink::Reply>,
     ) -> Result<()> {
         self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
         Ok(())
     }
     fn default_codec(&self) -> &str {
@@ -29,6 +29,7 @@ use halfbrown::HashMap;
 
 pub struct StdOut {
     postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
     stdout: io::Stdout,
     config: Config,
 }
@@ -50,12 +51,14 @@ impl offramp::Impl for StdOut {
         if let Some(config) = config {
             Ok(SinkManager::new_box(Self {
                 postprocessors: vec![],
+                metrics: None,
                 stdout: io::stdout(),
                 config: Config::new(config)?,
             }))
         } else {
             Ok(SinkManager::new_box(Self {
                 postprocessors: vec![],
+                metrics: None,
                 stdout: io::stdout(),
                 config: Config::default(),
             }))
@@ -74,7 +77,12 @@ impl Sink for StdOut {
         let ingest_ns = event.ingest_ns;
         for value in event.value_iter() {
             let raw = codec.encode(value)?;
-            for processed in postprocess(&mut self.postprocessors, ingest_ns, raw)? {
+            for processed in postprocess(
+                &mut self.postprocessors,
+                ingest_ns,
+                raw,
+                self.metrics.as_deref(),
+            )? {
                 self.stdout.write_all(self.config.prefix.as_bytes()).await?;
                 if self.config.raw {
                     self.stdout.write_all(&processed).await?;
@@ -103,6 +111,7 @@ impl Sink for StdOut {
         _reply_channel: Sender<sink::Reply>,
     ) -> Result<()> {
         self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
         Ok(())
     }
     fn default_codec(&self) -> &str {
@@ -16,7 +16,10 @@
 
 //! # TCP Offramp
 //!
-//! Sends each message as a tcp stream
+//! Sends each message as a tcp stream. Messages are framed by
+//! postprocessors, e.g. `lines`, `lines-null` or `length-prefixed`. With
+//! `tls` the connection is established over TLS. A lost connection is
+//! reestablished following the `reconnect` policy.
 //!
 //! ## Configuration
 //!
@@ -24,15 +27,22 @@
 
 use std::time::Instant;
 
+use crate::connectors::reconnect::{self, Reconnect};
+use crate::connectors::tls;
 use crate::sink::prelude::*;
 use async_std::net::TcpStream;
+use futures::AsyncWrite;
 use halfbrown::HashMap;
 
+type Stream = Box<dyn AsyncWrite + Unpin + Send + Sync>;
+
 /// An offramp streams over TCP/IP
 pub struct Tcp {
-    stream: Option<TcpStream>,
+    stream: Option<Stream>,
     postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
     config: Config,
+    reconnect: Reconnect,
 }
 
 #[derive(Deserialize, Debug)]
@@ -43,6 +53,12 @@ pub struct Config {
     pub ttl: u32,
     #[serde(default = "t")]
     pub is_no_delay: bool,
+    /// connect over TLS
+    #[serde(default = "Default::default")]
+    pub tls: Option<tls::ClientConfig>,
+    /// reconnect policy
+    #[serde(default)]
+    pub reconnect: reconnect::Config,
 }
 
 fn t() -> bool {
@@ -59,10 +75,13 @@ impl offramp::Impl for Tcp {
     fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
         if let Some(config) = config {
             let config: Config = Config::new(config)?;
+            let reconnect = Reconnect::new(config.reconnect.clone());
             Ok(SinkManager::new_box(Self {
                 config,
                 stream: None,
                 postprocessors: vec![],
+                metrics: None,
+                reconnect,
             }))
         } else {
             Err("TCP offramp requires a config".into())
@@ -71,6 +90,19 @@ impl offramp::Impl for Tcp {
 }
 
 impl Tcp {
+    async fn connect(&self) -> Result<Stream> {
+        let stream = TcpStream::connect((self.config.host.as_str(), self.config.port)).await?;
+        stream.set_ttl(self.config.ttl)?;
+        stream.set_nodelay(self.config.is_no_delay)?;
+        if let Some(tls_config) = &self.config.tls {
+            let domain = tls_config.domain.as_ref().unwrap_or(&self.config.host);
+            let stream = tls::connector(tls_config)?.connect(domain, stream).await?;
+            Ok(Box::new(stream))
+        } else {
+            Ok(Box::new(stream))
+        }
+    }
+
     async fn send_event(&mut self, codec: &mut dyn Codec, event: &Event) -> Result<()> {
         let stream = self
             .stream
@@ -78,11 +110,18 @@ impl Tcp {
             .ok_or_else(|| Error::from(ErrorKind::NoSocket))?;
         for value in event.value_iter() {
             let raw = codec.encode(value)?;
-            let packets = postprocess(&mut self.postprocessors, event.ingest_ns, raw)?;
+            let packets = postprocess(
+                &mut self.postprocessors,
+                event.ingest_ns,
+                raw,
+                self.metrics.as_deref(),
+            )?;
             for packet in packets {
                 stream.write_all(&packet).await?;
             }
         }
+        // TLS streams buffer writes until flushed
+        stream.flush().await?;
         Ok(())
     }
 }
@@ -116,6 +155,10 @@ impl Sink for Tcp {
             // for TCP we always trigger the CB for IO/socket related errors
             Err(e @ Error(ErrorKind::Io(_), _)) | Err(e @ Error(ErrorKind::NoSocket, _)) => {
                 debug!("[Sink::TCP] Error sending event: {}.", e);
+                // the connection is reestablished on signals once an attempt is due
+                if self.stream.take().is_some() {
+                    self.reconnect.failed(nanotime(), &e.to_string());
+                }
                 if event.transactional {
                     Some(vec![
                         sink::Reply::Insight(event.to_fail()),
@@ -154,31 +197,29 @@ impl Sink for Tcp {
         _reply_channel: Sender<sink::Reply>,
     ) -> Result<()> {
         self.postprocessors = make_postprocessors(processors.post)?;
-        let stream = TcpStream::connect((self.config.host.as_str(), self.config.port)).await?;
-        stream.set_ttl(self.config.ttl)?;
-        stream.set_nodelay(self.config.is_no_delay)?;
-        self.stream = Some(stream);
+        self.metrics = processors.metrics;
+        self.reconnect.report_to(processors.stats);
+        self.stream = Some(self.connect().await?);
         Ok(())
     }
     async fn on_signal(&mut self, signal: Event) -> ResultVec {
-        if self.stream.is_none() {
-            let stream = if let Ok(stream) =
-                TcpStream::connect((self.config.host.as_str(), self.config.port)).await
-            {
-                stream
-            } else {
-                return Ok(Some(vec![sink::Reply::Insight(Event::cb_trigger(
+        if self.stream.is_some() || !self.reconnect.due(nanotime()) {
+            return Ok(None);
+        }
+        match self.connect().await {
+            Ok(stream) => {
+                self.reconnect.connected();
+                self.stream = Some(stream);
+                Ok(Some(vec![sink::Reply::Insight(Event::cb_restore(
                     signal.ingest_ns,
-                ))]));
-            };
-            stream.set_ttl(self.config.ttl)?;
-            stream.set_nodelay(self.config.is_no_delay)?;
-            self.stream = Some(stream);
-            Ok(Some(vec![sink::Reply::Insight(Event::cb_restore(
-                signal.ingest_ns,
-            ))]))
-        } else {
-            Ok(None)
+                ))]))
+            }
+            Err(e) => {
+                self.reconnect.failed(nanotime(), &e.to_string());
+                Ok(Some(vec![sink::Reply::Insight(Event::cb_trigger(
+                    signal.ingest_ns,
+                ))]))
+            }
         }
     }
     fn is_active(&self) -> bool {
@@ -18,11 +18,26 @@
 //!
 //! Sends each message as a udp datagram
 //!
+//! With `coalesce` set, messages to the same destination are joined by a
+//! `separator` into datagrams of up to `mtu` bytes, e.g. for statsd. A
+//! datagram is sent once the next message doesn't fit anymore, or once it is
+//! `flush_timeout` milliseconds old. Messages larger than `mtu` are sent on
+//! their own.
+//!
+//! ```yaml
+//! config:
+//!   host: statsd.local
+//!   port: 8125
+//!   coalesce:
+//!     mtu: 1432
+//!     flush_timeout: 100
+//! ```
+//!
 //! ## Configuration
 //!
 //! See [Config](struct.Config.html) for details.
 
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::sink::prelude::*;
 use async_std::net::UdpSocket;
@@ -33,6 +48,53 @@ pub struct Udp {
     socket: Option<UdpSocket>,
     config: Config,
     postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    /// datagrams being coalesced by destination, `None` is the configured one
+    datagrams: HashMap<Option<(String, u16)>, Datagram>,
+}
+
+/// Messages coalesced into a datagram
+#[derive(Debug, Default)]
+struct Datagram {
+    data: Vec<u8>,
+    since: Option<Instant>,
+    /// insights of the events whose last message is in this datagram
+    insights: Vec<Event>,
+}
+
+impl Datagram {
+    /// appends a message, false if it doesn't fit
+    fn push(&mut self, msg: &[u8], coalesce: &Coalesce) -> bool {
+        let separator = coalesce.separator.as_bytes();
+        let len = if self.data.is_empty() {
+            msg.len()
+        } else {
+            self.data.len() + separator.len() + msg.len()
+        };
+        if len > coalesce.mtu {
+            return false;
+        }
+        if self.data.is_empty() {
+            self.since = Some(Instant::now());
+        } else {
+            self.data.extend_from_slice(separator);
+        }
+        self.data.extend_from_slice(msg);
+        true
+    }
+
+    fn is_due(&self, timeout: Duration) -> bool {
+        self.since.map_or(false, |since| since.elapsed() >= timeout)
+    }
+
+    /// takes the data and insights, leaving an empty datagram
+    fn take(&mut self) -> (Vec<u8>, Vec<Event>) {
+        self.since = None;
+        (
+            std::mem::take(&mut self.data),
+            std::mem::take(&mut self.insights),
+        )
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -60,12 +122,40 @@ pub struct Config {
     bind: Host,
     #[serde(default = "t")]
     bound: bool,
+    /// Join messages into datagrams
+    #[serde(default = "Default::default")]
+    coalesce: Option<Coalesce>,
 }
 
 fn t() -> bool {
     true
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct Coalesce {
+    /// Maximum datagram size in bytes
+    #[serde(default = "d_mtu")]
+    mtu: usize,
+    /// Separator between messages
+    #[serde(default = "d_separator")]
+    separator: String,
+    /// Milliseconds after which a datagram is sent even if it isn't full
+    #[serde(default = "d_flush_timeout")]
+    flush_timeout: u64,
+}
+
+fn d_mtu() -> usize {
+    1432
+}
+
+fn d_separator() -> String {
+    "\n".to_string()
+}
+
+fn d_flush_timeout() -> u64 {
+    100
+}
+
 impl ConfigImpl for Config {}
 impl Udp {
     /// Binds and possibly 'connects' the udp socket
@@ -114,6 +204,8 @@ impl offramp::Impl for Udp {
                 socket: None,
                 config,
                 postprocessors: vec![],
+                metrics: None,
+                datagrams: HashMap::new(),
             }))
         } else {
             Err("UDP offramp requires a config".into())
@@ -122,31 +214,153 @@ impl offramp::Impl for Udp {
 }
 
 impl Udp {
-    /// serialize event and send each serialized packet
-    async fn send_event(&mut self, codec: &mut dyn Codec, event: &Event) -> Result<()> {
+    /// sends a datagram to `$udp.host` and `$udp.port`, or else the
+    /// configured destination
+    async fn send(&self, dst: Option<(&str, u16)>, data: &[u8]) -> Result<()> {
         let socket = self
             .socket
             .as_ref()
             .ok_or_else(|| Error::from(ErrorKind::NoSocket))?;
+        if let Some((host, port)) = dst {
+            socket.send_to(data, (host, port)).await?;
+        } else if self.config.bound {
+            socket.send(data).await?;
+        } else {
+            warn!("using `bound` in the UDP sink config is deprecated please use $udp.host and $udp.port instead!");
+            // reaquire the destination to handle DNS changes or multi IP dns entries
+            socket
+                .send_to(data, (self.config.host.as_str(), self.config.port))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// serialize event and send each serialized packet
+    async fn send_event(&mut self, codec: &mut dyn Codec, event: &Event) -> Result<()> {
+        if self.socket.is_none() {
+            return Err(ErrorKind::NoSocket.into());
+        }
         let ingest_ns = event.ingest_ns;
         for (value, meta) in event.value_meta_iter() {
             let raw = codec.encode(value)?;
-            for processed in postprocess(&mut self.postprocessors, ingest_ns, raw)? {
-                let udp = meta.get("udp");
-                if let Some((host, port)) = udp.get_str("host").zip(udp.get_u16("port")) {
-                    socket.send_to(&processed, (host, port)).await?;
-                } else if self.config.bound {
-                    socket.send(&processed).await?;
-                } else {
-                    warn!("using `bound` in the UDP sink config is deprecated please use $udp.host and $udp.port instead!");
-                    // reaquire the destination to handle DNS changes or multi IP dns entries
-                    socket
-                        .send_to(&processed, (self.config.host.as_str(), self.config.port))
+            let udp = meta.get("udp");
+            let dst = udp.get_str("host").zip(udp.get_u16("port"));
+            for processed in postprocess(
+                &mut self.postprocessors,
+                ingest_ns,
+                raw,
+                self.metrics.as_deref(),
+            )? {
+                self.send(dst, &processed).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// sends a coalesced datagram, returns the insights of its events
+    async fn send_datagram(&mut self, dst: &Option<(String, u16)>) -> Vec<sink::Reply> {
+        let (data, mut insights) = self
+            .datagrams
+            .get_mut(dst)
+            .map(Datagram::take)
+            .unwrap_or_default();
+        if !data.is_empty() {
+            if let Err(e) = self
+                .send(dst.as_ref().map(|(h, p)| (h.as_str(), *p)), &data)
+                .await
+            {
+                error!("[Sink::UDP] Error sending datagram: {}", e);
+                for insight in &mut insights {
+                    insight.cb = CbAction::Fail;
+                }
+            }
+        }
+        insights.into_iter().map(sink::Reply::Insight).collect()
+    }
+
+    /// sends the datagrams older than `flush_timeout`, or all of them
+    async fn send_datagrams(&mut self, all: bool) -> Vec<sink::Reply> {
+        let timeout = self
+            .config
+            .coalesce
+            .as_ref()
+            .map_or(Duration::from_secs(0), |c| {
+                Duration::from_millis(c.flush_timeout)
+            });
+        let due: Vec<_> = self
+            .datagrams
+            .iter()
+            .filter(|(_, d)| all || d.is_due(timeout))
+            .map(|(dst, _)| dst.clone())
+            .collect();
+        let mut replies = Vec::new();
+        for dst in due {
+            replies.append(&mut self.send_datagram(&dst).await);
+        }
+        replies
+    }
+
+    /// serialize event and coalesce its messages into datagrams, sending the
+    /// ones that are full
+    async fn coalesce_event(
+        &mut self,
+        codec: &mut dyn Codec,
+        event: &mut Event,
+        coalesce: &Coalesce,
+    ) -> Result<Vec<sink::Reply>> {
+        if self.socket.is_none() {
+            return Err(ErrorKind::NoSocket.into());
+        }
+        let ingest_ns = event.ingest_ns;
+        let mut replies = Vec::new();
+        let mut last_dst = None;
+        let mut messages = Vec::new();
+        for (value, meta) in event.value_meta_iter() {
+            let raw = codec.encode(value)?;
+            let udp = meta.get("udp");
+            let dst = udp
+                .get_str("host")
+                .zip(udp.get_u16("port"))
+                .map(|(h, p)| (h.to_string(), p));
+            for processed in postprocess(
+                &mut self.postprocessors,
+                ingest_ns,
+                raw,
+                self.metrics.as_deref(),
+            )? {
+                messages.push((dst.clone(), processed));
+            }
+        }
+        for (dst, msg) in messages {
+            let datagram = self
+                .datagrams
+                .entry(dst.clone())
+                .or_insert_with(Datagram::default);
+            if !datagram.push(&msg, coalesce) {
+                replies.append(&mut self.send_datagram(&dst).await);
+                let datagram = self
+                    .datagrams
+                    .entry(dst.clone())
+                    .or_insert_with(Datagram::default);
+                if !datagram.push(&msg, coalesce) {
+                    // too large to coalesce
+                    self.send(dst.as_ref().map(|(h, p)| (h.as_str(), *p)), &msg)
                         .await?;
                 }
             }
+            last_dst = Some(dst);
         }
-        Ok(())
+        if event.transactional {
+            match last_dst.and_then(|dst| self.datagrams.get_mut(&dst)) {
+                Some(datagram) if !datagram.data.is_empty() => {
+                    datagram.insights.push(event.insight_ack());
+                }
+                // everything was sent already
+                _ => replies.push(sink::Reply::Insight(event.insight_ack())),
+            }
+        }
+        replies.append(&mut self.send_datagrams(false).await);
+        Ok(replies)
     }
 }
 
@@ -160,6 +374,27 @@ impl Sink for Udp {
         _codec_map: &HashMap<String, Box<dyn Codec>>,
         mut event: Event,
     ) -> ResultVec {
+        if let Some(coalesce) = self.config.coalesce.clone() {
+            return match self.coalesce_event(codec, &mut event, &coalesce).await {
+                Ok(replies) => Ok(Some(replies)),
+                Err(e @ Error(ErrorKind::NoSocket, _)) => {
+                    error!("[Sink::UDP] Error sending event: {}.", e);
+                    let mut replies = vec![sink::Reply::Insight(event.insight_trigger())];
+                    if event.transactional {
+                        replies.push(sink::Reply::Insight(event.to_fail()));
+                    }
+                    Ok(Some(replies))
+                }
+                Err(e) => {
+                    error!("[Sink::UDP] Error sending event: {}", e);
+                    if event.transactional {
+                        Ok(Some(vec![sink::Reply::Insight(event.to_fail())]))
+                    } else {
+                        Ok(None)
+                    }
+                }
+            };
+        }
         let processing_start = Instant::now();
         // TODO: how to properly report error metrics if we dont raise an error here?
         let replies = match self.send_event(codec, &event).await {
@@ -199,6 +434,15 @@ impl Sink for Udp {
         };
         Ok(replies)
     }
+    async fn flush(&mut self) -> ResultVec {
+        Ok(Some(self.send_datagrams(true).await))
+    }
+    fn flush_timeout(&self) -> Option<u64> {
+        self.config
+            .coalesce
+            .as_ref()
+            .map(|c| c.flush_timeout * 1_000_000)
+    }
     fn default_codec(&self) -> &str {
         "json"
     }
@@ -214,6 +458,7 @@ impl Sink for Udp {
         _reply_channel: Sender<sink::Reply>,
     ) -> Result<()> {
         self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
         self.bind().await?;
         Ok(())
     }
@@ -234,3 +479,31 @@ impl Sink for Udp {
         false
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn coalesce() {
+        let coalesce = Coalesce {
+            mtu: 10,
+            separator: "\n".to_string(),
+            flush_timeout: 100,
+        };
+        let mut d = Datagram::default();
+        assert!(d.push(b"snot", &coalesce));
+        assert!(d.push(b"badg", &coalesce));
+        assert_eq!(b"snot\nbadg", d.data.as_slice());
+        // would be 11 bytes
+        assert!(!d.push(b"er", &coalesce));
+        assert!(!d.is_due(Duration::from_secs(60)));
+        assert!(d.is_due(Duration::from_secs(0)));
+        let (data, insights) = d.take();
+        assert_eq!(9, data.len());
+        assert!(insights.is_empty());
+        assert!(!d.is_due(Duration::from_secs(0)));
+        assert!(!d.push(b"snotbadgers", &coalesce));
+        assert!(d.data.is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Unix Socket Offramp
+//!
+//! Sends each message to the unix domain socket at `path`, written to a
+//! connection with the `stream` socket type or sent as a datagram with
+//! `datagram`.
+//!
+//! Failing to send triggers the circuit breaker, the socket is reconnected
+//! on signals following the `reconnect` policy.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use std::time::Instant;
+
+use crate::connectors::reconnect::{self, Reconnect};
+use crate::connectors::unix_socket::SocketType;
+use crate::sink::prelude::*;
+use async_std::os::unix::net::{UnixDatagram, UnixStream};
+use halfbrown::HashMap;
+
+#[derive(Deserialize, Debug)]
+pub struct Config {
+    /// path of the socket to send to
+    pub path: String,
+    /// `stream` or `datagram`
+    #[serde(default)]
+    pub socket_type: SocketType,
+    /// reconnect policy
+    #[serde(default)]
+    pub reconnect: reconnect::Config,
+}
+
+impl ConfigImpl for Config {}
+
+enum Socket {
+    Stream(UnixStream),
+    Datagram(UnixDatagram),
+}
+
+/// An offramp sending to a unix domain socket
+pub struct UnixSocket {
+    socket: Option<Socket>,
+    postprocessors: Postprocessors,
+    metrics: Option<Arc<ByteMetrics>>,
+    config: Config,
+    reconnect: Reconnect,
+}
+
+impl offramp::Impl for UnixSocket {
+    fn from_config(config: &Option<OpConfig>) -> Result<Box<dyn Offramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            let reconnect = Reconnect::new(config.reconnect.clone());
+            Ok(SinkManager::new_box(Self {
+                config,
+                socket: None,
+                postprocessors: vec![],
+                metrics: None,
+                reconnect,
+            }))
+        } else {
+            Err("unix-socket offramp requires a config".into())
+        }
+    }
+}
+
+impl UnixSocket {
+    async fn connect(&mut self) -> Result<()> {
+        let path = self.config.path.as_str();
+        let socket = match self.config.socket_type {
+            SocketType::Stream => Socket::Stream(UnixStream::connect(path).await?),
+            SocketType::Datagram => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Socket::Datagram(socket)
+            }
+        };
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    async fn send_event(&mut self, codec: &mut dyn Codec, event: &Event) -> Result<()> {
+        let socket = self
+            .socket
+            .as_mut()
+            .ok_or_else(|| Error::from(ErrorKind::NoSocket))?;
+        for value in event.value_iter() {
+            let raw = codec.encode(value)?;
+            let packets = postprocess(
+                &mut self.postprocessors,
+                event.ingest_ns,
+                raw,
+                self.metrics.as_deref(),
+            )?;
+            for packet in packets {
+                match socket {
+                    Socket::Stream(stream) => stream.write_all(&packet).await?,
+                    Socket::Datagram(socket) => {
+                        socket.send(&packet).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for UnixSocket {
+    /// We acknowledge ourself
+    fn auto_ack(&self) -> bool {
+        false
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    async fn on_event(
+        &mut self,
+        _input: &str,
+        codec: &mut dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        mut event: Event,
+    ) -> ResultVec {
+        let processing_start = Instant::now();
+        let replies = match self.send_event(codec, &event).await {
+            Ok(()) => {
+                if event.transactional {
+                    Some(vec![sink::Reply::Insight(event.insight_ack_with_timing(
+                        processing_start.elapsed().as_millis() as u64,
+                    ))])
+                } else {
+                    None
+                }
+            }
+            // the peer is gone, reconnect on signals once an attempt is due
+            Err(e @ Error(ErrorKind::Io(_), _)) | Err(e @ Error(ErrorKind::NoSocket, _)) => {
+                debug!("[Sink::UnixSocket] Error sending event: {}.", e);
+                if self.socket.take().is_some() {
+                    self.reconnect.failed(nanotime(), &e.to_string());
+                }
+                if event.transactional {
+                    Some(vec![
+                        sink::Reply::Insight(event.to_fail()),
+                        sink::Reply::Insight(event.insight_trigger()),
+                    ])
+                } else {
+                    Some(vec![sink::Reply::Insight(event.insight_trigger())])
+                }
+            }
+            Err(e) => {
+                debug!("[Sink::UnixSocket] Error sending event: {}", e);
+                if event.transactional {
+                    Some(vec![sink::Reply::Insight(event.to_fail())])
+                } else {
+                    None
+                }
+            }
+        };
+        Ok(replies)
+    }
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+    #[allow(clippy::too_many_arguments)]
+    async fn init(
+        &mut self,
+        _sink_uid: u64,
+        _sink_url: &TremorUrl,
+        _codec: &dyn Codec,
+        _codec_map: &HashMap<String, Box<dyn Codec>>,
+        processors: Processors<'_>,
+        _is_linked: bool,
+        _reply_channel: Sender<sink::Reply>,
+    ) -> Result<()> {
+        self.postprocessors = make_postprocessors(processors.post)?;
+        self.metrics = processors.metrics;
+        self.reconnect.report_to(processors.stats);
+        self.connect().await
+    }
+    async fn on_signal(&mut self, signal: Event) -> ResultVec {
+        if self.socket.is_some() || !self.reconnect.due(nanotime()) {
+            Ok(None)
+        } else if let Err(e) = self.connect().await {
+            debug!(
+                "[Sink::UnixSocket] Error connecting to {}: {}",
+                self.config.path, e
+            );
+            self.reconnect.failed(nanotime(), &e.to_string());
+            Ok(Some(vec![sink::Reply::Insight(Event::cb_trigger(
+                signal.ingest_ns,
+            ))]))
+        } else {
+            self.reconnect.connected();
+            Ok(Some(vec![sink::Reply::Insight(Event::cb_restore(
+                signal.ingest_ns,
+            ))]))
+        }
+    }
+    fn is_active(&self) -> bool {
+        self.socket.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::codec::string::String as StringCodec;
+
+    #[async_std::test]
+    async fn datagram() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("sink.sock");
+        let receiver = UnixDatagram::bind(&path).await?;
+        let mut sink = UnixSocket {
+            socket: None,
+            postprocessors: vec![],
+            metrics: None,
+            config: Config {
+                path: path.to_string_lossy().to_string(),
+                socket_type: SocketType::Datagram,
+                reconnect: reconnect::Config::default(),
+            },
+            reconnect: Reconnect::new(reconnect::Config::default()),
+        };
+        sink.connect().await?;
+        let mut codec = StringCodec {};
+        let event = Event {
+            data: (Value::from("snot"), Value::object()).into(),
+            ..Event::default()
+        };
+        sink.send_event(&mut codec, &event).await?;
+        let mut buffer = [0; 16];
+        let n = receiver.recv(&mut buffer).await?;
+        assert_eq!(b"snot", &buffer[..n]);
+
+        // the receiver is gone
+        drop(receiver);
+        assert!(sink.send_event(&mut codec, &event).await.is_err());
+        Ok(())
+    }
+}
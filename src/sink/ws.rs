@@ -14,6 +14,7 @@
 
 #![cfg(not(tarpaulin_include))]
 
+use crate::connectors::reconnect::{self, Reconnect};
 use crate::sink::prelude::*;
 use crate::source::prelude::*;
 use async_channel::{bounded, unbounded, Receiver, Sender};
@@ -23,7 +24,6 @@ use async_tungstenite::tungstenite::Message;
 use futures::SinkExt;
 use halfbrown::HashMap;
 use std::boxed::Box;
-use std::time::Duration;
 use tremor_pipeline::{EventId, OpMeta};
 use tremor_script::LineValue;
 use url::Url;
@@ -47,6 +47,9 @@ pub struct Config {
     pub url: String,
     #[serde(default)]
     pub binary: bool,
+    /// reconnect policy
+    #[serde(default)]
+    pub reconnect: reconnect::Config,
 }
 
 enum WsConnectionMsg {
@@ -127,30 +130,34 @@ async fn ws_connection_loop(
     mut preprocessors: Preprocessors,
     mut postprocessors: Postprocessors,
     mut codec: Box<dyn Codec>,
+    mut reconnect: Reconnect,
 ) -> Result<()> {
     loop {
         let codec: &mut dyn Codec = codec.as_mut();
         info!("[Sink::{}] Connecting to {} ...", &sink_url, url);
-        let mut ws_stream = if let Ok((ws_stream, _)) = connect_async(&url).await {
-            if let Ok(peer) = ws_stream.get_ref().peer_addr() {
-                event_origin_url.port = Some(peer.port());
-                event_origin_url.host = peer.ip().to_string();
+        let mut ws_stream = match connect_async(&url).await {
+            Ok((ws_stream, _)) => {
+                if let Ok(peer) = ws_stream.get_ref().peer_addr() {
+                    event_origin_url.port = Some(peer.port());
+                    event_origin_url.host = peer.ip().to_string();
+                }
+                if let Ok(local) = ws_stream.get_ref().local_addr() {
+                    event_origin_url.path = vec![local.port().to_string()];
+                }
+                ws_stream
             }
-            if let Ok(local) = ws_stream.get_ref().local_addr() {
-                event_origin_url.path = vec![local.port().to_string()];
+            Err(e) => {
+                error!("[Sink::{}] Failed to connect to {}: {}", &sink_url, url, e);
+                connection_lifecycle_tx
+                    .send(WsConnectionMsg::Disconnected(url.clone()))
+                    .await?;
+                if reconnect.wait(&e.to_string()).await {
+                    continue;
+                }
+                return Err(format!("Gave up connecting to {}: {}", url, e).into());
             }
-            ws_stream
-        } else {
-            error!(
-                "[Sink::{}] Failed to connect to {}, retrying in 1s",
-                &sink_url, url
-            );
-            connection_lifecycle_tx
-                .send(WsConnectionMsg::Disconnected(url.clone()))
-                .await?;
-            task::sleep(Duration::from_secs(1)).await;
-            continue;
         };
+        reconnect.connected();
         connection_lifecycle_tx
             .send(WsConnectionMsg::Connected(url.clone(), tx.clone()))
             .await?;
@@ -342,7 +349,7 @@ fn event_to_message(
     binary: bool,
 ) -> Result<impl Iterator<Item = Result<Message>>> {
     let raw = codec.encode(data.suffix().value())?;
-    let datas = postprocess(postprocessors, ingest_ns, raw)?;
+    let datas = postprocess(postprocessors, ingest_ns, raw, None)?;
     Ok(datas.into_iter().map(move |raw_data| {
         if binary {
             Ok(Message::Binary(raw_data))
@@ -584,6 +591,7 @@ impl Sink for Ws {
                 make_preprocessors(self.preprocessors.as_slice())?,
                 make_postprocessors(self.postprocessors.as_slice())?,
                 self.shared_codec.boxed_clone(),
+                Reconnect::new(self.config.reconnect.clone()),
             ));
             // TODO default to None for initial connection? (like what happens for
             // default offramp config url). if we do circuit-breakers-per-url
@@ -665,6 +673,9 @@ impl Sink for Ws {
 
         // handle connection for the offramp config url (as default)
         let (conn_tx, conn_rx) = bounded(crate::QSIZE);
+        let mut reconnect = Reconnect::new(self.config.reconnect.clone());
+        // only the connection to the configured url is reported
+        reconnect.report_to(processors.stats);
         self.reply_tx = reply_channel;
         let handle = task::Builder::new()
             .name(format!("{}-connection-{}", &sink_url, &self.config.url))
@@ -680,6 +691,7 @@ impl Sink for Ws {
                 make_preprocessors(self.preprocessors.as_slice())?,
                 make_postprocessors(self.postprocessors.as_slice())?,
                 self.shared_codec.boxed_clone(),
+                reconnect,
             ))?;
         self.connections
             .insert(self.config.url.clone(), (None, handle));
@@ -761,6 +773,7 @@ mod test {
         let config = Config {
             url: "http://idonotexist:65535/path".to_string(),
             binary: true,
+            reconnect: reconnect::Config::default(),
         };
         let mut sink = Ws {
             sink_url: url.clone(),
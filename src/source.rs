@@ -13,23 +13,26 @@
 // limitations under the License.
 
 use crate::errors::Error;
+use crate::filter::Filter;
 use crate::metrics::RampReporter;
 use crate::onramp;
 use crate::pipeline;
-use crate::preprocessor::{make_preprocessors, preprocess, Preprocessors};
-use crate::url::ports::{ERR, METRICS, OUT};
+use crate::preprocessor::verify::VerifyMetrics;
+use crate::preprocessor::{make_configured_preprocessors, preprocess, Preprocessors};
+use crate::url::ports::{ACCESS_LOG, DIAGNOSTICS, ERR, METRICS, OUT};
 use crate::url::TremorUrl;
 use crate::{
     codec::{self, Codec},
     pipeline::ConnectTarget,
 };
 
-use crate::Result;
+use crate::{OpConfig, Result};
 use async_channel::{self, unbounded, Receiver, Sender};
 use async_std::task;
 use beef::Cow;
 use halfbrown::HashMap;
 use std::collections::BTreeMap;
+use std::sync::Arc;
 use std::time::Duration;
 use tremor_common::time::nanotime;
 use tremor_pipeline::{CbAction, Event, EventId, EventOriginUri, DEFAULT_STREAM_ID};
@@ -37,22 +40,47 @@ use tremor_script::prelude::*;
 
 use self::prelude::OnrampConfig;
 
+pub(crate) mod access_log;
+pub(crate) mod amqp;
 pub(crate) mod blaster;
 pub(crate) mod cb;
+pub(crate) mod checkpoint;
 pub(crate) mod crononome;
 pub(crate) mod discord;
+pub(crate) mod eventhubs;
 pub(crate) mod file;
+pub(crate) mod flow;
+pub(crate) mod grpc_web;
+pub(crate) mod http_poller;
 pub(crate) mod kafka;
+pub(crate) mod kinesis;
+pub(crate) mod lifecycle;
 pub(crate) mod metronome;
+pub(crate) mod mysql;
 pub(crate) mod nats;
+pub(crate) mod netflow;
 pub(crate) mod otel;
 pub(crate) mod postgres;
+pub(crate) mod postgres_cdc;
 pub(crate) mod prelude;
+pub(crate) mod pubsub;
+pub(crate) mod rate_limit;
+pub(crate) mod redis;
 pub(crate) mod rest;
+pub(crate) mod s3;
+pub(crate) mod sflow;
+pub(crate) mod snmp_trap;
+pub(crate) mod sqs;
+pub(crate) mod sse;
 pub(crate) mod stdin;
+pub(crate) mod tail;
 pub(crate) mod tcp;
 pub(crate) mod udp;
+#[cfg(unix)]
+pub(crate) mod unix_socket;
+pub(crate) mod webhook;
 pub(crate) mod ws;
+pub(crate) mod ws_client;
 
 struct StaticValue(Value<'static>);
 
@@ -63,6 +91,10 @@ pub struct Processors<'processor> {
     pub pre: &'processor [String],
     /// postprocessors
     pub post: &'processor [String],
+    /// byte metrics for the postprocessors, if metrics are enabled
+    pub metrics: Option<std::sync::Arc<crate::postprocessor::ByteMetrics>>,
+    /// runtime stats of the ramp, for connectors to report their connection
+    pub stats: Option<std::sync::Arc<crate::metrics::InstanceStats>>,
 }
 
 // This is ugly but we need to handle comments, thanks rental!
@@ -100,6 +132,17 @@ pub(crate) enum SourceReply {
         origin_uri: EventOriginUri,
         data: LineValue,
     },
+    /// Diagnostics about the source, sent to the `diagnostics` port
+    Diagnostics {
+        origin_uri: EventOriginUri,
+        data: Value<'static>,
+    },
+    /// An access log entry of a request or connection, sent to the
+    /// `access_log` port
+    AccessLog {
+        origin_uri: EventOriginUri,
+        data: Value<'static>,
+    },
     /// A stream is opened
     StartStream(usize),
     /// A stream is closed
@@ -171,6 +214,8 @@ where
     rx: Receiver<onramp::Msg>,
     tx: Sender<onramp::Msg>,
     pp_template: Vec<String>,
+    pp_config: Option<OpConfig>,
+    pp_metrics: Option<Arc<VerifyMetrics>>,
     preprocessors: BTreeMap<usize, Preprocessors>,
     codec: Box<dyn Codec>,
     codec_map: HashMap<String, Box<dyn Codec>>,
@@ -178,7 +223,12 @@ where
     triggered: bool,
     pipelines_out: Vec<(TremorUrl, pipeline::Addr)>,
     pipelines_err: Vec<(TremorUrl, pipeline::Addr)>,
+    pipelines_diagnostics: Vec<(TremorUrl, pipeline::Addr)>,
+    pipelines_access_log: Vec<(TremorUrl, pipeline::Addr)>,
+    /// binding filters by pipeline
+    filters: HashMap<TremorUrl, Filter>,
     err_required: bool,
+    correlation: Option<crate::correlation::Config>,
     id: u64,
     is_transactional: bool,
     /// Unique Id for the source
@@ -223,6 +273,18 @@ where
         match self.handle_pp(stream, ingest_ns, data) {
             Ok(data) => {
                 let meta_value = meta.map_or_else(Value::object, |m| m.0);
+                // a codec selected via `$codec` has to exist, other than the
+                // override which falls back to the configured codec
+                let meta_codec = meta_value
+                    .get_str(codec::META_FIELD)
+                    .map(ToString::to_string);
+                if let Some(name) = &meta_codec {
+                    if !self.codec_map.contains_key(name) {
+                        results.push(Err(format!("Unknown codec `{}` in `$codec`", name).into()));
+                        return results;
+                    }
+                }
+                let codec_override = meta_codec.or(codec_override);
                 for d in data {
                     let line_value = LineValue::try_new(vec![d], |mutd| {
                         // this is safe, because we get the vec we created in the previous argument and we now it has 1 element
@@ -246,7 +308,12 @@ where
                     .map_err(|e| e.0);
 
                     match line_value {
-                        Ok(decoded) => results.push(Ok(decoded)),
+                        Ok(mut decoded) => {
+                            if let Some(correlation) = &self.correlation {
+                                decoded.with_dependent_mut(|_, vm| correlation.apply(vm));
+                            }
+                            results.push(Ok(decoded))
+                        }
                         Err(RentalSnot::Skip) => (),
                         Err(RentalSnot::Error(e)) => {
                             // TODO: add error context (with error handling update)
@@ -297,6 +364,10 @@ where
                                 &mut self.pipelines_out
                             } else if port == ERR {
                                 &mut self.pipelines_err
+                            } else if port == DIAGNOSTICS {
+                                &mut self.pipelines_diagnostics
+                            } else if port == ACCESS_LOG {
+                                &mut self.pipelines_access_log
                             } else {
                                 return Err(format!(
                                     "Invalid Onramp Port: {}. Cannot connect.",
@@ -314,11 +385,18 @@ where
                         }
                     }
                 }
+                onramp::Msg::Filter { id, filter } => {
+                    info!("[Source::{}] Filtering events to {}.", self.source_id, id);
+                    self.filters.insert(id, filter);
+                }
                 onramp::Msg::Disconnect { id, tx } => {
+                    self.filters.remove(&id);
                     for (_, p) in self
                         .pipelines_out
                         .iter()
                         .chain(self.pipelines_err.iter())
+                        .chain(self.pipelines_diagnostics.iter())
+                        .chain(self.pipelines_access_log.iter())
                         .filter(|(pid, _)| pid == &id)
                     {
                         p.send_mgmt(pipeline::MgmtMsg::DisconnectInput(id.clone()))
@@ -330,6 +408,12 @@ where
                     empty_pipelines &= self.pipelines_out.is_empty();
                     self.pipelines_err.retain(|(pipeline, _)| pipeline != &id);
                     empty_pipelines &= self.pipelines_err.is_empty();
+                    self.pipelines_diagnostics
+                        .retain(|(pipeline, _)| pipeline != &id);
+                    empty_pipelines &= self.pipelines_diagnostics.is_empty();
+                    self.pipelines_access_log
+                        .retain(|(pipeline, _)| pipeline != &id);
+                    empty_pipelines &= self.pipelines_access_log.is_empty();
 
                     tx.send(empty_pipelines).await?;
                     if empty_pipelines {
@@ -394,19 +478,35 @@ where
             ingest_ns,
             // TODO make origin_uri non-optional here too?
             origin_uri: Some(origin_uri),
-            transactional: self.is_transactional,
+            // diagnostics and access logs aren't acked, the source doesn't track them
+            transactional: self.is_transactional && DIAGNOSTICS != port && ACCESS_LOG != port,
             ..Event::default()
         };
         let mut error = false;
         self.id += 1;
         let pipelines = if OUT == port {
-            &mut self.pipelines_out
+            &self.pipelines_out
         } else if ERR == port {
-            &mut self.pipelines_err
+            &self.pipelines_err
+        } else if DIAGNOSTICS == port {
+            &self.pipelines_diagnostics
+        } else if ACCESS_LOG == port {
+            &self.pipelines_access_log
         } else {
             return false;
         };
-        if let Some((last, pipelines)) = pipelines.split_last_mut() {
+        // only send to pipelines whose binding filter matches
+        let filters = &self.filters;
+        let data = event.data.suffix();
+        let matches = |id: &TremorUrl| {
+            filters.is_empty()
+                || filters
+                    .get(id)
+                    .map_or(true, |f| f.matches(data.value(), data.meta()))
+        };
+        if let Some(last) = pipelines.iter().rposition(|(id, _)| matches(id)) {
+            // ALLOW: rposition returns an index into pipelines
+            let (pipelines, last) = (&pipelines[..last], &pipelines[last]);
             if let Some(t) = self.metrics_reporter.periodic_flush(ingest_ns) {
                 self.metrics_reporter.send(self.source.metrics(t))
             }
@@ -414,11 +514,11 @@ where
             // TODO refactor metrics_reporter to do this by port now
             if ERR == port {
                 self.metrics_reporter.increment_err();
-            } else {
+            } else if OUT == port {
                 self.metrics_reporter.increment_out();
             }
 
-            for (input, addr) in pipelines {
+            for (input, addr) in pipelines.iter().filter(|(id, _)| matches(id)) {
                 if let Some(input) = input.instance_port() {
                     if let Err(e) = addr
                         .send(pipeline::Msg::Event {
@@ -451,11 +551,17 @@ where
                     error = true;
                 }
             }
+        } else if event.transactional && !pipelines.is_empty() {
+            // no binding filter matched, nobody is going to ack the event
+            self.source.ack(event.id.event_id());
         }
         error
     }
 
-    async fn new(mut source: T, config: OnrampConfig<'_>) -> Result<(Self, Sender<onramp::Msg>)> {
+    async fn new(
+        mut source: T,
+        mut config: OnrampConfig<'_>,
+    ) -> Result<(Self, Sender<onramp::Msg>)> {
         // We use a unbounded channel for counterflow, while an unbounded channel seems dangerous
         // there is soundness to this.
         // The unbounded channel ensures that on counterflow we never have to block, or in other
@@ -473,15 +579,20 @@ where
         // N is the maximum number of counterflow events a single event can trigger.
         // N is normally < 1.
         let (tx, rx) = unbounded();
-        let codec = codec::lookup(&config.codec)?;
+        let codec = codec::lookup_spec(config.codec, &config.codec_config)?;
         let mut resolved_codec_map = codec::builtin_codec_map();
         // override the builtin map
         for (k, v) in config.codec_map {
             resolved_codec_map.insert(k, codec::lookup(&v)?);
         }
         let pp_template = config.processors.pre.to_vec();
+        let pp_config = config.preprocessor_config;
+        let pp_metrics = config.metrics_reporter.verify_metrics();
         let mut preprocessors = BTreeMap::new();
-        preprocessors.insert(0, make_preprocessors(&&pp_template)?);
+        preprocessors.insert(
+            0,
+            make_configured_preprocessors(&pp_template, &pp_config, &pp_metrics)?,
+        );
 
         source.init().await?;
         let is_transactional = source.is_transactional();
@@ -489,6 +600,8 @@ where
             Self {
                 source_id: source.id().clone(),
                 pp_template,
+                pp_config,
+                pp_metrics,
                 source,
                 rx,
                 tx: tx.clone(),
@@ -501,9 +614,13 @@ where
                 id: 0,
                 pipelines_out: Vec::new(),
                 pipelines_err: Vec::new(),
+                pipelines_diagnostics: Vec::new(),
+                pipelines_access_log: Vec::new(),
+                filters: HashMap::new(),
                 uid: config.onramp_uid,
                 is_transactional,
                 err_required: config.err_required,
+                correlation: config.correlation,
             },
             tx,
         ))
@@ -512,7 +629,19 @@ where
     async fn start(source: T, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
         let name = source.id().short_id("src");
         let (manager, tx) = SourceManager::new(source, config).await?;
-        task::Builder::new().name(name).spawn(manager.run())?;
+        let stats = manager.metrics_reporter.stats();
+        task::Builder::new().name(name).spawn(async move {
+            let id = manager.source_id.to_string();
+            let res = manager.run().await;
+            stats.terminated(&res);
+            if let Err(e) = &res {
+                lifecycle::publish(lifecycle::Transition::ConnectorFailed {
+                    id,
+                    error: e.to_string(),
+                });
+            }
+            res
+        })?;
         Ok(tx)
     }
 
@@ -526,15 +655,37 @@ where
 
             if !self.triggered && !pipelines_out_empty {
                 match self.source.pull_event(self.id).await {
+                    Ok(SourceReply::Diagnostics { origin_uri, data }) => {
+                        let data = (data, Value::object()).into();
+                        self.transmit_event(data, nanotime(), origin_uri, DIAGNOSTICS)
+                            .await;
+                    }
+                    Ok(SourceReply::AccessLog { origin_uri, data }) => {
+                        let data = (data, Value::object()).into();
+                        self.transmit_event(data, nanotime(), origin_uri, ACCESS_LOG)
+                            .await;
+                    }
                     Ok(SourceReply::StartStream(id)) => {
-                        self.preprocessors
-                            .insert(id, make_preprocessors(&self.pp_template)?);
+                        self.preprocessors.insert(
+                            id,
+                            make_configured_preprocessors(
+                                &self.pp_template,
+                                &self.pp_config,
+                                &self.pp_metrics,
+                            )?,
+                        );
                     }
                     Ok(SourceReply::EndStream(id)) => {
                         self.preprocessors.remove(&id);
                     }
-                    Ok(SourceReply::Structured { origin_uri, data }) => {
+                    Ok(SourceReply::Structured {
+                        origin_uri,
+                        mut data,
+                    }) => {
                         let ingest_ns = nanotime();
+                        if let Some(correlation) = &self.correlation {
+                            data.with_dependent_mut(|_, vm| correlation.apply(vm));
+                        }
 
                         self.transmit_event(data, ingest_ns, origin_uri, OUT).await;
                     }
@@ -570,6 +721,7 @@ where
                                         "[Source::{}] Error decoding event data: {}",
                                         self.source_id, e
                                     );
+                                    self.metrics_reporter.set_last_error(&e);
                                     let mut error_meta = Object::with_capacity(1);
                                     error_meta.insert_nocheck("error".into(), e.to_string().into());
 
@@ -604,7 +756,7 @@ where
                     }
                     Err(e) => {
                         warn!("[Source::{}] Error: {}", self.source_id, e);
-                        self.metrics_reporter.increment_err();
+                        self.metrics_reporter.record_error(&e);
                     }
                 }
             }
@@ -615,6 +767,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::CodecSpec;
 
     #[derive(Debug)]
     struct FakeSource {
@@ -650,12 +803,15 @@ mod tests {
         };
         let o_config = OnrampConfig {
             onramp_uid: 1,
-            codec: "string",
+            codec: &CodecSpec::from("string"),
+            codec_config: None,
             codec_map: HashMap::new(),
             processors: Processors::default(),
+            preprocessor_config: None,
             metrics_reporter: RampReporter::new(onramp_url.clone(), None),
             is_linked: false,
             err_required: false,
+            correlation: None,
         };
         let (sm, sender) = SourceManager::new(s, o_config).await?;
         let handle = task::spawn(sm.run());
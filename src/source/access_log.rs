@@ -0,0 +1,111 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access logs of the `rest` and `ws` onramps
+//!
+//! Every request answered by the `rest` onramp and every connection closed by
+//! the `ws` onramp is logged as an event on the `access_log` port:
+//!
+//! ```json
+//! {
+//!   "method": "POST",
+//!   "path": "/orders",
+//!   "status": 200,
+//!   "duration_ns": 1340000,
+//!   "bytes_in": 512,
+//!   "bytes_out": 27,
+//!   "peer": "10.0.0.12:53120"
+//! }
+//! ```
+
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+use tremor_script::prelude::*;
+
+/// A request or connection being logged
+#[derive(Debug, Clone)]
+pub(crate) struct AccessLog {
+    method: String,
+    path: String,
+    peer: Option<String>,
+    start: Instant,
+}
+
+impl AccessLog {
+    /// Starts timing a request or connection
+    pub(crate) fn start(method: &str, path: &str, peer: Option<&str>) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            peer: peer.map(String::from),
+            start: Instant::now(),
+        }
+    }
+
+    /// Sets the path once it is known, like after a websocket handshake
+    pub(crate) fn set_path(&mut self, path: &str) {
+        self.path = path.to_string();
+    }
+
+    /// The access log event once the request is answered or the connection
+    /// closed
+    pub(crate) fn finish(&self, status: u16, bytes_in: usize, bytes_out: usize) -> Value<'static> {
+        self.entry(status, bytes_in, bytes_out, self.start.elapsed())
+    }
+
+    fn entry(
+        &self,
+        status: u16,
+        bytes_in: usize,
+        bytes_out: usize,
+        duration: Duration,
+    ) -> Value<'static> {
+        literal!({
+            "method": self.method.clone(),
+            "path": self.path.clone(),
+            "status": status,
+            "duration_ns": u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX),
+            "bytes_in": bytes_in,
+            "bytes_out": bytes_out,
+            "peer": self.peer.clone().map_or_else(Value::null, Value::from)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entry() {
+        let log = AccessLog::start("POST", "/orders", Some("10.0.0.12:53120"));
+        assert_eq!(
+            literal!({
+                "method": "POST",
+                "path": "/orders",
+                "status": 200,
+                "duration_ns": 1_340_000,
+                "bytes_in": 512,
+                "bytes_out": 27,
+                "peer": "10.0.0.12:53120"
+            }),
+            log.entry(200, 512, 27, Duration::from_micros(1340))
+        );
+        let log = AccessLog::start("GET", "/", None);
+        assert_eq!(
+            Some(true),
+            log.finish(101, 0, 0).get("peer").map(Value::is_null)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checkpoints of onramps reading partitioned streams, the position up to
+//! which each partition was processed
+
+use crate::errors::Result;
+use halfbrown::HashMap;
+
+/// Where checkpoints are kept
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "store", rename_all = "lowercase")]
+pub enum CheckpointConfig {
+    /// in memory, lost on restart
+    Memory,
+    /// in a sled database
+    Sled {
+        /// directory of the database
+        dir: String,
+    },
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        Self::Memory
+    }
+}
+
+/// Keeps the position up to which the records of a partition were processed
+pub(crate) trait CheckpointStore: Send + Sync {
+    fn get(&self, partition: &str) -> Result<Option<String>>;
+    fn set(&mut self, partition: &str, checkpoint: &str) -> Result<()>;
+}
+
+#[derive(Default)]
+pub(crate) struct MemoryStore {
+    checkpoints: HashMap<String, String>,
+}
+
+impl CheckpointStore for MemoryStore {
+    fn get(&self, partition: &str) -> Result<Option<String>> {
+        Ok(self.checkpoints.get(partition).cloned())
+    }
+
+    fn set(&mut self, partition: &str, checkpoint: &str) -> Result<()> {
+        self.checkpoints
+            .insert(partition.to_string(), checkpoint.to_string());
+        Ok(())
+    }
+}
+
+struct SledStore {
+    db: sled::Db,
+    namespace: String,
+}
+
+impl SledStore {
+    fn key(&self, partition: &str) -> String {
+        format!("{}/{}", self.namespace, partition)
+    }
+}
+
+impl CheckpointStore for SledStore {
+    fn get(&self, partition: &str) -> Result<Option<String>> {
+        match self.db.get(self.key(partition))? {
+            Some(v) => Ok(Some(String::from_utf8(v.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn set(&mut self, partition: &str, checkpoint: &str) -> Result<()> {
+        self.db.insert(self.key(partition), checkpoint.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Opens the store, the partitions of a sled store are keyed within
+/// `namespace`, so several streams can share a database
+pub(crate) fn store(
+    config: &CheckpointConfig,
+    namespace: &str,
+) -> Result<Box<dyn CheckpointStore>> {
+    Ok(match config {
+        CheckpointConfig::Memory => Box::new(MemoryStore::default()),
+        CheckpointConfig::Sled { dir } => Box::new(SledStore {
+            db: sled::open(dir)?,
+            namespace: namespace.to_string(),
+        }),
+    })
+}
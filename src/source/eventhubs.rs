@@ -0,0 +1,435 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! Azure Event Hubs onramp
+//!
+//! Receives the events of all partitions of an event hub, or the configured
+//! `partitions`, for a consumer group over AMQP 1.0. Authentication uses
+//! the shared access key of the `connection_string`.
+//!
+//! Once an event is acked its sequence number is checkpointed for its
+//! partition, receiving resumes after the checkpoint when the onramp is
+//! restarted. If an event fails its partition is received again from that
+//! event. Checkpoints are kept like for the `kinesis` onramp:
+//!
+//! ```yaml
+//! checkpoint:
+//!   store: sled
+//!   dir: /var/lib/tremor/eventhubs
+//! ```
+//!
+//! The event metadata is available as `$eventhubs`, with `partition`,
+//! `offset`, `sequence_number`, `enqueued_time`, in nanoseconds since
+//! epoch, `partition_key` and the application `properties`.
+
+use crate::connectors::azure::amqp::{Event, Message};
+use crate::connectors::azure::eventhubs::{self, Client, ConnectionString};
+use crate::source::checkpoint::{self, CheckpointConfig, CheckpointStore};
+use crate::source::prelude::*;
+use halfbrown::HashMap;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// how long a pull waits for events to arrive
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+/// how long to wait before attaching a partition again that was detached
+const REATTACH_DELAY: Duration = Duration::from_secs(5);
+/// how long to wait for the partitions of the event hub
+const MANAGEMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where to start receiving partitions without a checkpoint
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartPosition {
+    /// the oldest event retained
+    Earliest,
+    /// events arriving from now on
+    Latest,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// connection string of a shared access policy of the namespace or the
+    /// event hub
+    pub connection_string: String,
+    /// name of the event hub, defaults to the `EntityPath` of the connection
+    /// string
+    #[serde(default)]
+    pub event_hub: Option<String>,
+    /// consumer group to receive as
+    #[serde(default = "d_consumer_group")]
+    pub consumer_group: String,
+    /// partitions to receive, all partitions of the event hub by default
+    #[serde(default)]
+    pub partitions: Option<Vec<String>>,
+    /// where to start receiving partitions without a checkpoint
+    #[serde(default = "d_start_position")]
+    pub start_position: StartPosition,
+    /// where checkpoints are kept
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+    /// events received ahead of being pulled, per partition
+    #[serde(default = "d_prefetch")]
+    pub prefetch: u32,
+}
+
+fn d_consumer_group() -> String {
+    "$Default".to_string()
+}
+
+fn d_start_position() -> StartPosition {
+    StartPosition::Earliest
+}
+
+fn d_prefetch() -> u32 {
+    300
+}
+
+impl ConfigImpl for Config {}
+
+pub struct EventHubs {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for EventHubs {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for eventhubs onramp".into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for EventHubs {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+/// The receiving link of a partition
+#[derive(Debug, Default)]
+struct Receiver {
+    handle: Option<u32>,
+    /// sequence number of a failed event to receive again from
+    rewind: Option<i64>,
+    /// when the partition is attached again after it was detached
+    attach_at: Option<Instant>,
+}
+
+pub struct Int {
+    uid: u64,
+    onramp_id: TremorUrl,
+    config: Config,
+    connection_string: ConnectionString,
+    event_hub: String,
+    origin_uri: EventOriginUri,
+    client: Option<Client>,
+    store: Box<dyn CheckpointStore>,
+    /// sequence numbers of the last acked event by partition
+    checkpoints: HashMap<String, i64>,
+    receivers: BTreeMap<String, Receiver>,
+    /// partitions by the handle of their link
+    partitions: HashMap<u32, String>,
+    /// received events not pulled yet
+    received: VecDeque<(String, Message)>,
+    /// partition and sequence number by the id of their event
+    in_flight: HashMap<u64, (String, i64)>,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EventHubs")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        let config = config.clone();
+        let connection_string = ConnectionString::parse(&config.connection_string)?;
+        let event_hub = config
+            .event_hub
+            .clone()
+            .or_else(|| connection_string.entity_path.clone())
+            .ok_or("The eventhubs onramp requires an event_hub")?;
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-eventhubs".to_string(),
+            host: connection_string.host.clone(),
+            port: None,
+            path: vec![event_hub.clone(), config.consumer_group.clone()],
+        };
+        let store = checkpoint::store(
+            &config.checkpoint,
+            &format!(
+                "{}/{}/{}",
+                connection_string.host, event_hub, config.consumer_group
+            ),
+        )?;
+        Ok(Self {
+            uid,
+            onramp_id,
+            config,
+            connection_string,
+            event_hub,
+            origin_uri,
+            client: None,
+            store,
+            checkpoints: HashMap::new(),
+            receivers: BTreeMap::new(),
+            partitions: HashMap::new(),
+            received: VecDeque::new(),
+            in_flight: HashMap::new(),
+        })
+    }
+
+    /// the sequence number checkpointed for the partition
+    fn checkpoint(&mut self, partition: &str) -> Result<Option<i64>> {
+        if let Some(checkpoint) = self.checkpoints.get(partition) {
+            return Ok(Some(*checkpoint));
+        }
+        let checkpoint = match self.store.get(partition)? {
+            Some(c) => Some(c.parse::<i64>().map_err(|e| {
+                Error::from(format!(
+                    "Invalid checkpoint of partition {}: {}",
+                    partition, e
+                ))
+            })?),
+            None => None,
+        };
+        if let Some(c) = checkpoint {
+            self.checkpoints.insert(partition.to_string(), c);
+        }
+        Ok(checkpoint)
+    }
+
+    /// attaches the link of a partition, from a failed event, after its
+    /// checkpoint or from the start position
+    async fn attach(&mut self, partition: &str) -> Result<()> {
+        let rewind = self.receivers.get(partition).and_then(|r| r.rewind);
+        let filter = match (rewind, self.checkpoint(partition)?) {
+            (Some(sequence_number), _) => eventhubs::from_sequence_number(sequence_number, true),
+            (None, Some(sequence_number)) => {
+                eventhubs::from_sequence_number(sequence_number, false)
+            }
+            (None, None) => eventhubs::from_offset(match self.config.start_position {
+                StartPosition::Earliest => "-1",
+                StartPosition::Latest => "@latest",
+            }),
+        };
+        let client = self.client.as_mut().ok_or("Not connected")?;
+        let address =
+            eventhubs::partition_address(&self.event_hub, &self.config.consumer_group, partition);
+        let handle = client
+            .attach_receiver(
+                &format!("tremor-{}-{}-{}", hostname(), self.uid, partition),
+                &address,
+                &format!("tremor-{}", self.uid),
+                Some(filter),
+                self.config.prefetch,
+            )
+            .await?;
+        self.partitions.insert(handle, partition.to_string());
+        let receiver = self.receivers.entry(partition.to_string()).or_default();
+        receiver.handle = Some(handle);
+        receiver.rewind = None;
+        receiver.attach_at = None;
+        Ok(())
+    }
+
+    /// sends heartbeats and attaches partitions to rewind or detached ones
+    async fn maintain(&mut self) -> Result<()> {
+        if let Some(client) = self.client.as_mut() {
+            client.keep_alive().await?;
+        }
+        let now = Instant::now();
+        let pending: Vec<(String, Option<u32>)> = self
+            .receivers
+            .iter()
+            .filter(|(_, r)| r.rewind.is_some() || r.attach_at.map_or(false, |at| at <= now))
+            .map(|(p, r)| (p.clone(), r.handle))
+            .collect();
+        for (partition, handle) in pending {
+            if let Some(handle) = handle {
+                self.partitions.remove(&handle);
+                if let Some(client) = self.client.as_mut() {
+                    client.detach(handle).await?;
+                }
+            }
+            self.attach(&partition).await?;
+        }
+        Ok(())
+    }
+
+    fn on_detached(&mut self, handle: u32, error: Option<String>) {
+        if let Some(partition) = self.partitions.remove(&handle) {
+            warn!(
+                "[Source::{}] partition {} was detached: {}",
+                self.onramp_id,
+                partition,
+                error.unwrap_or_default()
+            );
+            if let Some(receiver) = self.receivers.get_mut(&partition) {
+                receiver.handle = None;
+                receiver.attach_at = Some(Instant::now() + REATTACH_DELAY);
+            }
+        }
+    }
+
+    fn disconnect(&mut self) -> SourceReply {
+        self.client = None;
+        self.partitions.clear();
+        // events received ahead are received again after the checkpoint
+        self.received.clear();
+        for receiver in self.receivers.values_mut() {
+            receiver.handle = None;
+        }
+        SourceReply::StateChange(SourceState::Disconnected)
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        if self.client.is_none() {
+            return Ok(SourceReply::StateChange(SourceState::Disconnected));
+        }
+        if let Err(e) = self.maintain().await {
+            error!("[Source::{}] attaching failed: {}", self.onramp_id, e);
+            return Ok(self.disconnect());
+        }
+        if self.received.is_empty() {
+            let client = self.client.as_mut().ok_or("Not connected")?;
+            match async_std::future::timeout(POLL_TIMEOUT, client.next()).await {
+                Ok(Ok(Event::Message { handle, message })) => {
+                    if let Some(partition) = self.partitions.get(&handle) {
+                        self.received.push_back((partition.clone(), message));
+                    }
+                }
+                Ok(Ok(Event::Detached { handle, error })) => self.on_detached(handle, error),
+                Ok(Ok(_)) => (),
+                Ok(Err(e)) => {
+                    error!("[Source::{}] receiving failed: {}", self.onramp_id, e);
+                    return Ok(self.disconnect());
+                }
+                // nothing arrived
+                Err(_) => (),
+            }
+        }
+        if let Some((partition, mut message)) = self.received.pop_front() {
+            let mut meta = Value::object_with_capacity(1);
+            meta.insert("eventhubs", eventhubs::message_meta(&partition, &message))?;
+            if let Some(sequence_number) = eventhubs::sequence_number(&message) {
+                self.in_flight.insert(id, (partition, sequence_number));
+            }
+            Ok(SourceReply::Data {
+                origin_uri: self.origin_uri.clone(),
+                data: std::mem::take(&mut message.body),
+                meta: Some(meta),
+                codec_override: None,
+                stream: 0,
+            })
+        } else {
+            Ok(SourceReply::Empty(0))
+        }
+    }
+
+    async fn on_empty_event(&mut self, id: u64, _stream: usize) -> Result<()> {
+        // nothing in flight downstream to wait for
+        self.ack(id);
+        Ok(())
+    }
+
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        let mut client = eventhubs::connect(
+            &self.connection_string,
+            &format!("tremor-{}-{}", hostname(), self.uid),
+        )
+        .await?;
+        let partitions = match &self.config.partitions {
+            Some(partitions) => partitions.clone(),
+            None => {
+                eventhubs::partition_ids(&mut client, &self.event_hub, MANAGEMENT_TIMEOUT).await?
+            }
+        };
+        self.client = Some(client);
+        for partition in partitions {
+            self.attach(&partition).await?;
+        }
+        Ok(SourceState::Connected)
+    }
+
+    async fn terminate(&mut self) {
+        if let Some(mut client) = self.client.take() {
+            if let Err(e) = client.close().await {
+                warn!("[Source::{}] closing failed: {}", self.onramp_id, e);
+            }
+        }
+    }
+
+    fn ack(&mut self, id: u64) {
+        if let Some((partition, sequence_number)) = self.in_flight.remove(&id) {
+            let newer = self
+                .checkpoints
+                .get(&partition)
+                .map_or(true, |c| sequence_number > *c);
+            if newer {
+                if let Err(e) = self.store.set(&partition, &sequence_number.to_string()) {
+                    error!(
+                        "[Source::{}] failed to checkpoint partition {}: {}",
+                        self.onramp_id, partition, e
+                    );
+                }
+                self.checkpoints.insert(partition, sequence_number);
+            }
+        }
+    }
+
+    fn fail(&mut self, id: u64) {
+        if let Some((partition, sequence_number)) = self.in_flight.remove(&id) {
+            // receive the partition again from the failed event, the events
+            // after it are received again as well
+            self.received.retain(|(p, _)| p != &partition);
+            self.in_flight
+                .retain(|_, (p, n)| p != &partition || *n < sequence_number);
+            if let Some(receiver) = self.receivers.get_mut(&partition) {
+                let rewind = receiver
+                    .rewind
+                    .map_or(sequence_number, |r| r.min(sequence_number));
+                receiver.rewind = Some(rewind);
+            }
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,253 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Flow Onramp
+//!
+//! Collects NetFlow v5, v9, IPFIX and sFlow v5 datagrams on `host`:`port`,
+//! the format is detected per datagram. Each flow record and sFlow sample
+//! becomes an event, see the [netflow](../netflow/index.html) and
+//! [sflow](../sflow/index.html) modules for their layout:
+//!
+//! ```json
+//! {
+//!   "format": "ipfix",
+//!   "exporter": "10.0.0.1",
+//!   "domain": 0,
+//!   "export_time": 1620000000,
+//!   "sequence": 1234,
+//!   "template_id": 256,
+//!   "flow": {
+//!     "sourceIPv4Address": "192.168.0.1",
+//!     "destinationIPv4Address": "192.168.0.2",
+//!     "destinationTransportPort": 443,
+//!     "octetDeltaCount": 1024
+//!   }
+//! }
+//! ```
+//!
+//! Datagrams that can't be decoded, and data sets of v9 and IPFIX templates
+//! not received yet, are reported on the `diagnostics` port.
+//!
+//! ```yaml
+//! host: 0.0.0.0
+//! port: 2055
+//! recv_buffer_size: 8388608
+//! ```
+//!
+//! The exporter is available as `$udp.host` and `$udp.port`.
+
+use crate::source::netflow;
+use crate::source::prelude::*;
+use crate::source::sflow;
+use async_std::net::{ToSocketAddrs, UdpSocket};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// The port to listen on.
+    #[serde(default = "d_port")]
+    pub port: u16,
+    pub host: String,
+    /// receive buffer of the socket in bytes, the system default if unset
+    #[serde(default)]
+    pub recv_buffer_size: Option<usize>,
+}
+
+fn d_port() -> u16 {
+    2055
+}
+
+impl ConfigImpl for Config {}
+
+pub struct Flow {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for Flow {
+    fn from_config(onramp_id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(Box::new(Self {
+                config,
+                onramp_id: onramp_id.clone(),
+            }))
+        } else {
+            Err("Missing config for flow onramp".into())
+        }
+    }
+}
+
+struct Int {
+    config: Config,
+    socket: Option<UdpSocket>,
+    onramp_id: TremorUrl,
+    origin_uri: EventOriginUri,
+    netflow: netflow::Decoder,
+    /// replies of the last datagram not sent yet
+    replies: VecDeque<SourceReply>,
+}
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Flow")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Self {
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-flow".to_string(),
+            host: String::default(),
+            port: None,
+            path: vec![config.port.to_string()],
+        };
+        Self {
+            config: config.clone(),
+            socket: None,
+            onramp_id,
+            origin_uri,
+            netflow: netflow::Decoder::default(),
+            replies: VecDeque::new(),
+        }
+    }
+
+    async fn bind(&self) -> Result<UdpSocket> {
+        let addr = (self.config.host.as_str(), self.config.port)
+            .to_socket_addrs()
+            .await?
+            .next()
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "{}:{} doesn't resolve to an address",
+                    self.config.host, self.config.port
+                ))
+            })?;
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        if let Some(size) = self.config.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        socket.bind(&addr.into())?;
+        info!(
+            "[Source::{}] listening on {}:{}",
+            self.onramp_id, self.config.host, self.config.port
+        );
+        Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+    }
+
+    /// decodes a datagram into the replies of its records
+    fn decode(&mut self, data: &[u8], peer: SocketAddr) {
+        let mut origin_uri = self.origin_uri.clone();
+        origin_uri.host = peer.ip().to_string();
+        origin_uri.port = Some(peer.port());
+        let exporter: IpAddr = peer.ip();
+        let decoded = if sflow::is_sflow(data) {
+            sflow::decode(exporter, data).map(|samples| (samples, 0))
+        } else {
+            self.netflow
+                .decode(exporter, data)
+                .map(|packet| (packet.records, packet.missing_templates))
+        };
+        let diagnostics = |error: &str| SourceReply::Diagnostics {
+            origin_uri: origin_uri.clone(),
+            data: literal!({
+                "dropped": {
+                    "host": peer.ip().to_string(),
+                    "port": peer.port(),
+                    "error": error.to_string()
+                }
+            }),
+        };
+        match decoded {
+            Ok((records, missing_templates)) => {
+                if missing_templates > 0 {
+                    self.replies.push_back(diagnostics(&format!(
+                        "{} data sets without a template",
+                        missing_templates
+                    )));
+                }
+                for record in records {
+                    let meta = literal!({
+                        "udp": {
+                            "host": peer.ip().to_string(),
+                            "port": peer.port()
+                        }
+                    });
+                    self.replies.push_back(SourceReply::Structured {
+                        origin_uri: origin_uri.clone(),
+                        data: (record, meta).into(),
+                    });
+                }
+            }
+            Err(e) => {
+                debug!(
+                    "[Source::{}] Dropped datagram from {}: {}",
+                    self.onramp_id, peer, e
+                );
+                self.replies.push_back(diagnostics(e));
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        if let Some(reply) = self.replies.pop_front() {
+            return Ok(reply);
+        }
+        let mut buf = [0; 65535];
+        if let Some(socket) = self.socket.as_mut() {
+            match socket.recv_from(&mut buf).await {
+                Ok((n, peer)) => {
+                    // ALLOW: we get n from recv
+                    self.decode(&buf[0..n], peer);
+                    Ok(self.replies.pop_front().unwrap_or(SourceReply::Empty(0)))
+                }
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        Ok(SourceReply::Empty(1))
+                    } else {
+                        Err(e.into())
+                    }
+                }
+            }
+        } else {
+            self.socket = Some(self.bind().await?);
+            Ok(SourceReply::StateChange(SourceState::Connected))
+        }
+    }
+    async fn init(&mut self) -> Result<SourceState> {
+        self.socket = Some(self.bind().await?);
+        Ok(SourceState::Connected)
+    }
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for Flow {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config);
+        SourceManager::start(source, config).await
+    }
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! gRPC-web framing of the `rest` onramp
+//!
+//! With `grpc_web` enabled, requests with an `application/grpc-web` or
+//! `application/grpc-web-text` (base64) content type carry their message in a
+//! length prefixed data frame. The message is unwrapped and decoded with the
+//! codec of the route or onramp, e.g. `protobuf`. Responses are wrapped into
+//! a data frame followed by a trailer frame carrying the `grpc-status`,
+//! derived from the HTTP status of the response.
+
+use crate::errors::Result;
+use std::convert::TryFrom;
+
+const DATA: u8 = 0x00;
+const TRAILER: u8 = 0x80;
+
+/// gRPC status codes
+pub(crate) const OK: u32 = 0;
+pub(crate) const INVALID_ARGUMENT: u32 = 3;
+
+/// How the frames are transferred
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Mode {
+    /// as is
+    Binary,
+    /// base64 encoded
+    Text,
+}
+
+impl Mode {
+    /// the mode of a content type, `None` if it isn't gRPC-web
+    pub(crate) fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        if essence.starts_with("application/grpc-web-text") {
+            Some(Self::Text)
+        } else if essence.starts_with("application/grpc-web") {
+            Some(Self::Binary)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Self::Binary => "application/grpc-web+proto",
+            Self::Text => "application/grpc-web-text+proto",
+        }
+    }
+}
+
+/// the messages of the data frames of a request body
+pub(crate) fn decode(body: &[u8], mode: Mode) -> Result<Vec<Vec<u8>>> {
+    let decoded;
+    let mut body = match mode {
+        Mode::Binary => body,
+        Mode::Text => {
+            decoded = base64::decode(body)?;
+            decoded.as_slice()
+        }
+    };
+    let mut messages = Vec::new();
+    while !body.is_empty() {
+        if body.len() < 5 {
+            return Err("Truncated gRPC-web frame header".into());
+        }
+        let flags = body[0];
+        let len = usize::try_from(u32::from_be_bytes([body[1], body[2], body[3], body[4]]))?;
+        let end = 5 + len;
+        if body.len() < end {
+            return Err("Truncated gRPC-web frame".into());
+        }
+        if flags & TRAILER == 0 {
+            messages.push(body[5..end].to_vec());
+        }
+        body = &body[end..];
+    }
+    Ok(messages)
+}
+
+fn frame(out: &mut Vec<u8>, flags: u8, payload: &[u8]) -> Result<()> {
+    out.push(flags);
+    out.extend_from_slice(&u32::try_from(payload.len())?.to_be_bytes());
+    out.extend_from_slice(payload);
+    Ok(())
+}
+
+/// a response body with the message, if any, and the trailers
+pub(crate) fn encode(
+    message: Option<&[u8]>,
+    status: u32,
+    reason: &str,
+    mode: Mode,
+) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    if let Some(message) = message {
+        frame(&mut out, DATA, message)?;
+    }
+    let reason: String = reason.chars().filter(|c| !c.is_control()).collect();
+    let trailers = format!("grpc-status:{}\r\ngrpc-message:{}\r\n", status, reason);
+    frame(&mut out, TRAILER, trailers.as_bytes())?;
+    Ok(match mode {
+        Mode::Binary => out,
+        Mode::Text => base64::encode(out).into_bytes(),
+    })
+}
+
+/// the gRPC status of an HTTP response status
+pub(crate) fn status(http_status: u16) -> u32 {
+    match http_status {
+        200..=299 => OK,
+        400 => INVALID_ARGUMENT,
+        401 => 16,
+        403 => 7,
+        404 => 12,
+        429 => 8,
+        503 => 14,
+        504 => 4,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        for mode in &[Mode::Binary, Mode::Text] {
+            let body = encode(Some(&b"snot"[..]), OK, "", *mode)?;
+            // the trailer frame isn't a message
+            assert_eq!(vec![b"snot".to_vec()], decode(&body, *mode)?);
+        }
+        let body = encode(None, status(429), "slow\r\ndown", Mode::Binary)?;
+        assert_eq!(TRAILER, body[0]);
+        assert_eq!(
+            b"grpc-status:8\r\ngrpc-message:slowdown\r\n".to_vec(),
+            body[5..].to_vec()
+        );
+        assert!(decode(&[0, 0, 0, 0, 9, 1], Mode::Binary).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(
+            Some(Mode::Binary),
+            Mode::from_content_type("application/grpc-web+proto")
+        );
+        assert_eq!(
+            Some(Mode::Text),
+            Mode::from_content_type("application/grpc-web-text; charset=utf-8")
+        );
+        assert_eq!(None, Mode::from_content_type("application/json"));
+    }
+}
@@ -0,0 +1,373 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # HTTP Poller Onramp
+//!
+//! The `http-poller` onramp sends a request to `url` every `interval`
+//! milliseconds and emits the response bodies, decoded with the configured
+//! codec. The response status and headers and the requested url are put
+//! into `$http_poller`.
+//!
+//! With `cursor` the responses are paginated: the value at the dotted
+//! `cursor.path` in the JSON response body is added as the `cursor.param`
+//! query parameter of the next request, which is sent right away, until a
+//! response has no cursor or `max_pages` are fetched. The `body` and
+//! `headers` are templates, `{cursor}` is replaced by the current cursor
+//! and `{page}` by the page number, starting at 0. With `cursor.resume`
+//! the next poll continues from the last cursor instead of starting over.
+//!
+//! Failed requests and error responses are logged and end the poll, the
+//! next one is tried after `interval` as usual.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::sink::rest::{fill, render};
+use crate::source::prelude::*;
+use async_channel::{Sender, TryRecvError};
+use async_std::task::JoinHandle;
+use halfbrown::HashMap;
+use http_types::Method;
+use std::str::FromStr;
+use std::time::Duration;
+use surf::{Body, RequestBuilder};
+use tremor_common::time::nanotime;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// The url to poll
+    pub url: String,
+    /// HTTP method (default: GET)
+    #[serde(default = "d_method")]
+    pub method: String,
+    /// Header templates
+    #[serde(default = "Default::default")]
+    pub headers: HashMap<String, String>,
+    /// Request body template, sent as JSON
+    #[serde(default = "Default::default")]
+    pub body: Option<YamlValue>,
+    /// Milliseconds between polls
+    pub interval: u64,
+    /// Pagination of the responses
+    #[serde(default = "Default::default")]
+    pub cursor: Option<Cursor>,
+    /// Maximum number of pages fetched per poll
+    #[serde(default = "d_max_pages")]
+    pub max_pages: u64,
+}
+
+/// Pagination cursor
+#[derive(Deserialize, Debug, Clone)]
+pub struct Cursor {
+    /// Dotted path of the cursor in the response body
+    pub path: String,
+    /// Query parameter the cursor is sent in, if any
+    #[serde(default = "Default::default")]
+    pub param: Option<String>,
+    /// Continue the next poll from the last cursor
+    #[serde(default = "Default::default")]
+    pub resume: bool,
+}
+
+fn d_method() -> String {
+    "GET".to_string()
+}
+
+fn d_max_pages() -> u64 {
+    100
+}
+
+impl ConfigImpl for Config {}
+
+pub struct HttpPoller {
+    pub config: Config,
+    method: Method,
+    url: url::Url,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for HttpPoller {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            let method = Method::from_str(&config.method)
+                .map_err(|e| Error::from(format!("Invalid method {}: {}", config.method, e)))?;
+            let url = url::Url::parse(&config.url)?;
+            if config.interval == 0 {
+                return Err("The http-poller `interval` must not be 0".into());
+            }
+            Ok(Box::new(Self {
+                config,
+                method,
+                url,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for http-poller onramp".into())
+        }
+    }
+}
+
+/// Polls the url and sends the responses on
+struct Poller {
+    source_id: TremorUrl,
+    config: Config,
+    method: Method,
+    url: url::Url,
+    origin_uri: EventOriginUri,
+    cursor: Option<String>,
+    tx: Sender<SourceReply>,
+}
+
+/// looks up a dotted path
+fn get_path<'value, 'event>(
+    mut value: &'value Value<'event>,
+    path: &str,
+) -> Option<&'value Value<'event>> {
+    for segment in path.split('.') {
+        value = value.get(segment)?;
+    }
+    Some(value)
+}
+
+/// the cursor in a response body, empty strings and `null` end the pages
+fn extract_cursor(body: &mut [u8], path: &str) -> Option<String> {
+    let body = tremor_value::parse_to_value(body).ok()?;
+    let cursor = get_path(&body, path).filter(|v| !v.is_null())?;
+    Some(
+        cursor
+            .as_str()
+            .map_or_else(|| cursor.encode(), ToString::to_string),
+    )
+    .filter(|c| !c.is_empty())
+}
+
+impl Poller {
+    fn request(&self, page: u64) -> Result<RequestBuilder> {
+        let context = literal!({
+            "cursor": self.cursor.clone(),
+            "page": page
+        });
+        let meta = Value::null();
+        let mut url = self.url.clone();
+        if let (Some(cursor), Some(param)) = (
+            &self.cursor,
+            self.config.cursor.as_ref().and_then(|c| c.param.as_ref()),
+        ) {
+            url.query_pairs_mut().append_pair(param, cursor);
+        }
+        let mut request = RequestBuilder::new(self.method, url);
+        for (name, value) in &self.config.headers {
+            request = request.header(
+                name.as_str(),
+                render(value, &context, &meta, str::to_string)?.as_str(),
+            );
+        }
+        if let Some(body) = &self.config.body {
+            let body = fill(body, &context, &meta)?;
+            request = request
+                .header("Content-Type", "application/json")
+                .body(Body::from_bytes(body.encode().into_bytes()));
+        }
+        Ok(request)
+    }
+
+    /// fetches the pages of a poll
+    async fn poll(&mut self) -> Result<()> {
+        if !self.config.cursor.as_ref().map_or(false, |c| c.resume) {
+            self.cursor = None;
+        }
+        for page in 0..self.config.max_pages {
+            let request = self.request(page)?;
+            let mut response = request.await?;
+            let status: u16 = response.status().into();
+            if status >= 400 {
+                return Err(format!("{} responded with status {}", self.url, status).into());
+            }
+            let mut headers = Value::object_with_capacity(8);
+            for (name, values) in response.iter() {
+                let values: Value = values
+                    .iter()
+                    .map(ToString::to_string)
+                    .map(Value::from)
+                    .collect();
+                headers.insert(name.to_string(), values)?;
+            }
+            let data = response.body_bytes().await?;
+            let mut meta = Value::object_with_capacity(1);
+            meta.insert(
+                "http_poller",
+                literal!({
+                    "url": self.url.to_string(),
+                    "status": status,
+                    "headers": headers,
+                    "page": page
+                }),
+            )?;
+            let next = self
+                .config
+                .cursor
+                .as_ref()
+                .and_then(|c| extract_cursor(&mut data.clone(), &c.path));
+            self.tx
+                .send(SourceReply::Data {
+                    origin_uri: self.origin_uri.clone(),
+                    data,
+                    meta: Some(meta),
+                    codec_override: None,
+                    stream: 0,
+                })
+                .await?;
+            if next.is_none() {
+                return Ok(());
+            }
+            self.cursor = next;
+        }
+        warn!(
+            "[Source::{}] Stopped paginating {} after {} pages",
+            self.source_id, self.url, self.config.max_pages
+        );
+        Ok(())
+    }
+
+    async fn run(mut self) {
+        let interval = self.config.interval * 1_000_000;
+        loop {
+            let start = nanotime();
+            if let Err(e) = self.poll().await {
+                if self.tx.is_closed() {
+                    return;
+                }
+                error!("[Source::{}] Failed to poll: {}", self.source_id, e);
+            }
+            let elapsed = nanotime().saturating_sub(start);
+            task::sleep(Duration::from_nanos(interval.saturating_sub(elapsed))).await;
+        }
+    }
+}
+
+pub struct Int {
+    uid: u64,
+    config: Config,
+    method: Method,
+    url: url::Url,
+    onramp_id: TremorUrl,
+    rx: Option<Receiver<SourceReply>>,
+    poller: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HttpPoller({})", self.url)
+    }
+}
+
+#[async_trait::async_trait()]
+impl Source for Int {
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        self.rx.as_ref().map_or_else(
+            || Ok(SourceReply::StateChange(SourceState::Disconnected)),
+            |rx| match rx.try_recv() {
+                Ok(r) => Ok(r),
+                Err(TryRecvError::Empty) => Ok(SourceReply::Empty(10)),
+                Err(TryRecvError::Closed) => {
+                    Ok(SourceReply::StateChange(SourceState::Disconnected))
+                }
+            },
+        )
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        let (tx, rx) = bounded(crate::QSIZE);
+        let origin_uri = EventOriginUri {
+            uid: self.uid,
+            scheme: "tremor-http-poller".to_string(),
+            host: self.url.host_str().unwrap_or_default().to_string(),
+            port: self.url.port_or_known_default(),
+            path: self.url.path_segments().map_or_else(Vec::new, |segments| {
+                segments.map(ToString::to_string).collect()
+            }),
+        };
+        let poller = Poller {
+            source_id: self.onramp_id.clone(),
+            config: self.config.clone(),
+            method: self.method,
+            url: self.url.clone(),
+            origin_uri,
+            cursor: None,
+            tx,
+        };
+        self.poller = Some(task::spawn(poller.run()));
+        self.rx = Some(rx);
+        Ok(SourceState::Connected)
+    }
+
+    async fn terminate(&mut self) {
+        if let Some(poller) = self.poller.take() {
+            poller.cancel().await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for HttpPoller {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int {
+            uid: config.onramp_uid,
+            config: self.config.clone(),
+            method: self.method,
+            url: self.url.clone(),
+            onramp_id: self.onramp_id.clone(),
+            rx: None,
+            poller: None,
+        };
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_extraction() {
+        let mut body = br#"{"meta": {"next": "abc"}, "data": []}"#.to_vec();
+        assert_eq!(
+            Some("abc".to_string()),
+            extract_cursor(&mut body, "meta.next")
+        );
+        let mut body = br#"{"meta": {"next": 42}}"#.to_vec();
+        assert_eq!(
+            Some("42".to_string()),
+            extract_cursor(&mut body, "meta.next")
+        );
+        let mut body = br#"{"meta": {"next": null}}"#.to_vec();
+        assert_eq!(None, extract_cursor(&mut body, "meta.next"));
+        let mut body = br#"{"meta": {"next": ""}}"#.to_vec();
+        assert_eq!(None, extract_cursor(&mut body, "meta.next"));
+        let mut body = b"not json".to_vec();
+        assert_eq!(None, extract_cursor(&mut body, "meta.next"));
+    }
+}
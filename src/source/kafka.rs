@@ -13,7 +13,9 @@
 // limitations under the License.
 #![cfg(not(tarpaulin_include))]
 
+use crate::codec::{self, Codec};
 use crate::errors::Result;
+use crate::ramp::kafka::{Security, TokenRefresh};
 use crate::source::prelude::*;
 
 //NOTE: This is required for StreamHandlers stream
@@ -29,14 +31,17 @@ use rdkafka::{
         CommitMode, Consumer, ConsumerContext, Rebalance,
     },
     error::{KafkaError, KafkaResult},
-    message::{BorrowedMessage, Headers},
+    message::{BorrowedMessage, Headers, Timestamp},
+    statistics::Statistics,
     util::AsyncRuntime,
     Message, Offset, TopicPartitionList,
 };
 use std::collections::{BTreeMap, HashMap as StdMap};
+use std::convert::TryFrom;
 use std::future::Future;
-use std::mem::{self, transmute};
+use std::mem::transmute;
 use std::time::{Duration, Instant};
+use tremor_common::time::nanotime;
 
 pub struct SmolRuntime;
 
@@ -66,8 +71,20 @@ pub struct Config {
     /// List of bootstrap brokers
     pub brokers: Vec<String>,
 
+    /// When consumer offsets are committed:
+    ///
+    /// * `auto` - periodically by the consumer, whether or not the events
+    ///   made it through the pipelines and offramps
+    /// * `ack` - per partition, once the events of all messages up to an
+    ///   offset are acknowledged, for at-least-once delivery
+    ///
+    /// `auto` is turned into `ack` if `enable.auto.commit` is set to false
+    /// in `rdkafka_options`
+    #[serde(default = "default_commit")]
+    pub commit: Commit,
+
     /// This config determines the behaviour of this source
-    /// if `enable.auto.commit` is set to false in `rdkafka_options`:
+    /// if offsets are committed on acknowledgement:
     ///
     /// if set to true this source will reset the consumer offset to a
     /// failed message, so it will effectively retry those messages.
@@ -91,6 +108,48 @@ pub struct Config {
     /// * `auto.commit.interval.ms"` - `"5000"`
     /// * `enable.auto.offset.store` - `"true"`
     pub rdkafka_options: Option<HashMap<String, String>>,
+
+    /// Typed SASL and TLS settings, they can't be combined with the same
+    /// settings in `rdkafka_options`
+    #[serde(default)]
+    pub security: Option<Security>,
+
+    /// Codec decoding message keys into `$kafka.key`, keys are kept as
+    /// bytes if it isn't set or decoding fails
+    #[serde(default)]
+    pub key_codec: Option<String>,
+
+    /// Codec decoding header values into `$kafka.headers`, values are kept
+    /// as bytes if it isn't set or decoding fails
+    #[serde(default)]
+    pub header_codec: Option<String>,
+
+    /// Milliseconds between consumer lag reports, `0` disables them. Sets
+    /// `statistics.interval.ms` unless it is given in `rdkafka_options`
+    #[serde(default = "default_lag_interval")]
+    pub lag_interval: u64,
+
+    /// Lag of a partition above which lag reports are alerts
+    #[serde(default)]
+    pub lag_alert: Option<u64>,
+}
+
+/// When consumer offsets are committed
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Commit {
+    /// periodically by the consumer
+    Auto,
+    /// once the events are acknowledged
+    Ack,
+}
+
+fn default_commit() -> Commit {
+    Commit::Auto
+}
+
+fn default_lag_interval() -> u64 {
+    10_000
 }
 
 /// defaults to `true` to keep backwards compatibility
@@ -100,25 +159,210 @@ fn default_retry_failed_events() -> bool {
 
 impl ConfigImpl for Config {}
 
+impl Config {
+    /// Validates the config ahead of starting the onramp
+    pub(crate) fn validate(&self) -> Result<()> {
+        // fail on unknown codecs before the onramp is started
+        for name in self.key_codec.iter().chain(self.header_codec.iter()) {
+            codec::lookup(name)?;
+        }
+        if let Some(security) = &self.security {
+            security.validate(self.rdkafka_options.as_ref())?;
+        }
+        Ok(())
+    }
+}
+
+/// the mime type of a `content-type` header without its parameters
+fn content_type_essence(value: &[u8]) -> Option<String> {
+    let value = std::str::from_utf8(value).ok()?;
+    let essence = value.split(';').next().unwrap_or_default().trim();
+    if essence.is_empty() {
+        None
+    } else {
+        Some(essence.to_lowercase())
+    }
+}
+
+/// Decodes a key or header value, keeping the bytes if there is no codec
+/// or it fails to decode them
+fn decode_bytes(
+    codec: Option<&mut Box<dyn Codec>>,
+    bytes: &[u8],
+    ingest_ns: u64,
+) -> Value<'static> {
+    if let Some(codec) = codec {
+        let mut data = bytes.to_vec();
+        match codec.decode(&mut data, ingest_ns) {
+            Ok(Some(value)) => return value.into_static(),
+            Ok(None) => return Value::null(),
+            Err(_) => (),
+        }
+    }
+    Value::Bytes(Vec::from(bytes).into())
+}
+
+/// The name of the timestamp type and the timestamp in milliseconds
+fn timestamp(timestamp: Timestamp) -> Option<(&'static str, i64)> {
+    match timestamp {
+        Timestamp::NotAvailable => None,
+        Timestamp::CreateTime(t) => Some(("create_time", t)),
+        Timestamp::LogAppendTime(t) => Some(("log_append_time", t)),
+    }
+}
+
+/// The consumer lag of an assigned partition
+#[derive(Debug, Clone, PartialEq)]
+struct PartitionLag {
+    topic: String,
+    partition: i32,
+    /// messages behind the high watermark
+    lag: i64,
+    high_watermark: i64,
+    /// offset of the next message handed to the onramp
+    offset: i64,
+    committed_offset: i64,
+}
+
+/// the lag of the partitions with a known lag, ordered by topic and
+/// partition
+fn partition_lags(statistics: &Statistics) -> Vec<PartitionLag> {
+    let mut lags: Vec<PartitionLag> = statistics
+        .topics
+        .values()
+        .flat_map(|topic| {
+            topic
+                .partitions
+                .values()
+                // -1 is the internal partition for unassigned messages
+                .filter(|p| p.partition >= 0 && p.consumer_lag >= 0)
+                .map(move |p| PartitionLag {
+                    topic: topic.topic.clone(),
+                    partition: p.partition,
+                    lag: p.consumer_lag,
+                    high_watermark: p.hi_offset,
+                    offset: p.app_offset,
+                    committed_offset: p.committed_offset,
+                })
+        })
+        .collect();
+    lags.sort_by(|a, b| (&a.topic, a.partition).cmp(&(&b.topic, b.partition)));
+    lags
+}
+
+/// The diagnostics event of a lag report and whether it is an alert
+fn lag_report(lags: &[PartitionLag], alert: Option<u64>) -> (Value<'static>, bool) {
+    let lagging = |l: &PartitionLag| {
+        alert
+            .zip(u64::try_from(l.lag).ok())
+            .map_or(false, |(alert, lag)| lag > alert)
+    };
+    let partitions: Vec<Value<'static>> = lags
+        .iter()
+        .map(|l| {
+            literal!({
+                "topic": l.topic.clone(),
+                "partition": l.partition,
+                "lag": l.lag,
+                "high_watermark": l.high_watermark,
+                "offset": l.offset,
+                "committed_offset": l.committed_offset,
+                "alert": lagging(l)
+            })
+        })
+        .collect();
+    let alerting = lags.iter().any(lagging);
+    let report = literal!({
+        "consumer_lag": {
+            "lag": lags.iter().map(|l| l.lag).sum::<i64>(),
+            "alert": alerting,
+            "partitions": partitions
+        }
+    });
+    (report, alerting)
+}
+
 pub struct Kafka {
     pub config: Config,
     onramp_id: TremorUrl,
 }
 
-#[derive(Debug)]
-struct MsgOffset {
-    topic: String,
-    partition: i32,
-    offset: Offset,
+/// Offsets of the messages whose events are in flight
+#[derive(Debug, Default)]
+struct Offsets {
+    /// topic, partition and offset of the message by event id
+    in_flight: BTreeMap<u64, ((String, i32), i64)>,
+    /// the in flight offsets of each topic and partition, and if their event
+    /// was acknowledged already
+    partitions: StdMap<(String, i32), BTreeMap<i64, bool>>,
 }
 
-impl<'consumer> From<BorrowedMessage<'consumer>> for MsgOffset {
-    fn from(m: BorrowedMessage) -> Self {
-        Self {
-            topic: m.topic().into(),
-            partition: m.partition(),
-            offset: Offset::Offset(m.offset()),
+impl Offsets {
+    fn sent(&mut self, id: u64, m: &BorrowedMessage) {
+        self.insert(id, (m.topic().to_string(), m.partition()), m.offset());
+    }
+
+    fn insert(&mut self, id: u64, tp: (String, i32), offset: i64) {
+        self.partitions
+            .entry(tp.clone())
+            .or_default()
+            .insert(offset, false);
+        self.in_flight.insert(id, (tp, offset));
+    }
+
+    /// acknowledges the event `id`, returns the offset to commit for its
+    /// topic and partition once all earlier messages of it are acknowledged
+    fn ack(&mut self, id: u64) -> StdMap<(String, i32), Offset> {
+        let mut tm = StdMap::with_capacity(1);
+        let (tp, offset) = if let Some(in_flight) = self.in_flight.remove(&id) {
+            in_flight
+        } else {
+            return tm;
+        };
+        let (committed, empty) = if let Some(partition) = self.partitions.get_mut(&tp) {
+            if let Some(acked) = partition.get_mut(&offset) {
+                *acked = true;
+            }
+            // only the acknowledged prefix can be committed, earlier messages
+            // still in flight would be skipped after a restart otherwise
+            let pending = partition
+                .iter()
+                .find(|(_, acked)| !**acked)
+                .map(|(offset, _)| *offset);
+            let rest = pending.map(|offset| partition.split_off(&offset));
+            let committed = partition.keys().next_back().copied();
+            *partition = rest.unwrap_or_default();
+            (committed, partition.is_empty())
+        } else {
+            (None, false)
+        };
+        if empty {
+            self.partitions.remove(&tp);
+        }
+        if let Some(offset) = committed {
+            // we need to commit the message offset + 1, dont ask
+            // The `KafkaConsumer` javadocs say:
+            //
+            // Note: The committed offset should always be the offset of the next message that your application will read. Thus, when calling commitSync(offsets) you should add one to the offset of the last message processed.
+            //
+            // See: https://kafka.apache.org/10/javadoc/org/apache/kafka/clients/consumer/KafkaConsumer.html
+            //
+            tm.insert(tp, Offset::Offset(offset + 1));
+        }
+        tm
+    }
+
+    /// fails the event `id`, returns the offset of its message to seek back
+    /// to. The events of the later messages of its partition are forgotten,
+    /// they are delivered again after seeking.
+    fn fail(&mut self, id: u64) -> Option<((String, i32), Offset)> {
+        let (failed_tp, failed_offset) = self.in_flight.get(&id).cloned()?;
+        self.in_flight
+            .retain(|_, (tp, offset)| *tp != failed_tp || *offset < failed_offset);
+        if let Some(partition) = self.partitions.get_mut(&failed_tp) {
+            partition.split_off(&failed_offset);
         }
+        Some((failed_tp, Offset::Offset(failed_offset)))
     }
 }
 
@@ -189,7 +433,16 @@ pub struct Int {
     stream: Option<rentals::MessageStream>,
     origin_uri: EventOriginUri,
     auto_commit: bool,
-    messages: BTreeMap<u64, MsgOffset>,
+    offsets: Offsets,
+    key_codec: Option<Box<dyn Codec>>,
+    header_codec: Option<Box<dyn Codec>>,
+    lag_reports: Option<Receiver<Vec<PartitionLag>>>,
+    /// the partition lags of the latest report
+    lags: Vec<PartitionLag>,
+    /// whether the latest report was an alert
+    lag_alerting: bool,
+    /// `OAUTHBEARER` tokens handed to the consumer
+    token_refresh: Option<TokenRefresh>,
 }
 
 impl std::fmt::Debug for Int {
@@ -199,40 +452,28 @@ impl std::fmt::Debug for Int {
 }
 
 impl Int {
-    /// get a map aggregating the highest offsets for each topic and partition
-    /// for which we have messages stored up to and including the id of this message
-    fn get_topic_map_for_id(&mut self, id: u64) -> StdMap<(String, i32), Offset> {
-        let mut split = self.messages.split_off(&(id + 1));
-        mem::swap(&mut split, &mut self.messages);
-        // split now contains all messages up to and including `id`
-        let mut tm = StdMap::with_capacity(split.len());
-        for (
-            _,
-            MsgOffset {
-                topic,
-                partition,
-                offset,
-            },
-        ) in split
-        {
-            if let Offset::Offset(msg_offset) = offset {
-                // we need to commit the message offset + 1, dont ask
-                // The `KafkaConsumer` javadocs say:
-                //
-                // Note: The committed offset should always be the offset of the next message that your application will read. Thus, when calling commitSync(offsets) you should add one to the offset of the last message processed.
-                //
-                // See: https://kafka.apache.org/10/javadoc/org/apache/kafka/clients/consumer/KafkaConsumer.html
-                //
-                let commit_offset = Offset::Offset(msg_offset + 1);
-                let this_offset = tm.entry((topic, partition)).or_insert(commit_offset);
-                if let (Offset::Offset(old), Offset::Offset(new)) = (this_offset, commit_offset) {
-                    *old = (*old).max(new)
-                }
-            }
+    /// keeps the lags of a report for the metrics and turns it into a
+    /// diagnostics event, logging changes of the alert state
+    fn lag_report(&mut self, lags: Vec<PartitionLag>) -> SourceReply {
+        let (data, alerting) = lag_report(&lags, self.config.lag_alert);
+        if alerting && !self.lag_alerting {
+            warn!(
+                "[Source::{}] Consumer lag above {} messages",
+                self.onramp_id,
+                self.config.lag_alert.unwrap_or_default()
+            );
+        } else if !alerting && self.lag_alerting {
+            info!("[Source::{}] Consumer lag recovered", self.onramp_id);
+        }
+        self.lag_alerting = alerting;
+        self.lags = lags;
+        SourceReply::Diagnostics {
+            origin_uri: self.origin_uri.clone(),
+            data,
         }
-        tm
     }
-    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Self {
+
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
         let origin_uri = EventOriginUri {
             uid,
             scheme: "tremor-kafka".to_string(),
@@ -241,21 +482,32 @@ impl Int {
             path: vec![],
         };
 
-        let auto_commit = config
-            .rdkafka_options
-            .as_ref()
-            .and_then(|m| m.get("enable.auto.commit"))
-            .map_or(true, |v| v == "true");
+        let auto_commit = config.commit == Commit::Auto
+            && config
+                .rdkafka_options
+                .as_ref()
+                .and_then(|m| m.get("enable.auto.commit"))
+                .map_or(true, |v| v == "true");
 
-        Self {
+        Ok(Self {
             uid,
             config: config.clone(),
             onramp_id,
             stream: None,
             origin_uri,
             auto_commit,
-            messages: BTreeMap::new(),
-        }
+            offsets: Offsets::default(),
+            key_codec: config.key_codec.as_deref().map(codec::lookup).transpose()?,
+            header_codec: config
+                .header_codec
+                .as_deref()
+                .map(codec::lookup)
+                .transpose()?,
+            lag_reports: None,
+            lags: Vec::new(),
+            lag_alerting: false,
+            token_refresh: config.security.as_ref().and_then(Security::token_refresh),
+        })
     }
 }
 
@@ -263,6 +515,7 @@ impl onramp::Impl for Kafka {
     fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
         if let Some(config) = config {
             let config: Config = Config::new(config)?;
+            config.validate()?;
             Ok(Box::new(Self {
                 config,
                 onramp_id: id.clone(),
@@ -277,9 +530,19 @@ impl onramp::Impl for Kafka {
 // offsets are committed
 pub struct LoggingConsumerContext {
     onramp_id: TremorUrl,
+    /// receives the partition lags of each statistics report
+    lags: async_channel::Sender<Vec<PartitionLag>>,
 }
 
-impl ClientContext for LoggingConsumerContext {}
+impl ClientContext for LoggingConsumerContext {
+    fn stats(&self, statistics: Statistics) {
+        // reports arriving while the previous one is still pending are dropped,
+        // the next one will be up to date again
+        if self.lags.try_send(partition_lags(&statistics)).is_err() {
+            debug!("[Source::{}] Dropped a lag report", self.onramp_id);
+        }
+    }
+}
 
 impl ConsumerContext for LoggingConsumerContext {
     fn post_rebalance(&self, rebalance: &Rebalance) {
@@ -366,6 +629,16 @@ impl Source for Int {
         &self.onramp_id
     }
     async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        if let Some(lags) = self.lag_reports.as_ref().and_then(|r| r.try_recv().ok()) {
+            return Ok(self.lag_report(lags));
+        }
+        if let (Some(token_refresh), Some(stream)) =
+            (self.token_refresh.as_mut(), self.stream.as_mut())
+        {
+            if let Err(e) = token_refresh.refresh(unsafe { stream.consumer() }.client()) {
+                error!("[Source::{}] {}", self.onramp_id, e);
+            }
+        }
         if let Some(stream) = self.stream.as_mut() {
             let s = unsafe { stream.mut_suffix() };
             let r = match timeout(Duration::from_millis(100), s.next()).await {
@@ -387,26 +660,33 @@ impl Source for Int {
                         m.offset().to_string(),
                     ];
                     let data = data.to_vec();
+                    let ingest_ns = nanotime();
                     let mut kafka_meta_data = Value::object_with_capacity(1);
                     let mut meta_key = None;
                     if let Some(key) = m.key() {
-                        meta_key = Some(key);
+                        meta_key = Some(decode_bytes(self.key_codec.as_mut(), key, ingest_ns));
                     }
                     let mut meta_headers = None;
+                    let mut codec_override = None;
                     if let Some(headers) = m.headers() {
                         let mut key_val = Value::object_with_capacity(headers.count());
                         for i in 0..headers.count() {
                             if let Some(header) = headers.get(i) {
+                                if header.0.eq_ignore_ascii_case("content-type") {
+                                    // decode by content type if it is in the codec map
+                                    codec_override = content_type_essence(header.1);
+                                }
                                 let key = String::from(header.0);
-                                let val = Value::Bytes(Vec::from(header.1).into());
+                                let val =
+                                    decode_bytes(self.header_codec.as_mut(), header.1, ingest_ns);
                                 key_val.insert(key, val)?;
                             }
                         }
                         meta_headers = Some(key_val);
                     }
-                    let mut meta_data = Value::object_with_capacity(6);
+                    let mut meta_data = Value::object_with_capacity(7);
                     if let Some(meta_key) = meta_key {
-                        meta_data.insert("key", Value::Bytes(Vec::from(meta_key).into()))?;
+                        meta_data.insert("key", meta_key)?;
                     }
                     if let Some(meta_headers) = meta_headers {
                         meta_data.insert("headers", meta_headers)?;
@@ -414,19 +694,20 @@ impl Source for Int {
                     meta_data.insert("topic", m.topic().to_string())?;
                     meta_data.insert("offset", m.offset())?;
                     meta_data.insert("partition", m.partition())?;
-                    if let Some(t) = m.timestamp().to_millis() {
+                    if let Some((timestamp_type, t)) = timestamp(m.timestamp()) {
                         meta_data.insert("timestamp", t)?;
+                        meta_data.insert("timestamp_type", timestamp_type)?;
                     }
                     kafka_meta_data.insert("kafka", meta_data)?;
 
                     if !self.auto_commit {
-                        self.messages.insert(id, MsgOffset::from(m));
+                        self.offsets.sent(id, &m);
                     }
                     Ok(SourceReply::Data {
                         origin_uri,
                         data,
                         meta: Some(kafka_meta_data),
-                        codec_override: None,
+                        codec_override,
                         stream: 0,
                     })
                 } else {
@@ -444,10 +725,38 @@ impl Source for Int {
         }
     }
 
+    fn metrics(&mut self, t: u64) -> Vec<Event> {
+        self.lags
+            .iter()
+            .map(|l| {
+                let mut tags: HashMap<Cow<'static, str>, Value<'static>> =
+                    HashMap::with_capacity(3);
+                tags.insert_nocheck(Cow::from("ramp"), self.onramp_id.to_string().into());
+                tags.insert_nocheck(Cow::from("topic"), l.topic.clone().into());
+                tags.insert_nocheck(Cow::from("partition"), l.partition.into());
+                let lag = u64::try_from(l.lag).unwrap_or_default();
+                Event {
+                    data: tremor_pipeline::influx_value(
+                        Cow::from("kafka_consumer_lag"),
+                        tags,
+                        lag,
+                        t,
+                    )
+                    .into(),
+                    ingest_ns: t,
+                    ..Event::default()
+                }
+            })
+            .collect()
+    }
+
     #[allow(clippy::clippy::too_many_lines)]
     async fn init(&mut self) -> Result<SourceState> {
+        // lag reports from the statistics callback
+        let (lags_tx, lags_rx) = bounded(1);
         let context = LoggingConsumerContext {
             onramp_id: self.onramp_id.clone(),
+            lags: lags_tx,
         };
         let mut client_config = ClientConfig::new();
         let tid = task::current().id();
@@ -500,7 +809,17 @@ impl Source for Int {
             .set("auto.commit.interval.ms", "5000")
             // but only commit the offsets explicitly stored via `consumer.store_offset`.
             .set("enable.auto.offset.store", "true");
+        if self.config.lag_interval > 0 {
+            client_config.set(
+                "statistics.interval.ms",
+                &self.config.lag_interval.to_string(),
+            );
+            self.lag_reports = Some(lags_rx);
+        }
 
+        if let Some(security) = &self.config.security {
+            security.apply(&mut client_config);
+        }
         self.config
             .rdkafka_options
             .iter()
@@ -508,6 +827,9 @@ impl Source for Int {
             .for_each(|(k, v)| {
                 client_config.set(k, v);
             });
+        if !self.auto_commit {
+            client_config.set("enable.auto.commit", "false");
+        }
 
         debug!(
             "[Source::{}] Consuming from Kafka with config: {:?}",
@@ -516,6 +838,11 @@ impl Source for Int {
 
         // Set up the the consumer
         let consumer: LoggingConsumer = client_config.create_with_context(context)?;
+        // authenticate with a token right away
+        if let Some(token_refresh) = self.token_refresh.as_mut() {
+            token_refresh.reset();
+            token_refresh.refresh(consumer.client())?;
+        }
 
         // Handle topics
         let topics: Vec<&str> = self
@@ -599,21 +926,18 @@ impl Source for Int {
     fn trigger_breaker(&mut self) {}
     fn restore_breaker(&mut self) {}
 
-    // If we fail a message we seek back to this failed
+    // If we fail a message we seek its partition back to this failed
     // message to replay data from here.
-    //
-    // This might seek over multiple topics but since we internally only keep
-    // track of a singular stream this is OK.
-    //
-    // If this is undesirable, multiple onramps with an onramp per topic
-    // should be used.
     fn fail(&mut self, id: u64) {
         trace!("[Source::{}] Fail {}", self.onramp_id, id);
         if !self.auto_commit && self.config.retry_failed_events {
-            let tm = self.get_topic_map_for_id(id);
-            if let Some(consumer) = self.stream.as_mut() {
-                if let Err(e) = consumer.seek(&tm) {
-                    error!("[Source::{}] failed to seek message: {}", self.onramp_id, e)
+            if let Some((tp, offset)) = self.offsets.fail(id) {
+                let mut tm = StdMap::with_capacity(1);
+                tm.insert(tp, offset);
+                if let Some(consumer) = self.stream.as_mut() {
+                    if let Err(e) = consumer.seek(&tm) {
+                        error!("[Source::{}] failed to seek message: {}", self.onramp_id, e)
+                    }
                 }
             }
         }
@@ -621,7 +945,7 @@ impl Source for Int {
     fn ack(&mut self, id: u64) {
         trace!("[Source::{}] Ack {}", self.onramp_id, id);
         if !self.auto_commit {
-            let tm = self.get_topic_map_for_id(id);
+            let tm = self.offsets.ack(id);
             if !tm.is_empty() {
                 if let Some(consumer) = self.stream.as_mut() {
                     if let Err(e) = consumer.commit(&tm, CommitMode::Async) {
@@ -639,10 +963,142 @@ impl Source for Int {
 #[async_trait::async_trait]
 impl Onramp for Kafka {
     async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
-        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config);
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
         SourceManager::start(source, config).await
     }
     fn default_codec(&self) -> &str {
         "json"
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn content_type() {
+        assert_eq!(
+            Some("application/json".to_string()),
+            content_type_essence(b"Application/JSON; charset=utf-8")
+        );
+        assert_eq!(
+            Some("text/plain".to_string()),
+            content_type_essence(b" text/plain ")
+        );
+        assert_eq!(None, content_type_essence(b""));
+        assert_eq!(None, content_type_essence(b"\xff"));
+    }
+
+    #[test]
+    fn decode_key() -> Result<()> {
+        let mut json = Some(codec::lookup("json")?);
+        assert_eq!(
+            Value::from(vec![1, 2]),
+            decode_bytes(json.as_mut(), b"[1,2]", 0)
+        );
+        assert_eq!(
+            Value::Bytes(b"snot".to_vec().into()),
+            decode_bytes(json.as_mut(), b"snot", 0)
+        );
+        let mut string = Some(codec::lookup("string")?);
+        assert_eq!(
+            Value::from("badger"),
+            decode_bytes(string.as_mut(), b"badger", 0)
+        );
+        assert_eq!(
+            Value::Bytes(b"badger".to_vec().into()),
+            decode_bytes(None, b"badger", 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(None, timestamp(Timestamp::NotAvailable));
+        assert_eq!(
+            Some(("create_time", 42)),
+            timestamp(Timestamp::CreateTime(42))
+        );
+        assert_eq!(
+            Some(("log_append_time", 7)),
+            timestamp(Timestamp::LogAppendTime(7))
+        );
+    }
+
+    fn lag(partition: i32, lag: i64) -> PartitionLag {
+        PartitionLag {
+            topic: "snot".to_string(),
+            partition,
+            lag,
+            high_watermark: 100,
+            offset: 100 - lag,
+            committed_offset: 100 - lag,
+        }
+    }
+
+    #[test]
+    fn offsets() {
+        let tp = |p: i32| ("snot".to_string(), p);
+        let mut offsets = Offsets::default();
+        for (id, p, o) in &[(0, 0, 10), (1, 1, 20), (2, 0, 11), (3, 0, 12), (4, 1, 21)] {
+            offsets.insert(*id, tp(*p), *o);
+        }
+        assert_eq!(Some(&Offset::Offset(21)), offsets.ack(1).get(&tp(1)));
+        assert_eq!(Some(&Offset::Offset(11)), offsets.ack(0).get(&tp(0)));
+        // acked events can't be failed anymore
+        assert_eq!(None, offsets.fail(1));
+        assert_eq!(Some((tp(0), Offset::Offset(12))), offsets.fail(3));
+        // the failed message is forgotten, it is delivered again
+        assert_eq!(Some(&Offset::Offset(12)), offsets.ack(2).get(&tp(0)));
+        assert!(offsets.ack(3).is_empty());
+        assert_eq!(Some(&Offset::Offset(22)), offsets.ack(4).get(&tp(1)));
+        assert!(offsets.ack(5).is_empty());
+        assert!(offsets.partitions.is_empty());
+    }
+
+    #[test]
+    fn offsets_acked_out_of_order() {
+        let tp = |p: i32| ("snot".to_string(), p);
+        let mut offsets = Offsets::default();
+        for (id, p, o) in &[(0, 0, 10), (1, 1, 20), (2, 0, 11), (3, 1, 21), (4, 0, 12)] {
+            offsets.insert(*id, tp(*p), *o);
+        }
+        // partition 0 has 10 and 11 in flight before 12
+        assert!(offsets.ack(4).is_empty());
+        // acking a later event of partition 1 doesn't commit its earlier one
+        assert!(offsets.ack(3).is_empty());
+        assert!(offsets.ack(2).is_empty());
+        let tm = offsets.ack(1);
+        assert_eq!(1, tm.len());
+        assert_eq!(Some(&Offset::Offset(22)), tm.get(&tp(1)));
+        // the whole acked prefix of partition 0 is committed at once
+        let tm = offsets.ack(0);
+        assert_eq!(1, tm.len());
+        assert_eq!(Some(&Offset::Offset(13)), tm.get(&tp(0)));
+        assert!(offsets.partitions.is_empty());
+    }
+
+    #[test]
+    fn lag_alerts() {
+        let lags = vec![lag(0, 3), lag(1, 20)];
+        let (report, alerting) = lag_report(&lags, Some(10));
+        assert!(alerting);
+        assert_eq!(Some(23), report.get("consumer_lag").get_i64("lag"));
+        let partitions = report
+            .get("consumer_lag")
+            .get_array("partitions")
+            .cloned()
+            .unwrap_or_default();
+        assert_eq!(
+            vec![Some(false), Some(true)],
+            partitions
+                .iter()
+                .map(|p| p.get_bool("alert"))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(80), partitions[1].get_i64("offset"));
+        assert!(!lag_report(&lags, Some(20)).1);
+        assert!(!lag_report(&lags, None).1);
+        assert!(!lag_report(&[], Some(0)).1);
+    }
+}
@@ -0,0 +1,595 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! AWS Kinesis Data Streams onramp
+//!
+//! Reads the shards of a stream round robin. Shards are discovered every
+//! `shard_refresh_interval` seconds, a shard created by a split or merge is
+//! only read once its parents were read to their end, so records of a
+//! partition key are read in order.
+//!
+//! Once an event is acked the sequence number of its record is checkpointed
+//! for its shard, reading resumes after the checkpoint when the onramp is
+//! restarted. If an event fails its shard is read again from its record.
+//! Checkpoints are kept in memory or, to survive restarts, in a sled
+//! database in `dir`:
+//!
+//! ```yaml
+//! checkpoint:
+//!   store: sled
+//!   dir: /var/lib/tremor/kinesis
+//! ```
+//!
+//! The shards of a stream can be distributed across several onramps, each
+//! configured with the same `instances` and its own `instance` index. Each
+//! shard is read by one of them, picked by a hash of the shard id. As a
+//! shard may have its parents read by another instance, the instances have
+//! to share their checkpoint store.
+//!
+//! The record metadata is available as `$kinesis`, with `stream`,
+//! `shard_id`, `sequence_number`, `partition_key` and
+//! `approximate_arrival_timestamp`.
+//!
+//! Credentials are read from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+//! and `AWS_SESSION_TOKEN` environment variables or the shared credentials
+//! file.
+
+use crate::connectors::aws::auth::{self, AwsClient};
+use crate::connectors::aws::kinesis::{
+    self, cmp_sequence_numbers, Position, Record, Shard, MAX_RECORDS,
+};
+use crate::source::checkpoint::{self, CheckpointConfig, CheckpointStore};
+use crate::source::prelude::*;
+use halfbrown::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// checkpoint of a shard read to its end with all its records acked
+const SHARD_END: &str = "SHARD_END";
+/// milliseconds between reads of a shard returning records, a shard allows
+/// five reads per second
+const MIN_POLL_INTERVAL: u64 = 200;
+
+/// Where to start reading shards without a checkpoint
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StartPosition {
+    /// the oldest record
+    TrimHorizon,
+    /// records arriving from now on
+    Latest,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// name of the stream
+    pub stream: String,
+    /// AWS region, defaults to `AWS_REGION`
+    #[serde(default)]
+    pub region: Option<String>,
+    /// overrides the `kinesis.{region}.amazonaws.com` endpoint
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// where to start reading shards without a checkpoint
+    #[serde(default = "d_start_position")]
+    pub start_position: StartPosition,
+    /// where checkpoints are kept
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+    /// index of this onramp among the onramps sharing the stream
+    #[serde(default)]
+    pub instance: u64,
+    /// number of onramps sharing the stream
+    #[serde(default = "d_instances")]
+    pub instances: u64,
+    /// records per read, at most 10000
+    #[serde(default = "d_limit")]
+    pub limit: u64,
+    /// milliseconds to wait before reading a shard again that had no new
+    /// records
+    #[serde(default = "d_poll_interval")]
+    pub poll_interval: u64,
+    /// seconds between discovering new shards
+    #[serde(default = "d_shard_refresh_interval")]
+    pub shard_refresh_interval: u64,
+}
+
+fn d_start_position() -> StartPosition {
+    StartPosition::TrimHorizon
+}
+
+fn d_instances() -> u64 {
+    1
+}
+
+fn d_limit() -> u64 {
+    1000
+}
+
+fn d_poll_interval() -> u64 {
+    1000
+}
+
+fn d_shard_refresh_interval() -> u64 {
+    60
+}
+
+impl ConfigImpl for Config {}
+
+/// whether the shard is read by this instance, by a hash of its id that is
+/// the same for all instances
+fn assigned(shard_id: &str, instance: u64, instances: u64) -> bool {
+    // FNV-1a
+    let hash = shard_id.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash % instances == instance
+}
+
+/// whether a shard can be read, it has to be unfinished and its parents
+/// have to be finished or expired
+fn ready(shard: &Shard, listed: &HashSet<&str>, store: &dyn CheckpointStore) -> Result<bool> {
+    let finished = |shard_id: &str| -> Result<bool> {
+        Ok(store.get(shard_id)?.map_or(false, |c| c == SHARD_END))
+    };
+    if finished(&shard.shard_id)? {
+        return Ok(false);
+    }
+    for parent in shard.parents() {
+        if listed.contains(parent) && !finished(parent)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+pub struct Kinesis {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for Kinesis {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let mut config: Config = Config::new(config)?;
+            if config.instance >= config.instances {
+                return Err(
+                    "The kinesis onramp `instance` has to be lower than `instances`".into(),
+                );
+            }
+            config.limit = config.limit.min(MAX_RECORDS).max(1);
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for kinesis onramp".into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for Kinesis {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+struct ShardReader {
+    iterator: Option<String>,
+    /// sequence number to read again from after a failed event
+    rewind: Option<String>,
+    next_read: Instant,
+    /// read to its end, it is finished once all its records are acked
+    closed: bool,
+}
+
+pub struct Int {
+    onramp_id: TremorUrl,
+    config: Config,
+    region: String,
+    client: Option<AwsClient>,
+    store: Box<dyn CheckpointStore>,
+    origin_uri: EventOriginUri,
+    /// the shards read by this instance
+    readers: BTreeMap<String, ShardReader>,
+    /// the shard read last, shards are read round robin
+    last_read: Option<String>,
+    next_refresh: Instant,
+    /// records read but not pulled yet, with their shard
+    received: VecDeque<(String, Record)>,
+    /// shard and sequence number by the id of their event
+    in_flight: HashMap<u64, (String, String)>,
+    /// the highest acked sequence number by shard
+    checkpoints: HashMap<String, String>,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Kinesis")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        let config = config.clone();
+        let region = auth::region(config.region.as_deref())?;
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-kinesis".to_string(),
+            host: config
+                .endpoint
+                .clone()
+                .unwrap_or_else(|| format!("kinesis.{}.amazonaws.com", region)),
+            port: None,
+            path: vec![config.stream.clone()],
+        };
+        Ok(Self {
+            onramp_id,
+            store: checkpoint::store(&config.checkpoint, &config.stream)?,
+            config,
+            region,
+            client: None,
+            origin_uri,
+            readers: BTreeMap::new(),
+            last_read: None,
+            next_refresh: Instant::now(),
+            received: VecDeque::new(),
+            in_flight: HashMap::new(),
+            checkpoints: HashMap::new(),
+        })
+    }
+
+    /// starts reading the new shards that are ready
+    async fn refresh(&mut self, client: &AwsClient) -> Result<()> {
+        let shards = kinesis::list_shards(client, &self.config.stream).await?;
+        let listed: HashSet<&str> = shards.iter().map(|s| s.shard_id.as_str()).collect();
+        for shard in &shards {
+            let id = &shard.shard_id;
+            if self.readers.contains_key(id)
+                || !assigned(id, self.config.instance, self.config.instances)
+                || !ready(shard, &listed, self.store.as_ref())?
+            {
+                continue;
+            }
+            if let Some(checkpoint) = self.store.get(id)? {
+                self.checkpoints.insert(id.clone(), checkpoint);
+            }
+            debug!("[Source::{}] reading shard {}", self.onramp_id, id);
+            self.readers.insert(
+                id.clone(),
+                ShardReader {
+                    iterator: None,
+                    rewind: None,
+                    next_read: Instant::now(),
+                    closed: false,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    /// the next shard due to be read after the last one read
+    fn next_shard(&self, now: Instant) -> Option<String> {
+        let last = self.last_read.as_deref().unwrap_or_default();
+        let due = |(_, r): &(&String, &ShardReader)| !r.closed && r.next_read <= now;
+        self.readers
+            .iter()
+            .filter(|(id, _)| id.as_str() > last)
+            .find(due)
+            .or_else(|| self.readers.iter().find(due))
+            .map(|(id, _)| id.clone())
+    }
+
+    /// milliseconds until the next shard is due
+    #[allow(clippy::cast_possible_truncation)]
+    fn wait_ms(&self, now: Instant) -> u64 {
+        self.readers
+            .values()
+            .filter(|r| !r.closed)
+            .map(|r| r.next_read.saturating_duration_since(now).as_millis() as u64)
+            .min()
+            .unwrap_or(self.config.poll_interval)
+            .max(1)
+    }
+
+    /// reads the next records of a shard
+    async fn read(&mut self, client: &AwsClient, shard_id: &str, now: Instant) {
+        let poll_interval = Duration::from_millis(self.config.poll_interval);
+        let checkpoint = self.checkpoints.get(shard_id).cloned();
+        let position = match self.readers.get_mut(shard_id) {
+            Some(reader) if reader.iterator.is_none() => Some(
+                reader
+                    .rewind
+                    .take()
+                    .map(Position::At)
+                    .or_else(|| checkpoint.map(Position::After))
+                    .unwrap_or(match self.config.start_position {
+                        StartPosition::TrimHorizon => Position::TrimHorizon,
+                        StartPosition::Latest => Position::Latest,
+                    }),
+            ),
+            Some(_) => None,
+            None => return,
+        };
+        self.last_read = Some(shard_id.to_string());
+        if let Some(position) = position {
+            let res =
+                kinesis::get_shard_iterator(client, &self.config.stream, shard_id, &position).await;
+            if let Some(reader) = self.readers.get_mut(shard_id) {
+                match res {
+                    Ok(iterator) => reader.iterator = Some(iterator),
+                    Err(e) => {
+                        warn!(
+                            "[Source::{}] failed to get an iterator for shard {}: {}",
+                            self.onramp_id, shard_id, e
+                        );
+                        reader.next_read = now + poll_interval;
+                        return;
+                    }
+                }
+            }
+        }
+        let iterator = match self.readers.get(shard_id).and_then(|r| r.iterator.clone()) {
+            Some(iterator) => iterator,
+            None => return,
+        };
+        let res = kinesis::get_records(client, &iterator, self.config.limit).await;
+        let closed = if let Some(reader) = self.readers.get_mut(shard_id) {
+            match res {
+                Ok(records) => {
+                    reader.next_read = if records.records.is_empty() {
+                        now + poll_interval
+                    } else {
+                        now + Duration::from_millis(MIN_POLL_INTERVAL)
+                    };
+                    reader.iterator = records.next_shard_iterator;
+                    reader.closed = reader.iterator.is_none();
+                    self.received.extend(
+                        records
+                            .records
+                            .into_iter()
+                            .map(|r| (shard_id.to_string(), r)),
+                    );
+                }
+                Err(e) => {
+                    // expired iterators are renewed from the checkpoint
+                    warn!(
+                        "[Source::{}] failed to read shard {}: {}",
+                        self.onramp_id, shard_id, e
+                    );
+                    reader.iterator = None;
+                    reader.next_read = now + poll_interval;
+                }
+            }
+            reader.closed
+        } else {
+            false
+        };
+        if closed {
+            self.finish(shard_id);
+        }
+    }
+
+    /// marks a closed shard as finished once all its records were acked, so
+    /// the shards split or merged from it are read
+    fn finish(&mut self, shard_id: &str) {
+        let closed = self.readers.get(shard_id).map_or(false, |r| r.closed);
+        let pending = self.received.iter().any(|(s, _)| s == shard_id)
+            || self.in_flight.values().any(|(s, _)| s == shard_id);
+        if !closed || pending {
+            return;
+        }
+        if let Err(e) = self.store.set(shard_id, SHARD_END) {
+            error!(
+                "[Source::{}] failed to checkpoint shard {}: {}",
+                self.onramp_id, shard_id, e
+            );
+            return;
+        }
+        debug!("[Source::{}] finished shard {}", self.onramp_id, shard_id);
+        self.readers.remove(shard_id);
+        self.checkpoints.remove(shard_id);
+        self.next_refresh = Instant::now();
+    }
+
+    fn emit(&mut self, id: u64, shard_id: String, record: Record) -> Result<SourceReply> {
+        let mut meta = Value::object_with_capacity(5);
+        meta.insert("stream", self.config.stream.clone())?;
+        meta.insert("shard_id", shard_id.clone())?;
+        meta.insert("sequence_number", record.sequence_number.clone())?;
+        meta.insert("partition_key", record.partition_key)?;
+        if let Some(timestamp) = record.approximate_arrival_timestamp {
+            meta.insert("approximate_arrival_timestamp", timestamp)?;
+        }
+        let mut kinesis_meta = Value::object_with_capacity(1);
+        kinesis_meta.insert("kinesis", meta)?;
+        let mut origin_uri = self.origin_uri.clone();
+        origin_uri.path.push(shard_id.clone());
+        self.in_flight
+            .insert(id, (shard_id, record.sequence_number));
+        Ok(SourceReply::Data {
+            origin_uri,
+            data: record.data,
+            meta: Some(kinesis_meta),
+            codec_override: None,
+            stream: 0,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        let client = if let Some(client) = self.client.clone() {
+            client
+        } else {
+            return Ok(SourceReply::StateChange(SourceState::Disconnected));
+        };
+        if let Some((shard_id, record)) = self.received.pop_front() {
+            return self.emit(id, shard_id, record);
+        }
+        let now = Instant::now();
+        if now >= self.next_refresh {
+            if let Err(e) = self.refresh(&client).await {
+                error!("[Source::{}] failed to list shards: {}", self.onramp_id, e);
+            }
+            self.next_refresh = now + Duration::from_secs(self.config.shard_refresh_interval);
+        }
+        if let Some(shard_id) = self.next_shard(now) {
+            self.read(&client, &shard_id, now).await;
+        } else {
+            return Ok(SourceReply::Empty(self.wait_ms(now)));
+        }
+        if let Some((shard_id, record)) = self.received.pop_front() {
+            self.emit(id, shard_id, record)
+        } else {
+            Ok(SourceReply::Empty(0))
+        }
+    }
+
+    async fn on_empty_event(&mut self, id: u64, _stream: usize) -> Result<()> {
+        // nothing in flight downstream to wait for
+        self.ack(id);
+        Ok(())
+    }
+
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        self.client = Some(AwsClient::new(
+            "kinesis",
+            &self.region,
+            self.config.endpoint.as_deref(),
+        )?);
+        // resume from the checkpoints
+        self.readers.clear();
+        self.received.clear();
+        self.in_flight.clear();
+        self.checkpoints.clear();
+        self.next_refresh = Instant::now();
+        Ok(SourceState::Connected)
+    }
+
+    fn ack(&mut self, id: u64) {
+        if let Some((shard_id, sequence_number)) = self.in_flight.remove(&id) {
+            let newer = self.checkpoints.get(&shard_id).map_or(true, |c| {
+                cmp_sequence_numbers(&sequence_number, c) == Ordering::Greater
+            });
+            if newer {
+                if let Err(e) = self.store.set(&shard_id, &sequence_number) {
+                    error!(
+                        "[Source::{}] failed to checkpoint shard {}: {}",
+                        self.onramp_id, shard_id, e
+                    );
+                }
+                self.checkpoints.insert(shard_id.clone(), sequence_number);
+            }
+            self.finish(&shard_id);
+        }
+    }
+
+    fn fail(&mut self, id: u64) {
+        if let Some((shard_id, sequence_number)) = self.in_flight.remove(&id) {
+            // read the shard again from the failed record, the records after
+            // it are read again as well
+            self.received.retain(|(s, _)| s != &shard_id);
+            self.in_flight.retain(|_, (s, n)| {
+                s != &shard_id || cmp_sequence_numbers(n, &sequence_number) == Ordering::Less
+            });
+            if let Some(reader) = self.readers.get_mut(&shard_id) {
+                reader.iterator = None;
+                reader.rewind = Some(sequence_number);
+                reader.closed = false;
+                reader.next_read = Instant::now();
+            }
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::source::checkpoint::MemoryStore;
+
+    fn shard(id: &str, parent: Option<&str>, adjacent: Option<&str>) -> Shard {
+        Shard {
+            shard_id: id.to_string(),
+            parent_shard_id: parent.map(ToString::to_string),
+            adjacent_parent_shard_id: adjacent.map(ToString::to_string),
+        }
+    }
+
+    #[test]
+    fn distribution() {
+        let ids: Vec<String> = (0..64).map(|i| format!("shardId-{:012}", i)).collect();
+        for id in &ids {
+            let owners = (0..3).filter(|i| assigned(id, *i, 3)).count();
+            assert_eq!(1, owners);
+            assert!(assigned(id, 0, 1));
+        }
+        // all instances get some shards
+        for i in 0..3 {
+            assert!(ids.iter().any(|id| assigned(id, i, 3)));
+        }
+    }
+
+    #[test]
+    fn lineage() -> Result<()> {
+        let mut store = MemoryStore::default();
+        let parent = shard("shardId-0", None, None);
+        let left = shard("shardId-1", Some("shardId-0"), None);
+        let merged = shard("shardId-3", Some("shardId-1"), Some("shardId-2"));
+        let listed: HashSet<&str> = vec!["shardId-0", "shardId-1", "shardId-2", "shardId-3"]
+            .into_iter()
+            .collect();
+
+        assert!(ready(&parent, &listed, &store)?);
+        assert!(!ready(&left, &listed, &store)?);
+        store.set("shardId-0", "42")?;
+        assert!(!ready(&left, &listed, &store)?);
+        store.set("shardId-0", SHARD_END)?;
+        assert!(!ready(&parent, &listed, &store)?);
+        assert!(ready(&left, &listed, &store)?);
+
+        // both parents have to be finished
+        store.set("shardId-1", SHARD_END)?;
+        assert!(!ready(&merged, &listed, &store)?);
+        store.set("shardId-2", SHARD_END)?;
+        assert!(ready(&merged, &listed, &store)?);
+
+        // expired parents aren't waited for
+        let listed: HashSet<&str> = vec!["shardId-1"].into_iter().collect();
+        assert!(ready(
+            &shard("shardId-1", Some("shardId-0"), None),
+            &listed,
+            &MemoryStore::default()
+        )?);
+        Ok(())
+    }
+}
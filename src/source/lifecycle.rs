@@ -0,0 +1,314 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Lifecycle Onramp
+//!
+//! The `lifecycle` onramp emits an event on every transition of the runtime,
+//! once startup is complete (`started`), for every loaded config or query
+//! file (`config_loaded`), for onramps and offramps that fail to start or
+//! stop with an error (`connector_failed`) and once shutdown is initiated
+//! (`stopping`). Received `signals` are emitted as `signal` events, so
+//! pipelines can for example flush their state on `SIGHUP`.
+//!
+//! Once an onramp listened for a signal the signal no longer terminates the
+//! process, even after the onramp is gone.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::source::prelude::*;
+use async_channel::{Sender, TryRecvError};
+use std::sync::Mutex;
+use tremor_common::time::nanotime;
+
+lazy_static! {
+    static ref SUBSCRIBERS: Mutex<Vec<Sender<Transition>>> = Mutex::new(Vec::new());
+}
+
+/// A transition of the runtime
+#[derive(Debug, Clone)]
+pub(crate) enum Transition {
+    /// All artefacts given at startup are deployed
+    Started,
+    /// A config or query file was loaded
+    ConfigLoaded(String),
+    /// An onramp or offramp failed to start or stopped with an error
+    ConnectorFailed { id: String, error: String },
+    /// Shutdown was initiated
+    Stopping,
+    /// A signal was received
+    Signal(&'static str),
+}
+
+impl Transition {
+    fn into_value(self) -> Value<'static> {
+        let ingest_ns = nanotime();
+        match self {
+            Self::Started => literal!({"event": "started", "ingest_ns": ingest_ns}),
+            Self::ConfigLoaded(file) => literal!({
+                "event": "config_loaded",
+                "ingest_ns": ingest_ns,
+                "file": file
+            }),
+            Self::ConnectorFailed { id, error } => literal!({
+                "event": "connector_failed",
+                "ingest_ns": ingest_ns,
+                "id": id,
+                "error": error
+            }),
+            Self::Stopping => literal!({"event": "stopping", "ingest_ns": ingest_ns}),
+            Self::Signal(signal) => literal!({
+                "event": "signal",
+                "ingest_ns": ingest_ns,
+                "signal": signal
+            }),
+        }
+    }
+}
+
+/// Hands a transition to all `lifecycle` onramps, transitions are dropped
+/// for onramps that are falling behind
+pub(crate) fn publish(transition: Transition) {
+    match SUBSCRIBERS.lock() {
+        Ok(mut subscribers) => subscribers.retain(|tx| match tx.try_send(transition.clone()) {
+            Ok(()) => true,
+            Err(async_channel::TrySendError::Full(_)) => {
+                warn!(
+                    "[Source::lifecycle] Dropping {:?}, the onramp is full",
+                    transition
+                );
+                true
+            }
+            Err(async_channel::TrySendError::Closed(_)) => false,
+        }),
+        Err(e) => error!(
+            "[Source::lifecycle] Failed to publish {:?}: {}",
+            transition, e
+        ),
+    }
+}
+
+#[cfg(unix)]
+mod signals {
+    use super::{publish, Transition};
+    use crate::errors::Result;
+    use signal_hook::consts::{SIGHUP, SIGUSR1, SIGUSR2};
+    use signal_hook::iterator::{Handle, Signals};
+    use std::sync::Mutex;
+
+    lazy_static! {
+        static ref HANDLE: Mutex<Option<Handle>> = Mutex::new(None);
+    }
+
+    const SUPPORTED: [(&str, i32); 3] = [
+        ("SIGHUP", SIGHUP),
+        ("SIGUSR1", SIGUSR1),
+        ("SIGUSR2", SIGUSR2),
+    ];
+
+    pub(super) fn number(name: &str) -> Result<i32> {
+        SUPPORTED
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, signal)| *signal)
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported signal {}, supported are SIGHUP, SIGUSR1 and SIGUSR2",
+                    name
+                )
+                .into()
+            })
+    }
+
+    fn name(signal: i32) -> &'static str {
+        SUPPORTED
+            .iter()
+            .find(|(_, s)| *s == signal)
+            .map_or("unknown", |(name, _)| name)
+    }
+
+    /// Listens for the signals, the listening thread is shared by all onramps
+    pub(super) fn listen(signals: &[i32]) -> Result<()> {
+        let mut handle = HANDLE.lock()?;
+        if let Some(handle) = handle.as_ref() {
+            for signal in signals {
+                handle.add_signal(*signal)?;
+            }
+        } else {
+            let mut received = Signals::new(signals)?;
+            *handle = Some(received.handle());
+            std::thread::Builder::new()
+                .name("lifecycle-signals".to_string())
+                .spawn(move || {
+                    for signal in received.forever() {
+                        publish(Transition::Signal(name(signal)));
+                    }
+                })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// Signals to emit events for, of `SIGHUP`, `SIGUSR1` and `SIGUSR2`
+    /// (default: `["SIGHUP", "SIGUSR1"]`)
+    #[serde(default = "d_signals")]
+    pub signals: Vec<String>,
+}
+
+impl ConfigImpl for Config {}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            signals: d_signals(),
+        }
+    }
+}
+
+fn d_signals() -> Vec<String> {
+    vec!["SIGHUP".to_string(), "SIGUSR1".to_string()]
+}
+
+pub struct Lifecycle {
+    pub config: Config,
+    origin_uri: EventOriginUri,
+    onramp_id: TremorUrl,
+    rx: Option<Receiver<Transition>>,
+}
+
+impl onramp::Impl for Lifecycle {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        let config = if let Some(config) = config {
+            Config::new(config)?
+        } else {
+            Config::default()
+        };
+        #[cfg(unix)]
+        for signal in &config.signals {
+            signals::number(signal)?;
+        }
+        #[cfg(not(unix))]
+        if !config.signals.is_empty() {
+            return Err("Signals are only supported on unix".into());
+        }
+        Ok(Box::new(Self::new(id, config)))
+    }
+}
+
+impl Lifecycle {
+    fn new(id: &TremorUrl, config: Config) -> Self {
+        let origin_uri = EventOriginUri {
+            uid: 0,
+            scheme: "tremor-lifecycle".to_string(),
+            host: hostname(),
+            port: None,
+            path: vec![],
+        };
+        Self {
+            config,
+            origin_uri,
+            onramp_id: id.clone(),
+            rx: None,
+        }
+    }
+}
+
+#[async_trait::async_trait()]
+impl Source for Lifecycle {
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        let rx = if let Some(rx) = self.rx.as_ref() {
+            rx
+        } else {
+            return Ok(SourceReply::StateChange(SourceState::Disconnected));
+        };
+        match rx.try_recv() {
+            Ok(transition) => Ok(SourceReply::Structured {
+                origin_uri: self.origin_uri.clone(),
+                data: transition.into_value().into(),
+            }),
+            Err(TryRecvError::Empty) => Ok(SourceReply::Empty(100)),
+            Err(TryRecvError::Closed) => Ok(SourceReply::StateChange(SourceState::Disconnected)),
+        }
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        #[cfg(unix)]
+        {
+            let signals = self
+                .config
+                .signals
+                .iter()
+                .map(|s| signals::number(s))
+                .collect::<Result<Vec<_>>>()?;
+            if !signals.is_empty() {
+                signals::listen(&signals)?;
+            }
+        }
+        let (tx, rx) = bounded(crate::QSIZE);
+        SUBSCRIBERS.lock()?.push(tx);
+        self.rx = Some(rx);
+        Ok(SourceState::Connected)
+    }
+
+    async fn terminate(&mut self) {
+        // closing the channel unsubscribes the onramp on the next transition
+        if let Some(rx) = self.rx.take() {
+            rx.close();
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for Lifecycle {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Self::new(&self.onramp_id, self.config.clone());
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn transitions_reach_subscribers() -> Result<()> {
+        let id = TremorUrl::parse("/onramp/lifecycle/test")?;
+        let mut source = Lifecycle::new(&id, Config { signals: vec![] });
+        source.init().await?;
+        publish(Transition::ConfigLoaded("test.yaml".to_string()));
+        match source.pull_event(0).await? {
+            SourceReply::Structured { data, .. } => {
+                let event = data.suffix().value();
+                assert_eq!(Some("config_loaded"), event.get_str("event"));
+                assert_eq!(Some("test.yaml"), event.get_str("file"));
+            }
+            _ => panic!("expected a structured reply"),
+        }
+        source.terminate().await;
+        publish(Transition::Stopping);
+        assert!(SUBSCRIBERS.lock()?.iter().all(|tx| !tx.is_closed()));
+        Ok(())
+    }
+}
@@ -0,0 +1,706 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![cfg(not(tarpaulin_include))]
+
+//! # MySQL Binlog Onramp
+//!
+//! Reads the row changes of a MySQL server from its binlog, as a replica
+//! with the id `server_id`, and emits them as `insert`, `update` and
+//! `delete` events:
+//!
+//! ```json
+//! {
+//!   "op": "update",
+//!   "database": "shop",
+//!   "table": "orders",
+//!   "gtid": "3e11fa47-71ca-11e1-9e33-c80aa9429562:23",
+//!   "before": {"id": 1, "state": "new"},
+//!   "after": {"id": 1, "state": "shipped"}
+//! }
+//! ```
+//!
+//! The binlog only carries the positions of the columns, their names are
+//! fetched from `information_schema` and refetched after DDL statements.
+//! The server needs `gtid_mode = ON`, `binlog_format = ROW` and
+//! `binlog_row_image = FULL`.
+//!
+//! Once all events of a transaction are acked its GTID is added to the
+//! checkpointed GTID set, replication resumes from it after a restart or a
+//! failed event, so events are delivered at least once. Without a
+//! checkpoint replication starts at `start_gtid`, or at the current end of
+//! the binlog if it isn't set:
+//!
+//! ```yaml
+//! host: localhost
+//! user: replicator
+//! password: snot
+//! server_id: 4242
+//! databases: [shop]
+//! checkpoint:
+//!   store: sled
+//!   dir: /var/lib/tremor/mysql
+//! ```
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::source::checkpoint::{self, CheckpointConfig, CheckpointStore};
+use crate::source::prelude::*;
+use async_channel::{Sender, TryRecvError};
+use async_compat::Compat;
+use halfbrown::HashMap;
+use mysql_async::binlog::events::EventData;
+use mysql_async::binlog::value::BinlogValue;
+use mysql_async::prelude::Queryable;
+use mysql_async::{BinlogRequest, Conn, GnoInterval, Opts, OptsBuilder, Sid};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+/// checkpoint of the executed GTID set
+const GTID_CHECKPOINT: &str = "gtid_executed";
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    pub host: String,
+    #[serde(default = "d_port")]
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    /// id of the onramp as a replica, unique among the replicas of the server
+    pub server_id: u32,
+    /// databases to read the changes of, all if empty
+    #[serde(default)]
+    pub databases: Vec<String>,
+    /// GTID set to start after without a checkpoint
+    #[serde(default)]
+    pub start_gtid: Option<String>,
+    /// where the executed GTID set is kept
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+}
+
+fn d_port() -> u16 {
+    3306
+}
+
+impl ConfigImpl for Config {}
+
+impl Config {
+    fn opts(&self) -> Opts {
+        OptsBuilder::default()
+            .ip_or_hostname(self.host.clone())
+            .tcp_port(self.port)
+            .user(Some(self.user.clone()))
+            .pass(Some(self.password.clone()))
+            .into()
+    }
+}
+
+/// A set of GTIDs, the inclusive intervals of transaction numbers by
+/// server uuid
+#[derive(Debug, Clone, Default, PartialEq)]
+struct GtidSet {
+    sids: BTreeMap<String, Vec<(u64, u64)>>,
+}
+
+impl GtidSet {
+    /// Parses the textual form, like `3e11fa47-71ca-11e1-9e33-c80aa9429562:1-5:7`
+    fn parse(set: &str) -> Result<Self> {
+        let invalid = || Error::from(format!("Invalid GTID set: {}", set));
+        let mut res = Self::default();
+        for sid in set.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut parts = sid.split(':');
+            let uuid = parts.next().ok_or_else(invalid)?.to_lowercase();
+            for interval in parts {
+                let mut bounds = interval.splitn(2, '-');
+                let start: u64 = bounds
+                    .next()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or_else(invalid)?;
+                let end = match bounds.next() {
+                    Some(end) => end.parse().map_err(|_| invalid())?,
+                    None => start,
+                };
+                if end < start {
+                    return Err(invalid());
+                }
+                res.add_interval(&uuid, start, end);
+            }
+        }
+        Ok(res)
+    }
+
+    fn add_interval(&mut self, uuid: &str, start: u64, end: u64) {
+        let intervals = self.sids.entry(uuid.to_string()).or_default();
+        intervals.push((start, end));
+        intervals.sort_unstable();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(intervals.len());
+        for (start, end) in intervals.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+
+    fn add(&mut self, uuid: &str, gno: u64) {
+        self.add_interval(uuid, gno, gno);
+    }
+
+    /// The set as requested from the server, with exclusive interval ends
+    fn sids(&self) -> Result<Vec<Sid<'static>>> {
+        self.sids
+            .iter()
+            .map(|(uuid, intervals)| {
+                let mut sid = Sid::new(uuid_bytes(uuid)?);
+                for (start, end) in intervals {
+                    sid = sid.with_interval(GnoInterval::new(*start, end + 1));
+                }
+                Ok(sid)
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for GtidSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for (uuid, intervals) in &self.sids {
+            if !first {
+                write!(f, ",")?;
+            }
+            first = false;
+            write!(f, "{}", uuid)?;
+            for (start, end) in intervals {
+                if start == end {
+                    write!(f, ":{}", start)?;
+                } else {
+                    write!(f, ":{}-{}", start, end)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn uuid_bytes(uuid: &str) -> Result<[u8; 16]> {
+    let hex: Vec<u8> = uuid.bytes().filter(|b| *b != b'-').collect();
+    let mut res = [0; 16];
+    if hex.len() != 32 {
+        return Err(format!("Invalid server uuid: {}", uuid).into());
+    }
+    for (byte, pair) in res.iter_mut().zip(hex.chunks(2)) {
+        *byte = std::str::from_utf8(pair)
+            .ok()
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or_else(|| Error::from(format!("Invalid server uuid: {}", uuid)))?;
+    }
+    Ok(res)
+}
+
+fn uuid_string(bytes: [u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Converts a column of a row image
+fn column_value(value: &mysql_async::Value) -> Value<'static> {
+    use mysql_async::Value as My;
+    match value {
+        My::NULL => Value::null(),
+        My::Bytes(bytes) => Value::from(String::from_utf8_lossy(bytes).to_string()),
+        My::Int(i) => Value::from(*i),
+        My::UInt(u) => Value::from(*u),
+        My::Float(f) => Value::from(f64::from(*f)),
+        My::Double(f) => Value::from(*f),
+        My::Date(year, month, day, hour, minute, second, micros) => {
+            let mut date = format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                year, month, day, hour, minute, second
+            );
+            if *micros > 0 {
+                date.push_str(&format!(".{:06}", micros));
+            }
+            Value::from(date)
+        }
+        My::Time(negative, days, hours, minutes, seconds, micros) => {
+            let hours = u64::from(*days) * 24 + u64::from(*hours);
+            let mut time = format!(
+                "{}{:02}:{:02}:{:02}",
+                if *negative { "-" } else { "" },
+                hours,
+                minutes,
+                seconds
+            );
+            if *micros > 0 {
+                time.push_str(&format!(".{:06}", micros));
+            }
+            Value::from(time)
+        }
+    }
+}
+
+/// The changes of a committed transaction
+#[derive(Debug)]
+struct Transaction {
+    gtid: Option<(String, u64)>,
+    changes: Vec<Value<'static>>,
+}
+
+/// Column names of the tables, fetched on demand
+struct Schemas {
+    conn: Conn,
+    columns: HashMap<(String, String), Vec<String>>,
+}
+
+impl Schemas {
+    async fn columns(&mut self, database: &str, table: &str) -> Result<&[String]> {
+        let key = (database.to_string(), table.to_string());
+        if !self.columns.contains_key(&key) {
+            let names: Vec<String> = Compat::new(self.conn.exec(
+                "SELECT COLUMN_NAME FROM information_schema.columns \
+                 WHERE table_schema = ? AND table_name = ? ORDER BY ORDINAL_POSITION",
+                (database, table),
+            ))
+            .await?;
+            self.columns.insert(key.clone(), names);
+        }
+        Ok(self.columns.get(&key).map_or(&[], Vec::as_slice))
+    }
+
+    /// forgets the columns of `database` after a schema change
+    fn invalidate(&mut self, database: &str) {
+        self.columns.retain(|(db, _), _| db != database);
+    }
+}
+
+fn row_value(
+    row: Option<&mysql_async::binlog::row::BinlogRow>,
+    columns: &[String],
+) -> Value<'static> {
+    row.map_or_else(Value::null, |row| {
+        let mut res = Value::object_with_capacity(row.len());
+        for i in 0..row.len() {
+            let name = columns.get(i).cloned().unwrap_or_else(|| format!("@{}", i));
+            let value = match row.as_ref(i) {
+                Some(BinlogValue::Value(value)) => column_value(value),
+                Some(other) => Value::from(format!("{:?}", other)),
+                None => Value::null(),
+            };
+            res.try_insert(name, value);
+        }
+        res
+    })
+}
+
+/// Reads the binlog after `executed`, sending committed transactions to `tx`
+async fn replicate(config: Config, executed: GtidSet, tx: Sender<Transaction>) -> Result<()> {
+    let mut schemas = Schemas {
+        conn: Compat::new(Conn::new(config.opts())).await?,
+        columns: HashMap::new(),
+    };
+    let conn = Compat::new(Conn::new(config.opts())).await?;
+    let request = BinlogRequest::new(config.server_id)
+        .with_gtid()
+        .with_gtid_set(executed.sids()?);
+    let mut stream = Compat::new(conn.get_binlog_stream(request)).await?;
+    let mut gtid = None;
+    let mut changes = Vec::new();
+    while let Some(event) = Compat::new(stream.next()).await {
+        let event = event?;
+        match event.read_data()? {
+            Some(EventData::GtidEvent(e)) => {
+                gtid = Some((uuid_string(e.sid()), e.gno()));
+                changes.clear();
+            }
+            Some(EventData::RowsEvent(rows)) => {
+                let tme = stream
+                    .get_tme(rows.table_id())
+                    .ok_or_else(|| Error::from("Rows event without table map"))?;
+                let database = tme.database_name().to_string();
+                let table = tme.table_name().to_string();
+                if !config.databases.is_empty() && !config.databases.contains(&database) {
+                    continue;
+                }
+                let columns = schemas.columns(&database, &table).await?.to_vec();
+                let gtid = gtid.as_ref().map_or_else(Value::null, |(uuid, gno)| {
+                    Value::from(format!("{}:{}", uuid, gno))
+                });
+                for row in rows.rows(tme) {
+                    let (before, after) = row?;
+                    let op = match (&before, &after) {
+                        (None, _) => "insert",
+                        (_, None) => "delete",
+                        _ => "update",
+                    };
+                    changes.push(literal!({
+                        "op": op,
+                        "database": database.clone(),
+                        "table": table.clone(),
+                        "gtid": gtid.clone(),
+                        "before": row_value(before.as_ref(), &columns),
+                        "after": row_value(after.as_ref(), &columns)
+                    }));
+                }
+            }
+            Some(EventData::QueryEvent(query)) => {
+                let sql = query.query().to_uppercase();
+                if sql.starts_with("COMMIT") {
+                    // transactions of non transactional engines
+                    let transaction = Transaction {
+                        gtid: gtid.take(),
+                        changes: std::mem::take(&mut changes),
+                    };
+                    tx.send(transaction).await?;
+                } else if sql.starts_with("ALTER")
+                    || sql.starts_with("CREATE")
+                    || sql.starts_with("DROP")
+                    || sql.starts_with("RENAME")
+                    || sql.starts_with("TRUNCATE")
+                {
+                    schemas.invalidate(&query.schema());
+                    // DDL is a transaction of its own
+                    let transaction = Transaction {
+                        gtid: gtid.take(),
+                        changes: Vec::new(),
+                    };
+                    tx.send(transaction).await?;
+                }
+            }
+            Some(EventData::XidEvent(_)) => {
+                let transaction = Transaction {
+                    gtid: gtid.take(),
+                    changes: std::mem::take(&mut changes),
+                };
+                tx.send(transaction).await?;
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Tracks the transactions in flight, to checkpoint their GTID once all
+/// their events are acked
+#[derive(Debug, Default)]
+struct Acks {
+    /// event id, GTID and whether it was acked, transactions without events
+    /// have no event id
+    in_flight: VecDeque<(Option<u64>, Option<(String, u64)>, bool)>,
+}
+
+impl Acks {
+    fn sent(&mut self, id: u64, gtid: Option<(String, u64)>) {
+        self.in_flight.push_back((Some(id), gtid, false));
+    }
+
+    fn empty(&mut self, gtid: Option<(String, u64)>) {
+        self.in_flight.push_back((None, gtid, true));
+    }
+
+    /// Marks `id` as acked and returns the GTIDs of the completed
+    /// transactions
+    fn ack(&mut self, id: u64) -> Vec<(String, u64)> {
+        if let Some(entry) = self.in_flight.iter_mut().find(|e| e.0 == Some(id)) {
+            entry.2 = true;
+        }
+        let mut completed = Vec::new();
+        while self.in_flight.front().map_or(false, |e| e.2) {
+            if let Some((_, gtid, _)) = self.in_flight.pop_front() {
+                // the transaction is complete once its last event is acked
+                let more = self.in_flight.front().map_or(false, |e| e.1 == gtid);
+                if let (false, Some(gtid)) = (more, gtid) {
+                    completed.push(gtid);
+                }
+            }
+        }
+        completed
+    }
+
+    /// forgets the transactions in flight, returns if `id` was one of them
+    fn fail(&mut self, id: u64) -> bool {
+        let known = self.in_flight.iter().any(|e| e.0 == Some(id));
+        if known {
+            self.in_flight.clear();
+        }
+        known
+    }
+}
+
+pub struct Mysql {
+    onramp_id: TremorUrl,
+    pub config: Config,
+}
+
+pub struct Int {
+    config: Config,
+    onramp_id: TremorUrl,
+    origin_uri: EventOriginUri,
+    store: Box<dyn CheckpointStore>,
+    executed: GtidSet,
+    listener: Option<Receiver<Transaction>>,
+    queue: VecDeque<(Option<(String, u64)>, Value<'static>)>,
+    acks: Acks,
+}
+
+impl fmt::Debug for Int {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Mysql")
+    }
+}
+
+impl onramp::Impl for Mysql {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            if let Some(start) = &config.start_gtid {
+                GtidSet::parse(start)?;
+            }
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for mysql onramp".into())
+        }
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-mysql".to_string(),
+            host: config.host.clone(),
+            port: Some(config.port),
+            path: vec![config.server_id.to_string()],
+        };
+        Ok(Self {
+            store: checkpoint::store(&config.checkpoint, &onramp_id.to_string())?,
+            config: config.clone(),
+            onramp_id,
+            origin_uri,
+            executed: GtidSet::default(),
+            listener: None,
+            queue: VecDeque::new(),
+            acks: Acks::default(),
+        })
+    }
+
+    /// The GTID set to resume after, from the checkpoint, the config or the
+    /// server
+    async fn resume_from(&self) -> Result<GtidSet> {
+        if let Some(checkpoint) = self.store.get(GTID_CHECKPOINT)? {
+            return GtidSet::parse(&checkpoint);
+        }
+        if let Some(start) = &self.config.start_gtid {
+            return GtidSet::parse(start);
+        }
+        let mut conn = Compat::new(Conn::new(self.config.opts())).await?;
+        let executed: Option<String> =
+            Compat::new(conn.query_first("SELECT @@GLOBAL.gtid_executed")).await?;
+        GtidSet::parse(&executed.unwrap_or_default())
+    }
+
+    fn start(&mut self) {
+        let (tx, rx) = bounded(crate::QSIZE);
+        let config = self.config.clone();
+        let executed = self.executed.clone();
+        let onramp_id = self.onramp_id.clone();
+        task::spawn(async move {
+            if let Err(e) = replicate(config, executed, tx).await {
+                error!("[Source::{}] replication failed: {}", onramp_id, e);
+            }
+        });
+        self.listener = Some(rx);
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        if let Some((gtid, data)) = self.queue.pop_front() {
+            self.acks.sent(id, gtid);
+            return Ok(SourceReply::Structured {
+                origin_uri: self.origin_uri.clone(),
+                data: data.into(),
+            });
+        }
+        let listener = if let Some(listener) = self.listener.as_ref() {
+            listener
+        } else {
+            // replication stopped, resume after the acked transactions
+            self.start();
+            return Ok(SourceReply::Empty(1000));
+        };
+        match listener.try_recv() {
+            Ok(transaction) => {
+                if transaction.changes.is_empty() {
+                    self.acks.empty(transaction.gtid);
+                } else {
+                    for change in transaction.changes {
+                        self.queue.push_back((transaction.gtid.clone(), change));
+                    }
+                }
+                Ok(SourceReply::Empty(0))
+            }
+            Err(TryRecvError::Empty) => Ok(SourceReply::Empty(10)),
+            Err(TryRecvError::Closed) => {
+                self.listener = None;
+                Ok(SourceReply::Empty(1000))
+            }
+        }
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        self.executed = self.resume_from().await?;
+        info!(
+            "[Source::{}] replicating after {}",
+            self.onramp_id, self.executed
+        );
+        self.start();
+        Ok(SourceState::Connected)
+    }
+
+    fn ack(&mut self, id: u64) {
+        let completed = self.acks.ack(id);
+        if completed.is_empty() {
+            return;
+        }
+        for (uuid, gno) in completed {
+            self.executed.add(&uuid, gno);
+        }
+        if let Err(e) = self.store.set(GTID_CHECKPOINT, &self.executed.to_string()) {
+            error!(
+                "[Source::{}] failed to checkpoint {}: {}",
+                self.onramp_id, self.executed, e
+            );
+        }
+    }
+
+    fn fail(&mut self, id: u64) {
+        // replicate again after the last fully acked transaction
+        if self.acks.fail(id) {
+            self.queue.clear();
+            self.listener = None;
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for Mysql {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const UUID: &str = "3e11fa47-71ca-11e1-9e33-c80aa9429562";
+
+    #[test]
+    fn gtid_set() -> Result<()> {
+        let set = GtidSet::parse(&format!(
+            "{}:1-5:7, 4E11FA47-71CA-11E1-9E33-C80AA9429562:1-2",
+            UUID
+        ))?;
+        assert_eq!(
+            format!("{}:1-5:7,4e11fa47-71ca-11e1-9e33-c80aa9429562:1-2", UUID),
+            set.to_string()
+        );
+        let mut set = GtidSet::parse(&format!("{}:1-5:7", UUID))?;
+        set.add(UUID, 6);
+        set.add(UUID, 9);
+        assert_eq!(format!("{}:1-7:9", UUID), set.to_string());
+        assert_eq!(GtidSet::default(), GtidSet::parse("")?);
+        assert!(GtidSet::parse(&format!("{}:x", UUID)).is_err());
+        assert_eq!(1, set.sids()?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn uuid() -> Result<()> {
+        let bytes = uuid_bytes(UUID)?;
+        assert_eq!(0x3e, bytes[0]);
+        assert_eq!(UUID, uuid_string(bytes));
+        assert!(uuid_bytes("3e11fa47").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn columns() {
+        use mysql_async::Value as My;
+        assert_eq!(Value::null(), column_value(&My::NULL));
+        assert_eq!(
+            Value::from("snot"),
+            column_value(&My::Bytes(b"snot".to_vec()))
+        );
+        assert_eq!(Value::from(-1), column_value(&My::Int(-1)));
+        assert_eq!(
+            Value::from("2021-03-04 05:06:07.000008"),
+            column_value(&My::Date(2021, 3, 4, 5, 6, 7, 8))
+        );
+        assert_eq!(
+            Value::from("-26:00:01"),
+            column_value(&My::Time(true, 1, 2, 0, 1, 0))
+        );
+    }
+
+    #[test]
+    fn acks() {
+        let gtid = |gno| Some((UUID.to_string(), gno));
+        let mut acks = Acks::default();
+        // a transaction with two events, an empty one and one with one event
+        acks.sent(1, gtid(1));
+        acks.sent(2, gtid(1));
+        acks.empty(gtid(2));
+        acks.sent(3, gtid(3));
+        assert!(acks.ack(1).is_empty());
+        assert!(acks.ack(3).is_empty());
+        assert_eq!(
+            vec![
+                (UUID.to_string(), 1),
+                (UUID.to_string(), 2),
+                (UUID.to_string(), 3)
+            ],
+            acks.ack(2)
+        );
+        acks.sent(4, gtid(4));
+        assert!(!acks.fail(7));
+        assert!(acks.fail(4));
+        assert!(acks.ack(4).is_empty());
+    }
+}
@@ -0,0 +1,565 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! NetFlow v5, v9 and IPFIX decoding of the `flow` onramp
+//!
+//! Every flow record becomes an event, with its fields named after the IPFIX
+//! information elements, e.g. `sourceIPv4Address` or `octetDeltaCount`, for
+//! all three versions. Elements without a known name are passed as `ie{id}`,
+//! enterprise specific ones as `ie{enterprise}.{id}`, with their value as
+//! unsigned integer if it fits and as hex otherwise.
+//!
+//! v9 and IPFIX templates are cached per exporter address, observation
+//! domain (v9 source id) and template id. Data records of templates that
+//! weren't received yet are skipped.
+
+use halfbrown::HashMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tremor_script::prelude::*;
+
+type Result<T> = std::result::Result<T, &'static str>;
+
+const VARIABLE_LENGTH: u16 = 65535;
+const ENTERPRISE_BIT: u16 = 0x8000;
+
+/// information elements of IPv4, IPv6 and MAC addresses
+const IPV4_ADDRESSES: [u16; 6] = [8, 12, 15, 18, 225, 226];
+const IPV6_ADDRESSES: [u16; 3] = [27, 28, 62];
+const MAC_ADDRESSES: [u16; 4] = [56, 57, 80, 81];
+
+/// A reader of big endian values
+pub(crate) struct Reader<'d> {
+    data: &'d [u8],
+}
+
+impl<'d> Reader<'d> {
+    pub(crate) fn new(data: &'d [u8]) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
+    pub(crate) fn bytes(&mut self, n: usize) -> Result<&'d [u8]> {
+        if self.data.len() < n {
+            return Err("truncated datagram");
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64> {
+        Ok((u64::from(self.u32()?) << 32) | u64::from(self.u32()?))
+    }
+
+    /// a length prefixed XDR opaque, padded to 4 bytes
+    pub(crate) fn opaque(&mut self) -> Result<&'d [u8]> {
+        let len = usize::try_from(self.u32()?).map_err(|_| "invalid length")?;
+        let data = self.bytes(len)?;
+        self.bytes((4 - len % 4) % 4)?;
+        Ok(data)
+    }
+}
+
+/// formats an IPv4 or IPv6 address, other lengths as hex
+pub(crate) fn ip(data: &[u8]) -> String {
+    match data {
+        [a, b, c, d] => Ipv4Addr::new(*a, *b, *c, *d).to_string(),
+        _ if data.len() == 16 => {
+            let mut octets = [0; 16];
+            octets.copy_from_slice(data);
+            Ipv6Addr::from(octets).to_string()
+        }
+        _ => hex(data),
+    }
+}
+
+pub(crate) fn mac(data: &[u8]) -> String {
+    data.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A field of a template
+#[derive(Clone, Debug, PartialEq)]
+struct Field {
+    id: u16,
+    enterprise: Option<u32>,
+    length: u16,
+}
+
+/// the cache key of a template: exporter, observation domain and template id
+type TemplateKey = (IpAddr, u32, u16);
+
+/// Decodes NetFlow and IPFIX packets, caching templates per exporter
+#[derive(Debug, Default)]
+pub(crate) struct Decoder {
+    templates: HashMap<TemplateKey, Vec<Field>>,
+}
+
+/// The records of a packet
+#[derive(Debug, Default)]
+pub(crate) struct Packet {
+    pub(crate) records: Vec<Value<'static>>,
+    /// data sets skipped for lack of their template
+    pub(crate) missing_templates: usize,
+}
+
+/// the IPFIX name of an information element
+fn name(id: u16) -> Option<&'static str> {
+    Some(match id {
+        1 => "octetDeltaCount",
+        2 => "packetDeltaCount",
+        3 => "deltaFlowCount",
+        4 => "protocolIdentifier",
+        5 => "ipClassOfService",
+        6 => "tcpControlBits",
+        7 => "sourceTransportPort",
+        8 => "sourceIPv4Address",
+        9 => "sourceIPv4PrefixLength",
+        10 => "ingressInterface",
+        11 => "destinationTransportPort",
+        12 => "destinationIPv4Address",
+        13 => "destinationIPv4PrefixLength",
+        14 => "egressInterface",
+        15 => "ipNextHopIPv4Address",
+        16 => "bgpSourceAsNumber",
+        17 => "bgpDestinationAsNumber",
+        18 => "bgpNextHopIPv4Address",
+        21 => "flowEndSysUpTime",
+        22 => "flowStartSysUpTime",
+        27 => "sourceIPv6Address",
+        28 => "destinationIPv6Address",
+        29 => "sourceIPv6PrefixLength",
+        30 => "destinationIPv6PrefixLength",
+        31 => "flowLabelIPv6",
+        32 => "icmpTypeCodeIPv4",
+        34 => "samplingInterval",
+        35 => "samplingAlgorithm",
+        56 => "sourceMacAddress",
+        57 => "postDestinationMacAddress",
+        58 => "vlanId",
+        59 => "postVlanId",
+        60 => "ipVersion",
+        61 => "flowDirection",
+        62 => "ipNextHopIPv6Address",
+        80 => "destinationMacAddress",
+        81 => "postSourceMacAddress",
+        85 => "octetTotalCount",
+        86 => "packetTotalCount",
+        89 => "forwardingStatus",
+        136 => "flowEndReason",
+        139 => "icmpTypeCodeIPv6",
+        148 => "flowId",
+        150 => "flowStartSeconds",
+        151 => "flowEndSeconds",
+        152 => "flowStartMilliseconds",
+        153 => "flowEndMilliseconds",
+        154 => "flowStartMicroseconds",
+        155 => "flowEndMicroseconds",
+        156 => "flowStartNanoseconds",
+        157 => "flowEndNanoseconds",
+        176 => "icmpTypeIPv4",
+        177 => "icmpCodeIPv4",
+        225 => "postNATSourceIPv4Address",
+        226 => "postNATDestinationIPv4Address",
+        227 => "postNAPTSourceTransportPort",
+        228 => "postNAPTDestinationTransportPort",
+        _ => return None,
+    })
+}
+
+fn key(field: &Field) -> String {
+    match (field.enterprise, name(field.id)) {
+        (None, Some(name)) => name.to_string(),
+        (None, None) => format!("ie{}", field.id),
+        (Some(enterprise), _) => format!("ie{}.{}", enterprise, field.id),
+    }
+}
+
+/// decodes the value of a field by its information element
+fn value(field: &Field, data: &[u8]) -> Value<'static> {
+    let standard = field.enterprise.is_none();
+    let address = match data.len() {
+        4 => IPV4_ADDRESSES.contains(&field.id),
+        16 => IPV6_ADDRESSES.contains(&field.id),
+        _ => false,
+    };
+    if standard && address {
+        Value::from(ip(data))
+    } else if standard && data.len() == 6 && MAC_ADDRESSES.contains(&field.id) {
+        Value::from(mac(data))
+    } else if data.len() <= 8 {
+        Value::from(data.iter().fold(0_u64, |n, b| (n << 8) | u64::from(*b)))
+    } else {
+        Value::from(hex(data))
+    }
+}
+
+/// reads the fields of a template
+fn fields(reader: &mut Reader<'_>, count: u16, ipfix: bool) -> Result<Vec<Field>> {
+    let mut fields = Vec::with_capacity(usize::from(count));
+    for _ in 0..count {
+        let id = reader.u16()?;
+        let length = reader.u16()?;
+        let (id, enterprise) = if ipfix && id & ENTERPRISE_BIT != 0 {
+            (id & !ENTERPRISE_BIT, Some(reader.u32()?))
+        } else {
+            (id, None)
+        };
+        if !ipfix && length == VARIABLE_LENGTH {
+            return Err("variable length field in a NetFlow v9 template");
+        }
+        fields.push(Field {
+            id,
+            enterprise,
+            length,
+        });
+    }
+    Ok(fields)
+}
+
+/// the minimal length of a record of a template
+fn min_length(fields: &[Field]) -> usize {
+    fields
+        .iter()
+        .map(|f| {
+            if f.length == VARIABLE_LENGTH {
+                1
+            } else {
+                usize::from(f.length)
+            }
+        })
+        .sum()
+}
+
+impl Decoder {
+    /// decodes a NetFlow v5, v9 or IPFIX packet from `exporter`
+    pub(crate) fn decode(&mut self, exporter: IpAddr, data: &[u8]) -> Result<Packet> {
+        let mut reader = Reader::new(data);
+        match reader.u16()? {
+            5 => v5(exporter, &mut reader),
+            9 => self.v9(exporter, &mut reader),
+            10 => self.ipfix(exporter, &mut reader),
+            _ => Err("unsupported NetFlow version"),
+        }
+    }
+
+    fn v9(&mut self, exporter: IpAddr, reader: &mut Reader<'_>) -> Result<Packet> {
+        let _count = reader.u16()?;
+        let uptime = reader.u32()?;
+        let export_time = reader.u32()?;
+        let sequence = reader.u32()?;
+        let domain = reader.u32()?;
+        let header = literal!({
+            "format": "netflow9",
+            "exporter": exporter.to_string(),
+            "domain": domain,
+            "uptime": uptime,
+            "export_time": export_time,
+            "sequence": sequence
+        });
+        self.sets(exporter, domain, reader, &header, false)
+    }
+
+    fn ipfix(&mut self, exporter: IpAddr, reader: &mut Reader<'_>) -> Result<Packet> {
+        let length = usize::from(reader.u16()?);
+        // the length includes the 4 bytes read already
+        let mut reader = Reader::new(reader.bytes(length.checked_sub(4).ok_or("invalid length")?)?);
+        let export_time = reader.u32()?;
+        let sequence = reader.u32()?;
+        let domain = reader.u32()?;
+        let header = literal!({
+            "format": "ipfix",
+            "exporter": exporter.to_string(),
+            "domain": domain,
+            "export_time": export_time,
+            "sequence": sequence
+        });
+        self.sets(exporter, domain, &mut reader, &header, true)
+    }
+
+    /// decodes the flow sets of a v9 or the sets of an IPFIX packet
+    fn sets(
+        &mut self,
+        exporter: IpAddr,
+        domain: u32,
+        reader: &mut Reader<'_>,
+        header: &Value<'static>,
+        ipfix: bool,
+    ) -> Result<Packet> {
+        let (template_set, options_set) = if ipfix { (2, 3) } else { (0, 1) };
+        let mut packet = Packet::default();
+        while reader.remaining() >= 4 {
+            let id = reader.u16()?;
+            let length = usize::from(reader.u16()?);
+            let mut set =
+                Reader::new(reader.bytes(length.checked_sub(4).ok_or("invalid set length")?)?);
+            if id == template_set {
+                while set.remaining() >= 4 {
+                    let template_id = set.u16()?;
+                    let count = set.u16()?;
+                    if count == 0 {
+                        // withdrawn
+                        self.templates.remove(&(exporter, domain, template_id));
+                        continue;
+                    }
+                    let fields = fields(&mut set, count, ipfix)?;
+                    self.templates
+                        .insert((exporter, domain, template_id), fields);
+                }
+            } else if id == options_set {
+                while set.remaining() >= 6 {
+                    let template_id = set.u16()?;
+                    let fields = if ipfix {
+                        let count = set.u16()?;
+                        let _scope_count = set.u16()?;
+                        fields(&mut set, count, true)?
+                    } else {
+                        // v9 has the lengths of the scope and option fields in bytes
+                        let scope_length = set.u16()?;
+                        let option_length = set.u16()?;
+                        fields(&mut set, scope_length / 4 + option_length / 4, false)?
+                    };
+                    self.templates
+                        .insert((exporter, domain, template_id), fields);
+                }
+            } else if id >= 256 {
+                let fields = if let Some(fields) = self.templates.get(&(exporter, domain, id)) {
+                    fields
+                } else {
+                    packet.missing_templates += 1;
+                    continue;
+                };
+                let min_length = min_length(fields).max(1);
+                // the remainder is padding
+                while set.remaining() >= min_length {
+                    let mut flow = Value::object_with_capacity(fields.len());
+                    for field in fields {
+                        let length = if field.length == VARIABLE_LENGTH {
+                            match set.u8()? {
+                                255 => usize::from(set.u16()?),
+                                length => usize::from(length),
+                            }
+                        } else {
+                            usize::from(field.length)
+                        };
+                        flow.try_insert(key(field), value(field, set.bytes(length)?));
+                    }
+                    let mut record = header.clone();
+                    record.try_insert("template_id", id);
+                    record.try_insert("flow", flow);
+                    packet.records.push(record);
+                }
+            }
+        }
+        Ok(packet)
+    }
+}
+
+/// decodes the fixed records of a v5 packet
+fn v5(exporter: IpAddr, reader: &mut Reader<'_>) -> Result<Packet> {
+    let count = reader.u16()?;
+    let uptime = reader.u32()?;
+    let export_time = reader.u32()?;
+    let _nsecs = reader.u32()?;
+    let sequence = reader.u32()?;
+    let _engine_type = reader.u8()?;
+    let engine_id = reader.u8()?;
+    let sampling = reader.u16()?;
+    let mut packet = Packet::default();
+    for _ in 0..count {
+        let mut r = Reader::new(reader.bytes(48)?);
+        let mut flow = Value::object_with_capacity(18);
+        flow.try_insert("sourceIPv4Address", ip(r.bytes(4)?));
+        flow.try_insert("destinationIPv4Address", ip(r.bytes(4)?));
+        flow.try_insert("ipNextHopIPv4Address", ip(r.bytes(4)?));
+        flow.try_insert("ingressInterface", r.u16()?);
+        flow.try_insert("egressInterface", r.u16()?);
+        flow.try_insert("packetDeltaCount", r.u32()?);
+        flow.try_insert("octetDeltaCount", r.u32()?);
+        flow.try_insert("flowStartSysUpTime", r.u32()?);
+        flow.try_insert("flowEndSysUpTime", r.u32()?);
+        flow.try_insert("sourceTransportPort", r.u16()?);
+        flow.try_insert("destinationTransportPort", r.u16()?);
+        let _pad = r.u8()?;
+        flow.try_insert("tcpControlBits", r.u8()?);
+        flow.try_insert("protocolIdentifier", r.u8()?);
+        flow.try_insert("ipClassOfService", r.u8()?);
+        flow.try_insert("bgpSourceAsNumber", r.u16()?);
+        flow.try_insert("bgpDestinationAsNumber", r.u16()?);
+        flow.try_insert("sourceIPv4PrefixLength", r.u8()?);
+        flow.try_insert("destinationIPv4PrefixLength", r.u8()?);
+        packet.records.push(literal!({
+            "format": "netflow5",
+            "exporter": exporter.to_string(),
+            "domain": engine_id,
+            "uptime": uptime,
+            "export_time": export_time,
+            "sequence": sequence,
+            // the upper 2 bits are the sampling mode
+            "sampling_interval": sampling & 0x3fff,
+            "flow": flow
+        }));
+    }
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const EXPORTER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+    fn u16s(out: &mut Vec<u8>, values: &[u16]) {
+        for v in values {
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn v5_records() -> Result<()> {
+        let mut data = Vec::new();
+        u16s(&mut data, &[5, 1]);
+        data.extend_from_slice(&[
+            0, 0, 0, 100, 0, 0, 0, 200, 0, 0, 0, 0, 0, 0, 0, 7, 0, 3, 0, 10,
+        ]);
+        data.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 2, 0, 0, 0, 0]);
+        u16s(&mut data, &[1, 2]);
+        data.extend_from_slice(&[0, 0, 0, 5, 0, 0, 1, 0, 0, 0, 0, 10, 0, 0, 0, 20]);
+        u16s(&mut data, &[1234, 443]);
+        data.extend_from_slice(&[0, 0x12, 6, 0, 0, 0, 0, 0, 24, 24, 0, 0]);
+
+        let packet = Decoder::default().decode(EXPORTER, &data)?;
+        assert_eq!(1, packet.records.len());
+        let record = &packet.records[0];
+        assert_eq!(Some("netflow5"), record.get_str("format"));
+        assert_eq!(Some(3), record.get_u64("domain"));
+        assert_eq!(Some(10), record.get_u64("sampling_interval"));
+        let flow = record.get("flow");
+        assert_eq!(Some("192.168.0.1"), flow.get_str("sourceIPv4Address"));
+        assert_eq!(Some(443), flow.get_u64("destinationTransportPort"));
+        assert_eq!(Some(256), flow.get_u64("octetDeltaCount"));
+        assert_eq!(Some(0x12), flow.get_u64("tcpControlBits"));
+        assert_eq!(Some(6), flow.get_u64("protocolIdentifier"));
+
+        assert!(Decoder::default().decode(EXPORTER, &data[..60]).is_err());
+        Ok(())
+    }
+
+    /// an IPFIX template set for template 256 with source and destination
+    /// address, octets and an enterprise specific variable length field
+    fn ipfix_template() -> Vec<u8> {
+        let mut set = Vec::new();
+        u16s(
+            &mut set,
+            &[2, 28, 256, 4, 8, 4, 12, 4, 1, 8, 0x8001, VARIABLE_LENGTH],
+        );
+        set.extend_from_slice(&9_u32.to_be_bytes());
+        set
+    }
+
+    fn ipfix_data() -> Vec<u8> {
+        let mut set = Vec::new();
+        u16s(&mut set, &[256, 28]);
+        set.extend_from_slice(&[
+            10, 1, 1, 1, 10, 2, 2, 2, 0, 0, 0, 0, 0, 0, 4, 0, 3, b'a', b'b', b'c',
+        ]);
+        // padding
+        set.extend_from_slice(&[0, 0, 0, 0]);
+        set
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn ipfix(sets: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = sets.concat();
+        let mut data = Vec::new();
+        u16s(&mut data, &[10, (16 + body.len()) as u16]);
+        data.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 42]);
+        data.extend(body);
+        data
+    }
+
+    #[test]
+    fn ipfix_templates() -> Result<()> {
+        let mut decoder = Decoder::default();
+        // data before its template
+        let packet = decoder.decode(EXPORTER, &ipfix(&[ipfix_data()]))?;
+        assert!(packet.records.is_empty());
+        assert_eq!(1, packet.missing_templates);
+
+        let packet = decoder.decode(EXPORTER, &ipfix(&[ipfix_template(), ipfix_data()]))?;
+        assert_eq!(1, packet.records.len());
+        let record = &packet.records[0];
+        assert_eq!(Some("ipfix"), record.get_str("format"));
+        assert_eq!(Some(42), record.get_u64("domain"));
+        assert_eq!(Some(256), record.get_u64("template_id"));
+        let flow = record.get("flow");
+        assert_eq!(Some("10.1.1.1"), flow.get_str("sourceIPv4Address"));
+        assert_eq!(Some("10.2.2.2"), flow.get_str("destinationIPv4Address"));
+        assert_eq!(Some(1024), flow.get_u64("octetDeltaCount"));
+        assert_eq!(Some(0x61_62_63), flow.get_u64("ie9.1"));
+
+        // templates are cached per exporter
+        let packet = decoder.decode(EXPORTER, &ipfix(&[ipfix_data()]))?;
+        assert_eq!(1, packet.records.len());
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let packet = decoder.decode(other, &ipfix(&[ipfix_data()]))?;
+        assert_eq!(1, packet.missing_templates);
+        Ok(())
+    }
+
+    #[test]
+    fn v9_templates() -> Result<()> {
+        let mut data = Vec::new();
+        u16s(&mut data, &[9, 2]);
+        data.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0]);
+        // template 300 with protocol and destination port
+        u16s(&mut data, &[0, 16, 300, 2, 4, 1, 11, 2]);
+        // two records and a padding byte
+        u16s(&mut data, &[300, 11]);
+        data.extend_from_slice(&[17, 0, 53, 6, 1, 187, 0]);
+
+        let packet = Decoder::default().decode(EXPORTER, &data)?;
+        assert_eq!(2, packet.records.len());
+        assert_eq!(Some("netflow9"), packet.records[0].get_str("format"));
+        let flow = packet.records[1].get("flow");
+        assert_eq!(Some(6), flow.get_u64("protocolIdentifier"));
+        assert_eq!(Some(443), flow.get_u64("destinationTransportPort"));
+        Ok(())
+    }
+}
@@ -0,0 +1,809 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![cfg(not(tarpaulin_include))]
+
+//! # Postgres CDC Onramp
+//!
+//! Reads the changes of a postgres database from a logical replication slot
+//! as `insert`, `update` and `delete` events:
+//!
+//! ```json
+//! {
+//!   "op": "update",
+//!   "schema": "public",
+//!   "table": "orders",
+//!   "lsn": "0/16B3748",
+//!   "new": {"id": 1, "state": "shipped"},
+//!   "old": {"id": 1}
+//! }
+//! ```
+//!
+//! The slot is decoded with the builtin `pgoutput` plugin, for the tables of
+//! `publication`, or with `wal2json`. `old` holds the replica identity of
+//! updated and deleted rows and is `null` if postgres doesn't send it.
+//!
+//! The slot only moves past a transaction once all of its events are acked.
+//! After a failed event or a restart the changes are read again from the
+//! last fully acked transaction, so they are delivered at least once.
+//!
+//! The database needs `wal_level = logical`, the slot is created if missing:
+//!
+//! ```yaml
+//! host: localhost
+//! user: replicator
+//! password: snot
+//! dbname: shop
+//! slot: tremor
+//! plugin: pgoutput
+//! publication: tremor
+//! ```
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::source::prelude::*;
+use async_compat::Compat;
+use halfbrown::HashMap;
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+use tokio_postgres::{Client, NoTls};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Plugin {
+    /// the builtin logical replication plugin
+    Pgoutput,
+    /// the `wal2json` extension
+    Wal2json,
+}
+
+impl Default for Plugin {
+    fn default() -> Self {
+        Self::Pgoutput
+    }
+}
+
+impl Plugin {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Pgoutput => "pgoutput",
+            Self::Wal2json => "wal2json",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    pub host: String,
+    #[serde(default = "d_port")]
+    pub port: u16,
+    pub user: String,
+    pub password: String,
+    pub dbname: String,
+    /// name of the logical replication slot
+    pub slot: String,
+    /// output plugin decoding the slot
+    #[serde(default)]
+    pub plugin: Plugin,
+    /// publication of the tables to read with `pgoutput`
+    #[serde(default)]
+    pub publication: Option<String>,
+    /// create the slot if it doesn't exist
+    #[serde(default = "d_true")]
+    pub create_slot: bool,
+    /// maximum number of changes read at once
+    #[serde(default = "d_batch_size")]
+    pub batch_size: i32,
+    /// milliseconds to wait after reading no changes
+    #[serde(default = "d_poll_interval")]
+    pub poll_interval: u64,
+}
+
+fn d_port() -> u16 {
+    5432
+}
+
+fn d_true() -> bool {
+    true
+}
+
+fn d_batch_size() -> i32 {
+    1000
+}
+
+fn d_poll_interval() -> u64 {
+    500
+}
+
+impl ConfigImpl for Config {}
+
+/// Parses a textual LSN like `16/B374D848`
+fn parse_lsn(lsn: &str) -> Result<u64> {
+    let invalid = || Error::from(format!("Invalid LSN: {}", lsn));
+    let mut parts = lsn.splitn(2, '/');
+    let hi = parts.next().ok_or_else(invalid)?;
+    let lo = parts.next().ok_or_else(invalid)?;
+    let hi = u64::from_str_radix(hi, 16).map_err(|_| invalid())?;
+    let lo = u64::from_str_radix(lo, 16).map_err(|_| invalid())?;
+    Ok(hi << 32 | lo)
+}
+
+fn format_lsn(lsn: u64) -> String {
+    format!("{:X}/{:X}", lsn >> 32, lsn & 0xFFFF_FFFF)
+}
+
+/// A changed row
+#[derive(Debug, Clone, PartialEq)]
+struct Change {
+    op: &'static str,
+    schema: String,
+    table: String,
+    new: Option<Value<'static>>,
+    old: Option<Value<'static>>,
+}
+
+impl Change {
+    fn into_value(self, lsn: u64) -> Value<'static> {
+        literal!({
+            "op": self.op,
+            "schema": self.schema,
+            "table": self.table,
+            "lsn": format_lsn(lsn),
+            "new": self.new.unwrap_or_else(Value::null),
+            "old": self.old.unwrap_or_else(Value::null)
+        })
+    }
+}
+
+/// A decoded row of a replication slot
+#[derive(Debug, PartialEq)]
+enum Decoded {
+    Begin,
+    Change(Change),
+    Commit,
+    /// messages without events, like relations and truncates
+    Skip,
+}
+
+fn op_of(action: u8) -> Option<&'static str> {
+    match action {
+        b'I' => Some("insert"),
+        b'U' => Some("update"),
+        b'D' => Some("delete"),
+        _ => None,
+    }
+}
+
+/// Decodes a change of `wal2json` with `format-version` 2
+fn decode_wal2json(data: &str) -> Result<Decoded> {
+    let mut raw = data.as_bytes().to_vec();
+    let change = tremor_value::parse_to_value(&mut raw)
+        .map_err(|e| Error::from(format!("Invalid wal2json change: {}", e)))?;
+    let action = change.get_str("action").unwrap_or_default();
+    let columns = |field: &str| {
+        change.get_array(field).map(|columns| {
+            columns
+                .iter()
+                .filter_map(|c| {
+                    let name = c.get_str("name")?.to_string();
+                    Some((name, c.get("value")?.clone_static()))
+                })
+                .collect::<Value<'static>>()
+        })
+    };
+    Ok(match action.as_bytes() {
+        b"B" => Decoded::Begin,
+        b"C" => Decoded::Commit,
+        [a] if op_of(*a).is_some() => Decoded::Change(Change {
+            op: op_of(*a).unwrap_or_default(),
+            schema: change.get_str("schema").unwrap_or_default().to_string(),
+            table: change.get_str("table").unwrap_or_default().to_string(),
+            new: columns("columns"),
+            old: columns("identity"),
+        }),
+        _ => Decoded::Skip,
+    })
+}
+
+/// Reads the fields of a `pgoutput` message
+struct Reader<'data> {
+    data: &'data [u8],
+}
+
+impl<'data> Reader<'data> {
+    fn bytes(&mut self, n: usize) -> Result<&'data [u8]> {
+        if self.data.len() < n {
+            return Err("Truncated pgoutput message".into());
+        }
+        let (bytes, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let end = self
+            .data
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| Error::from("Unterminated string in pgoutput message"))?;
+        let s = String::from_utf8_lossy(self.bytes(end)?).to_string();
+        self.bytes(1)?;
+        Ok(s)
+    }
+}
+
+/// A table as described by a `pgoutput` relation message
+#[derive(Debug)]
+struct Relation {
+    schema: String,
+    table: String,
+    /// name and type oid of the columns
+    columns: Vec<(String, u32)>,
+}
+
+/// Converts a column in text format to a value by its type oid
+fn column_value(text: &str, oid: u32) -> Value<'static> {
+    match oid {
+        // bool
+        16 => Value::from(text == "t"),
+        // int8, int2, int4
+        20 | 21 | 23 => text
+            .parse::<i64>()
+            .map_or_else(|_| Value::from(text.to_string()), Value::from),
+        // float4, float8
+        700 | 701 => text
+            .parse::<f64>()
+            .map_or_else(|_| Value::from(text.to_string()), Value::from),
+        _ => Value::from(text.to_string()),
+    }
+}
+
+/// Decodes the binary `pgoutput` protocol, version 1
+#[derive(Debug, Default)]
+struct PgOutput {
+    relations: HashMap<u32, Relation>,
+}
+
+impl PgOutput {
+    fn tuple(relation: &Relation, reader: &mut Reader) -> Result<Value<'static>> {
+        let n = reader.u16()?;
+        let mut tuple = Value::object_with_capacity(usize::from(n));
+        for i in 0..usize::from(n) {
+            let (name, oid) = relation
+                .columns
+                .get(i)
+                .ok_or_else(|| Error::from("Tuple with more columns than its relation"))?;
+            match reader.u8()? {
+                b'n' => {
+                    tuple.try_insert(name.clone(), Value::null());
+                }
+                // unchanged toasted value, not sent
+                b'u' => (),
+                b't' => {
+                    let len = reader.u32()?;
+                    let text = String::from_utf8_lossy(
+                        reader.bytes(usize::try_from(len).unwrap_or(usize::MAX))?,
+                    );
+                    tuple.try_insert(name.clone(), column_value(&text, *oid));
+                }
+                kind => {
+                    return Err(format!("Invalid tuple column kind: {}", kind).into());
+                }
+            }
+        }
+        Ok(tuple)
+    }
+
+    fn decode(&mut self, data: &[u8]) -> Result<Decoded> {
+        let mut reader = Reader { data };
+        let kind = reader.u8()?;
+        match kind {
+            b'B' => Ok(Decoded::Begin),
+            b'C' => Ok(Decoded::Commit),
+            b'R' => {
+                let id = reader.u32()?;
+                let schema = reader.string()?;
+                let table = reader.string()?;
+                let _replica_identity = reader.u8()?;
+                let n = reader.u16()?;
+                let mut columns = Vec::with_capacity(usize::from(n));
+                for _ in 0..n {
+                    let _flags = reader.u8()?;
+                    let name = reader.string()?;
+                    let oid = reader.u32()?;
+                    let _type_modifier = reader.u32()?;
+                    columns.push((name, oid));
+                }
+                self.relations.insert(
+                    id,
+                    Relation {
+                        schema,
+                        table,
+                        columns,
+                    },
+                );
+                Ok(Decoded::Skip)
+            }
+            b'I' | b'U' | b'D' => {
+                let id = reader.u32()?;
+                let relation = self
+                    .relations
+                    .get(&id)
+                    .ok_or_else(|| Error::from(format!("Change of unknown relation {}", id)))?;
+                let mut old = None;
+                let mut new = None;
+                let mut marker = reader.u8()?;
+                if marker == b'K' || marker == b'O' {
+                    old = Some(Self::tuple(relation, &mut reader)?);
+                    if kind == b'U' {
+                        marker = reader.u8()?;
+                    }
+                }
+                if marker == b'N' {
+                    new = Some(Self::tuple(relation, &mut reader)?);
+                }
+                Ok(Decoded::Change(Change {
+                    op: op_of(kind).unwrap_or_default(),
+                    schema: relation.schema.clone(),
+                    table: relation.table.clone(),
+                    new,
+                    old,
+                }))
+            }
+            _ => Ok(Decoded::Skip),
+        }
+    }
+}
+
+/// Tracks the events in flight, to confirm transactions once all their
+/// events are acked
+#[derive(Debug, Default)]
+struct Acks {
+    /// commit LSN of the events in flight and whether they were acked
+    in_flight: BTreeMap<u64, (u64, bool)>,
+    /// LSN up to which all transactions are acked
+    confirmable: u64,
+    /// LSN the slot was advanced to
+    confirmed: u64,
+}
+
+impl Acks {
+    fn sent(&mut self, id: u64, lsn: u64) {
+        self.in_flight.insert(id, (lsn, false));
+    }
+
+    /// a transaction without events
+    fn empty(&mut self, lsn: u64) {
+        if self.in_flight.is_empty() {
+            self.confirmable = self.confirmable.max(lsn);
+        }
+    }
+
+    fn ack(&mut self, id: u64) {
+        if let Some((_, acked)) = self.in_flight.get_mut(&id) {
+            *acked = true;
+        }
+        loop {
+            let front = self
+                .in_flight
+                .iter()
+                .next()
+                .map(|(id, (lsn, acked))| (*id, *lsn, *acked));
+            if let Some((id, lsn, true)) = front {
+                self.in_flight.remove(&id);
+                // the transaction is complete once its last event is acked
+                if self
+                    .in_flight
+                    .values()
+                    .next()
+                    .map_or(true, |(l, _)| *l != lsn)
+                {
+                    self.confirmable = self.confirmable.max(lsn);
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// forgets the events in flight, returns if `id` was one of them
+    fn fail(&mut self, id: u64) -> bool {
+        let known = self.in_flight.contains_key(&id);
+        if known {
+            self.in_flight.clear();
+        }
+        known
+    }
+
+    fn to_confirm(&self) -> Option<u64> {
+        (self.confirmable > self.confirmed).then(|| self.confirmable)
+    }
+}
+
+pub struct PostgresCdc {
+    onramp_id: TremorUrl,
+    pub config: Config,
+}
+
+pub struct Int {
+    config: Config,
+    onramp_id: TremorUrl,
+    origin_uri: EventOriginUri,
+    cli: Option<Client>,
+    pgoutput: PgOutput,
+    /// changes of the transaction being read
+    txn: Vec<Change>,
+    /// events of read transactions with their commit LSN
+    queue: VecDeque<(u64, Value<'static>)>,
+    /// commit LSN of the last transaction read
+    read_lsn: u64,
+    acks: Acks,
+}
+
+impl fmt::Debug for Int {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PostgresCdc")
+    }
+}
+
+impl onramp::Impl for PostgresCdc {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            if config.plugin == Plugin::Pgoutput && config.publication.is_none() {
+                return Err("postgres-cdc onramp with pgoutput requires a publication".into());
+            }
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for postgres-cdc onramp".into())
+        }
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Self {
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-postgres-cdc".to_string(),
+            host: config.host.clone(),
+            port: Some(config.port),
+            path: vec![config.dbname.clone(), config.slot.clone()],
+        };
+        Self {
+            config: config.clone(),
+            onramp_id,
+            origin_uri,
+            cli: None,
+            pgoutput: PgOutput::default(),
+            txn: Vec::new(),
+            queue: VecDeque::new(),
+            read_lsn: 0,
+            acks: Acks::default(),
+        }
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        let conn_str = format!(
+            "host={} user={} password={} port={} dbname={}",
+            self.config.host,
+            self.config.user,
+            self.config.password,
+            self.config.port,
+            self.config.dbname
+        );
+        let (client, connection) = Compat::new(tokio_postgres::connect(&conn_str, NoTls)).await?;
+        task::spawn(async move {
+            if let Err(e) = Compat::new(connection).await {
+                error!("connection error: {}", e);
+            }
+        });
+        if self.config.create_slot {
+            Compat::new(client.execute(
+                "SELECT pg_create_logical_replication_slot($1, $2) \
+                 WHERE NOT EXISTS (SELECT 1 FROM pg_replication_slots WHERE slot_name = $1)",
+                &[&self.config.slot, &self.config.plugin.name()],
+            ))
+            .await?;
+        }
+        self.cli = Some(client);
+        Ok(())
+    }
+
+    /// Advances the slot past the acked transactions and reads the next
+    /// changes
+    async fn poll(&mut self) -> Result<()> {
+        let client = self
+            .cli
+            .as_ref()
+            .ok_or_else(|| Error::from("No postgres connection"))?;
+        if let Some(lsn) = self.acks.to_confirm() {
+            Compat::new(client.execute(
+                "SELECT pg_replication_slot_advance($1, $2::text::pg_lsn)",
+                &[&self.config.slot, &format_lsn(lsn)],
+            ))
+            .await?;
+            self.acks.confirmed = lsn;
+        }
+        let slot = &self.config.slot;
+        let batch_size = &self.config.batch_size;
+        let pgoutput = &mut self.pgoutput;
+        let rows: Vec<(String, Result<Decoded>)> = match self.config.plugin {
+            Plugin::Pgoutput => {
+                let publication = self.config.publication.clone().unwrap_or_default();
+                Compat::new(client.query(
+                    "SELECT lsn::text, data FROM pg_logical_slot_peek_binary_changes(\
+                     $1, NULL, $2, 'proto_version', '1', 'publication_names', $3)",
+                    &[slot, batch_size, &publication],
+                ))
+                .await?
+                .iter()
+                .map(|row| {
+                    let data: Vec<u8> = row.get(1);
+                    (row.get(0), pgoutput.decode(&data))
+                })
+                .collect()
+            }
+            Plugin::Wal2json => Compat::new(client.query(
+                "SELECT lsn::text, data FROM pg_logical_slot_peek_changes(\
+                 $1, NULL, $2, 'format-version', '2')",
+                &[slot, batch_size],
+            ))
+            .await?
+            .iter()
+            .map(|row| {
+                let data: String = row.get(1);
+                (row.get(0), decode_wal2json(&data))
+            })
+            .collect(),
+        };
+        for (lsn, decoded) in rows {
+            match decoded? {
+                Decoded::Begin => self.txn.clear(),
+                Decoded::Change(change) => self.txn.push(change),
+                Decoded::Commit => {
+                    let lsn = parse_lsn(&lsn)?;
+                    // transactions read before, that aren't confirmed yet
+                    if lsn <= self.read_lsn {
+                        self.txn.clear();
+                        continue;
+                    }
+                    self.read_lsn = lsn;
+                    if self.txn.is_empty() {
+                        self.acks.empty(lsn);
+                    }
+                    for change in self.txn.drain(..) {
+                        self.queue.push_back((lsn, change.into_value(lsn)));
+                    }
+                }
+                Decoded::Skip => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        if let Some((lsn, data)) = self.queue.pop_front() {
+            self.acks.sent(id, lsn);
+            return Ok(SourceReply::Structured {
+                origin_uri: self.origin_uri.clone(),
+                data: data.into(),
+            });
+        }
+        if self.cli.is_none() {
+            if let Err(e) = self.connect().await {
+                warn!("[Source::{}] failed to connect: {}", self.onramp_id, e);
+                return Ok(SourceReply::Empty(self.config.poll_interval));
+            }
+        }
+        if let Err(e) = self.poll().await {
+            warn!("[Source::{}] failed to read changes: {}", self.onramp_id, e);
+            self.cli = None;
+            self.txn.clear();
+        }
+        if self.queue.is_empty() {
+            Ok(SourceReply::Empty(self.config.poll_interval))
+        } else {
+            Ok(SourceReply::Empty(0))
+        }
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        Ok(SourceState::Connected)
+    }
+
+    fn ack(&mut self, id: u64) {
+        self.acks.ack(id);
+    }
+
+    fn fail(&mut self, id: u64) {
+        // read the changes again from the last fully acked transaction
+        if self.acks.fail(id) {
+            self.queue.clear();
+            self.txn.clear();
+            self.read_lsn = self.acks.confirmable;
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for PostgresCdc {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config);
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lsn() -> Result<()> {
+        assert_eq!(0x16_B374_D848, parse_lsn("16/B374D848")?);
+        assert_eq!("16/B374D848", format_lsn(0x16_B374_D848));
+        assert_eq!("0/0", format_lsn(0));
+        assert!(parse_lsn("16").is_err());
+        assert!(parse_lsn("x/1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn wal2json() -> Result<()> {
+        assert_eq!(Decoded::Begin, decode_wal2json(r#"{"action":"B"}"#)?);
+        assert_eq!(Decoded::Commit, decode_wal2json(r#"{"action":"C"}"#)?);
+        assert_eq!(Decoded::Skip, decode_wal2json(r#"{"action":"T"}"#)?);
+        let update = decode_wal2json(
+            r#"{"action":"U","schema":"public","table":"orders",
+                "columns":[{"name":"id","type":"integer","value":1},{"name":"state","type":"text","value":"shipped"}],
+                "identity":[{"name":"id","type":"integer","value":1}]}"#,
+        )?;
+        assert_eq!(
+            Decoded::Change(Change {
+                op: "update",
+                schema: "public".to_string(),
+                table: "orders".to_string(),
+                new: Some(literal!({"id": 1, "state": "shipped"})),
+                old: Some(literal!({"id": 1})),
+            }),
+            update
+        );
+        assert!(decode_wal2json("{").is_err());
+        Ok(())
+    }
+
+    fn relation() -> Vec<u8> {
+        let mut msg = vec![b'R'];
+        msg.extend_from_slice(&16384_u32.to_be_bytes());
+        msg.extend_from_slice(b"public\0orders\0");
+        msg.push(b'd');
+        msg.extend_from_slice(&2_u16.to_be_bytes());
+        for (name, oid) in &[("id", 23_u32), ("state", 25)] {
+            msg.push(1);
+            msg.extend_from_slice(name.as_bytes());
+            msg.push(0);
+            msg.extend_from_slice(&oid.to_be_bytes());
+            msg.extend_from_slice(&u32::MAX.to_be_bytes());
+        }
+        msg
+    }
+
+    fn tuple(msg: &mut Vec<u8>, columns: &[Option<&str>]) {
+        msg.extend_from_slice(&2_u16.to_be_bytes());
+        for column in columns {
+            if let Some(text) = column {
+                msg.push(b't');
+                msg.extend_from_slice(&u32::try_from(text.len()).unwrap_or_default().to_be_bytes());
+                msg.extend_from_slice(text.as_bytes());
+            } else {
+                msg.push(b'n');
+            }
+        }
+    }
+
+    #[test]
+    fn pgoutput() -> Result<()> {
+        let mut decoder = PgOutput::default();
+        let mut update = vec![b'U'];
+        update.extend_from_slice(&16384_u32.to_be_bytes());
+        update.push(b'O');
+        tuple(&mut update, &[Some("1"), Some("new")]);
+        update.push(b'N');
+        tuple(&mut update, &[Some("1"), None]);
+        // relations are sent before their first change
+        assert!(decoder.decode(&update).is_err());
+
+        assert_eq!(Decoded::Skip, decoder.decode(&relation())?);
+        assert_eq!(
+            Decoded::Change(Change {
+                op: "update",
+                schema: "public".to_string(),
+                table: "orders".to_string(),
+                new: Some(literal!({"id": 1, "state": null})),
+                old: Some(literal!({"id": 1, "state": "new"})),
+            }),
+            decoder.decode(&update)?
+        );
+
+        let mut insert = vec![b'I'];
+        insert.extend_from_slice(&16384_u32.to_be_bytes());
+        insert.push(b'N');
+        tuple(&mut insert, &[Some("2"), Some("new")]);
+        match decoder.decode(&insert)? {
+            Decoded::Change(change) => {
+                assert_eq!("insert", change.op);
+                assert_eq!(None, change.old);
+            }
+            other => return Err(format!("expected a change, got {:?}", other).into()),
+        }
+        assert!(decoder.decode(&insert[..8]).is_err());
+        assert_eq!(Decoded::Begin, decoder.decode(b"B")?);
+        Ok(())
+    }
+
+    #[test]
+    fn acks() {
+        let mut acks = Acks::default();
+        // a transaction with two events and one with one
+        acks.sent(1, 10);
+        acks.sent(2, 10);
+        acks.sent(3, 20);
+        acks.ack(1);
+        assert_eq!(None, acks.to_confirm());
+        acks.ack(3);
+        assert_eq!(None, acks.to_confirm());
+        acks.ack(2);
+        assert_eq!(Some(20), acks.to_confirm());
+        acks.confirmed = 20;
+        acks.empty(30);
+        assert_eq!(Some(30), acks.to_confirm());
+
+        acks.sent(4, 40);
+        assert!(!acks.fail(7));
+        assert!(acks.fail(4));
+        acks.ack(4);
+        assert_eq!(30, acks.confirmable);
+    }
+}
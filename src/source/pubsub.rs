@@ -0,0 +1,344 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! Google Cloud Pub/Sub onramp
+//!
+//! Receives messages from a subscription over a gRPC streaming pull. A
+//! message is acknowledged once its event was delivered downstream and its
+//! ack deadline is set to zero if it failed, so it is redelivered right
+//! away. While an event is in flight the ack deadline of its message is
+//! extended, so it isn't redelivered to another subscriber.
+//!
+//! The message id, attributes, ordering key and publish time are available
+//! as `$pubsub.message_id`, `$pubsub.attributes`, `$pubsub.ordering_key` and
+//! `$pubsub.publish_time`, in nanoseconds since epoch.
+//!
+//! Credentials are looked up like for the `gcs` offramp. A plain text
+//! `endpoint`, like `http://localhost:8085` for the emulator, is used
+//! without credentials.
+
+use crate::connectors::gcp::pubsub::{
+    self, Connection, ReceivedMessage, StreamingPullRequest, StreamingPullResponse,
+    MAX_ACK_DEADLINE, MAX_ACK_IDS, MIN_ACK_DEADLINE,
+};
+use crate::source::prelude::*;
+use async_channel::Sender;
+use halfbrown::HashMap;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tonic::Streaming;
+
+/// seconds before the ack deadline of a message in which it is extended
+const EXTENSION_MARGIN: u64 = 5;
+/// how long a pull waits for messages to arrive before acks and deadline
+/// extensions are sent again
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// subscription to pull from, `projects/{project}/subscriptions/{name}`
+    pub subscription: String,
+    /// overrides the `https://pubsub.googleapis.com` endpoint
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// seconds a received message is hidden from other subscribers, and by
+    /// which it is extended while its event is in flight, 10 to 600
+    #[serde(default = "d_ack_deadline")]
+    pub ack_deadline: u64,
+    /// maximum number of unacknowledged messages
+    #[serde(default = "d_max_outstanding_messages")]
+    pub max_outstanding_messages: i64,
+}
+
+fn d_ack_deadline() -> u64 {
+    60
+}
+
+fn d_max_outstanding_messages() -> i64 {
+    1000
+}
+
+impl ConfigImpl for Config {}
+
+pub struct PubSub {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for PubSub {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let mut config: Config = Config::new(config)?;
+            config.ack_deadline = config
+                .ack_deadline
+                .max(MIN_ACK_DEADLINE)
+                .min(MAX_ACK_DEADLINE);
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for pubsub onramp".into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for PubSub {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+/// A received message and when its ack deadline expires
+struct Received {
+    message: ReceivedMessage,
+    expires: Instant,
+}
+
+pub struct Int {
+    uid: u64,
+    onramp_id: TremorUrl,
+    config: Config,
+    origin_uri: EventOriginUri,
+    /// requests sent on the stream, carrying acks and deadline changes
+    requests: Option<Sender<StreamingPullRequest>>,
+    responses: Option<Streaming<StreamingPullResponse>>,
+    /// received messages not pulled yet
+    received: VecDeque<Received>,
+    /// messages by the id of their event
+    in_flight: HashMap<u64, Received>,
+    /// ack ids of delivered events not acknowledged yet
+    acks: Vec<String>,
+    /// ack ids of failed events not returned yet
+    nacks: Vec<String>,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PubSub")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        let config = config.clone();
+        let host = match &config.endpoint {
+            Some(endpoint) => url::Url::parse(endpoint)?
+                .host_str()
+                .unwrap_or_default()
+                .to_string(),
+            None => "pubsub.googleapis.com".to_string(),
+        };
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-pubsub".to_string(),
+            host,
+            port: None,
+            path: config
+                .subscription
+                .split('/')
+                .map(ToString::to_string)
+                .collect(),
+        };
+        Ok(Self {
+            uid,
+            onramp_id,
+            config,
+            origin_uri,
+            requests: None,
+            responses: None,
+            received: VecDeque::new(),
+            in_flight: HashMap::new(),
+            acks: Vec::new(),
+            nacks: Vec::new(),
+        })
+    }
+
+    /// sends the pending acks and nacks and extends the ack deadlines of
+    /// messages about to expire, keeping them pending while disconnected
+    #[allow(clippy::cast_possible_truncation)]
+    fn flush(&mut self) {
+        let requests = if let Some(requests) = &self.requests {
+            requests
+        } else {
+            return;
+        };
+        let now = Instant::now();
+        let threshold = now + Duration::from_secs(EXTENSION_MARGIN);
+        let deadline = Duration::from_secs(self.config.ack_deadline);
+        let mut modifications: Vec<(String, i32)> = Vec::new();
+        for received in self.in_flight.values_mut().chain(self.received.iter_mut()) {
+            if received.expires <= threshold {
+                received.expires = now + deadline;
+                modifications.push((
+                    received.message.ack_id.clone(),
+                    self.config.ack_deadline as i32,
+                ));
+            }
+        }
+        modifications.extend(self.nacks.drain(..).map(|ack_id| (ack_id, 0)));
+
+        let mut sent = Vec::new();
+        for chunk in self.acks.chunks(MAX_ACK_IDS) {
+            sent.push(requests.try_send(StreamingPullRequest {
+                ack_ids: chunk.to_vec(),
+                ..StreamingPullRequest::default()
+            }));
+        }
+        self.acks.clear();
+        for chunk in modifications.chunks(MAX_ACK_IDS) {
+            sent.push(requests.try_send(StreamingPullRequest {
+                modify_deadline_ack_ids: chunk.iter().map(|(id, _)| id.clone()).collect(),
+                modify_deadline_seconds: chunk.iter().map(|(_, s)| *s).collect(),
+                ..StreamingPullRequest::default()
+            }));
+        }
+        // unacknowledged messages are redelivered once their deadline expired
+        if sent.iter().any(std::result::Result::is_err) {
+            warn!(
+                "[Source::{}] the stream closed before acks were sent",
+                self.onramp_id
+            );
+        }
+    }
+
+    fn disconnect(&mut self) -> SourceReply {
+        self.requests = None;
+        self.responses = None;
+        SourceReply::StateChange(SourceState::Disconnected)
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        self.flush();
+        if self.received.is_empty() {
+            let responses = if let Some(responses) = self.responses.as_mut() {
+                responses
+            } else {
+                return Ok(SourceReply::StateChange(SourceState::Disconnected));
+            };
+            match async_std::future::timeout(POLL_TIMEOUT, responses.message()).await {
+                Ok(Ok(Some(response))) => {
+                    let expires = Instant::now() + Duration::from_secs(self.config.ack_deadline);
+                    self.received.extend(
+                        response
+                            .received_messages
+                            .into_iter()
+                            .map(|message| Received { message, expires }),
+                    );
+                }
+                Ok(Ok(None)) => {
+                    info!("[Source::{}] the stream was closed", self.onramp_id);
+                    return Ok(self.disconnect());
+                }
+                Ok(Err(status)) => {
+                    error!(
+                        "[Source::{}] streaming pull failed: {}",
+                        self.onramp_id, status
+                    );
+                    return Ok(self.disconnect());
+                }
+                // nothing arrived
+                Err(_) => return Ok(SourceReply::Empty(0)),
+            }
+        }
+        if let Some(mut received) = self.received.pop_front() {
+            let mut meta = Value::object_with_capacity(1);
+            meta.insert("pubsub", pubsub::message_meta(&received.message))?;
+            let data = received
+                .message
+                .message
+                .as_mut()
+                .map(|m| std::mem::take(&mut m.data))
+                .unwrap_or_default();
+            self.in_flight.insert(id, received);
+            Ok(SourceReply::Data {
+                origin_uri: self.origin_uri.clone(),
+                data,
+                meta: Some(meta),
+                codec_override: None,
+                stream: 0,
+            })
+        } else {
+            Ok(SourceReply::Empty(0))
+        }
+    }
+
+    async fn on_empty_event(&mut self, id: u64, _stream: usize) -> Result<()> {
+        // nothing in flight downstream to wait for
+        self.ack(id);
+        Ok(())
+    }
+
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    async fn init(&mut self) -> Result<SourceState> {
+        let connection = Connection::new(self.config.endpoint.as_deref()).await?;
+        let (requests, stream) = async_channel::unbounded();
+        requests.try_send(StreamingPullRequest {
+            subscription: self.config.subscription.clone(),
+            stream_ack_deadline_seconds: self.config.ack_deadline as i32,
+            // keeps ordered messages on the same subscriber across streams
+            client_id: format!("tremor-{}-{}", hostname(), self.uid),
+            max_outstanding_messages: self.config.max_outstanding_messages,
+            ..StreamingPullRequest::default()
+        })?;
+        let request = connection.request(stream).await?;
+        let responses = connection
+            .subscriber()
+            .streaming_pull(request)
+            .await
+            .map_err(|e| Error::from(format!("Streaming pull failed: {}", e)))?
+            .into_inner();
+        // ack ids stay valid across streams, acks of the previous one are sent
+        // on this one
+        self.requests = Some(requests);
+        self.responses = Some(responses);
+        Ok(SourceState::Connected)
+    }
+
+    async fn terminate(&mut self) {
+        self.flush();
+        self.requests = None;
+    }
+
+    fn ack(&mut self, id: u64) {
+        if let Some(received) = self.in_flight.remove(&id) {
+            self.acks.push(received.message.ack_id);
+        }
+    }
+
+    fn fail(&mut self, id: u64) {
+        if let Some(received) = self.in_flight.remove(&id) {
+            self.nacks.push(received.message.ack_id);
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+}
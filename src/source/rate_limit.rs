@@ -0,0 +1,211 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per client rate limiting of the `rest` and `ws` onramps
+//!
+//! Each client gets a token bucket holding up to `burst` tokens, refilled
+//! with `rate` tokens per second. Every request, and every websocket
+//! connection and message, takes a token. Requests without a token left are
+//! rejected before they are turned into events, with `429 Too Many Requests`
+//! and a `Retry-After` header for HTTP requests and websocket handshakes.
+//!
+//! Clients are identified by the value of `header`, e.g. an API token, or by
+//! their IP if it isn't set or missing from a request.
+//!
+//! ```yaml
+//! rate_limit:
+//!   rate: 10
+//!   burst: 50
+//!   header: x-api-token
+//! ```
+
+use lru::LruCache;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Requests per second per client
+    pub rate: u64,
+    /// Requests a client can send at once, defaults to `rate`
+    #[serde(default = "Default::default")]
+    pub burst: Option<u64>,
+    /// Header identifying clients, clients are identified by their IP if not
+    /// set
+    #[serde(default = "Default::default")]
+    pub header: Option<String>,
+    /// Maximum number of clients tracked, the least recently seen client is
+    /// forgotten first
+    #[serde(default = "d_max_clients")]
+    pub max_clients: usize,
+}
+
+fn d_max_clients() -> usize {
+    10_000
+}
+
+/// The token bucket of a client, in nanoseconds of refill time
+#[derive(Debug)]
+struct Bucket {
+    credit_ns: u64,
+    last_ns: u64,
+}
+
+/// Token buckets by client
+pub(crate) struct RateLimiter {
+    header: Option<String>,
+    /// nanoseconds to refill a token
+    interval_ns: u64,
+    /// nanoseconds to refill all tokens
+    capacity_ns: u64,
+    buckets: LruCache<String, Bucket>,
+    start: Instant,
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RateLimiter({} clients)", self.buckets.len())
+    }
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: &Config) -> Self {
+        let interval_ns = 1_000_000_000 / config.rate.max(1);
+        let burst = config.burst.unwrap_or(config.rate).max(1);
+        Self {
+            header: config.header.clone(),
+            interval_ns,
+            capacity_ns: interval_ns.saturating_mul(burst),
+            buckets: LruCache::new(config.max_clients),
+            start: Instant::now(),
+        }
+    }
+
+    /// The header identifying clients
+    pub(crate) fn header(&self) -> Option<&str> {
+        self.header.as_deref()
+    }
+
+    /// The client key, the header value if given or else the peer IP
+    pub(crate) fn key(header: Option<&str>, peer: Option<&str>) -> String {
+        header.map_or_else(
+            || {
+                peer.map(|p| {
+                    p.parse::<SocketAddr>()
+                        .map_or_else(|_| p.to_string(), |a| a.ip().to_string())
+                })
+                .unwrap_or_default()
+            },
+            |h| format!("header:{}", h),
+        )
+    }
+
+    /// Takes a token for a request of the client `key`, returns how long to
+    /// wait for the next token if there is none left
+    pub(crate) fn check(&mut self, key: &str) -> std::result::Result<(), Duration> {
+        let now_ns = u64::try_from(self.start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.check_at(key, now_ns)
+    }
+
+    fn check_at(&mut self, key: &str, now_ns: u64) -> std::result::Result<(), Duration> {
+        let (interval_ns, capacity_ns) = (self.interval_ns, self.capacity_ns);
+        if self.buckets.peek(key).is_none() {
+            self.buckets.put(
+                key.to_string(),
+                Bucket {
+                    credit_ns: capacity_ns,
+                    last_ns: now_ns,
+                },
+            );
+        }
+        let bucket = self
+            .buckets
+            .get_mut(key)
+            .ok_or_else(|| Duration::from_nanos(interval_ns))?;
+        bucket.credit_ns = bucket
+            .credit_ns
+            .saturating_add(now_ns.saturating_sub(bucket.last_ns))
+            .min(capacity_ns);
+        bucket.last_ns = now_ns;
+        if bucket.credit_ns >= interval_ns {
+            bucket.credit_ns -= interval_ns;
+            Ok(())
+        } else {
+            Err(Duration::from_nanos(interval_ns - bucket.credit_ns))
+        }
+    }
+}
+
+/// `Retry-After` in whole seconds, rounded up
+pub(crate) fn retry_after(wait: Duration) -> String {
+    let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    secs.max(1).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn limiter(rate: u64, burst: Option<u64>) -> RateLimiter {
+        RateLimiter::new(&Config {
+            rate,
+            burst,
+            header: None,
+            max_clients: 2,
+        })
+    }
+
+    #[test]
+    fn burst_and_refill() {
+        let mut l = limiter(2, Some(3));
+        assert!(l.check_at("a", 0).is_ok());
+        assert!(l.check_at("a", 0).is_ok());
+        assert!(l.check_at("a", 0).is_ok());
+        assert_eq!(Err(Duration::from_millis(500)), l.check_at("a", 0));
+        // other clients have their own bucket
+        assert!(l.check_at("b", 0).is_ok());
+        assert_eq!(
+            Err(Duration::from_millis(100)),
+            l.check_at("a", 400_000_000)
+        );
+        assert!(l.check_at("a", 500_000_000).is_ok());
+        assert!(l.check_at("a", 500_000_000).is_err());
+        // refills up to the burst only
+        for _ in 0..3 {
+            assert!(l.check_at("a", 60_000_000_000).is_ok());
+        }
+        assert!(l.check_at("a", 60_000_000_000).is_err());
+    }
+
+    #[test]
+    fn forgets_least_recent_clients() {
+        let mut l = limiter(1, None);
+        assert!(l.check_at("a", 0).is_ok());
+        assert!(l.check_at("a", 0).is_err());
+        assert!(l.check_at("b", 0).is_ok());
+        assert!(l.check_at("c", 0).is_ok());
+        // `a` was forgotten and starts with a full bucket again
+        assert!(l.check_at("a", 0).is_ok());
+    }
+
+    #[test]
+    fn keys() {
+        assert_eq!("10.0.0.12", RateLimiter::key(None, Some("10.0.0.12:53120")));
+        assert_eq!("header:snot", RateLimiter::key(Some("snot"), Some("::1")));
+        assert_eq!("", RateLimiter::key(None, None));
+        assert_eq!("1", retry_after(Duration::from_millis(100)));
+        assert_eq!("2", retry_after(Duration::from_millis(1500)));
+    }
+}
@@ -0,0 +1,523 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! Redis Streams onramp
+//!
+//! Reads the entries of a stream as a consumer of a consumer group with
+//! `XREADGROUP`. The value of the `field` of an entry is the event data,
+//! all its fields are available as `$redis.fields` along with `$redis.stream`
+//! and `$redis.id`. An entry is acknowledged once its event was delivered
+//! downstream and claimed again, to be read once more, if it failed.
+//!
+//! Entries left pending by this consumer, e.g. before a restart, are read
+//! first. Entries pending for longer than `claim_idle` milliseconds with
+//! other consumers of the group, e.g. ones that went away, are claimed every
+//! `claim_interval` seconds.
+
+use crate::source::prelude::*;
+use halfbrown::HashMap;
+use redis::aio::Connection;
+use redis::Value as RedisValue;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+/// milliseconds a read waits for entries to arrive
+const BLOCK: u64 = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// redis url, e.g. `redis://localhost:6379`
+    pub url: String,
+    /// stream to read
+    pub stream: String,
+    /// consumer group to read as
+    pub group: String,
+    /// name of the consumer within the group, defaults to `tremor-{hostname}`.
+    /// It has to be unique and stable across restarts to read the entries
+    /// left pending
+    #[serde(default)]
+    pub consumer: Option<String>,
+    /// create the group, and the stream, if they don't exist
+    #[serde(default = "d_true")]
+    pub create_group: bool,
+    /// id after which a created group starts reading, `$` for new entries
+    /// and `0` for all entries
+    #[serde(default = "d_start_id")]
+    pub start_id: String,
+    /// field holding the event data
+    #[serde(default = "d_field")]
+    pub field: String,
+    /// maximum number of entries per read
+    #[serde(default = "d_count")]
+    pub count: usize,
+    /// milliseconds an entry of another consumer has to be pending for to
+    /// be claimed
+    #[serde(default = "d_claim_idle")]
+    pub claim_idle: u64,
+    /// seconds between claiming the entries pending with other consumers
+    #[serde(default = "d_claim_interval")]
+    pub claim_interval: u64,
+}
+
+fn d_true() -> bool {
+    true
+}
+
+fn d_start_id() -> String {
+    "$".to_string()
+}
+
+fn d_field() -> String {
+    "data".to_string()
+}
+
+fn d_count() -> usize {
+    100
+}
+
+fn d_claim_idle() -> u64 {
+    60_000
+}
+
+fn d_claim_interval() -> u64 {
+    30
+}
+
+impl ConfigImpl for Config {}
+
+pub struct Redis {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for Redis {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for redis onramp".into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for Redis {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+/// A stream entry, without fields if it was deleted while pending
+#[derive(Debug, PartialEq)]
+struct Entry {
+    id: String,
+    fields: Option<Vec<(String, Vec<u8>)>>,
+}
+
+fn string(value: &RedisValue) -> Option<String> {
+    match value {
+        RedisValue::Data(data) => Some(String::from_utf8_lossy(data).to_string()),
+        RedisValue::Status(status) => Some(status.clone()),
+        _ => None,
+    }
+}
+
+/// the entries of an `XCLAIM` reply or of a stream of an `XREADGROUP` reply
+fn entries(value: &RedisValue) -> Vec<Entry> {
+    let entries = if let RedisValue::Bulk(entries) = value {
+        entries
+    } else {
+        return vec![];
+    };
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            RedisValue::Bulk(entry) => {
+                let id = entry.get(0).and_then(string)?;
+                let fields = match entry.get(1) {
+                    Some(RedisValue::Bulk(fields)) => Some(
+                        fields
+                            .chunks(2)
+                            .filter_map(|kv| match kv {
+                                [k, RedisValue::Data(v)] => Some((string(k)?, v.clone())),
+                                _ => None,
+                            })
+                            .collect(),
+                    ),
+                    _ => None,
+                };
+                Some(Entry { id, fields })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// the entries of the first stream of an `XREADGROUP` reply
+fn read_entries(value: &RedisValue) -> Vec<Entry> {
+    match value {
+        RedisValue::Bulk(streams) => match streams.get(0) {
+            Some(RedisValue::Bulk(stream)) => stream.get(1).map(entries).unwrap_or_default(),
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// id, consumer and idle milliseconds of the entries of an `XPENDING` reply
+fn pending_entries(value: &RedisValue) -> Vec<(String, String, u64)> {
+    match value {
+        RedisValue::Bulk(entries) => entries
+            .iter()
+            .filter_map(|entry| match entry {
+                RedisValue::Bulk(entry) => match entry.as_slice() {
+                    [id, consumer, RedisValue::Int(idle), ..] => {
+                        Some((string(id)?, string(consumer)?, u64::try_from(*idle).ok()?))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+pub struct Int {
+    onramp_id: TremorUrl,
+    config: Config,
+    consumer: String,
+    origin_uri: EventOriginUri,
+    connection: Option<Connection>,
+    /// id after which the entries pending with this consumer are read, until
+    /// all were read
+    recover_from: Option<String>,
+    /// entries read but not pulled yet
+    received: VecDeque<Entry>,
+    /// entry ids by the id of their event
+    in_flight: HashMap<u64, String>,
+    /// ids of entries to acknowledge
+    acks: Vec<String>,
+    /// ids of entries whose events failed
+    failed: Vec<String>,
+    next_claim: Instant,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Redis")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        let config = config.clone();
+        let url = url::Url::parse(&config.url)?;
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-redis".to_string(),
+            host: url.host_str().unwrap_or("localhost").to_string(),
+            port: url.port(),
+            path: vec![config.stream.clone()],
+        };
+        let consumer = config
+            .consumer
+            .clone()
+            .unwrap_or_else(|| format!("tremor-{}", hostname()));
+        Ok(Self {
+            onramp_id,
+            config,
+            consumer,
+            origin_uri,
+            connection: None,
+            recover_from: None,
+            received: VecDeque::new(),
+            in_flight: HashMap::new(),
+            acks: Vec::new(),
+            failed: Vec::new(),
+            next_claim: Instant::now(),
+        })
+    }
+
+    /// acknowledges and claims entries and reads new ones
+    async fn fetch(&mut self) -> Result<()> {
+        let connection = self.connection.as_mut().ok_or("Not connected")?;
+        let stream = &self.config.stream;
+        let group = &self.config.group;
+        if !self.acks.is_empty() {
+            redis::cmd("XACK")
+                .arg(stream)
+                .arg(group)
+                .arg(&self.acks)
+                .query_async::<_, ()>(connection)
+                .await?;
+            self.acks.clear();
+        }
+        if !self.failed.is_empty() {
+            // claiming them without a minimum idle time delivers them again
+            let reply: RedisValue = redis::cmd("XCLAIM")
+                .arg(stream)
+                .arg(group)
+                .arg(&self.consumer)
+                .arg(0)
+                .arg(&self.failed)
+                .query_async(connection)
+                .await?;
+            self.failed.clear();
+            self.received.extend(entries(&reply));
+        }
+        let now = Instant::now();
+        if self.next_claim <= now {
+            self.next_claim = now + Duration::from_secs(self.config.claim_interval);
+            let reply: RedisValue = redis::cmd("XPENDING")
+                .arg(stream)
+                .arg(group)
+                .arg("-")
+                .arg("+")
+                .arg(self.config.count)
+                .query_async(connection)
+                .await?;
+            let claim_idle = self.config.claim_idle;
+            let consumer = &self.consumer;
+            let ids: Vec<String> = pending_entries(&reply)
+                .into_iter()
+                .filter(|(_, c, idle)| c != consumer && *idle >= claim_idle)
+                .map(|(id, _, _)| id)
+                .collect();
+            if !ids.is_empty() {
+                let reply: RedisValue = redis::cmd("XCLAIM")
+                    .arg(stream)
+                    .arg(group)
+                    .arg(consumer)
+                    .arg(claim_idle)
+                    .arg(&ids)
+                    .query_async(connection)
+                    .await?;
+                self.received.extend(entries(&reply));
+            }
+        }
+        if self.received.is_empty() {
+            let reply: RedisValue = redis::cmd("XREADGROUP")
+                .arg("GROUP")
+                .arg(group)
+                .arg(&self.consumer)
+                .arg("COUNT")
+                .arg(self.config.count)
+                .arg("BLOCK")
+                .arg(BLOCK)
+                .arg("STREAMS")
+                .arg(stream)
+                .arg(self.recover_from.as_deref().unwrap_or(">"))
+                .query_async(connection)
+                .await?;
+            let entries = read_entries(&reply);
+            if self.recover_from.is_some() {
+                self.recover_from = entries.last().map(|e| e.id.clone());
+            }
+            self.received.extend(entries);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        if self.connection.is_none() {
+            return Ok(SourceReply::StateChange(SourceState::Disconnected));
+        }
+        if let Err(e) = self.fetch().await {
+            error!("[Source::{}] failed to read: {}", self.onramp_id, e);
+            self.connection = None;
+            return Ok(SourceReply::StateChange(SourceState::Disconnected));
+        }
+        while let Some(entry) = self.received.pop_front() {
+            let fields = if let Some(fields) = entry.fields {
+                fields
+            } else {
+                // deleted while pending
+                self.acks.push(entry.id);
+                continue;
+            };
+            let data = fields
+                .iter()
+                .find(|(k, _)| k == &self.config.field)
+                .map(|(_, v)| v.clone());
+            let data = if let Some(data) = data {
+                data
+            } else {
+                warn!(
+                    "[Source::{}] entry {} has no field {}",
+                    self.onramp_id, entry.id, self.config.field
+                );
+                self.acks.push(entry.id);
+                continue;
+            };
+            let mut meta_fields = Value::object_with_capacity(fields.len());
+            for (k, v) in fields {
+                let v = match String::from_utf8(v) {
+                    Ok(s) => Value::from(s),
+                    Err(e) => Value::Bytes(e.into_bytes().into()),
+                };
+                meta_fields.insert(k, v)?;
+            }
+            let mut meta_data = Value::object_with_capacity(3);
+            meta_data.insert("stream", self.config.stream.clone())?;
+            meta_data.insert("id", entry.id.clone())?;
+            meta_data.insert("fields", meta_fields)?;
+            let mut meta = Value::object_with_capacity(1);
+            meta.insert("redis", meta_data)?;
+            self.in_flight.insert(id, entry.id);
+            return Ok(SourceReply::Data {
+                origin_uri: self.origin_uri.clone(),
+                data,
+                meta: Some(meta),
+                codec_override: None,
+                stream: 0,
+            });
+        }
+        Ok(SourceReply::Empty(0))
+    }
+
+    async fn on_empty_event(&mut self, id: u64, _stream: usize) -> Result<()> {
+        // nothing in flight downstream to wait for
+        self.ack(id);
+        Ok(())
+    }
+
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        let client = redis::Client::open(self.config.url.as_str())?;
+        let mut connection = client.get_async_std_connection().await?;
+        if self.config.create_group {
+            let created = redis::cmd("XGROUP")
+                .arg("CREATE")
+                .arg(&self.config.stream)
+                .arg(&self.config.group)
+                .arg(&self.config.start_id)
+                .arg("MKSTREAM")
+                .query_async::<_, ()>(&mut connection)
+                .await;
+            match created {
+                Err(e) if e.code() != Some("BUSYGROUP") => return Err(e.into()),
+                _ => (),
+            }
+        }
+        self.connection = Some(connection);
+        self.recover_from = Some("0".to_string());
+        self.next_claim = Instant::now();
+        Ok(SourceState::Connected)
+    }
+
+    async fn terminate(&mut self) {
+        if let Some(connection) = self.connection.as_mut() {
+            if !self.acks.is_empty() {
+                let res = redis::cmd("XACK")
+                    .arg(&self.config.stream)
+                    .arg(&self.config.group)
+                    .arg(&self.acks)
+                    .query_async::<_, ()>(connection)
+                    .await;
+                if let Err(e) = res {
+                    error!("[Source::{}] failed to ack: {}", self.onramp_id, e);
+                }
+            }
+        }
+    }
+
+    fn ack(&mut self, id: u64) {
+        if let Some(entry_id) = self.in_flight.remove(&id) {
+            self.acks.push(entry_id);
+        }
+    }
+
+    fn fail(&mut self, id: u64) {
+        if let Some(entry_id) = self.in_flight.remove(&id) {
+            self.failed.push(entry_id);
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn data(s: &str) -> RedisValue {
+        RedisValue::Data(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn read_reply() {
+        let reply = RedisValue::Bulk(vec![RedisValue::Bulk(vec![
+            data("snot"),
+            RedisValue::Bulk(vec![
+                RedisValue::Bulk(vec![
+                    data("1-0"),
+                    RedisValue::Bulk(vec![data("data"), data("{}"), data("k"), data("v")]),
+                ]),
+                RedisValue::Bulk(vec![data("2-0"), RedisValue::Nil]),
+            ]),
+        ])]);
+        assert_eq!(
+            vec![
+                Entry {
+                    id: "1-0".to_string(),
+                    fields: Some(vec![
+                        ("data".to_string(), b"{}".to_vec()),
+                        ("k".to_string(), b"v".to_vec())
+                    ])
+                },
+                Entry {
+                    id: "2-0".to_string(),
+                    fields: None
+                }
+            ],
+            read_entries(&reply)
+        );
+        assert!(read_entries(&RedisValue::Nil).is_empty());
+    }
+
+    #[test]
+    fn pending_reply() {
+        let reply = RedisValue::Bulk(vec![RedisValue::Bulk(vec![
+            data("1-0"),
+            data("badger"),
+            RedisValue::Int(70_000),
+            RedisValue::Int(2),
+        ])]);
+        assert_eq!(
+            vec![("1-0".to_string(), "badger".to_string(), 70_000)],
+            pending_entries(&reply)
+        );
+    }
+}
@@ -17,13 +17,20 @@
 
 use crate::codec::Codec;
 use crate::postprocessor::{make_postprocessors, postprocess, Postprocessors};
+use crate::source::access_log::AccessLog;
+use crate::source::grpc_web;
 use crate::source::prelude::*;
+use crate::source::rate_limit::{self, RateLimiter};
+use crate::source::webhook::{self, secret, token_matches, Webhook};
 use async_channel::{unbounded, Sender, TryRecvError};
 use halfbrown::HashMap;
-use http_types::Mime;
+use http_types::{Method, Mime};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tide::http::headers::HeaderValue;
 use tide::{Body, Request, Response};
+use tremor_common::time::nanotime;
 use tremor_script::Value;
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -34,6 +41,178 @@ pub struct Config {
     /// port to listen to, defaults to 8000
     #[serde(default = "dflt_port")]
     pub port: u16,
+    /// milliseconds a linked request waits for its response before it is
+    /// answered with `504 Gateway Timeout`, waits forever if not set
+    #[serde(default = "Default::default")]
+    pub timeout: Option<u64>,
+    /// per client rate limit, requests beyond it are answered with
+    /// `429 Too Many Requests`
+    #[serde(default = "Default::default")]
+    pub rate_limit: Option<rate_limit::Config>,
+    /// webhook paths only accepting requests with a valid signature of their
+    /// provider, answered with `401 Unauthorized` otherwise
+    #[serde(default = "Default::default")]
+    pub webhooks: Vec<webhook::Config>,
+    /// routes served instead of accepting requests to any path and method
+    #[serde(default = "Default::default")]
+    pub routes: Vec<RouteConfig>,
+    /// responses served for `GET` and `HEAD` requests to their path without
+    /// involving a pipeline, e.g. a health page or a favicon
+    #[serde(default = "Default::default")]
+    pub static_responses: Vec<StaticConfig>,
+    /// accept gRPC-web requests, unwrapping their message and wrapping
+    /// responses into gRPC-web frames
+    #[serde(default = "Default::default")]
+    pub grpc_web: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StaticConfig {
+    /// Request path
+    pub path: String,
+    /// Response status
+    #[serde(default = "d_static_status")]
+    pub status: u16,
+    /// Content type of the body
+    #[serde(default = "d_static_content_type")]
+    pub content_type: String,
+    /// Body
+    #[serde(default = "Default::default")]
+    pub body: Option<String>,
+    /// File the body is read from when the onramp starts
+    #[serde(default = "Default::default")]
+    pub file: Option<String>,
+}
+
+fn d_static_status() -> u16 {
+    200
+}
+
+fn d_static_content_type() -> String {
+    "text/plain".to_string()
+}
+
+/// A static response with its body loaded
+#[derive(Clone)]
+struct StaticResponse {
+    path: String,
+    status: u16,
+    mime: Mime,
+    body: Vec<u8>,
+}
+
+impl StaticResponse {
+    fn from_config(config: &StaticConfig) -> Result<Self> {
+        let body = match (&config.body, &config.file) {
+            (Some(body), _) => body.clone().into_bytes(),
+            (None, Some(file)) => std::fs::read(file)
+                .map_err(|e| Error::from(format!("Unable to read {}: {}", file, e)))?,
+            (None, None) => Vec::new(),
+        };
+        let mime = Mime::from_str(&config.content_type).map_err(|e| {
+            Error::from(format!(
+                "Invalid content type {}: {}",
+                config.content_type, e
+            ))
+        })?;
+        Ok(Self {
+            path: config.path.clone(),
+            status: config.status,
+            mime,
+            body,
+        })
+    }
+
+    fn respond(&self) -> Response {
+        let mut body = Body::from_bytes(self.body.clone());
+        body.set_mime(self.mime.clone());
+        Response::builder(self.status)
+            .header("Server", "Tremor")
+            .body(body)
+            .build()
+    }
+}
+
+/// A route, its name is put into `$request.route` so bindings can filter
+/// the requests of each route to their own pipeline
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    /// Name of the route
+    pub name: String,
+    /// Path pattern, e.g. `/orders/:id` or `/logs/*`
+    pub path: String,
+    /// Accepted methods, all if empty
+    #[serde(default = "Default::default")]
+    pub methods: Vec<String>,
+    /// Codec, by its key in the codec map, used instead of the one
+    /// selected by the content type
+    #[serde(default = "Default::default")]
+    pub codec: Option<String>,
+    /// Token requests have to carry as `Authorization: Bearer <token>`
+    #[serde(default = "Default::default")]
+    pub token: Option<String>,
+    /// Environment variable holding the token
+    #[serde(default = "Default::default")]
+    pub token_env: Option<String>,
+    /// Signature requests have to carry
+    #[serde(default = "Default::default")]
+    pub webhook: Option<webhook::Signature>,
+}
+
+/// A route with its methods parsed and secrets read
+struct Route {
+    name: String,
+    path: String,
+    methods: Vec<Method>,
+    codec: Option<String>,
+    token: Option<Vec<u8>>,
+    webhook: Option<Webhook>,
+}
+
+impl Route {
+    fn from_config(config: &RouteConfig) -> Result<Self> {
+        let methods = config
+            .methods
+            .iter()
+            .map(|m| {
+                Method::from_str(m).map_err(|e| Error::from(format!("Invalid method {}: {}", m, e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let token = secret(&config.token, &config.token_env)?;
+        Ok(Self {
+            name: config.name.clone(),
+            path: config.path.clone(),
+            methods,
+            codec: config.codec.clone(),
+            token: Some(token.into_bytes()).filter(|t| !t.is_empty()),
+            webhook: config
+                .webhook
+                .as_ref()
+                .map(|w| Webhook::new(&config.path, w))
+                .transpose()?,
+        })
+    }
+
+    /// checks the token and signature of a request
+    fn authorize(
+        &self,
+        req: &Request<ServerState>,
+        body: &[u8],
+    ) -> std::result::Result<(), &'static str> {
+        let header = |name: &str| req.header(name).map(|values| values.last().as_str());
+        if let Some(token) = &self.token {
+            let presented = header("Authorization")
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or("missing bearer token")?;
+            if !token_matches(token, presented.as_bytes()) {
+                return Err("invalid bearer token");
+            }
+        }
+        if let Some(webhook) = &self.webhook {
+            webhook.verify(header, body, nanotime() / 1_000_000_000)?;
+        }
+        Ok(())
+    }
 }
 
 // TODO possible to do this in source trait?
@@ -80,6 +259,9 @@ pub struct Int {
     config: Config,
     listener: Option<Receiver<RestSourceReply>>,
     post_processors: Postprocessors,
+    webhooks: Arc<Vec<Webhook>>,
+    routes: Vec<Arc<Route>>,
+    static_responses: Vec<StaticResponse>,
     onramp_id: TremorUrl,
     is_linked: bool,
     // TODO better way to manage this?
@@ -102,11 +284,29 @@ impl Int {
     ) -> Result<Self> {
         let config = config.clone();
         let post_processors = make_postprocessors(post_processors)?;
+        let webhooks = config
+            .webhooks
+            .iter()
+            .map(Webhook::from_config)
+            .collect::<Result<Vec<_>>>()?;
+        let routes = config
+            .routes
+            .iter()
+            .map(|r| Route::from_config(r).map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+        let static_responses = config
+            .static_responses
+            .iter()
+            .map(StaticResponse::from_config)
+            .collect::<Result<Vec<_>>>()?;
         Ok(Self {
             uid,
             config,
             listener: None,
             post_processors,
+            webhooks: Arc::new(webhooks),
+            routes,
+            static_responses,
             onramp_id,
             is_linked,
             response_txes: HashMap::new(),
@@ -139,11 +339,15 @@ struct ServerState {
     tx: Sender<RestSourceReply>,
     uid: u64,
     link: bool,
+    timeout: Option<Duration>,
+    rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    webhooks: Arc<Vec<Webhook>>,
+    grpc_web: bool,
 }
 
-async fn handle_request(mut req: Request<ServerState>) -> tide::Result<Response> {
+fn origin_uri(req: &Request<ServerState>) -> EventOriginUri {
     // TODO cache parts of this and update host only on new request
-    let origin_uri = EventOriginUri {
+    EventOriginUri {
         uid: req.state().uid,
         scheme: "tremor-rest".to_string(),
         host: req
@@ -153,7 +357,93 @@ async fn handle_request(mut req: Request<ServerState>) -> tide::Result<Response>
         port: None,
         // TODO add server port here (like for tcp onramp)
         path: vec![String::default()],
+    }
+}
+
+/// Handles a request and logs it to the `access_log` port once answered
+async fn handle_request(
+    req: Request<ServerState>,
+    route: Option<Arc<Route>>,
+) -> tide::Result<Response> {
+    let log = AccessLog::start(&req.method().to_string(), req.url().path(), req.peer_addr());
+    let bytes_in = req.len().unwrap_or_default();
+    let origin_uri = origin_uri(&req);
+    let tx = req.state().tx.clone();
+
+    let res = handle(req, route).await;
+    let status = res
+        .as_ref()
+        .map_or_else(|e| u16::from(e.status()), |r| u16::from(r.status()));
+    let bytes_out = res
+        .as_ref()
+        .ok()
+        .and_then(Response::len)
+        .unwrap_or_default();
+    let data = log.finish(status, bytes_in, bytes_out);
+    tx.send(SourceReply::AccessLog { origin_uri, data }.into())
+        .await?;
+    res
+}
+
+/// Takes a token of the client's rate limit, returns how long to wait for
+/// the next token if there is none left
+fn rate_limited(req: &Request<ServerState>) -> Option<Duration> {
+    let limiter = req.state().rate_limiter.as_ref()?;
+    let mut limiter = limiter.lock().ok()?;
+    let header = limiter
+        .header()
+        .and_then(|name| req.header(name))
+        .map(|values| values.last().as_str().to_string());
+    let key = RateLimiter::key(header.as_deref(), req.peer_addr());
+    limiter.check(&key).err()
+}
+
+async fn handle(req: Request<ServerState>, route: Option<Arc<Route>>) -> tide::Result<Response> {
+    let grpc_web = req
+        .content_type()
+        .filter(|_| req.state().grpc_web)
+        .and_then(|ct| grpc_web::Mode::from_content_type(ct.essence()));
+    let res = handle_http(req, route, grpc_web).await?;
+    if let Some(mode) = grpc_web {
+        grpc_web_response(mode, res).await
+    } else {
+        Ok(res)
+    }
+}
+
+/// Wraps a response into gRPC-web frames, gRPC-web clients expect a
+/// `200 OK` with the outcome in the trailers
+async fn grpc_web_response(mode: grpc_web::Mode, mut res: Response) -> tide::Result<Response> {
+    let status = grpc_web::status(u16::from(res.status()));
+    let data = res.take_body().into_bytes().await?;
+    let (message, reason) = if status == grpc_web::OK {
+        (Some(data.as_slice()).filter(|d| !d.is_empty()), "")
+    } else {
+        (None, res.status().canonical_reason())
     };
+    let data = grpc_web::encode(message, status, reason, mode)
+        .map_err(|e| tide::Error::from_str(500, e.to_string()))?;
+    let mut body = Body::from_bytes(data);
+    body.set_mime(Mime::from_str(mode.content_type())?);
+    Ok(Response::builder(200)
+        .header("Server", "Tremor")
+        .body(body)
+        .build())
+}
+
+async fn handle_http(
+    mut req: Request<ServerState>,
+    route: Option<Arc<Route>>,
+    grpc_web: Option<grpc_web::Mode>,
+) -> tide::Result<Response> {
+    if let Some(wait) = rate_limited(&req) {
+        return Ok(Response::builder(429)
+            .header("Server", "Tremor")
+            .header("Retry-After", rate_limit::retry_after(wait).as_str())
+            .body(Body::empty())
+            .build());
+    }
+    let origin_uri = origin_uri(&req);
 
     let headers = req
         .header_names()
@@ -173,11 +463,15 @@ async fn handle_request(mut req: Request<ServerState>) -> tide::Result<Response>
         .collect::<Value>();
 
     let ct: Option<Mime> = req.content_type();
-    let codec_override = ct.map(|ct| ct.essence().to_string());
+    let codec_override = route.as_ref().and_then(|r| r.codec.clone()).or_else(|| {
+        // the gRPC-web content type doesn't select a codec
+        ct.filter(|_| grpc_web.is_none())
+            .map(|ct| ct.essence().to_string())
+    });
 
     // request metadata
     let mut meta = Value::object_with_capacity(1);
-    let mut request_meta = Value::object_with_capacity(3);
+    let mut request_meta = Value::object_with_capacity(4);
     let mut url_meta = Value::object_with_capacity(7);
     let url = req.url();
     url_meta.insert("scheme", url.scheme().to_string())?;
@@ -198,21 +492,70 @@ async fn handle_request(mut req: Request<ServerState>) -> tide::Result<Response>
     request_meta.insert("method", req.method().to_string())?;
     request_meta.insert("headers", headers)?;
     request_meta.insert("url", url_meta)?;
+    if let Some(route) = &route {
+        request_meta.insert("route", route.name.clone())?;
+    }
     meta.insert("request", request_meta)?;
 
     let data = req.body_bytes().await?;
+    if let Some(route) = &route {
+        if let Err(reason) = route.authorize(&req, &data) {
+            warn!(
+                "[Source::REST] Rejecting request to route {}: {}",
+                route.name, reason
+            );
+            return Ok(Response::builder(401)
+                .header("Server", "Tremor")
+                .body(Body::empty())
+                .build());
+        }
+    }
+    if let Some(webhook) = req
+        .state()
+        .webhooks
+        .iter()
+        .find(|w| w.path == req.url().path())
+    {
+        let header = |name: &str| req.header(name).map(|values| values.last().as_str());
+        if let Err(reason) = webhook.verify(header, &data, nanotime() / 1_000_000_000) {
+            warn!(
+                "[Source::REST] Rejecting request to webhook {}: {}",
+                webhook.path, reason
+            );
+            return Ok(Response::builder(401)
+                .header("Server", "Tremor")
+                .body(Body::empty())
+                .build());
+        }
+    }
+    let data = if let Some(mode) = grpc_web {
+        match grpc_web::decode(&data, mode) {
+            Ok(mut messages) if messages.len() == 1 => messages.remove(0),
+            Ok(messages) => {
+                warn!(
+                    "[Source::REST] Rejecting gRPC-web request with {} messages",
+                    messages.len()
+                );
+                return Ok(Response::builder(400).body(Body::empty()).build());
+            }
+            Err(e) => {
+                warn!("[Source::REST] Rejecting invalid gRPC-web request: {}", e);
+                return Ok(Response::builder(400).body(Body::empty()).build());
+            }
+        }
+    } else {
+        data
+    };
     if req.state().link {
         let (response_tx, response_rx) = unbounded();
 
-        // TODO check how tide handles request timeouts here
-        //
-        // also figure out best way to bubble up errors (to the end user)
+        // TODO figure out best way to bubble up errors (to the end user)
         // during processing of this data, that may occur before a valid
         // event is sent back from the pipeline.
         // eg: in case of invalid json input with json content-type/codec,
         // event processing fails at the codec decoding stage, even before
         // this event reaches pipeline, but the rest source request just
-        // hangs (until it times out).
+        // hangs (until it times out, if a timeout is configured).
         req.state()
             .tx
             .send(RestSourceReply(
@@ -227,7 +570,18 @@ async fn handle_request(mut req: Request<ServerState>) -> tide::Result<Response>
             ))
             .await?;
         // TODO honor accept header
-        Ok(response_rx.recv().await?)
+        if let Some(timeout) = req.state().timeout {
+            if let Ok(response) = async_std::future::timeout(timeout, response_rx.recv()).await {
+                Ok(response?)
+            } else {
+                Ok(Response::builder(504)
+                    .header("Server", "Tremor")
+                    .body(Body::empty())
+                    .build())
+            }
+        } else {
+            Ok(response_rx.recv().await?)
+        }
     } else {
         req.state()
             .tx
@@ -248,6 +602,26 @@ async fn handle_request(mut req: Request<ServerState>) -> tide::Result<Response>
     }
 }
 
+/// Answers events rejected by a limiter with `429 Too Many Requests`
+fn make_rejection(codec: &dyn Codec, rejected: &Value) -> Response {
+    let retry_after_s = (rejected.get_u64("retry_after_ms").unwrap_or(1000) + 999) / 1000;
+    let mut builder = Response::builder(429)
+        .header("Server", "Tremor")
+        .header("Retry-After", retry_after_s.to_string().as_str());
+    if let Ok(data) = codec.encode(rejected) {
+        let mut body = Body::from_bytes(data);
+        if let Some(mime) = codec
+            .mime_types()
+            .drain(..)
+            .find_map(|mstr| Mime::from_str(mstr).ok())
+        {
+            body.set_mime(mime);
+        }
+        builder = builder.body(body);
+    }
+    builder.build()
+}
+
 fn make_response(
     default_codec: &dyn Codec,
     codec_map: &HashMap<String, Box<dyn Codec>>,
@@ -258,6 +632,11 @@ fn make_response(
     let ingest_ns = event.ingest_ns;
     let (response_data, meta) = event.value_meta_iter().next().ok_or(err)?;
 
+    if meta.get("response").is_none() {
+        if let Some(rejected) = meta.get("rejected") {
+            return Ok(make_rejection(default_codec, rejected));
+        }
+    }
     if let Some(response_meta) = meta.get("response") {
         let status = response_meta.get_u16("status").unwrap_or(200);
 
@@ -300,6 +679,7 @@ fn make_response(
                 post_processors.as_mut_slice(),
                 ingest_ns,
                 dynamic_codec.encode(response_data)?,
+                None,
             )?;
 
             // TODO: see if we can use a reader instead of creating a new vector
@@ -314,6 +694,7 @@ fn make_response(
                 post_processors.as_mut_slice(),
                 ingest_ns,
                 default_codec.encode(response_data)?,
+                None,
             )?;
             // TODO: see if we can use a reader instead of creating a new vector
             let v: Vec<u8> = processed.into_iter().flatten().collect();
@@ -393,13 +774,20 @@ impl Source for Int {
                                     break;
                                 }
                             }
+                            builder = builder.body(body);
                         } else {
                             builder = builder.body(Body::from_string(e.to_string()));
                         }
                         builder.build()
                     }
                 };
-                response_tx.send(res).await?
+                if response_tx.send(res).await.is_err() {
+                    // the request timed out or the client went away
+                    debug!(
+                        "[Source::{}] HTTP session for event-id {} closed before the reply",
+                        self.onramp_id, event_id
+                    );
+                }
             } else {
                 debug!("No outstanding HTTP session for event-id {}", event_id);
             }
@@ -431,12 +819,44 @@ impl Source for Int {
             tx: tx.clone(),
             uid: self.uid,
             link: self.is_linked,
+            timeout: self.config.timeout.map(Duration::from_millis),
+            rate_limiter: self
+                .config
+                .rate_limit
+                .as_ref()
+                .map(|c| Arc::new(Mutex::new(RateLimiter::new(c)))),
+            webhooks: self.webhooks.clone(),
+            grpc_web: self.config.grpc_web,
         });
 
-        // TODO add override for path and method from config (defaulting to
-        // all paths and methods like below if not provided)
-        server.at("/").all(handle_request);
-        server.at("/*").all(handle_request);
+        if self.routes.is_empty() {
+            server.at("/").all(|req| handle_request(req, None));
+            server.at("/*").all(|req| handle_request(req, None));
+        }
+        for response in &self.static_responses {
+            let mut at = server.at(&response.path);
+            let get = response.clone();
+            at.get(move |_| {
+                let res = get.respond();
+                async move { Ok(res) }
+            });
+            let head = response.clone();
+            at.head(move |_| {
+                let res = head.respond();
+                async move { Ok(res) }
+            });
+        }
+        for route in &self.routes {
+            let mut at = server.at(&route.path);
+            if route.methods.is_empty() {
+                let route = route.clone();
+                at.all(move |req| handle_request(req, Some(route.clone())));
+            }
+            for method in &route.methods {
+                let route = route.clone();
+                at.method(*method, move |req| handle_request(req, Some(route.clone())));
+            }
+        }
         // alt method without relying on server state
         //server.at("/*").all(|r| handle_request(r, self.uid, self.is_linked));
 
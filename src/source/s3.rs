@@ -0,0 +1,373 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! AWS S3 onramp
+//!
+//! Lists the objects of `bucket` with keys starting with `prefix` every
+//! `poll_interval` seconds and reads the ones not processed yet, in the
+//! order of their keys.
+//!
+//! Each object is read as a stream of its own, so preprocessors keep their
+//! state per object. With a `chunk_size` the contents are sent in chunks of
+//! that many bytes, to split large objects with the `lines` preprocessor.
+//! Without it each object is sent whole, as preprocessors like `gzip` need.
+//!
+//! Once an object was read to its end its key is recorded with its etag,
+//! so it is neither read again by the next listing nor after a restart, but
+//! read again once it is overwritten. An object failing to be read is
+//! retried with the next listing, its chunks sent already are sent again.
+//! Processed keys are kept in memory or, to survive restarts, in a sled
+//! database in `dir`:
+//!
+//! ```yaml
+//! checkpoint:
+//!   store: sled
+//!   dir: /var/lib/tremor/s3
+//! ```
+//!
+//! The object metadata is available as `$s3`, with `bucket`, `key`,
+//! `etag`, `size` and `last_modified`.
+//!
+//! Credentials are read from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+//! and `AWS_SESSION_TOKEN` environment variables or the shared credentials
+//! file.
+
+use crate::connectors::aws::auth::{self, AwsClient};
+use crate::connectors::aws::s3::{self, Object};
+use crate::source::checkpoint::{self, CheckpointConfig, CheckpointStore};
+use crate::source::prelude::*;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// bucket to read objects from
+    pub bucket: String,
+    /// prefix of the keys of the objects to read
+    #[serde(default)]
+    pub prefix: String,
+    /// AWS region, defaults to `AWS_REGION`
+    #[serde(default)]
+    pub region: Option<String>,
+    /// overrides the `s3.{region}.amazonaws.com` endpoint
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// seconds between listings of the bucket
+    #[serde(default = "d_poll_interval")]
+    pub poll_interval: u64,
+    /// bytes of the chunks objects are sent in, `0` sends them whole
+    #[serde(default)]
+    pub chunk_size: usize,
+    /// where the processed keys are kept
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+}
+
+fn d_poll_interval() -> u64 {
+    60
+}
+
+impl ConfigImpl for Config {}
+
+pub struct S3 {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for S3 {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for s3 onramp".into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for S3 {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+/// The next chunk of `buffer`, the whole buffer for a `chunk_size` of `0`
+fn next_chunk(buffer: &mut Vec<u8>, chunk_size: usize) -> Vec<u8> {
+    if chunk_size > 0 && buffer.len() > chunk_size {
+        let rest = buffer.split_off(chunk_size);
+        std::mem::replace(buffer, rest)
+    } else {
+        std::mem::take(buffer)
+    }
+}
+
+/// The `$s3` metadata of an object
+fn object_meta(bucket: &str, object: &Object) -> Value<'static> {
+    literal!({
+        "s3": {
+            "bucket": bucket.to_string(),
+            "key": object.key.clone(),
+            "etag": object.etag.clone(),
+            "size": object.size,
+            "last_modified": object.last_modified.clone()
+        }
+    })
+}
+
+/// An object being read
+struct Reading {
+    object: Object,
+    stream: usize,
+    response: reqwest::Response,
+    /// bytes read but not sent yet
+    buffer: Vec<u8>,
+    /// whether the body was read to its end
+    done: bool,
+}
+
+pub struct Int {
+    onramp_id: TremorUrl,
+    config: Config,
+    region: String,
+    client: Option<AwsClient>,
+    origin_uri: EventOriginUri,
+    store: Box<dyn CheckpointStore>,
+    /// listed objects not read yet
+    pending: VecDeque<Object>,
+    reading: Option<Reading>,
+    next_stream: usize,
+    next_poll: Instant,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "S3")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        let config = config.clone();
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-s3".to_string(),
+            host: config.bucket.clone(),
+            port: None,
+            path: vec![],
+        };
+        Ok(Self {
+            onramp_id,
+            region: auth::region(config.region.as_deref())?,
+            store: checkpoint::store(&config.checkpoint, &config.bucket)?,
+            config,
+            client: None,
+            origin_uri,
+            pending: VecDeque::new(),
+            reading: None,
+            // stream 0 is the default stream of the onramp
+            next_stream: 1,
+            next_poll: Instant::now(),
+        })
+    }
+
+    /// queues the objects not processed yet
+    async fn list(&mut self, client: &AwsClient) -> Result<()> {
+        let mut token = None;
+        loop {
+            let (objects, next) = s3::list_objects(
+                client,
+                &self.config.bucket,
+                &self.config.prefix,
+                token.as_deref(),
+            )
+            .await?;
+            for object in objects {
+                if self.store.get(&object.key)?.as_deref() != Some(object.etag.as_str()) {
+                    self.pending.push_back(object);
+                }
+            }
+            if next.is_none() {
+                return Ok(());
+            }
+            token = next;
+        }
+    }
+
+    /// records a read object, so it isn't read again
+    fn processed(&mut self, object: &Object) {
+        if let Err(e) = self.store.set(&object.key, &object.etag) {
+            error!(
+                "[Source::{}] failed to record {} as processed: {}",
+                self.onramp_id, object.key, e
+            );
+        }
+    }
+
+    /// the next chunk of the object being read
+    async fn read(&mut self) -> SourceReply {
+        let reading = if let Some(reading) = self.reading.as_mut() {
+            reading
+        } else {
+            return SourceReply::Empty(0);
+        };
+        let chunk_size = self.config.chunk_size;
+        while !reading.done && (chunk_size == 0 || reading.buffer.len() < chunk_size) {
+            match reading.response.chunk().await {
+                Ok(Some(bytes)) => reading.buffer.extend_from_slice(&bytes),
+                Ok(None) => reading.done = true,
+                Err(e) => {
+                    error!(
+                        "[Source::{}] failed to read {}: {}",
+                        self.onramp_id, reading.object.key, e
+                    );
+                    // retried with the next listing
+                    let stream = reading.stream;
+                    self.reading = None;
+                    return SourceReply::EndStream(stream);
+                }
+            }
+        }
+        if reading.buffer.is_empty() {
+            let stream = reading.stream;
+            if let Some(reading) = self.reading.take() {
+                self.processed(&reading.object);
+            }
+            return SourceReply::EndStream(stream);
+        }
+        let mut origin_uri = self.origin_uri.clone();
+        origin_uri.path = reading.object.key.split('/').map(String::from).collect();
+        SourceReply::Data {
+            origin_uri,
+            data: next_chunk(&mut reading.buffer, chunk_size),
+            meta: Some(object_meta(&self.config.bucket, &reading.object)),
+            codec_override: None,
+            stream: reading.stream,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        let client = if let Some(client) = self.client.clone() {
+            client
+        } else {
+            return Ok(SourceReply::StateChange(SourceState::Disconnected));
+        };
+        if self.reading.is_some() {
+            return Ok(self.read().await);
+        }
+        if let Some(object) = self.pending.pop_front() {
+            return match s3::get_object(&client, &self.config.bucket, &object.key).await {
+                Ok(response) => {
+                    let stream = self.next_stream;
+                    self.next_stream += 1;
+                    debug!("[Source::{}] reading {}", self.onramp_id, object.key);
+                    self.reading = Some(Reading {
+                        object,
+                        stream,
+                        response,
+                        buffer: Vec::new(),
+                        done: false,
+                    });
+                    Ok(SourceReply::StartStream(stream))
+                }
+                Err(e) => {
+                    error!(
+                        "[Source::{}] failed to get {}: {}",
+                        self.onramp_id, object.key, e
+                    );
+                    Ok(SourceReply::Empty(0))
+                }
+            };
+        }
+        let now = Instant::now();
+        if now >= self.next_poll {
+            if let Err(e) = self.list(&client).await {
+                error!(
+                    "[Source::{}] failed to list {}: {}",
+                    self.onramp_id, self.config.bucket, e
+                );
+            }
+            self.next_poll = now + Duration::from_secs(self.config.poll_interval);
+            return Ok(SourceReply::Empty(0));
+        }
+        let wait = self.next_poll.saturating_duration_since(now).as_millis();
+        Ok(SourceReply::Empty(u64::try_from(wait).unwrap_or(u64::MAX)))
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        self.client = Some(AwsClient::new(
+            "s3",
+            &self.region,
+            self.config.endpoint.as_deref(),
+        )?);
+        Ok(SourceState::Connected)
+    }
+
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let mut buffer = b"snotbadger".to_vec();
+        assert_eq!(b"snot".to_vec(), next_chunk(&mut buffer, 4));
+        assert_eq!(b"badg".to_vec(), next_chunk(&mut buffer, 4));
+        assert_eq!(b"er".to_vec(), next_chunk(&mut buffer, 4));
+        assert!(buffer.is_empty());
+        let mut buffer = b"snotbadger".to_vec();
+        assert_eq!(b"snotbadger".to_vec(), next_chunk(&mut buffer, 0));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn meta() {
+        let object = Object {
+            key: "logs/a.log".to_string(),
+            etag: "9b2cf535f27731c974343645a3985328".to_string(),
+            size: 42,
+            last_modified: "2021-05-01T10:00:00.000Z".to_string(),
+        };
+        assert_eq!(
+            literal!({
+                "s3": {
+                    "bucket": "snot",
+                    "key": "logs/a.log",
+                    "etag": "9b2cf535f27731c974343645a3985328",
+                    "size": 42,
+                    "last_modified": "2021-05-01T10:00:00.000Z"
+                }
+            }),
+            object_meta("snot", &object)
+        );
+    }
+}
@@ -0,0 +1,368 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! sFlow v5 decoding of the `flow` onramp
+//!
+//! Every flow and counter sample of a datagram becomes an event. The records
+//! of a sample are decoded into its `records`:
+//!
+//! * `raw_header`: the sampled packet, with the ethernet, IPv4 or IPv6 and
+//!   TCP or UDP header fields named like their IPFIX information elements
+//! * `extended_switch`: the source and destination VLANs and priorities
+//! * `generic_interface`: the interface counters
+//!
+//! Other records are passed with their `enterprise:format` as `type` and
+//! their data as hex.
+
+use crate::source::netflow::{hex, ip, mac, Reader};
+use std::net::IpAddr;
+use tremor_script::prelude::*;
+
+type Result<T> = std::result::Result<T, &'static str>;
+
+const FLOW_SAMPLE: u32 = 1;
+const COUNTER_SAMPLE: u32 = 2;
+const EXPANDED_FLOW_SAMPLE: u32 = 3;
+const EXPANDED_COUNTER_SAMPLE: u32 = 4;
+
+const RAW_HEADER: u32 = 1;
+const EXTENDED_SWITCH: u32 = 1001;
+const GENERIC_INTERFACE: u32 = 1;
+
+const ETHERNET: u32 = 1;
+const VLAN: u16 = 0x8100;
+const IPV4: u16 = 0x0800;
+const IPV6: u16 = 0x86dd;
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+/// is the datagram an sFlow v5 datagram
+pub(crate) fn is_sflow(data: &[u8]) -> bool {
+    data.starts_with(&[0, 0, 0, 5])
+}
+
+/// decodes the samples of an sFlow v5 datagram from `exporter`
+pub(crate) fn decode(exporter: IpAddr, data: &[u8]) -> Result<Vec<Value<'static>>> {
+    let mut reader = Reader::new(data);
+    if reader.u32()? != 5 {
+        return Err("unsupported sFlow version");
+    }
+    let agent = match reader.u32()? {
+        1 => ip(reader.bytes(4)?),
+        2 => ip(reader.bytes(16)?),
+        _ => return Err("invalid agent address"),
+    };
+    let sub_agent_id = reader.u32()?;
+    let sequence = reader.u32()?;
+    let uptime = reader.u32()?;
+    let count = reader.u32()?;
+    let header = literal!({
+        "format": "sflow",
+        "exporter": exporter.to_string(),
+        "agent": agent,
+        "sub_agent_id": sub_agent_id,
+        "sequence": sequence,
+        "uptime": uptime
+    });
+    let mut samples = Vec::new();
+    for _ in 0..count {
+        let format = reader.u32()?;
+        let mut sample = Reader::new(reader.opaque()?);
+        let sample = match format {
+            FLOW_SAMPLE => flow_sample(&mut sample, false)?,
+            EXPANDED_FLOW_SAMPLE => flow_sample(&mut sample, true)?,
+            COUNTER_SAMPLE => counter_sample(&mut sample, false)?,
+            EXPANDED_COUNTER_SAMPLE => counter_sample(&mut sample, true)?,
+            // enterprise specific
+            _ => continue,
+        };
+        let mut event = header.clone();
+        event.try_insert("sample", sample);
+        samples.push(event);
+    }
+    Ok(samples)
+}
+
+/// reads the source id, compact or expanded
+fn source_id(reader: &mut Reader<'_>, expanded: bool) -> Result<(u32, u32)> {
+    if expanded {
+        Ok((reader.u32()?, reader.u32()?))
+    } else {
+        let id = reader.u32()?;
+        Ok((id >> 24, id & 0x00ff_ffff))
+    }
+}
+
+/// reads an interface, the format is in the upper 2 bits of compact ones
+fn interface(reader: &mut Reader<'_>, expanded: bool) -> Result<(u32, u32)> {
+    if expanded {
+        Ok((reader.u32()?, reader.u32()?))
+    } else {
+        let interface = reader.u32()?;
+        Ok((interface >> 30, interface & 0x3fff_ffff))
+    }
+}
+
+fn flow_sample(reader: &mut Reader<'_>, expanded: bool) -> Result<Value<'static>> {
+    let sequence = reader.u32()?;
+    let (source_id_type, source_id_index) = source_id(reader, expanded)?;
+    let sampling_rate = reader.u32()?;
+    let sample_pool = reader.u32()?;
+    let drops = reader.u32()?;
+    let (input_format, input) = interface(reader, expanded)?;
+    let (output_format, output) = interface(reader, expanded)?;
+    let records = records(reader, true)?;
+    Ok(literal!({
+        "type": "flow",
+        "sequence": sequence,
+        "source_id_type": source_id_type,
+        "source_id_index": source_id_index,
+        "sampling_rate": sampling_rate,
+        "sample_pool": sample_pool,
+        "drops": drops,
+        "input_format": input_format,
+        "input": input,
+        "output_format": output_format,
+        "output": output,
+        "records": records
+    }))
+}
+
+fn counter_sample(reader: &mut Reader<'_>, expanded: bool) -> Result<Value<'static>> {
+    let sequence = reader.u32()?;
+    let (source_id_type, source_id_index) = source_id(reader, expanded)?;
+    let records = records(reader, false)?;
+    Ok(literal!({
+        "type": "counter",
+        "sequence": sequence,
+        "source_id_type": source_id_type,
+        "source_id_index": source_id_index,
+        "records": records
+    }))
+}
+
+fn records(reader: &mut Reader<'_>, flow: bool) -> Result<Vec<Value<'static>>> {
+    let count = reader.u32()?;
+    let mut records = Vec::new();
+    for _ in 0..count {
+        let format = reader.u32()?;
+        let data = reader.opaque()?;
+        let mut record = Reader::new(data);
+        records.push(match (flow, format) {
+            (true, RAW_HEADER) => raw_header(&mut record)?,
+            (true, EXTENDED_SWITCH) => literal!({
+                "type": "extended_switch",
+                "src_vlan": record.u32()?,
+                "src_priority": record.u32()?,
+                "dst_vlan": record.u32()?,
+                "dst_priority": record.u32()?
+            }),
+            (false, GENERIC_INTERFACE) => generic_interface(&mut record)?,
+            _ => literal!({
+                "type": format!("{}:{}", format >> 12, format & 0xfff),
+                "data": hex(data)
+            }),
+        });
+    }
+    Ok(records)
+}
+
+fn raw_header(reader: &mut Reader<'_>) -> Result<Value<'static>> {
+    let protocol = reader.u32()?;
+    let frame_length = reader.u32()?;
+    let stripped = reader.u32()?;
+    let header = reader.opaque()?;
+    let mut record = literal!({
+        "type": "raw_header",
+        "header_protocol": protocol,
+        "frame_length": frame_length,
+        "stripped": stripped
+    });
+    if protocol == ETHERNET {
+        // sampled headers are truncated, so decode as much as there is
+        if let Err(e) = ethernet(&mut Reader::new(header), &mut record) {
+            debug!("Stopped decoding a sampled header: {}", e);
+        }
+    } else {
+        record.try_insert("header", hex(header));
+    }
+    Ok(record)
+}
+
+/// decodes the ethernet, IP and transport headers of a sampled packet
+fn ethernet(reader: &mut Reader<'_>, record: &mut Value<'static>) -> Result<()> {
+    record.try_insert("destinationMacAddress", mac(reader.bytes(6)?));
+    record.try_insert("sourceMacAddress", mac(reader.bytes(6)?));
+    let mut ether_type = reader.u16()?;
+    if ether_type == VLAN {
+        record.try_insert("vlanId", reader.u16()? & 0x0fff);
+        ether_type = reader.u16()?;
+    }
+    let protocol = match ether_type {
+        IPV4 => {
+            let header = reader.bytes(20)?;
+            record.try_insert("ipVersion", 4);
+            record.try_insert("ipClassOfService", header[1]);
+            record.try_insert("protocolIdentifier", header[9]);
+            record.try_insert("sourceIPv4Address", ip(&header[12..16]));
+            record.try_insert("destinationIPv4Address", ip(&header[16..20]));
+            // options
+            reader.bytes(usize::from(header[0] & 0x0f).saturating_sub(5) * 4)?;
+            header[9]
+        }
+        IPV6 => {
+            let header = reader.bytes(40)?;
+            record.try_insert("ipVersion", 6);
+            record.try_insert(
+                "ipClassOfService",
+                ((header[0] & 0x0f) << 4) | (header[1] >> 4),
+            );
+            record.try_insert("protocolIdentifier", header[6]);
+            record.try_insert("sourceIPv6Address", ip(&header[8..24]));
+            record.try_insert("destinationIPv6Address", ip(&header[24..40]));
+            header[6]
+        }
+        _ => return Ok(()),
+    };
+    if protocol == TCP || protocol == UDP {
+        record.try_insert("sourceTransportPort", reader.u16()?);
+        record.try_insert("destinationTransportPort", reader.u16()?);
+    }
+    if protocol == TCP {
+        let header = reader.bytes(10)?;
+        record.try_insert("tcpControlBits", header[9]);
+    }
+    Ok(())
+}
+
+fn generic_interface(reader: &mut Reader<'_>) -> Result<Value<'static>> {
+    Ok(literal!({
+        "type": "generic_interface",
+        "ifIndex": reader.u32()?,
+        "ifType": reader.u32()?,
+        "ifSpeed": reader.u64()?,
+        "ifDirection": reader.u32()?,
+        "ifStatus": reader.u32()?,
+        "ifInOctets": reader.u64()?,
+        "ifInUcastPkts": reader.u32()?,
+        "ifInMulticastPkts": reader.u32()?,
+        "ifInBroadcastPkts": reader.u32()?,
+        "ifInDiscards": reader.u32()?,
+        "ifInErrors": reader.u32()?,
+        "ifInUnknownProtos": reader.u32()?,
+        "ifOutOctets": reader.u64()?,
+        "ifOutUcastPkts": reader.u32()?,
+        "ifOutMulticastPkts": reader.u32()?,
+        "ifOutBroadcastPkts": reader.u32()?,
+        "ifOutDiscards": reader.u32()?,
+        "ifOutErrors": reader.u32()?,
+        "ifPromiscuousMode": reader.u32()?
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn u32s(out: &mut Vec<u8>, values: &[u32]) {
+        for v in values {
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+    }
+
+    /// an ethernet frame with a VLAN tag, an IPv4 and a TCP header
+    fn frame() -> Vec<u8> {
+        let mut frame = vec![
+            0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 0x81, 0, 0, 42, 0x08, 0,
+        ];
+        frame.extend_from_slice(&[0x45, 0x10, 0, 40, 0, 0, 0, 0, 64, 6, 0, 0]);
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        frame.extend_from_slice(&[0x04, 0xd2, 0x01, 0xbb, 0, 0, 0, 0, 0, 0, 0, 0, 0x50, 0x12]);
+        // padded to 4 bytes
+        frame.extend_from_slice(&[0, 0]);
+        frame
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn datagram() -> Vec<u8> {
+        let frame = frame();
+        let mut raw_header = Vec::new();
+        u32s(
+            &mut raw_header,
+            &[ETHERNET, 1500, 4, frame.len() as u32 - 2],
+        );
+        raw_header.extend(frame);
+
+        let mut flow = Vec::new();
+        // source id 7, sampling 1 in 1000 and interfaces 1 and 2
+        u32s(&mut flow, &[1, 7, 1000, 5000, 0, 1, 2, 2]);
+        u32s(&mut flow, &[RAW_HEADER, raw_header.len() as u32]);
+        flow.extend(raw_header);
+        u32s(&mut flow, &[EXTENDED_SWITCH, 16, 10, 0, 20, 0]);
+
+        let mut counters = Vec::new();
+        u32s(&mut counters, &[3, 7, 1, (4 << 12) | 1, 4, 0xdead_beef]);
+
+        let mut data = Vec::new();
+        u32s(&mut data, &[5, 1]);
+        data.extend_from_slice(&[192, 168, 1, 1]);
+        u32s(&mut data, &[0, 99, 123_456, 2]);
+        u32s(&mut data, &[FLOW_SAMPLE, flow.len() as u32]);
+        data.extend(flow);
+        u32s(&mut data, &[COUNTER_SAMPLE, counters.len() as u32]);
+        data.extend(counters);
+        data
+    }
+
+    #[test]
+    fn samples() -> Result<()> {
+        let exporter = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1));
+        let data = datagram();
+        assert!(is_sflow(&data));
+        let samples = decode(exporter, &data)?;
+        assert_eq!(2, samples.len());
+
+        let flow = &samples[0];
+        assert_eq!(Some("192.168.1.1"), flow.get_str("agent"));
+        assert_eq!(Some(99), flow.get_u64("sequence"));
+        let sample = flow.get("sample");
+        assert_eq!(Some("flow"), sample.get_str("type"));
+        assert_eq!(Some(7), sample.get_u64("source_id_index"));
+        assert_eq!(Some(1000), sample.get_u64("sampling_rate"));
+        let records = sample.get_array("records").ok_or("no records")?;
+        let header = &records[0];
+        assert_eq!(Some("raw_header"), header.get_str("type"));
+        assert_eq!(
+            Some("06:07:08:09:0a:0b"),
+            header.get_str("sourceMacAddress")
+        );
+        assert_eq!(Some(42), header.get_u64("vlanId"));
+        assert_eq!(Some("10.0.0.2"), header.get_str("destinationIPv4Address"));
+        assert_eq!(Some(6), header.get_u64("protocolIdentifier"));
+        assert_eq!(Some(1234), header.get_u64("sourceTransportPort"));
+        assert_eq!(Some(443), header.get_u64("destinationTransportPort"));
+        assert_eq!(Some(0x12), header.get_u64("tcpControlBits"));
+        assert_eq!(Some(20), records[1].get_u64("dst_vlan"));
+
+        let sample = samples[1].get("sample");
+        assert_eq!(Some("counter"), sample.get_str("type"));
+        let records = sample.get_array("records").ok_or("no records")?;
+        assert_eq!(Some("4:1"), records[0].get_str("type"));
+        assert_eq!(Some("deadbeef"), records[0].get_str("data"));
+
+        assert!(decode(exporter, &data[..40]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,921 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # SNMP Trap Onramp
+//!
+//! Receives SNMP v1, v2c and v3 traps and informs on `host`:`port` and turns
+//! each into an event. No MIBs are loaded, OIDs are passed through in their
+//! dotted form:
+//!
+//! ```json
+//! {
+//!   "version": "2c",
+//!   "community": "public",
+//!   "pdu": "trap",
+//!   "request_id": 42,
+//!   "uptime": 123456,
+//!   "trap_oid": "1.3.6.1.6.3.1.1.5.3",
+//!   "varbinds": [
+//!     {"oid": "1.3.6.1.2.1.2.2.1.1.2", "type": "integer", "value": 2}
+//!   ]
+//! }
+//! ```
+//!
+//! v1 traps carry `enterprise`, `agent_addr`, `generic_trap`, `specific_trap`
+//! and `uptime` instead, v3 traps the `user` and `engine_id` instead of the
+//! `community`. Octet strings that aren't printable UTF-8 are passed as hex
+//! with `"encoding": "hex"`. v1 and v2c informs are acknowledged.
+//!
+//! With `communities` set, v1 and v2c traps of other communities are dropped.
+//! v3 traps are only accepted from the `users`, authenticated with
+//! `usmHMAC192SHA256AuthProtocol` if the user has an `auth_password` (or
+//! `auth_password_env`). Encrypted v3 traps aren't supported, and neither are
+//! v3 informs, as they require engine discovery. Dropped traps are reported
+//! on the `diagnostics` port.
+//!
+//! ```yaml
+//! host: 0.0.0.0
+//! port: 162
+//! communities:
+//!   - public
+//! users:
+//!   - name: noc
+//!     auth_password_env: SNMP_NOC_AUTH
+//! ```
+//!
+//! The peer is available as `$udp.host` and `$udp.port`.
+
+use crate::source::prelude::*;
+use crate::source::webhook::{secret, token_matches};
+use async_std::net::{ToSocketAddrs, UdpSocket};
+use halfbrown::HashMap;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct User {
+    /// USM user name
+    pub name: String,
+    /// password the authentication key is derived from
+    #[serde(default = "Default::default")]
+    pub auth_password: Option<String>,
+    /// environment variable holding the password
+    #[serde(default = "Default::default")]
+    pub auth_password_env: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// The port to listen on.
+    #[serde(default = "d_port")]
+    pub port: u16,
+    pub host: String,
+    /// communities v1 and v2c traps are accepted for, any if empty
+    #[serde(default = "Default::default")]
+    pub communities: Vec<String>,
+    /// users v3 traps are accepted from
+    #[serde(default = "Default::default")]
+    pub users: Vec<User>,
+}
+
+fn d_port() -> u16 {
+    162
+}
+
+impl ConfigImpl for Config {}
+
+pub struct SnmpTrap {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for SnmpTrap {
+    fn from_config(onramp_id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(Box::new(Self {
+                config,
+                onramp_id: onramp_id.clone(),
+            }))
+        } else {
+            Err("Missing config for snmp-trap onramp".into())
+        }
+    }
+}
+
+/// BER tags
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const IP_ADDRESS: u8 = 0x40;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIMETICKS: u8 = 0x43;
+const OPAQUE: u8 = 0x44;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+const RESPONSE: u8 = 0xA2;
+const TRAP_V1: u8 = 0xA4;
+const INFORM: u8 = 0xA6;
+const TRAP: u8 = 0xA7;
+
+const SYS_UPTIME: &str = "1.3.6.1.2.1.1.3.0";
+const SNMP_TRAP_OID: &str = "1.3.6.1.6.3.1.1.4.1.0";
+
+/// length of the truncated HMAC-SHA-256 of `usmHMAC192SHA256AuthProtocol`
+const AUTH_LEN: usize = 24;
+const FLAG_AUTH: u8 = 0x01;
+const FLAG_PRIV: u8 = 0x02;
+
+/// A reader of BER encoded values
+struct Ber<'d> {
+    data: &'d [u8],
+}
+
+impl<'d> Ber<'d> {
+    fn new(data: &'d [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// the next tag and its contents
+    fn next(&mut self) -> std::result::Result<(u8, &'d [u8]), &'static str> {
+        let (&tag, rest) = self.data.split_first().ok_or("truncated value")?;
+        let (&first, mut rest) = rest.split_first().ok_or("truncated length")?;
+        let len = if first & 0x80 == 0 {
+            usize::from(first)
+        } else {
+            let n = usize::from(first & 0x7f);
+            if n == 0 || n > 4 || rest.len() < n {
+                return Err("invalid length");
+            }
+            let len = rest[..n]
+                .iter()
+                .fold(0_usize, |len, b| (len << 8) | usize::from(*b));
+            rest = &rest[n..];
+            len
+        };
+        if rest.len() < len {
+            return Err("truncated value");
+        }
+        let (content, rest) = rest.split_at(len);
+        self.data = rest;
+        Ok((tag, content))
+    }
+
+    fn expect(&mut self, expected: u8) -> std::result::Result<&'d [u8], &'static str> {
+        match self.next()? {
+            (tag, content) if tag == expected => Ok(content),
+            _ => Err("unexpected tag"),
+        }
+    }
+
+    fn integer(&mut self) -> std::result::Result<i64, &'static str> {
+        integer(self.expect(INTEGER)?)
+    }
+}
+
+fn integer(content: &[u8]) -> std::result::Result<i64, &'static str> {
+    if content.is_empty() || content.len() > 8 {
+        return Err("invalid integer");
+    }
+    let init = if content[0] & 0x80 == 0 { 0 } else { -1 };
+    Ok(content
+        .iter()
+        .fold(init, |n: i64, b| (n << 8) | i64::from(*b)))
+}
+
+fn unsigned(content: &[u8]) -> std::result::Result<u64, &'static str> {
+    // a leading zero byte keeps the sign bit clear
+    let content = match content {
+        [0, rest @ ..] if rest.len() == 8 => rest,
+        _ => content,
+    };
+    if content.is_empty() || content.len() > 8 {
+        return Err("invalid unsigned integer");
+    }
+    Ok(content.iter().fold(0, |n: u64, b| (n << 8) | u64::from(*b)))
+}
+
+fn oid(content: &[u8]) -> std::result::Result<String, &'static str> {
+    let mut arcs: Vec<u64> = Vec::new();
+    let mut arc: u64 = 0;
+    for (i, b) in content.iter().enumerate() {
+        if arc > (u64::MAX >> 7) {
+            return Err("invalid oid");
+        }
+        arc = (arc << 7) | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            if arcs.is_empty() {
+                // the first subidentifier encodes the first two arcs
+                let first = (arc / 40).min(2);
+                arcs.push(first);
+                arcs.push(arc - first * 40);
+            } else {
+                arcs.push(arc);
+            }
+            arc = 0;
+        } else if i + 1 == content.len() {
+            return Err("truncated oid");
+        }
+    }
+    if arcs.is_empty() {
+        return Err("empty oid");
+    }
+    Ok(arcs
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join("."))
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn string(data: &[u8]) -> std::result::Result<String, &'static str> {
+    String::from_utf8(data.to_vec()).map_err(|_| "invalid utf-8")
+}
+
+/// decodes a varbind value into its type name and value
+fn varbind_value(tag: u8, content: &[u8]) -> std::result::Result<Value<'static>, &'static str> {
+    Ok(match tag {
+        INTEGER => literal!({"type": "integer", "value": integer(content)?}),
+        OCTET_STRING => match std::str::from_utf8(content) {
+            Ok(s) if !s.chars().any(|c| c.is_control() && !c.is_whitespace()) => {
+                literal!({"type": "octet-string", "value": s.to_string()})
+            }
+            _ => literal!({"type": "octet-string", "value": hex(content), "encoding": "hex"}),
+        },
+        NULL => literal!({"type": "null", "value": null}),
+        OID => literal!({"type": "oid", "value": oid(content)?}),
+        IP_ADDRESS => match content {
+            [a, b, c, d] => {
+                literal!({"type": "ip-address", "value": format!("{}.{}.{}.{}", a, b, c, d)})
+            }
+            _ => return Err("invalid ip address"),
+        },
+        COUNTER32 => literal!({"type": "counter32", "value": unsigned(content)?}),
+        GAUGE32 => literal!({"type": "gauge32", "value": unsigned(content)?}),
+        TIMETICKS => literal!({"type": "timeticks", "value": unsigned(content)?}),
+        OPAQUE => literal!({"type": "opaque", "value": hex(content), "encoding": "hex"}),
+        COUNTER64 => literal!({"type": "counter64", "value": unsigned(content)?}),
+        NO_SUCH_OBJECT => literal!({"type": "no-such-object", "value": null}),
+        NO_SUCH_INSTANCE => literal!({"type": "no-such-instance", "value": null}),
+        END_OF_MIB_VIEW => literal!({"type": "end-of-mib-view", "value": null}),
+        _ => return Err("unknown value type"),
+    })
+}
+
+/// decodes a varbind list, pulling `sysUpTime.0` and `snmpTrapOID.0` into
+/// the event
+fn varbinds(content: &[u8], event: &mut Value<'static>) -> std::result::Result<(), &'static str> {
+    let mut list = Ber::new(content);
+    let mut res = Vec::new();
+    while !list.is_empty() {
+        let mut varbind = Ber::new(list.expect(SEQUENCE)?);
+        let name = oid(varbind.expect(OID)?)?;
+        let (tag, value) = varbind.next()?;
+        let mut value = varbind_value(tag, value)?;
+        match name.as_str() {
+            SYS_UPTIME => {
+                if let Some(uptime) = value.as_object_mut().and_then(|o| o.remove("value")) {
+                    event.try_insert("uptime", uptime);
+                }
+            }
+            SNMP_TRAP_OID => {
+                if let Some(trap_oid) = value.as_object_mut().and_then(|o| o.remove("value")) {
+                    event.try_insert("trap_oid", trap_oid);
+                }
+            }
+            _ => {
+                value.try_insert("oid", name);
+                res.push(value);
+            }
+        }
+    }
+    event.try_insert("varbinds", Value::from(res));
+    Ok(())
+}
+
+/// decodes a PDU into the event
+fn pdu(
+    tag: u8,
+    content: &[u8],
+    event: &mut Value<'static>,
+) -> std::result::Result<(), &'static str> {
+    let mut pdu = Ber::new(content);
+    match tag {
+        TRAP_V1 => {
+            event.try_insert("pdu", "trap-v1");
+            event.try_insert("enterprise", oid(pdu.expect(OID)?)?);
+            match pdu.expect(IP_ADDRESS)? {
+                [a, b, c, d] => {
+                    event.try_insert("agent_addr", format!("{}.{}.{}.{}", a, b, c, d));
+                }
+                _ => return Err("invalid agent address"),
+            }
+            event.try_insert("generic_trap", pdu.integer()?);
+            event.try_insert("specific_trap", pdu.integer()?);
+            event.try_insert("uptime", unsigned(pdu.expect(TIMETICKS)?)?);
+        }
+        TRAP | INFORM => {
+            event.try_insert("pdu", if tag == TRAP { "trap" } else { "inform" });
+            event.try_insert("request_id", pdu.integer()?);
+            // error status and index are always 0 for traps
+            pdu.integer()?;
+            pdu.integer()?;
+        }
+        _ => return Err("not a trap"),
+    }
+    varbinds(pdu.expect(SEQUENCE)?, event)
+}
+
+/// appends a BER encoded value
+fn encode(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+    let bytes = content.len().to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    match &bytes[skip..] {
+        [len] if *len < 0x80 => out.push(*len),
+        [] => out.push(0),
+        len => {
+            // at most 8 length bytes
+            #[allow(clippy::cast_possible_truncation)]
+            out.push(0x80 | len.len() as u8);
+            out.extend_from_slice(len);
+        }
+    }
+    out.extend_from_slice(content);
+}
+
+fn encode_integer(out: &mut Vec<u8>, n: i64) {
+    let bytes = n.to_be_bytes();
+    let mut skip = 0;
+    // drop redundant leading bytes, keeping the sign bit
+    while skip < 7
+        && ((bytes[skip] == 0 && bytes[skip + 1] & 0x80 == 0)
+            || (bytes[skip] == 0xff && bytes[skip + 1] & 0x80 != 0))
+    {
+        skip += 1;
+    }
+    encode(out, INTEGER, &bytes[skip..]);
+}
+
+/// the response acknowledging a v1 or v2c inform
+fn inform_response(version: i64, community: &[u8], request_id: i64, varbinds: &[u8]) -> Vec<u8> {
+    let mut pdu = Vec::new();
+    encode_integer(&mut pdu, request_id);
+    encode_integer(&mut pdu, 0);
+    encode_integer(&mut pdu, 0);
+    encode(&mut pdu, SEQUENCE, varbinds);
+    let mut message = Vec::new();
+    encode_integer(&mut message, version);
+    encode(&mut message, OCTET_STRING, community);
+    encode(&mut message, RESPONSE, &pdu);
+    let mut out = Vec::new();
+    encode(&mut out, SEQUENCE, &message);
+    out
+}
+
+/// localizes the key of a password for an engine (RFC 3414 A.2.1, RFC 7860)
+fn localize_key(password: &[u8], engine_id: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    if !password.is_empty() {
+        // the password repeated to 1 MiB
+        let mut chunk = [0_u8; 64];
+        let mut password = password.iter().cycle();
+        for _ in 0..(1_048_576 / 64) {
+            for (b, p) in chunk.iter_mut().zip(&mut password) {
+                *b = *p;
+            }
+            hasher.update(&chunk);
+        }
+    }
+    let ku = hasher.finalize();
+    let mut hasher = Sha256::new();
+    hasher.update(&ku);
+    hasher.update(engine_id);
+    hasher.update(&ku);
+    hasher.finalize().to_vec()
+}
+
+/// A decoded trap
+#[derive(Debug)]
+struct Trap {
+    event: Value<'static>,
+    /// the response to send back, for informs
+    response: Option<Vec<u8>>,
+}
+
+/// Decodes traps, checking communities and users
+struct Decoder {
+    communities: Vec<String>,
+    /// user name to its password, if it has one
+    users: HashMap<String, Option<String>>,
+    /// localized keys by user and engine id
+    keys: HashMap<(String, Vec<u8>), Vec<u8>>,
+}
+
+impl Decoder {
+    fn from_config(config: &Config) -> Result<Self> {
+        let mut users = HashMap::new();
+        for user in &config.users {
+            let password = secret(&user.auth_password, &user.auth_password_env)?;
+            users.insert(
+                user.name.clone(),
+                if password.is_empty() {
+                    None
+                } else {
+                    Some(password)
+                },
+            );
+        }
+        Ok(Self {
+            communities: config.communities.clone(),
+            users,
+            keys: HashMap::new(),
+        })
+    }
+
+    fn decode(&mut self, data: &[u8]) -> std::result::Result<Trap, &'static str> {
+        let mut outer = Ber::new(data);
+        let mut message = Ber::new(outer.expect(SEQUENCE)?);
+        let version = message.integer()?;
+        match version {
+            0 | 1 => {
+                let community = message.expect(OCTET_STRING)?;
+                if !self.communities.is_empty()
+                    && !self
+                        .communities
+                        .iter()
+                        .any(|c| token_matches(c.as_bytes(), community))
+                {
+                    return Err("unknown community");
+                }
+                let mut event = Value::object_with_capacity(8);
+                event.try_insert("version", if version == 0 { "1" } else { "2c" });
+                event.try_insert("community", string(community)?);
+                let (tag, content) = message.next()?;
+                pdu(tag, content, &mut event)?;
+                let response = if tag == INFORM {
+                    let mut inform = Ber::new(content);
+                    let request_id = inform.integer()?;
+                    inform.integer()?;
+                    inform.integer()?;
+                    Some(inform_response(
+                        version,
+                        community,
+                        request_id,
+                        inform.expect(SEQUENCE)?,
+                    ))
+                } else {
+                    None
+                };
+                Ok(Trap { event, response })
+            }
+            3 => self.decode_v3(data, message).map(|event| Trap {
+                event,
+                response: None,
+            }),
+            _ => Err("unsupported version"),
+        }
+    }
+
+    fn decode_v3(
+        &mut self,
+        data: &[u8],
+        mut message: Ber<'_>,
+    ) -> std::result::Result<Value<'static>, &'static str> {
+        let mut global = Ber::new(message.expect(SEQUENCE)?);
+        let _msg_id = global.integer()?;
+        let _max_size = global.integer()?;
+        let flags = *global
+            .expect(OCTET_STRING)?
+            .first()
+            .ok_or("missing flags")?;
+        if global.integer()? != 3 {
+            return Err("unsupported security model");
+        }
+        let mut usm = Ber::new(message.expect(OCTET_STRING)?);
+        let mut usm = Ber::new(usm.expect(SEQUENCE)?);
+        let engine_id = usm.expect(OCTET_STRING)?;
+        let _boots = usm.integer()?;
+        let _time = usm.integer()?;
+        let user = string(usm.expect(OCTET_STRING)?)?;
+        let auth = usm.expect(OCTET_STRING)?;
+
+        let password = self.users.get(&user).ok_or("unknown user")?.clone();
+        if flags & FLAG_PRIV != 0 {
+            return Err("encrypted traps are not supported");
+        }
+        match (password, flags & FLAG_AUTH != 0) {
+            (Some(password), true) => {
+                if auth.len() != AUTH_LEN {
+                    return Err("unsupported authentication protocol");
+                }
+                let key = self
+                    .keys
+                    .entry((user.clone(), engine_id.to_vec()))
+                    .or_insert_with(|| localize_key(password.as_bytes(), engine_id));
+                // the digest is computed with the authentication parameters zeroed
+                let start = auth.as_ptr() as usize - data.as_ptr() as usize;
+                let mut zeroed = data.to_vec();
+                for b in zeroed.iter_mut().skip(start).take(AUTH_LEN) {
+                    *b = 0;
+                }
+                let mut mac = Hmac::<Sha256>::new_varkey(key).map_err(|_| "invalid key")?;
+                mac.update(&zeroed);
+                let digest = mac.finalize().into_bytes();
+                if !token_matches(&digest[..AUTH_LEN], auth) {
+                    return Err("authentication failed");
+                }
+            }
+            (Some(_), false) => return Err("unauthenticated trap"),
+            (None, _) => (),
+        }
+
+        let mut scoped = Ber::new(message.expect(SEQUENCE)?);
+        let _context_engine_id = scoped.expect(OCTET_STRING)?;
+        let context = string(scoped.expect(OCTET_STRING)?)?;
+        let mut event = Value::object_with_capacity(8);
+        event.try_insert("version", "3");
+        event.try_insert("user", user);
+        event.try_insert("engine_id", hex(engine_id));
+        event.try_insert("context", context);
+        let (tag, content) = scoped.next()?;
+        if tag == INFORM {
+            return Err("v3 informs are not supported");
+        }
+        pdu(tag, content, &mut event)?;
+        Ok(event)
+    }
+}
+
+struct Int {
+    config: Config,
+    socket: Option<UdpSocket>,
+    decoder: Decoder,
+    onramp_id: TremorUrl,
+    origin_uri: EventOriginUri,
+}
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SnmpTrap")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-snmp-trap".to_string(),
+            host: String::default(),
+            port: None,
+            path: vec![config.port.to_string()],
+        };
+        Ok(Self {
+            config: config.clone(),
+            socket: None,
+            decoder: Decoder::from_config(config)?,
+            onramp_id,
+            origin_uri,
+        })
+    }
+
+    async fn bind(&self) -> Result<UdpSocket> {
+        let addr = (self.config.host.as_str(), self.config.port)
+            .to_socket_addrs()
+            .await?
+            .next()
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "{}:{} doesn't resolve to an address",
+                    self.config.host, self.config.port
+                ))
+            })?;
+        let socket = UdpSocket::bind(addr).await?;
+        info!(
+            "[Source::{}] listening on {}:{}",
+            self.onramp_id, self.config.host, self.config.port
+        );
+        Ok(socket)
+    }
+
+    async fn handle(&mut self, data: &[u8], peer: SocketAddr) -> Result<SourceReply> {
+        let mut origin_uri = self.origin_uri.clone();
+        origin_uri.host = peer.ip().to_string();
+        origin_uri.port = Some(peer.port());
+        match self.decoder.decode(data) {
+            Ok(Trap { event, response }) => {
+                if let (Some(response), Some(socket)) = (response, self.socket.as_ref()) {
+                    socket.send_to(&response, peer).await?;
+                }
+                let meta = literal!({
+                    "udp": {
+                        "host": peer.ip().to_string(),
+                        "port": peer.port()
+                    }
+                });
+                Ok(SourceReply::Structured {
+                    origin_uri,
+                    data: (event, meta).into(),
+                })
+            }
+            Err(e) => {
+                debug!(
+                    "[Source::{}] Dropped trap from {}: {}",
+                    self.onramp_id, peer, e
+                );
+                Ok(SourceReply::Diagnostics {
+                    origin_uri,
+                    data: literal!({
+                        "dropped": {
+                            "host": peer.ip().to_string(),
+                            "port": peer.port(),
+                            "error": e
+                        }
+                    }),
+                })
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        let mut buf = [0; 65535];
+        if let Some(socket) = self.socket.as_mut() {
+            match socket.recv_from(&mut buf).await {
+                // ALLOW: we get n from recv
+                Ok((n, peer)) => self.handle(&buf[0..n], peer).await,
+                Err(e) => {
+                    if e.kind() == std::io::ErrorKind::WouldBlock {
+                        Ok(SourceReply::Empty(1))
+                    } else {
+                        Err(e.into())
+                    }
+                }
+            }
+        } else {
+            self.socket = Some(self.bind().await?);
+            Ok(SourceReply::StateChange(SourceState::Connected))
+        }
+    }
+    async fn init(&mut self) -> Result<SourceState> {
+        self.socket = Some(self.bind().await?);
+        Ok(SourceState::Connected)
+    }
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for SnmpTrap {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varbind(name: &[u8], tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut content = Vec::new();
+        encode(&mut content, OID, name);
+        encode(&mut content, tag, value);
+        let mut out = Vec::new();
+        encode(&mut out, SEQUENCE, &content);
+        out
+    }
+
+    fn v2c(tag: u8, community: &[u8]) -> Vec<u8> {
+        let mut list = Vec::new();
+        // sysUpTime.0, snmpTrapOID.0 = linkUp, ifIndex.2 and ifDescr.2
+        list.extend(varbind(
+            &[0x2b, 6, 1, 2, 1, 1, 3, 0],
+            TIMETICKS,
+            &[0x01, 0xe2, 0x40],
+        ));
+        list.extend(varbind(
+            &[0x2b, 6, 1, 6, 3, 1, 1, 4, 1, 0],
+            OID,
+            &[0x2b, 6, 1, 6, 3, 1, 1, 5, 4],
+        ));
+        list.extend(varbind(&[0x2b, 6, 1, 2, 1, 2, 2, 1, 1, 2], INTEGER, &[2]));
+        list.extend(varbind(
+            &[0x2b, 6, 1, 2, 1, 2, 2, 1, 2, 2],
+            OCTET_STRING,
+            b"eth0",
+        ));
+        let mut pdu = Vec::new();
+        encode_integer(&mut pdu, 42);
+        encode_integer(&mut pdu, 0);
+        encode_integer(&mut pdu, 0);
+        encode(&mut pdu, SEQUENCE, &list);
+        let mut message = Vec::new();
+        encode_integer(&mut message, 1);
+        encode(&mut message, OCTET_STRING, community);
+        encode(&mut message, tag, &pdu);
+        let mut out = Vec::new();
+        encode(&mut out, SEQUENCE, &message);
+        out
+    }
+
+    fn decoder(communities: &[&str]) -> Result<Decoder> {
+        Decoder::from_config(&Config {
+            port: 162,
+            host: "localhost".to_string(),
+            communities: communities.iter().map(ToString::to_string).collect(),
+            users: vec![User {
+                name: "noc".to_string(),
+                auth_password: Some("maplesyrup".to_string()),
+                auth_password_env: None,
+            }],
+        })
+    }
+
+    #[test]
+    fn decode_v2c_trap() -> Result<()> {
+        let mut decoder = decoder(&["public"])?;
+        let trap = decoder.decode(&v2c(TRAP, b"public"))?;
+        assert!(trap.response.is_none());
+        assert_eq!(
+            literal!({
+                "version": "2c",
+                "community": "public",
+                "pdu": "trap",
+                "request_id": 42,
+                "uptime": 123_456_u64,
+                "trap_oid": "1.3.6.1.6.3.1.1.5.4",
+                "varbinds": [
+                    {"type": "integer", "value": 2, "oid": "1.3.6.1.2.1.2.2.1.1.2"},
+                    {"type": "octet-string", "value": "eth0", "oid": "1.3.6.1.2.1.2.2.1.2.2"}
+                ]
+            }),
+            trap.event
+        );
+        assert_eq!(
+            Err("unknown community"),
+            decoder.decode(&v2c(TRAP, b"private")).map(|_| ())
+        );
+        assert!(decoder.decode(&[0x30, 0x05, 0x02]).is_err());
+        Ok(())
+    }
+
+    fn v3(user: &[u8], flags: u8, password: &[u8]) -> Result<Vec<u8>> {
+        let engine_id = [0x80, 0, 0x1f, 0x88, 4, 1, 2, 3];
+        let mut usm = Vec::new();
+        encode(&mut usm, OCTET_STRING, &engine_id);
+        encode_integer(&mut usm, 1);
+        encode_integer(&mut usm, 1000);
+        encode(&mut usm, OCTET_STRING, user);
+        encode(&mut usm, OCTET_STRING, &[0; AUTH_LEN]);
+        encode(&mut usm, OCTET_STRING, &[]);
+        let mut security = Vec::new();
+        encode(&mut security, SEQUENCE, &usm);
+        let mut global = Vec::new();
+        encode_integer(&mut global, 7);
+        encode_integer(&mut global, 65507);
+        encode(&mut global, OCTET_STRING, &[flags]);
+        encode_integer(&mut global, 3);
+        let mut pdu = Vec::new();
+        encode_integer(&mut pdu, 42);
+        encode_integer(&mut pdu, 0);
+        encode_integer(&mut pdu, 0);
+        encode(&mut pdu, SEQUENCE, &[]);
+        let mut scoped = Vec::new();
+        encode(&mut scoped, OCTET_STRING, &engine_id);
+        encode(&mut scoped, OCTET_STRING, b"");
+        encode(&mut scoped, TRAP, &pdu);
+        let mut message = Vec::new();
+        encode_integer(&mut message, 3);
+        encode(&mut message, SEQUENCE, &global);
+        encode(&mut message, OCTET_STRING, &security);
+        encode(&mut message, SEQUENCE, &scoped);
+        let mut out = Vec::new();
+        encode(&mut out, SEQUENCE, &message);
+
+        let mut mac = Hmac::<Sha256>::new_varkey(&localize_key(password, &engine_id))
+            .map_err(|e| e.to_string())?;
+        mac.update(&out);
+        let digest = mac.finalize().into_bytes();
+        let mut placeholder = vec![OCTET_STRING, 24];
+        placeholder.extend_from_slice(&[0; AUTH_LEN]);
+        let start = out
+            .windows(placeholder.len())
+            .position(|w| w == placeholder.as_slice())
+            .ok_or("no auth parameters")?
+            + 2;
+        out[start..start + AUTH_LEN].copy_from_slice(&digest[..AUTH_LEN]);
+        Ok(out)
+    }
+
+    #[test]
+    fn decode_v3_trap() -> Result<()> {
+        let mut decoder = decoder(&[])?;
+        let trap = decoder.decode(&v3(b"noc", FLAG_AUTH, b"maplesyrup")?)?;
+        assert_eq!(Some("noc"), trap.event.get_str("user"));
+        assert_eq!(Some("80001f8804010203"), trap.event.get_str("engine_id"));
+        assert_eq!(Some(42), trap.event.get_i64("request_id"));
+        assert_eq!(
+            Err("authentication failed"),
+            decoder
+                .decode(&v3(b"noc", FLAG_AUTH, b"pancakes")?)
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err("unauthenticated trap"),
+            decoder.decode(&v3(b"noc", 0, b"")?).map(|_| ())
+        );
+        assert_eq!(
+            Err("encrypted traps are not supported"),
+            decoder
+                .decode(&v3(b"noc", FLAG_AUTH | FLAG_PRIV, b"maplesyrup")?)
+                .map(|_| ())
+        );
+        assert_eq!(
+            Err("unknown user"),
+            decoder.decode(&v3(b"root", 0, b"")?).map(|_| ())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn acknowledge_inform() -> Result<()> {
+        let mut decoder = decoder(&[])?;
+        let response = decoder
+            .decode(&v2c(INFORM, b"private"))?
+            .response
+            .ok_or("no response")?;
+        let mut outer = Ber::new(&response);
+        let mut message = Ber::new(outer.expect(SEQUENCE)?);
+        assert_eq!(Ok(1), message.integer());
+        assert_eq!(Ok(&b"private"[..]), message.expect(OCTET_STRING));
+        let mut pdu = Ber::new(message.expect(RESPONSE)?);
+        assert_eq!(Ok(42), pdu.integer());
+        Ok(())
+    }
+
+    #[test]
+    fn codec() {
+        assert_eq!(
+            Ok("1.3.6.1.4.1.2021".to_string()),
+            oid(&[0x2b, 6, 1, 4, 1, 0x8f, 0x65])
+        );
+        assert!(oid(&[0x2b, 0x81]).is_err());
+        for n in &[0, 1, -1, 127, 128, -129, 42_000, i64::MIN, i64::MAX] {
+            let mut out = Vec::new();
+            encode_integer(&mut out, *n);
+            assert_eq!(Ok(*n), Ber::new(&out).integer());
+        }
+        assert_eq!(
+            Ok(u64::MAX),
+            unsigned(&[0, 255, 255, 255, 255, 255, 255, 255, 255])
+        );
+        let long = vec![0; 300];
+        let mut out = Vec::new();
+        encode(&mut out, OCTET_STRING, &long);
+        assert_eq!(Ok(&long[..]), Ber::new(&out).expect(OCTET_STRING));
+    }
+
+    #[test]
+    fn localized_key() {
+        // the password and engine id of RFC 3414 A.3.1, with SHA-256
+        assert_eq!(
+            "8982e0e549e866db361a6b625d84cccc11162d453ee8ce3a6445c2d6776f0f8b",
+            hex(&localize_key(
+                b"maplesyrup",
+                &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]
+            ))
+        );
+    }
+}
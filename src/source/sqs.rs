@@ -0,0 +1,307 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! AWS SQS onramp
+//!
+//! Receives messages from a queue with long polling. A message is deleted
+//! once its event was delivered downstream and returned to the queue right
+//! away if it failed. While an event is in flight the visibility timeout of
+//! its message is extended, so it isn't redelivered to another consumer.
+//!
+//! The message attributes are available as `$sqs.attributes` and
+//! `$sqs.message_attributes`, the message id as `$sqs.message_id`.
+//!
+//! Credentials are read from the `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`
+//! and `AWS_SESSION_TOKEN` environment variables or the shared credentials
+//! file.
+
+use crate::connectors::aws::auth::{self, AwsClient};
+use crate::connectors::aws::sqs::{self, Message, MAX_MESSAGES, MAX_WAIT_TIME};
+use crate::source::prelude::*;
+use halfbrown::HashMap;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// seconds before a message becomes visible again in which its visibility
+/// timeout is extended, on top of the time a receive call may wait
+const EXTENSION_MARGIN: u64 = 5;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// url of the queue
+    pub queue_url: String,
+    /// AWS region, defaults to `AWS_REGION`
+    #[serde(default)]
+    pub region: Option<String>,
+    /// overrides the `sqs.{region}.amazonaws.com` endpoint
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// seconds a receive call waits for messages to arrive, at most 20
+    #[serde(default = "d_wait_time")]
+    pub wait_time: u64,
+    /// messages per receive call, at most 10
+    #[serde(default = "d_max_messages")]
+    pub max_messages: u64,
+    /// seconds a received message is hidden from other consumers, and by
+    /// which it is extended while its event is in flight
+    #[serde(default = "d_visibility_timeout")]
+    pub visibility_timeout: u64,
+}
+
+fn d_wait_time() -> u64 {
+    MAX_WAIT_TIME
+}
+
+fn d_max_messages() -> u64 {
+    MAX_MESSAGES
+}
+
+fn d_visibility_timeout() -> u64 {
+    60
+}
+
+impl ConfigImpl for Config {}
+
+pub struct Sqs {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for Sqs {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let mut config: Config = Config::new(config)?;
+            config.wait_time = config.wait_time.min(MAX_WAIT_TIME);
+            config.max_messages = config.max_messages.min(MAX_MESSAGES).max(1);
+            if config.visibility_timeout <= config.wait_time + EXTENSION_MARGIN {
+                return Err(format!(
+                    "The sqs onramp `visibility_timeout` has to exceed `wait_time` by more than {} seconds",
+                    EXTENSION_MARGIN
+                )
+                .into());
+            }
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for sqs onramp".into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for Sqs {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+/// A received message and when it becomes visible to other consumers again
+struct Received {
+    message: Message,
+    visible_at: Instant,
+}
+
+pub struct Int {
+    onramp_id: TremorUrl,
+    config: Config,
+    region: String,
+    client: Option<AwsClient>,
+    origin_uri: EventOriginUri,
+    /// received messages not pulled yet
+    received: VecDeque<Received>,
+    /// messages by the id of their event
+    in_flight: HashMap<u64, Received>,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SQS")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        let config = config.clone();
+        let url = url::Url::parse(&config.queue_url)?;
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-sqs".to_string(),
+            host: url.host_str().unwrap_or_default().to_string(),
+            port: url.port(),
+            path: url
+                .path_segments()
+                .map(|s| s.map(ToString::to_string).collect())
+                .unwrap_or_default(),
+        };
+        Ok(Self {
+            onramp_id,
+            region: auth::region(config.region.as_deref())?,
+            config,
+            client: None,
+            origin_uri,
+            received: VecDeque::new(),
+            in_flight: HashMap::new(),
+        })
+    }
+
+    /// extends the visibility timeout of messages that would become visible
+    /// again before the next receive call returns
+    async fn extend_visibility(&mut self, client: &AwsClient) {
+        let threshold =
+            Instant::now() + Duration::from_secs(self.config.wait_time + EXTENSION_MARGIN);
+        let expiring = self
+            .in_flight
+            .values_mut()
+            .chain(self.received.iter_mut())
+            .filter(|r| r.visible_at <= threshold);
+        for received in expiring {
+            let extended_at = Instant::now();
+            match sqs::change_message_visibility(
+                client,
+                &self.config.queue_url,
+                &received.message.receipt_handle,
+                self.config.visibility_timeout,
+            )
+            .await
+            {
+                Ok(()) => {
+                    received.visible_at =
+                        extended_at + Duration::from_secs(self.config.visibility_timeout);
+                }
+                Err(e) => warn!(
+                    "[Source::{}] failed to extend the visibility timeout: {}",
+                    self.onramp_id, e
+                ),
+            }
+        }
+    }
+
+    /// settles the message of an event, in the background as acks and fails
+    /// aren't async
+    fn settle(&mut self, id: u64, delete: bool) {
+        if let Some((received, client)) = self.in_flight.remove(&id).zip(self.client.clone()) {
+            let onramp_id = self.onramp_id.clone();
+            let queue_url = self.config.queue_url.clone();
+            task::spawn(async move {
+                let receipt_handle = &received.message.receipt_handle;
+                let res = if delete {
+                    sqs::delete_message(&client, &queue_url, receipt_handle).await
+                } else {
+                    sqs::change_message_visibility(&client, &queue_url, receipt_handle, 0).await
+                };
+                if let Err(e) = res {
+                    error!("[Source::{}] failed to settle message: {}", onramp_id, e);
+                }
+            });
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        let client = if let Some(client) = self.client.clone() {
+            client
+        } else {
+            return Ok(SourceReply::StateChange(SourceState::Disconnected));
+        };
+        self.extend_visibility(&client).await;
+        if self.received.is_empty() {
+            let received_at = Instant::now();
+            match sqs::receive_message(
+                &client,
+                &self.config.queue_url,
+                self.config.max_messages,
+                self.config.wait_time,
+                self.config.visibility_timeout,
+            )
+            .await
+            {
+                Ok(messages) => {
+                    let visible_at =
+                        received_at + Duration::from_secs(self.config.visibility_timeout);
+                    self.received
+                        .extend(messages.into_iter().map(|message| Received {
+                            message,
+                            visible_at,
+                        }));
+                }
+                Err(e) => {
+                    error!("[Source::{}] failed to receive: {}", self.onramp_id, e);
+                    return Ok(SourceReply::Empty(1000));
+                }
+            }
+        }
+        if let Some(received) = self.received.pop_front() {
+            let message = &received.message;
+            let mut meta = Value::object_with_capacity(3);
+            meta.insert("message_id", message.message_id.clone())?;
+            meta.insert("attributes", message.attributes.clone())?;
+            meta.insert("message_attributes", message.message_attributes.clone())?;
+            let mut sqs_meta = Value::object_with_capacity(1);
+            sqs_meta.insert("sqs", meta)?;
+            let data = message.body.as_bytes().to_vec();
+            self.in_flight.insert(id, received);
+            Ok(SourceReply::Data {
+                origin_uri: self.origin_uri.clone(),
+                data,
+                meta: Some(sqs_meta),
+                codec_override: None,
+                stream: 0,
+            })
+        } else {
+            Ok(SourceReply::Empty(0))
+        }
+    }
+
+    async fn on_empty_event(&mut self, id: u64, _stream: usize) -> Result<()> {
+        // nothing in flight downstream to wait for
+        self.settle(id, true);
+        Ok(())
+    }
+
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        self.client = Some(AwsClient::new(
+            "sqs",
+            &self.region,
+            self.config.endpoint.as_deref(),
+        )?);
+        Ok(SourceState::Connected)
+    }
+
+    fn ack(&mut self, id: u64) {
+        self.settle(id, true);
+    }
+
+    fn fail(&mut self, id: u64) {
+        self.settle(id, false);
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+}
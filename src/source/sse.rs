@@ -0,0 +1,363 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # Server-Sent Events Onramp
+//!
+//! The `sse` onramp connects to a `text/event-stream` endpoint and emits the
+//! `data` of every received message, decoded with the configured codec. The
+//! event name and the last event id are put into `$sse.event` and `$sse.id`.
+//!
+//! Lost connections are retried following the `reconnect` policy, starting
+//! with the delay the server asked for with `retry`, if any. Reconnects send
+//! the last event id as `Last-Event-ID` header, so the server can resume the
+//! stream. `last_event_id` sets the id sent on the first connect.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::reconnect::{self, Reconnect};
+use crate::metrics::InstanceStats;
+use crate::source::prelude::*;
+use async_channel::{Sender, TryRecvError};
+use async_std::io::BufReader;
+use async_std::task::JoinHandle;
+use halfbrown::HashMap;
+use std::sync::Arc;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// The url of the event stream
+    pub url: String,
+    /// Headers added to the request
+    #[serde(default = "Default::default")]
+    pub headers: HashMap<String, String>,
+    /// Event id sent as `Last-Event-ID` on the first connect
+    #[serde(default = "Default::default")]
+    pub last_event_id: Option<String>,
+    /// reconnect policy
+    #[serde(default)]
+    pub reconnect: reconnect::Config,
+}
+
+impl ConfigImpl for Config {}
+
+/// A dispatched message
+#[derive(Debug, PartialEq)]
+struct Message {
+    event: String,
+    data: String,
+    id: Option<String>,
+}
+
+/// Parses the lines of an event stream into messages
+#[derive(Debug, Default)]
+struct Parser {
+    event: Option<String>,
+    data: String,
+    last_event_id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl Parser {
+    /// feeds a line, returning the message a blank line dispatches
+    fn line(&mut self, line: &str) -> Option<Message> {
+        if line.is_empty() {
+            let event = self.event.take();
+            if self.data.is_empty() {
+                return None;
+            }
+            let mut data = std::mem::take(&mut self.data);
+            data.pop();
+            return Some(Message {
+                event: event.unwrap_or_else(|| "message".to_string()),
+                data,
+                id: self.last_event_id.clone(),
+            });
+        }
+        // lines starting with a colon are comments
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = line.split_at(line.find(':').unwrap_or_else(|| line.len()));
+        let value = value.strip_prefix(':').unwrap_or(value);
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" => {
+                if let Ok(retry) = value.parse() {
+                    self.retry = Some(retry);
+                }
+            }
+            _ => (),
+        }
+        None
+    }
+}
+
+pub struct Sse {
+    pub config: Config,
+    url: url::Url,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for Sse {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            let url = url::Url::parse(&config.url)?;
+            Ok(Box::new(Self {
+                config,
+                url,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for sse onramp".into())
+        }
+    }
+}
+
+pub struct Int {
+    uid: u64,
+    config: Config,
+    url: url::Url,
+    onramp_id: TremorUrl,
+    stats: Arc<InstanceStats>,
+    rx: Option<Receiver<SourceReply>>,
+    connection: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Sse({})", self.url)
+    }
+}
+
+/// connects to the event stream and forwards the received messages, until
+/// the reconnect policy gives up or the onramp is stopped
+async fn connection_loop(
+    source_id: TremorUrl,
+    origin_uri: EventOriginUri,
+    config: Config,
+    stats: Arc<InstanceStats>,
+    tx: Sender<SourceReply>,
+) -> Result<()> {
+    let mut reconnect = Reconnect::new(config.reconnect.clone());
+    reconnect.report_to(Some(stats.clone()));
+    let mut parser = Parser {
+        last_event_id: config.last_event_id.clone(),
+        ..Parser::default()
+    };
+    let mut stream = 0;
+    loop {
+        info!("[Source::{}] Connecting to {} ...", source_id, config.url);
+        let mut request = surf::get(&config.url)
+            .header("Accept", "text/event-stream")
+            .header("Cache-Control", "no-cache");
+        for (name, value) in &config.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(id) = &parser.last_event_id {
+            request = request.header("Last-Event-ID", id.as_str());
+        }
+        let response = match request.await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                let error = format!("{} responded with status {}", config.url, response.status());
+                error!("[Source::{}] {}", source_id, error);
+                if reconnect.wait(&error).await {
+                    continue;
+                }
+                return Err(format!("Gave up connecting to {}: {}", config.url, error).into());
+            }
+            Err(e) => {
+                error!(
+                    "[Source::{}] Failed to connect to {}: {}",
+                    source_id, config.url, e
+                );
+                if reconnect.wait(&e.to_string()).await {
+                    continue;
+                }
+                return Err(format!("Gave up connecting to {}: {}", config.url, e).into());
+            }
+        };
+        reconnect.connected();
+        stream += 1;
+        tx.send(SourceReply::StartStream(stream)).await?;
+
+        // a partially received message is dropped with the connection
+        parser.event = None;
+        parser.data.clear();
+        let mut lines = BufReader::new(response).lines();
+        let error = loop {
+            let line = match lines.next().await {
+                Some(Ok(line)) => line,
+                Some(Err(e)) => break e.to_string(),
+                None => break "connection closed".to_string(),
+            };
+            if let Some(msg) = parser.line(&line) {
+                let mut meta = Value::object_with_capacity(1);
+                meta.insert(
+                    "sse",
+                    literal!({
+                        "event": msg.event,
+                        "id": msg.id
+                    }),
+                )?;
+                tx.send(SourceReply::Data {
+                    origin_uri: origin_uri.clone(),
+                    data: msg.data.into_bytes(),
+                    meta: Some(meta),
+                    codec_override: None,
+                    stream,
+                })
+                .await?;
+            }
+        };
+        tx.send(SourceReply::EndStream(stream)).await?;
+
+        warn!(
+            "[Source::{}] Lost connection to {}: {}",
+            source_id, config.url, error
+        );
+        // the server asked for a different reconnect delay
+        if let Some(retry) = parser.retry.take() {
+            reconnect = Reconnect::new(reconnect::Config {
+                initial_delay: retry,
+                ..config.reconnect.clone()
+            });
+            reconnect.report_to(Some(stats.clone()));
+        }
+        if !reconnect.wait(&error).await {
+            return Err(format!("Gave up reconnecting to {}: {}", config.url, error).into());
+        }
+    }
+}
+
+#[async_trait::async_trait()]
+impl Source for Int {
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        self.rx.as_ref().map_or_else(
+            || Ok(SourceReply::StateChange(SourceState::Disconnected)),
+            |rx| match rx.try_recv() {
+                Ok(r) => Ok(r),
+                Err(TryRecvError::Empty) => Ok(SourceReply::Empty(10)),
+                Err(TryRecvError::Closed) => {
+                    Ok(SourceReply::StateChange(SourceState::Disconnected))
+                }
+            },
+        )
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        let (tx, rx) = bounded(crate::QSIZE);
+        let origin_uri = EventOriginUri {
+            uid: self.uid,
+            scheme: "tremor-sse".to_string(),
+            host: self.url.host_str().unwrap_or_default().to_string(),
+            port: self.url.port_or_known_default(),
+            path: self.url.path_segments().map_or_else(Vec::new, |segments| {
+                segments.map(ToString::to_string).collect()
+            }),
+        };
+        let source_id = self.onramp_id.clone();
+        let connection = connection_loop(
+            self.onramp_id.clone(),
+            origin_uri,
+            self.config.clone(),
+            self.stats.clone(),
+            tx,
+        );
+        self.connection = Some(task::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("[Source::{}] {}", source_id, e);
+            }
+        }));
+        self.rx = Some(rx);
+        Ok(SourceState::Connected)
+    }
+
+    async fn terminate(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.cancel().await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for Sse {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int {
+            uid: config.onramp_uid,
+            config: self.config.clone(),
+            url: self.url.clone(),
+            onramp_id: self.onramp_id.clone(),
+            stats: config.metrics_reporter.stats(),
+            rx: None,
+            connection: None,
+        };
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "string"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(parser: &mut Parser, stream: &str) -> Vec<Message> {
+        stream.split('\n').filter_map(|l| parser.line(l)).collect()
+    }
+
+    #[test]
+    fn parse_messages() {
+        let mut parser = Parser::default();
+        let msgs = feed(
+            &mut parser,
+            ": comment\nretry: 500\ndata: first\ndata:second\n\nevent: update\nid: 42\ndata: {\"a\": 1}\n\nid\n\n",
+        );
+        assert_eq!(
+            vec![
+                Message {
+                    event: "message".to_string(),
+                    data: "first\nsecond".to_string(),
+                    id: None,
+                },
+                Message {
+                    event: "update".to_string(),
+                    data: "{\"a\": 1}".to_string(),
+                    id: Some("42".to_string()),
+                },
+            ],
+            msgs
+        );
+        assert_eq!(Some(500), parser.retry);
+        // an empty id resets the last event id, without dispatching a message
+        assert_eq!(Some(String::new()), parser.last_event_id);
+    }
+}
@@ -0,0 +1,538 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! File tail onramp
+//!
+//! Follows the files matching the glob patterns in `paths`, sending each
+//! line appended to them as an event. The patterns are matched again every
+//! `scan_interval` milliseconds to pick up new files.
+//!
+//! Files are told apart by device and inode rather than by path, so a file
+//! renamed by log rotation is read to its end while the file replacing it is
+//! read from its beginning. A file truncated in place is read again from its
+//! beginning.
+//!
+//! Once an event is acked the offset after its line is checkpointed for its
+//! file, reading resumes there when the onramp is restarted. If an event
+//! fails its file is read again from its line. Checkpoints are kept in
+//! memory or, to survive restarts, in a sled database in `dir`:
+//!
+//! ```yaml
+//! checkpoint:
+//!   store: sled
+//!   dir: /var/lib/tremor/tail
+//! ```
+//!
+//! Files found by the first scan without a checkpoint are read from the
+//! position given by `start_at`, files appearing later from their beginning.
+//!
+//! The path of the file and the offset of the line are available as
+//! `$file.path` and `$file.offset`.
+
+use crate::source::checkpoint::{self, CheckpointConfig, CheckpointStore};
+use crate::source::prelude::*;
+use async_std::fs::File;
+use async_std::io::{BufReader, SeekFrom};
+use halfbrown::HashMap;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tremor_common::asy::file;
+
+/// Where to start reading files without a checkpoint
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartAt {
+    /// read the whole file
+    Beginning,
+    /// read only the lines appended from now on
+    End,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// glob patterns of the files to follow
+    pub paths: Vec<String>,
+    /// where to start reading the files found on start without a checkpoint
+    #[serde(default = "d_start_at")]
+    pub start_at: StartAt,
+    /// milliseconds between matching the patterns for new, rotated or
+    /// truncated files
+    #[serde(default = "d_scan_interval")]
+    pub scan_interval: u64,
+    /// milliseconds to wait for new lines once all files are read to their
+    /// end
+    #[serde(default = "d_poll_interval")]
+    pub poll_interval: u64,
+    /// where checkpoints are kept
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+}
+
+fn d_start_at() -> StartAt {
+    StartAt::Beginning
+}
+
+fn d_scan_interval() -> u64 {
+    1000
+}
+
+fn d_poll_interval() -> u64 {
+    100
+}
+
+impl ConfigImpl for Config {}
+
+pub struct Tail {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for Tail {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            for pattern in &config.paths {
+                glob::Pattern::new(pattern)?;
+            }
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for tail onramp".into())
+        }
+    }
+}
+
+/// Identifies a file independent of its path
+#[cfg(unix)]
+fn file_id(_path: &str, metadata: &std::fs::Metadata) -> String {
+    use std::os::unix::fs::MetadataExt;
+    format!("{}:{}", metadata.dev(), metadata.ino())
+}
+
+/// Identifies a file by its path, rotations are taken for truncations
+#[cfg(not(unix))]
+fn file_id(path: &str, _metadata: &std::fs::Metadata) -> String {
+    path.to_string()
+}
+
+/// The line without its line ending
+fn strip_line_ending(mut line: Vec<u8>) -> Vec<u8> {
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+    line
+}
+
+/// A file being followed
+struct Tailed {
+    path: String,
+    reader: BufReader<File>,
+    /// offset of the next line to send
+    offset: u64,
+    /// the start of a line not yet terminated
+    partial: Vec<u8>,
+    /// whether the file was read to its end
+    eof: bool,
+    /// whether the file no longer matches, it is dropped once read to its end
+    gone: bool,
+    /// offset to read again from, as a line failed
+    rewind: Option<u64>,
+}
+
+impl Tailed {
+    async fn open(path: &str, offset: u64) -> Result<Self> {
+        let mut reader = BufReader::new(file::open(path).await?);
+        reader.seek(SeekFrom::Start(offset)).await?;
+        Ok(Self {
+            path: path.to_string(),
+            reader,
+            offset,
+            partial: Vec::new(),
+            eof: false,
+            gone: false,
+            rewind: None,
+        })
+    }
+
+    async fn rewind(&mut self, offset: u64) -> Result<()> {
+        self.reader.seek(SeekFrom::Start(offset)).await?;
+        self.offset = offset;
+        self.partial.clear();
+        self.eof = false;
+        self.rewind = None;
+        Ok(())
+    }
+
+    /// The next complete line and its offset
+    async fn next_line(&mut self) -> Result<Option<(u64, Vec<u8>)>> {
+        if let Some(offset) = self.rewind {
+            self.rewind(offset).await?;
+        }
+        let read = self.reader.read_until(b'\n', &mut self.partial).await?;
+        if read > 0 && self.partial.last() == Some(&b'\n') {
+            let start = self.offset;
+            let line = std::mem::take(&mut self.partial);
+            self.offset += line.len() as u64;
+            Ok(Some((start, line)))
+        } else {
+            // the line is completed by a later write
+            self.eof = true;
+            Ok(None)
+        }
+    }
+}
+
+struct Int {
+    uid: u64,
+    config: Config,
+    onramp_id: TremorUrl,
+    store: Box<dyn CheckpointStore>,
+    /// files by id, in the order they are read
+    files: BTreeMap<String, Tailed>,
+    /// the file read last, files are read round robin
+    current: Option<String>,
+    /// file id, offset of the line and offset after it by event id
+    in_flight: HashMap<u64, (String, u64, u64)>,
+    checkpoints: HashMap<String, u64>,
+    scanned: bool,
+    next_scan: Instant,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Tail")
+    }
+}
+
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Result<Self> {
+        Ok(Self {
+            uid,
+            store: checkpoint::store(&config.checkpoint, &onramp_id.to_string())?,
+            config: config.clone(),
+            onramp_id,
+            files: BTreeMap::new(),
+            current: None,
+            in_flight: HashMap::new(),
+            checkpoints: HashMap::new(),
+            scanned: false,
+            next_scan: Instant::now(),
+        })
+    }
+
+    /// Matches the patterns, following new files and rewinding truncated
+    /// ones
+    async fn scan(&mut self) -> Result<()> {
+        for tailed in self.files.values_mut() {
+            tailed.gone = true;
+        }
+        for pattern in &self.config.paths {
+            for path in glob::glob(pattern)? {
+                let path = match path {
+                    Ok(path) => path,
+                    Err(e) => {
+                        warn!("[Source::{}] {}", self.onramp_id, e);
+                        continue;
+                    }
+                };
+                let metadata = match std::fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => metadata,
+                    _ => continue,
+                };
+                let path = path.to_string_lossy().to_string();
+                let id = file_id(&path, &metadata);
+                if let Some(tailed) = self.files.get_mut(&id) {
+                    tailed.gone = false;
+                    tailed.path = path;
+                    if metadata.len() < tailed.offset {
+                        info!(
+                            "[Source::{}] {} was truncated, reading it again",
+                            self.onramp_id, tailed.path
+                        );
+                        self.in_flight.retain(|_, (f, _, _)| f != &id);
+                        self.checkpoints.remove(&id);
+                        tailed.rewind(0).await?;
+                    } else if metadata.len() > tailed.offset {
+                        tailed.eof = false;
+                    }
+                    continue;
+                }
+                let checkpoint = self
+                    .store
+                    .get(&id)?
+                    .and_then(|c| c.parse::<u64>().ok())
+                    // the inode was reused by a file shorter than the checkpoint
+                    .filter(|c| *c <= metadata.len());
+                let offset = match (checkpoint, self.config.start_at) {
+                    (Some(offset), _) => offset,
+                    (None, StartAt::End) if !self.scanned => metadata.len(),
+                    (None, _) => 0,
+                };
+                debug!(
+                    "[Source::{}] following {} from {}",
+                    self.onramp_id, path, offset
+                );
+                match Tailed::open(&path, offset).await {
+                    Ok(tailed) => {
+                        if let Some(checkpoint) = checkpoint {
+                            self.checkpoints.insert(id.clone(), checkpoint);
+                        }
+                        self.files.insert(id, tailed);
+                    }
+                    Err(e) => error!(
+                        "[Source::{}] failed to open {}: {}",
+                        self.onramp_id, path, e
+                    ),
+                }
+            }
+        }
+        self.scanned = true;
+        self.drop_gone();
+        Ok(())
+    }
+
+    /// Stops following files no longer matching once they are read to their
+    /// end
+    fn drop_gone(&mut self) {
+        let gone: Vec<String> = self
+            .files
+            .iter()
+            .filter(|(_, t)| t.gone && t.eof)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in gone {
+            if let Some(tailed) = self.files.remove(&id) {
+                debug!("[Source::{}] done with {}", self.onramp_id, tailed.path);
+            }
+            self.checkpoints.remove(&id);
+        }
+    }
+
+    /// The next file with lines to read, round robin
+    fn next_file(&self) -> Option<String> {
+        let after = |id: &String| self.current.as_ref().map_or(true, |c| id > c);
+        let unread = |t: &Tailed| !t.eof || t.rewind.is_some();
+        self.files
+            .iter()
+            .find(|(id, t)| after(id) && unread(t))
+            .or_else(|| self.files.iter().find(|(_, t)| unread(t)))
+            .map(|(id, _)| id.clone())
+    }
+
+    fn emit(&mut self, id: u64, file_id: String, start: u64, line: Vec<u8>) -> Result<SourceReply> {
+        let path = self
+            .files
+            .get(&file_id)
+            .map(|t| t.path.clone())
+            .unwrap_or_default();
+        let end = start + line.len() as u64;
+        let mut meta = Value::object_with_capacity(2);
+        meta.insert("path", path.clone())?;
+        meta.insert("offset", start)?;
+        let mut file_meta = Value::object_with_capacity(1);
+        file_meta.insert("file", meta)?;
+        self.in_flight.insert(id, (file_id, start, end));
+        Ok(SourceReply::Data {
+            origin_uri: EventOriginUri {
+                uid: self.uid,
+                scheme: "tremor-file".to_string(),
+                host: hostname(),
+                port: None,
+                path: vec![path],
+            },
+            data: strip_line_ending(line),
+            meta: Some(file_meta),
+            codec_override: None,
+            stream: 0,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for Int {
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn pull_event(&mut self, id: u64) -> Result<SourceReply> {
+        let now = Instant::now();
+        if now >= self.next_scan {
+            if let Err(e) = self.scan().await {
+                error!("[Source::{}] failed to scan: {}", self.onramp_id, e);
+            }
+            self.next_scan = now + Duration::from_millis(self.config.scan_interval);
+        }
+        while let Some(file_id) = self.next_file() {
+            self.current = Some(file_id.clone());
+            if let Some(tailed) = self.files.get_mut(&file_id) {
+                match tailed.next_line().await {
+                    Ok(Some((start, line))) => return self.emit(id, file_id, start, line),
+                    Ok(None) => (),
+                    Err(e) => {
+                        error!(
+                            "[Source::{}] failed to read {}: {}",
+                            self.onramp_id, tailed.path, e
+                        );
+                        tailed.eof = true;
+                    }
+                }
+            }
+        }
+        self.drop_gone();
+        Ok(SourceReply::Empty(self.config.poll_interval))
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        Ok(SourceState::Connected)
+    }
+
+    fn ack(&mut self, id: u64) {
+        if let Some((file_id, _, end)) = self.in_flight.remove(&id) {
+            if self.checkpoints.get(&file_id).map_or(true, |c| end > *c) {
+                if let Err(e) = self.store.set(&file_id, &end.to_string()) {
+                    error!(
+                        "[Source::{}] failed to checkpoint {}: {}",
+                        self.onramp_id, file_id, e
+                    );
+                }
+                self.checkpoints.insert(file_id, end);
+            }
+        }
+    }
+
+    fn fail(&mut self, id: u64) {
+        if let Some((file_id, start, _)) = self.in_flight.remove(&id) {
+            // read the file again from the failed line, the lines after it
+            // are read again as well
+            self.in_flight
+                .retain(|_, (f, s, _)| f != &file_id || *s < start);
+            if let Some(tailed) = self.files.get_mut(&file_id) {
+                tailed.rewind = Some(tailed.rewind.map_or(start, |r| r.min(start)));
+                tailed.eof = false;
+            }
+        }
+    }
+
+    fn is_transactional(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for Tail {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config)?;
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "string"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(b"snot".to_vec(), strip_line_ending(b"snot\n".to_vec()));
+        assert_eq!(b"snot".to_vec(), strip_line_ending(b"snot\r\n".to_vec()));
+        assert_eq!(b"snot\r".to_vec(), strip_line_ending(b"snot\r".to_vec()));
+        assert_eq!(Vec::<u8>::new(), strip_line_ending(b"\n".to_vec()));
+    }
+
+    fn lines(source: &mut Int) -> Result<Vec<(String, u64, String)>> {
+        let mut lines = Vec::new();
+        let mut id = 0;
+        while let SourceReply::Data { data, meta, .. } = task::block_on(source.pull_event(id))? {
+            let meta = meta.unwrap_or_else(Value::object);
+            lines.push((
+                meta.get("file")
+                    .get_str("path")
+                    .unwrap_or_default()
+                    .to_string(),
+                meta.get("file").get_u64("offset").unwrap_or_default(),
+                String::from_utf8(data)?,
+            ));
+            id += 1;
+        }
+        Ok(lines)
+    }
+
+    #[test]
+    fn follow() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tremor-tail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let log = dir.join("app.log").to_string_lossy().to_string();
+        let rotated = dir.join("app.log.1").to_string_lossy().to_string();
+        let config = Config {
+            paths: vec![dir.join("app.log*").to_string_lossy().to_string()],
+            start_at: StartAt::Beginning,
+            scan_interval: 0,
+            poll_interval: 0,
+            checkpoint: CheckpointConfig::Memory,
+        };
+        let mut source = Int::from_config(0, TremorUrl::parse("/onramp/tail/01")?, &config)?;
+
+        std::fs::write(&log, "snot\nbad")?;
+        assert_eq!(
+            vec![(log.clone(), 0, "snot".to_string())],
+            lines(&mut source)?
+        );
+
+        // the line is completed
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log)?
+            .write_all(b"ger\n")?;
+        assert_eq!(
+            vec![(log.clone(), 5, "badger".to_string())],
+            lines(&mut source)?
+        );
+
+        // rotated, the old file is read to its end
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log)?
+            .write_all(b"old\n")?;
+        std::fs::rename(&log, &rotated)?;
+        std::fs::write(&log, "new\n")?;
+        let mut read = lines(&mut source)?;
+        read.sort();
+        assert_eq!(
+            vec![
+                (log.clone(), 0, "new".to_string()),
+                (rotated, 12, "old".to_string())
+            ],
+            read
+        );
+
+        // truncated
+        std::fs::write(&log, "")?;
+        std::fs::write(&log, "a\n")?;
+        assert_eq!(vec![(log, 0, "a".to_string())], lines(&mut source)?);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
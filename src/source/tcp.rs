@@ -13,9 +13,22 @@
 // limitations under the License.
 #![cfg(not(tarpaulin_include))]
 
+//! # TCP Onramp
+//!
+//! Listens on `host` and `port` and emits the data received on each
+//! connection. Messages are framed by preprocessors, e.g. `lines`,
+//! `lines-null` or `length-prefixed`. With `tls` the onramp terminates TLS
+//! on accepted connections.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::tls;
 use crate::source::prelude::*;
-use async_channel::TryRecvError;
+use async_channel::{Sender, TryRecvError};
 use async_std::net::TcpListener;
+use futures::AsyncRead;
 
 // TODO expose this as config (would have to change buffer to be vector?)
 const BUFFER_SIZE_BYTES: usize = 8192;
@@ -24,6 +37,9 @@ const BUFFER_SIZE_BYTES: usize = 8192;
 pub struct Config {
     pub port: u16,
     pub host: String,
+    /// terminate TLS on accepted connections
+    #[serde(default = "Default::default")]
+    pub tls: Option<tls::Config>,
 }
 
 impl ConfigImpl for Config {}
@@ -71,6 +87,46 @@ impl onramp::Impl for Tcp {
     }
 }
 
+/// forwards the data received on a connection
+async fn handle_connection<S>(
+    mut stream: S,
+    tx: Sender<SourceReply>,
+    origin_uri: EventOriginUri,
+    stream_id: usize,
+) where
+    S: AsyncRead + Unpin + Send,
+{
+    //let (reader, writer) = &mut (&stream, &stream);
+    let mut buffer = [0; BUFFER_SIZE_BYTES];
+    if let Err(e) = tx.send(SourceReply::StartStream(stream_id)).await {
+        error!("TCP Error: {}", e);
+        return;
+    }
+
+    while let Ok(n) = stream.read(&mut buffer).await {
+        if n == 0 {
+            if let Err(e) = tx.send(SourceReply::EndStream(stream_id)).await {
+                error!("TCP Error: {}", e);
+            };
+            break;
+        };
+        if let Err(e) = tx
+            .send(SourceReply::Data {
+                origin_uri: origin_uri.clone(),
+                // ALLOW: we define n as part of the read
+                data: buffer[0..n].to_vec(),
+                meta: None, // TODO: add peer address etc. to meta
+                codec_override: None,
+                stream: stream_id,
+            })
+            .await
+        {
+            error!("TCP Error: {}", e);
+            break;
+        };
+    }
+}
+
 #[async_trait::async_trait()]
 impl Source for Int {
     fn id(&self) -> &TremorUrl {
@@ -95,9 +151,10 @@ impl Source for Int {
         let (tx, rx) = bounded(crate::QSIZE);
         let uid = self.uid;
         let path = vec![self.config.port.to_string()];
+        let acceptor = self.config.tls.as_ref().map(tls::acceptor).transpose()?;
         task::spawn(async move {
             let mut stream_id = 0;
-            while let Ok((mut stream, peer)) = listener.accept().await {
+            while let Ok((stream, peer)) = listener.accept().await {
                 let tx = tx.clone();
                 stream_id += 1;
                 let origin_uri = EventOriginUri {
@@ -108,37 +165,19 @@ impl Source for Int {
                     // TODO also add token_num here?
                     path: path.clone(), // captures server port
                 };
-                task::spawn(async move {
-                    //let (reader, writer) = &mut (&stream, &stream);
-                    let mut buffer = [0; BUFFER_SIZE_BYTES];
-                    if let Err(e) = tx.send(SourceReply::StartStream(stream_id)).await {
-                        error!("TCP Error: {}", e);
-                        return;
-                    }
-
-                    while let Ok(n) = stream.read(&mut buffer).await {
-                        if n == 0 {
-                            if let Err(e) = tx.send(SourceReply::EndStream(stream_id)).await {
-                                error!("TCP Error: {}", e);
-                            };
-                            break;
-                        };
-                        if let Err(e) = tx
-                            .send(SourceReply::Data {
-                                origin_uri: origin_uri.clone(),
-                                // ALLOW: we define n as part of the read
-                                data: buffer[0..n].to_vec(),
-                                meta: None, // TODO: add peer address etc. to meta
-                                codec_override: None,
-                                stream: stream_id,
-                            })
-                            .await
-                        {
-                            error!("TCP Error: {}", e);
-                            break;
-                        };
-                    }
-                });
+                if let Some(acceptor) = &acceptor {
+                    let handshake = acceptor.accept(stream);
+                    task::spawn(async move {
+                        match handshake.await {
+                            Ok(stream) => {
+                                handle_connection(stream, tx, origin_uri, stream_id).await
+                            }
+                            Err(e) => warn!("TLS handshake with {} failed: {}", peer, e),
+                        }
+                    });
+                } else {
+                    task::spawn(handle_connection(stream, tx, origin_uri, stream_id));
+                }
             }
         });
         self.listener = Some(rx);
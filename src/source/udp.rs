@@ -13,14 +13,71 @@
 // limitations under the License.
 #![cfg(not(tarpaulin_include))]
 
+//! # UDP Onramp
+//!
+//! Receives datagrams on `host`:`port`.
+//!
+//! With the default `packet` framing each datagram is an event. With the
+//! `delimiter` framing datagrams are split at `delimiter` into events, as
+//! syslog and statsd clients pack several messages into one datagram. Unlike
+//! the `lines` preprocessor the framing never joins datagrams of different
+//! peers.
+//!
+//! Under high packet rates the kernel drops datagrams once the receive
+//! buffer of the socket is full, `recv_buffer_size` raises it in bytes,
+//! within the limit of `net.core.rmem_max`:
+//!
+//! ```yaml
+//! host: 0.0.0.0
+//! port: 8125
+//! framing: delimiter
+//! delimiter: "\n"
+//! recv_buffer_size: 8388608
+//! ```
+//!
+//! The peer is available as `$udp.host` and `$udp.port`, so the `udp`
+//! offramp can reply to it.
+
 use crate::source::prelude::*;
-use async_std::net::UdpSocket;
+use async_std::net::{ToSocketAddrs, UdpSocket};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+/// How datagrams are split into events
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Framing {
+    /// each datagram is an event
+    Packet,
+    /// datagrams are split at the `delimiter`
+    Delimiter,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self::Packet
+    }
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     /// The port to listen on.
     pub port: u16,
     pub host: String,
+    /// how datagrams are split into events
+    #[serde(default)]
+    pub framing: Framing,
+    /// delimiter of the `delimiter` framing
+    #[serde(default = "d_delimiter")]
+    pub delimiter: String,
+    /// receive buffer of the socket in bytes, the system default if unset
+    #[serde(default)]
+    pub recv_buffer_size: Option<usize>,
+}
+
+fn d_delimiter() -> String {
+    "\n".to_string()
 }
 
 impl ConfigImpl for Config {}
@@ -35,6 +92,8 @@ struct Int {
     socket: Option<UdpSocket>,
     onramp_id: TremorUrl,
     origin_uri: EventOriginUri,
+    /// frames of the last datagram not sent yet, with its peer
+    frames: VecDeque<(Vec<u8>, SocketAddr)>,
 }
 impl std::fmt::Debug for Int {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -42,6 +101,41 @@ impl std::fmt::Debug for Int {
     }
 }
 
+/// Splits a datagram into the data of its events, empty frames are dropped
+fn frames(data: &[u8], framing: Framing, delimiter: &[u8]) -> Vec<Vec<u8>> {
+    match framing {
+        Framing::Packet => vec![data.to_vec()],
+        Framing::Delimiter if delimiter.is_empty() => vec![data.to_vec()],
+        Framing::Delimiter => {
+            let mut res = Vec::new();
+            let mut start = 0;
+            let mut i = 0;
+            while i + delimiter.len() <= data.len() {
+                if data[i..].starts_with(delimiter) {
+                    res.push(data[start..i].to_vec());
+                    i += delimiter.len();
+                    start = i;
+                } else {
+                    i += 1;
+                }
+            }
+            res.push(data[start..].to_vec());
+            res.retain(|frame| !frame.is_empty());
+            res
+        }
+    }
+}
+
+/// The `$udp` metadata of an event from `peer`
+fn peer_meta(peer: &SocketAddr) -> Value<'static> {
+    literal!({
+        "udp": {
+            "host": peer.ip().to_string(),
+            "port": peer.port()
+        }
+    })
+}
+
 impl Int {
     fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Self {
         let origin_uri = EventOriginUri {
@@ -56,7 +150,55 @@ impl Int {
             socket: None,
             onramp_id,
             origin_uri,
+            frames: VecDeque::new(),
+        }
+    }
+
+    async fn bind(&self) -> Result<UdpSocket> {
+        let addr = (self.config.host.as_str(), self.config.port)
+            .to_socket_addrs()
+            .await?
+            .next()
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "{}:{} doesn't resolve to an address",
+                    self.config.host, self.config.port
+                ))
+            })?;
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        if let Some(size) = self.config.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+            let actual = socket.recv_buffer_size()?;
+            // linux reports double the requested size, less means it was capped
+            if actual < size {
+                warn!(
+                    "[Source::{}] receive buffer is {} bytes instead of {}, raise net.core.rmem_max",
+                    self.onramp_id, actual, size
+                );
+            }
         }
+        socket.bind(&addr.into())?;
+        info!(
+            "[Source::{}] listening on {}:{}",
+            self.onramp_id, self.config.host, self.config.port
+        );
+        Ok(UdpSocket::from(std::net::UdpSocket::from(socket)))
+    }
+
+    fn next_frame(&mut self) -> Option<SourceReply> {
+        let (data, peer) = self.frames.pop_front()?;
+        let mut origin_uri = self.origin_uri.clone();
+
+        // TODO add a method in origin_uri for changes like this?
+        origin_uri.host = peer.ip().to_string();
+        origin_uri.port = Some(peer.port());
+        Some(SourceReply::Data {
+            origin_uri,
+            data,
+            meta: Some(peer_meta(&peer)),
+            codec_override: None,
+            stream: 0,
+        })
     }
 }
 impl onramp::Impl for Udp {
@@ -76,24 +218,21 @@ impl onramp::Impl for Udp {
 #[async_trait::async_trait]
 impl Source for Int {
     async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        if let Some(reply) = self.next_frame() {
+            return Ok(reply);
+        }
         let mut buf = [0; 65535];
 
         if let Some(socket) = self.socket.as_mut() {
             match socket.recv_from(&mut buf).await {
                 Ok((n, peer)) => {
-                    let mut origin_uri = self.origin_uri.clone();
-
-                    // TODO add a method in origin_uri for changes like this?
-                    origin_uri.host = peer.ip().to_string();
-                    origin_uri.port = Some(peer.port());
-                    Ok(SourceReply::Data {
-                        origin_uri,
-                        // ALLOW: we get n from recv
-                        data: buf[0..n].to_vec(),
-                        meta: None,
-                        codec_override: None,
-                        stream: 0,
-                    })
+                    // ALLOW: we get n from recv
+                    let data = &buf[0..n];
+                    for frame in frames(data, self.config.framing, self.config.delimiter.as_bytes())
+                    {
+                        self.frames.push_back((frame, peer));
+                    }
+                    Ok(self.next_frame().unwrap_or(SourceReply::Empty(0)))
                 }
                 Err(e) => {
                     if e.kind() == std::io::ErrorKind::WouldBlock {
@@ -104,22 +243,12 @@ impl Source for Int {
                 }
             }
         } else {
-            let socket = UdpSocket::bind((self.config.host.as_str(), self.config.port)).await?;
-            info!(
-                "[UDP Onramp] listening on {}:{}",
-                self.config.host, self.config.port
-            );
-            self.socket = Some(socket);
+            self.socket = Some(self.bind().await?);
             Ok(SourceReply::StateChange(SourceState::Connected))
         }
     }
     async fn init(&mut self) -> Result<SourceState> {
-        let socket = UdpSocket::bind((self.config.host.as_str(), self.config.port)).await?;
-        info!(
-            "[UDP Onramp] listening on {}:{}",
-            self.config.host, self.config.port
-        );
-        self.socket = Some(socket);
+        self.socket = Some(self.bind().await?);
         Ok(SourceState::Connected)
     }
     fn id(&self) -> &TremorUrl {
@@ -137,3 +266,70 @@ impl Onramp for Udp {
         "string"
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn framing() {
+        let data = b"a:1|c\nb:2|g\n\nc:3|ms";
+        assert_eq!(vec![data.to_vec()], frames(data, Framing::Packet, b"\n"));
+        assert_eq!(
+            vec![b"a:1|c".to_vec(), b"b:2|g".to_vec(), b"c:3|ms".to_vec()],
+            frames(data, Framing::Delimiter, b"\n")
+        );
+        assert_eq!(
+            vec![b"snot".to_vec(), b"badger".to_vec()],
+            frames(b"snot\r\nbadger\r\n", Framing::Delimiter, b"\r\n")
+        );
+        assert!(frames(b"\n\n", Framing::Delimiter, b"\n").is_empty());
+        assert_eq!(
+            vec![b"snot".to_vec()],
+            frames(b"snot", Framing::Delimiter, b"")
+        );
+    }
+
+    #[test]
+    fn meta() {
+        let peer: SocketAddr = ([127, 0, 0, 1], 5140).into();
+        assert_eq!(
+            literal!({"udp": {"host": "127.0.0.1", "port": 5140}}),
+            peer_meta(&peer)
+        );
+    }
+
+    #[async_std::test]
+    async fn receive() -> Result<()> {
+        let config = Config {
+            port: 0,
+            host: "127.0.0.1".to_string(),
+            framing: Framing::Delimiter,
+            delimiter: "\n".to_string(),
+            recv_buffer_size: Some(65536),
+        };
+        let mut source = Int::from_config(0, TremorUrl::parse("/onramp/udp/01")?, &config);
+        source.init().await?;
+        let addr = source
+            .socket
+            .as_ref()
+            .ok_or_else(|| Error::from("not bound"))?
+            .local_addr()?;
+        let client = std::net::UdpSocket::bind("127.0.0.1:0")?;
+        client.send_to(b"snot\nbadger\n", addr)?;
+        let client_port = client.local_addr()?.port();
+        for expected in &[b"snot".to_vec(), b"badger".to_vec()] {
+            match source.pull_event(0).await? {
+                SourceReply::Data { data, meta, .. } => {
+                    assert_eq!(expected, &data);
+                    assert_eq!(
+                        Some(literal!({"udp": {"host": "127.0.0.1", "port": client_port}})),
+                        meta
+                    );
+                }
+                _ => return Err("expected data".into()),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![cfg(not(tarpaulin_include))]
+
+//! # Unix Socket Onramp
+//!
+//! Listens on a unix domain socket at `path`, to receive from local daemons
+//! without the overhead of loopback TCP.
+//!
+//! With the `stream` socket type each accepted connection is a stream of its
+//! own, like the `tcp` onramp. With `datagram` each datagram is received as
+//! it is, like the `udp` onramp.
+//!
+//! A socket left behind at `path` by a previous run is replaced. The
+//! permissions of the socket can be set with `permissions`:
+//!
+//! ```yaml
+//! path: /run/tremor/statsd.sock
+//! socket_type: datagram
+//! permissions: "0660"
+//! ```
+
+use crate::connectors::unix_socket::{self, SocketType};
+use crate::source::prelude::*;
+use async_channel::{Sender, TryRecvError};
+use async_std::os::unix::net::{UnixDatagram, UnixListener};
+use std::path::Path;
+
+const BUFFER_SIZE_BYTES: usize = 8192;
+const DATAGRAM_SIZE_BYTES: usize = 65535;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// path of the socket
+    pub path: String,
+    /// `stream` or `datagram`
+    #[serde(default)]
+    pub socket_type: SocketType,
+    /// octal permissions of the socket, like `0660`
+    #[serde(default)]
+    pub permissions: Option<String>,
+}
+
+impl ConfigImpl for Config {}
+
+pub struct UnixSocket {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+pub struct Int {
+    config: Config,
+    listener: Option<Receiver<SourceReply>>,
+    onramp_id: TremorUrl,
+    origin_uri: EventOriginUri,
+}
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "UnixSocket")
+    }
+}
+impl Int {
+    fn from_config(uid: u64, onramp_id: TremorUrl, config: &Config) -> Self {
+        let origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-unix-socket".to_string(),
+            host: "localhost".to_string(),
+            port: None,
+            path: config
+                .path
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect(),
+        };
+        Self {
+            config: config.clone(),
+            listener: None,
+            onramp_id,
+            origin_uri,
+        }
+    }
+
+    /// accepts connections, each is read as a stream of its own
+    fn accept(&self, listener: UnixListener, tx: Sender<SourceReply>) {
+        let origin_uri = self.origin_uri.clone();
+        let onramp_id = self.onramp_id.clone();
+        task::spawn(async move {
+            let mut stream_id = 0;
+            while let Ok((mut stream, _peer)) = listener.accept().await {
+                let tx = tx.clone();
+                let origin_uri = origin_uri.clone();
+                let onramp_id = onramp_id.clone();
+                stream_id += 1;
+                task::spawn(async move {
+                    let mut buffer = [0; BUFFER_SIZE_BYTES];
+                    if let Err(e) = tx.send(SourceReply::StartStream(stream_id)).await {
+                        error!("[Source::{}] {}", onramp_id, e);
+                        return;
+                    }
+                    loop {
+                        let n = match stream.read(&mut buffer).await {
+                            Ok(n) => n,
+                            Err(e) => {
+                                warn!("[Source::{}] failed to read: {}", onramp_id, e);
+                                0
+                            }
+                        };
+                        if n == 0 {
+                            if let Err(e) = tx.send(SourceReply::EndStream(stream_id)).await {
+                                error!("[Source::{}] {}", onramp_id, e);
+                            };
+                            break;
+                        };
+                        if let Err(e) = tx
+                            .send(SourceReply::Data {
+                                origin_uri: origin_uri.clone(),
+                                // ALLOW: we define n as part of the read
+                                data: buffer[0..n].to_vec(),
+                                meta: None,
+                                codec_override: None,
+                                stream: stream_id,
+                            })
+                            .await
+                        {
+                            error!("[Source::{}] {}", onramp_id, e);
+                            break;
+                        };
+                    }
+                });
+            }
+        });
+    }
+
+    /// receives datagrams
+    fn receive(&self, socket: UnixDatagram, tx: Sender<SourceReply>) {
+        let origin_uri = self.origin_uri.clone();
+        let onramp_id = self.onramp_id.clone();
+        task::spawn(async move {
+            let mut buffer = [0; DATAGRAM_SIZE_BYTES];
+            loop {
+                match socket.recv(&mut buffer).await {
+                    Ok(n) => {
+                        let reply = SourceReply::Data {
+                            origin_uri: origin_uri.clone(),
+                            // ALLOW: we get n from recv
+                            data: buffer[0..n].to_vec(),
+                            meta: None,
+                            codec_override: None,
+                            stream: 0,
+                        };
+                        if tx.send(reply).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        error!("[Source::{}] failed to receive: {}", onramp_id, e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl onramp::Impl for UnixSocket {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for unix-socket onramp".into())
+        }
+    }
+}
+
+#[async_trait::async_trait()]
+impl Source for Int {
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        self.listener.as_ref().map_or_else(
+            || Ok(SourceReply::StateChange(SourceState::Disconnected)),
+            |listener| match listener.try_recv() {
+                Ok(r) => Ok(r),
+                Err(TryRecvError::Empty) => Ok(SourceReply::Empty(10)),
+                Err(TryRecvError::Closed) => {
+                    Ok(SourceReply::StateChange(SourceState::Disconnected))
+                }
+            },
+        )
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        let path = Path::new(&self.config.path);
+        unix_socket::remove_stale(path)?;
+        let (tx, rx) = bounded(crate::QSIZE);
+        match self.config.socket_type {
+            SocketType::Stream => self.accept(UnixListener::bind(path).await?, tx),
+            SocketType::Datagram => self.receive(UnixDatagram::bind(path).await?, tx),
+        }
+        if let Some(permissions) = &self.config.permissions {
+            unix_socket::set_mode(path, permissions)?;
+        }
+        info!(
+            "[Source::{}] listening on {}",
+            self.onramp_id, self.config.path
+        );
+        self.listener = Some(rx);
+        Ok(SourceState::Connected)
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for UnixSocket {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int::from_config(config.onramp_uid, self.onramp_id.clone(), &self.config);
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use async_std::os::unix::net::UnixStream;
+
+    async fn next_data(source: &mut Int) -> Result<Vec<u8>> {
+        loop {
+            match source.pull_event(0).await? {
+                SourceReply::Data { data, .. } => return Ok(data),
+                SourceReply::Empty(_) | SourceReply::StartStream(_) => {
+                    task::sleep(std::time::Duration::from_millis(1)).await;
+                }
+                _ => return Err("unexpected reply".into()),
+            }
+        }
+    }
+
+    fn config(path: &Path, socket_type: SocketType) -> Config {
+        Config {
+            path: path.to_string_lossy().to_string(),
+            socket_type,
+            permissions: None,
+        }
+    }
+
+    #[async_std::test]
+    async fn stream() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("stream.sock");
+        let id = TremorUrl::parse("/onramp/unix-socket/01")?;
+        let mut source = Int::from_config(0, id, &config(&path, SocketType::Stream));
+        assert!(matches!(source.init().await?, SourceState::Connected));
+
+        let mut client = UnixStream::connect(&path).await?;
+        client.write_all(b"snot").await?;
+        assert_eq!(b"snot".to_vec(), next_data(&mut source).await?);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn datagram() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("datagram.sock");
+        let id = TremorUrl::parse("/onramp/unix-socket/01")?;
+        let mut source = Int::from_config(0, id, &config(&path, SocketType::Datagram));
+        // replaces the socket of a previous run
+        std::os::unix::net::UnixDatagram::bind(&path)?;
+        assert!(matches!(source.init().await?, SourceState::Connected));
+
+        let client = UnixDatagram::unbound()?;
+        client.send_to(b"badger", &path).await?;
+        assert_eq!(b"badger".to_vec(), next_data(&mut source).await?);
+        Ok(())
+    }
+}
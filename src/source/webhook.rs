@@ -0,0 +1,300 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Webhook signature verification of the `rest` onramp
+//!
+//! Requests to the `path` of a webhook have to carry a valid signature of the
+//! `provider`, made with the shared `secret`, or they are rejected with
+//! `401 Unauthorized` before they are turned into events:
+//!
+//! * `github`: `X-Hub-Signature-256` is `sha256=` and the hex HMAC-SHA256 of
+//!   the body
+//! * `stripe`: `Stripe-Signature` holds the timestamp `t` and one or more
+//!   `v1` hex HMAC-SHA256s of `{t}.{body}`
+//! * `slack`: `X-Slack-Signature` is `v0=` and the hex HMAC-SHA256 of
+//!   `v0:{timestamp}:{body}`, with the timestamp in
+//!   `X-Slack-Request-Timestamp`
+//!
+//! Stripe and Slack requests signed more than `tolerance` seconds ago are
+//! rejected as well, to prevent replays. The secret can be read from the
+//! environment variable named in `secret_env` instead. Routes of the `rest`
+//! onramp take the same settings, without the `path`, as their `webhook`.
+//!
+//! ```yaml
+//! webhooks:
+//!   - path: /github
+//!     provider: github
+//!     secret_env: GITHUB_WEBHOOK_SECRET
+//!   - path: /stripe
+//!     provider: stripe
+//!     secret_env: STRIPE_WEBHOOK_SECRET
+//!     tolerance: 300
+//! ```
+
+use crate::errors::{Error, Result};
+use crate::preprocessor::verify::decode_hex;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    Github,
+    Stripe,
+    Slack,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Request path the webhook is received at
+    pub path: String,
+    #[serde(flatten)]
+    pub signature: Signature,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Signature {
+    /// Signature scheme of the sender
+    pub provider: Provider,
+    /// Shared signing secret
+    #[serde(default = "Default::default")]
+    pub secret: Option<String>,
+    /// Environment variable holding the signing secret
+    #[serde(default = "Default::default")]
+    pub secret_env: Option<String>,
+    /// Seconds a signature's timestamp may be off from now
+    #[serde(default = "d_tolerance")]
+    pub tolerance: u64,
+}
+
+fn d_tolerance() -> u64 {
+    300
+}
+
+/// reads a secret given inline or as the name of an environment variable
+pub(crate) fn secret(secret: &Option<String>, secret_env: &Option<String>) -> Result<String> {
+    match (secret, secret_env) {
+        (Some(secret), _) => Ok(secret.clone()),
+        (None, Some(var)) => std::env::var(var)
+            .map_err(|e| Error::from(format!("Unable to read secret from {}: {}", var, e))),
+        (None, None) => Ok(String::new()),
+    }
+}
+
+/// compares a presented token in constant time
+pub(crate) fn token_matches(expected: &[u8], presented: &[u8]) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Verifies the signatures of the requests to a webhook path
+#[derive(Debug, Clone)]
+pub(crate) struct Webhook {
+    pub(crate) path: String,
+    provider: Provider,
+    secret: Vec<u8>,
+    tolerance: u64,
+}
+
+impl Webhook {
+    pub(crate) fn from_config(config: &Config) -> Result<Self> {
+        Self::new(&config.path, &config.signature)
+    }
+
+    pub(crate) fn new(path: &str, config: &Signature) -> Result<Self> {
+        let secret = secret(&config.secret, &config.secret_env)?;
+        if secret.is_empty() {
+            return Err(format!("The webhook at {} requires a `secret`", path).into());
+        }
+        Ok(Self {
+            path: path.to_string(),
+            provider: config.provider,
+            secret: secret.into_bytes(),
+            tolerance: config.tolerance,
+        })
+    }
+
+    /// checks the hex HMAC-SHA256 of the signed parts in constant time
+    fn matches(&self, hex: &str, signed: &[&[u8]]) -> bool {
+        match (
+            decode_hex(hex.as_bytes()),
+            Hmac::<Sha256>::new_varkey(&self.secret),
+        ) {
+            (Some(signature), Ok(mut mac)) => {
+                for part in signed {
+                    mac.update(part);
+                }
+                mac.verify(&signature).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    fn fresh(&self, timestamp: &str, now_s: u64) -> std::result::Result<(), &'static str> {
+        let timestamp: u64 = timestamp.parse().map_err(|_| "invalid timestamp")?;
+        if now_s.max(timestamp) - now_s.min(timestamp) > self.tolerance {
+            Err("timestamp outside of the tolerance")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// verifies the signature of a request, `header` looks up request headers
+    pub(crate) fn verify<'h>(
+        &self,
+        header: impl Fn(&str) -> Option<&'h str>,
+        body: &[u8],
+        now_s: u64,
+    ) -> std::result::Result<(), &'static str> {
+        match self.provider {
+            Provider::Github => {
+                let signature = header("X-Hub-Signature-256")
+                    .and_then(|s| s.strip_prefix("sha256="))
+                    .ok_or("missing X-Hub-Signature-256")?;
+                if self.matches(signature, &[body]) {
+                    Ok(())
+                } else {
+                    Err("invalid signature")
+                }
+            }
+            Provider::Stripe => {
+                let header = header("Stripe-Signature").ok_or("missing Stripe-Signature")?;
+                let mut timestamp = None;
+                let mut signatures = Vec::new();
+                for (key, value) in header.split(',').filter_map(|kv| {
+                    let mut kv = kv.trim().splitn(2, '=');
+                    Some((kv.next()?, kv.next()?))
+                }) {
+                    match key {
+                        "t" => timestamp = Some(value),
+                        "v1" => signatures.push(value),
+                        _ => (),
+                    }
+                }
+                let timestamp = timestamp.ok_or("missing timestamp")?;
+                self.fresh(timestamp, now_s)?;
+                let signed: [&[u8]; 3] = [timestamp.as_bytes(), b".", body];
+                if signatures.iter().any(|s| self.matches(s, &signed)) {
+                    Ok(())
+                } else {
+                    Err("invalid signature")
+                }
+            }
+            Provider::Slack => {
+                let timestamp = header("X-Slack-Request-Timestamp")
+                    .ok_or("missing X-Slack-Request-Timestamp")?;
+                let signature = header("X-Slack-Signature")
+                    .and_then(|s| s.strip_prefix("v0="))
+                    .ok_or("missing X-Slack-Signature")?;
+                self.fresh(timestamp, now_s)?;
+                if self.matches(signature, &[b"v0:", timestamp.as_bytes(), b":", body]) {
+                    Ok(())
+                } else {
+                    Err("invalid signature")
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(provider: Provider) -> Result<Webhook> {
+        Webhook::new(
+            "/hook",
+            &Signature {
+                provider,
+                secret: Some("secret".to_string()),
+                secret_env: None,
+                tolerance: 300,
+            },
+        )
+    }
+
+    fn sign(parts: &[&[u8]]) -> Result<String> {
+        let mut mac = Hmac::<Sha256>::new_varkey(b"secret").map_err(|e| e.to_string())?;
+        for part in parts {
+            mac.update(part);
+        }
+        Ok(mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
+
+    #[test]
+    fn github() -> Result<()> {
+        let hook = webhook(Provider::Github)?;
+        let signature = format!("sha256={}", sign(&[b"{}"])?);
+        let headers = |name: &str| {
+            if name == "X-Hub-Signature-256" {
+                Some(signature.as_str())
+            } else {
+                None
+            }
+        };
+        assert_eq!(Ok(()), hook.verify(headers, b"{}", 0));
+        assert!(hook.verify(headers, b"{\"forged\": true}", 0).is_err());
+        assert!(hook.verify(|_| None, b"{}", 0).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn stripe() -> Result<()> {
+        let hook = webhook(Provider::Stripe)?;
+        let signature = format!("t=1000,v1=00,v1={}", sign(&[b"1000.{}"])?);
+        let headers = |_: &str| Some(signature.as_str());
+        assert_eq!(Ok(()), hook.verify(headers, b"{}", 1200));
+        // replayed too late
+        assert!(hook.verify(headers, b"{}", 1400).is_err());
+        assert!(hook.verify(headers, b"{ }", 1200).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn slack() -> Result<()> {
+        let hook = webhook(Provider::Slack)?;
+        let signature = format!("v0={}", sign(&[b"v0:1000:a=b"])?);
+        let headers = |name: &str| match name {
+            "X-Slack-Request-Timestamp" => Some("1000"),
+            "X-Slack-Signature" => Some(signature.as_str()),
+            _ => None,
+        };
+        assert_eq!(Ok(()), hook.verify(headers, b"a=b", 1000));
+        assert!(hook.verify(headers, b"a=b", 2000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn requires_secret() {
+        assert!(Webhook::new(
+            "/hook",
+            &Signature {
+                provider: Provider::Github,
+                secret: None,
+                secret_env: None,
+                tolerance: 300,
+            }
+        )
+        .is_err());
+    }
+}
@@ -13,15 +13,28 @@
 // limitations under the License.
 #![cfg(not(tarpaulin_include))]
 
+use crate::connectors::tls;
 use crate::postprocessor::{make_postprocessors, postprocess, Postprocessors};
+use crate::source::access_log::AccessLog;
+use crate::source::rate_limit::{self, RateLimiter};
+use crate::source::webhook::{secret, token_matches};
 use crate::{codec::Codec, source::prelude::*};
 use async_channel::{Sender, TryRecvError};
-use async_std::net::{TcpListener, TcpStream};
+use async_std::net::TcpListener;
 use async_std::task;
+use async_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use async_tungstenite::tungstenite::http::{
+    header::{AUTHORIZATION, RETRY_AFTER, SEC_WEBSOCKET_PROTOCOL},
+    HeaderValue, StatusCode,
+};
+use async_tungstenite::tungstenite::protocol::frame::{coding::CloseCode, CloseFrame};
 use async_tungstenite::tungstenite::Message;
-use futures::{SinkExt, StreamExt};
+use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt};
 use halfbrown::HashMap;
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tremor_pipeline::EventId;
 use tremor_script::Value;
 
@@ -31,10 +44,149 @@ pub struct Config {
     pub port: u16,
     /// Host to listen on
     pub host: String,
+    /// per client rate limit on connections and messages, handshakes beyond
+    /// it are answered with `429 Too Many Requests`, messages are dropped
+    #[serde(default = "Default::default")]
+    pub rate_limit: Option<rate_limit::Config>,
+    /// terminate TLS, accepting `wss://` connections
+    #[serde(default = "Default::default")]
+    pub tls: Option<tls::Config>,
+    /// clients have to authenticate with a token
+    #[serde(default = "Default::default")]
+    pub auth: Option<AuthConfig>,
+    /// maximum number of concurrent connections, handshakes beyond it are
+    /// answered with `503 Service Unavailable`
+    #[serde(default = "Default::default")]
+    pub max_connections: Option<usize>,
+    /// supported subprotocols, e.g. versions of the protocol spoken over the
+    /// connection, in order of preference. Clients have to offer one of them
+    /// in `Sec-WebSocket-Protocol`, or the handshake fails with
+    /// `400 Bad Request`
+    #[serde(default = "Default::default")]
+    pub protocols: Vec<String>,
+    /// milliseconds between pings sent to clients
+    #[serde(default = "Default::default")]
+    pub ping_interval: Option<u64>,
+    /// milliseconds without any message from a client, pongs included,
+    /// after which its connection is closed
+    #[serde(default = "Default::default")]
+    pub idle_timeout: Option<u64>,
 }
 
 impl ConfigImpl for Config {}
 
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// the handshake carries `Authorization: Bearer <token>`
+    Bearer,
+    /// the first message is the token
+    Message,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AuthConfig {
+    /// How clients present the token
+    #[serde(default = "d_auth_mode")]
+    pub mode: AuthMode,
+    /// The token
+    #[serde(default = "Default::default")]
+    pub token: Option<String>,
+    /// Environment variable holding the token
+    #[serde(default = "Default::default")]
+    pub token_env: Option<String>,
+    /// Milliseconds to wait for the token message in `message` mode
+    #[serde(default = "d_auth_timeout")]
+    pub timeout: u64,
+}
+
+fn d_auth_mode() -> AuthMode {
+    AuthMode::Bearer
+}
+
+fn d_auth_timeout() -> u64 {
+    5000
+}
+
+/// Checks the tokens clients present
+struct Auth {
+    mode: AuthMode,
+    token: Vec<u8>,
+    timeout: Duration,
+}
+
+impl Auth {
+    fn from_config(config: &AuthConfig) -> Result<Self> {
+        let token = secret(&config.token, &config.token_env)?;
+        if token.is_empty() {
+            return Err("The websocket `auth` requires a `token` or `token_env`".into());
+        }
+        Ok(Self {
+            mode: config.mode,
+            token: token.into_bytes(),
+            timeout: Duration::from_millis(config.timeout),
+        })
+    }
+
+    fn check(&self, presented: &[u8]) -> bool {
+        token_matches(&self.token, presented)
+    }
+}
+
+/// Subprotocol negotiation and heartbeats of the connections
+struct Protocol {
+    protocols: Vec<String>,
+    ping_interval: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+impl Protocol {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            protocols: config.protocols.clone(),
+            ping_interval: config.ping_interval.map(Duration::from_millis),
+            idle_timeout: config.idle_timeout.map(Duration::from_millis),
+        }
+    }
+
+    /// picks the most preferred of the subprotocols a client offers
+    fn negotiate(&self, req: &Request) -> Option<&String> {
+        let offered: Vec<&str> = req
+            .headers()
+            .get_all(SEC_WEBSOCKET_PROTOCOL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        self.protocols
+            .iter()
+            .find(|protocol| offered.contains(&protocol.as_str()))
+    }
+}
+
+/// An accepted connection, counted as open until it is dropped
+struct Connection {
+    open: Arc<AtomicUsize>,
+    over_limit: bool,
+}
+
+impl Connection {
+    fn new(open: &Arc<AtomicUsize>, max_connections: Option<usize>) -> Self {
+        let count = open.fetch_add(1, Ordering::Relaxed) + 1;
+        Self {
+            open: open.clone(),
+            over_limit: max_connections.map_or(false, |max| count > max),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 pub struct Ws {
     pub config: Config,
     onramp_id: TremorUrl,
@@ -57,7 +209,7 @@ impl onramp::Impl for Ws {
 enum WsSourceReply {
     StartStream(usize, Option<Sender<SerializedResponse>>),
     EndStream(usize),
-    Data(SourceReply), // stupid wrapper around SourceReply::Data
+    Data(SourceReply), // stupid wrapper around SourceReply::Data and SourceReply::AccessLog
 }
 
 /// encoded response and additional information
@@ -67,6 +219,8 @@ pub struct SerializedResponse {
     ingest_ns: u64,
     data: Vec<u8>,
     binary: bool,
+    /// close the connection instead of sending data
+    close: Option<CloseFrame<'static>>,
 }
 
 pub struct Int {
@@ -123,23 +277,165 @@ impl Int {
     }
 }
 
-async fn handle_connection(
+/// Takes a token of the client's rate limit, returns how long to wait for
+/// the next token if there is none left
+fn rate_limited(limiter: Option<&Mutex<RateLimiter>>, key: &str) -> Option<std::time::Duration> {
+    limiter?.lock().ok()?.check(key).err()
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_connection<S>(
     source_url: TremorUrl,
     tx: Sender<WsSourceReply>,
-    raw_stream: TcpStream,
+    raw_stream: S,
+    peer: Option<String>,
     origin_uri: EventOriginUri,
     processors: Vec<String>,
     stream: usize,
     link: bool,
-) -> Result<()> {
-    let ws_stream = async_tungstenite::accept_async(raw_stream).await?;
+    rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    auth: Option<Arc<Auth>>,
+    protocol: Arc<Protocol>,
+    connection: Connection,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let mut log = AccessLog::start("GET", "/", peer.as_deref());
+    let mut rate_limit_key = RateLimiter::key(None, peer.as_deref());
+    let mut status = 400;
+    let mut negotiated = None;
+    let handshake = async_tungstenite::accept_hdr_async(
+        raw_stream,
+        |req: &Request, mut res: Response| -> std::result::Result<Response, ErrorResponse> {
+            log.set_path(req.uri().path());
+            if connection.over_limit {
+                status = 503;
+                let mut err = ErrorResponse::new(Some("Service Unavailable".to_string()));
+                *err.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                return Err(err);
+            }
+            let limiter = rate_limiter.as_deref();
+            if let Some(value) = limiter
+                .and_then(|l| l.lock().ok()?.header().map(String::from))
+                .and_then(|name| req.headers().get(name.as_str())?.to_str().ok())
+            {
+                rate_limit_key = RateLimiter::key(Some(value), peer.as_deref());
+            }
+            if let Some(wait) = rate_limited(limiter, &rate_limit_key) {
+                status = 429;
+                let mut err = ErrorResponse::new(Some("Too Many Requests".to_string()));
+                *err.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                if let Ok(retry_after) = HeaderValue::from_str(&rate_limit::retry_after(wait)) {
+                    err.headers_mut().insert(RETRY_AFTER, retry_after);
+                }
+                return Err(err);
+            }
+            if let Some(auth) = auth.as_deref().filter(|a| a.mode == AuthMode::Bearer) {
+                let token = req
+                    .headers()
+                    .get(AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer "));
+                if !token.map_or(false, |token| auth.check(token.as_bytes())) {
+                    status = 401;
+                    let mut err = ErrorResponse::new(Some("Unauthorized".to_string()));
+                    *err.status_mut() = StatusCode::UNAUTHORIZED;
+                    return Err(err);
+                }
+            }
+            if !protocol.protocols.is_empty() {
+                let selected = protocol
+                    .negotiate(req)
+                    .and_then(|p| Some((p, HeaderValue::from_str(p).ok()?)));
+                if let Some((selected, value)) = selected {
+                    res.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value);
+                    negotiated = Some(selected.clone());
+                } else {
+                    let mut err = ErrorResponse::new(Some(format!(
+                        "Unsupported protocol, supported are: {}",
+                        protocol.protocols.join(", ")
+                    )));
+                    *err.status_mut() = StatusCode::BAD_REQUEST;
+                    return Err(err);
+                }
+            }
+            Ok(res)
+        },
+    )
+    .await;
+    let ws_stream = match handshake {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            let data = log.finish(status, 0, 0);
+            tx.send(WsSourceReply::Data(SourceReply::AccessLog {
+                origin_uri,
+                data,
+            }))
+            .await?;
+            return Err(e.into());
+        }
+    };
+    let mut bytes_in = 0;
+    let bytes_out = Arc::new(AtomicUsize::new(0));
 
     let (mut ws_write, mut ws_read) = ws_stream.split();
 
+    if let Some(auth) = auth.as_deref().filter(|a| a.mode == AuthMode::Message) {
+        let authenticated = match async_std::future::timeout(auth.timeout, ws_read.next()).await {
+            Ok(Some(Ok(Message::Text(token)))) => auth.check(token.as_bytes()),
+            Ok(Some(Ok(Message::Binary(token)))) => auth.check(&token),
+            _ => false,
+        };
+        if !authenticated {
+            warn!(
+                "[Source::{}] Closing unauthenticated connection {}",
+                source_url,
+                peer.as_deref().unwrap_or_default()
+            );
+            let close = CloseFrame {
+                code: CloseCode::Policy,
+                reason: "authentication failed".into(),
+            };
+            // the client may be gone already
+            let _ = ws_write.send(Message::Close(Some(close))).await;
+            let data = log.finish(401, 0, 0);
+            tx.send(WsSourceReply::Data(SourceReply::AccessLog {
+                origin_uri,
+                data,
+            }))
+            .await?;
+            return Ok(());
+        }
+    }
+
+    // all messages to the client, responses and pings, go through one writer
+    let (out_tx, out_rx): (Sender<Message>, Receiver<Message>) = bounded(crate::QSIZE);
+    let writer_bytes_out = bytes_out.clone();
+    task::spawn::<_, Result<()>>(async move {
+        while let Ok(msg) = out_rx.recv().await {
+            writer_bytes_out.fetch_add(msg.len(), Ordering::Relaxed);
+            ws_write.send(msg).await?;
+        }
+        Ok(())
+    });
+    if let Some(interval) = protocol.ping_interval {
+        let out_tx = out_tx.clone();
+        task::spawn(async move {
+            loop {
+                task::sleep(interval).await;
+                if out_tx.send(Message::Ping(vec![])).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
     // TODO maybe send ws_write from tx and get rid of this task + extra channel?
     let stream_sender = if link {
         let (stream_tx, stream_rx): (Sender<SerializedResponse>, Receiver<SerializedResponse>) =
             bounded(crate::QSIZE);
+        let out_tx = out_tx.clone();
         // response handling task
         task::spawn::<_, Result<()>>(async move {
             // create post-processors for this stream
@@ -175,7 +471,7 @@ async fn handle_connection(
                             }
                         };
                         for msg in msgs {
-                            ws_write.send(msg).await?;
+                            out_tx.send(msg).await?;
                         }
                     }
                 }
@@ -194,8 +490,45 @@ async fn handle_connection(
     tx.send(WsSourceReply::StartStream(stream, stream_sender))
         .await?;
 
-    while let Some(msg) = ws_read.next().await {
-        let mut meta = Value::object_with_capacity(1);
+    loop {
+        let next = if let Some(idle_timeout) = protocol.idle_timeout {
+            if let Ok(next) = async_std::future::timeout(idle_timeout, ws_read.next()).await {
+                next
+            } else {
+                info!(
+                    "[Source::{}] Closing idle connection {}",
+                    source_url,
+                    peer.as_deref().unwrap_or_default()
+                );
+                let close = CloseFrame {
+                    code: CloseCode::Policy,
+                    reason: "idle timeout".into(),
+                };
+                // the client may be gone already
+                let _ = out_tx.send(Message::Close(Some(close))).await;
+                tx.send(WsSourceReply::EndStream(stream)).await?;
+                break;
+            }
+        } else {
+            ws_read.next().await
+        };
+        let msg = if let Some(msg) = next { msg } else { break };
+        let mut meta = Value::object_with_capacity(2);
+        if let Some(protocol) = &negotiated {
+            meta.insert("protocol", protocol.clone())?;
+        }
+        if let Ok(msg) = &msg {
+            bytes_in += msg.len();
+            if (msg.is_text() || msg.is_binary())
+                && rate_limited(rate_limiter.as_deref(), &rate_limit_key).is_some()
+            {
+                debug!(
+                    "[Source::{}] Dropping message of rate limited client {}",
+                    source_url, rate_limit_key
+                );
+                continue;
+            }
+        }
         match msg {
             Ok(Message::Text(t)) => {
                 meta.insert("binary", false)?;
@@ -227,6 +560,12 @@ async fn handle_connection(
             Err(e) => error!("WS error returned while waiting for client data: {}", e),
         }
     }
+    let data = log.finish(101, bytes_in, bytes_out.load(Ordering::Relaxed));
+    tx.send(WsSourceReply::Data(SourceReply::AccessLog {
+        origin_uri,
+        data,
+    }))
+    .await?;
     Ok(())
 }
 
@@ -234,8 +573,16 @@ fn make_messages(
     response: SerializedResponse,
     processors: &mut Postprocessors,
 ) -> Result<Vec<Message>> {
+    if let Some(frame) = response.close {
+        return Ok(vec![Message::Close(Some(frame))]);
+    }
     let send_as_binary = response.binary;
-    let processed = postprocess(processors.as_mut_slice(), response.ingest_ns, response.data)?;
+    let processed = postprocess(
+        processors.as_mut_slice(),
+        response.ingest_ns,
+        response.data,
+        None,
+    )?;
     Ok(processed
         .into_iter()
         .map(|data| {
@@ -273,6 +620,7 @@ impl Source for Int {
                             messages.insert(id, stream);
                             Ok(wrapped)
                         }
+                        SourceReply::AccessLog { .. } => Ok(wrapped),
                         _ => Err(
                             "Invalid WsSourceReply received in pull_event. Something is fishy!"
                                 .into(),
@@ -308,6 +656,23 @@ impl Source for Int {
         if let Some((_stream, eid)) = event.id.get_max_by_source(self.uid) {
             if let Some(tx) = self.get_stream_sender_for_id(eid) {
                 for (value, meta) in event.value_meta_iter() {
+                    // events rejected by a limiter close the connection with `try again later`
+                    if let Some(rejected) = meta.get("rejected") {
+                        let retry_after_ms = rejected.get_u64("retry_after_ms").unwrap_or(1000);
+                        let res = SerializedResponse {
+                            event_id: event.id.clone(),
+                            ingest_ns: event.ingest_ns,
+                            data: vec![],
+                            binary: false,
+                            close: Some(CloseFrame {
+                                code: CloseCode::Again,
+                                reason: format!("rate limited, retry after {}ms", retry_after_ms)
+                                    .into(),
+                            }),
+                        };
+                        tx.send(res).await?;
+                        continue;
+                    }
                     let binary = meta.get_bool("binary").unwrap_or_default();
                     // we do the encoding here, and the post-processing later on the sending task, as this is stream-based
                     let data = match codec.encode(value) {
@@ -331,6 +696,7 @@ impl Source for Int {
                         ingest_ns: event.ingest_ns,
                         data,
                         binary,
+                        close: None,
                     };
                     tx.send(res).await?;
                 }
@@ -350,27 +716,93 @@ impl Source for Int {
 
         make_postprocessors(self.post_processors.as_slice())?; // just for verification before starting the onramp
         let processors = self.post_processors.clone();
+        let rate_limiter = self
+            .config
+            .rate_limit
+            .as_ref()
+            .map(|c| Arc::new(Mutex::new(RateLimiter::new(c))));
+        let acceptor = self.config.tls.as_ref().map(tls::acceptor).transpose()?;
+        let auth = self
+            .config
+            .auth
+            .as_ref()
+            .map(Auth::from_config)
+            .transpose()?
+            .map(Arc::new);
+        let protocol = Arc::new(Protocol::from_config(&self.config));
+        let max_connections = self.config.max_connections;
+        let open = Arc::new(AtomicUsize::new(0));
         task::spawn(async move {
             let mut stream_id = 0;
             while let Ok((stream, socket)) = listener.accept().await {
                 let uri = EventOriginUri {
                     uid,
-                    scheme: "tremor-ws".to_string(),
+                    scheme: if acceptor.is_some() {
+                        "tremor-wss"
+                    } else {
+                        "tremor-ws"
+                    }
+                    .to_string(),
                     host: socket.ip().to_string(),
                     port: Some(socket.port()),
                     path: vec![listen_port.to_string()],
                 };
+                let peer = Some(socket.to_string());
 
                 stream_id += 1;
-                task::spawn(handle_connection(
-                    source_url.clone(),
-                    tx.clone(),
-                    stream,
-                    uri,
-                    processors.clone(),
-                    stream_id,
-                    link,
-                ));
+                let source_url = source_url.clone();
+                let tx = tx.clone();
+                let processors = processors.clone();
+                let rate_limiter = rate_limiter.clone();
+                let auth = auth.clone();
+                let protocol = protocol.clone();
+                let connection = Connection::new(&open, max_connections);
+                if let Some(acceptor) = &acceptor {
+                    let handshake = acceptor.accept(stream);
+                    task::spawn(async move {
+                        match handshake.await {
+                            Ok(stream) => {
+                                handle_connection(
+                                    source_url,
+                                    tx,
+                                    stream,
+                                    peer,
+                                    uri,
+                                    processors,
+                                    stream_id,
+                                    link,
+                                    rate_limiter,
+                                    auth,
+                                    protocol,
+                                    connection,
+                                )
+                                .await
+                            }
+                            Err(e) => {
+                                warn!(
+                                    "[Source::{}] TLS handshake with {} failed: {}",
+                                    source_url, socket, e
+                                );
+                                Ok(())
+                            }
+                        }
+                    });
+                } else {
+                    task::spawn(handle_connection(
+                        source_url,
+                        tx,
+                        stream,
+                        peer,
+                        uri,
+                        processors,
+                        stream_id,
+                        link,
+                        rate_limiter,
+                        auth,
+                        protocol,
+                        connection,
+                    ));
+                }
             }
         });
 
@@ -0,0 +1,327 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(not(tarpaulin_include))]
+
+//! # WebSocket Client Onramp
+//!
+//! The `ws-client` onramp connects out to a websocket endpoint, e.g. a market
+//! data feed, and emits the text and binary messages it receives, with
+//! `$binary` set like the `ws` onramp does. Each connection is its own
+//! stream, so preprocessors start over after a reconnect.
+//!
+//! The `subscribe` messages are sent on every (re)connect and, with
+//! `subscribe_interval`, periodically after. Strings are sent as they are,
+//! other values as JSON. Lost connections are retried following the
+//! `reconnect` policy.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+
+use crate::connectors::reconnect::{self, Reconnect};
+use crate::metrics::InstanceStats;
+use crate::source::prelude::*;
+use async_channel::{Sender, TryRecvError};
+use async_std::task::JoinHandle;
+use async_tungstenite::async_std::connect_async;
+use async_tungstenite::tungstenite::client::IntoClientRequest;
+use async_tungstenite::tungstenite::handshake::client::Request;
+use async_tungstenite::tungstenite::http::header::{
+    HeaderName, HeaderValue, SEC_WEBSOCKET_PROTOCOL,
+};
+use async_tungstenite::tungstenite::Message;
+use futures::{SinkExt, StreamExt};
+use halfbrown::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Config {
+    /// The `ws://` url to connect to
+    pub url: String,
+    /// Subprotocols requested in the handshake
+    #[serde(default = "Default::default")]
+    pub protocols: Vec<String>,
+    /// Headers added to the handshake request
+    #[serde(default = "Default::default")]
+    pub headers: HashMap<String, String>,
+    /// Messages sent on every (re)connect
+    #[serde(default = "Default::default")]
+    pub subscribe: Vec<YamlValue>,
+    /// Milliseconds after which the `subscribe` messages are sent again
+    #[serde(default = "Default::default")]
+    pub subscribe_interval: Option<u64>,
+    /// reconnect policy
+    #[serde(default)]
+    pub reconnect: reconnect::Config,
+}
+
+impl ConfigImpl for Config {}
+
+impl Config {
+    /// the handshake request
+    fn request(&self) -> Result<Request> {
+        let mut request = self.url.as_str().into_client_request()?;
+        let headers = request.headers_mut();
+        if !self.protocols.is_empty() {
+            headers.insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_str(&self.protocols.join(", "))?,
+            );
+        }
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| Error::from(format!("Invalid header name {}: {}", name, e)))?;
+            headers.insert(name, HeaderValue::from_str(value)?);
+        }
+        Ok(request)
+    }
+
+    /// the `subscribe` messages, strings as they are, other values as JSON
+    fn subscribe_messages(&self) -> Result<Vec<Message>> {
+        self.subscribe
+            .iter()
+            .map(|msg| match msg {
+                YamlValue::String(s) => Ok(Message::Text(s.clone())),
+                other => Ok(Message::Text(String::from_utf8(simd_json::to_vec(other)?)?)),
+            })
+            .collect()
+    }
+}
+
+pub struct WsClient {
+    pub config: Config,
+    onramp_id: TremorUrl,
+}
+
+impl onramp::Impl for WsClient {
+    fn from_config(id: &TremorUrl, config: &Option<YamlValue>) -> Result<Box<dyn Onramp>> {
+        if let Some(config) = config {
+            let config: Config = Config::new(config)?;
+            // fail on invalid urls, headers and messages before connecting
+            config.request()?;
+            config.subscribe_messages()?;
+            Ok(Box::new(Self {
+                config,
+                onramp_id: id.clone(),
+            }))
+        } else {
+            Err("Missing config for ws-client onramp".into())
+        }
+    }
+}
+
+pub struct Int {
+    uid: u64,
+    config: Config,
+    onramp_id: TremorUrl,
+    stats: Arc<InstanceStats>,
+    rx: Option<Receiver<SourceReply>>,
+    connection: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Int {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WsClient({})", self.config.url)
+    }
+}
+
+/// sends the `subscribe` messages every `interval_ms`
+async fn resubscribe<S>(mut write: S, messages: Vec<Message>, interval_ms: u64)
+where
+    S: futures::Sink<Message> + Unpin,
+{
+    loop {
+        task::sleep(Duration::from_millis(interval_ms)).await;
+        for msg in &messages {
+            if write.send(msg.clone()).await.is_err() {
+                // the connection is gone, the read side reconnects
+                return;
+            }
+        }
+    }
+}
+
+/// connects to the endpoint and forwards the received messages, until the
+/// reconnect policy gives up or the onramp is stopped
+async fn connection_loop(
+    source_id: TremorUrl,
+    uid: u64,
+    config: Config,
+    mut reconnect: Reconnect,
+    tx: Sender<SourceReply>,
+) -> Result<()> {
+    let messages = config.subscribe_messages()?;
+    let mut stream = 0;
+    loop {
+        info!("[Source::{}] Connecting to {} ...", source_id, config.url);
+        let ws_stream = match connect_async(config.request()?).await {
+            Ok((ws_stream, _)) => ws_stream,
+            Err(e) => {
+                error!(
+                    "[Source::{}] Failed to connect to {}: {}",
+                    source_id, config.url, e
+                );
+                if reconnect.wait(&e.to_string()).await {
+                    continue;
+                }
+                return Err(format!("Gave up connecting to {}: {}", config.url, e).into());
+            }
+        };
+        let mut origin_uri = EventOriginUri {
+            uid,
+            scheme: "tremor-ws-client".to_string(),
+            host: hostname(),
+            port: None,
+            path: vec![config.url.clone()],
+        };
+        if let Ok(peer) = ws_stream.get_ref().peer_addr() {
+            origin_uri.host = peer.ip().to_string();
+            origin_uri.port = Some(peer.port());
+        }
+        reconnect.connected();
+        stream += 1;
+        tx.send(SourceReply::StartStream(stream)).await?;
+
+        let (mut write, mut read) = ws_stream.split();
+        let mut subscribed = Ok(());
+        for msg in &messages {
+            subscribed = write.send(msg.clone()).await;
+            if subscribed.is_err() {
+                break;
+            }
+        }
+        let resubscriber =
+            match (subscribed.is_ok(), config.subscribe_interval) {
+                (true, Some(interval_ms)) if !messages.is_empty() => Some(task::spawn(
+                    resubscribe(write, messages.clone(), interval_ms),
+                )),
+                _ => None,
+            };
+
+        let mut error = subscribed.err().map(|e| e.to_string());
+        while error.is_none() {
+            let (data, binary) = match read.next().await {
+                Some(Ok(Message::Text(text))) => (text.into_bytes(), false),
+                Some(Ok(Message::Binary(data))) => (data, true),
+                Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(frame))) => {
+                    error = Some(frame.map_or_else(
+                        || "connection closed".to_string(),
+                        |f| format!("connection closed: {}", f),
+                    ));
+                    break;
+                }
+                Some(Err(e)) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+                None => {
+                    error = Some("connection closed".to_string());
+                    break;
+                }
+            };
+            let mut meta = Value::object_with_capacity(1);
+            meta.insert("binary", binary)?;
+            tx.send(SourceReply::Data {
+                origin_uri: origin_uri.clone(),
+                data,
+                meta: Some(meta),
+                codec_override: None,
+                stream,
+            })
+            .await?;
+        }
+        if let Some(resubscriber) = resubscriber {
+            resubscriber.cancel().await;
+        }
+        tx.send(SourceReply::EndStream(stream)).await?;
+
+        let error = error.unwrap_or_default();
+        warn!(
+            "[Source::{}] Lost connection to {}: {}",
+            source_id, config.url, error
+        );
+        if !reconnect.wait(&error).await {
+            return Err(format!("Gave up reconnecting to {}: {}", config.url, error).into());
+        }
+    }
+}
+
+#[async_trait::async_trait()]
+impl Source for Int {
+    fn id(&self) -> &TremorUrl {
+        &self.onramp_id
+    }
+
+    async fn pull_event(&mut self, _id: u64) -> Result<SourceReply> {
+        self.rx.as_ref().map_or_else(
+            || Ok(SourceReply::StateChange(SourceState::Disconnected)),
+            |rx| match rx.try_recv() {
+                Ok(r) => Ok(r),
+                Err(TryRecvError::Empty) => Ok(SourceReply::Empty(10)),
+                Err(TryRecvError::Closed) => {
+                    Ok(SourceReply::StateChange(SourceState::Disconnected))
+                }
+            },
+        )
+    }
+
+    async fn init(&mut self) -> Result<SourceState> {
+        let (tx, rx) = bounded(crate::QSIZE);
+        let mut reconnect = Reconnect::new(self.config.reconnect.clone());
+        reconnect.report_to(Some(self.stats.clone()));
+        let source_id = self.onramp_id.clone();
+        let connection = connection_loop(
+            self.onramp_id.clone(),
+            self.uid,
+            self.config.clone(),
+            reconnect,
+            tx,
+        );
+        self.connection = Some(task::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("[Source::{}] {}", source_id, e);
+            }
+        }));
+        self.rx = Some(rx);
+        Ok(SourceState::Connected)
+    }
+
+    async fn terminate(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            connection.cancel().await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Onramp for WsClient {
+    async fn start(&mut self, config: OnrampConfig<'_>) -> Result<onramp::Addr> {
+        let source = Int {
+            uid: config.onramp_uid,
+            config: self.config.clone(),
+            onramp_id: self.onramp_id.clone(),
+            stats: config.metrics_reporter.stats(),
+            rx: None,
+            connection: None,
+        };
+        SourceManager::start(source, config).await
+    }
+
+    fn default_codec(&self) -> &str {
+        "json"
+    }
+}
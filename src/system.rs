@@ -15,17 +15,21 @@
 use crate::config::{BindingVec, Config, MappingMap, OffRampVec, OnRampVec};
 use crate::errors::{Error, ErrorKind, Result};
 use crate::lifecycle::{ActivationState, ActivatorLifecycleFsm};
+use crate::metrics::{self, InstanceStats, InstanceStatsSnapshot};
 use crate::registry::{Registries, ServantId};
 use crate::repository::{
     Artefact, BindingArtefact, OfframpArtefact, OnrampArtefact, PipelineArtefact, Repositories,
 };
+use crate::source::lifecycle;
 use crate::url::ports::METRICS;
 use crate::url::TremorUrl;
 use async_channel::bounded;
 use async_std::io::prelude::*;
 use async_std::path::Path;
+use async_std::sync::RwLock;
 use async_std::task::{self, JoinHandle};
 use hashbrown::HashMap;
+use std::sync::Arc;
 use tremor_common::asy::file;
 use tremor_common::time::nanotime;
 
@@ -129,6 +133,7 @@ pub struct World {
     pub repo: Repositories,
     /// Registry
     pub reg: Registries,
+    stats: Arc<RwLock<HashMap<ServantId, Arc<InstanceStats>>>>,
     storage_directory: Option<String>,
 }
 
@@ -220,6 +225,7 @@ impl World {
             (Some(_artefact), Some(_instance_id)) => {
                 let r = self.reg.unpublish_pipeline(id).await?;
                 self.repo.unbind_pipeline(id).await?;
+                self.unregister_stats(id).await;
                 Ok(r)
             }
             (None, _) => Err(ErrorKind::ArtefactNotFound(id.to_string()).into()),
@@ -232,8 +238,16 @@ impl World {
     /// # Errors
     ///  * if the system failed to stop
     pub async fn stop(&self) -> Result<()> {
+        lifecycle::publish(lifecycle::Transition::Stopping);
         Ok(self.system.send(ManagerMsg::Stop).await?)
     }
+
+    /// Announces that startup is complete, emitted as `started` by
+    /// `lifecycle` onramps
+    pub fn started(&self) {
+        lifecycle::publish(lifecycle::Transition::Started);
+    }
+
     /// Links a pipeline
     ///
     /// # Errors
@@ -342,6 +356,7 @@ impl World {
             (Some(_artefact), Some(_instsance_id)) => {
                 let r = self.reg.unpublish_onramp(id).await;
                 self.repo.unbind_onramp(id).await?;
+                self.unregister_stats(id).await;
                 r
             }
             (None, _) => Err(ErrorKind::ArtefactNotFound(id.to_string()).into()),
@@ -446,6 +461,7 @@ impl World {
             (Some(_artefact), Some(_instsance_id)) => {
                 let r = self.reg.unpublish_offramp(id).await;
                 self.repo.unbind_offramp(id).await?;
+                self.unregister_stats(id).await;
                 r
             }
             (None, _) => Err(ErrorKind::ArtefactNotFound(id.to_string()).into()),
@@ -550,6 +566,7 @@ impl World {
     ///
     /// # Errors
     ///  * If the id isn't a binding or the bindig can't be linked
+    ///  * If a binding it `depends_on` isn't linked
     pub async fn link_binding(
         &self,
         id: &TremorUrl,
@@ -559,6 +576,20 @@ impl World {
         >,
     ) -> Result<<BindingArtefact as Artefact>::LinkResult> {
         if let Some(binding_a) = self.repo.find_binding(id).await? {
+            let depends_on = &binding_a.artefact.binding.depends_on;
+            if !depends_on.is_empty() {
+                let linked = self.reg.serialize_mappings().await?;
+                if let Some(dep) = depends_on
+                    .iter()
+                    .find(|dep| !linked.keys().any(|l| l.artefact() == Some(dep.as_str())))
+                {
+                    return Err(format!(
+                        "Binding {} depends on binding {} which is not linked",
+                        id, dep
+                    )
+                    .into());
+                }
+            }
             let r = binding_a.artefact.link(self, id, mappings).await?;
             if self.reg.find_binding(id).await?.is_none() {
                 self.bind_binding_a(id, &r).await?;
@@ -585,10 +616,15 @@ impl World {
             .collect();
         let mapping: MappingMap = self.reg.serialize_mappings().await?;
         let config = crate::config::Config {
+            include: vec![],
             onramp,
             offramp,
+            pipeline: vec![],
             binding,
             mapping,
+            template: vec![],
+            instance: vec![],
+            env: vec![],
         };
         Ok(config)
     }
@@ -671,6 +707,7 @@ impl World {
             system,
             repo,
             reg,
+            stats: Arc::default(),
             storage_directory,
         };
 
@@ -696,10 +733,12 @@ impl World {
             .await?;
         self.bind_pipeline(&METRICS_PIPELINE).await?;
 
-        self.reg
+        let metrics_pipeline = self
+            .reg
             .find_pipeline(&METRICS_PIPELINE)
             .await?
             .ok_or_else(|| Error::from("Failed to initialize metrics pipeline."))?;
+        metrics::report_script_metrics((METRICS_PIPELINE.clone(), metrics_pipeline));
 
         let artefact_passthrough = tremor_pipeline::query::Query::parse(
             &module_path,
@@ -753,12 +792,41 @@ type: stderr
         id: ServantId,
     ) -> Result<pipeline::Addr> {
         let (tx, rx) = bounded(1);
+        let stats = Arc::new(InstanceStats::default());
         self.system
             .send(ManagerMsg::CreatePipeline(
                 tx,
-                pipeline::Create { config, id },
+                pipeline::Create {
+                    config,
+                    id: id.clone(),
+                    stats: stats.clone(),
+                },
             ))
             .await?;
-        rx.recv().await?
+        let addr = rx.recv().await??;
+        self.register_stats(id, stats).await;
+        Ok(addr)
+    }
+
+    /// Runtime statistics of an artefact instance
+    pub async fn instance_stats(&self, id: &TremorUrl) -> Option<InstanceStatsSnapshot> {
+        let mut id = id.clone();
+        id.trim_to_instance();
+        self.stats
+            .read()
+            .await
+            .get(&id)
+            .map(|stats| stats.snapshot())
+    }
+
+    pub(crate) async fn register_stats(&self, mut id: ServantId, stats: Arc<InstanceStats>) {
+        id.trim_to_instance();
+        self.stats.write().await.insert(id, stats);
+    }
+
+    async fn unregister_stats(&self, id: &TremorUrl) {
+        let mut id = id.clone();
+        id.trim_to_instance();
+        self.stats.write().await.remove(&id);
     }
 }
@@ -57,6 +57,12 @@ pub mod ports {
 
     /// standard metrics port
     pub const METRICS: Cow<'static, str> = Cow::const_str("metrics");
+
+    /// onramp port for diagnostics about the onramp itself
+    pub const DIAGNOSTICS: Cow<'static, str> = Cow::const_str("diagnostics");
+
+    /// onramp port for access logs of requests and connections
+    pub const ACCESS_LOG: Cow<'static, str> = Cow::const_str("access_log");
 }
 
 /// A tremor URL identifying an entity in tremor
@@ -154,6 +154,8 @@ test_cases!(
     // TODO
     // const_in_const_lookup,
     // INSERT
+    try_catch,
+    try_catch_literal,
     match_multiple_exprs,
     xz_compressed_fixtures,
     match_assign,
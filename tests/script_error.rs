@@ -134,6 +134,7 @@ test_cases!(
     pp_cyclic,
     pp_nest_cyclic,
     // INSERT
+    schema_invalid_type,
     merge_ident,
     select_ident,
     function_already_defined,
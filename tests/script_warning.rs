@@ -63,6 +63,8 @@ test_cases!(
     match_imut_no_default,
     match_imut_multiple_default,
     // INSERT
+    schema_type_mismatch,
+    schema_undeclared_field,
     recordpattern_absence_and_extractor,
     recordpattern_presence_and_extractor,
 );
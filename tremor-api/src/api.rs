@@ -13,9 +13,11 @@
 // limitations under the License.
 
 use crate::errors::Error;
+use hashbrown::HashMap;
 use http_types::{headers, StatusCode};
 use serde::{Deserialize, Serialize};
 use tide::Response;
+use tremor_runtime::metrics::InstanceStatsSnapshot;
 use tremor_runtime::system::World;
 use tremor_runtime::url::TremorUrl;
 
@@ -147,6 +149,42 @@ where
     }
 }
 
+/// Checks for a `force` query parameter, e.g. `DELETE /onramp/snot?force`
+fn is_forced(req: &Request) -> bool {
+    req.url()
+        .query_pairs()
+        .any(|(k, v)| k == "force" && v != "false")
+}
+
+/// Lists the ids of the bindings referring to an artefact
+async fn referrers(req: &Request, url: &TremorUrl) -> Result<Vec<String>> {
+    Ok(req
+        .state()
+        .world
+        .repo
+        .referrers(url)
+        .await?
+        .iter()
+        .filter_map(|v| v.artefact().map(String::from))
+        .collect())
+}
+
+/// Collects the runtime statistics of artefact instances, keyed by instance id
+async fn instance_stats(
+    req: &Request,
+    instances: &[TremorUrl],
+) -> HashMap<String, InstanceStatsSnapshot> {
+    let world = &req.state().world;
+    let mut res = HashMap::with_capacity(instances.len());
+    for instance in instances {
+        if let (Some(id), Some(stats)) = (instance.instance(), world.instance_stats(instance).await)
+        {
+            res.insert(id.to_string(), stats);
+        }
+    }
+    res
+}
+
 fn build_url(path: &[&str]) -> Result<TremorUrl> {
     let url = format!("/{}", path.join("/"));
     TremorUrl::parse(&url).map_err(|_e| {
@@ -13,10 +13,15 @@
 // limitations under the License.
 
 use crate::api::prelude::*;
+use hashbrown::HashMap;
+use tremor_runtime::archive::{self, Query};
+use tremor_runtime::metrics::InstanceStatsSnapshot;
+
 #[derive(Serialize)]
 struct OffRampWrap {
     artefact: tremor_runtime::config::OffRamp,
     instances: Vec<String>,
+    stats: HashMap<String, InstanceStatsSnapshot>,
 }
 
 pub async fn list_artefact(req: Request) -> Result<Response> {
@@ -42,6 +47,9 @@ pub async fn unpublish_artefact(req: Request) -> Result<Response> {
     let id = req.param("aid").unwrap_or_default();
     let url = build_url(&["offramp", id])?;
     let repo = &req.state().world.repo;
+    if !is_forced(&req) {
+        repo.ensure_unreferenced(&url).await?;
+    }
     let result = repo.unpublish_offramp(&url).await?;
     reply(req, result, true, StatusCode::Ok).await
 }
@@ -54,6 +62,7 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
         .find_offramp(&url)
         .await?
         .ok_or_else(Error::not_found)?;
+    let stats = instance_stats(&req, &result.instances).await;
     let result = OffRampWrap {
         artefact: result.artefact,
         instances: result
@@ -61,7 +70,62 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
             .iter()
             .filter_map(|v| v.instance().map(String::from))
             .collect(),
+        stats,
     };
 
     reply(req, result, false, StatusCode::Ok).await
 }
+
+pub async fn get_referrers(req: Request) -> Result<Response> {
+    let id = req.param("aid").unwrap_or_default();
+    let url = build_url(&["offramp", id])?;
+    let repo = &req.state().world.repo;
+    repo.find_offramp(&url)
+        .await?
+        .ok_or_else(Error::not_found)?;
+    let result = referrers(&req, &url).await?;
+    reply(req, result, false, StatusCode::Ok).await
+}
+
+fn query_param<T: std::str::FromStr>(name: &str, value: &str) -> Result<T> {
+    value.parse().map_err(|_| {
+        Error::new(
+            StatusCode::BadRequest,
+            format!("Invalid query parameter `{}`: {}", name, value),
+        )
+    })
+}
+
+/// Queries the events archived by an `archive` offramp, `from`, `to` and
+/// `limit` select the time range in nanoseconds and the number of events,
+/// all other query parameters filter on indexed fields
+pub async fn query_archive(req: Request) -> Result<Response> {
+    let id = req.param("aid").unwrap_or_default();
+    let url = build_url(&["offramp", id])?;
+    let repo = &req.state().world.repo;
+    let offramp = repo
+        .find_offramp(&url)
+        .await?
+        .ok_or_else(Error::not_found)?;
+    let mut query = Query {
+        limit: 100,
+        ..Query::default()
+    };
+    for (k, v) in req.url().query_pairs() {
+        match k.as_ref() {
+            "from" => query.from = Some(query_param(&k, &v)?),
+            "to" => query.to = Some(query_param(&k, &v)?),
+            "limit" => query.limit = query_param(&k, &v)?,
+            _ => query.filters.push((k.to_string(), v.to_string())),
+        }
+    }
+    let result = archive::query(&offramp.artefact, &query)
+        .await?
+        .ok_or_else(|| {
+            Error::new(
+                StatusCode::BadRequest,
+                format!("Offramp {} is not an archive", id),
+            )
+        })?;
+    reply(req, result, false, StatusCode::Ok).await
+}
@@ -13,10 +13,14 @@
 // limitations under the License.
 
 use crate::api::prelude::*;
+use hashbrown::HashMap;
+use tremor_runtime::metrics::InstanceStatsSnapshot;
+
 #[derive(Serialize)]
 struct OnRampWrap {
     artefact: tremor_runtime::config::OnRamp,
     instances: Vec<String>,
+    stats: HashMap<String, InstanceStatsSnapshot>,
 }
 
 pub async fn list_artefact(req: Request) -> Result<Response> {
@@ -43,6 +47,9 @@ pub async fn unpublish_artefact(req: Request) -> Result<Response> {
     let id = req.param("aid").unwrap_or_default();
     let url = build_url(&["onramp", id])?;
     let repo = &req.state().world.repo;
+    if !is_forced(&req) {
+        repo.ensure_unreferenced(&url).await?;
+    }
     let result = repo.unpublish_onramp(&url).await?;
     reply(req, result, true, StatusCode::Ok).await
 }
@@ -52,6 +59,7 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
     let url = build_url(&["onramp", id])?;
     let repo = &req.state().world.repo;
     let result = repo.find_onramp(&url).await?.ok_or_else(Error::not_found)?;
+    let stats = instance_stats(&req, &result.instances).await;
     let result = OnRampWrap {
         artefact: result.artefact,
         instances: result
@@ -59,7 +67,17 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
             .iter()
             .filter_map(|v| v.instance().map(String::from))
             .collect(),
+        stats,
     };
 
     reply(req, result, false, StatusCode::Ok).await
 }
+
+pub async fn get_referrers(req: Request) -> Result<Response> {
+    let id = req.param("aid").unwrap_or_default();
+    let url = build_url(&["onramp", id])?;
+    let repo = &req.state().world.repo;
+    repo.find_onramp(&url).await?.ok_or_else(Error::not_found)?;
+    let result = referrers(&req, &url).await?;
+    reply(req, result, false, StatusCode::Ok).await
+}
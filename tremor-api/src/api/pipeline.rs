@@ -15,6 +15,8 @@
 use tremor_pipeline::{query::Query, FN_REGISTRY};
 
 use crate::api::prelude::*;
+use hashbrown::HashMap;
+use tremor_runtime::metrics::InstanceStatsSnapshot;
 
 #[derive(Serialize)]
 struct PipelineWrap {
@@ -96,6 +98,7 @@ pub async fn reply_trickle_instanced(
     req: Request,
     mut result_in: String,
     instances: Vec<String>,
+    stats: HashMap<String, InstanceStatsSnapshot>,
     persist: bool,
     ok_code: StatusCode,
 ) -> Result<Response> {
@@ -108,9 +111,25 @@ pub async fn reply_trickle_instanced(
         ResourceType::Trickle => {
             let mut r = Response::new(ok_code);
             r.insert_header(headers::CONTENT_TYPE, ResourceType::Trickle.as_str());
-            let instances = instances.join("#  * ");
-            result_in.push_str("\n# Instances:\n#  * ");
-            result_in.push_str(&instances);
+            result_in.push_str("\n# Instances:");
+            for instance in &instances {
+                result_in.push_str("\n#  * ");
+                result_in.push_str(instance);
+                if let Some(stats) = stats.get(instance) {
+                    result_in.push_str(&format!(
+                        " ({:?}, up {}s, in: {}, out: {}, errors: {})",
+                        stats.state,
+                        stats.uptime_s,
+                        stats.events_in,
+                        stats.events_out,
+                        stats.errors
+                    ));
+                    if let Some(last_error) = &stats.last_error {
+                        result_in.push_str("\n#    last error: ");
+                        result_in.push_str(&last_error.replace('\n', " "));
+                    }
+                }
+            }
             r.set_body(result_in);
             Ok(r)
         }
@@ -121,6 +140,9 @@ pub async fn unpublish_artefact(req: Request) -> Result<Response> {
     let id = req.param("aid").unwrap_or_default();
     let url = build_url(&["pipeline", id])?;
     let repo = &req.state().world.repo;
+    if !is_forced(&req) {
+        repo.ensure_unreferenced(&url).await?;
+    }
     let result = repo
         .unpublish_pipeline(&url)
         .await
@@ -136,6 +158,7 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
         .find_pipeline(&url)
         .await?
         .ok_or_else(Error::not_found)?;
+    let stats = instance_stats(&req, &result.instances).await;
 
     reply_trickle_instanced(
         req,
@@ -145,8 +168,54 @@ pub async fn get_artefact(req: Request) -> Result<Response> {
             .iter()
             .filter_map(|v| v.instance().map(String::from))
             .collect(),
+        stats,
         false,
         StatusCode::Ok,
     )
     .await
 }
+
+pub async fn get_referrers(req: Request) -> Result<Response> {
+    let id = req.param("aid").unwrap_or_default();
+    let url = build_url(&["pipeline", id])?;
+    let repo = &req.state().world.repo;
+    repo.find_pipeline(&url)
+        .await?
+        .ok_or_else(Error::not_found)?;
+    let result = referrers(&req, &url).await?;
+    reply(req, result, false, StatusCode::Ok).await
+}
+
+pub async fn get_stats(req: Request) -> Result<Response> {
+    let id = req.param("aid").unwrap_or_default();
+    let url = build_url(&["pipeline", id])?;
+    let repo = &req.state().world.repo;
+    let result = repo
+        .find_pipeline(&url)
+        .await?
+        .ok_or_else(Error::not_found)?;
+    let result = instance_stats(&req, &result.instances).await;
+    reply(req, result, false, StatusCode::Ok).await
+}
+
+pub async fn get_recording(req: Request) -> Result<Response> {
+    let a_id = req.param("aid").unwrap_or_default();
+    let s_id = req.param("sid").unwrap_or_default();
+    let url = build_url(&["pipeline", a_id, s_id])?;
+
+    let registry = &req.state().world.reg;
+    let result = registry
+        .find_pipeline(&url)
+        .await?
+        .ok_or_else(Error::not_found)?
+        .recording()
+        .await?
+        .ok_or_else(|| {
+            Error::new(
+                StatusCode::NotFound,
+                format!("Pipeline {} is not recording", url),
+            )
+        })?;
+
+    reply(req, result, false, StatusCode::Ok).await
+}
@@ -128,6 +128,13 @@ impl From<TremorError> for Error {
                 StatusCode::Forbidden,
                 "System artefacts cannot be unpublished".into(),
             ),
+            ErrorKind::UnpublishFailedReferenced(_, referrers) => Error::new(
+                StatusCode::Conflict,
+                format!(
+                    "Resource is still referenced by bindings: {}",
+                    referrers.join(", ")
+                ),
+            ),
             _e => Error::new(
                 StatusCode::InternalServerError,
                 "Internal server error".into(),
@@ -64,18 +64,30 @@ fn api_server(world: &World) -> tide::Server<api::State> {
     app.at("/pipeline/:aid")
         .get(|r| handle_api_request(r, api::pipeline::get_artefact))
         .delete(|r| handle_api_request(r, api::pipeline::unpublish_artefact));
+    app.at("/pipeline/:aid/referrers")
+        .get(|r| handle_api_request(r, api::pipeline::get_referrers));
+    app.at("/pipeline/:aid/stats")
+        .get(|r| handle_api_request(r, api::pipeline::get_stats));
+    app.at("/pipeline/:aid/:sid/recording")
+        .get(|r| handle_api_request(r, api::pipeline::get_recording));
     app.at("/onramp")
         .get(|r| handle_api_request(r, api::onramp::list_artefact))
         .post(|r| handle_api_request(r, api::onramp::publish_artefact));
     app.at("/onramp/:aid")
         .get(|r| handle_api_request(r, api::onramp::get_artefact))
         .delete(|r| handle_api_request(r, api::onramp::unpublish_artefact));
+    app.at("/onramp/:aid/referrers")
+        .get(|r| handle_api_request(r, api::onramp::get_referrers));
     app.at("/offramp")
         .get(|r| handle_api_request(r, api::offramp::list_artefact))
         .post(|r| handle_api_request(r, api::offramp::publish_artefact));
     app.at("/offramp/:aid")
         .get(|r| handle_api_request(r, api::offramp::get_artefact))
         .delete(|r| handle_api_request(r, api::offramp::unpublish_artefact));
+    app.at("/offramp/:aid/referrers")
+        .get(|r| handle_api_request(r, api::offramp::get_referrers));
+    app.at("/offramp/:aid/archive")
+        .get(|r| handle_api_request(r, api::offramp::query_archive));
 
     app
 }
@@ -151,6 +163,8 @@ pub(crate) async fn run_dun(matches: &ArgMatches) -> Result<()> {
         }
     }
 
+    world.started();
+
     if !matches.is_present("no-api") {
         let host = matches
             .value_of("api-host")
@@ -116,6 +116,10 @@ where
                             .map_err(|e| Error::ParseIntError(total_idx, e))?,
                     ))
                 }
+            } else if let Some(s) = s.strip_suffix('u') {
+                Ok(V::from(
+                    lexical::parse::<u64, _>(s).map_err(|e| Error::ParseIntError(total_idx, e))?,
+                ))
             } else {
                 Ok(V::from(
                     lexical::parse::<f64, _>(s)
@@ -143,7 +147,8 @@ where
         offset += idx;
         input = rest;
         if let Some(rest) = input.strip_prefix('\\') {
-            let mut res = String::with_capacity(256);
+            // escaped values can't be longer than the remaining input
+            let mut res = String::with_capacity(data.len() + input.len());
             res.push_str(data);
             parse_value_complex(total_index + offset, res, rest)
         } else if input.starts_with(',') {
@@ -280,7 +285,7 @@ where
         input = rest;
         if let Some(rest) = input.strip_prefix('\\') {
             input = rest;
-            let mut res = String::with_capacity(256);
+            let mut res = String::with_capacity(data.len() + input.len());
             res.push_str(data);
             // https://docs.influxdata.com/influxdb/v1.7/write_protocols/line_protocol_reference/#special-characters
             if !input.starts_with(search) {
@@ -83,6 +83,11 @@ where
         } else {
             match value.value_type() {
                 ValueType::F64 | ValueType::Bool => value.write(&mut output)?,
+                // unsigned integers that don't fit into a signed one
+                ValueType::U64 if value.as_i64().is_none() => {
+                    value.write(&mut output)?;
+                    output.write_all(&[b'u'])?
+                }
                 ValueType::U64 | ValueType::I64 => {
                     value.write(&mut output)?;
                     output.write_all(&[b'i'])?
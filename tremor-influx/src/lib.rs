@@ -181,6 +181,59 @@ mod tests {
         assert_eq!(Some(r), parsed);
     }
 
+    #[test]
+    fn parse_no_timestamp_str_value() {
+        let s = "weather,location=us-midwest temperature=82u,summary=\"too warm\"";
+        let parsed = decode(s, 1_465_839_830_100_400_200u64).expect("failed to parse");
+        let r: BorrowedValue = json!({
+            "measurement": "weather",
+            "tags": {
+                "location": "us-midwest"
+            },
+            "fields": {
+                "temperature": 82,
+                "summary": "too warm"
+            },
+            "timestamp": 1_465_839_830_100_400_200i64,
+        })
+        .into();
+        assert_eq!(Some(r), parsed);
+    }
+
+    #[test]
+    fn parse_uint_value() {
+        let s = "weather temperature=18446744073709551615u 1465839830100400200";
+        let r: BorrowedValue = json!({
+            "measurement": "weather",
+            "tags": {},
+            "fields": {
+                "temperature": 18_446_744_073_709_551_615u64
+            },
+            "timestamp": 1_465_839_830_100_400_200i64,
+        })
+        .into();
+        assert_eq!(Ok(Some(r.clone())), decode(s, 0));
+        assert_eq!(Ok(s.as_bytes().to_vec()), encode(&r));
+        assert!(decode::<BorrowedValue>("weather temperature=-1u", 0).is_err());
+    }
+
+    #[test]
+    fn parse_exponent_value() {
+        let s = "weather temperature=8.2e1,pressure=-1.5E-3,humidity=1e+2 1465839830100400200";
+        let r: BorrowedValue = json!({
+            "measurement": "weather",
+            "tags": {},
+            "fields": {
+                "temperature": 82.0,
+                "pressure": -0.0015,
+                "humidity": 100.0
+            },
+            "timestamp": 1_465_839_830_100_400_200i64,
+        })
+        .into();
+        assert_eq!(Ok(Some(r)), decode(s, 0))
+    }
+
     #[test]
     fn parse_float_value() {
         let s = "weather temperature=82 1465839830100400200";
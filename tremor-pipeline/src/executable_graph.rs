@@ -14,6 +14,7 @@
 
 use std::{fmt, fmt::Display, sync::Arc};
 
+use crate::recording::{recorded_event, Recorder, Recording};
 use crate::{
     common_cow,
     errors::Result,
@@ -22,11 +23,13 @@ use crate::{
     op::{prelude::IN, trickle::select::WindowImpl},
     ConfigMap, ExecPortIndexMap, NodeLookupFn,
 };
-use crate::{op::EventAndInsights, Event, NodeKind, Operator};
+use crate::{op::EventAndInsights, CbAction, Event, NodeKind, Operator};
 use beef::Cow;
 use halfbrown::HashMap;
 use tremor_common::stry;
+use tremor_common::time::nanotime;
 use tremor_script::{query::StmtRentalWrapper, Value};
+use tremor_value::literal;
 
 /// Configuration for a node
 #[derive(Debug, Clone, PartialOrd, Eq, Default)]
@@ -173,6 +176,10 @@ impl Operator for OperatorNode {
     fn skippable(&self) -> bool {
         self.op.skippable()
     }
+
+    fn snapshot(&self) -> Option<Value<'static>> {
+        self.op.snapshot()
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -250,6 +257,81 @@ impl NodeMetrics {
     }
 }
 
+/// Latency budget of a pipeline, tracking the time from ingest until
+/// an event is acknowledged by the sink against the budget.
+#[derive(Debug, Clone)]
+pub(crate) struct LatencyBudget {
+    budget_ns: u64,
+    /// ratio of events that should be within budget, e.g. `0.99`
+    objective: f64,
+    acked: u64,
+    violations: u64,
+    window_acked: u64,
+    window_violations: u64,
+}
+
+impl LatencyBudget {
+    pub(crate) fn new(budget_ns: u64, objective: f64) -> Result<Self> {
+        if objective <= 0.0 || objective >= 1.0 {
+            return Err(format!(
+                "latency_objective must be between 0 and 1 (exclusive), got {}",
+                objective
+            )
+            .into());
+        }
+        Ok(Self {
+            budget_ns,
+            objective,
+            acked: 0,
+            violations: 0,
+            window_acked: 0,
+            window_violations: 0,
+        })
+    }
+
+    fn record(&mut self, latency_ns: u64) {
+        self.acked += 1;
+        self.window_acked += 1;
+        if latency_ns > self.budget_ns {
+            self.violations += 1;
+            self.window_violations += 1;
+        }
+    }
+
+    /// Rate at which the error budget was consumed since the last report,
+    /// `1.0` means the budget is used up exactly at the end of the SLO period
+    #[allow(clippy::cast_precision_loss)]
+    fn burn_rate(&self) -> f64 {
+        if self.window_acked == 0 {
+            0.0
+        } else {
+            (self.window_violations as f64 / self.window_acked as f64) / (1.0 - self.objective)
+        }
+    }
+
+    /// Reports the SLO metrics since the last report and starts a new window
+    fn to_value(&mut self, pipeline: &str, timestamp: u64) -> Value<'static> {
+        let value = literal!({
+            "measurement": "latency_budget",
+            "tags": {
+                "pipeline": pipeline.to_string()
+            },
+            "fields": {
+                "budget_ns": self.budget_ns,
+                "acked": self.window_acked,
+                "violations": self.window_violations,
+                "acked_total": self.acked,
+                "violations_total": self.violations,
+                "burn_rate": self.burn_rate()
+            },
+            "timestamp": timestamp
+        });
+        self.window_acked = 0;
+        self.window_violations = 0;
+        value
+    }
+}
+
 /// An executable graph, this is the executable
 /// form of a pipeline
 #[derive(Debug)]
@@ -266,7 +348,11 @@ pub struct ExecutableGraph {
     pub(crate) metrics: Vec<NodeMetrics>,
     pub(crate) metrics_idx: usize,
     pub(crate) last_metrics: u64,
+    /// source, stream and event id of the last event each node received
+    pub(crate) last_event_ids: Vec<Option<(u64, u64, u64)>>,
     pub(crate) metric_interval: Option<u64>,
+    pub(crate) latency_budget: Option<LatencyBudget>,
+    pub(crate) recorder: Option<Recorder>,
     /// snot
     pub insights: Vec<(usize, Event)>,
     /// source code of the pipeline
@@ -278,6 +364,29 @@ pub struct ExecutableGraph {
 /// The return of a graph execution
 pub type Returns = Vec<(Cow<'static, str>, Event)>;
 impl ExecutableGraph {
+    /// The id of the last event each operator received, by operator id
+    #[must_use]
+    pub fn last_event_ids(&self) -> Vec<(String, String)> {
+        self.graph
+            .iter()
+            .zip(&self.last_event_ids)
+            .filter_map(|(node, last)| {
+                last.map(|(source, stream, event)| {
+                    (
+                        node.id.to_string(),
+                        format!("{}:{}:{}", source, stream, event),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// The steps recorded for time-travel debugging, if the pipeline records
+    #[must_use]
+    pub fn recording(&self) -> Option<Recording> {
+        self.recorder.as_ref().map(|r| r.recording(&self.id))
+    }
+
     /// Tries to optimise a pipeline
     pub fn optimize(&mut self) -> Option<()> {
         let mut i = 0;
@@ -444,6 +553,7 @@ impl ExecutableGraph {
             let mut tags = HashMap::with_capacity(8);
             tags.insert("pipeline".into(), common_cow(&self.id).into());
             self.enqueue_metrics("events", tags, event.ingest_ns);
+            self.enqueue_latency_budget(event.ingest_ns);
             self.last_metrics = event.ingest_ns;
         }
         let input = *stry!(self.inputs.get(stream_name).ok_or_else(|| {
@@ -476,10 +586,25 @@ impl ExecutableGraph {
                 if let NodeKind::Output(port) = &node.kind {
                     returns.push((port.clone(), event));
                 } else {
+                    if let Some(last) = self.last_event_ids.get_mut(idx) {
+                        let id = &event.id;
+                        *last = Some((id.source_id, id.stream_id, id.event_id));
+                    }
+                    let recorded = self.recorder.as_ref().map(|_| recorded_event(&event));
                     // ALLOW: We know the state was initiated
                     let state = unsafe { self.state.ops.get_unchecked_mut(idx) };
                     let EventAndInsights { events, insights } =
                         stry!(node.on_event(0, &port, state, event));
+                    if let (Some(recorder), Some(recorded)) = (&mut self.recorder, recorded) {
+                        recorder.record(
+                            nanotime(),
+                            &node.id,
+                            &port,
+                            recorded,
+                            state.clone(),
+                            node.snapshot(),
+                        );
+                    }
 
                     for (out_port, _) in &events {
                         unsafe { self.metrics.get_unchecked_mut(idx) }.inc_output(out_port);
@@ -538,6 +663,22 @@ impl ExecutableGraph {
             }
         }
     }
+    fn enqueue_latency_budget(&mut self, ingest_ns: u64) {
+        if let Some(budget) = &mut self.latency_budget {
+            let value = budget.to_value(&self.id, ingest_ns);
+            self.stack.push((
+                self.metrics_idx,
+                IN,
+                Event {
+                    data: value.into(),
+                    ingest_ns,
+                    origin_uri: None,
+                    ..Event::default()
+                },
+            ));
+        }
+    }
+
     #[inline]
     fn enqueue_events(&mut self, idx: usize, events: Vec<(Cow<'static, str>, Event)>) {
         for (out_port, event) in events {
@@ -558,6 +699,12 @@ impl ExecutableGraph {
     }
     /// Enque a contraflow insight
     pub fn contraflow(&mut self, mut skip_to: Option<usize>, mut insight: Event) -> Event {
+        // only acks from downstream count against the latency budget, not
+        // insights created by our own operators
+        if let (Some(budget), None, CbAction::Ack) = (&mut self.latency_budget, skip_to, insight.cb)
+        {
+            budget.record(nanotime().saturating_sub(insight.ingest_ns));
+        }
         for idx in &self.contraflow {
             if skip_to.is_none() {
                 let op = unsafe { self.graph.get_unchecked_mut(*idx) }; // We know this exists
@@ -838,7 +985,10 @@ mod test {
             // The index of the metrics node in our pipeline
             metrics_idx: 4,
             last_metrics: 0,
+            last_event_ids: vec![None; 6],
             metric_interval: Some(1),
+            latency_budget: None,
+            recorder: None,
             insights: vec![],
             source: None,
             dot: String::from(""),
@@ -940,7 +1090,10 @@ mod test {
             // The index of the metrics node in our pipeline
             metrics_idx: 5,
             last_metrics: 0,
+            last_event_ids: vec![None; 6],
             metric_interval: Some(1),
+            latency_budget: None,
+            recorder: None,
             insights: vec![],
             source: None,
             dot: String::from(""),
@@ -967,4 +1120,30 @@ mod test {
             Some(&vec![(4usize, IN)])
         );
     }
+
+    #[test]
+    fn latency_budget() -> Result<()> {
+        assert!(LatencyBudget::new(1_000, 1.0).is_err());
+        assert!(LatencyBudget::new(1_000, 0.0).is_err());
+        let mut budget = LatencyBudget::new(1_000, 0.9)?;
+        assert!(budget.burn_rate().abs() < f64::EPSILON);
+        budget.record(10);
+        budget.record(1_000);
+        budget.record(1_001);
+        budget.record(5);
+        // 25% violations against an allowed 10%
+        assert!((budget.burn_rate() - 2.5).abs() < f64::EPSILON * 10.0);
+        let v = budget.to_value("pipe", 42);
+        assert_eq!(v["measurement"], "latency_budget");
+        assert_eq!(v["tags"]["pipeline"], "pipe");
+        assert_eq!(v["fields"]["acked"], 4);
+        assert_eq!(v["fields"]["violations"], 1);
+        assert_eq!(v["timestamp"], 42);
+        budget.record(2_000);
+        let v = budget.to_value("pipe", 43);
+        assert_eq!(v["fields"]["acked"], 1);
+        assert_eq!(v["fields"]["acked_total"], 5);
+        assert_eq!(v["fields"]["violations_total"], 2);
+        Ok(())
+    }
 }
@@ -70,6 +70,8 @@ const TIMESTAMP: Cow<'static, str> = Cow::const_str("timestamp");
 
 /// Tools to turn tremor query into pipelines
 pub mod query;
+/// Recording of operator state for time-travel debugging
+pub mod recording;
 pub use crate::event::{Event, ValueIter, ValueMetaIter};
 pub use crate::executable_graph::{ExecutableGraph, OperatorNode};
 pub(crate) use crate::executable_graph::{NodeMetrics, State};
@@ -696,26 +698,40 @@ pub enum SignalKind {
 fn factory(node: &NodeConfig) -> Result<Box<dyn InitializableOperator>> {
     #[cfg(feature = "bert")]
     use op::bert::{SequenceClassificationFactory, SummerizationFactory};
-    use op::debug::EventHistoryFactory;
-    use op::generic::{BatchFactory, CounterFactory};
+    use op::debug::{EventHistoryFactory, SequenceFactory};
+    use op::generic::{
+        BatchFactory, CacheFactory, CoerceFactory, CounterFactory, DelayFactory, EscalationFactory,
+        FlattenFactory, GateFactory, ThrottleFactory, UnflattenFactory,
+    };
     use op::grouper::BucketGrouperFactory;
     use op::identity::PassthroughFactory;
     use op::qos::{BackpressureFactory, PercentileFactory, RoundRobinFactory, WalFactory};
+    use op::schema::RegistryFactory;
     let name_parts: Vec<&str> = node.op_type.split("::").collect();
     let factory = match name_parts.as_slice() {
         ["passthrough"] => PassthroughFactory::new_boxed(),
         ["debug", "history"] => EventHistoryFactory::new_boxed(),
+        ["debug", "sequence"] => SequenceFactory::new_boxed(),
         ["grouper", "bucket"] => BucketGrouperFactory::new_boxed(),
         ["generic", "batch"] => BatchFactory::new_boxed(),
         ["generic", "backpressure"] => {
             error!("The generic::backpressure operator is depricated, please use qos::backpressure instread.");
             BackpressureFactory::new_boxed()
         }
+        ["generic", "coerce"] => CoerceFactory::new_boxed(),
         ["generic", "counter"] => CounterFactory::new_boxed(),
+        ["generic", "flatten"] => FlattenFactory::new_boxed(),
+        ["generic", "gate"] => GateFactory::new_boxed(),
+        ["generic", "cache"] => CacheFactory::new_boxed(),
+        ["generic", "delay"] => DelayFactory::new_boxed(),
+        ["generic", "escalation"] => EscalationFactory::new_boxed(),
+        ["generic", "throttle"] => ThrottleFactory::new_boxed(),
+        ["generic", "unflatten"] => UnflattenFactory::new_boxed(),
         ["qos", "backpressure"] => BackpressureFactory::new_boxed(),
         ["qos", "roundrobin"] => RoundRobinFactory::new_boxed(),
         ["qos", "wal"] => WalFactory::new_boxed(),
         ["qos", "percentile"] => PercentileFactory::new_boxed(),
+        ["schema", "registry"] => RegistryFactory::new_boxed(),
         #[cfg(feature = "bert")]
         ["bert", "sequence_classification"] => SequenceClassificationFactory::new_boxed(),
         #[cfg(feature = "bert")]
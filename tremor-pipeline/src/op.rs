@@ -20,6 +20,7 @@ pub mod grouper;
 pub mod identity;
 pub mod prelude;
 pub mod qos;
+pub mod schema;
 pub mod trickle;
 
 use self::prelude::OUT;
@@ -29,6 +30,7 @@ use beef::Cow;
 use halfbrown::HashMap;
 use regex::Regex;
 use tremor_script::Value;
+use value_trait::ValueAccess;
 
 lazy_static::lazy_static! {
     static ref LINE_REGEXP: Regex = {
@@ -140,6 +142,11 @@ pub trait Operator: std::fmt::Debug + Send {
     fn skippable(&self) -> bool {
         false
     }
+
+    /// The internal state of the operator for recordings, defaults to none.
+    fn snapshot(&self) -> Option<Value<'static>> {
+        None
+    }
 }
 
 /// Initialisable trait that can be turned from a `NodeConfig`
@@ -177,6 +184,21 @@ pub trait ConfigImpl {
     }
 }
 
+/// Looks up a dotted path in an event, `meta.` prefixed paths are looked up
+/// in the event metadata
+pub(crate) fn lookup<'v, 'value>(
+    value: &'v Value<'value>,
+    meta: &'v Value<'value>,
+    path: &str,
+) -> Option<&'v Value<'value>> {
+    let mut segments = path.split('.');
+    let (start, first) = match segments.next()? {
+        "meta" => (meta, segments.next()?),
+        first => (value, first),
+    };
+    segments.try_fold(start.get(first)?, |v, s| v.get(s))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -12,5 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 pub mod history;
+pub mod sequence;
 
 pub use history::EventHistoryFactory;
+pub use sequence::SequenceFactory;
@@ -0,0 +1,323 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Sequence number gap detection
+//!
+//! Tracks a monotonically increasing sequence number per key and reports
+//! gaps, duplicates and out of order events. All events are forwarded to
+//! `out` unchanged, the reports are emitted as separate events on the
+//! `diagnostics` port.
+//!
+//! ## Configuration
+//!
+//! * `field` - path of the sequence number in the event, e.g. `meta.seq`
+//! * `key` - path of the key to track sequences for, all events share one
+//!   sequence if not set
+//! * `cardinality` - maximum number of keys tracked, the least recently seen
+//!   key is forgotten first (default: 1000)
+//!
+//! ```yaml
+//! - id: seq
+//!   op: debug::sequence
+//!   config:
+//!     field: seq
+//!     key: partition
+//! ```
+//!
+//! ## Diagnostics
+//!
+//! ```json
+//! {"kind": "gap", "key": 1, "expected": 4, "received": 7, "missing": 3}
+//! {"kind": "duplicate", "key": 1, "expected": 8, "received": 7}
+//! {"kind": "out_of_order", "key": 1, "expected": 8, "received": 5}
+//! ```
+//!
+//! Events without a sequence number are forwarded without a report.
+
+use crate::op::prelude::*;
+use crate::{ConfigImpl, EventIdGenerator};
+use lru::LruCache;
+use tremor_script::prelude::*;
+
+const DIAGNOSTICS: Cow<'static, str> = Cow::const_str("diagnostics");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Path of the sequence number
+    pub field: String,
+    /// Path of the key to track sequences for
+    #[serde(default = "Default::default")]
+    pub key: Option<String>,
+    /// Maximum number of keys tracked
+    #[serde(default = "d_cardinality")]
+    pub cardinality: usize,
+}
+
+fn d_cardinality() -> usize {
+    1000
+}
+
+impl ConfigImpl for Config {}
+
+op!(SequenceFactory(uid, node) {
+if let Some(map) = &node.config {
+    let config: Config = Config::new(map)?;
+    Ok(Box::new(Sequence {
+        last: LruCache::new(config.cardinality),
+        config,
+        id: node.id.clone(),
+        event_id_gen: EventIdGenerator::new(uid),
+    }))
+} else {
+    Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+
+}});
+
+pub struct Sequence {
+    pub config: Config,
+    pub id: Cow<'static, str>,
+    /// highest sequence number seen per encoded key
+    last: LruCache<String, u64>,
+    event_id_gen: EventIdGenerator,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Sequence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Sequence({})", self.id)
+    }
+}
+
+impl Operator for Sequence {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<EventAndInsights> {
+        let data = event.data.borrow_dependent();
+        let (value, meta) = (data.value(), data.meta());
+        let received = if let Some(received) =
+            lookup(value, meta, &self.config.field).and_then(ValueTrait::as_u64)
+        {
+            received
+        } else {
+            return Ok(event.into());
+        };
+        let key: Value<'static> = self
+            .config
+            .key
+            .as_ref()
+            .and_then(|k| lookup(value, meta, k))
+            .map_or_else(Value::null, Value::clone_static);
+        let encoded = key.encode();
+
+        let report = match self.last.get_mut(&encoded) {
+            None => {
+                self.last.put(encoded, received);
+                None
+            }
+            Some(last) => {
+                let expected = last.saturating_add(1);
+                if received == expected {
+                    *last = received;
+                    None
+                } else if received > expected {
+                    *last = received;
+                    Some(literal!({
+                        "kind": "gap",
+                        "key": key,
+                        "expected": expected,
+                        "received": received,
+                        "missing": received - expected
+                    }))
+                } else if received == *last {
+                    Some(literal!({
+                        "kind": "duplicate",
+                        "key": key,
+                        "expected": expected,
+                        "received": received
+                    }))
+                } else {
+                    Some(literal!({
+                        "kind": "out_of_order",
+                        "key": key,
+                        "expected": expected,
+                        "received": received
+                    }))
+                }
+            }
+        };
+
+        if let Some(report) = report {
+            let diagnostic = Event {
+                id: self.event_id_gen.next_id(),
+                ingest_ns: event.ingest_ns,
+                data: (report, Value::object()).into(),
+                ..Event::default()
+            };
+            Ok(vec![(OUT, event), (DIAGNOSTICS, diagnostic)].into())
+        } else {
+            Ok(event.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::EventId;
+
+    #[test]
+    fn sequence() {
+        let config = Config {
+            field: "seq".to_string(),
+            key: None,
+            cardinality: 10,
+        };
+        let mut op = Sequence {
+            last: LruCache::new(config.cardinality),
+            config,
+            id: "badger".into(),
+            event_id_gen: EventIdGenerator::new(0),
+        };
+        let mut state = Value::null();
+
+        let mut kinds = Vec::new();
+        for (i, value) in vec![
+            literal!({"seq": 1}),
+            literal!({"seq": 2}),
+            literal!({"seq": 5}),
+            literal!({"seq": 5}),
+            literal!({"seq": 3}),
+            literal!({"seq": 6}),
+            // events without a sequence number pass
+            literal!({"snot": "badger"}),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let event = Event {
+                id: EventId::new(0, 0, i as u64),
+                ingest_ns: 1,
+                data: value.into(),
+                ..Event::default()
+            };
+            let r = op
+                .on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+            assert_eq!("out", r.events[0].0);
+            for (port, diagnostic) in r.events.iter().skip(1) {
+                assert_eq!("diagnostics", port);
+                let value = diagnostic.data.borrow_dependent().value();
+                kinds.push((i, value.get_str("kind").unwrap_or_default().to_string()));
+            }
+        }
+        assert_eq!(
+            vec![
+                (2, "gap".to_string()),
+                (3, "duplicate".to_string()),
+                (4, "out_of_order".to_string())
+            ],
+            kinds
+        );
+    }
+
+    #[test]
+    fn gap_report() {
+        let config = Config {
+            field: "seq".to_string(),
+            key: None,
+            cardinality: 10,
+        };
+        let mut op = Sequence {
+            last: LruCache::new(config.cardinality),
+            config,
+            id: "badger".into(),
+            event_id_gen: EventIdGenerator::new(0),
+        };
+        let mut state = Value::null();
+
+        let event = Event {
+            id: EventId::new(0, 0, 1),
+            ingest_ns: 1,
+            data: literal!({"seq": 3}).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+
+        let event = Event {
+            id: EventId::new(0, 0, 2),
+            ingest_ns: 1,
+            data: literal!({"seq": 7}).into(),
+            ..Event::default()
+        };
+        let mut r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 2);
+        let (port, diagnostic) = r.events.pop().expect("no results");
+        assert_eq!("diagnostics", port);
+        assert_eq!(
+            &literal!({
+                "kind": "gap",
+                "key": null,
+                "expected": 4,
+                "received": 7,
+                "missing": 3
+            }),
+            diagnostic.data.borrow_dependent().value()
+        );
+    }
+
+    #[test]
+    fn per_key() {
+        let config = Config {
+            field: "seq".to_string(),
+            key: Some("meta.partition".to_string()),
+            cardinality: 10,
+        };
+        let mut op = Sequence {
+            last: LruCache::new(config.cardinality),
+            config,
+            id: "badger".into(),
+            event_id_gen: EventIdGenerator::new(0),
+        };
+        let mut state = Value::null();
+
+        let mut diagnostics = 0;
+        for (seq, partition) in &[(1, 1), (1, 2), (2, 1), (2, 2), (4, 1), (3, 2)] {
+            let event = Event {
+                id: EventId::new(0, 0, *seq),
+                ingest_ns: 1,
+                data: (
+                    literal!({ "seq": *seq }),
+                    literal!({ "partition": *partition }),
+                )
+                    .into(),
+                ..Event::default()
+            };
+            let r = op
+                .on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+            diagnostics += r.len() - 1;
+        }
+        // only the gap in partition 1
+        assert_eq!(1, diagnostics);
+    }
+}
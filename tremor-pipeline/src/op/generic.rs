@@ -13,7 +13,21 @@
 // limitations under the License.
 
 pub mod batch;
+pub mod cache;
+pub mod coerce;
 pub mod counter;
+pub mod delay;
+pub mod escalation;
+pub mod flatten;
+pub mod gate;
+pub mod throttle;
 
 pub use batch::BatchFactory;
+pub use cache::CacheFactory;
+pub use coerce::CoerceFactory;
 pub use counter::CounterFactory;
+pub use delay::DelayFactory;
+pub use escalation::EscalationFactory;
+pub use flatten::{FlattenFactory, UnflattenFactory};
+pub use gate::GateFactory;
+pub use throttle::ThrottleFactory;
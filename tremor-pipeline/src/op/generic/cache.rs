@@ -0,0 +1,449 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Response cache
+//!
+//! Caches responses of linked request/response flows, e.g. a linked `rest`
+//! onramp enriching requests by calling out to a linked `rest` offramp, so
+//! repeated requests are answered without calling out again.
+//!
+//! * Requests arrive on `in`. Their cache key is built from the values at
+//!   the dotted `key` paths, `meta.` prefixed paths are looked up in the
+//!   event metadata. On a hit the cached response is sent to the `response`
+//!   port in place of the request, carrying the request's event id and
+//!   `$correlation`. On a miss the request is forwarded to `out`.
+//! * Responses to forwarded requests arrive on the `response` port. They are
+//!   matched to their request by event id, cached under its key and
+//!   forwarded to `response`.
+//!
+//! Responses are cached for `ttl` seconds, at most `max_entries` of them, the
+//! least recently used are evicted first. Hits and misses are reported as
+//! `cache_hits` and `cache_misses` metrics.
+//!
+//! ```yaml
+//! - id: cache
+//!   op: generic::cache
+//!   config:
+//!     key:
+//!       - meta.request.method
+//!       - meta.request.url.path
+//!     ttl: 300
+//!     max_entries: 10000
+//! ```
+
+use crate::op::prelude::*;
+use crate::{influx_value, ConfigImpl, EventId};
+use lru::LruCache;
+use std::collections::VecDeque;
+use tremor_script::prelude::*;
+
+const RESPONSE: Cow<'static, str> = Cow::const_str("response");
+const CACHE_HITS: Cow<'static, str> = Cow::const_str("cache_hits");
+const CACHE_MISSES: Cow<'static, str> = Cow::const_str("cache_misses");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Paths of the values the cache key is built from
+    pub key: Vec<String>,
+    /// Seconds a response is served from the cache
+    #[serde(default = "d_ttl")]
+    pub ttl: u64,
+    /// Maximum number of cached responses
+    #[serde(default = "d_max_entries")]
+    pub max_entries: usize,
+}
+
+fn d_ttl() -> u64 {
+    60
+}
+
+fn d_max_entries() -> usize {
+    1000
+}
+
+impl ConfigImpl for Config {}
+
+op!(CacheFactory(_uid, node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        if config.key.is_empty() {
+            return Err(ErrorKind::BadOpConfig("The cache `key` must not be empty".to_string()).into());
+        }
+        Ok(Box::new(Cache::new(config)))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+/// A cached response
+#[derive(Debug)]
+struct Entry {
+    value: Value<'static>,
+    meta: Value<'static>,
+    stored_ns: u64,
+}
+
+/// A forwarded request waiting for its response
+#[derive(Debug)]
+struct Pending {
+    id: EventId,
+    key: String,
+}
+
+pub struct Cache {
+    pub config: Config,
+    entries: LruCache<String, Entry>,
+    /// forwarded requests, oldest first
+    pending: VecDeque<Pending>,
+    hits: u64,
+    misses: u64,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Cache({} entries)", self.entries.len())
+    }
+}
+
+impl Cache {
+    fn new(config: Config) -> Self {
+        Self {
+            entries: LruCache::new(config.max_entries),
+            pending: VecDeque::new(),
+            config,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// the encoded cache key of a request
+    fn key(&self, event: &Event) -> String {
+        let data = event.data.borrow_dependent();
+        let (value, meta) = (data.value(), data.meta());
+        let key: Value = self
+            .config
+            .key
+            .iter()
+            .map(|path| lookup(value, meta, path).map_or_else(Value::null, Value::clone_static))
+            .collect();
+        key.encode()
+    }
+
+    /// the cached response for `key`, unless it expired
+    fn get(&mut self, key: &str, now_ns: u64) -> Option<&Entry> {
+        let ttl_ns = self.config.ttl.saturating_mul(1_000_000_000);
+        let expired = self
+            .entries
+            .peek(key)
+            .map(|entry| now_ns.saturating_sub(entry.stored_ns) >= ttl_ns)?;
+        if expired {
+            self.entries.pop(key);
+            None
+        } else {
+            self.entries.get(key)
+        }
+    }
+
+    fn on_request(&mut self, event: Event) -> EventAndInsights {
+        let key = self.key(&event);
+        let hit = self.get(&key, event.ingest_ns).map(|entry| {
+            let mut meta = entry.meta.clone();
+            if let Some(correlation) = event.correlation_meta() {
+                meta.try_insert("correlation", correlation);
+            }
+            (entry.value.clone(), meta)
+        });
+        if let Some(data) = hit {
+            self.hits += 1;
+            let response = Event {
+                data: data.into(),
+                is_batch: false,
+                ..event
+            };
+            vec![(RESPONSE, response)].into()
+        } else {
+            self.misses += 1;
+            if self.pending.len() >= self.config.max_entries {
+                self.pending.pop_front();
+            }
+            self.pending.push_back(Pending {
+                id: event.id.clone(),
+                key,
+            });
+            event.into()
+        }
+    }
+
+    fn on_response(&mut self, event: Event) -> EventAndInsights {
+        let request = self
+            .pending
+            .iter()
+            .position(|p| event.id.is_tracking(&p.id))
+            .and_then(|i| self.pending.remove(i));
+        if let Some(Pending { key, .. }) = request {
+            let data = event.data.borrow_dependent();
+            let mut meta = data.meta().clone_static();
+            // the correlation belongs to the request, not the response
+            if let Some(meta) = meta.as_object_mut() {
+                meta.remove("correlation");
+            }
+            self.entries.put(
+                key,
+                Entry {
+                    value: data.value().clone_static(),
+                    meta,
+                    stored_ns: event.ingest_ns,
+                },
+            );
+        }
+        vec![(RESPONSE, event)].into()
+    }
+}
+
+impl Operator for Cache {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        port: &str,
+        _state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<EventAndInsights> {
+        if port.eq_ignore_ascii_case(RESPONSE.as_ref()) {
+            Ok(self.on_response(event))
+        } else {
+            Ok(self.on_request(event))
+        }
+    }
+
+    fn metrics(
+        &self,
+        tags: &HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        Ok(vec![
+            influx_value(CACHE_HITS, tags.clone(), self.hits, timestamp),
+            influx_value(CACHE_MISSES, tags.clone(), self.misses, timestamp),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    #[test]
+    fn hit_and_miss() {
+        let mut op = Cache::new(Config {
+            key: vec!["meta.path".to_string()],
+            ttl: 60,
+            max_entries: 10,
+        });
+        let mut state = Value::null();
+
+        let request = Event {
+            id: EventId::new(1, 0, 1),
+            data: (Value::null(), literal!({"path": "/a", "correlation": 1})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, request.clone())
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        assert_eq!("out", r.events[0].0);
+
+        let mut id = EventId::new(2, 0, 1);
+        id.track(&request.id);
+        let response = Event {
+            id,
+            data: (
+                Value::from("a"),
+                literal!({"response": {"status": 200}, "correlation": 1}),
+            )
+                .into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "response", &mut state, response)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        assert_eq!("response", r.events[0].0);
+
+        let request = Event {
+            id: EventId::new(1, 0, 2),
+            ingest_ns: 1,
+            data: (Value::null(), literal!({"path": "/a", "correlation": 2})).into(),
+            ..Event::default()
+        };
+        let mut r = op
+            .on_event(0, "in", &mut state, request)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        let (port, hit) = r.events.pop().expect("no results");
+        assert_eq!("response", port);
+        assert_eq!(EventId::new(1, 0, 2), hit.id);
+        let data = hit.data.borrow_dependent();
+        assert_eq!(&Value::from("a"), data.value());
+        assert_eq!(
+            &literal!({"response": {"status": 200}, "correlation": 2}),
+            data.meta()
+        );
+
+        let request = Event {
+            id: EventId::new(1, 0, 3),
+            ingest_ns: 2,
+            data: (Value::null(), literal!({"path": "/b", "correlation": 3})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, request)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        assert_eq!("out", r.events[0].0);
+        assert_eq!((1, 2), (op.hits, op.misses));
+    }
+
+    #[test]
+    fn ttl() {
+        let mut op = Cache::new(Config {
+            key: vec!["meta.path".to_string()],
+            ttl: 1,
+            max_entries: 10,
+        });
+        let mut state = Value::null();
+
+        let request = Event {
+            id: EventId::new(1, 0, 1),
+            data: (Value::null(), literal!({"path": "/a"})).into(),
+            ..Event::default()
+        };
+        op.on_event(0, "in", &mut state, request.clone())
+            .expect("could not run pipeline");
+        let mut id = EventId::new(2, 0, 1);
+        id.track(&request.id);
+        let response = Event {
+            id,
+            data: (Value::from("a"), literal!({"response": {"status": 200}})).into(),
+            ..Event::default()
+        };
+        op.on_event(0, "response", &mut state, response)
+            .expect("could not run pipeline");
+
+        let request = Event {
+            id: EventId::new(1, 0, 2),
+            ingest_ns: 999_999_999,
+            data: (Value::null(), literal!({"path": "/a"})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, request)
+            .expect("could not run pipeline");
+        assert_eq!("response", r.events[0].0);
+
+        let request = Event {
+            id: EventId::new(1, 0, 3),
+            ingest_ns: 1_000_000_000,
+            data: (Value::null(), literal!({"path": "/a"})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, request)
+            .expect("could not run pipeline");
+        assert_eq!("out", r.events[0].0);
+    }
+
+    #[test]
+    fn max_entries() {
+        let mut op = Cache::new(Config {
+            key: vec!["meta.path".to_string()],
+            ttl: 60,
+            max_entries: 2,
+        });
+        let mut state = Value::null();
+
+        for (i, path) in ["/a", "/b", "/c"].iter().enumerate() {
+            let request = Event {
+                id: EventId::new(1, 0, i as u64),
+                data: (Value::null(), literal!({ "path": path.to_string() })).into(),
+                ..Event::default()
+            };
+            op.on_event(0, "in", &mut state, request.clone())
+                .expect("could not run pipeline");
+            let mut id = EventId::new(2, 0, i as u64);
+            id.track(&request.id);
+            let response = Event {
+                id,
+                data: (Value::from(*path), literal!({"response": {"status": 200}})).into(),
+                ..Event::default()
+            };
+            op.on_event(0, "response", &mut state, response)
+                .expect("could not run pipeline");
+        }
+
+        // the least recently used entry was evicted
+        let request = Event {
+            id: EventId::new(1, 0, 3),
+            data: (Value::null(), literal!({"path": "/a"})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, request)
+            .expect("could not run pipeline");
+        assert_eq!("out", r.events[0].0);
+
+        let request = Event {
+            id: EventId::new(1, 0, 4),
+            data: (Value::null(), literal!({"path": "/c"})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, request)
+            .expect("could not run pipeline");
+        assert_eq!("response", r.events[0].0);
+    }
+
+    #[test]
+    fn unmatched_response() {
+        let mut op = Cache::new(Config {
+            key: vec!["meta.path".to_string()],
+            ttl: 60,
+            max_entries: 10,
+        });
+        let mut state = Value::null();
+
+        let mut id = EventId::new(2, 0, 1);
+        id.track(&EventId::new(1, 0, 1));
+        let response = Event {
+            id,
+            data: (Value::from("a"), literal!({"response": {"status": 200}})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "response", &mut state, response)
+            .expect("could not run pipeline");
+        assert_eq!("response", r.events[0].0);
+
+        // nothing was cached
+        let request = Event {
+            id: EventId::new(1, 0, 2),
+            data: (Value::null(), literal!({"path": "/a"})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, request)
+            .expect("could not run pipeline");
+        assert_eq!("out", r.events[0].0);
+    }
+}
@@ -0,0 +1,461 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Field type coercion
+//!
+//! Coerces fields to declared types, for sources sending a field as a number
+//! one time and as a string the next. Fields are given by their dotted path,
+//! `meta.` prefixed paths refer to the event metadata.
+//!
+//! * `string` - numbers and booleans are formatted
+//! * `integer` - from integral floats and integer strings
+//! * `float` - from integers and numeric strings
+//! * `bool` - from `true`/`false`, `yes`/`no`, `on`/`off` and `1`/`0`, as
+//!   strings or integers
+//! * `timestamp` - nanoseconds since epoch, from RFC 3339 strings and from
+//!   epoch seconds, milliseconds, microseconds or nanoseconds, picked by
+//!   magnitude, as numbers or numeric strings
+//!
+//! Missing and `null` fields are left alone. Events with a field that can't
+//! be coerced are sent to the `uncoercible` port with the paths of those
+//! fields in `$coerce.failed`, their other fields are coerced. Failures are
+//! counted per field and reported as `coercion_failures` metrics.
+//!
+//! ```yaml
+//! - id: types
+//!   op: generic::coerce
+//!   config:
+//!     fields:
+//!       status: integer
+//!       request.duration: float
+//!       success: bool
+//!       timestamp: timestamp
+//!       meta.host: string
+//! ```
+
+use crate::op::prelude::*;
+use crate::{influx_value, ConfigImpl};
+use std::collections::BTreeMap;
+use tremor_script::prelude::*;
+
+const UNCOERCIBLE: Cow<'static, str> = Cow::const_str("uncoercible");
+const COERCION_FAILURES: Cow<'static, str> = Cow::const_str("coercion_failures");
+const FIELD: Cow<'static, str> = Cow::const_str("field");
+
+/// The types fields can be coerced to
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Type {
+    /// a string
+    String,
+    /// a signed or unsigned integer
+    Integer,
+    /// a float
+    Float,
+    /// a boolean
+    Bool,
+    /// nanoseconds since epoch
+    Timestamp,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Types by field path
+    pub fields: BTreeMap<String, Type>,
+}
+
+impl ConfigImpl for Config {}
+
+#[derive(Debug)]
+struct Field {
+    path: String,
+    meta: bool,
+    segments: Vec<String>,
+    ty: Type,
+    failures: u64,
+}
+
+impl Field {
+    fn new(path: &str, ty: Type) -> Result<Self> {
+        let mut segments: Vec<String> = path.split('.').map(ToString::to_string).collect();
+        let meta = segments.len() > 1 && segments.first().map_or(false, |s| s == "meta");
+        if meta {
+            segments.remove(0);
+        }
+        if segments.iter().any(String::is_empty) {
+            return Err(ErrorKind::BadOpConfig(format!("Invalid field path `{}`", path)).into());
+        }
+        Ok(Self {
+            path: path.to_string(),
+            meta,
+            segments,
+            ty,
+            failures: 0,
+        })
+    }
+}
+
+op!(CoerceFactory(_uid, node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        let fields = config
+            .fields
+            .iter()
+            .map(|(path, ty)| Field::new(path, *ty))
+            .collect::<Result<_>>()?;
+        Ok(Box::new(Coerce { fields }))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+#[derive(Debug)]
+pub struct Coerce {
+    fields: Vec<Field>,
+}
+
+fn lookup_mut<'v, 'value>(
+    mut value: &'v mut Value<'value>,
+    segments: &[String],
+) -> Option<&'v mut Value<'value>> {
+    for segment in segments {
+        value = value.get_mut(segment.as_str())?;
+    }
+    Some(value)
+}
+
+/// nanoseconds since epoch from epoch seconds, milliseconds, microseconds
+/// or nanoseconds
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss,
+    clippy::cast_precision_loss
+)]
+fn epoch_ns(epoch: f64) -> Option<u64> {
+    if !epoch.is_finite() || epoch < 0.0 {
+        return None;
+    }
+    let ns = if epoch < 1e11 {
+        epoch * 1e9
+    } else if epoch < 1e14 {
+        epoch * 1e6
+    } else if epoch < 1e17 {
+        epoch * 1e3
+    } else {
+        epoch
+    };
+    if ns < u64::MAX as f64 {
+        Some(ns.round() as u64)
+    } else {
+        None
+    }
+}
+
+/// the value coerced to `ty`, `None` if it can't be coerced
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+fn coerce(value: &Value, ty: Type) -> Option<Value<'static>> {
+    let text = value.as_str().map(str::trim);
+    match ty {
+        Type::String => {
+            if let Some(s) = value.as_str() {
+                Some(Value::from(s.to_string()))
+            } else if value.is_bool() || value.is_number() {
+                Some(Value::from(value.encode()))
+            } else {
+                None
+            }
+        }
+        Type::Integer => {
+            if let Some(i) = value.as_i64() {
+                Some(Value::from(i))
+            } else if let Some(u) = value.as_u64() {
+                Some(Value::from(u))
+            } else if let Some(f) = value.as_f64() {
+                if f.fract().abs() < f64::EPSILON && f >= i64::MIN as f64 && f < i64::MAX as f64 {
+                    Some(Value::from(f as i64))
+                } else {
+                    None
+                }
+            } else if let Some(s) = text {
+                s.parse::<i64>()
+                    .map(Value::from)
+                    .or_else(|_| s.parse::<u64>().map(Value::from))
+                    .ok()
+            } else {
+                None
+            }
+        }
+        Type::Float => {
+            if let Some(f) = value.cast_f64() {
+                Some(Value::from(f))
+            } else {
+                text.and_then(|s| s.parse::<f64>().ok())
+                    .filter(|f| f.is_finite())
+                    .map(Value::from)
+            }
+        }
+        Type::Bool => {
+            if let Some(b) = value.as_bool() {
+                Some(Value::from(b))
+            } else if let Some(i) = value.as_u64() {
+                match i {
+                    0 => Some(Value::from(false)),
+                    1 => Some(Value::from(true)),
+                    _ => None,
+                }
+            } else {
+                match text.map(str::to_lowercase).as_deref() {
+                    Some("true") | Some("yes") | Some("on") | Some("1") => Some(Value::from(true)),
+                    Some("false") | Some("no") | Some("off") | Some("0") => {
+                        Some(Value::from(false))
+                    }
+                    _ => None,
+                }
+            }
+        }
+        Type::Timestamp => {
+            if let Some(u) = value.as_u64() {
+                epoch_ns(u as f64).map(|ns| {
+                    // integer nanoseconds are kept exact
+                    Value::from(if u >= 100_000_000_000_000_000 { u } else { ns })
+                })
+            } else if let Some(f) = value.cast_f64() {
+                epoch_ns(f).map(Value::from)
+            } else if let Some(s) = text {
+                if let Ok(u) = s.parse::<u64>() {
+                    coerce(&Value::from(u), ty)
+                } else if let Ok(f) = s.parse::<f64>() {
+                    epoch_ns(f).map(Value::from)
+                } else {
+                    chrono::DateTime::parse_from_rfc3339(s)
+                        .ok()
+                        .map(|d| d.timestamp_nanos())
+                        .filter(|ns| *ns >= 0)
+                        .map(|ns| Value::from(ns as u64))
+                }
+            } else {
+                None
+            }
+        }
+    }
+}
+
+impl Operator for Coerce {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let fields = &mut self.fields;
+        let failed = event.data.with_dependent_mut(|_, parsed| {
+            let mut failed = Vec::new();
+            for field in fields.iter_mut() {
+                let root = if field.meta {
+                    parsed.meta_mut()
+                } else {
+                    parsed.value_mut()
+                };
+                match lookup_mut(root, &field.segments) {
+                    Some(target) if !target.is_null() => {
+                        if let Some(coerced) = coerce(target, field.ty) {
+                            *target = coerced;
+                        } else {
+                            field.failures += 1;
+                            failed.push(Value::from(field.path.clone()));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            if !failed.is_empty() {
+                if let Some(meta) = parsed.meta_mut().as_object_mut() {
+                    let mut report = Value::object_with_capacity(1);
+                    report.try_insert("failed", failed.clone());
+                    meta.insert("coerce".into(), report);
+                }
+            }
+            failed
+        });
+        if failed.is_empty() {
+            Ok(event.into())
+        } else {
+            Ok(vec![(UNCOERCIBLE, event)].into())
+        }
+    }
+
+    fn metrics(
+        &self,
+        tags: &HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        let mut tags = tags.clone();
+        Ok(self
+            .fields
+            .iter()
+            .map(|field| {
+                tags.insert(FIELD, Value::from(field.path.clone()));
+                influx_value(COERCION_FAILURES, tags.clone(), field.failures, timestamp)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::EventId;
+    use tremor_value::literal;
+
+    #[test]
+    fn coercions() {
+        let c = |v: Value<'static>, ty: Type| coerce(&v, ty);
+        assert_eq!(Some(Value::from("42")), c(Value::from(42), Type::String));
+        assert_eq!(
+            Some(Value::from("true")),
+            c(Value::from(true), Type::String)
+        );
+        assert_eq!(None, c(literal!([1]), Type::String));
+
+        assert_eq!(Some(Value::from(42)), c(Value::from(" 42 "), Type::Integer));
+        assert_eq!(Some(Value::from(-1)), c(Value::from("-1"), Type::Integer));
+        assert_eq!(Some(Value::from(3)), c(Value::from(3.0), Type::Integer));
+        assert_eq!(None, c(Value::from(3.5), Type::Integer));
+        assert_eq!(None, c(Value::from("snot"), Type::Integer));
+
+        assert_eq!(Some(Value::from(1.5)), c(Value::from("1.5"), Type::Float));
+        assert_eq!(Some(Value::from(2.0)), c(Value::from(2), Type::Float));
+        assert_eq!(None, c(Value::from("NaN"), Type::Float));
+
+        assert_eq!(Some(Value::from(true)), c(Value::from("Yes"), Type::Bool));
+        assert_eq!(Some(Value::from(false)), c(Value::from("off"), Type::Bool));
+        assert_eq!(Some(Value::from(true)), c(Value::from(1), Type::Bool));
+        assert_eq!(None, c(Value::from(2), Type::Bool));
+        assert_eq!(None, c(Value::from("maybe"), Type::Bool));
+    }
+
+    #[test]
+    fn timestamps() {
+        let ns = 1_616_417_712_000_000_000_u64;
+        let c = |v: Value<'static>| coerce(&v, Type::Timestamp);
+        assert_eq!(Some(Value::from(ns)), c(Value::from(1_616_417_712)));
+        assert_eq!(Some(Value::from(ns)), c(Value::from(1_616_417_712_000_u64)));
+        assert_eq!(
+            Some(Value::from(ns)),
+            c(Value::from(1_616_417_712_000_000_u64))
+        );
+        assert_eq!(Some(Value::from(ns + 1)), c(Value::from(ns + 1)));
+        assert_eq!(
+            Some(Value::from(ns + 500_000_000)),
+            c(Value::from(1_616_417_712.5))
+        );
+        assert_eq!(Some(Value::from(ns)), c(Value::from("1616417712")));
+        assert_eq!(
+            Some(Value::from(ns)),
+            c(Value::from("2021-03-22T12:55:12Z"))
+        );
+        assert_eq!(
+            Some(Value::from(ns)),
+            c(Value::from("2021-03-22T13:55:12+01:00"))
+        );
+        assert_eq!(None, c(Value::from("yesterday")));
+        assert_eq!(None, c(Value::from(-1)));
+    }
+
+    #[test]
+    fn routing() -> Result<()> {
+        let mut op = Coerce {
+            fields: vec![
+                Field::new("status", Type::Integer)?,
+                Field::new("request.ok", Type::Bool)?,
+                Field::new("meta.host", Type::String)?,
+                Field::new("missing", Type::Float)?,
+            ],
+        };
+        let event = |value: Value<'static>, meta: Value<'static>| Event {
+            id: EventId::new(0, 0, 1),
+            data: (value, meta).into(),
+            ..Event::default()
+        };
+        let mut state = Value::null();
+
+        let mut res = op
+            .on_event(
+                0,
+                "in",
+                &mut state,
+                event(
+                    literal!({"status": "200", "request": {"ok": "true"}}),
+                    literal!({"host": 1}),
+                ),
+            )
+            .map(|r| r.events)?;
+        let (port, event) = res.pop().expect("no event");
+        assert_eq!("out", port);
+        let data = event.data.borrow_dependent();
+        assert_eq!(
+            &literal!({"status": 200, "request": {"ok": true}}),
+            data.value()
+        );
+        assert_eq!(&literal!({"host": "1"}), data.meta());
+
+        let mut res = op
+            .on_event(
+                0,
+                "in",
+                &mut state,
+                event(
+                    literal!({"status": "snot", "request": {"ok": "no"}}),
+                    Value::object(),
+                ),
+            )
+            .map(|r| r.events)?;
+        let (port, event) = res.pop().expect("no event");
+        assert_eq!("uncoercible", port);
+        let data = event.data.borrow_dependent();
+        assert_eq!(
+            &literal!({"status": "snot", "request": {"ok": false}}),
+            data.value()
+        );
+        assert_eq!(&literal!({"coerce": {"failed": ["status"]}}), data.meta());
+
+        let metrics = op.metrics(&HashMap::new(), 0)?;
+        let failures: Vec<(String, u64)> = metrics
+            .iter()
+            .filter_map(|m| {
+                Some((
+                    m.get("tags")?.get_str("field")?.to_string(),
+                    m.get("fields")?.get_u64("count")?,
+                ))
+            })
+            .collect();
+        assert!(failures.contains(&("status".to_string(), 1)));
+        assert!(failures.contains(&("request.ok".to_string(), 0)));
+        Ok(())
+    }
+
+    #[test]
+    fn paths() {
+        assert!(Field::new("a..b", Type::String).is_err());
+        assert!(Field::new("meta.", Type::String).is_err());
+        // a field called meta
+        let field = Field::new("meta", Type::String).expect("valid path");
+        assert!(!field.meta);
+    }
+}
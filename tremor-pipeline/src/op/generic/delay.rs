@@ -0,0 +1,410 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Event delay
+//!
+//! Holds events back before forwarding them to `out`, for delay lines,
+//! honouring retry-after hints or simple debouncing.
+//!
+//! Events are delayed by `delay` milliseconds, or by the milliseconds at the
+//! dotted `delay_field` path if the event has a number there, `meta.`
+//! prefixed paths refer to the event metadata. Delays are capped at
+//! `max_delay`.
+//!
+//! Held events are kept in a scheduling wheel with a slot per `resolution`
+//! milliseconds up to `max_delay`, they are forwarded in order of their due
+//! time once an event or a tick signal arrives past it. At most `max_events`
+//! are held, further events are sent to the `overflow` port right away.
+//! Held events and overflows are reported as `delay_pending` and
+//! `delay_overflow` metrics.
+//!
+//! ```yaml
+//! - id: delay
+//!   op: generic::delay
+//!   config:
+//!     delay: 1000
+//!     delay_field: meta.retry_after_ms
+//!     max_delay: 60000
+//! ```
+
+use crate::op::prelude::*;
+use crate::{influx_value, ConfigImpl};
+use std::mem;
+use tremor_script::prelude::*;
+
+const OVERFLOW: Cow<'static, str> = Cow::const_str("overflow");
+const DELAY_PENDING: Cow<'static, str> = Cow::const_str("delay_pending");
+const DELAY_OVERFLOW: Cow<'static, str> = Cow::const_str("delay_overflow");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Milliseconds events are delayed by
+    #[serde(default = "Default::default")]
+    pub delay: u64,
+    /// Path of a field with the milliseconds to delay an event by
+    #[serde(default = "Default::default")]
+    pub delay_field: Option<String>,
+    /// Maximum delay in milliseconds
+    #[serde(default = "d_max_delay")]
+    pub max_delay: u64,
+    /// Milliseconds covered by a slot of the scheduling wheel
+    #[serde(default = "d_resolution")]
+    pub resolution: u64,
+    /// Maximum number of held events
+    #[serde(default = "d_max_events")]
+    pub max_events: usize,
+}
+
+fn d_max_delay() -> u64 {
+    60_000
+}
+
+fn d_resolution() -> u64 {
+    10
+}
+
+fn d_max_events() -> usize {
+    10_000
+}
+
+impl ConfigImpl for Config {}
+
+op!(DelayFactory(_uid, node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        if config.resolution == 0 {
+            return Err(ErrorKind::BadOpConfig("The delay `resolution` must not be 0".to_string()).into());
+        }
+        if config.delay > config.max_delay {
+            return Err(ErrorKind::BadOpConfig("The `delay` must not exceed `max_delay`".to_string()).into());
+        }
+        Ok(Box::new(Delay::new(config)))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+pub struct Delay {
+    pub config: Config,
+    /// held events, event due at tick `t` are in slot `t % slots.len()`
+    slots: Vec<Vec<Event>>,
+    /// the last tick whose slot was drained
+    tick: u64,
+    resolution_ns: u64,
+    pending: usize,
+    overflow: u64,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Delay {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Delay({} pending)", self.pending)
+    }
+}
+
+impl Delay {
+    fn new(config: Config) -> Self {
+        // delays never reach further than `max_delay`, rounded up, past the
+        // current tick, so the wheel never wraps onto held events
+        let slots = config.max_delay / config.resolution + 2;
+        Self {
+            slots: (0..slots).map(|_| Vec::new()).collect(),
+            tick: 0,
+            resolution_ns: config.resolution.saturating_mul(1_000_000),
+            config,
+            pending: 0,
+            overflow: 0,
+        }
+    }
+
+    /// the delay of an event in milliseconds
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn delay_ms(&self, event: &Event) -> u64 {
+        let data = event.data.borrow_dependent();
+        let delay = self
+            .config
+            .delay_field
+            .as_deref()
+            .and_then(|path| lookup(data.value(), data.meta(), path))
+            .and_then(|v| {
+                v.as_u64()
+                    .or_else(|| v.as_f64().filter(|f| *f >= 0.0).map(|f| f as u64))
+            })
+            .unwrap_or(self.config.delay);
+        delay.min(self.config.max_delay)
+    }
+
+    /// forwards all events due up to `now_ns`
+    fn advance(&mut self, now_ns: u64, out: &mut Vec<(Cow<'static, str>, Event)>) {
+        let now = now_ns / self.resolution_ns;
+        if now <= self.tick {
+            return;
+        }
+        let len = self.slots.len() as u64;
+        let steps = (now - self.tick).min(len);
+        for tick in self.tick + 1..=self.tick + steps {
+            #[allow(clippy::cast_possible_truncation)]
+            let slot = mem::take(&mut self.slots[(tick % len) as usize]);
+            self.pending -= slot.len();
+            out.extend(slot.into_iter().map(|e| (OUT, e)));
+        }
+        self.tick = now;
+    }
+
+    fn schedule(&mut self, event: Event, out: &mut Vec<(Cow<'static, str>, Event)>) {
+        let due_ns = event
+            .ingest_ns
+            .saturating_add(self.delay_ms(&event).saturating_mul(1_000_000));
+        // round up so events are never forwarded early
+        let due = (due_ns + self.resolution_ns - 1) / self.resolution_ns;
+        let len = self.slots.len() as u64;
+        if due_ns <= event.ingest_ns || due <= self.tick {
+            out.push((OUT, event));
+        } else if self.pending >= self.config.max_events {
+            self.overflow += 1;
+            out.push((OVERFLOW, event));
+        } else {
+            // ingest times running behind the wheel are capped to its reach
+            let due = due.min(self.tick + len - 1);
+            #[allow(clippy::cast_possible_truncation)]
+            let slot = (due % len) as usize;
+            self.slots[slot].push(event);
+            self.pending += 1;
+        }
+    }
+}
+
+impl Operator for Delay {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<EventAndInsights> {
+        let mut out = Vec::new();
+        self.advance(event.ingest_ns, &mut out);
+        self.schedule(event, &mut out);
+        Ok(out.into())
+    }
+
+    fn handles_signal(&self) -> bool {
+        true
+    }
+
+    fn on_signal(
+        &mut self,
+        _uid: u64,
+        _state: &Value<'static>,
+        signal: &mut Event,
+    ) -> Result<EventAndInsights> {
+        let mut out = Vec::new();
+        self.advance(signal.ingest_ns, &mut out);
+        Ok(out.into())
+    }
+
+    fn metrics(
+        &self,
+        tags: &HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        Ok(vec![
+            influx_value(DELAY_PENDING, tags.clone(), self.pending as u64, timestamp),
+            influx_value(DELAY_OVERFLOW, tags.clone(), self.overflow, timestamp),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::EventId;
+    use tremor_value::literal;
+
+    #[test]
+    fn fixed_delay() {
+        let mut op = Delay::new(Config {
+            delay: 100,
+            delay_field: None,
+            max_delay: 1000,
+            resolution: 10,
+            max_events: 10,
+        });
+        let mut state = Value::null();
+
+        let event = Event {
+            id: EventId::new(1, 0, 1),
+            ingest_ns: 1_000_000_000,
+            data: Value::from("snot").into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+
+        let mut signal = Event {
+            ingest_ns: 1_099_000_000,
+            ..Event::default()
+        };
+        let r = op
+            .on_signal(0, &state, &mut signal)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+
+        let mut signal = Event {
+            ingest_ns: 1_100_000_000,
+            ..Event::default()
+        };
+        let mut r = op
+            .on_signal(0, &state, &mut signal)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        let (out, event) = r.events.pop().expect("no results");
+        assert_eq!("out", out);
+        assert_eq!(EventId::new(1, 0, 1), event.id);
+        assert_eq!(0, op.pending);
+    }
+
+    #[test]
+    fn field_delay() {
+        let mut op = Delay::new(Config {
+            delay: 500,
+            delay_field: Some("meta.delay".to_string()),
+            max_delay: 1000,
+            resolution: 10,
+            max_events: 10,
+        });
+        let mut state = Value::null();
+
+        // delays are capped at `max_delay`
+        for (id, delay) in &[(1, None), (2, Some(50)), (3, Some(5_000))] {
+            let meta = delay.map_or_else(Value::object, |d: u64| literal!({ "delay": d }));
+            let event = Event {
+                id: EventId::new(1, 0, *id),
+                ingest_ns: 1_000_000_000,
+                data: (Value::from("snot"), meta).into(),
+                ..Event::default()
+            };
+            let r = op
+                .on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+            assert_eq!(r.len(), 0);
+        }
+
+        let event = Event {
+            id: EventId::new(1, 0, 4),
+            ingest_ns: 1_060_000_000,
+            data: (Value::from("snot"), literal!({"delay": 0})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        let ids: Vec<_> = r.events.iter().map(|(_, e)| e.id.event_id()).collect();
+        assert_eq!(vec![2, 4], ids);
+
+        let mut signal = Event {
+            ingest_ns: 1_500_000_000,
+            ..Event::default()
+        };
+        let r = op
+            .on_signal(0, &state, &mut signal)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        assert_eq!(EventId::new(1, 0, 1), r.events[0].1.id);
+
+        let mut signal = Event {
+            ingest_ns: 2_000_000_000,
+            ..Event::default()
+        };
+        let r = op
+            .on_signal(0, &state, &mut signal)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        assert_eq!(EventId::new(1, 0, 3), r.events[0].1.id);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut op = Delay::new(Config {
+            delay: 100,
+            delay_field: None,
+            max_delay: 1000,
+            resolution: 10,
+            max_events: 1,
+        });
+        let mut state = Value::null();
+
+        let event = Event {
+            id: EventId::new(1, 0, 1),
+            ingest_ns: 1_000_000_000,
+            data: Value::from("snot").into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+
+        let event = Event {
+            id: EventId::new(1, 0, 2),
+            ingest_ns: 1_000_000_000,
+            data: Value::from("badger").into(),
+            ..Event::default()
+        };
+        let mut r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        let (out, event) = r.events.pop().expect("no results");
+        assert_eq!("overflow", out);
+        assert_eq!(EventId::new(1, 0, 2), event.id);
+        assert_eq!(1, op.overflow);
+    }
+
+    #[test]
+    fn idle_gap() {
+        let mut op = Delay::new(Config {
+            delay: 100,
+            delay_field: Some("meta.delay".to_string()),
+            max_delay: 1000,
+            resolution: 10,
+            max_events: 10,
+        });
+        let mut state = Value::null();
+
+        for (id, delay) in &[(1, 100), (2, 900)] {
+            let event = Event {
+                id: EventId::new(1, 0, *id),
+                ingest_ns: 1_000_000_000,
+                data: (Value::from("snot"), literal!({ "delay": *delay })).into(),
+                ..Event::default()
+            };
+            op.on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+        }
+
+        // a signal long after the wheel went around releases everything
+        let mut signal = Event {
+            ingest_ns: 1_000_000_000_000,
+            ..Event::default()
+        };
+        let r = op
+            .on_signal(0, &state, &mut signal)
+            .expect("could not run pipeline");
+        let ids: Vec<_> = r.events.iter().map(|(_, e)| e.id.event_id()).collect();
+        assert_eq!(vec![1, 2], ids);
+    }
+}
@@ -0,0 +1,384 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Escalation state machine
+//!
+//! Tracks a state per key, e.g. `ok`, `warn` and `crit`, driven by the
+//! number at the dotted `field` path of the events, and only emits an event
+//! when the state of a key changes, so alerts don't refire on every sample.
+//!
+//! The state of a key starts out as `initial`, a sample at or above the
+//! `threshold` of a level moves it to the highest such level right away.
+//! Samples below the current level only move it down once they stayed below
+//! for `hold_down` milliseconds, to the highest level seen in that time.
+//!
+//! On a change the triggering event is sent on with its data replaced by the
+//! `key`, the states it changed `from` and `to` and the `value`, other events
+//! are dropped. Events without a number at `field` are sent to `err`. The
+//! key is built like for `generic::throttle`, `meta.` prefixed paths are
+//! looked up in the event metadata. At most `max_keys` keys are tracked, the
+//! least recently seen are dropped first. State changes are reported as
+//! `escalation_transitions` metrics.
+//!
+//! ```yaml
+//! - id: escalation
+//!   op: generic::escalation
+//!   config:
+//!     key:
+//!       - host
+//!     field: cpu
+//!     initial: ok
+//!     levels:
+//!       - name: warn
+//!         threshold: 80
+//!       - name: crit
+//!         threshold: 95
+//!     hold_down: 60000
+//! ```
+
+use crate::op::prelude::*;
+use crate::{influx_value, ConfigImpl};
+use lru::LruCache;
+use tremor_script::prelude::*;
+
+const ESCALATION_TRANSITIONS: Cow<'static, str> = Cow::const_str("escalation_transitions");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Level {
+    /// Name of the state
+    pub name: String,
+    /// Samples at or above it enter the state
+    pub threshold: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Paths of the values the key is built from
+    #[serde(default = "Default::default")]
+    pub key: Vec<String>,
+    /// Path of the sampled number
+    pub field: String,
+    /// Name of the state below all levels
+    #[serde(default = "d_initial")]
+    pub initial: String,
+    /// Levels in ascending order of their thresholds
+    pub levels: Vec<Level>,
+    /// Milliseconds samples have to stay below a level to leave it
+    #[serde(default = "Default::default")]
+    pub hold_down: u64,
+    /// Maximum number of tracked keys
+    #[serde(default = "d_max_keys")]
+    pub max_keys: usize,
+}
+
+fn d_initial() -> String {
+    "ok".to_string()
+}
+
+fn d_max_keys() -> usize {
+    10_000
+}
+
+impl ConfigImpl for Config {}
+
+op!(EscalationFactory(_uid, node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        if config.levels.is_empty() {
+            return Err(ErrorKind::BadOpConfig("The escalation `levels` must not be empty".to_string()).into());
+        }
+        if config.levels.windows(2).any(|w| w[0].threshold >= w[1].threshold) {
+            return Err(ErrorKind::BadOpConfig("The escalation `levels` must be in ascending order of their thresholds".to_string()).into());
+        }
+        Ok(Box::new(Escalation::new(config)))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+/// The state of a key
+#[derive(Debug, Default)]
+struct State {
+    /// 0 is `initial`, `n` the `n`th level
+    level: usize,
+    /// since when samples are below `level` and the highest level among them
+    below: Option<(u64, usize)>,
+}
+
+pub struct Escalation {
+    pub config: Config,
+    states: LruCache<String, State>,
+    transitions: u64,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Escalation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Escalation({} keys)", self.states.len())
+    }
+}
+
+impl Escalation {
+    fn new(config: Config) -> Self {
+        Self {
+            states: LruCache::new(config.max_keys),
+            config,
+            transitions: 0,
+        }
+    }
+
+    fn name(&self, level: usize) -> &str {
+        level
+            .checked_sub(1)
+            .map_or(&self.config.initial, |i| &self.config.levels[i].name)
+    }
+
+    /// the level a sample falls into
+    fn level(&self, sample: f64) -> usize {
+        self.config
+            .levels
+            .iter()
+            .take_while(|l| sample >= l.threshold)
+            .count()
+    }
+
+    /// moves the state to the level of the sample, returns the previous and
+    /// the new level on a change
+    fn sample(&mut self, encoded: String, sample: f64, now_ns: u64) -> Option<(usize, usize)> {
+        let level = self.level(sample);
+        let hold_down_ns = self.config.hold_down.saturating_mul(1_000_000);
+        if !self.states.contains(&encoded) {
+            self.states.put(encoded.clone(), State::default());
+        }
+        let state = self.states.get_mut(&encoded)?;
+        let from = state.level;
+        if level > state.level {
+            state.level = level;
+            state.below = None;
+        } else if level == state.level {
+            state.below = None;
+        } else {
+            let (since, highest) = state.below.get_or_insert((now_ns, level));
+            *highest = level.max(*highest);
+            if now_ns.saturating_sub(*since) >= hold_down_ns {
+                state.level = *highest;
+                state.below = None;
+            }
+        }
+        if state.level == from {
+            None
+        } else {
+            Some((from, state.level))
+        }
+    }
+}
+
+impl Operator for Escalation {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<EventAndInsights> {
+        let (key, sample) = {
+            let data = event.data.borrow_dependent();
+            let (value, meta) = (data.value(), data.meta());
+            let key: Value<'static> = self
+                .config
+                .key
+                .iter()
+                .map(|path| lookup(value, meta, path).map_or_else(Value::null, Value::clone_static))
+                .collect();
+            let sample = lookup(value, meta, &self.config.field).and_then(ValueTrait::cast_f64);
+            (key, sample)
+        };
+        let sample = if let Some(sample) = sample {
+            sample
+        } else {
+            return Ok(vec![(ERR, event)].into());
+        };
+        let (from, to) =
+            if let Some(transition) = self.sample(key.encode(), sample, event.ingest_ns) {
+                transition
+            } else {
+                return Ok(EventAndInsights::default());
+            };
+        self.transitions += 1;
+        let data = literal!({
+            "key": key,
+            "from": self.name(from).to_string(),
+            "to": self.name(to).to_string(),
+            "value": sample
+        });
+        let meta = event.data.borrow_dependent().meta().clone_static();
+        let transition = Event {
+            data: (data, meta).into(),
+            ..event
+        };
+        Ok(vec![(OUT, transition)].into())
+    }
+
+    fn metrics(
+        &self,
+        tags: &HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        Ok(vec![influx_value(
+            ESCALATION_TRANSITIONS,
+            tags.clone(),
+            self.transitions,
+            timestamp,
+        )])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::EventId;
+
+    #[test]
+    fn escalate() {
+        let mut op = Escalation::new(Config {
+            key: vec!["host".to_string()],
+            field: "cpu".to_string(),
+            initial: "ok".to_string(),
+            levels: vec![
+                Level {
+                    name: "warn".to_string(),
+                    threshold: 80.0,
+                },
+                Level {
+                    name: "crit".to_string(),
+                    threshold: 95.0,
+                },
+            ],
+            hold_down: 0,
+            max_keys: 10,
+        });
+        let mut state = Value::null();
+
+        let mut transitions = Vec::new();
+        // no refiring while the state holds
+        for (id, cpu) in &[(1, 10.0), (2, 85.0), (3, 90.0), (4, 99.0), (5, 10.0)] {
+            let event = Event {
+                id: EventId::new(1, 0, *id),
+                ingest_ns: *id * 1_000_000,
+                data: literal!({"host": "a", "cpu": *cpu}).into(),
+                ..Event::default()
+            };
+            let r = op
+                .on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+            for (port, event) in r.events {
+                assert_eq!("out", port);
+                let data = event.data.borrow_dependent().value();
+                transitions.push(format!(
+                    "{}->{}",
+                    data.get_str("from").unwrap_or_default(),
+                    data.get_str("to").unwrap_or_default()
+                ));
+            }
+        }
+        assert_eq!(vec!["ok->warn", "warn->crit", "crit->ok"], transitions);
+        assert_eq!(3, op.transitions);
+    }
+
+    #[test]
+    fn hold_down() {
+        let mut op = Escalation::new(Config {
+            key: vec!["host".to_string()],
+            field: "cpu".to_string(),
+            initial: "ok".to_string(),
+            levels: vec![
+                Level {
+                    name: "warn".to_string(),
+                    threshold: 80.0,
+                },
+                Level {
+                    name: "crit".to_string(),
+                    threshold: 95.0,
+                },
+            ],
+            hold_down: 100,
+            max_keys: 10,
+        });
+        let mut state = Value::null();
+
+        let mut transitions = Vec::new();
+        for (id, host, cpu, ingest_ms) in &[
+            (1, "a", 99.0, 0),
+            (2, "a", 10.0, 10),
+            (3, "a", 85.0, 50),
+            // other keys aren't affected
+            (4, "b", 85.0, 60),
+            // the highest level seen while held down is entered
+            (5, "a", 10.0, 110),
+            // going back up resets the hold down
+            (6, "a", 10.0, 120),
+            (7, "a", 85.0, 130),
+            (8, "a", 10.0, 200),
+        ] {
+            let event = Event {
+                id: EventId::new(1, 0, *id),
+                ingest_ns: *ingest_ms * 1_000_000,
+                data: literal!({"host": host.to_string(), "cpu": *cpu}).into(),
+                ..Event::default()
+            };
+            let r = op
+                .on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+            for (_, event) in r.events {
+                let data = event.data.borrow_dependent().value();
+                transitions.push(format!(
+                    "{}: {}->{}",
+                    id,
+                    data.get_str("from").unwrap_or_default(),
+                    data.get_str("to").unwrap_or_default()
+                ));
+            }
+        }
+        assert_eq!(
+            vec!["1: ok->crit", "4: ok->warn", "5: crit->warn"],
+            transitions
+        );
+    }
+
+    #[test]
+    fn missing_field() {
+        let mut op = Escalation::new(Config {
+            key: vec!["host".to_string()],
+            field: "cpu".to_string(),
+            initial: "ok".to_string(),
+            levels: vec![Level {
+                name: "warn".to_string(),
+                threshold: 80.0,
+            }],
+            hold_down: 0,
+            max_keys: 10,
+        });
+        let mut state = Value::null();
+
+        let event = Event {
+            data: literal!({"host": "a"}).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        assert_eq!(ERR, r.events[0].0);
+    }
+}
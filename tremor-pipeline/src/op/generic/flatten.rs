@@ -0,0 +1,404 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Flatten and unflatten
+//!
+//! `generic::flatten` turns nested objects into a single object with the
+//! keys joined by `separator`, for column stores and metric systems that
+//! can't handle nesting:
+//!
+//! ```json
+//! {"a": {"b": 1, "c": [2, 3]}}
+//! ```
+//!
+//! becomes
+//!
+//! ```json
+//! {"a.b": 1, "a.c.0": 2, "a.c.1": 3}
+//! ```
+//!
+//! With `arrays: index`, the default, array elements are flattened with
+//! their index as key, with `arrays: keep` arrays are kept as values. Empty
+//! objects and arrays are kept as values.
+//!
+//! `generic::unflatten` reverses this, splitting keys on `separator`. With
+//! `arrays: index` objects with the keys `0` to `n - 1` become arrays again.
+//! Events with conflicting keys, like `a` and `a.b`, are sent to the `err`
+//! port unchanged.
+//!
+//! Events that aren't objects pass both operators unchanged.
+//!
+//! ```yaml
+//! - id: flatten
+//!   op: generic::flatten
+//!   config:
+//!     separator: "_"
+//!     arrays: keep
+//! ```
+
+use crate::op::prelude::*;
+use crate::{ConfigImpl, NodeConfig};
+use tremor_script::prelude::*;
+
+/// How arrays are handled
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Arrays {
+    /// array elements are keyed by their index
+    Index,
+    /// arrays are kept as values
+    Keep,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Separator of the keys
+    #[serde(default = "d_separator")]
+    pub separator: String,
+    /// How arrays are handled
+    #[serde(default = "d_arrays")]
+    pub arrays: Arrays,
+}
+
+fn d_separator() -> String {
+    ".".to_string()
+}
+
+fn d_arrays() -> Arrays {
+    Arrays::Index
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            separator: d_separator(),
+            arrays: d_arrays(),
+        }
+    }
+}
+
+impl ConfigImpl for Config {}
+
+fn config(node: &NodeConfig) -> Result<Config> {
+    let config = if let Some(map) = &node.config {
+        Config::new(map)?
+    } else {
+        Config::default()
+    };
+    if config.separator.is_empty() {
+        return Err(ErrorKind::BadOpConfig("The separator must not be empty".into()).into());
+    }
+    Ok(config)
+}
+
+op!(FlattenFactory(_uid, node) {
+    Ok(Box::new(Flatten { config: config(node)? }))
+});
+
+op!(UnflattenFactory(_uid, node) {
+    Ok(Box::new(Unflatten { config: config(node)? }))
+});
+
+#[derive(Debug)]
+pub struct Flatten {
+    pub config: Config,
+}
+
+#[derive(Debug)]
+pub struct Unflatten {
+    pub config: Config,
+}
+
+fn flatten_into<'v>(config: &Config, key: String, value: Value<'v>, into: &mut Object<'v>) {
+    let prefix = |k: &str| format!("{}{}{}", key, config.separator, k);
+    match value {
+        Value::Object(o) if !o.is_empty() => {
+            for (k, v) in *o {
+                flatten_into(config, prefix(&k), v, into);
+            }
+        }
+        Value::Array(a) if config.arrays == Arrays::Index && !a.is_empty() => {
+            for (i, v) in a.into_iter().enumerate() {
+                flatten_into(config, prefix(&i.to_string()), v, into);
+            }
+        }
+        value => {
+            into.insert(key.into(), value);
+        }
+    }
+}
+
+/// flattens an object, other values are returned as they are
+fn flatten<'v>(config: &Config, value: Value<'v>) -> Value<'v> {
+    match value {
+        Value::Object(o) => {
+            let mut flat = Object::with_capacity(o.len());
+            for (k, v) in *o {
+                flatten_into(config, k.to_string(), v, &mut flat);
+            }
+            Value::from(flat)
+        }
+        value => value,
+    }
+}
+
+/// inserts `value` at `path`, `false` if it conflicts with a value there
+fn insert_at<'v>(into: &mut Object<'v>, path: &[&str], value: Value<'v>) -> bool {
+    match path {
+        [key] => match into.get(*key) {
+            // an empty object added nothing to the one already there
+            Some(existing)
+                if existing.is_object() && value.as_object().map_or(false, |o| o.is_empty()) =>
+            {
+                true
+            }
+            Some(_) => false,
+            None => {
+                into.insert((*key).to_string().into(), value);
+                true
+            }
+        },
+        [key, rest @ ..] => {
+            let nested = into
+                .entry((*key).to_string().into())
+                .or_insert_with(Value::object);
+            nested
+                .as_object_mut()
+                .map_or(false, |nested| insert_at(nested, rest, value))
+        }
+        [] => false,
+    }
+}
+
+/// turns objects keyed `0` to `n - 1` into arrays, innermost first
+fn rebuild_arrays(value: &mut Value) {
+    if let Some(o) = value.as_object_mut() {
+        for (_, v) in o.iter_mut() {
+            rebuild_arrays(v);
+        }
+        let len = o.len();
+        let indexed = len > 0
+            && o.keys().all(|k| {
+                k.parse::<usize>()
+                    .map_or(false, |i| i < len && &**k == i.to_string())
+            });
+        if indexed {
+            let mut elements: Vec<(usize, Value)> = std::mem::take(o)
+                .into_iter()
+                .filter_map(|(k, v)| Some((k.parse().ok()?, v)))
+                .collect();
+            elements.sort_by_key(|(i, _)| *i);
+            *value = Value::from(elements.into_iter().map(|(_, v)| v).collect::<Vec<_>>());
+        }
+    }
+}
+
+/// unflattens an object, `None` on conflicting keys
+fn unflatten<'v>(config: &Config, value: &Value<'v>) -> Option<Value<'v>> {
+    let flat = value.as_object()?;
+    let mut nested = Object::with_capacity(flat.len());
+    for (k, v) in flat.iter() {
+        let path: Vec<&str> = k.split(config.separator.as_str()).collect();
+        if !insert_at(&mut nested, &path, v.clone()) {
+            return None;
+        }
+    }
+    let mut nested = Value::from(nested);
+    if config.arrays == Arrays::Index {
+        rebuild_arrays(&mut nested);
+    }
+    Some(nested)
+}
+
+impl Operator for Flatten {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let config = &self.config;
+        event.data.with_dependent_mut(|_, parsed| {
+            let value = parsed.value_mut();
+            let nested = std::mem::take(value);
+            *value = flatten(config, nested);
+        });
+        Ok(event.into())
+    }
+}
+
+impl Operator for Unflatten {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let config = &self.config;
+        let unflattened = event.data.with_dependent_mut(|_, parsed| {
+            let value = parsed.value_mut();
+            if !value.is_object() {
+                return true;
+            }
+            if let Some(nested) = unflatten(config, value) {
+                *value = nested;
+                true
+            } else {
+                false
+            }
+        });
+        if unflattened {
+            Ok(event.into())
+        } else {
+            Ok(vec![(ERR, event)].into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    fn config(separator: &str, arrays: Arrays) -> Config {
+        Config {
+            separator: separator.to_string(),
+            arrays,
+        }
+    }
+
+    #[test]
+    fn flatten_index() {
+        let config = Config::default();
+        let nested = literal!({
+            "a": {"b": 1, "c": [2, {"d": 3}]},
+            "e": {},
+            "f": [],
+            "g": "snot"
+        });
+        let flat = literal!({
+            "a.b": 1,
+            "a.c.0": 2,
+            "a.c.1.d": 3,
+            "e": {},
+            "f": [],
+            "g": "snot"
+        });
+        assert_eq!(flat, flatten(&config, nested.clone()));
+        assert_eq!(Some(nested), unflatten(&config, &flat));
+    }
+
+    #[test]
+    fn flatten_keep() {
+        let config = config("_", Arrays::Keep);
+        let nested = literal!({"a": {"b": [1, {"c": 2}]}});
+        let flat = literal!({"a_b": [1, {"c": 2}]});
+        assert_eq!(flat, flatten(&config, nested.clone()));
+        assert_eq!(Some(nested), unflatten(&config, &flat));
+
+        // index keys stay objects
+        assert_eq!(
+            Some(literal!({"a": {"0": 1}})),
+            unflatten(&config, &literal!({"a_0": 1}))
+        );
+    }
+
+    #[test]
+    fn unflatten_arrays() {
+        let config = Config::default();
+        // not a complete index
+        assert_eq!(
+            Some(literal!({"a": {"0": 1, "2": 2}})),
+            unflatten(&config, &literal!({"a.0": 1, "a.2": 2}))
+        );
+        assert_eq!(
+            Some(literal!({"a": {"00": 1}})),
+            unflatten(&config, &literal!({"a.00": 1}))
+        );
+        assert_eq!(
+            Some(literal!({"a": [[1, 2], 3]})),
+            unflatten(&config, &literal!({"a.1": 3, "a.0.1": 2, "a.0.0": 1}))
+        );
+    }
+
+    #[test]
+    fn conflicts() {
+        let config = Config::default();
+        assert_eq!(None, unflatten(&config, &literal!({"a": 1, "a.b": 2})));
+        assert_eq!(
+            Some(literal!({"a": {"b": 2}})),
+            unflatten(&config, &literal!({"a": {}, "a.b": 2}))
+        );
+    }
+
+    #[test]
+    fn events() -> Result<()> {
+        let mut state = Value::null();
+        let mut flatten = Flatten {
+            config: Config::default(),
+        };
+        let mut unflatten = Unflatten {
+            config: Config::default(),
+        };
+        let event = |value: Value<'static>| Event {
+            data: (value, Value::object()).into(),
+            ..Event::default()
+        };
+
+        let mut res = flatten
+            .on_event(0, "in", &mut state, event(literal!({"a": {"b": 1}})))?
+            .events;
+        let (port, e) = res.pop().expect("no event");
+        assert_eq!("out", port);
+        assert_eq!(&literal!({"a.b": 1}), e.data.borrow_dependent().value());
+
+        let mut res = unflatten.on_event(0, "in", &mut state, e)?.events;
+        let (port, e) = res.pop().expect("no event");
+        assert_eq!("out", port);
+        assert_eq!(
+            &literal!({"a": {"b": 1}}),
+            e.data.borrow_dependent().value()
+        );
+
+        let mut res = unflatten
+            .on_event(0, "in", &mut state, event(literal!({"a": 1, "a.b": 2})))?
+            .events;
+        let (port, e) = res.pop().expect("no event");
+        assert_eq!("err", port);
+        assert_eq!(
+            &literal!({"a": 1, "a.b": 2}),
+            e.data.borrow_dependent().value()
+        );
+
+        // other values pass unchanged
+        let mut res = flatten
+            .on_event(0, "in", &mut state, event(Value::from("snot")))?
+            .events;
+        let (port, e) = res.pop().expect("no event");
+        assert_eq!("out", port);
+        assert_eq!(&Value::from("snot"), e.data.borrow_dependent().value());
+        Ok(())
+    }
+
+    #[test]
+    fn empty_separator() {
+        let node = NodeConfig {
+            config: Some(serde_yaml::from_str("separator: ''").expect("valid yaml")),
+            ..NodeConfig::default()
+        };
+        assert!(super::config(&node).is_err());
+    }
+}
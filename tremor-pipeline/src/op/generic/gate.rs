@@ -0,0 +1,264 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Warm-up gate
+//!
+//! Holds back events until the pipeline is ready, then opens and passes all
+//! events from there on. The gate opens when
+//!
+//! * any event arrives on its `control` port, e.g. from a pipeline loading
+//!   reference data once it is done, control events are not forwarded
+//! * `open_after` seconds elapsed since the gate saw its first event or signal
+//!
+//! While closed, events are either buffered and released in order once the
+//! gate opens (`buffer`, the default), or dropped (`drop`). The buffer holds
+//! up to `max_buffered` events, the oldest are dropped beyond that.
+//!
+//! ```yaml
+//! - id: warmup
+//!   op: generic::gate
+//!   config:
+//!     mode: buffer
+//!     max_buffered: 10000
+//!     open_after: 30
+//! ```
+
+use crate::op::prelude::*;
+use crate::ConfigImpl;
+use std::collections::VecDeque;
+use tremor_script::prelude::*;
+
+const CONTROL: &str = "control";
+
+/// What happens to events while the gate is closed
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// hold them back until the gate opens
+    Buffer,
+    /// drop them
+    Drop,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// What happens to events while the gate is closed
+    #[serde(default = "d_mode")]
+    pub mode: Mode,
+    /// Maximum number of events held back
+    #[serde(default = "d_max_buffered")]
+    pub max_buffered: usize,
+    /// Seconds after which the gate opens on its own
+    #[serde(default = "Default::default")]
+    pub open_after: Option<u64>,
+}
+
+fn d_mode() -> Mode {
+    Mode::Buffer
+}
+
+fn d_max_buffered() -> usize {
+    1000
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: d_mode(),
+            max_buffered: d_max_buffered(),
+            open_after: None,
+        }
+    }
+}
+
+impl ConfigImpl for Config {}
+
+op!(GateFactory(_uid, node) {
+    let config = if let Some(map) = &node.config {
+        Config::new(map)?
+    } else {
+        Config::default()
+    };
+    Ok(Box::new(Gate::new(config)))
+});
+
+#[derive(Debug)]
+pub struct Gate {
+    pub config: Config,
+    open: bool,
+    /// when the gate saw its first event or signal
+    since_ns: Option<u64>,
+    buffered: VecDeque<Event>,
+    /// events dropped while closed
+    dropped: u64,
+}
+
+impl Gate {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            open: false,
+            since_ns: None,
+            buffered: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// opens the gate, releasing the buffered events
+    fn open(&mut self) -> EventAndInsights {
+        self.open = true;
+        if self.dropped > 0 {
+            warn!(
+                "Gate opened after dropping {} events while closed",
+                self.dropped
+            );
+        }
+        self.buffered
+            .drain(..)
+            .map(|e| (OUT, e))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    fn timed_out(&mut self, now_ns: u64) -> bool {
+        let since_ns = *self.since_ns.get_or_insert(now_ns);
+        self.config.open_after.map_or(false, |s| {
+            now_ns.saturating_sub(since_ns) >= s * 1_000_000_000
+        })
+    }
+}
+
+impl Operator for Gate {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        port: &str,
+        _state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<EventAndInsights> {
+        if port.eq_ignore_ascii_case(CONTROL) {
+            return Ok(if self.open {
+                EventAndInsights::default()
+            } else {
+                self.open()
+            });
+        }
+        if self.open {
+            return Ok(event.into());
+        }
+        if self.timed_out(event.ingest_ns) {
+            let mut res = self.open();
+            res.events.push((OUT, event));
+            return Ok(res);
+        }
+        match self.config.mode {
+            Mode::Buffer => {
+                if self.buffered.len() >= self.config.max_buffered {
+                    self.buffered.pop_front();
+                    self.dropped += 1;
+                }
+                self.buffered.push_back(event);
+            }
+            Mode::Drop => self.dropped += 1,
+        }
+        Ok(EventAndInsights::default())
+    }
+
+    fn handles_signal(&self) -> bool {
+        true
+    }
+
+    fn on_signal(
+        &mut self,
+        _uid: u64,
+        _state: &Value<'static>,
+        signal: &mut Event,
+    ) -> Result<EventAndInsights> {
+        if !self.open && self.timed_out(signal.ingest_ns) {
+            Ok(self.open())
+        } else {
+            Ok(EventAndInsights::default())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::EventId;
+
+    fn event(id: u64, ingest_ns: u64) -> Event {
+        Event {
+            id: EventId::new(0, 0, id),
+            ingest_ns,
+            data: Value::from(id).into(),
+            ..Event::default()
+        }
+    }
+
+    fn ids(res: EventAndInsights) -> Vec<u64> {
+        res.events.iter().map(|(_, e)| e.id.event_id()).collect()
+    }
+
+    #[test]
+    fn buffer_until_control() -> Result<()> {
+        let mut op = Gate::new(Config {
+            max_buffered: 2,
+            ..Config::default()
+        });
+        let mut state = Value::null();
+        assert!(ids(op.on_event(0, "in", &mut state, event(1, 1))?).is_empty());
+        assert!(ids(op.on_event(0, "in", &mut state, event(2, 2))?).is_empty());
+        // the oldest is dropped
+        assert!(ids(op.on_event(0, "in", &mut state, event(3, 3))?).is_empty());
+        assert_eq!(
+            vec![2, 3],
+            ids(op.on_event(0, "control", &mut state, event(4, 4))?)
+        );
+        assert_eq!(vec![5], ids(op.on_event(0, "in", &mut state, event(5, 5))?));
+        assert!(ids(op.on_event(0, "control", &mut state, event(6, 6))?).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn drop_until_timeout() -> Result<()> {
+        let mut op = Gate::new(Config {
+            mode: Mode::Drop,
+            open_after: Some(1),
+            ..Config::default()
+        });
+        let mut state = Value::null();
+        let mut signal = event(0, 0);
+        assert!(ids(op.on_signal(0, &state, &mut signal)?).is_empty());
+        assert!(ids(op.on_event(0, "in", &mut state, event(1, 500_000_000))?).is_empty());
+        assert_eq!(
+            vec![2],
+            ids(op.on_event(0, "in", &mut state, event(2, 1_000_000_000))?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn open_on_signal() -> Result<()> {
+        let mut op = Gate::new(Config {
+            open_after: Some(1),
+            ..Config::default()
+        });
+        let mut state = Value::null();
+        assert!(ids(op.on_event(0, "in", &mut state, event(1, 0))?).is_empty());
+        let mut signal = event(0, 2_000_000_000);
+        assert_eq!(vec![1], ids(op.on_signal(0, &state, &mut signal)?));
+        Ok(())
+    }
+}
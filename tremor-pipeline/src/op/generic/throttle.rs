@@ -0,0 +1,409 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Throttle by key
+//!
+//! Forwards the first event per key and suppresses further events with the
+//! same key for `cooldown` milliseconds, the building block for de-noising
+//! alerts. The key is built from the values at the dotted `key` paths,
+//! `meta.` prefixed paths are looked up in the event metadata.
+//!
+//! With `summarize` a summary is sent to the `summary` port once the
+//! cooldown of a key with suppressed events ends, with the `key`, the number
+//! of `suppressed` events and the ingest times of the first and last of
+//! them. Cooldowns end on the next event or tick signal past them.
+//!
+//! At most `max_keys` keys are tracked, the least recently seen are dropped
+//! first, summarized if need be. Suppressed events are reported as
+//! `throttle_suppressed` metrics.
+//!
+//! ```yaml
+//! - id: throttle
+//!   op: generic::throttle
+//!   config:
+//!     key:
+//!       - host
+//!       - alert
+//!     cooldown: 300000
+//!     summarize: true
+//! ```
+
+use crate::op::prelude::*;
+use crate::{influx_value, ConfigImpl, EventIdGenerator};
+use lru::LruCache;
+use std::collections::VecDeque;
+use tremor_script::prelude::*;
+
+const SUMMARY: Cow<'static, str> = Cow::const_str("summary");
+const THROTTLE_SUPPRESSED: Cow<'static, str> = Cow::const_str("throttle_suppressed");
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Paths of the values the key is built from
+    pub key: Vec<String>,
+    /// Milliseconds further events with the same key are suppressed for
+    pub cooldown: u64,
+    /// Send a summary of the suppressed events to `summary` once the
+    /// cooldown ends
+    #[serde(default = "Default::default")]
+    pub summarize: bool,
+    /// Maximum number of tracked keys
+    #[serde(default = "d_max_keys")]
+    pub max_keys: usize,
+}
+
+fn d_max_keys() -> usize {
+    10_000
+}
+
+impl ConfigImpl for Config {}
+
+op!(ThrottleFactory(uid, node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        if config.key.is_empty() {
+            return Err(ErrorKind::BadOpConfig("The throttle `key` must not be empty".to_string()).into());
+        }
+        Ok(Box::new(Throttle::new(uid, config)))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+/// The cooldown of a key
+#[derive(Debug)]
+struct Cooldown {
+    key: Value<'static>,
+    end_ns: u64,
+    suppressed: u64,
+    first_ns: u64,
+    last_ns: u64,
+}
+
+pub struct Throttle {
+    pub config: Config,
+    cooldowns: LruCache<String, Cooldown>,
+    /// keys in the order their cooldown ends, entries of renewed or dropped
+    /// cooldowns are skipped
+    ending: VecDeque<(u64, String)>,
+    id_gen: EventIdGenerator,
+    suppressed: u64,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl std::fmt::Debug for Throttle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Throttle({} keys)", self.cooldowns.len())
+    }
+}
+
+impl Throttle {
+    fn new(uid: u64, config: Config) -> Self {
+        Self {
+            cooldowns: LruCache::new(config.max_keys),
+            ending: VecDeque::new(),
+            config,
+            id_gen: EventIdGenerator::new(uid),
+            suppressed: 0,
+        }
+    }
+
+    /// the key of an event
+    fn key(&self, event: &Event) -> Value<'static> {
+        let data = event.data.borrow_dependent();
+        let (value, meta) = (data.value(), data.meta());
+        self.config
+            .key
+            .iter()
+            .map(|path| lookup(value, meta, path).map_or_else(Value::null, Value::clone_static))
+            .collect()
+    }
+
+    fn summarize(
+        &mut self,
+        cooldown: Cooldown,
+        now_ns: u64,
+        out: &mut Vec<(Cow<'static, str>, Event)>,
+    ) {
+        if self.config.summarize && cooldown.suppressed > 0 {
+            let data = literal!({
+                "key": cooldown.key,
+                "suppressed": cooldown.suppressed,
+                "first_ns": cooldown.first_ns,
+                "last_ns": cooldown.last_ns
+            });
+            let summary = Event {
+                id: self.id_gen.next_id(),
+                ingest_ns: now_ns,
+                data: data.into(),
+                ..Event::default()
+            };
+            out.push((SUMMARY, summary));
+        }
+    }
+
+    /// ends all cooldowns up to `now_ns`
+    fn expire(&mut self, now_ns: u64, out: &mut Vec<(Cow<'static, str>, Event)>) {
+        while let Some((end_ns, _)) = self.ending.front() {
+            if *end_ns > now_ns {
+                break;
+            }
+            if let Some((end_ns, key)) = self.ending.pop_front() {
+                let current = self.cooldowns.peek(&key).map(|c| c.end_ns) == Some(end_ns);
+                if current {
+                    if let Some(cooldown) = self.cooldowns.pop(&key) {
+                        self.summarize(cooldown, now_ns, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Operator for Throttle {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        event: Event,
+    ) -> Result<EventAndInsights> {
+        let mut out = Vec::new();
+        let now_ns = event.ingest_ns;
+        self.expire(now_ns, &mut out);
+        let key = self.key(&event);
+        let encoded = key.encode();
+        if let Some(cooldown) = self.cooldowns.get_mut(&encoded) {
+            if cooldown.suppressed == 0 {
+                cooldown.first_ns = now_ns;
+            }
+            cooldown.suppressed += 1;
+            cooldown.last_ns = now_ns;
+            self.suppressed += 1;
+        } else {
+            if self.cooldowns.len() >= self.config.max_keys {
+                if let Some((_, cooldown)) = self.cooldowns.pop_lru() {
+                    self.summarize(cooldown, now_ns, &mut out);
+                }
+            }
+            let end_ns = now_ns.saturating_add(self.config.cooldown.saturating_mul(1_000_000));
+            self.ending.push_back((end_ns, encoded.clone()));
+            self.cooldowns.put(
+                encoded,
+                Cooldown {
+                    key,
+                    end_ns,
+                    suppressed: 0,
+                    first_ns: 0,
+                    last_ns: 0,
+                },
+            );
+            out.push((OUT, event));
+        }
+        Ok(out.into())
+    }
+
+    fn handles_signal(&self) -> bool {
+        self.config.summarize
+    }
+
+    fn on_signal(
+        &mut self,
+        _uid: u64,
+        _state: &Value<'static>,
+        signal: &mut Event,
+    ) -> Result<EventAndInsights> {
+        let mut out = Vec::new();
+        self.expire(signal.ingest_ns, &mut out);
+        Ok(out.into())
+    }
+
+    fn metrics(
+        &self,
+        tags: &HashMap<Cow<'static, str>, Value<'static>>,
+        timestamp: u64,
+    ) -> Result<Vec<Value<'static>>> {
+        Ok(vec![influx_value(
+            THROTTLE_SUPPRESSED,
+            tags.clone(),
+            self.suppressed,
+            timestamp,
+        )])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::EventId;
+
+    #[test]
+    fn suppress() {
+        let mut op = Throttle::new(
+            0,
+            Config {
+                key: vec!["host".to_string(), "meta.alert".to_string()],
+                cooldown: 100,
+                summarize: false,
+                max_keys: 10,
+            },
+        );
+        let mut state = Value::null();
+
+        let mut forwarded = Vec::new();
+        // other keys aren't affected by a cooldown
+        for (id, alert, ingest_ns) in &[
+            (1, "cpu", 0),
+            (2, "cpu", 50_000_000),
+            (3, "mem", 50_000_000),
+            (4, "cpu", 100_000_000),
+        ] {
+            let event = Event {
+                id: EventId::new(1, 0, *id),
+                ingest_ns: *ingest_ns,
+                data: (
+                    literal!({"host": "a"}),
+                    literal!({ "alert": alert.to_string() }),
+                )
+                    .into(),
+                ..Event::default()
+            };
+            let r = op
+                .on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+            for (port, event) in r.events {
+                assert_eq!("out", port);
+                forwarded.push(event.id.event_id());
+            }
+        }
+        assert_eq!(vec![1, 3, 4], forwarded);
+        assert_eq!(1, op.suppressed);
+    }
+
+    #[test]
+    fn summarize() {
+        let mut op = Throttle::new(
+            0,
+            Config {
+                key: vec!["host".to_string(), "meta.alert".to_string()],
+                cooldown: 100,
+                summarize: true,
+                max_keys: 10,
+            },
+        );
+        let mut state = Value::null();
+
+        // no summary without suppressed events for host `b`
+        for (id, host, ingest_ns) in &[
+            (1, "a", 0),
+            (2, "a", 10_000_000),
+            (3, "a", 20_000_000),
+            (4, "b", 20_000_000),
+        ] {
+            let event = Event {
+                id: EventId::new(1, 0, *id),
+                ingest_ns: *ingest_ns,
+                data: (
+                    literal!({ "host": host.to_string() }),
+                    literal!({"alert": "cpu"}),
+                )
+                    .into(),
+                ..Event::default()
+            };
+            op.on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+        }
+
+        let mut signal = Event {
+            ingest_ns: 99_000_000,
+            ..Event::default()
+        };
+        let r = op
+            .on_signal(0, &state, &mut signal)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 0);
+
+        let mut signal = Event {
+            ingest_ns: 200_000_000,
+            ..Event::default()
+        };
+        let mut r = op
+            .on_signal(0, &state, &mut signal)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        let (port, summary) = r.events.pop().expect("no results");
+        assert_eq!("summary", port);
+        let (first_ns, last_ns): (u64, u64) = (10_000_000, 20_000_000);
+        assert_eq!(
+            &literal!({
+                "key": ["a", "cpu"],
+                "suppressed": 2,
+                "first_ns": first_ns,
+                "last_ns": last_ns
+            }),
+            summary.data.borrow_dependent().value()
+        );
+    }
+
+    #[test]
+    fn max_keys() {
+        let mut op = Throttle::new(
+            0,
+            Config {
+                key: vec!["host".to_string(), "meta.alert".to_string()],
+                cooldown: 100,
+                summarize: true,
+                max_keys: 1,
+            },
+        );
+        let mut state = Value::null();
+
+        for (id, ingest_ns) in &[(1, 0), (2, 10_000_000)] {
+            let event = Event {
+                id: EventId::new(1, 0, *id),
+                ingest_ns: *ingest_ns,
+                data: (literal!({"host": "a"}), literal!({"alert": "cpu"})).into(),
+                ..Event::default()
+            };
+            op.on_event(0, "in", &mut state, event)
+                .expect("could not run pipeline");
+        }
+
+        // the dropped key is summarized
+        let event = Event {
+            id: EventId::new(1, 0, 3),
+            ingest_ns: 20_000_000,
+            data: (literal!({"host": "b"}), literal!({"alert": "cpu"})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        let ports: Vec<_> = r.events.iter().map(|(p, _)| p.as_ref()).collect();
+        assert_eq!(vec!["summary", "out"], ports);
+
+        // and forwarded again
+        let event = Event {
+            id: EventId::new(1, 0, 4),
+            ingest_ns: 30_000_000,
+            data: (literal!({"host": "a"}), literal!({"alert": "cpu"})).into(),
+            ..Event::default()
+        };
+        let r = op
+            .on_event(0, "in", &mut state, event)
+            .expect("could not run pipeline");
+        assert_eq!(r.len(), 1);
+        assert_eq!("out", r.events[0].0);
+    }
+}
@@ -32,6 +32,11 @@
 //! The 1st additional output is used to route data that was decided to
 //! be discarded to.
 //!
+//! Discarded events carry a `$rejected` record with the `reason`, the `class`
+//! and a `retry_after_ms` estimate. Connecting the `overflow` port back to a
+//! linked `rest` or `ws` onramp answers the client with a `429` response or a
+//! `1013` (try again later) close frame respectively.
+//!
 //! # Example
 //!
 //! ```yaml
@@ -147,7 +152,7 @@ impl Operator for Grouper {
         _uid: u64,
         _port: &str,
         _state: &mut Value<'static>,
-        event: Event,
+        mut event: Event,
     ) -> Result<EventAndInsights> {
         let meta = event.data.borrow_dependent().meta();
         if let Some(class) = meta.get_str("class") {
@@ -191,6 +196,19 @@ impl Operator for Grouper {
                 Ok(event.into())
             } else {
                 groups.overflow += 1;
+                // the earliest a slot frees up is when the oldest window rotates out
+                let retry_after_ms = Rate::from_meta(&meta)
+                    .map_or(0, |rate| rate.time_range / (rate.windows.max(1) as u64))
+                    .max(1);
+                let mut rejected = Value::object_with_capacity(3);
+                rejected.insert("reason", "rate_limited")?;
+                rejected.insert("class", class.to_string())?;
+                rejected.insert("retry_after_ms", retry_after_ms)?;
+                event.data.with_dependent_mut(|_, parsed| {
+                    if let Some(meta) = parsed.meta_mut().as_object_mut() {
+                        meta.insert("rejected".into(), rejected);
+                    }
+                });
                 Ok(vec![(OVERFLOW, event)].into())
             }
         } else {
@@ -283,7 +301,14 @@ mod test {
         let (port, e) = r.events.pop().unwrap();
         assert!(r.events.is_empty());
         assert_eq!(port, "overflow");
-        assert_eq!(e, event2);
+        assert_eq!(
+            e.data.borrow_dependent().value(),
+            event2.data.borrow_dependent().value()
+        );
+        assert_eq!(
+            Some(&literal!({"reason": "rate_limited", "class": "test", "retry_after_ms": 10})),
+            e.data.borrow_dependent().meta().get("rejected")
+        );
 
         let event3 = Event {
             id: (1, 1, 1).into(),
@@ -0,0 +1,902 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! # Schema registry validation
+//!
+//! Validates events against JSON schemas fetched by subject from a
+//! Confluent or Apicurio schema registry.
+//!
+//! Schemas are cached for `cache_ttl_s` seconds. When a refreshed schema
+//! is not backward compatible with the cached one, the new version is
+//! refused and the cached schema stays in use.
+//!
+//! Schemas are fetched in the background, a configured `subject` right
+//! when the operator is created. Events of a subject without a schema are
+//! treated as invalid until it arrives. Failed fetches are retried with an
+//! exponential backoff of up to `cache_ttl_s`. Stale schemas are refreshed
+//! in the background as well and stay in use until the refreshed version
+//! arrives.
+//!
+//! ## Configuration
+//!
+//! See [Config](struct.Config.html) for details.
+//!
+//! ## Outputs
+//!
+//! Valid events are sent to `out`. Invalid events are sent to `err` with
+//! the validation errors in `$schema_errors` or dropped, depending on
+//! `on_failure`.
+
+use crate::errors::{ErrorKind, Result};
+use crate::op::prelude::*;
+use std::collections::HashSet;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Duration;
+use tremor_script::prelude::*;
+
+const SCHEMA_ERRORS: &str = "schema_errors";
+/// initial delay before retrying a failed fetch
+const BACKOFF_NS: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Flavor {
+    /// Confluent schema registry
+    Confluent,
+    /// Apicurio registry (v2 API)
+    Apicurio,
+}
+
+impl Default for Flavor {
+    fn default() -> Self {
+        Self::Confluent
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// send invalid events to the `err` port
+    Route,
+    /// drop invalid events
+    Drop,
+}
+
+impl Default for OnFailure {
+    fn default() -> Self {
+        Self::Route
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compatibility {
+    /// accept every new schema version
+    None,
+    /// only accept new schema versions that can read events valid under the previous one
+    Backward,
+}
+
+impl Default for Compatibility {
+    fn default() -> Self {
+        Self::Backward
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    /// Base url of the schema registry
+    pub url: String,
+    /// Registry flavor, `confluent` or `apicurio`
+    ///
+    /// default: `confluent`
+    #[serde(default)]
+    pub registry: Flavor,
+    /// Artifact group, only used for `apicurio`
+    #[serde(default = "d_group")]
+    pub group: String,
+    /// Static subject to validate against
+    pub subject: Option<String>,
+    /// Metadata key to read the subject from if no static `subject` is configured
+    #[serde(default = "d_subject_meta")]
+    pub subject_meta: String,
+    /// Time in seconds after which a cached schema is refreshed
+    #[serde(default = "d_ttl")]
+    pub cache_ttl_s: u64,
+    /// Timeout for registry requests in milliseconds
+    #[serde(default = "d_timeout")]
+    pub timeout_ms: u64,
+    /// What to do with events failing validation, `route` or `drop`
+    #[serde(default)]
+    pub on_failure: OnFailure,
+    /// Compatibility required between schema versions, `none` or `backward`
+    #[serde(default)]
+    pub compatibility: Compatibility,
+}
+
+impl ConfigImpl for Config {}
+
+fn d_group() -> String {
+    "default".to_string()
+}
+
+fn d_subject_meta() -> String {
+    "subject".to_string()
+}
+
+fn d_ttl() -> u64 {
+    300
+}
+
+fn d_timeout() -> u64 {
+    1000
+}
+
+/// A source of versioned schemas
+pub trait Registry: std::fmt::Debug + Send + Sync {
+    /// Fetches the latest version of the schema for a given subject
+    ///
+    /// # Errors
+    /// if the schema can not be fetched or parsed
+    fn fetch(&self, subject: &str) -> Result<(u64, OwnedValue)>;
+}
+
+#[derive(Debug)]
+struct HttpRegistry {
+    url: String,
+    flavor: Flavor,
+    group: String,
+    timeout: Duration,
+}
+
+fn parse_schema(raw: &str) -> Result<OwnedValue> {
+    let mut raw = raw.as_bytes().to_vec();
+    Ok(simd_json::to_owned_value(&mut raw)?)
+}
+
+impl HttpRegistry {
+    /// the url of the latest version of `subject`, with the subject percent-encoded
+    fn url(&self, subject: &str) -> Result<url::Url> {
+        let mut url = url::Url::parse(&self.url)?;
+        url.path_segments_mut()
+            .map_err(|_| Error::from(format!("Invalid schema registry url {}", self.url)))?
+            .pop_if_empty()
+            .extend(match self.flavor {
+                Flavor::Confluent => vec!["subjects", subject, "versions", "latest"],
+                Flavor::Apicurio => vec![
+                    "apis",
+                    "registry",
+                    "v2",
+                    "groups",
+                    self.group.as_str(),
+                    "artifacts",
+                    subject,
+                ],
+            });
+        Ok(url)
+    }
+}
+
+impl Registry for HttpRegistry {
+    fn fetch(&self, subject: &str) -> Result<(u64, OwnedValue)> {
+        let url = self.url(subject)?;
+        let res = attohttpc::get(url.as_str())
+            .timeout(self.timeout)
+            .send()
+            .map_err(|e| Error::from(format!("Schema registry request failed: {}", e)))?;
+        if !res.is_success() {
+            return Err(format!("Schema registry returned {} for {}", res.status(), url).into());
+        }
+        let version = res
+            .headers()
+            .get("X-Registry-Version")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        let body = res
+            .text()
+            .map_err(|e| Error::from(format!("Invalid schema registry response: {}", e)))?;
+        match self.flavor {
+            Flavor::Confluent => {
+                let response = parse_schema(&body)?;
+                let schema_type = response.get("schemaType").and_then(OwnedValue::as_str);
+                if schema_type.map_or(false, |t| t != "JSON") {
+                    return Err(format!("Subject {} is not a JSON schema", subject).into());
+                }
+                let version = response
+                    .get("version")
+                    .and_then(OwnedValue::as_u64)
+                    .unwrap_or_default();
+                let schema = response
+                    .get("schema")
+                    .and_then(OwnedValue::as_str)
+                    .ok_or_else(|| Error::from("Schema registry response is missing `schema`"))?;
+                Ok((version, parse_schema(schema)?))
+            }
+            Flavor::Apicurio => Ok((version.unwrap_or_default(), parse_schema(&body)?)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Cached {
+    version: u64,
+    schema: OwnedValue,
+    fetched_ns: u64,
+}
+
+/// A subject that couldn't be fetched
+#[derive(Debug)]
+struct Failed {
+    error: String,
+    backoff_ns: u64,
+    retry_ns: u64,
+}
+
+#[derive(Debug)]
+pub struct SchemaRegistry {
+    pub config: Config,
+    cache: HashMap<String, Cached>,
+    /// subjects without a schema that are not fetched again before their retry
+    failed: HashMap<String, Failed>,
+    /// subjects with a fetch in flight
+    refreshing: HashSet<String>,
+    refresh_tx: Sender<String>,
+    refreshed_rx: Receiver<(String, Result<(u64, OwnedValue)>)>,
+    ttl_ns: u64,
+}
+
+impl SchemaRegistry {
+    fn new(config: Config, registry: Arc<dyn Registry>) -> Self {
+        let ttl_ns = config.cache_ttl_s * 1_000_000_000;
+        let (refresh_tx, requests) = channel::<String>();
+        let (replies, refreshed_rx) = channel();
+        // fetches happen here, so events don't wait for the registry;
+        // the thread ends with the operator dropping `refresh_tx`
+        std::thread::spawn(move || {
+            for subject in requests {
+                let fetched = registry.fetch(&subject);
+                if replies.send((subject, fetched)).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut refreshing = HashSet::new();
+        if let Some(subject) = &config.subject {
+            if refresh_tx.send(subject.clone()).is_ok() {
+                refreshing.insert(subject.clone());
+            }
+        }
+        Self {
+            config,
+            cache: HashMap::new(),
+            failed: HashMap::new(),
+            refreshing,
+            refresh_tx,
+            refreshed_rx,
+            ttl_ns,
+        }
+    }
+
+    /// Returns the schema for the given subject, fetching or refreshing it
+    /// in the background if required
+    fn schema(&mut self, subject: &str, now_ns: u64) -> std::result::Result<&OwnedValue, String> {
+        while let Ok((refreshed, fetched)) = self.refreshed_rx.try_recv() {
+            self.refreshing.remove(&refreshed);
+            self.update(&refreshed, fetched, now_ns);
+        }
+        let fetch = match (self.cache.get(subject), self.failed.get(subject)) {
+            (Some(cached), _) => now_ns.saturating_sub(cached.fetched_ns) >= self.ttl_ns,
+            (None, Some(failed)) => now_ns >= failed.retry_ns,
+            (None, None) => true,
+        };
+        if fetch
+            && !self.refreshing.contains(subject)
+            && self.refresh_tx.send(subject.to_string()).is_ok()
+        {
+            self.refreshing.insert(subject.to_string());
+        }
+        match (self.cache.get(subject), self.failed.get(subject)) {
+            (Some(cached), _) => Ok(&cached.schema),
+            (None, Some(failed)) => {
+                Err(format!("Schema {} unavailable: {}", subject, failed.error))
+            }
+            (None, None) => Err(format!("Schema {} is being fetched", subject)),
+        }
+    }
+
+    /// Caches a fetched schema, unless it is refused for not being compatible
+    /// with the cached version
+    fn update(&mut self, subject: &str, fetched: Result<(u64, OwnedValue)>, now_ns: u64) {
+        match fetched {
+            Ok((version, schema)) => {
+                self.failed.remove(subject);
+                let refused = match self.cache.get(subject) {
+                    Some(cached)
+                        if cached.version != version
+                            && self.config.compatibility == Compatibility::Backward =>
+                    {
+                        is_backward_compatible(&cached.schema, &schema, "").err()
+                    }
+                    _ => None,
+                };
+                if let Some(cached) = self.cache.get_mut(subject) {
+                    cached.fetched_ns = now_ns;
+                    if let Some(reason) = refused {
+                        warn!(
+                            "Refusing version {} of schema {}, it is not backward compatible with version {}: {}",
+                            version, subject, cached.version, reason
+                        );
+                    } else {
+                        cached.version = version;
+                        cached.schema = schema;
+                    }
+                } else {
+                    self.cache.insert(
+                        subject.to_string(),
+                        Cached {
+                            version,
+                            schema,
+                            fetched_ns: now_ns,
+                        },
+                    );
+                }
+            }
+            Err(e) => {
+                if let Some(cached) = self.cache.get_mut(subject) {
+                    // keep using the stale schema until the next refresh
+                    warn!("Failed to refresh schema {}: {}", subject, e);
+                    cached.fetched_ns = now_ns;
+                } else {
+                    let backoff_ns = self.failed.get(subject).map_or(BACKOFF_NS, |f| {
+                        f.backoff_ns
+                            .saturating_mul(2)
+                            .min(self.ttl_ns.max(BACKOFF_NS))
+                    });
+                    warn!(
+                        "Failed to fetch schema {}, retrying in {}ms: {}",
+                        subject,
+                        backoff_ns / 1_000_000,
+                        e
+                    );
+                    self.failed.insert(
+                        subject.to_string(),
+                        Failed {
+                            error: e.to_string(),
+                            backoff_ns,
+                            retry_ns: now_ns.saturating_add(backoff_ns),
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+op!(RegistryFactory(_uid, node) {
+    if let Some(map) = &node.config {
+        let config: Config = Config::new(map)?;
+        let registry = HttpRegistry {
+            url: config.url.clone(),
+            flavor: config.registry,
+            group: config.group.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+        };
+        Ok(Box::new(SchemaRegistry::new(config, Arc::new(registry))))
+    } else {
+        Err(ErrorKind::MissingOpConfig(node.id.to_string()).into())
+    }
+});
+
+impl Operator for SchemaRegistry {
+    fn on_event(
+        &mut self,
+        _uid: u64,
+        _port: &str,
+        _state: &mut Value<'static>,
+        mut event: Event,
+    ) -> Result<EventAndInsights> {
+        let subject = if let Some(subject) = &self.config.subject {
+            Some(subject.clone())
+        } else {
+            event
+                .data
+                .borrow_dependent()
+                .meta()
+                .get_str(self.config.subject_meta.as_str())
+                .map(ToString::to_string)
+        };
+        let mut errors = Vec::new();
+        if let Some(subject) = subject {
+            let ingest_ns = event.ingest_ns;
+            match self.schema(&subject, ingest_ns) {
+                Ok(schema) => {
+                    for value in event.value_iter() {
+                        validate(schema, value, "", &mut errors);
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        } else {
+            errors.push(format!(
+                "No subject found in `${}`",
+                self.config.subject_meta
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(event.into())
+        } else if self.config.on_failure == OnFailure::Drop {
+            Ok(EventAndInsights::default())
+        } else {
+            event.data.with_dependent_mut(|_, parsed| {
+                if let Some(meta) = parsed.meta_mut().as_object_mut() {
+                    let errors: Vec<Value> = errors.into_iter().map(Value::from).collect();
+                    meta.insert(SCHEMA_ERRORS.into(), Value::from(errors));
+                }
+            });
+            Ok(vec![(ERR, event)].into())
+        }
+    }
+}
+
+fn type_matches(expected: &str, value: &Value) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_str(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.cast_f64().is_some(),
+        "boolean" => value.is_bool(),
+        "null" => value.is_null(),
+        _ => false,
+    }
+}
+
+fn value_eq(schema_value: &OwnedValue, value: &Value) -> bool {
+    // compare via the owned representation to avoid lifetime and type juggling
+    *schema_value == OwnedValue::from(value.clone_static())
+}
+
+/// Validates `value` against a JSON schema, collecting all violations in `errors`
+///
+/// Supports `type`, `enum`, `const`, `properties`, `required`,
+/// `additionalProperties`, `items`, `minItems`, `maxItems`, `minLength`,
+/// `maxLength`, `minimum` and `maximum`.
+pub fn validate(schema: &OwnedValue, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let at = if path.is_empty() { "/" } else { path };
+    if let Some(t) = schema.get("type") {
+        let ok = if let Some(t) = t.as_str() {
+            type_matches(t, value)
+        } else if let Some(ts) = t.as_array() {
+            ts.iter()
+                .filter_map(OwnedValue::as_str)
+                .any(|t| type_matches(t, value))
+        } else {
+            true
+        };
+        if !ok {
+            errors.push(format!("{}: expected type {}", at, t.encode()));
+            // no point in checking further constraints
+            return;
+        }
+    }
+    if let Some(options) = schema.get("enum").and_then(OwnedValue::as_array) {
+        if !options.iter().any(|o| value_eq(o, value)) {
+            errors.push(format!("{}: value is not one of {:?}", at, options));
+        }
+    }
+    if let Some(c) = schema.get("const") {
+        if !value_eq(c, value) {
+            errors.push(format!("{}: value must be {}", at, c.encode()));
+        }
+    }
+    if let Some(obj) = value.as_object() {
+        if let Some(required) = schema.get("required").and_then(OwnedValue::as_array) {
+            for r in required.iter().filter_map(OwnedValue::as_str) {
+                if !obj.contains_key(r) {
+                    errors.push(format!("{}: missing required field `{}`", at, r));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(OwnedValue::as_object);
+        let additional = schema.get("additionalProperties");
+        for (k, v) in obj.iter() {
+            let child = format!("{}/{}", path, k);
+            if let Some(s) = properties.and_then(|p| p.get(&**k)) {
+                validate(s, v, &child, errors);
+            } else if let Some(additional) = additional {
+                if additional.as_bool() == Some(false) {
+                    errors.push(format!("{}: additional field `{}` not allowed", at, k));
+                } else if additional.is_object() {
+                    validate(additional, v, &child, errors);
+                }
+            }
+        }
+    }
+    if let Some(arr) = value.as_array() {
+        if let Some(items) = schema.get("items") {
+            for (i, v) in arr.iter().enumerate() {
+                validate(items, v, &format!("{}/{}", path, i), errors);
+            }
+        }
+        if let Some(min) = schema.get("minItems").and_then(OwnedValue::as_usize) {
+            if arr.len() < min {
+                errors.push(format!("{}: expected at least {} items", at, min));
+            }
+        }
+        if let Some(max) = schema.get("maxItems").and_then(OwnedValue::as_usize) {
+            if arr.len() > max {
+                errors.push(format!("{}: expected at most {} items", at, max));
+            }
+        }
+    }
+    if let Some(s) = value.as_str() {
+        let len = s.chars().count();
+        if let Some(min) = schema.get("minLength").and_then(OwnedValue::as_usize) {
+            if len < min {
+                errors.push(format!("{}: expected at least {} characters", at, min));
+            }
+        }
+        if let Some(max) = schema.get("maxLength").and_then(OwnedValue::as_usize) {
+            if len > max {
+                errors.push(format!("{}: expected at most {} characters", at, max));
+            }
+        }
+    }
+    if let Some(n) = value.cast_f64() {
+        if let Some(min) = schema.get("minimum").and_then(OwnedValue::cast_f64) {
+            if n < min {
+                errors.push(format!("{}: {} is less than {}", at, n, min));
+            }
+        }
+        if let Some(max) = schema.get("maximum").and_then(OwnedValue::cast_f64) {
+            if n > max {
+                errors.push(format!("{}: {} is greater than {}", at, n, max));
+            }
+        }
+    }
+}
+
+/// Checks if events valid under `old` are still valid under `new`
+///
+/// This is a structural check covering the most common evolution mistakes:
+/// new required fields, changed types, removed enum options and newly
+/// forbidden additional properties.
+///
+/// # Errors
+/// with the reason if `new` is not backward compatible
+pub fn is_backward_compatible(
+    old: &OwnedValue,
+    new: &OwnedValue,
+    path: &str,
+) -> std::result::Result<(), String> {
+    let at = if path.is_empty() { "/" } else { path };
+    if let (Some(o), Some(n)) = (old.get("type"), new.get("type")) {
+        let types = |v: &OwnedValue| -> Vec<String> {
+            if let Some(s) = v.as_str() {
+                vec![s.to_string()]
+            } else {
+                v.as_array()
+                    .map(|a| {
+                        a.iter()
+                            .filter_map(OwnedValue::as_str)
+                            .map(ToString::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            }
+        };
+        let new_types = types(n);
+        for t in types(o) {
+            // integers are still valid numbers
+            if !new_types.contains(&t)
+                && !(t == "integer" && new_types.iter().any(|n| n == "number"))
+            {
+                return Err(format!("{}: type `{}` is no longer allowed", at, t));
+            }
+        }
+    }
+    if let Some(new_required) = new.get("required").and_then(OwnedValue::as_array) {
+        let old_required = old.get("required").and_then(OwnedValue::as_array);
+        for r in new_required.iter().filter_map(OwnedValue::as_str) {
+            let was_required =
+                old_required.map_or(false, |o| o.iter().any(|v| v.as_str() == Some(r)));
+            if !was_required {
+                return Err(format!("{}: field `{}` became required", at, r));
+            }
+        }
+    }
+    if let Some(new_enum) = new.get("enum").and_then(OwnedValue::as_array) {
+        match old.get("enum").and_then(OwnedValue::as_array) {
+            Some(old_enum) => {
+                if let Some(missing) = old_enum.iter().find(|o| !new_enum.contains(o)) {
+                    return Err(format!(
+                        "{}: enum option {} was removed",
+                        at,
+                        missing.encode()
+                    ));
+                }
+            }
+            None => return Err(format!("{}: value was restricted to an enum", at)),
+        }
+    }
+    if new
+        .get("additionalProperties")
+        .and_then(OwnedValue::as_bool)
+        == Some(false)
+        && old
+            .get("additionalProperties")
+            .and_then(OwnedValue::as_bool)
+            != Some(false)
+    {
+        return Err(format!(
+            "{}: additional properties are no longer allowed",
+            at
+        ));
+    }
+    if let (Some(o), Some(n)) = (
+        old.get("properties").and_then(OwnedValue::as_object),
+        new.get("properties").and_then(OwnedValue::as_object),
+    ) {
+        for (k, old_prop) in o.iter() {
+            if let Some(new_prop) = n.get(k) {
+                is_backward_compatible(old_prop, new_prop, &format!("{}/{}", path, k))?;
+            }
+        }
+    }
+    if let (Some(o), Some(n)) = (old.get("items"), new.get("items")) {
+        is_backward_compatible(o, n, &format!("{}/items", path))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[derive(Debug, Clone, Default)]
+    struct StaticRegistry {
+        versions: Arc<Mutex<Vec<(u64, OwnedValue)>>>,
+        fetches: Arc<AtomicUsize>,
+    }
+
+    impl Registry for StaticRegistry {
+        fn fetch(&self, _subject: &str) -> Result<(u64, OwnedValue)> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            self.versions
+                .lock()?
+                .last()
+                .cloned()
+                .ok_or_else(|| Error::from("no schema"))
+        }
+    }
+
+    fn schema(s: &str) -> OwnedValue {
+        parse_schema(s).expect("invalid test schema")
+    }
+
+    fn config() -> Config {
+        Config {
+            url: "http://localhost:8081".to_string(),
+            registry: Flavor::Confluent,
+            group: d_group(),
+            subject: Some("snot".to_string()),
+            subject_meta: d_subject_meta(),
+            cache_ttl_s: 1,
+            timeout_ms: d_timeout(),
+            on_failure: OnFailure::Route,
+            compatibility: Compatibility::Backward,
+        }
+    }
+
+    /// waits for the fetch of `subject` in flight and returns its schema
+    fn fetched(
+        op: &mut SchemaRegistry,
+        subject: &str,
+        now_ns: u64,
+    ) -> std::result::Result<OwnedValue, String> {
+        loop {
+            let schema = op.schema(subject, now_ns).map(Clone::clone);
+            if !op.refreshing.contains(subject) {
+                return schema;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    const V1: &str = r#"{"type":"object","required":["name"],"properties":{"name":{"type":"string"},"age":{"type":"integer","minimum":0}}}"#;
+
+    #[test]
+    fn validates_events() {
+        let mut errors = vec![];
+        let s = schema(V1);
+        validate(&s, &literal!({"name": "badger", "age": 3}), "", &mut errors);
+        assert!(errors.is_empty());
+        validate(&s, &literal!({"age": -1}), "", &mut errors);
+        assert_eq!(2, errors.len());
+        errors.clear();
+        validate(&s, &literal!({"name": 42}), "", &mut errors);
+        assert_eq!(vec!["/name: expected type \"string\"".to_string()], errors);
+    }
+
+    #[test]
+    fn backward_compatibility() {
+        let v1 = schema(V1);
+        let added_optional = schema(
+            r#"{"type":"object","required":["name"],"properties":{"name":{"type":"string"},"age":{"type":"number"},"city":{"type":"string"}}}"#,
+        );
+        assert!(is_backward_compatible(&v1, &added_optional, "").is_ok());
+        let added_required = schema(
+            r#"{"type":"object","required":["name","city"],"properties":{"name":{"type":"string"}}}"#,
+        );
+        assert!(is_backward_compatible(&v1, &added_required, "").is_err());
+        let changed_type = schema(
+            r#"{"type":"object","required":["name"],"properties":{"name":{"type":"integer"}}}"#,
+        );
+        assert_eq!(
+            Err("/name: type `string` is no longer allowed".to_string()),
+            is_backward_compatible(&v1, &changed_type, "")
+        );
+    }
+
+    #[test]
+    fn routes_and_refuses_incompatible_versions() -> Result<()> {
+        let versions = Arc::new(Mutex::new(vec![(1, schema(V1))]));
+        let registry = StaticRegistry {
+            versions: versions.clone(),
+            ..StaticRegistry::default()
+        };
+        let mut op = SchemaRegistry::new(config(), Arc::new(registry));
+        let mut state = Value::null();
+
+        fetched(&mut op, "snot", 0)?;
+
+        let event = Event {
+            ingest_ns: 1,
+            data: (literal!({"name": "badger"}), Value::object()).into(),
+            ..Event::default()
+        };
+        let mut r = op.on_event(0, "in", &mut state, event)?.events;
+        assert_eq!(Some(OUT), r.pop().map(|(port, _)| port));
+
+        let event = Event {
+            ingest_ns: 2,
+            data: (literal!({"snot": "badger"}), Value::object()).into(),
+            ..Event::default()
+        };
+        let (port, event) = op
+            .on_event(0, "in", &mut state, event)?
+            .events
+            .pop()
+            .expect("no results");
+        assert_eq!(ERR, port);
+        assert!(event
+            .data
+            .borrow_dependent()
+            .meta()
+            .get(SCHEMA_ERRORS)
+            .is_some());
+
+        // an incompatible version gets refused after the ttl expired
+        versions
+            .lock()?
+            .push((2, schema(r#"{"type":"object","required":["name","city"]}"#)));
+        let event = Event {
+            ingest_ns: 2_000_000_000,
+            data: (literal!({"name": "badger"}), Value::object()).into(),
+            ..Event::default()
+        };
+        let mut r = op.on_event(0, "in", &mut state, event)?.events;
+        assert_eq!(Some(OUT), r.pop().map(|(port, _)| port));
+        fetched(&mut op, "snot", 2_000_000_001)?;
+        assert_eq!(Some(1), op.cache.get("snot").map(|c| c.version));
+        Ok(())
+    }
+
+    #[test]
+    fn routes_events_until_the_schema_arrived() -> Result<()> {
+        let registry = StaticRegistry::default();
+        registry.versions.lock()?.push((1, schema(V1)));
+        let mut config = config();
+        config.subject = None;
+        let mut op = SchemaRegistry::new(config, Arc::new(registry));
+        let mut state = Value::null();
+        let event = || Event {
+            data: (literal!({"name": "badger"}), literal!({"subject": "snot"})).into(),
+            ..Event::default()
+        };
+
+        let (port, event1) = op
+            .on_event(0, "in", &mut state, event())?
+            .events
+            .pop()
+            .expect("no results");
+        assert_eq!(ERR, port);
+        assert_eq!(
+            Some(&literal!(["Schema snot is being fetched"])),
+            event1.data.borrow_dependent().meta().get(SCHEMA_ERRORS)
+        );
+        fetched(&mut op, "snot", 0)?;
+        let mut r = op.on_event(0, "in", &mut state, event())?.events;
+        assert_eq!(Some(OUT), r.pop().map(|(port, _)| port));
+        Ok(())
+    }
+
+    #[test]
+    fn refreshes_in_the_background() -> Result<()> {
+        let registry = StaticRegistry::default();
+        registry.versions.lock()?.push((1, schema(V1)));
+        let mut op = SchemaRegistry::new(config(), Arc::new(registry.clone()));
+        fetched(&mut op, "snot", 1)?;
+        registry
+            .versions
+            .lock()?
+            .push((2, schema(r#"{"type":"object"}"#)));
+        // the stale version is used until the refreshed one arrived
+        assert_eq!(Some(&schema(V1)), op.schema("snot", 2_000_000_000).ok());
+        fetched(&mut op, "snot", 2_000_000_001)?;
+        assert_eq!(Some(2), op.cache.get("snot").map(|c| c.version));
+        assert_eq!(2, registry.fetches.load(Ordering::SeqCst));
+        Ok(())
+    }
+
+    #[test]
+    fn backs_off_failed_fetches() -> Result<()> {
+        let registry = StaticRegistry::default();
+        let mut config = config();
+        config.cache_ttl_s = 10;
+        let mut op = SchemaRegistry::new(config, Arc::new(registry.clone()));
+        assert!(fetched(&mut op, "snot", 0).is_err());
+        // the registry isn't asked again before the backoff passed
+        assert!(op.schema("snot", BACKOFF_NS - 1).is_err());
+        assert!(op.refreshing.is_empty());
+        assert_eq!(1, registry.fetches.load(Ordering::SeqCst));
+        assert!(op.schema("snot", BACKOFF_NS).is_err());
+        assert!(fetched(&mut op, "snot", BACKOFF_NS).is_err());
+        assert_eq!(2, registry.fetches.load(Ordering::SeqCst));
+        // the backoff doubles
+        assert_eq!(
+            Some(BACKOFF_NS * 3),
+            op.failed.get("snot").map(|f| f.retry_ns)
+        );
+        registry.versions.lock()?.push((1, schema(V1)));
+        assert!(op.schema("snot", BACKOFF_NS * 3).is_err());
+        assert!(fetched(&mut op, "snot", BACKOFF_NS * 3).is_ok());
+        assert!(op.failed.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn encodes_subjects() -> Result<()> {
+        let mut registry = HttpRegistry {
+            url: "http://localhost:8081/".to_string(),
+            flavor: Flavor::Confluent,
+            group: d_group(),
+            timeout: Duration::from_millis(d_timeout()),
+        };
+        assert_eq!(
+            "http://localhost:8081/subjects/snot%2Fbadger%20value/versions/latest",
+            registry.url("snot/badger value")?.as_str()
+        );
+        registry.flavor = Flavor::Apicurio;
+        assert_eq!(
+            "http://localhost:8081/apis/registry/v2/groups/default/artifacts/snot%3F",
+            registry.url("snot?")?.as_str()
+        );
+        Ok(())
+    }
+}
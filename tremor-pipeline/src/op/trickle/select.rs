@@ -33,8 +33,9 @@ use tremor_script::{
     query::StmtRental,
     Value,
 };
-use tremor_script::{ast::NodeMetas, utils::sorted_serialize};
+use tremor_script::{ast::NodeMetas, utils::sorted_serialize, Object};
 use tremor_script::{interpreter::LocalStack, query::StmtRentalWrapper};
+use tremor_value::literal;
 
 #[derive(Debug, Clone)]
 pub struct GroupData<'groups> {
@@ -119,6 +120,9 @@ pub trait WindowTrait: std::fmt::Debug {
     /// decreasing this value will guard against runwaway memory growth
     /// when faced with unexpected huge cardinalities for grouping dimensions
     fn max_groups(&self) -> u64;
+    /// whether aggregate functions accumulate values in columns that are
+    /// aggregated in bulk
+    fn columnar(&self) -> bool;
 }
 
 #[derive(Debug)]
@@ -162,6 +166,9 @@ impl WindowImpl {
     // do not emit empty windows by default
     // this preserves backward compatibility
     pub const DEFAULT_EMIT_EMPTY_WINDOWS: bool = false;
+
+    // aggregate value by value by default
+    pub const DEFAULT_COLUMNAR: bool = false;
 }
 
 impl std::default::Default for WindowImpl {
@@ -170,6 +177,7 @@ impl std::default::Default for WindowImpl {
             interval: 15_000_000_000,
             emit_empty_windows: Self::DEFAULT_EMIT_EMPTY_WINDOWS,
             max_groups: Self::DEFAULT_MAX_GROUPS,
+            columnar: Self::DEFAULT_COLUMNAR,
             next_window: None,
             events: 0,
             script: None,
@@ -210,6 +218,13 @@ impl WindowTrait for WindowImpl {
             Self::No(w) => w.max_groups(),
         }
     }
+    fn columnar(&self) -> bool {
+        match self {
+            Self::TumblingTimeBased(w) => w.columnar(),
+            Self::TumblingCountBased(w) => w.columnar(),
+            Self::No(w) => w.columnar(),
+        }
+    }
 }
 
 impl From<NoWindow> for WindowImpl {
@@ -259,6 +274,9 @@ impl WindowTrait for NoWindow {
     fn max_groups(&self) -> u64 {
         u64::MAX
     }
+    fn columnar(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Default, Debug, Clone)]
@@ -266,6 +284,7 @@ pub struct TumblingWindowOnTime {
     next_window: Option<u64>,
     emit_empty_windows: bool,
     max_groups: u64,
+    columnar: bool,
     events: u64,
     interval: u64,
     ttl: Option<u64>,
@@ -276,6 +295,7 @@ impl TumblingWindowOnTime {
         interval: u64,
         emit_empty_windows: bool,
         max_groups: u64,
+        columnar: bool,
         ttl: Option<u64>,
         script: Option<&WindowDecl>,
         stmt: &StmtRentalWrapper,
@@ -291,6 +311,7 @@ impl TumblingWindowOnTime {
             next_window: None,
             emit_empty_windows,
             max_groups,
+            columnar,
             events: 0,
             interval,
             ttl,
@@ -334,6 +355,9 @@ impl WindowTrait for TumblingWindowOnTime {
     fn max_groups(&self) -> u64 {
         self.max_groups
     }
+    fn columnar(&self) -> bool {
+        self.columnar
+    }
     fn on_event(&mut self, event: &Event) -> Result<WindowEvent> {
         self.events += 1; // count events to check if we should emit, as we avoid to emit if we have no events
         let time = self
@@ -399,6 +423,7 @@ impl WindowTrait for TumblingWindowOnTime {
 pub struct TumblingWindowOnNumber {
     count: u64,
     max_groups: u64,
+    columnar: bool,
     size: u64,
     ttl: Option<u64>,
     script: Option<rentals::Window>,
@@ -408,6 +433,7 @@ impl TumblingWindowOnNumber {
     pub fn from_stmt(
         size: u64,
         max_groups: u64,
+        columnar: bool,
         ttl: Option<u64>,
         script: Option<&WindowDecl>,
         stmt: &StmtRentalWrapper,
@@ -424,6 +450,7 @@ impl TumblingWindowOnNumber {
         Self {
             count: 0,
             max_groups,
+            columnar,
             size,
             script,
             ttl,
@@ -437,6 +464,9 @@ impl WindowTrait for TumblingWindowOnNumber {
     fn max_groups(&self) -> u64 {
         self.max_groups
     }
+    fn columnar(&self) -> bool {
+        self.columnar
+    }
     fn on_event(&mut self, event: &Event) -> Result<WindowEvent> {
         let count = self
             .script
@@ -652,7 +682,12 @@ fn get_or_create_group<'window>(
         RawEntryMut::Occupied(e) => e.into_key_value(),
         RawEntryMut::Vacant(e) if groups_len < window.window_impl.max_groups() => {
             let k = group_str.to_string();
-            let aggrs = aggregates.to_vec();
+            let mut aggrs = aggregates.to_vec();
+            if window_impl.columnar() {
+                for aggr in &mut aggrs {
+                    aggr.invocable.columnar();
+                }
+            }
             let v = last_groups.remove(group_str).unwrap_or_else(|| {
                 GroupData {
                     window: window_impl.clone(),
@@ -1657,6 +1692,36 @@ impl Operator for TrickleSelect {
     fn handles_signal(&self) -> bool {
         true
     }
+
+    /// The current aggregates of every group, per window
+    fn snapshot(&self) -> Option<Value<'static>> {
+        let mut windows = Object::with_capacity(self.windows.len());
+        for window in &self.windows {
+            let groups: Vec<Value<'static>> = window
+                .dims
+                .groups
+                .values()
+                .map(|group| {
+                    let aggregates: Vec<Value<'static>> = group
+                        .aggrs
+                        .iter()
+                        .map(|aggr| {
+                            aggr.invocable
+                                .clone()
+                                .emit()
+                                .unwrap_or_else(|_| Value::null())
+                        })
+                        .collect();
+                    literal!({
+                        "group": group.group.clone(),
+                        "aggregates": aggregates
+                    })
+                })
+                .collect();
+            windows.insert(window.name.clone().into(), Value::from(groups));
+        }
+        Some(Value::from(windows))
+    }
 }
 
 #[cfg(test)]
@@ -1749,6 +1814,7 @@ mod test {
                     emit_empty_windows: false,
                     ttl: None,
                     max_groups: WindowImpl::DEFAULT_MAX_GROUPS,
+                    columnar: WindowImpl::DEFAULT_COLUMNAR,
                     interval: 15_000_000_000,
                     next_window: None,
                     events: 0,
@@ -1762,6 +1828,7 @@ mod test {
                     emit_empty_windows: false,
                     ttl: None,
                     max_groups: WindowImpl::DEFAULT_MAX_GROUPS,
+                    columnar: WindowImpl::DEFAULT_COLUMNAR,
                     interval: 30_000_000_000,
                     next_window: None,
                     events: 0,
@@ -1918,6 +1985,47 @@ mod test {
         Ok(TrickleSelect::with_stmt(42, id, &groups, windows, &stmt)?)
     }
 
+    #[test]
+    fn columnar_window() -> Result<()> {
+        let query = |columnar: bool| {
+            format!(
+                r#"
+            define tumbling window w
+            with
+                size = 16,
+                columnar = {}
+            end;
+            select aggr::stats::sum(event.x) from in[w] into out;
+            "#,
+                columnar
+            )
+        };
+        // 2^53 + 1 rounds back to 2^53, summing one value after the other
+        // loses the ones, the lanes of the columnar sum keep them apart
+        let mut values = vec![9_007_199_254_740_992.0];
+        values.resize(8, 1.0);
+        values.push(-9_007_199_254_740_992.0);
+        values.resize(16, 0.0);
+        for (columnar, expected) in &[(false, 0.0), (true, 7.0)] {
+            let mut select = select_stmt_from_query(&query(*columnar))?;
+            assert_eq!(*columnar, select.windows[0].window_impl.columnar());
+            let mut state = Value::null();
+            let mut eis = EventAndInsights::default();
+            for (i, x) in (0_u64..).zip(&values) {
+                let event = Event {
+                    id: (1, 1, i).into(),
+                    ingest_ns: i,
+                    data: Value::from(json!({ "x": x })).into(),
+                    ..Event::default()
+                };
+                eis = select.on_event(42, "in", &mut state, event)?;
+            }
+            assert_eq!(1, eis.events.len());
+            assert_eq!(*eis.events[0].1.data.suffix().value(), *expected);
+        }
+        Ok(())
+    }
+
     fn test_tick(ns: u64) -> Event {
         Event {
             id: EventId::new(1, 1, ns),
@@ -2609,6 +2717,7 @@ mod test {
             10 * 1_000_000_000,
             WindowImpl::DEFAULT_EMIT_EMPTY_WINDOWS,
             WindowImpl::DEFAULT_MAX_GROUPS,
+            WindowImpl::DEFAULT_COLUMNAR,
             None,
             None,
             &stmt,
@@ -2694,6 +2803,7 @@ mod test {
             interval,
             WindowImpl::DEFAULT_EMIT_EMPTY_WINDOWS,
             WindowImpl::DEFAULT_MAX_GROUPS,
+            WindowImpl::DEFAULT_COLUMNAR,
             None,
             Some(&window_decl),
             &stmt,
@@ -2750,6 +2860,7 @@ mod test {
             100,
             WindowImpl::DEFAULT_EMIT_EMPTY_WINDOWS,
             WindowImpl::DEFAULT_MAX_GROUPS,
+            WindowImpl::DEFAULT_COLUMNAR,
             None,
             None,
             &stmt,
@@ -2812,6 +2923,7 @@ mod test {
             100,
             true,
             WindowImpl::DEFAULT_MAX_GROUPS,
+            WindowImpl::DEFAULT_COLUMNAR,
             None,
             None,
             &stmt,
@@ -2909,8 +3021,14 @@ mod test {
     #[test]
     fn tumbling_window_on_number_emit() -> Result<()> {
         let stmt = stmt_rental()?;
-        let mut window =
-            TumblingWindowOnNumber::from_stmt(3, WindowImpl::DEFAULT_MAX_GROUPS, None, None, &stmt);
+        let mut window = TumblingWindowOnNumber::from_stmt(
+            3,
+            WindowImpl::DEFAULT_MAX_GROUPS,
+            WindowImpl::DEFAULT_COLUMNAR,
+            None,
+            None,
+            &stmt,
+        );
         // do not emit yet
         assert_eq!(
             WindowEvent {
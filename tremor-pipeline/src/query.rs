@@ -14,6 +14,7 @@
 
 use crate::op::prelude::{ERR, IN, METRICS, OUT};
 use crate::op::trickle::select::WindowImpl;
+use crate::recording::Recorder;
 use crate::{
     common_cow, op, ConfigGraph, NodeConfig, NodeKind, Operator, OperatorNode, PortIndexMap,
 };
@@ -111,6 +112,11 @@ pub(crate) fn window_decl_to_impl<'script>(
                 .get(WindowDecl::EMIT_EMPTY_WINDOWS)
                 .and_then(Value::as_bool)
                 .unwrap_or(WindowImpl::DEFAULT_EMIT_EMPTY_WINDOWS);
+            let columnar = d
+                .params
+                .get(WindowDecl::COLUMNAR)
+                .and_then(Value::as_bool)
+                .unwrap_or(WindowImpl::DEFAULT_COLUMNAR);
 
             match (
                 d.params.get(WindowDecl::INTERVAL).and_then(Value::as_u64),
@@ -120,13 +126,14 @@ pub(crate) fn window_decl_to_impl<'script>(
                     interval,
                     emit_empty_windows,
                     max_groups,
+                    columnar,
                     ttl,
                     script,
                     stmt,
                 )
                 .into()),
                 (None, Some(size)) => Ok(TumblingWindowOnNumber::from_stmt(
-                    size, max_groups, ttl, script, stmt,
+                    size, max_groups, columnar, ttl, script, stmt,
                 )
                 .into()),
                 (Some(_), Some(_)) => Err(Error::from(
@@ -186,7 +193,7 @@ impl Query {
     /// if the graph can not be turned into a pipeline
     #[allow(clippy::too_many_lines)]
     pub fn to_pipe(&self, idgen: &mut OperatorIdGen) -> Result<crate::ExecutableGraph> {
-        use crate::{ExecutableGraph, NodeMetrics, State};
+        use crate::{executable_graph::LatencyBudget, ExecutableGraph, NodeMetrics, State};
         use std::iter;
 
         let query = self.0.suffix();
@@ -204,6 +211,26 @@ impl Query {
             .and_then(Value::as_u64)
             .map(|i| i * 1_000_000_000);
 
+        let latency_budget = query
+            .config
+            .get("latency_budget_ms")
+            .and_then(Value::as_u64)
+            .map(|budget| {
+                let objective = query
+                    .config
+                    .get("latency_objective")
+                    .and_then(Value::cast_f64)
+                    .unwrap_or(0.99);
+                LatencyBudget::new(budget * 1_000_000, objective)
+            })
+            .transpose()?;
+
+        let recorder = query
+            .config
+            .get("record_s")
+            .and_then(Value::as_u64)
+            .map(|s| Recorder::new(s * 1_000_000_000));
+
         let pipeline_id = query
             .config
             .get("id")
@@ -668,6 +695,7 @@ impl Query {
                 id: pipeline_id.to_string(), // TODO make configurable
                 metrics_idx,
                 last_metrics: 0,
+                last_event_ids: vec![None; graph.len()],
                 state: State::new(iter::repeat(Value::null()).take(graph.len()).collect()),
                 graph,
                 inputs: inputs2,
@@ -675,6 +703,8 @@ impl Query {
                 contraflow,
                 signalflow,
                 metric_interval,
+                latency_budget,
+                recorder,
                 insights: Vec::new(),
                 source: Some(self.0.source.clone()),
                 dot: format!("{}", dot),
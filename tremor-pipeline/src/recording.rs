@@ -0,0 +1,353 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording of operator inputs and state for time-travel debugging
+//!
+//! A pipeline with `#!config record_s = 60` records every event an operator
+//! receives, together with the state of the operator after handling it, for
+//! the last `record_s` seconds and up to `MAX_STEPS` steps. Recording is
+//! expensive, it copies every event and the state of stateful operators like
+//! `select` with every step, so it is meant for diagnosing operator bugs.
+//!
+//! The recording is fetched as a value, stored and later loaded with
+//! [`Recording::from_value`] to step through it with a [`Cursor`].
+
+use crate::errors::{Error, Result};
+use crate::Event;
+use std::collections::VecDeque;
+use tremor_script::prelude::*;
+
+/// Upper bound of the steps kept, to bound the memory under high event rates
+pub const MAX_STEPS: usize = 100_000;
+
+/// An event handled by an operator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// position of the step since the recording started
+    pub seq: u64,
+    /// nanoseconds since the epoch the step was recorded at
+    pub time: u64,
+    /// id of the operator
+    pub node: String,
+    /// port the event was received on
+    pub port: String,
+    /// `source:stream:event` id of the event
+    pub event_id: String,
+    /// the event
+    pub data: Value<'static>,
+    /// the metadata of the event
+    pub meta: Value<'static>,
+    /// the pipeline state of the operator after handling the event
+    pub state: Value<'static>,
+    /// the internal state of the operator after handling the event, for
+    /// operators exposing it
+    pub snapshot: Option<Value<'static>>,
+}
+
+impl Step {
+    fn to_value(&self) -> Value<'static> {
+        literal!({
+            "seq": self.seq,
+            "time": self.time,
+            "node": self.node.clone(),
+            "port": self.port.clone(),
+            "event_id": self.event_id.clone(),
+            "data": self.data.clone(),
+            "meta": self.meta.clone(),
+            "state": self.state.clone(),
+            "snapshot": self.snapshot.clone().unwrap_or_else(Value::null)
+        })
+    }
+
+    fn from_value(value: &Value) -> Result<Self> {
+        let missing = |field: &str| Error::from(format!("Recorded step without `{}`", field));
+        let u64_field = |field: &str| value.get_u64(field).ok_or_else(|| missing(field));
+        let str_field = |field: &str| {
+            value
+                .get_str(field)
+                .map(String::from)
+                .ok_or_else(|| missing(field))
+        };
+        let value_field = |field: &str| {
+            value
+                .get(field)
+                .map(Value::clone_static)
+                .ok_or_else(|| missing(field))
+        };
+        Ok(Self {
+            seq: u64_field("seq")?,
+            time: u64_field("time")?,
+            node: str_field("node")?,
+            port: str_field("port")?,
+            event_id: str_field("event_id")?,
+            data: value_field("data")?,
+            meta: value_field("meta")?,
+            state: value_field("state")?,
+            snapshot: value
+                .get("snapshot")
+                .filter(|s| !s.is_null())
+                .map(Value::clone_static),
+        })
+    }
+}
+
+/// Records the steps of the last `window_ns` nanoseconds
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    window_ns: u64,
+    next_seq: u64,
+    steps: VecDeque<Step>,
+}
+
+impl Recorder {
+    pub(crate) fn new(window_ns: u64) -> Self {
+        Self {
+            window_ns,
+            next_seq: 0,
+            steps: VecDeque::new(),
+        }
+    }
+
+    /// Records an event received by `node` on `port` at `time`, as returned by
+    /// `recorded_event`, with the state of the operator after handling it
+    pub(crate) fn record(
+        &mut self,
+        time: u64,
+        node: &str,
+        port: &str,
+        (event_id, (data, meta)): (String, (Value<'static>, Value<'static>)),
+        state: Value<'static>,
+        snapshot: Option<Value<'static>>,
+    ) {
+        let oldest = time.saturating_sub(self.window_ns);
+        while self
+            .steps
+            .front()
+            .map_or(false, |s| s.time < oldest || self.steps.len() >= MAX_STEPS)
+        {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(Step {
+            seq: self.next_seq,
+            time,
+            node: node.to_string(),
+            port: port.to_string(),
+            event_id,
+            data,
+            meta,
+            state,
+            snapshot,
+        });
+        self.next_seq += 1;
+    }
+
+    /// The steps recorded so far
+    pub(crate) fn recording(&self, pipeline: &str) -> Recording {
+        Recording {
+            pipeline: pipeline.to_string(),
+            steps: self.steps.iter().cloned().collect(),
+        }
+    }
+}
+
+/// The id and data of an event, as recorded
+pub(crate) fn recorded_event(event: &Event) -> (String, (Value<'static>, Value<'static>)) {
+    let id = format!(
+        "{}:{}:{}",
+        event.id.source_id, event.id.stream_id, event.id.event_id
+    );
+    let data = event.data.suffix();
+    (
+        id,
+        (data.value().clone_static(), data.meta().clone_static()),
+    )
+}
+
+/// The steps recorded by a pipeline
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    /// id of the pipeline
+    pub pipeline: String,
+    /// the steps in the order they were taken
+    pub steps: Vec<Step>,
+}
+
+impl Recording {
+    /// The recording as a value, to be stored
+    #[must_use]
+    pub fn to_value(&self) -> Value<'static> {
+        let steps: Vec<Value<'static>> = self.steps.iter().map(Step::to_value).collect();
+        literal!({
+            "pipeline": self.pipeline.clone(),
+            "steps": steps
+        })
+    }
+
+    /// Loads a stored recording
+    ///
+    /// # Errors
+    /// if the value isn't a recording
+    pub fn from_value(value: &Value) -> Result<Self> {
+        let pipeline = value
+            .get_str("pipeline")
+            .ok_or_else(|| Error::from("Recording without `pipeline`"))?;
+        let steps = value
+            .get_array("steps")
+            .ok_or_else(|| Error::from("Recording without `steps`"))?
+            .iter()
+            .map(Step::from_value)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            pipeline: pipeline.to_string(),
+            steps,
+        })
+    }
+
+    /// A cursor on the first step
+    #[must_use]
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            steps: &self.steps,
+            pos: 0,
+        }
+    }
+}
+
+/// Steps through a recording, forwards and backwards
+#[derive(Debug, Clone)]
+pub struct Cursor<'recording> {
+    steps: &'recording [Step],
+    pos: usize,
+}
+
+impl<'recording> Cursor<'recording> {
+    /// The step at the cursor
+    #[must_use]
+    pub fn current(&self) -> Option<&'recording Step> {
+        self.steps.get(self.pos)
+    }
+
+    /// Moves to the next step
+    pub fn forward(&mut self) -> Option<&'recording Step> {
+        if self.pos + 1 < self.steps.len() {
+            self.pos += 1;
+            self.current()
+        } else {
+            None
+        }
+    }
+
+    /// Moves to the previous step
+    pub fn back(&mut self) -> Option<&'recording Step> {
+        if self.pos > 0 && !self.steps.is_empty() {
+            self.pos -= 1;
+            self.current()
+        } else {
+            None
+        }
+    }
+
+    /// Moves to the step with the sequence number `seq`
+    pub fn seek(&mut self, seq: u64) -> Option<&'recording Step> {
+        let pos = self.steps.binary_search_by_key(&seq, |s| s.seq).ok()?;
+        self.pos = pos;
+        self.current()
+    }
+
+    /// Moves to the next step of the operator `node`
+    pub fn forward_to(&mut self, node: &str) -> Option<&'recording Step> {
+        let offset = self
+            .steps
+            .iter()
+            .skip(self.pos + 1)
+            .position(|s| s.node == node)?;
+        self.pos += offset + 1;
+        self.current()
+    }
+
+    /// The last step of the operator `node` up to the cursor, holding the
+    /// state the operator was in at that point
+    #[must_use]
+    pub fn state_of(&self, node: &str) -> Option<&'recording Step> {
+        self.steps
+            .iter()
+            .take(self.pos + 1)
+            .rev()
+            .find(|s| s.node == node)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(recorder: &mut Recorder, time: u64, node: &str, count: u64) {
+        recorder.record(
+            time,
+            node,
+            "in",
+            (
+                format!("0:0:{}", count),
+                (Value::from(count), Value::object()),
+            ),
+            literal!({ "count": count }),
+            None,
+        );
+    }
+
+    #[test]
+    fn window() {
+        let mut recorder = Recorder::new(10);
+        record(&mut recorder, 1, "a", 1);
+        record(&mut recorder, 5, "b", 2);
+        record(&mut recorder, 12, "a", 3);
+        let recording = recorder.recording("p");
+        // the first step fell out of the window
+        let seqs: Vec<u64> = recording.steps.iter().map(|s| s.seq).collect();
+        assert_eq!(vec![1, 2], seqs);
+    }
+
+    #[test]
+    fn step_through() -> Result<()> {
+        let mut recorder = Recorder::new(u64::MAX);
+        record(&mut recorder, 1, "a", 1);
+        record(&mut recorder, 2, "b", 2);
+        record(&mut recorder, 3, "a", 3);
+        record(&mut recorder, 4, "b", 4);
+        // stored and loaded again
+        let recording = Recording::from_value(&recorder.recording("p").to_value())?;
+        assert_eq!(recorder.recording("p"), recording);
+
+        let mut cursor = recording.cursor();
+        assert_eq!(Some(0), cursor.current().map(|s| s.seq));
+        assert_eq!(None, cursor.back().map(|s| s.seq));
+        assert_eq!(Some(2), cursor.forward_to("a").map(|s| s.seq));
+        assert_eq!(
+            Some(&literal!({ "count": 2 })),
+            cursor.state_of("b").map(|s| &s.state)
+        );
+        assert_eq!(Some(3), cursor.forward().map(|s| s.seq));
+        assert_eq!(None, cursor.forward().map(|s| s.seq));
+        assert_eq!(None, cursor.forward_to("a").map(|s| s.seq));
+        assert_eq!(Some(1), cursor.seek(1).map(|s| s.seq));
+        assert_eq!(Some(0), cursor.back().map(|s| s.seq));
+        assert!(cursor.seek(7).is_none());
+
+        assert!(Recording::from_value(&literal!({"pipeline": "p"})).is_err());
+        assert!(
+            Recording::from_value(&literal!({"pipeline": "p", "steps": [{"seq": 1}]})).is_err()
+        );
+        Ok(())
+    }
+}
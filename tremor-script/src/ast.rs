@@ -22,6 +22,7 @@ pub mod eq;
 /// Query AST
 pub mod query;
 pub(crate) mod raw;
+pub(crate) mod schema;
 mod support;
 mod upable;
 /// collection of AST visitors
@@ -463,6 +464,11 @@ where
     docs: Docs<'script>,
     module: Vec<String>,
     possible_leaf: bool,
+    /// depth of `try` expressions being lowered, errors in them are caught at
+    /// runtime, so they aren't folded
+    in_try: usize,
+    /// Declared event schema, accesses to `event` are checked against it
+    pub(crate) schema: Option<schema::Schema>,
     fn_argc: usize,
     is_open: bool,
     file_offset: Location,
@@ -544,6 +550,8 @@ where
             docs: Docs::default(),
             module: Vec::new(),
             possible_leaf: false,
+            in_try: 0,
+            schema: None,
             fn_argc: 0,
             is_open: false,
             file_offset: Location::default(),
@@ -608,6 +616,36 @@ where
     fn warn(&mut self, warning: Warning) {
         self.warnings.insert(warning);
     }
+
+    /// Checks a read of `event` against the declared schema
+    fn check_event_path(&mut self, path: &EventPath) {
+        if let Some(Err(msg)) = self.schema.as_ref().map(|s| s.resolve(&path.segments)) {
+            let extent = path.extent(&self.meta);
+            self.warn(Warning::new_with_scope(extent, msg));
+        }
+    }
+
+    /// Checks a binary operation between an `event` path and a literal against
+    /// the declared schema
+    fn check_event_bin(&mut self, binary: &BinExpr) {
+        let (path, literal) = match (&binary.lhs, &binary.rhs) {
+            (ImutExprInt::Path(Path::Event(p)), ImutExprInt::Literal(l))
+            | (ImutExprInt::Literal(l), ImutExprInt::Path(Path::Event(p))) => (p, l),
+            _ => return,
+        };
+        let msg = match self.schema.as_ref().map(|s| s.resolve(&path.segments)) {
+            Some(Ok(Some(schema))) => schema.check_bin(
+                &schema::path_name(&path.segments),
+                binary.kind,
+                &literal.value,
+            ),
+            _ => None,
+        };
+        if let Some(msg) = msg {
+            let extent = binary.extent(&self.meta);
+            self.warn(Warning::new_with_scope(extent, msg));
+        }
+    }
 }
 
 /// A tremor script instance
@@ -1000,6 +1038,8 @@ pub enum ImutExprInt<'script> {
     Comprehension(Box<Comprehension<'script, Self>>),
     /// Merge
     Merge(Box<Merge<'script>>),
+    /// Try
+    Try(Box<Try<'script>>),
     /// Path
     Path(Path<'script>),
     /// A string literal
@@ -1706,6 +1746,22 @@ pub struct Merge<'script> {
 }
 impl_expr_mid!(Merge);
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+/// Encapsulates a try form
+pub struct Try<'script> {
+    /// Id
+    pub mid: usize,
+    /// Expression that might fail
+    pub expr: ImutExprInt<'script>,
+    /// Name of the error in the catch expression
+    pub err_name: Cow<'script, str>,
+    /// Local index of the error
+    pub err_idx: usize,
+    /// Expression evaluated with the error if `expr` fails
+    pub catch: ImutExprInt<'script>,
+}
+impl_expr_mid!(Try);
+
 #[derive(Clone, Debug, PartialEq, Serialize)]
 /// Encapsulates a structure comprehension form
 pub struct Comprehension<'script, Ex: Expression + 'script> {
@@ -169,6 +169,7 @@ impl<'script> BaseExpr for ImutExprInt<'script> {
             }
             ImutExprInt::Match(e) => e.s(meta),
             ImutExprInt::Merge(e) => e.s(meta),
+            ImutExprInt::Try(e) => e.s(meta),
             ImutExprInt::Patch(e) => e.s(meta),
             ImutExprInt::Path(e) => e.s(meta),
             ImutExprInt::Record(e) => e.s(meta),
@@ -191,6 +192,7 @@ impl<'script> BaseExpr for ImutExprInt<'script> {
             ImutExprInt::Literal(e) => e.e(meta),
             ImutExprInt::Match(e) => e.e(meta),
             ImutExprInt::Merge(e) => e.e(meta),
+            ImutExprInt::Try(e) => e.e(meta),
             ImutExprInt::Patch(e) => e.e(meta),
             ImutExprInt::Path(e) => e.e(meta),
             ImutExprInt::Recur(e) => e.e(meta),
@@ -216,6 +218,7 @@ impl<'script> BaseExpr for ImutExprInt<'script> {
             ImutExprInt::Literal(e) => e.mid(),
             ImutExprInt::Match(e) => e.mid(),
             ImutExprInt::Merge(e) => e.mid(),
+            ImutExprInt::Try(e) => e.mid(),
             ImutExprInt::Patch(e) => e.mid(),
             ImutExprInt::Path(e) => e.mid(),
             ImutExprInt::Recur(e) => e.mid(),
@@ -317,6 +320,7 @@ impl<'script> BaseExpr for ImutExprRaw<'script> {
             ImutExprRaw::Literal(e) => e.s(meta),
             ImutExprRaw::Match(e) => e.start,
             ImutExprRaw::Merge(e) => e.start,
+            ImutExprRaw::Try(e) => e.start,
             ImutExprRaw::Patch(e) => e.start,
             ImutExprRaw::Path(e) => e.s(meta),
             ImutExprRaw::Present { start, .. } => *start,
@@ -336,6 +340,7 @@ impl<'script> BaseExpr for ImutExprRaw<'script> {
             ImutExprRaw::Literal(e) => e.e(meta),
             ImutExprRaw::Match(e) => e.end,
             ImutExprRaw::Merge(e) => e.end,
+            ImutExprRaw::Try(e) => e.end,
             ImutExprRaw::Patch(e) => e.end,
             ImutExprRaw::Path(e) => e.e(meta),
             ImutExprRaw::Present { end, .. } => *end,
@@ -6,7 +6,7 @@ use super::{
     Field, ImutExpr, ImutExprInt, Invocable, Invoke, InvokeAggr, List, Literal, LocalPath, Match,
     Merge, MetadataPath, Patch, PatchOperation, Path, Pattern, PredicateClause, PredicatePattern,
     Record, RecordPattern, Recur, ReservedPath, Segment, StatePath, StrLitElement, StringLit,
-    TestExpr, TuplePattern, UnaryExpr,
+    TestExpr, Try, TuplePattern, UnaryExpr,
 };
 
 // Copyright 2020-2021, The Tremor Team
@@ -72,7 +72,7 @@ impl<'script> AstEq for ImutExprInt<'script> {
     fn ast_eq(&self, other: &Self) -> bool {
         use ImutExprInt::{
             Binary, Bytes, Comprehension, Invoke, Invoke1, Invoke2, Invoke3, InvokeAggr, List,
-            Literal, Local, Match, Merge, Patch, Path, Present, Record, Recur, String, Unary,
+            Literal, Local, Match, Merge, Patch, Path, Present, Record, Recur, String, Try, Unary,
         };
         match (self, other) {
             (Record(r1), Record(r2)) => r1.ast_eq(r2),
@@ -83,6 +83,7 @@ impl<'script> AstEq for ImutExprInt<'script> {
             (Match(m1), Match(m2)) => m1.ast_eq(m2),
             (Comprehension(c1), Comprehension(c2)) => c1.ast_eq(c2),
             (Merge(m1), Merge(m2)) => m1.ast_eq(m2),
+            (Try(t1), Try(t2)) => t1.ast_eq(t2),
             (Path(p1), Path(p2)) => p1.ast_eq(p2),
             // special case for `Path`(i.e. `LocalPath`) and `Local`
             // which can be considered the same if they reference the same local (without segments)
@@ -388,6 +389,14 @@ impl<'script> AstEq for Merge<'script> {
     }
 }
 
+impl<'script> AstEq for Try<'script> {
+    fn ast_eq(&self, other: &Self) -> bool {
+        self.err_idx == other.err_idx
+            && self.expr.ast_eq(&other.expr)
+            && self.catch.ast_eq(&other.catch)
+    }
+}
+
 impl<'script, Ex> AstEq for Comprehension<'script, Ex>
 where
     Ex: Expression + AstEq + 'script,
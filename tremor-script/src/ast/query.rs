@@ -273,6 +273,8 @@ impl<'script> WindowDecl<'script> {
     pub const EVICTION_PERIOD: &'static str = "eviction_period";
     /// `max_groups` setting
     pub const MAX_GROUPS: &'static str = "max_groups";
+    /// `columnar` setting
+    pub const COLUMNAR: &'static str = "columnar";
     /// `interval` setting
     pub const INTERVAL: &'static str = "interval";
     /// `size` setting
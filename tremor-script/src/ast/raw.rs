@@ -23,9 +23,9 @@ use super::{
     ImutExprInt, Invocable, Invoke, InvokeAggr, InvokeAggrFn, List, Literal, LocalPath, Match,
     Merge, MetadataPath, ModDoc, NodeMetas, Patch, PatchOperation, Path, Pattern, PredicateClause,
     PredicatePattern, Record, RecordPattern, Recur, ReservedPath, Script, Segment, StatePath,
-    StrLitElement, StringLit, TestExpr, TuplePattern, UnaryExpr, UnaryOpKind, Warning,
+    StrLitElement, StringLit, TestExpr, Try, TuplePattern, UnaryExpr, UnaryOpKind, Warning,
 };
-use super::{upable::Upable, BytesPart};
+use super::{schema::Schema, upable::Upable, BytesPart};
 use crate::impl_expr;
 use crate::pos::{Location, Range};
 use crate::prelude::*;
@@ -101,6 +101,15 @@ impl<'script> ScriptRaw<'script> {
                     let f = f.up(&mut helper)?;
                     helper.register_fun(f.into())?;
                 }
+                ExprRaw::Schema { expr, start, end } => {
+                    let r = Range::from((start, end));
+                    let expr = expr.up(&mut helper)?.try_reduce(&helper)?;
+                    let v = reduce2(expr, &helper)?;
+                    let schema = Schema::from_value(&v).map_err(|e| {
+                        Error::from(ErrorKind::InvalidSchema(r.expand_lines(2), r, e))
+                    })?;
+                    helper.schema = Some(schema);
+                }
                 other => exprs.push(other.up(&mut helper)?),
             }
         }
@@ -501,6 +510,18 @@ pub(crate) fn reduce2<'script>(
 impl<'script> ImutExprInt<'script> {
     pub(crate) fn try_reduce(self, helper: &Helper<'script, '_>) -> Result<Self> {
         match self {
+            // folding would turn errors the `catch` handles into compile errors
+            ImutExprInt::Unary(_)
+            | ImutExprInt::Bytes(_)
+            | ImutExprInt::Binary(_)
+            | ImutExprInt::Invoke1(_)
+            | ImutExprInt::Invoke2(_)
+            | ImutExprInt::Invoke3(_)
+            | ImutExprInt::Invoke(_)
+                if helper.in_try > 0 =>
+            {
+                Ok(self)
+            }
             ImutExprInt::Unary(u) => u.try_reduce(helper),
             ImutExprInt::Bytes(b) => b.try_reduce(helper),
             ImutExprInt::Binary(b) => b.try_reduce(helper),
@@ -535,6 +556,15 @@ pub enum ExprRaw<'script> {
     /// we're forced to make this pub because of lalrpop
     Module(ModuleRaw<'script>),
     /// we're forced to make this pub because of lalrpop
+    Schema {
+        /// we're forced to make this pub because of lalrpop
+        expr: ImutExprRaw<'script>,
+        /// we're forced to make this pub because of lalrpop
+        start: Location,
+        /// we're forced to make this pub because of lalrpop
+        end: Location,
+    },
+    /// we're forced to make this pub because of lalrpop
     MatchExpr(Box<MatchRaw<'script, Self>>),
     /// we're forced to make this pub because of lalrpop
     Assign(Box<AssignRaw<'script>>),
@@ -582,6 +612,14 @@ impl<'script> Upable<'script> for ExprRaw<'script> {
                 )
                 .into());
             }
+            ExprRaw::Schema { start, end, .. } => {
+                return Err(ErrorKind::InvalidSchema(
+                    Range::from((start, end)).expand_lines(2),
+                    Range::from((start, end)),
+                    "schemas can only be declared at the top level of a script".to_string(),
+                )
+                .into());
+            }
             ExprRaw::MatchExpr(m) => match m.up(helper)? {
                 Match {
                     mid,
@@ -695,7 +733,9 @@ impl<'script> BaseExpr for ExprRaw<'script> {
     }
     fn s(&self, meta: &NodeMetas) -> Location {
         match self {
-            ExprRaw::Const { start, .. } | ExprRaw::Drop { start, .. } => *start,
+            ExprRaw::Const { start, .. }
+            | ExprRaw::Schema { start, .. }
+            | ExprRaw::Drop { start, .. } => *start,
             ExprRaw::Module(e) => e.s(meta),
             ExprRaw::MatchExpr(e) => e.s(meta),
             ExprRaw::Assign(e) => e.s(meta),
@@ -707,7 +747,9 @@ impl<'script> BaseExpr for ExprRaw<'script> {
     }
     fn e(&self, meta: &NodeMetas) -> Location {
         match self {
-            ExprRaw::Const { end, .. } | ExprRaw::Drop { end, .. } => *end,
+            ExprRaw::Const { end, .. }
+            | ExprRaw::Schema { end, .. }
+            | ExprRaw::Drop { end, .. } => *end,
             ExprRaw::Module(e) => e.e(meta),
             ExprRaw::MatchExpr(e) => e.e(meta),
             ExprRaw::Assign(e) => e.e(meta),
@@ -980,6 +1022,8 @@ pub enum ImutExprRaw<'script> {
     /// we're forced to make this pub because of lalrpop
     Merge(Box<MergeRaw<'script>>),
     /// we're forced to make this pub because of lalrpop
+    Try(Box<TryRaw<'script>>),
+    /// we're forced to make this pub because of lalrpop
     Match(Box<MatchRaw<'script, Self>>),
     /// we're forced to make this pub because of lalrpop
     Comprehension(Box<ComprehensionRaw<'script, Self>>),
@@ -1111,11 +1155,17 @@ impl<'script> Upable<'script> for ImutExprRaw<'script> {
             ImutExprRaw::Merge(m) => {
                 ImutExprInt::Merge(Box::new(m.up(helper)?)).try_reduce(helper)?
             }
-            ImutExprRaw::Present { path, start, end } => ImutExprInt::Present {
-                path: path.up(helper)?,
-                mid: helper.add_meta(start, end),
+            ImutExprRaw::Present { path, start, end } => {
+                let path = path.up(helper)?;
+                if let Path::Event(p) = &path {
+                    helper.check_event_path(p);
+                }
+                ImutExprInt::Present {
+                    path,
+                    mid: helper.add_meta(start, end),
+                }
+                .try_reduce(helper)?
             }
-            .try_reduce(helper)?,
             ImutExprRaw::Path(p) => match p.up(helper)? {
                 Path::Local(LocalPath {
                     is_const,
@@ -1123,7 +1173,12 @@ impl<'script> Upable<'script> for ImutExprRaw<'script> {
                     idx,
                     ref segments,
                 }) if segments.is_empty() => ImutExprInt::Local { mid, idx, is_const },
-                p => ImutExprInt::Path(p),
+                p => {
+                    if let Path::Event(p) = &p {
+                        helper.check_event_path(p);
+                    }
+                    ImutExprInt::Path(p)
+                }
             }
             .try_reduce(helper)?,
             ImutExprRaw::Literal(l) => ImutExprInt::Literal(l.up(helper)?).try_reduce(helper)?,
@@ -1150,6 +1205,10 @@ impl<'script> Upable<'script> for ImutExprRaw<'script> {
                 ImutExprInt::Match(Box::new(m.up(helper)?))
             }
             ImutExprRaw::Comprehension(c) => ImutExprInt::Comprehension(Box::new(c.up(helper)?)),
+            ImutExprRaw::Try(t) => {
+                helper.possible_leaf = was_leaf;
+                ImutExprInt::Try(Box::new(t.up(helper)?))
+            }
             ImutExprRaw::Bytes(b) => ImutExprInt::Bytes(b.up(helper)?).try_reduce(helper)?,
         };
         helper.possible_leaf = was_leaf;
@@ -1433,6 +1492,41 @@ impl<'script> Upable<'script> for MergeRaw<'script> {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TryRaw<'script> {
+    pub start: Location,
+    pub end: Location,
+    pub expr: ImutExprRaw<'script>,
+    pub err_name: Cow<'script, str>,
+    pub catch: ImutExprRaw<'script>,
+}
+
+impl<'script> Upable<'script> for TryRaw<'script> {
+    type Target = Try<'script>;
+    fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
+        let was_leaf = helper.possible_leaf;
+        helper.possible_leaf = false;
+        helper.in_try += 1;
+        let expr = self.expr.up(helper);
+        helper.in_try -= 1;
+        let expr = expr?;
+        helper.possible_leaf = was_leaf;
+
+        // the error is only visible inside of the catch expression
+        let err_idx = helper.register_shadow_var(&self.err_name);
+        let catch = self.catch.up(helper);
+        helper.end_shadow_var();
+
+        Ok(Try {
+            mid: helper.add_meta(self.start, self.end),
+            expr,
+            err_name: self.err_name,
+            err_idx,
+            catch: catch?,
+        })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct ComprehensionRaw<'script, Ex>
 where
@@ -2252,12 +2346,14 @@ pub struct BinExprRaw<'script> {
 impl<'script> Upable<'script> for BinExprRaw<'script> {
     type Target = BinExpr<'script>;
     fn up<'registry>(self, helper: &mut Helper<'script, 'registry>) -> Result<Self::Target> {
-        Ok(BinExpr {
+        let binary = BinExpr {
             mid: helper.add_meta(self.start, self.end),
             kind: self.kind,
             lhs: self.lhs.up(helper)?,
             rhs: self.rhs.up(helper)?,
-        })
+        };
+        helper.check_event_bin(&binary);
+        Ok(binary)
     }
 }
 
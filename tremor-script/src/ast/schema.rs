@@ -0,0 +1,331 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Event schemas declared in scripts with
+//!
+//! ```tremor
+//! schema {
+//!   "host": "string",
+//!   "count": "integer",
+//!   "tags": ["string"],
+//!   "user": { "id": "integer", "name": "string?" },
+//!   "extra": "record"
+//! };
+//! ```
+//!
+//! Types are `any`, `null`, `bool`, `integer`, `float`, `number`, `string`,
+//! `binary`, `array` and `record`, a trailing `?` makes them nullable.
+//! Arrays of a type are declared as a one element array and records with
+//! known fields as a record. Records declared this way are closed, accessing
+//! fields not declared in them is flagged, `record` allows any field.
+//!
+//! Accesses to `event` following a schema are checked against it while the
+//! script is compiled and reported as warnings.
+
+use super::{BinOpKind, Segment};
+use crate::prelude::*;
+use halfbrown::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Schema {
+    Any,
+    Null,
+    Bool,
+    Integer,
+    Float,
+    Number,
+    String,
+    Binary,
+    Array(Box<Schema>),
+    Record {
+        fields: HashMap<String, Schema>,
+        open: bool,
+    },
+    Optional(Box<Schema>),
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Schema::Any => f.write_str("any"),
+            Schema::Null => f.write_str("null"),
+            Schema::Bool => f.write_str("bool"),
+            Schema::Integer => f.write_str("integer"),
+            Schema::Float => f.write_str("float"),
+            Schema::Number => f.write_str("number"),
+            Schema::String => f.write_str("string"),
+            Schema::Binary => f.write_str("binary"),
+            Schema::Array(inner) => write!(f, "[{}]", inner),
+            Schema::Record { .. } => f.write_str("record"),
+            Schema::Optional(inner) => write!(f, "{}?", inner),
+        }
+    }
+}
+
+impl Schema {
+    /// Builds a schema from its declaration
+    pub(crate) fn from_value(value: &Value) -> std::result::Result<Self, String> {
+        match value {
+            Value::String(s) => {
+                if let Some(inner) = s.strip_suffix('?') {
+                    return Ok(Schema::Optional(Box::new(Self::from_value(&Value::from(
+                        inner,
+                    ))?)));
+                }
+                Ok(match s.as_ref() {
+                    "any" => Schema::Any,
+                    "null" => Schema::Null,
+                    "bool" => Schema::Bool,
+                    "integer" => Schema::Integer,
+                    "float" => Schema::Float,
+                    "number" => Schema::Number,
+                    "string" => Schema::String,
+                    "binary" => Schema::Binary,
+                    "array" => Schema::Array(Box::new(Schema::Any)),
+                    "record" => Schema::Record {
+                        fields: HashMap::new(),
+                        open: true,
+                    },
+                    other => return Err(format!("unknown type `{}`", other)),
+                })
+            }
+            Value::Array(a) if a.len() == 1 => a
+                .first()
+                .map(Self::from_value)
+                .transpose()?
+                .map(|inner| Schema::Array(Box::new(inner)))
+                .ok_or_else(|| "arrays are declared with a single element type".to_string()),
+            Value::Array(_) => Err("arrays are declared with a single element type".to_string()),
+            Value::Object(o) => {
+                let mut fields = HashMap::with_capacity(o.len());
+                for (k, v) in o.iter() {
+                    fields.insert(k.to_string(), Self::from_value(v)?);
+                }
+                Ok(Schema::Record {
+                    fields,
+                    open: false,
+                })
+            }
+            other => Err(format!(
+                "types are declared as strings, arrays or records, not `{}`",
+                other.encode()
+            )),
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Schema::Integer | Schema::Float | Schema::Number)
+    }
+
+    /// Resolves the schema of `event` followed by `segments`, `None` if it
+    /// can't be known statically
+    pub(crate) fn resolve(
+        &self,
+        segments: &[Segment],
+    ) -> std::result::Result<Option<&Schema>, String> {
+        let mut schema = self;
+        for (i, segment) in segments.iter().enumerate() {
+            while let Schema::Optional(inner) = schema {
+                schema = inner;
+            }
+            let path = || path_name(segments.get(..i).unwrap_or_default());
+            schema = match (segment, schema) {
+                (_, Schema::Any) | (Segment::Element { .. }, Schema::Record { .. }) => {
+                    return Ok(None)
+                }
+                (Segment::Id { key, .. }, Schema::Record { fields, open }) => {
+                    match fields.get(key.key().as_ref()) {
+                        Some(field) => field,
+                        None if *open => return Ok(None),
+                        None => {
+                            return Err(format!(
+                                "`{}` is not declared in the event schema",
+                                path_name(segments.get(..=i).unwrap_or_default())
+                            ))
+                        }
+                    }
+                }
+                (Segment::Idx { .. }, Schema::Array(inner))
+                | (Segment::Element { .. }, Schema::Array(inner)) => inner,
+                (Segment::Range { .. }, Schema::Array(_)) => schema,
+                (Segment::Id { key, .. }, other) => {
+                    return Err(format!(
+                        "`{}` is declared as {} and has no field `{}`",
+                        path(),
+                        other,
+                        key.key()
+                    ))
+                }
+                (_, other) => {
+                    return Err(format!(
+                        "`{}` is declared as {} and can't be indexed",
+                        path(),
+                        other
+                    ))
+                }
+            };
+        }
+        Ok(Some(schema))
+    }
+
+    /// Checks if `value` is of this type, numbers are considered compatible
+    /// with each other
+    pub(crate) fn accepts(&self, value: &Value) -> bool {
+        match self {
+            Schema::Any => true,
+            Schema::Optional(inner) => value.is_null() || inner.accepts(value),
+            Schema::Null => value.is_null(),
+            Schema::Bool => value.is_bool(),
+            s if s.is_numeric() => matches!(
+                value.value_type(),
+                ValueType::I64 | ValueType::U64 | ValueType::F64
+            ),
+            Schema::String => value.is_str(),
+            Schema::Binary => value.value_type() == ValueType::Custom("bytes"),
+            Schema::Array(inner) => value
+                .as_array()
+                .map_or(false, |a| a.iter().all(|v| inner.accepts(v))),
+            Schema::Record { fields, open } => value.as_object().map_or(false, |o| {
+                o.iter().all(|(k, v)| {
+                    fields
+                        .get(k.as_ref())
+                        .map_or(*open, |field| field.accepts(v))
+                })
+            }),
+            _ => false,
+        }
+    }
+
+    /// Checks an operation between a value of this type and the literal
+    /// `value`, returning a description of the mismatch
+    pub(crate) fn check_bin(&self, path: &str, kind: BinOpKind, value: &Value) -> Option<String> {
+        match kind {
+            // bit shifts are checked at runtime and don't need compatible
+            // operands
+            BinOpKind::RBitShiftSigned | BinOpKind::RBitShiftUnsigned | BinOpKind::LBitShift => {
+                None
+            }
+            _ if self.accepts(value) => None,
+            _ => Some(format!(
+                "`{}` is declared as {} but used with `{}` in `{}`",
+                path,
+                self,
+                value.encode(),
+                kind
+            )),
+        }
+    }
+}
+
+/// Renders `event` followed by `segments`
+pub(crate) fn path_name(segments: &[Segment]) -> String {
+    let mut path = String::from("event");
+    for segment in segments {
+        match segment {
+            Segment::Id { key, .. } => {
+                path.push('.');
+                path.push_str(key.key());
+            }
+            Segment::Idx { idx, .. } => path.push_str(&format!("[{}]", idx)),
+            Segment::Element { .. } | Segment::Range { .. } => path.push_str("[..]"),
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tremor_value::literal;
+
+    fn schema() -> Schema {
+        Schema::from_value(&literal!({
+            "host": "string",
+            "count": "integer?",
+            "tags": ["string"],
+            "user": {"id": "integer"},
+            "extra": "record"
+        }))
+        .expect("valid schema")
+    }
+
+    fn id(key: &'static str) -> Segment<'static> {
+        Segment::Id {
+            key: KnownKey::from(key),
+            mid: 0,
+        }
+    }
+
+    #[test]
+    fn from_value() {
+        assert_eq!(
+            Ok(Schema::Array(Box::new(Schema::Optional(Box::new(
+                Schema::Float
+            ))))),
+            Schema::from_value(&literal!(["float?"]))
+        );
+        assert!(Schema::from_value(&literal!("snot")).is_err());
+        assert!(Schema::from_value(&literal!(["string", "integer"])).is_err());
+        assert!(Schema::from_value(&literal!({"a": 1})).is_err());
+    }
+
+    #[test]
+    fn resolve() {
+        let s = schema();
+        assert_eq!(Ok(Some(&Schema::String)), s.resolve(&[id("host")]));
+        assert_eq!(
+            Ok(Some(&Schema::Integer)),
+            s.resolve(&[id("user"), id("id")])
+        );
+        assert_eq!(
+            Ok(Some(&Schema::String)),
+            s.resolve(&[id("tags"), Segment::Idx { idx: 0, mid: 0 }])
+        );
+        assert_eq!(Ok(None), s.resolve(&[id("extra"), id("snot")]));
+        assert_eq!(
+            Err("`event.hots` is not declared in the event schema".to_string()),
+            s.resolve(&[id("hots")])
+        );
+        assert_eq!(
+            Err("`event.user.name` is not declared in the event schema".to_string()),
+            s.resolve(&[id("user"), id("name")])
+        );
+        assert!(s.resolve(&[id("host"), id("name")]).is_err());
+        assert!(s
+            .resolve(&[id("count"), Segment::Idx { idx: 0, mid: 0 }])
+            .is_err());
+    }
+
+    #[test]
+    fn check_bin() {
+        let s = schema();
+        let count = s.resolve(&[id("count")]).ok().flatten().expect("declared");
+        assert_eq!(
+            None,
+            count.check_bin("event.count", BinOpKind::Gt, &literal!(1.5))
+        );
+        assert_eq!(
+            None,
+            count.check_bin("event.count", BinOpKind::Eq, &literal!(null))
+        );
+        assert_eq!(
+            Some("`event.count` is declared as integer? but used with `\"1\"` in `==`".to_string()),
+            count.check_bin("event.count", BinOpKind::Eq, &literal!("1"))
+        );
+        assert!(s
+            .check_bin("event", BinOpKind::Eq, &literal!({"host": "a", "snot": 1}))
+            .is_some());
+    }
+}
@@ -18,7 +18,7 @@ use super::{
     ArrayPattern, ArrayPredicatePattern, BinExpr, Bytes, EventPath, GroupBy, GroupByInt,
     ImutExprInt, Invoke, InvokeAggr, List, Literal, LocalPath, Match, Merge, MetadataPath,
     NodeMetas, Patch, PatchOperation, Path, Pattern, PredicatePattern, Record, RecordPattern,
-    Recur, ReservedPath, Segment, StatePath, StrLitElement, StringLit, UnaryExpr,
+    Recur, ReservedPath, Segment, StatePath, StrLitElement, StringLit, Try, UnaryExpr,
 };
 use crate::errors::{error_event_ref_not_allowed, Result};
 /// Return value from visit methods for `ImutExprIntVisitor`
@@ -379,6 +379,22 @@ pub trait ImutExprIntVisitor<'script> {
         self.walk_expr(&mut merge.expr)
     }
 
+    /// visit a try expr
+    ///
+    /// # Errors
+    /// if the walker function fails
+    fn visit_try(&mut self, _try_expr: &mut Try<'script>) -> Result<VisitRes> {
+        Ok(Walk)
+    }
+    /// walk a try expr
+    ///
+    /// # Errors
+    /// if the walker function fails
+    fn walk_try(&mut self, try_expr: &mut Try<'script>) -> Result<()> {
+        self.walk_expr(&mut try_expr.expr)?;
+        self.walk_expr(&mut try_expr.catch)
+    }
+
     /// walk a path segment
     ///
     /// # Errors
@@ -609,6 +625,11 @@ pub trait ImutExprIntVisitor<'script> {
                         self.walk_merge(merge.as_mut())?;
                     }
                 }
+                ImutExprInt::Try(try_expr) => {
+                    if let Walk = self.visit_try(try_expr.as_mut())? {
+                        self.walk_try(try_expr.as_mut())?;
+                    }
+                }
                 ImutExprInt::Path(path) => {
                     if let Walk = self.visit_path(path)? {
                         self.walk_path(path)?;
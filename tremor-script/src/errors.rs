@@ -167,7 +167,7 @@ impl ErrorKind {
             EmptyInterpolation, EmptyScript, ExtraToken, Generic, Grok, InvalidAssign,
             InvalidBinary, InvalidBitshift, InvalidConst, InvalidDrop, InvalidEmit,
             InvalidExtractor, InvalidFloatLiteral, InvalidFn, InvalidHexLiteral, InvalidInfluxData,
-            InvalidIntLiteral, InvalidMod, InvalidRecur, InvalidToken, InvalidUnary,
+            InvalidIntLiteral, InvalidMod, InvalidRecur, InvalidSchema, InvalidToken, InvalidUnary,
             InvalidUtf8Sequence, Io, JsonError, MergeTypeConflict, MissingEffectors,
             MissingFunction, MissingModule, ModuleNotFound, Msg, NoClauseHit, NoConstsAllowed,
             NoEventReferencesAllowed, NoLocalsAllowed, NoObjectError, NotConstant, NotFound, Oops,
@@ -206,6 +206,7 @@ impl ErrorKind {
             | RecursionLimit(outer, inner)
             | InvalidConst(outer, inner)
             | InvalidMod(outer, inner)
+            | InvalidSchema(outer, inner, _)
             | InvalidFn(outer, inner)
             | AssignToConst(outer, inner, _)
             | DoubleConst(outer, inner, _)
@@ -685,6 +686,10 @@ error_chain! {
             description("Can't declare a module here")
                 display("Can't declare a module here")
         }
+        InvalidSchema(expr: Range, inner: Range, msg: String) {
+            description("Invalid event schema")
+                display("Invalid event schema: {}", msg)
+        }
         InvalidFn(expr: Range, inner: Range) {
             description("Can't declare a function here")
                 display("Can't declare a function here")
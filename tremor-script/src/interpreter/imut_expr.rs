@@ -21,7 +21,7 @@ use crate::{
     ast::binary::extend_bytes_from_value,
     errors::{
         error_bad_key, error_decreasing_range, error_invalid_unary, error_need_obj, error_need_str,
-        error_no_clause_hit, error_oops, Result,
+        error_no_clause_hit, error_oops, Error, ErrorKind, Result,
     },
 };
 use crate::{ast::Comprehension, stry};
@@ -33,7 +33,7 @@ use crate::{ast::StringLit, interpreter::value_to_index};
 use crate::{
     ast::{
         BaseExpr, BinExpr, ImutExpr, ImutExprInt, Invoke, InvokeAggr, LocalPath, Merge, Patch,
-        Path, Recur, ReservedPath, Segment, UnaryExpr,
+        Path, Recur, ReservedPath, Segment, Try, UnaryExpr,
     },
     errors::error_oops_err,
 };
@@ -291,6 +291,32 @@ where
             ImutExprInt::Comprehension(ref expr) => {
                 self.comprehension(opts, env, event, state, meta, local, expr)
             }
+            ImutExprInt::Try(ref expr) => self.try_expr(opts, env, event, state, meta, local, expr),
+        }
+    }
+
+    fn try_expr(
+        &'script self,
+        opts: ExecOpts,
+        env: &'run Env<'run, 'event, 'script>,
+        event: &'run Value<'event>,
+        state: &'run Value<'static>,
+        meta: &'run Value<'event>,
+        local: &'run LocalStack<'event>,
+        expr: &'script Try<'script>,
+    ) -> Result<Cow<'run, Value<'event>>> {
+        match expr.expr.run(opts, env, event, state, meta, local) {
+            Ok(v) => Ok(v),
+            Err(e) => {
+                stry!(set_local_shadow(
+                    self,
+                    local,
+                    &env.meta,
+                    expr.err_idx,
+                    caught_error(&e)
+                ));
+                expr.catch.run(opts, env, event, state, meta, local)
+            }
         }
     }
 
@@ -841,3 +867,32 @@ where
         _ => arg.0.run(opts, env, event, state, meta, local),
     }
 }
+
+/// Turns an error into the record bound in a `catch` expression
+fn caught_error(e: &Error) -> Value<'static> {
+    let kind = match e.kind() {
+        ErrorKind::BadAccessInLocal(..)
+        | ErrorKind::BadAccessInGlobal(..)
+        | ErrorKind::BadAccessInEvent(..)
+        | ErrorKind::BadAccessInState(..) => "missing_key",
+        ErrorKind::BadArrayIndex(..)
+        | ErrorKind::ArrayOutOfRange(..)
+        | ErrorKind::DecreasingRange(..) => "bad_index",
+        ErrorKind::TypeConflict(..)
+        | ErrorKind::InvalidUnary(..)
+        | ErrorKind::InvalidBinary(..)
+        | ErrorKind::InvalidBitshift(..)
+        | ErrorKind::MergeTypeConflict(..) => "bad_type",
+        ErrorKind::BadType(..) | ErrorKind::RuntimeError(..) | ErrorKind::BadArity(..) => {
+            "function"
+        }
+        ErrorKind::InvalidExtractor(..) => "extractor",
+        ErrorKind::NoClauseHit(..) => "no_clause_hit",
+        ErrorKind::PatchKeyExists(..) | ErrorKind::UpdateKeyMissing(..) => "patch",
+        _ => "error",
+    };
+    let mut err = Value::object_with_capacity(2);
+    err.try_insert("kind", kind);
+    err.try_insert("message", e.to_string());
+    err
+}
@@ -82,6 +82,9 @@ pub(crate) fn ident_to_token(ident: &str) -> Token {
     match ident {
         "intrinsic" => Token::Intrinsic,
         "mod" => Token::Module,
+        "schema" => Token::Schema,
+        "try" => Token::Try,
+        "catch" => Token::Catch,
         "const" => Token::Const,
         "let" => Token::Let,
         "match" => Token::Match,
@@ -244,6 +247,12 @@ pub enum Token<'input> {
     Intrinsic,
     /// the `mod` keyword
     Module,
+    /// the `schema` keyword
+    Schema,
+    /// the `try` keyword
+    Try,
+    /// the `catch` keyword
+    Catch,
     /// the `_` token
     DontCare,
     /// the `recur` token
@@ -426,6 +435,7 @@ impl<'input> Token<'input> {
                 | Token::Args
                 | Token::By
                 | Token::Case
+                | Token::Catch
                 | Token::Const
                 | Token::Copy
                 | Token::Create
@@ -455,6 +465,7 @@ impl<'input> Token<'input> {
                 | Token::Order
                 | Token::Patch
                 | Token::Present
+                | Token::Schema
                 | Token::Script
                 | Token::Select
                 | Token::Set
@@ -463,6 +474,7 @@ impl<'input> Token<'input> {
                 | Token::Sliding
                 | Token::State
                 | Token::Stream
+                | Token::Try
                 | Token::Tumbling
                 | Token::Update
                 | Token::Upsert
@@ -676,6 +688,9 @@ impl<'input> fmt::Display for Token<'input> {
             Token::Default => write!(f, "default"),
             Token::Intrinsic => write!(f, "intrinsic"),
             Token::Module => write!(f, "mod"),
+            Token::Schema => write!(f, "schema"),
+            Token::Try => write!(f, "try"),
+            Token::Catch => write!(f, "catch"),
             Token::BSlash => write!(f, "\\"),
             Token::Colon => write!(f, ":"),
             Token::ColonColon => write!(f, "::"),
@@ -2833,6 +2848,15 @@ mod tests {
         lex_ok! {
             " intrinsic ",
             " ~~~~~~~~~ " => Token::Intrinsic, };
+        lex_ok! {
+            " schema ",
+            " ~~~~~~ " => Token::Schema, };
+        lex_ok! {
+            " try ",
+            " ~~~ " => Token::Try, };
+        lex_ok! {
+            " catch ",
+            " ~~~~~ " => Token::Catch, };
         Ok(())
     }
 
@@ -80,6 +80,7 @@ pub use crate::registry::{
     TremorAggrFnWrapper, TremorFn, TremorFnWrapper,
 };
 pub use crate::script::{Return, Script};
+pub use crate::std_lib::metrics::take_changed as take_script_metrics;
 use lazy_static::lazy_static;
 use self_cell::self_cell;
 use std::sync::atomic::{AtomicU32, Ordering};
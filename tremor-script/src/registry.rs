@@ -74,6 +74,9 @@ pub trait TremorAggrFn: DowncastSync + Sync + Send {
     fn warning(&self) -> Option<String> {
         None
     }
+    /// Switches the function to accumulate values into a column that is
+    /// aggregated in bulk, functions without a columnar mode ignore this
+    fn columnar(&mut self) {}
 }
 impl_downcast!(sync TremorAggrFn);
 
@@ -725,6 +728,11 @@ impl TremorAggrFnWrapper {
         self.fun.init()
     }
 
+    /// Switches the function to accumulate values into a column
+    pub fn columnar(&mut self) {
+        self.fun.columnar()
+    }
+
     /// Tests if a given arity is valid
     #[must_use]
     pub fn valid_arity(&self, n: usize) -> bool {
@@ -27,8 +27,11 @@ mod datetime;
 mod dummy;
 mod float;
 mod integer;
+mod jmespath;
 mod json;
+mod jsonpath;
 mod math;
+pub(crate) mod metrics;
 mod origin;
 mod random;
 mod range;
@@ -43,6 +46,44 @@ mod url;
 mod win;
 
 use crate::registry::{Aggr as AggrRegistry, Registry};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Cache for expressions passed to functions as strings, so they are only
+/// parsed once
+pub(crate) struct ExprCache<T> {
+    exprs: Mutex<HashMap<String, Arc<T>>>,
+}
+
+impl<T> ExprCache<T> {
+    /// maximum number of cached expressions, the cache is cleared once full
+    const MAX: usize = 1024;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            exprs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn get_or_parse<F>(&self, expr: &str, parse: F) -> Result<Arc<T>, String>
+    where
+        F: FnOnce(&str) -> Result<T, String>,
+    {
+        let mut exprs = match self.exprs.lock() {
+            Ok(exprs) => exprs,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(parsed) = exprs.get(expr) {
+            return Ok(parsed.clone());
+        }
+        let parsed = Arc::new(parse(expr)?);
+        if exprs.len() >= Self::MAX {
+            exprs.clear();
+        }
+        exprs.insert(expr.to_string(), parsed.clone());
+        Ok(parsed)
+    }
+}
 
 pub fn load(registry: &mut Registry) {
     array::load(registry);
@@ -53,8 +94,11 @@ pub fn load(registry: &mut Registry) {
     dummy::load(registry);
     float::load(registry);
     integer::load(registry);
+    jmespath::load(registry);
     json::load(registry);
+    jsonpath::load(registry);
     math::load(registry);
+    metrics::load(registry);
     origin::load(registry);
     random::load(registry);
     range::load(registry);
@@ -0,0 +1,1214 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `JMESPath` expressions as specified on <https://jmespath.org/specification.html>,
+//! including projections, filters, multi-selects, pipes and the builtin
+//! functions. The parser follows the top down operator precedence parser of
+//! the reference implementation.
+
+use super::ExprCache;
+use crate::prelude::*;
+use crate::registry::Registry;
+use crate::tremor_const_fn;
+use crate::Value;
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::ops::Deref;
+
+lazy_static! {
+    static ref CACHE: ExprCache<Node> = ExprCache::new();
+}
+
+type Res<T> = std::result::Result<T, String>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    Star,
+    Flatten,
+    Filter,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Pipe,
+    Or,
+    And,
+    Not,
+    Comma,
+    Colon,
+    Current,
+    Expref,
+    Cmp(Cmp),
+    Number(i64),
+    Identifier(String),
+    QuotedIdentifier(String),
+    Literal(Value<'static>),
+    Eof,
+}
+
+impl Token {
+    /// binding power, as in the reference implementation
+    fn bp(&self) -> u8 {
+        match self {
+            Token::Pipe => 1,
+            Token::Or => 2,
+            Token::And => 3,
+            Token::Cmp(_) => 5,
+            Token::Flatten => 9,
+            Token::Star => 20,
+            Token::Filter => 21,
+            Token::Dot => 40,
+            Token::Not => 45,
+            Token::LBrace => 50,
+            Token::LBracket => 55,
+            Token::LParen => 60,
+            _ => 0,
+        }
+    }
+}
+
+/// projections stop at tokens binding weaker than this
+const PROJECTION_STOP: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Current,
+    Field(String),
+    Literal(Value<'static>),
+    Subexpr(Box<Node>, Box<Node>),
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Projection(Box<Node>, Box<Node>),
+    ValueProjection(Box<Node>, Box<Node>),
+    FilterProjection(Box<Node>, Box<Node>, Box<Node>),
+    Flatten(Box<Node>),
+    Comparator(Cmp, Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Pipe(Box<Node>, Box<Node>),
+    MultiList(Vec<Node>),
+    MultiHash(Vec<(String, Node)>),
+    Function(String, Vec<Node>),
+    Expref(Box<Node>),
+}
+
+fn parse_json(src: &str) -> Res<Value<'static>> {
+    let mut bytes = src.as_bytes().to_vec();
+    tremor_value::parse_to_value(&mut bytes)
+        .map(Value::into_static)
+        .map_err(|e| format!("Invalid JSON `{}`: {}", src, e))
+}
+
+#[allow(clippy::too_many_lines)]
+fn lex(src: &str) -> Res<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let next = chars.peek().map(|(_, c)| *c);
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '.' => Token::Dot,
+            '*' => Token::Star,
+            ']' => Token::RBracket,
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '@' => Token::Current,
+            '[' => match next {
+                Some(']') => {
+                    chars.next();
+                    Token::Flatten
+                }
+                Some('?') => {
+                    chars.next();
+                    Token::Filter
+                }
+                _ => Token::LBracket,
+            },
+            '|' | '&' | '!' | '<' | '>' | '=' => {
+                let token = match (c, next) {
+                    ('|', Some('|')) => Token::Or,
+                    ('&', Some('&')) => Token::And,
+                    ('!', Some('=')) => Token::Cmp(Cmp::Ne),
+                    ('<', Some('=')) => Token::Cmp(Cmp::Lte),
+                    ('>', Some('=')) => Token::Cmp(Cmp::Gte),
+                    ('=', Some('=')) => Token::Cmp(Cmp::Eq),
+                    ('|', _) => Token::Pipe,
+                    ('&', _) => Token::Expref,
+                    ('!', _) => Token::Not,
+                    ('<', _) => Token::Cmp(Cmp::Lt),
+                    ('>', _) => Token::Cmp(Cmp::Gt),
+                    _ => return Err(format!("Unexpected `=` at position {}", start)),
+                };
+                if matches!(
+                    token,
+                    Token::Or
+                        | Token::And
+                        | Token::Cmp(Cmp::Ne)
+                        | Token::Cmp(Cmp::Lte)
+                        | Token::Cmp(Cmp::Gte)
+                        | Token::Cmp(Cmp::Eq)
+                ) {
+                    chars.next();
+                }
+                token
+            }
+            '"' | '\'' | '`' => {
+                let mut raw = String::new();
+                let mut closed = false;
+                while let Some((_, d)) = chars.next() {
+                    if d == c {
+                        closed = true;
+                        break;
+                    } else if d == '\\' {
+                        match chars.next() {
+                            // quotes only need to be unescaped in raw
+                            // strings and literals
+                            Some((_, e)) if e == c && c != '"' => raw.push(e),
+                            Some((_, e)) => {
+                                raw.push(d);
+                                raw.push(e);
+                            }
+                            None => break,
+                        }
+                    } else {
+                        raw.push(d);
+                    }
+                }
+                if !closed {
+                    return Err(format!("Unterminated `{}` at position {}", c, start));
+                }
+                match c {
+                    '"' => match parse_json(&format!("\"{}\"", raw))? {
+                        Value::String(s) => Token::QuotedIdentifier(s.to_string()),
+                        _ => return Err(format!("Invalid identifier at position {}", start)),
+                    },
+                    '\'' => Token::Literal(Value::from(raw)),
+                    _ => Token::Literal(parse_json(&raw)?),
+                }
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, d)) = chars.peek().copied() {
+                    if !d.is_ascii_digit() {
+                        break;
+                    }
+                    chars.next();
+                    end = i + d.len_utf8();
+                }
+                let n = &src[start..end];
+                Token::Number(
+                    n.parse()
+                        .map_err(|_| format!("Invalid number `{}` at position {}", n, start))?,
+                )
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, d)) = chars.peek().copied() {
+                    if !(d.is_ascii_alphanumeric() || d == '_') {
+                        break;
+                    }
+                    chars.next();
+                    end = i + d.len_utf8();
+                }
+                Token::Identifier(src[start..end].to_string())
+            }
+            c => return Err(format!("Unexpected `{}` at position {}", c, start)),
+        };
+        tokens.push(token);
+    }
+    tokens.push(Token::Eof);
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn current(&self) -> &Token {
+        self.lookahead(0)
+    }
+
+    fn lookahead(&self, n: usize) -> &Token {
+        self.tokens.get(self.pos + n).unwrap_or(&Token::Eof)
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.current().clone();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: &Token) -> Res<()> {
+        if self.current() == token {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected {:?} but got {:?}", token, self.current()))
+        }
+    }
+
+    fn parse(mut self) -> Res<Node> {
+        let node = self.expression(0)?;
+        if self.current() == &Token::Eof {
+            Ok(node)
+        } else {
+            Err(format!("Unexpected {:?}", self.current()))
+        }
+    }
+
+    fn expression(&mut self, bp: u8) -> Res<Node> {
+        let token = self.advance();
+        let mut left = self.nud(token)?;
+        while bp < self.current().bp() {
+            let token = self.advance();
+            left = self.led(token, left)?;
+        }
+        Ok(left)
+    }
+
+    fn nud(&mut self, token: Token) -> Res<Node> {
+        match token {
+            Token::Literal(v) => Ok(Node::Literal(v)),
+            Token::Identifier(name) => Ok(Node::Field(name)),
+            Token::QuotedIdentifier(name) => {
+                if self.current() == &Token::LParen {
+                    Err("Quoted identifiers can't be used as function names".to_string())
+                } else {
+                    Ok(Node::Field(name))
+                }
+            }
+            Token::Star => {
+                let right = if self.current() == &Token::RBracket {
+                    Node::Current
+                } else {
+                    self.projection_rhs(Token::Star.bp())?
+                };
+                Ok(Node::ValueProjection(
+                    Box::new(Node::Current),
+                    Box::new(right),
+                ))
+            }
+            Token::Filter => self.led(Token::Filter, Node::Current),
+            Token::LBrace => self.multi_hash(),
+            Token::LParen => {
+                let node = self.expression(0)?;
+                self.expect(&Token::RParen)?;
+                Ok(node)
+            }
+            Token::Flatten => {
+                let left = Node::Flatten(Box::new(Node::Current));
+                let right = self.projection_rhs(Token::Flatten.bp())?;
+                Ok(Node::Projection(Box::new(left), Box::new(right)))
+            }
+            Token::Not => Ok(Node::Not(Box::new(self.expression(Token::Not.bp())?))),
+            Token::LBracket => match (self.current(), self.lookahead(1)) {
+                (Token::Number(_), _) | (Token::Colon, _) => {
+                    let right = self.index_expression()?;
+                    self.project_if_slice(Node::Current, right)
+                }
+                (Token::Star, Token::RBracket) => {
+                    self.pos += 2;
+                    let right = self.projection_rhs(Token::Star.bp())?;
+                    Ok(Node::Projection(Box::new(Node::Current), Box::new(right)))
+                }
+                _ => self.multi_list(),
+            },
+            Token::Current => Ok(Node::Current),
+            Token::Expref => Ok(Node::Expref(Box::new(self.expression(0)?))),
+            t => Err(format!("Unexpected {:?}", t)),
+        }
+    }
+
+    fn led(&mut self, token: Token, left: Node) -> Res<Node> {
+        match token {
+            Token::Dot => {
+                if self.current() == &Token::Star {
+                    self.advance();
+                    let right = self.projection_rhs(Token::Dot.bp())?;
+                    Ok(Node::ValueProjection(Box::new(left), Box::new(right)))
+                } else {
+                    let right = self.dot_rhs(Token::Dot.bp())?;
+                    Ok(Node::Subexpr(Box::new(left), Box::new(right)))
+                }
+            }
+            Token::Pipe => Ok(Node::Pipe(
+                Box::new(left),
+                Box::new(self.expression(Token::Pipe.bp())?),
+            )),
+            Token::Or => Ok(Node::Or(
+                Box::new(left),
+                Box::new(self.expression(Token::Or.bp())?),
+            )),
+            Token::And => Ok(Node::And(
+                Box::new(left),
+                Box::new(self.expression(Token::And.bp())?),
+            )),
+            Token::LParen => {
+                let name = match left {
+                    Node::Field(name) => name,
+                    other => return Err(format!("Invalid function name {:?}", other)),
+                };
+                let mut args = Vec::new();
+                while self.current() != &Token::RParen {
+                    args.push(self.expression(0)?);
+                    if self.current() == &Token::Comma {
+                        self.advance();
+                    }
+                }
+                self.expect(&Token::RParen)?;
+                Ok(Node::Function(name, args))
+            }
+            Token::Filter => {
+                let condition = self.expression(0)?;
+                self.expect(&Token::RBracket)?;
+                let right = if self.current() == &Token::Flatten {
+                    Node::Current
+                } else {
+                    self.projection_rhs(Token::Filter.bp())?
+                };
+                Ok(Node::FilterProjection(
+                    Box::new(left),
+                    Box::new(right),
+                    Box::new(condition),
+                ))
+            }
+            Token::Cmp(cmp) => Ok(Node::Comparator(
+                cmp,
+                Box::new(left),
+                Box::new(self.expression(Token::Cmp(cmp).bp())?),
+            )),
+            Token::Flatten => {
+                let left = Node::Flatten(Box::new(left));
+                let right = self.projection_rhs(Token::Flatten.bp())?;
+                Ok(Node::Projection(Box::new(left), Box::new(right)))
+            }
+            Token::LBracket => {
+                if let Token::Number(_) | Token::Colon = self.current() {
+                    let right = self.index_expression()?;
+                    self.project_if_slice(left, right)
+                } else {
+                    self.expect(&Token::Star)?;
+                    self.expect(&Token::RBracket)?;
+                    let right = self.projection_rhs(Token::Star.bp())?;
+                    Ok(Node::Projection(Box::new(left), Box::new(right)))
+                }
+            }
+            t => Err(format!("Unexpected {:?}", t)),
+        }
+    }
+
+    fn index_expression(&mut self) -> Res<Node> {
+        if self.current() == &Token::Colon || self.lookahead(1) == &Token::Colon {
+            return self.slice();
+        }
+        match self.advance() {
+            Token::Number(n) => {
+                self.expect(&Token::RBracket)?;
+                Ok(Node::Index(n))
+            }
+            t => Err(format!("Expected an index but got {:?}", t)),
+        }
+    }
+
+    fn slice(&mut self) -> Res<Node> {
+        let mut parts = [None; 3];
+        let mut i = 0;
+        while self.current() != &Token::RBracket {
+            match self.advance() {
+                Token::Colon if i < 2 => i += 1,
+                Token::Number(n) if parts.get(i) == Some(&None) => {
+                    if let Some(part) = parts.get_mut(i) {
+                        *part = Some(n);
+                    }
+                }
+                t => return Err(format!("Unexpected {:?} in slice", t)),
+            }
+        }
+        self.expect(&Token::RBracket)?;
+        let [start, stop, step] = parts;
+        Ok(Node::Slice(start, stop, step))
+    }
+
+    fn project_if_slice(&mut self, left: Node, right: Node) -> Res<Node> {
+        let is_slice = matches!(right, Node::Slice(..));
+        let node = Node::Subexpr(Box::new(left), Box::new(right));
+        if is_slice {
+            let right = self.projection_rhs(Token::Star.bp())?;
+            Ok(Node::Projection(Box::new(node), Box::new(right)))
+        } else {
+            Ok(node)
+        }
+    }
+
+    fn projection_rhs(&mut self, bp: u8) -> Res<Node> {
+        match self.current() {
+            t if t.bp() < PROJECTION_STOP => Ok(Node::Current),
+            Token::LBracket | Token::Filter => self.expression(bp),
+            Token::Dot => {
+                self.advance();
+                self.dot_rhs(bp)
+            }
+            t => Err(format!("Unexpected {:?} after projection", t)),
+        }
+    }
+
+    fn dot_rhs(&mut self, bp: u8) -> Res<Node> {
+        match self.current() {
+            Token::Identifier(_) | Token::QuotedIdentifier(_) | Token::Star => self.expression(bp),
+            Token::LBracket => {
+                self.advance();
+                self.multi_list()
+            }
+            Token::LBrace => {
+                self.advance();
+                self.multi_hash()
+            }
+            t => Err(format!("Unexpected {:?} after `.`", t)),
+        }
+    }
+
+    fn multi_list(&mut self) -> Res<Node> {
+        let mut items = Vec::new();
+        loop {
+            items.push(self.expression(0)?);
+            if self.current() == &Token::RBracket {
+                break;
+            }
+            self.expect(&Token::Comma)?;
+        }
+        self.expect(&Token::RBracket)?;
+        Ok(Node::MultiList(items))
+    }
+
+    fn multi_hash(&mut self) -> Res<Node> {
+        let mut items = Vec::new();
+        loop {
+            let key = match self.advance() {
+                Token::Identifier(key) | Token::QuotedIdentifier(key) => key,
+                t => return Err(format!("Expected a key but got {:?}", t)),
+            };
+            self.expect(&Token::Colon)?;
+            items.push((key, self.expression(0)?));
+            match self.advance() {
+                Token::Comma => (),
+                Token::RBrace => return Ok(Node::MultiHash(items)),
+                t => return Err(format!("Expected `,` or `}}` but got {:?}", t)),
+            }
+        }
+    }
+}
+
+fn parse(src: &str) -> Res<Node> {
+    Parser {
+        tokens: lex(src)?,
+        pos: 0,
+    }
+    .parse()
+}
+
+fn null<'v, 'e>() -> Res<Cow<'v, Value<'e>>> {
+    Ok(Cow::Owned(Value::null()))
+}
+
+fn truthy(v: &Value) -> bool {
+    match v {
+        Value::Static(StaticNode::Null) | Value::Static(StaticNode::Bool(false)) => false,
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+        _ => true,
+    }
+}
+
+fn is_number(v: &Value) -> bool {
+    matches!(
+        v.value_type(),
+        ValueType::I64 | ValueType::U64 | ValueType::F64
+    )
+}
+
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, a)| b.get(k).map_or(false, |b| equal(a, b)))
+        }
+        (a, b) if is_number(a) && is_number(b) => {
+            a.cast_f64().partial_cmp(&b.cast_f64()) == Some(Ordering::Equal)
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// orders numbers and strings, other types can't be ordered
+fn order(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (a, b) if is_number(a) && is_number(b) => a.cast_f64().partial_cmp(&b.cast_f64()),
+        _ => None,
+    }
+}
+
+fn slice<'e>(
+    a: &[Value<'e>],
+    start: Option<i64>,
+    stop: Option<i64>,
+    step: Option<i64>,
+) -> Res<Vec<Value<'e>>> {
+    let step = step.unwrap_or(1);
+    if step == 0 {
+        return Err("Slice step can't be 0".to_string());
+    }
+    let len = i64::try_from(a.len()).unwrap_or(i64::MAX);
+    let cap = |i: i64| {
+        if i < 0 {
+            (i + len).max(if step < 0 { -1 } else { 0 })
+        } else {
+            i.min(if step < 0 { len - 1 } else { len })
+        }
+    };
+    let mut i = start.map_or(if step < 0 { len - 1 } else { 0 }, cap);
+    let stop = stop.map_or(if step < 0 { -1 } else { len }, cap);
+    let mut res = Vec::new();
+    while (step > 0 && i < stop) || (step < 0 && i > stop) {
+        res.extend(usize::try_from(i).ok().and_then(|i| a.get(i)).cloned());
+        i += step;
+    }
+    Ok(res)
+}
+
+fn project<'v, 'e>(base: &Value<'e>, right: &Node) -> Res<Cow<'v, Value<'e>>> {
+    if let Some(a) = base.as_array() {
+        let mut res = Vec::with_capacity(a.len());
+        for v in a {
+            let v = eval(right, v)?;
+            if !v.is_null() {
+                res.push(v.into_owned());
+            }
+        }
+        Ok(Cow::Owned(Value::from(res)))
+    } else {
+        null()
+    }
+}
+
+/// evaluates `node` against the result of a previous evaluation
+fn chain<'v, 'e>(node: &Node, base: Cow<'v, Value<'e>>) -> Res<Cow<'v, Value<'e>>> {
+    match base {
+        Cow::Borrowed(v) => eval(node, v),
+        Cow::Owned(v) => Ok(Cow::Owned(eval(node, &v)?.into_owned())),
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn eval<'v, 'e>(node: &Node, value: &'v Value<'e>) -> Res<Cow<'v, Value<'e>>> {
+    match node {
+        Node::Current => Ok(Cow::Borrowed(value)),
+        Node::Field(name) => value
+            .as_object()
+            .and_then(|o| o.get(name.as_str()))
+            .map_or_else(null, |v| Ok(Cow::Borrowed(v))),
+        Node::Literal(v) => Ok(Cow::Owned(v.clone())),
+        Node::Subexpr(l, r) | Node::Pipe(l, r) => chain(r, eval(l, value)?),
+        Node::Index(i) => {
+            if let Some(a) = value.as_array() {
+                let len = i64::try_from(a.len()).unwrap_or(i64::MAX);
+                let i = if *i < 0 { len + i } else { *i };
+                usize::try_from(i)
+                    .ok()
+                    .and_then(|i| a.get(i))
+                    .map_or_else(null, |v| Ok(Cow::Borrowed(v)))
+            } else {
+                null()
+            }
+        }
+        Node::Slice(start, stop, step) => {
+            if let Some(a) = value.as_array() {
+                Ok(Cow::Owned(Value::from(slice(a, *start, *stop, *step)?)))
+            } else {
+                null()
+            }
+        }
+        Node::Projection(l, r) => project(&eval(l, value)?, r),
+        Node::ValueProjection(l, r) => {
+            let base = eval(l, value)?;
+            if let Some(o) = base.as_object() {
+                let mut res = Vec::with_capacity(o.len());
+                for v in o.values() {
+                    let v = eval(r, v)?;
+                    if !v.is_null() {
+                        res.push(v.into_owned());
+                    }
+                }
+                Ok(Cow::Owned(Value::from(res)))
+            } else {
+                null()
+            }
+        }
+        Node::FilterProjection(l, r, condition) => {
+            let base = eval(l, value)?;
+            if let Some(a) = base.as_array() {
+                let mut res = Vec::with_capacity(a.len());
+                for v in a {
+                    if truthy(&eval(condition, v)?) {
+                        let v = eval(r, v)?;
+                        if !v.is_null() {
+                            res.push(v.into_owned());
+                        }
+                    }
+                }
+                Ok(Cow::Owned(Value::from(res)))
+            } else {
+                null()
+            }
+        }
+        Node::Flatten(node) => {
+            let base = eval(node, value)?;
+            if let Some(a) = base.as_array() {
+                let mut res = Vec::with_capacity(a.len());
+                for v in a {
+                    if let Some(inner) = v.as_array() {
+                        res.extend(inner.iter().cloned());
+                    } else {
+                        res.push(v.clone());
+                    }
+                }
+                Ok(Cow::Owned(Value::from(res)))
+            } else {
+                null()
+            }
+        }
+        Node::Comparator(cmp, l, r) => {
+            let l = eval(l, value)?;
+            let r = eval(r, value)?;
+            let res = match cmp {
+                Cmp::Eq => Some(equal(&l, &r)),
+                Cmp::Ne => Some(!equal(&l, &r)),
+                _ if !is_number(&l) || !is_number(&r) => None,
+                Cmp::Lt => order(&l, &r).map(|o| o == Ordering::Less),
+                Cmp::Lte => order(&l, &r).map(|o| o != Ordering::Greater),
+                Cmp::Gt => order(&l, &r).map(|o| o == Ordering::Greater),
+                Cmp::Gte => order(&l, &r).map(|o| o != Ordering::Less),
+            };
+            Ok(Cow::Owned(res.map_or_else(Value::null, Value::from)))
+        }
+        Node::Or(l, r) => {
+            let l = eval(l, value)?;
+            if truthy(&l) {
+                Ok(l)
+            } else {
+                eval(r, value)
+            }
+        }
+        Node::And(l, r) => {
+            let l = eval(l, value)?;
+            if truthy(&l) {
+                eval(r, value)
+            } else {
+                Ok(l)
+            }
+        }
+        Node::Not(node) => Ok(Cow::Owned(Value::from(!truthy(&eval(node, value)?)))),
+        Node::MultiList(items) => {
+            if value.is_null() {
+                return null();
+            }
+            let mut res = Vec::with_capacity(items.len());
+            for item in items {
+                res.push(eval(item, value)?.into_owned());
+            }
+            Ok(Cow::Owned(Value::from(res)))
+        }
+        Node::MultiHash(items) => {
+            if value.is_null() {
+                return null();
+            }
+            let mut res = Value::object_with_capacity(items.len());
+            for (key, item) in items {
+                res.try_insert(key.clone(), eval(item, value)?.into_owned());
+            }
+            Ok(Cow::Owned(res))
+        }
+        Node::Function(name, args) => call(name, args, value).map(Cow::Owned),
+        Node::Expref(_) => Err("Expression references can only be function arguments".to_string()),
+    }
+}
+
+fn type_error(name: &str, expected: &str) -> String {
+    format!("Invalid type for `{}`, expected {}", name, expected)
+}
+
+fn arity(name: &str, args: &[Node], n: usize) -> Res<()> {
+    if args.len() == n {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{}` takes {} arguments but got {}",
+            name,
+            n,
+            args.len()
+        ))
+    }
+}
+
+fn number<'e>(f: f64) -> Value<'e> {
+    Value::from(f)
+}
+
+fn sum(name: &str, a: &[Value]) -> Res<Value<'static>> {
+    let mut int = Some(0_i64);
+    let mut float = 0.0;
+    for v in a {
+        if !is_number(v) {
+            return Err(type_error(name, "an array of numbers"));
+        }
+        int = int.and_then(|i| v.as_i64().and_then(|v| i.checked_add(v)));
+        float += v.cast_f64().unwrap_or_default();
+    }
+    Ok(int.map_or_else(|| number(float), Value::from))
+}
+
+/// evaluates `key` for each element, all keys have to be numbers or strings
+fn keys<'e>(name: &str, a: &[Value<'e>], key: &Node) -> Res<Vec<Value<'e>>> {
+    let keys = a
+        .iter()
+        .map(|v| eval(key, v).map(Cow::into_owned))
+        .collect::<Res<Vec<_>>>()?;
+    if keys.iter().all(is_number) || keys.iter().all(Value::is_str) {
+        Ok(keys)
+    } else {
+        Err(type_error(
+            name,
+            "an expression returning numbers or strings",
+        ))
+    }
+}
+
+fn expref<'n>(name: &str, node: Option<&'n Node>) -> Res<&'n Node> {
+    match node {
+        Some(Node::Expref(node)) => Ok(node),
+        _ => Err(type_error(name, "an expression reference")),
+    }
+}
+
+#[allow(
+    clippy::too_many_lines,
+    clippy::cast_possible_wrap,
+    clippy::cast_precision_loss
+)]
+fn call<'e>(name: &str, nodes: &[Node], value: &Value<'e>) -> Res<Value<'e>> {
+    // functions taking expression references evaluate their arguments
+    // themselves
+    match name {
+        "map" => {
+            arity(name, nodes, 2)?;
+            let key = expref(name, nodes.first())?;
+            let base = eval(&nodes[1], value)?;
+            let a = base
+                .as_array()
+                .ok_or_else(|| type_error(name, "an array"))?;
+            let res = a
+                .iter()
+                .map(|v| eval(key, v).map(Cow::into_owned))
+                .collect::<Res<Vec<_>>>()?;
+            return Ok(Value::from(res));
+        }
+        "sort_by" | "max_by" | "min_by" => {
+            arity(name, nodes, 2)?;
+            let key = expref(name, nodes.get(1))?;
+            let base = eval(&nodes[0], value)?;
+            let a = base
+                .as_array()
+                .ok_or_else(|| type_error(name, "an array"))?;
+            let keys = keys(name, a, key)?;
+            let mut idx: Vec<usize> = (0..a.len()).collect();
+            idx.sort_by(|x, y| order(&keys[*x], &keys[*y]).unwrap_or(Ordering::Equal));
+            let pick = match name {
+                "max_by" => idx.last(),
+                "min_by" => idx.first(),
+                _ => {
+                    return Ok(Value::from(
+                        idx.iter().map(|i| a[*i].clone()).collect::<Vec<_>>(),
+                    ))
+                }
+            };
+            return Ok(pick.map_or_else(Value::null, |i| a[*i].clone()));
+        }
+        _ => (),
+    }
+    let mut args = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        args.push(eval(node, value)?);
+    }
+    let arg = |i: usize| args.get(i).map(Deref::deref);
+    let fixed = |n: usize| arity(name, nodes, n);
+    match name {
+        "abs" => {
+            fixed(1)?;
+            match arg(0) {
+                Some(v) if v.is_u64() => Ok(v.clone()),
+                Some(v) if v.is_i64() => {
+                    Ok(Value::from(v.as_i64().unwrap_or_default().saturating_abs()))
+                }
+                Some(v) if is_number(v) => Ok(number(v.cast_f64().unwrap_or_default().abs())),
+                _ => Err(type_error(name, "a number")),
+            }
+        }
+        "ceil" | "floor" => {
+            fixed(1)?;
+            let f = arg(0)
+                .filter(|v| is_number(v))
+                .and_then(ValueAccessTrait::cast_f64)
+                .ok_or_else(|| type_error(name, "a number"))?;
+            Ok(number(if name == "ceil" { f.ceil() } else { f.floor() }))
+        }
+        "avg" | "sum" => {
+            fixed(1)?;
+            let a = arg(0)
+                .and_then(ValueAccessTrait::as_array)
+                .ok_or_else(|| type_error(name, "an array of numbers"))?;
+            let total = sum(name, a)?;
+            if name == "sum" {
+                Ok(total)
+            } else if a.is_empty() {
+                Ok(Value::null())
+            } else {
+                Ok(number(
+                    total.cast_f64().unwrap_or_default() / a.len() as f64,
+                ))
+            }
+        }
+        "contains" => {
+            fixed(2)?;
+            match (arg(0), arg(1)) {
+                (Some(Value::Array(a)), Some(v)) => Ok(Value::from(a.iter().any(|x| equal(x, v)))),
+                (Some(Value::String(s)), Some(Value::String(v))) => {
+                    Ok(Value::from(s.contains(&**v)))
+                }
+                (Some(Value::String(_)), Some(_)) => Ok(Value::from(false)),
+                _ => Err(type_error(name, "an array or a string")),
+            }
+        }
+        "starts_with" | "ends_with" => {
+            fixed(2)?;
+            match (
+                arg(0).and_then(ValueAccessTrait::as_str),
+                arg(1).and_then(ValueAccessTrait::as_str),
+            ) {
+                (Some(s), Some(p)) => Ok(Value::from(if name == "starts_with" {
+                    s.starts_with(p)
+                } else {
+                    s.ends_with(p)
+                })),
+                _ => Err(type_error(name, "strings")),
+            }
+        }
+        "join" => {
+            fixed(2)?;
+            let glue = arg(0)
+                .and_then(ValueAccessTrait::as_str)
+                .ok_or_else(|| type_error(name, "a string"))?;
+            let parts = arg(1)
+                .and_then(ValueAccessTrait::as_array)
+                .and_then(|a| {
+                    a.iter()
+                        .map(ValueAccessTrait::as_str)
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| type_error(name, "an array of strings"))?;
+            Ok(Value::from(parts.join(glue)))
+        }
+        "keys" | "values" => {
+            fixed(1)?;
+            let o = arg(0)
+                .and_then(ValueAccessTrait::as_object)
+                .ok_or_else(|| type_error(name, "an object"))?;
+            Ok(Value::from(if name == "keys" {
+                o.keys()
+                    .map(|k| Value::from(k.to_string()))
+                    .collect::<Vec<_>>()
+            } else {
+                o.values().cloned().collect::<Vec<_>>()
+            }))
+        }
+        "length" => {
+            fixed(1)?;
+            match arg(0) {
+                Some(Value::String(s)) => Ok(Value::from(s.chars().count() as i64)),
+                Some(Value::Array(a)) => Ok(Value::from(a.len() as i64)),
+                Some(Value::Object(o)) => Ok(Value::from(o.len() as i64)),
+                _ => Err(type_error(name, "a string, array or object")),
+            }
+        }
+        "max" | "min" => {
+            fixed(1)?;
+            let a = arg(0)
+                .and_then(ValueAccessTrait::as_array)
+                .filter(|a| a.iter().all(is_number) || a.iter().all(Value::is_str))
+                .ok_or_else(|| type_error(name, "an array of numbers or strings"))?;
+            let res = a.iter().fold(None, |acc: Option<&Value>, v| match acc {
+                Some(acc) => {
+                    let o = order(v, acc);
+                    let better = if name == "max" {
+                        o == Some(Ordering::Greater)
+                    } else {
+                        o == Some(Ordering::Less)
+                    };
+                    Some(if better { v } else { acc })
+                }
+                None => Some(v),
+            });
+            Ok(res.cloned().unwrap_or_else(Value::null))
+        }
+        "merge" => {
+            let mut res = Value::object_with_capacity(args.len());
+            for v in &args {
+                let o = v.as_object().ok_or_else(|| type_error(name, "objects"))?;
+                for (k, v) in o.iter() {
+                    res.try_insert(k.clone(), v.clone());
+                }
+            }
+            Ok(res)
+        }
+        "not_null" => {
+            if args.is_empty() {
+                return Err(format!("`{}` takes at least 1 argument", name));
+            }
+            Ok(args
+                .iter()
+                .find(|v| !v.is_null())
+                .map_or_else(Value::null, |v| v.as_ref().clone()))
+        }
+        "reverse" => {
+            fixed(1)?;
+            match arg(0) {
+                Some(Value::String(s)) => Ok(Value::from(s.chars().rev().collect::<String>())),
+                Some(Value::Array(a)) => {
+                    Ok(Value::from(a.iter().rev().cloned().collect::<Vec<_>>()))
+                }
+                _ => Err(type_error(name, "a string or an array")),
+            }
+        }
+        "sort" => {
+            fixed(1)?;
+            let mut a = arg(0)
+                .and_then(ValueAccessTrait::as_array)
+                .filter(|a| a.iter().all(is_number) || a.iter().all(Value::is_str))
+                .ok_or_else(|| type_error(name, "an array of numbers or strings"))?
+                .clone();
+            a.sort_by(|x, y| order(x, y).unwrap_or(Ordering::Equal));
+            Ok(Value::from(a))
+        }
+        "to_array" => {
+            fixed(1)?;
+            match arg(0) {
+                Some(v @ Value::Array(_)) => Ok(v.clone()),
+                Some(v) => Ok(Value::from(vec![v.clone()])),
+                None => Ok(Value::null()),
+            }
+        }
+        "to_number" => {
+            fixed(1)?;
+            match arg(0) {
+                Some(v) if is_number(v) => Ok(v.clone()),
+                Some(Value::String(s)) => Ok(s
+                    .parse::<i64>()
+                    .map(Value::from)
+                    .or_else(|_| s.parse::<f64>().map(number))
+                    .unwrap_or_else(|_| Value::null())),
+                _ => Ok(Value::null()),
+            }
+        }
+        "to_string" => {
+            fixed(1)?;
+            match arg(0) {
+                Some(v @ Value::String(_)) => Ok(v.clone()),
+                Some(v) => Ok(Value::from(v.encode())),
+                None => Ok(Value::null()),
+            }
+        }
+        "type" => {
+            fixed(1)?;
+            let t = match arg(0).map(|v| v.value_type()) {
+                Some(ValueType::Null) | None => "null",
+                Some(ValueType::Bool) => "boolean",
+                Some(ValueType::I64) | Some(ValueType::U64) | Some(ValueType::F64) => "number",
+                Some(ValueType::String) => "string",
+                Some(ValueType::Array) => "array",
+                Some(ValueType::Object) => "object",
+                Some(ValueType::Custom(c)) => c,
+            };
+            Ok(Value::from(t))
+        }
+        _ => Err(format!("Unknown function `{}`", name)),
+    }
+}
+
+pub fn load(registry: &mut Registry) {
+    registry.insert(tremor_const_fn! (jmespath|search(_context, _value, _expr) {
+        let expr = _expr.as_str().ok_or(FunctionError::BadType{ mfa: this_mfa() })?;
+        let node = CACHE.get_or_parse(expr, parse).map_err(to_runtime_error)?;
+        eval(&node, _value).map(Cow::into_owned).map_err(to_runtime_error)
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use crate::registry::fun;
+    use crate::Value;
+    use tremor_value::literal;
+
+    fn data() -> Value<'static> {
+        literal!({
+            "people": [
+                {"name": "snot", "age": 30, "tags": ["a", "b"]},
+                {"name": "badger", "age": 40, "tags": ["c"]},
+                {"name": "cake", "age": 20}
+            ],
+            "ops": {"a": {"n": 1}, "b": {"n": 2}},
+            "nested": [[1, 2], [3, [4]]],
+            "empty": ""
+        })
+    }
+
+    fn search(expr: &str) -> Value<'static> {
+        let f = fun("jmespath", "search");
+        let v = data();
+        f(&[&v, &Value::from(expr)])
+            .expect("valid expression")
+            .into_static()
+    }
+
+    #[test]
+    fn basic() {
+        assert_eq!(literal!("snot"), search("people[0].name"));
+        assert_eq!(literal!("cake"), search("people[-1].name"));
+        assert_eq!(literal!(null), search("people[5].name"));
+        assert_eq!(literal!(2), search("ops.b.n"));
+        assert_eq!(literal!(2), search("\"ops\".\"b\".n"));
+        assert_eq!(literal!(null), search("snot.badger"));
+        assert_eq!(literal!([1, 2]), search("ops.*.n"));
+        assert_eq!(literal!([1, 2, 3, [4]]), search("nested[]"));
+        assert_eq!(literal!(["snot", "cake"]), search("people[::2].name"));
+        assert_eq!(
+            literal!(["cake", "badger", "snot"]),
+            search("people[::-1].name")
+        );
+        assert_eq!(literal!(["a", "b", "c"]), search("people[*].tags[]"));
+        assert_eq!(literal!("badger"), search("people[1] | name"));
+        assert_eq!(literal!("default"), search("empty || 'default'"));
+        assert_eq!(literal!(true), search("!empty"));
+        assert_eq!(literal!({"x": 42}), search("`{\"x\": 42}`"));
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            literal!(["snot", "badger"]),
+            search("people[?age >= `30`].name")
+        );
+        assert_eq!(
+            literal!(["badger"]),
+            search("people[?age > `20` && name != 'snot'].name")
+        );
+        assert_eq!(literal!(["cake"]), search("people[?!tags].name"));
+        assert_eq!(literal!([]), search("people[?name < `1`].name"));
+    }
+
+    #[test]
+    fn multi_select() {
+        assert_eq!(
+            literal!([["snot", 30], ["badger", 40], ["cake", 20]]),
+            search("people[].[name, age]")
+        );
+        assert_eq!(
+            literal!([{"n": "snot", "t": 2}, {"n": "badger", "t": 1}]),
+            search("people[?tags].{n: name, t: length(tags)}")
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(literal!(3), search("length(people)"));
+        assert_eq!(literal!(90), search("sum(people[*].age)"));
+        assert_eq!(literal!(30.0), search("avg(people[*].age)"));
+        assert_eq!(literal!(40), search("max(people[*].age)"));
+        assert_eq!(literal!("badger"), search("min(people[*].name)"));
+        assert_eq!(literal!("cake"), search("min_by(people, &age).name"));
+        assert_eq!(literal!("badger"), search("max_by(people, &age).name"));
+        assert_eq!(
+            literal!(["cake", "snot", "badger"]),
+            search("sort_by(people, &age)[*].name")
+        );
+        assert_eq!(
+            literal!(["badger", "cake", "snot"]),
+            search("sort(people[*].name)")
+        );
+        assert_eq!(literal!([4, 6, 4]), search("map(&length(name), people)"));
+        assert_eq!(literal!(["a", "b"]), search("keys(ops)"));
+        assert_eq!(
+            literal!("snot, badger, cake"),
+            search("join(', ', people[*].name)")
+        );
+        assert_eq!(literal!(true), search("contains(people[0].tags, 'b')"));
+        assert_eq!(literal!(true), search("starts_with(people[1].name, 'bad')"));
+        assert_eq!(
+            literal!({"a": 1, "b": 3}),
+            search("merge(`{\"a\": 1, \"b\": 2}`, `{\"b\": 3}`)")
+        );
+        assert_eq!(literal!("c"), search("not_null(snot, people[1].tags[0])"));
+        assert_eq!(literal!(42), search("to_number('42')"));
+        assert_eq!(literal!("[1,2]"), search("to_string(nested[0])"));
+        assert_eq!(literal!("object"), search("type(ops)"));
+        assert_eq!(literal!(2.0), search("abs(floor(`-1.5`))"));
+    }
+
+    #[test]
+    fn invalid() {
+        let f = fun("jmespath", "search");
+        let v = data();
+        assert!(f(&[&v, &Value::from("people[")]).is_err());
+        assert!(f(&[&v, &Value::from("people[?age > ]")]).is_err());
+        assert!(f(&[&v, &Value::from("snot(people)")]).is_err());
+        assert!(f(&[&v, &Value::from("length(ops.a.n)")]).is_err());
+        assert!(f(&[&v, &Value::from("people[::0]")]).is_err());
+        assert!(f(&[&v, &Value::from(1)]).is_err());
+    }
+}
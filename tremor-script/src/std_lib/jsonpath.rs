@@ -0,0 +1,618 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `JSONPath` expressions as commonly implemented: `$`, `.name`, `['name']`,
+//! `[0]`, `[-1]`, `[*]`, `.*`, `..name`, unions `[0,2]`, slices `[0:4:2]` and
+//! filters `[?(@.price < 10 && @.tags)]` comparing `@` or `$` paths with
+//! literals.
+
+use super::ExprCache;
+use crate::prelude::*;
+use crate::registry::Registry;
+use crate::tremor_const_fn;
+use crate::Value;
+use lazy_static::lazy_static;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+lazy_static! {
+    static ref CACHE: ExprCache<Path> = ExprCache::new();
+}
+
+type Path = Vec<Selector>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Selector {
+    Child(String),
+    Index(i64),
+    Wildcard,
+    Slice(Option<i64>, Option<i64>, i64),
+    Union(Vec<Selector>),
+    Filter(Filter),
+    Descendants(Box<Selector>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Or(Box<Filter>, Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Cmp(Operand, Cmp, Operand),
+    Exists(Operand),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Current(Path),
+    Root(Path),
+    Literal(Value<'static>),
+}
+
+struct Parser<'src> {
+    src: &'src str,
+    pos: usize,
+}
+
+impl<'src> Parser<'src> {
+    fn peek(&self) -> Option<char> {
+        self.src.get(self.pos..).and_then(|s| s.chars().next())
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        if self.src.get(self.pos..).map_or(false, |r| r.starts_with(s)) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_ws(&mut self) {
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.bump();
+        }
+    }
+
+    fn err<T>(&self, msg: &str) -> Result<T, String> {
+        Err(format!(
+            "{} at position {} in `{}`",
+            msg, self.pos, self.src
+        ))
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_ws();
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.err(&format!("Expected `{}`", c))
+        }
+    }
+
+    fn parse(mut self) -> Result<Path, String> {
+        self.skip_ws();
+        if !self.eat('$') {
+            return self.err("Expected `$`");
+        }
+        let path = self.path()?;
+        self.skip_ws();
+        if self.peek().is_some() {
+            return self.err("Unexpected input");
+        }
+        Ok(path)
+    }
+
+    fn path(&mut self) -> Result<Path, String> {
+        let mut path = Vec::new();
+        loop {
+            match self.peek() {
+                Some('.') => {
+                    self.bump();
+                    if self.eat('.') {
+                        let selector = if self.peek() == Some('[') {
+                            self.bracket()?
+                        } else if self.eat('*') {
+                            Selector::Wildcard
+                        } else {
+                            Selector::Child(self.name()?)
+                        };
+                        path.push(Selector::Descendants(Box::new(selector)));
+                    } else if self.eat('*') {
+                        path.push(Selector::Wildcard);
+                    } else {
+                        path.push(Selector::Child(self.name()?));
+                    }
+                }
+                Some('[') => path.push(self.bracket()?),
+                _ => return Ok(path),
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .map_or(false, |c| c.is_alphanumeric() || c == '_' || c == '-')
+        {
+            self.bump();
+        }
+        if start == self.pos {
+            self.err("Expected a name")
+        } else {
+            Ok(self.src[start..self.pos].to_string())
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let quote = self.bump().unwrap_or_default();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c) => s.push(c),
+                    None => return self.err("Unterminated string"),
+                },
+                Some(c) if c == quote => return Ok(s),
+                Some(c) => s.push(c),
+                None => return self.err("Unterminated string"),
+            }
+        }
+    }
+
+    fn int(&mut self) -> Result<Option<i64>, String> {
+        self.skip_ws();
+        let start = self.pos;
+        self.eat('-');
+        while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            self.bump();
+        }
+        if start == self.pos {
+            Ok(None)
+        } else {
+            self.src[start..self.pos]
+                .parse()
+                .map(Some)
+                .or_else(|_| self.err("Invalid integer"))
+        }
+    }
+
+    fn bracket(&mut self) -> Result<Selector, String> {
+        self.expect('[')?;
+        self.skip_ws();
+        if self.eat('?') {
+            self.expect('(')?;
+            let filter = self.or()?;
+            self.expect(')')?;
+            self.expect(']')?;
+            return Ok(Selector::Filter(filter));
+        }
+        if self.eat('*') {
+            self.expect(']')?;
+            return Ok(Selector::Wildcard);
+        }
+        let mut items = Vec::new();
+        loop {
+            self.skip_ws();
+            let item = if let Some('\'') | Some('"') = self.peek() {
+                Selector::Child(self.string()?)
+            } else {
+                let start = self.int()?;
+                self.skip_ws();
+                if self.eat(':') {
+                    let end = self.int()?;
+                    self.skip_ws();
+                    let step = if self.eat(':') { self.int()? } else { None };
+                    Selector::Slice(start, end, step.unwrap_or(1))
+                } else if let Some(index) = start {
+                    Selector::Index(index)
+                } else {
+                    return self.err("Expected an index, slice or name");
+                }
+            };
+            items.push(item);
+            self.skip_ws();
+            if !self.eat(',') {
+                break;
+            }
+        }
+        self.expect(']')?;
+        if items.len() == 1 {
+            Ok(items.remove(0))
+        } else {
+            Ok(Selector::Union(items))
+        }
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut lhs = self.and()?;
+        loop {
+            self.skip_ws();
+            if self.eat_str("||") {
+                lhs = Filter::Or(Box::new(lhs), Box::new(self.and()?));
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            self.skip_ws();
+            if self.eat_str("&&") {
+                lhs = Filter::And(Box::new(lhs), Box::new(self.unary()?));
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        self.skip_ws();
+        if self.eat('!') {
+            return Ok(Filter::Not(Box::new(self.unary()?)));
+        }
+        if self.eat('(') {
+            let filter = self.or()?;
+            self.expect(')')?;
+            return Ok(filter);
+        }
+        let lhs = self.operand()?;
+        self.skip_ws();
+        let cmp = if self.eat_str("==") {
+            Cmp::Eq
+        } else if self.eat_str("!=") {
+            Cmp::Ne
+        } else if self.eat_str("<=") {
+            Cmp::Lte
+        } else if self.eat_str(">=") {
+            Cmp::Gte
+        } else if self.eat('<') {
+            Cmp::Lt
+        } else if self.eat('>') {
+            Cmp::Gt
+        } else {
+            return Ok(Filter::Exists(lhs));
+        };
+        Ok(Filter::Cmp(lhs, cmp, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        self.skip_ws();
+        match self.peek() {
+            Some('@') => {
+                self.bump();
+                Ok(Operand::Current(self.path()?))
+            }
+            Some('$') => {
+                self.bump();
+                Ok(Operand::Root(self.path()?))
+            }
+            Some('\'') | Some('"') => Ok(Operand::Literal(Value::from(self.string()?))),
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.pos;
+                while self
+                    .peek()
+                    .map_or(false, |c| c.is_ascii_digit() || "-+.eE".contains(c))
+                {
+                    self.bump();
+                }
+                let n = &self.src[start..self.pos];
+                if let Ok(i) = n.parse::<i64>() {
+                    Ok(Operand::Literal(Value::from(i)))
+                } else if let Ok(f) = n.parse::<f64>() {
+                    Ok(Operand::Literal(Value::from(f)))
+                } else {
+                    self.err("Invalid number")
+                }
+            }
+            _ => {
+                if self.eat_str("true") {
+                    Ok(Operand::Literal(Value::from(true)))
+                } else if self.eat_str("false") {
+                    Ok(Operand::Literal(Value::from(false)))
+                } else if self.eat_str("null") {
+                    Ok(Operand::Literal(Value::null()))
+                } else {
+                    self.err("Expected `@`, `$` or a literal")
+                }
+            }
+        }
+    }
+}
+
+fn parse(src: &str) -> Result<Path, String> {
+    Parser { src, pos: 0 }.parse()
+}
+
+fn index(len: usize, i: i64) -> Option<usize> {
+    let len = i64::try_from(len).ok()?;
+    let i = if i < 0 { len + i } else { i };
+    if i >= 0 && i < len {
+        usize::try_from(i).ok()
+    } else {
+        None
+    }
+}
+
+fn slice<'v, 'e>(
+    a: &'v [Value<'e>],
+    start: Option<i64>,
+    end: Option<i64>,
+    step: i64,
+) -> Vec<&'v Value<'e>> {
+    let len = i64::try_from(a.len()).unwrap_or(i64::MAX);
+    let clamp = |i: i64, lo: i64, hi: i64| {
+        let i = if i < 0 { i + len } else { i };
+        i.max(lo).min(hi)
+    };
+    let mut res = Vec::new();
+    if step > 0 {
+        let mut i = start.map_or(0, |s| clamp(s, 0, len));
+        let end = end.map_or(len, |e| clamp(e, 0, len));
+        while i < end {
+            res.extend(usize::try_from(i).ok().and_then(|i| a.get(i)));
+            i += step;
+        }
+    } else if step < 0 {
+        let mut i = start.map_or(len - 1, |s| clamp(s, -1, len - 1));
+        let end = end.map_or(-1, |e| clamp(e, -1, len - 1));
+        while i > end {
+            res.extend(usize::try_from(i).ok().and_then(|i| a.get(i)));
+            i += step;
+        }
+    }
+    res
+}
+
+fn children<'v, 'e>(node: &'v Value<'e>) -> Vec<&'v Value<'e>> {
+    match node {
+        Value::Array(a) => a.iter().collect(),
+        Value::Object(o) => o.values().collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn descendants<'v, 'e>(node: &'v Value<'e>, out: &mut Vec<&'v Value<'e>>) {
+    out.push(node);
+    for child in children(node) {
+        descendants(child, out);
+    }
+}
+
+fn apply<'v, 'e>(
+    selector: &Selector,
+    root: &'v Value<'e>,
+    node: &'v Value<'e>,
+    out: &mut Vec<&'v Value<'e>>,
+) {
+    match selector {
+        Selector::Child(name) => out.extend(node.as_object().and_then(|o| o.get(name.as_str()))),
+        Selector::Index(i) => {
+            if let Some(a) = node.as_array() {
+                out.extend(index(a.len(), *i).and_then(|i| a.get(i)));
+            }
+        }
+        Selector::Wildcard => out.extend(children(node)),
+        Selector::Slice(start, end, step) => {
+            if let Some(a) = node.as_array() {
+                out.extend(slice(a, *start, *end, *step));
+            }
+        }
+        Selector::Union(selectors) => {
+            for s in selectors {
+                apply(s, root, node, out);
+            }
+        }
+        Selector::Filter(filter) => out.extend(
+            children(node)
+                .into_iter()
+                .filter(|child| matches(filter, root, child)),
+        ),
+        Selector::Descendants(selector) => {
+            let mut nodes = Vec::new();
+            descendants(node, &mut nodes);
+            for n in nodes {
+                apply(selector, root, n, out);
+            }
+        }
+    }
+}
+
+fn select<'v, 'e>(
+    path: &[Selector],
+    root: &'v Value<'e>,
+    node: &'v Value<'e>,
+) -> Vec<&'v Value<'e>> {
+    let mut nodes = vec![node];
+    for selector in path {
+        let mut next = Vec::new();
+        for n in nodes {
+            apply(selector, root, n, &mut next);
+        }
+        nodes = next;
+    }
+    nodes
+}
+
+fn operand<'v, 'e>(
+    operand: &'v Operand,
+    root: &'v Value<'e>,
+    node: &'v Value<'e>,
+) -> Option<&'v Value<'e>> {
+    match operand {
+        Operand::Current(path) => select(path, root, node).into_iter().next(),
+        Operand::Root(path) => select(path, root, root).into_iter().next(),
+        Operand::Literal(v) => Some(v),
+    }
+}
+
+fn compare(lhs: &Value, cmp: Cmp, rhs: &Value) -> bool {
+    let ord = match (lhs, rhs) {
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        (l, r) => match (l.cast_f64(), r.cast_f64()) {
+            (Some(l), Some(r)) if !l.is_nan() && !r.is_nan() => l.partial_cmp(&r),
+            _ => None,
+        },
+    };
+    match (cmp, ord) {
+        (Cmp::Eq, Some(o)) => o == Ordering::Equal,
+        (Cmp::Eq, None) => lhs == rhs,
+        (Cmp::Ne, Some(o)) => o != Ordering::Equal,
+        (Cmp::Ne, None) => lhs != rhs,
+        (Cmp::Lt, Some(o)) => o == Ordering::Less,
+        (Cmp::Lte, Some(o)) => o != Ordering::Greater,
+        (Cmp::Gt, Some(o)) => o == Ordering::Greater,
+        (Cmp::Gte, Some(o)) => o != Ordering::Less,
+        (_, None) => false,
+    }
+}
+
+fn matches(filter: &Filter, root: &Value, node: &Value) -> bool {
+    match filter {
+        Filter::Or(l, r) => matches(l, root, node) || matches(r, root, node),
+        Filter::And(l, r) => matches(l, root, node) && matches(r, root, node),
+        Filter::Not(f) => !matches(f, root, node),
+        Filter::Exists(o) => operand(o, root, node).map_or(false, |v| v != &Value::from(false)),
+        Filter::Cmp(l, cmp, r) => match (operand(l, root, node), operand(r, root, node)) {
+            (Some(l), Some(r)) => compare(l, *cmp, r),
+            _ => false,
+        },
+    }
+}
+
+pub fn load(registry: &mut Registry) {
+    registry.insert(tremor_const_fn! (jsonpath|select(_context, _value, _expr) {
+        let expr = _expr.as_str().ok_or(FunctionError::BadType{ mfa: this_mfa() })?;
+        let path = CACHE.get_or_parse(expr, parse).map_err(to_runtime_error)?;
+        let res: Vec<Value> = select(&path, _value, _value).into_iter().cloned().collect();
+        Ok(Value::from(res))
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::fun;
+    use tremor_value::literal;
+
+    fn store() -> Value<'static> {
+        literal!({
+            "store": {
+                "book": [
+                    {"category": "reference", "author": "Nigel Rees", "price": 8.95},
+                    {"category": "fiction", "author": "Evelyn Waugh", "price": 12.99},
+                    {"category": "fiction", "author": "Herman Melville", "price": 8.99, "isbn": "0-553-21311-3"},
+                    {"category": "fiction", "author": "J. R. R. Tolkien", "price": 22.99, "isbn": "0-395-19395-8"}
+                ],
+                "bicycle": {"color": "red", "price": 19.95}
+            },
+            "max": 10
+        })
+    }
+
+    fn select(expr: &str) -> Value<'static> {
+        let f = fun("jsonpath", "select");
+        let v = store();
+        f(&[&v, &Value::from(expr)])
+            .expect("valid expression")
+            .into_static()
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            literal!([
+                "Nigel Rees",
+                "Evelyn Waugh",
+                "Herman Melville",
+                "J. R. R. Tolkien"
+            ]),
+            select("$.store.book[*].author")
+        );
+        assert_eq!(literal!(["J. R. R. Tolkien"]), select("$..book[-1].author"));
+        assert_eq!(literal!(["red"]), select("$['store']['bicycle'].color"));
+        assert_eq!(
+            literal!(["Nigel Rees", "Herman Melville"]),
+            select("$.store.book[0:4:2].author")
+        );
+        assert_eq!(
+            literal!(["J. R. R. Tolkien", "Herman Melville"]),
+            select("$.store.book[-1:1:-1].author")
+        );
+        assert_eq!(
+            literal!([8.95, 12.99, 8.99, 22.99, 19.95]),
+            select("$.store..price")
+        );
+        assert_eq!(literal!([]), select("$.snot.badger"));
+    }
+
+    #[test]
+    fn filters() {
+        assert_eq!(
+            literal!(["Nigel Rees", "Herman Melville"]),
+            select("$.store.book[?(@.price < $.max)].author")
+        );
+        assert_eq!(
+            literal!(["0-553-21311-3", "0-395-19395-8"]),
+            select("$..book[?(@.isbn)].isbn")
+        );
+        assert_eq!(
+            literal!(["Evelyn Waugh"]),
+            select("$..book[?(@.category == 'fiction' && !(@.isbn))].author")
+        );
+        assert_eq!(
+            literal!(["Nigel Rees", "J. R. R. Tolkien"]),
+            select("$..book[?(@.price > 20 || @.category != \"fiction\")].author")
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let f = fun("jsonpath", "select");
+        let v = store();
+        assert!(f(&[&v, &Value::from("store")]).is_err());
+        assert!(f(&[&v, &Value::from("$.store[")]).is_err());
+        assert!(f(&[&v, &Value::from("$[?(@.a == )]")]).is_err());
+        assert!(f(&[&v, &Value::from(1)]).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::prelude::*;
+use crate::registry::{FResult, FunctionError, Mfa, Registry};
+use crate::tremor_fn;
+use halfbrown::HashMap;
+use lazy_static::lazy_static;
+use std::sync::{Mutex, MutexGuard};
+use tremor_value::literal;
+
+/// maximum number of series (name and tags) tracked
+const MAX_SERIES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Counter,
+    Gauge,
+}
+
+#[derive(Debug)]
+struct Series {
+    name: String,
+    kind: Kind,
+    tags: Value<'static>,
+    value: f64,
+    /// updated since it was last reported
+    changed: bool,
+}
+
+lazy_static! {
+    /// series by name and encoded tags, shared by all scripts of the process
+    static ref SERIES: Mutex<HashMap<(String, String), Series>> = Mutex::new(HashMap::new());
+}
+
+fn series() -> MutexGuard<'static, HashMap<(String, String), Series>> {
+    match SERIES.lock() {
+        Ok(series) => series,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Returns the series updated by `metrics::counter` and `metrics::gauge`
+/// since the last call as metrics events, counters with their total
+pub fn take_changed(timestamp: u64) -> Vec<Value<'static>> {
+    series()
+        .values_mut()
+        .filter(|s| s.changed)
+        .map(|s| {
+            s.changed = false;
+            literal!({
+                "measurement": s.name.clone(),
+                "tags": s.tags.clone(),
+                "fields": {
+                    "value": s.value
+                },
+                "timestamp": timestamp
+            })
+        })
+        .collect()
+}
+
+/// updates a series, creating it if it doesn't exist yet
+fn update(
+    mfa: Mfa,
+    kind: Kind,
+    name: &Value,
+    value: &Value,
+    tags: &Value,
+) -> FResult<Value<'static>> {
+    let (name, value, tag_map) = match (name.as_str(), value.cast_f64(), tags.as_object()) {
+        (Some(name), Some(value), Some(tag_map)) => (name, value, tag_map),
+        _ => return Err(FunctionError::BadType { mfa }),
+    };
+    if kind == Kind::Counter && value < 0.0 {
+        return Err(FunctionError::RuntimeError {
+            mfa,
+            error: "Counters can only be increased".to_string(),
+        });
+    }
+    // tags are keyed independent of their order
+    let mut pairs: Vec<(String, String)> = tag_map
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.encode()))
+        .collect();
+    pairs.sort();
+    let key = (name.to_string(), format!("{:?}", pairs));
+
+    let mut series = series();
+    let len = series.len();
+    if let Some(existing) = series.get_mut(&key) {
+        if existing.kind != kind {
+            return Err(FunctionError::RuntimeError {
+                mfa,
+                error: format!("`{}` is already used by a different kind of metric", name),
+            });
+        }
+        match kind {
+            Kind::Counter => existing.value += value,
+            Kind::Gauge => existing.value = value,
+        }
+        existing.changed = true;
+        Ok(Value::from(existing.value))
+    } else if len >= MAX_SERIES {
+        Err(FunctionError::RuntimeError {
+            mfa,
+            error: format!("Too many metrics, at most {} are tracked", MAX_SERIES),
+        })
+    } else {
+        series.insert(
+            key,
+            Series {
+                name: name.to_string(),
+                kind,
+                tags: tags.clone_static(),
+                value,
+                changed: true,
+            },
+        );
+        Ok(Value::from(value))
+    }
+}
+
+pub fn load(registry: &mut Registry) {
+    registry
+        .insert(
+            tremor_fn! (metrics|counter(_context, _name, _delta, _tags) {
+                update(this_mfa(), Kind::Counter, _name, _delta, _tags)
+            }),
+        )
+        .insert(tremor_fn! (metrics|gauge(_context, _name, _value, _tags) {
+            update(this_mfa(), Kind::Gauge, _name, _value, _tags)
+        }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::registry::fun;
+
+    fn reported(name: &str) -> Vec<Value<'static>> {
+        take_changed(42)
+            .into_iter()
+            .filter(|m| m.get_str("measurement") == Some(name))
+            .collect()
+    }
+
+    // a single test as reporting takes the changes of all series
+    #[test]
+    fn counter_and_gauge() {
+        let f = fun("metrics", "counter");
+        let name = Value::from("test_counter");
+        let tags = literal!({"customer": "snot", "source": "badger"});
+        let reordered = literal!({"source": "badger", "customer": "snot"});
+        assert_val!(f(&[&name, &Value::from(2), &tags]), 2.0);
+        assert_val!(f(&[&name, &Value::from(3), &reordered]), 5.0);
+        assert_eq!(
+            vec![literal!({
+                "measurement": "test_counter",
+                "tags": {"customer": "snot", "source": "badger"},
+                "fields": {"value": 5.0},
+                "timestamp": 42
+            })],
+            reported("test_counter")
+        );
+        // unchanged series aren't reported again
+        assert!(reported("test_counter").is_empty());
+        assert_val!(f(&[&name, &Value::from(1), &tags]), 6.0);
+        assert_eq!(1, reported("test_counter").len());
+
+        assert!(f(&[&name, &Value::from(-1), &tags]).is_err());
+        assert!(f(&[&name, &Value::from("snot"), &tags]).is_err());
+        assert!(f(&[&name, &Value::from(1), &Value::from(1)]).is_err());
+
+        let f = fun("metrics", "gauge");
+        let name = Value::from("test_gauge");
+        let tags = Value::object();
+        assert_val!(f(&[&name, &Value::from(2.5), &tags]), 2.5);
+        assert_val!(f(&[&name, &Value::from(-1), &tags]), -1.0);
+        assert_eq!(
+            vec![literal!({
+                "measurement": "test_gauge",
+                "tags": {},
+                "fields": {"value": -1.0},
+                "timestamp": 42
+            })],
+            reported("test_gauge")
+        );
+        // a gauge can't be updated as a counter
+        let counter = fun("metrics", "counter");
+        assert!(counter(&[&name, &Value::from(1), &tags]).is_err());
+    }
+}
@@ -18,6 +18,7 @@ use crate::registry::{
     mfa, Aggr as AggrRegistry, FResult, FunctionError, TremorAggrFn, TremorAggrFnWrapper,
 };
 use crate::Value;
+use column::Column;
 use halfbrown::hashmap;
 use hdrhistogram::Histogram;
 use sketches_ddsketch::{Config as DDSketchConfig, DDSketch};
@@ -26,6 +27,8 @@ use std::f64;
 use std::ops::RangeInclusive;
 use std::u64;
 
+mod column;
+
 /// Round up.
 ///
 /// Round `value` up to accuracy defined by `scale`.
@@ -78,7 +81,16 @@ impl TremorAggrFn for Count {
 }
 
 #[derive(Clone, Debug, Default)]
-struct Sum(f64);
+struct Sum(f64, Column);
+impl Sum {
+    fn total(&self) -> f64 {
+        self.0 + column::sum(self.1.values())
+    }
+    fn fold(&mut self) {
+        self.0 = self.total();
+        self.1.clear();
+    }
+}
 impl TremorAggrFn for Sum {
     fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
         args.first().cast_f64().map_or_else(
@@ -88,7 +100,11 @@ impl TremorAggrFn for Sum {
                 })
             },
             |v| {
-                self.0 += v;
+                if !self.1.push(v) {
+                    self.0 += v;
+                } else if self.1.is_full() {
+                    self.fold();
+                }
                 Ok(())
             },
         )
@@ -107,19 +123,23 @@ impl TremorAggrFn for Sum {
         )
     }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
-        Ok(Value::from(self.0))
+        Ok(Value::from(self.total()))
     }
     fn init(&mut self) {
         self.0 = 0.0;
+        self.1.clear();
     }
     fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
         if let Some(other) = src.downcast_ref::<Self>() {
             // On self is earlier then other, so as long
             // as other has a value we take it
-            self.0 += other.0;
+            self.0 += other.total();
         }
         Ok(())
     }
+    fn columnar(&mut self) {
+        self.1.enable();
+    }
     fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -129,7 +149,16 @@ impl TremorAggrFn for Sum {
 }
 
 #[derive(Clone, Debug, Default)]
-struct Mean(i64, f64);
+struct Mean(i64, f64, Column);
+impl Mean {
+    fn total(&self) -> f64 {
+        self.1 + column::sum(self.2.values())
+    }
+    fn fold(&mut self) {
+        self.1 = self.total();
+        self.2.clear();
+    }
+}
 impl TremorAggrFn for Mean {
     fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
         self.0 += 1;
@@ -140,7 +169,11 @@ impl TremorAggrFn for Mean {
                 })
             },
             |v| {
-                self.1 += v;
+                if !self.2.push(v) {
+                    self.1 += v;
+                } else if self.2.is_full() {
+                    self.fold();
+                }
                 Ok(())
             },
         )
@@ -163,22 +196,26 @@ impl TremorAggrFn for Mean {
         if self.0 == 0 {
             Ok(Value::null())
         } else {
-            Ok(Value::from(self.1 / (self.0 as f64)))
+            Ok(Value::from(self.total() / (self.0 as f64)))
         }
     }
     fn init(&mut self) {
         self.0 = 0;
         self.1 = 0.0;
+        self.2.clear();
     }
     fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
         if let Some(other) = src.downcast_ref::<Self>() {
             // On self is earlier then other, so as long
             // as other has a value we take it
             self.0 += other.0;
-            self.1 += other.1;
+            self.1 += other.total();
         }
         Ok(())
     }
+    fn columnar(&mut self) {
+        self.2.enable();
+    }
     fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -187,8 +224,25 @@ impl TremorAggrFn for Mean {
     }
 }
 
+/// `f` of `a` and `b`, or the one of them that is set
+fn either(a: Option<f64>, b: Option<f64>, f: fn(f64, f64) -> f64) -> Option<f64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(f(a, b)),
+        (a, b) => a.or(b),
+    }
+}
+
 #[derive(Clone, Debug, Default)]
-struct Min(Option<f64>);
+struct Min(Option<f64>, Column);
+impl Min {
+    fn current(&self) -> Option<f64> {
+        either(self.0, column::min(self.1.values()), f64::min)
+    }
+    fn fold(&mut self) {
+        self.0 = self.current();
+        self.1.clear();
+    }
+}
 impl TremorAggrFn for Min {
     fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
         args.first().cast_f64().map_or_else(
@@ -198,7 +252,11 @@ impl TremorAggrFn for Min {
                 })
             },
             |v| {
-                if self.0.is_none() || Some(v) < self.0 {
+                if self.1.push(v) {
+                    if self.1.is_full() {
+                        self.fold();
+                    }
+                } else if self.0.is_none() || Some(v) < self.0 {
                     self.0 = Some(v);
                 };
                 Ok(())
@@ -224,21 +282,24 @@ impl TremorAggrFn for Min {
         Ok(())
     }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
-        Ok(Value::from(self.0.unwrap_or_default()))
+        Ok(Value::from(self.current().unwrap_or_default()))
     }
     fn init(&mut self) {
         self.0 = None;
+        self.1.clear();
     }
     fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
         if let Some(other) = src.downcast_ref::<Self>() {
             // On self is earlier then other, so as long
             // as other has a value we take it
-            if other.0 < self.0 {
-                self.0 = other.0;
-            }
+            self.0 = either(self.current(), other.current(), f64::min);
+            self.1.clear();
         }
         Ok(())
     }
+    fn columnar(&mut self) {
+        self.1.enable();
+    }
     fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -248,7 +309,16 @@ impl TremorAggrFn for Min {
 }
 
 #[derive(Clone, Debug, Default)]
-struct Max(Option<f64>);
+struct Max(Option<f64>, Column);
+impl Max {
+    fn current(&self) -> Option<f64> {
+        either(self.0, column::max(self.1.values()), f64::max)
+    }
+    fn fold(&mut self) {
+        self.0 = self.current();
+        self.1.clear();
+    }
+}
 impl TremorAggrFn for Max {
     fn accumulate<'event>(&mut self, args: &[&Value<'event>]) -> FResult<()> {
         args.first().cast_f64().map_or_else(
@@ -258,7 +328,11 @@ impl TremorAggrFn for Max {
                 })
             },
             |v| {
-                if self.0.is_none() || Some(v) > self.0 {
+                if self.1.push(v) {
+                    if self.1.is_full() {
+                        self.fold();
+                    }
+                } else if self.0.is_none() || Some(v) > self.0 {
                     self.0 = Some(v);
                 };
                 Ok(())
@@ -270,21 +344,24 @@ impl TremorAggrFn for Max {
         Ok(())
     }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
-        Ok(Value::from(self.0.unwrap_or_default()))
+        Ok(Value::from(self.current().unwrap_or_default()))
     }
     fn init(&mut self) {
         self.0 = None;
+        self.1.clear();
     }
     fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
         if let Some(other) = src.downcast_ref::<Self>() {
             // On self is earlier then other, so as long
             // as other has a value we take it
-            if other.0 > self.0 {
-                self.0 = other.0;
-            }
+            self.0 = either(self.current(), other.current(), f64::max);
+            self.1.clear();
         }
         Ok(())
     }
+    fn columnar(&mut self) {
+        self.1.enable();
+    }
     fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -299,6 +376,16 @@ struct Var {
     k: f64,
     ex: f64,
     ex2: f64,
+    column: Column,
+}
+
+impl Var {
+    fn fold(&mut self) {
+        let (ex, ex2) = column::shifted_sums(self.column.values(), self.k);
+        self.ex += ex;
+        self.ex2 += ex2;
+        self.column.clear();
+    }
 }
 
 impl TremorAggrFn for Var {
@@ -314,8 +401,12 @@ impl TremorAggrFn for Var {
                     self.k = v;
                 }
                 self.n += 1;
-                self.ex += v - self.k;
-                self.ex2 += (v - self.k) * (v - self.k);
+                if !self.column.push(v) {
+                    self.ex += v - self.k;
+                    self.ex2 += (v - self.k) * (v - self.k);
+                } else if self.column.is_full() {
+                    self.fold();
+                }
                 Ok(())
             },
         )
@@ -336,6 +427,7 @@ impl TremorAggrFn for Var {
         )
     }
     fn emit<'event>(&mut self) -> FResult<Value<'event>> {
+        self.fold();
         if self.n == 0 {
             Ok(Value::from(0.0))
         } else {
@@ -350,16 +442,22 @@ impl TremorAggrFn for Var {
         self.k = 0.0;
         self.ex = 0.0;
         self.ex2 = 0.0;
+        self.column.clear();
     }
     fn merge(&mut self, src: &dyn TremorAggrFn) -> FResult<()> {
         if let Some(other) = src.downcast_ref::<Self>() {
+            self.fold();
+            let (ex, ex2) = column::shifted_sums(other.column.values(), other.k);
             self.n += other.n;
             self.k += other.k;
-            self.ex += other.ex;
-            self.ex2 += other.ex2;
+            self.ex += other.ex + ex;
+            self.ex2 += other.ex2 + ex2;
         }
         Ok(())
     }
+    fn columnar(&mut self) {
+        self.column.enable();
+    }
     fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -394,6 +492,9 @@ impl TremorAggrFn for Stdev {
         }
         Ok(())
     }
+    fn columnar(&mut self) {
+        self.0.columnar()
+    }
     fn boxed_clone(&self) -> Box<dyn TremorAggrFn> {
         Box::new(self.clone())
     }
@@ -1036,6 +1137,36 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn columnar() -> Result<()> {
+        fn check(mut scalar: Box<dyn TremorAggrFn>) -> Result<()> {
+            let mut columnar = scalar.boxed_clone();
+            columnar.columnar();
+            let mut merged = columnar.boxed_clone();
+            // spans full columns and a partial one
+            for i in 0..10_000 {
+                let v = Value::from(f64::from(i % 97) * 1.5 - 20.0);
+                scalar.accumulate(&[&v])?;
+                columnar.accumulate(&[&v])?;
+            }
+            merged.merge(columnar.as_ref())?;
+            let expected = scalar.emit()?.cast_f64().unwrap_or_default();
+            for aggr in &mut [columnar, merged] {
+                let r = aggr.emit()?.cast_f64().unwrap_or_default();
+                // the kernels add in a different order
+                assert!(((expected - r) / expected).abs() < 0.000_000_001);
+            }
+            Ok(())
+        }
+        check(Box::new(Sum::default()))?;
+        check(Box::new(Mean::default()))?;
+        check(Box::new(Min::default()))?;
+        check(Box::new(Max::default()))?;
+        check(Box::new(Var::default()))?;
+        check(Box::new(Stdev::default()))?;
+        Ok(())
+    }
+
     #[test]
     fn hdr() -> Result<()> {
         let mut a = Hdr::default();
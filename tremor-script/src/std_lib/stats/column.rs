@@ -0,0 +1,167 @@
+// Copyright 2020-2021, The Tremor Team
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Columnar accumulation of aggregate functions
+//!
+//! In columnar mode values are buffered in a typed column instead of being
+//! folded into the aggregate one by one. Full columns, and the column on
+//! emit, are folded with kernels working on `LANES` independent
+//! accumulators, which the compiler turns into SIMD instructions.
+
+/// values buffered before they are folded into the aggregate
+pub(super) const CHUNK: usize = 4096;
+
+/// independent accumulators of the kernels, 8 doubles fill an AVX-512
+/// register or two AVX registers
+const LANES: usize = 8;
+
+/// Values buffered by an aggregate function in columnar mode
+#[derive(Clone, Debug, Default)]
+pub(super) struct Column {
+    enabled: bool,
+    values: Vec<f64>,
+}
+
+impl Column {
+    pub(super) fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Buffers `v`, returns `false` if the column isn't enabled
+    pub(super) fn push(&mut self, v: f64) -> bool {
+        if self.enabled {
+            // grows as needed, groups with few values keep small columns
+            self.values.push(v);
+        }
+        self.enabled
+    }
+
+    pub(super) fn is_full(&self) -> bool {
+        self.values.len() >= CHUNK
+    }
+
+    pub(super) fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+/// Folds `values` into `LANES` accumulators and the remainder
+fn fold_lanes<F>(values: &[f64], init: f64, f: F) -> ([f64; LANES], &[f64])
+where
+    F: Fn(f64, f64) -> f64,
+{
+    let mut lanes = [init; LANES];
+    let chunks = values.chunks_exact(LANES);
+    let rest = chunks.remainder();
+    for chunk in chunks {
+        for (lane, v) in lanes.iter_mut().zip(chunk) {
+            *lane = f(*lane, *v);
+        }
+    }
+    (lanes, rest)
+}
+
+pub(super) fn sum(values: &[f64]) -> f64 {
+    let (lanes, rest) = fold_lanes(values, 0.0, |a, v| a + v);
+    lanes.iter().chain(rest).sum()
+}
+
+pub(super) fn min(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let (lanes, rest) = fold_lanes(values, f64::INFINITY, f64::min);
+    Some(
+        lanes
+            .iter()
+            .chain(rest)
+            .fold(f64::INFINITY, |a, v| a.min(*v)),
+    )
+}
+
+pub(super) fn max(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let (lanes, rest) = fold_lanes(values, f64::NEG_INFINITY, f64::max);
+    Some(
+        lanes
+            .iter()
+            .chain(rest)
+            .fold(f64::NEG_INFINITY, |a, v| a.max(*v)),
+    )
+}
+
+/// The sums of `v - k` and `(v - k)²`
+pub(super) fn shifted_sums(values: &[f64], k: f64) -> (f64, f64) {
+    let mut ex = [0.0; LANES];
+    let mut ex2 = [0.0; LANES];
+    let chunks = values.chunks_exact(LANES);
+    let rest = chunks.remainder();
+    for chunk in chunks {
+        for ((ex, ex2), v) in ex.iter_mut().zip(ex2.iter_mut()).zip(chunk) {
+            let d = v - k;
+            *ex += d;
+            *ex2 += d * d;
+        }
+    }
+    let (rest_ex, rest_ex2) = rest.iter().fold((0.0, 0.0), |(ex, ex2), v| {
+        (ex + (v - k), ex2 + (v - k) * (v - k))
+    });
+    (
+        ex.iter().sum::<f64>() + rest_ex,
+        ex2.iter().sum::<f64>() + rest_ex2,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use float_cmp::approx_eq;
+
+    #[test]
+    fn kernels() {
+        let values: Vec<f64> = (1..=21).map(f64::from).collect();
+        assert!(approx_eq!(f64, 231.0, sum(&values)));
+        assert!(approx_eq!(f64, 1.0, min(&values).unwrap_or_default()));
+        assert!(approx_eq!(f64, 21.0, max(&values).unwrap_or_default()));
+        let (ex, ex2) = shifted_sums(&values, 1.0);
+        assert!(approx_eq!(f64, 210.0, ex));
+        assert!(approx_eq!(f64, 2870.0, ex2));
+
+        assert!(approx_eq!(f64, 0.0, sum(&[])));
+        assert!(min(&[]).is_none());
+        assert!(max(&[]).is_none());
+        assert!(approx_eq!(f64, -3.0, min(&[2.0, -3.0]).unwrap_or_default()));
+    }
+
+    #[test]
+    fn column() {
+        let mut column = Column::default();
+        assert!(!column.push(1.0));
+        assert!(column.values().is_empty());
+        column.enable();
+        for _ in 0..CHUNK {
+            assert!(!column.is_full());
+            assert!(column.push(1.0));
+        }
+        assert!(column.is_full());
+        column.clear();
+        assert!(column.values().is_empty());
+    }
+}